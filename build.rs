@@ -0,0 +1,10 @@
+fn main() {
+    // Generate gRPC service bindings using the vendored protoc
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("Error locating protoc");
+        unsafe { std::env::set_var("PROTOC", protoc) };
+
+        tonic_build::compile_protos("proto/radio.proto").expect("Error compiling protobufs");
+    }
+}
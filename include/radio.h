@@ -0,0 +1,204 @@
+#ifndef RADIO_H
+#define RADIO_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Operation completed successfully
+ */
+#define RADIO_OK 0
+
+/**
+ * Operation timed out
+ */
+#define RADIO_ERR_TIMEOUT -1
+
+/**
+ * Invalid (null) argument
+ */
+#define RADIO_ERR_INVALID -2
+
+/**
+ * Blocking operation options
+ */
+typedef struct RadioBlockingOptions {
+  /**
+   * Interval for polling for device state in microseconds
+   */
+  uint32_t poll_interval_us;
+  /**
+   * Timeout for blocking operations in microseconds
+   */
+  uint32_t timeout_us;
+} RadioBlockingOptions;
+
+/**
+ * Ping-pong operation options
+ */
+typedef struct RadioPingPongOptions {
+  /**
+   * Number of rounds to run
+   */
+  uint32_t rounds;
+  /**
+   * Set transmit power before running
+   */
+  bool set_power;
+  /**
+   * Transmit power in dBm
+   */
+  int8_t power;
+  /**
+   * Delay between rounds in microseconds
+   */
+  uint32_t delay_us;
+  /**
+   * Parse RSSI from responses (echo server must append info)
+   */
+  bool parse_info;
+  /**
+   * Payload size in bytes (minimum 4)
+   */
+  size_t size;
+  /**
+   * Blocking options for each transmit and receive
+   */
+  struct RadioBlockingOptions blocking;
+} RadioPingPongOptions;
+
+/**
+ * Radio driver interface, implemented by C code
+ *
+ * Callbacks returning `i32` must return `0` on success or a positive error code.
+ */
+typedef struct RadioVTable {
+  /**
+   * Driver context, passed to each callback
+   */
+  void *ctx;
+  /**
+   * Start transmitting a packet
+   */
+  int32_t (*start_transmit)(void *ctx, const uint8_t *data, size_t len);
+  /**
+   * Check for transmit completion, setting `done` when complete
+   */
+  int32_t (*check_transmit)(void *ctx, bool *done);
+  /**
+   * Enter receive mode
+   */
+  int32_t (*start_receive)(void *ctx);
+  /**
+   * Check for a received packet, setting `ready` when available
+   */
+  int32_t (*check_receive)(void *ctx, bool restart, bool *ready);
+  /**
+   * Fetch a received packet, setting the received length and RSSI
+   */
+  int32_t (*get_received)(void *ctx, uint8_t *buff, size_t len, size_t *received, int16_t *rssi);
+  /**
+   * Set transmit power in dBm
+   */
+  int32_t (*set_power)(void *ctx, int8_t power);
+  /**
+   * Delay for the provided number of microseconds
+   */
+  void (*delay_us)(void *ctx, uint32_t us);
+} RadioVTable;
+
+/**
+ * Ping-pong link test results
+ *
+ * Statistics without samples are reported as `NaN`.
+ */
+typedef struct RadioLinkTestInfo {
+  /**
+   * Packets sent
+   */
+  uint32_t sent;
+  /**
+   * Responses received
+   */
+  uint32_t received;
+  /**
+   * Mean local RSSI in dBm
+   */
+  float local_rssi_mean;
+  /**
+   * Mean remote RSSI in dBm
+   */
+  float remote_rssi_mean;
+  /**
+   * Mean round trip time in milliseconds
+   */
+  float rtt_mean_ms;
+  /**
+   * Maximum round trip time in milliseconds
+   */
+  float rtt_max_ms;
+  /**
+   * Number of loss bursts
+   */
+  uint32_t loss_bursts;
+} RadioLinkTestInfo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Default blocking options
+ */
+struct RadioBlockingOptions radio_blocking_options_default(void);
+
+/**
+ * Default ping-pong options
+ */
+struct RadioPingPongOptions radio_ping_pong_options_default(void);
+
+/**
+ * Transmit a packet
+ *
+ * # Safety
+ * `radio` must point to a valid vtable and `data` to `len` readable bytes.
+ */
+int32_t radio_do_transmit(const struct RadioVTable *radio,
+                          const uint8_t *data,
+                          size_t len,
+                          struct RadioBlockingOptions options);
+
+/**
+ * Receive a single packet, writing the received length and RSSI
+ *
+ * # Safety
+ * `radio` must point to a valid vtable, `buff` to `len` writable bytes,
+ * and `received` and `rssi` must be valid or null.
+ */
+int32_t radio_do_receive(const struct RadioVTable *radio,
+                         uint8_t *buff,
+                         size_t len,
+                         size_t *received,
+                         int16_t *rssi,
+                         struct RadioBlockingOptions options);
+
+/**
+ * Run a ping-pong link test against an echo server, writing results to `info`
+ *
+ * # Safety
+ * `radio` and `options` must point to valid structures, and `info` must be valid or null.
+ */
+int32_t radio_do_ping_pong(const struct RadioVTable *radio,
+                           const struct RadioPingPongOptions *options,
+                           struct RadioLinkTestInfo *info);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RADIO_H */
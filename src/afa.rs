@@ -0,0 +1,514 @@
+//! Listen-before-talk with adaptive frequency agility (LBT + AFA)
+//!
+//! ETSI EN 300 220 polite spectrum access requires transmitters to listen for a minimum
+//! time (plus a random extension) before each transmission, moving to another channel
+//! when the current channel is busy, and limits the transmitter on-time of each
+//! transmission. [`Afa`] wraps a radio implementing [`Channel`], combining clear channel
+//! assessment with a [`ChannelBlacklist`] fed by each assessment, so persistently busy
+//! channels are dropped from the hop set until a quiet re-probe restores them.
+//!
+//! Defaults follow the EN 300 220-1 LBT parameters (160 µs minimum listen time, up to
+//! 5 ms random extension and a 1 s maximum transmitter on-time), thresholds and limits
+//! for a particular sub-band should be checked against the standard.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, warn};
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::{debug, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::blacklist::{BlacklistEvent, BlacklistOptions, ChannelBlacklist};
+use crate::cca::CcaError;
+use crate::clock::Clock;
+use crate::prng::XorShift32;
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// AfaOptions configure LBT + AFA polite spectrum access
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AfaOptions {
+    /// Enable LBT + AFA polite spectrum access (ETSI EN 300 220) over the configured channels
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub afa: bool,
+
+    /// Channel busy threshold in dBm
+    #[cfg_attr(
+        feature = "clap",
+        clap(long, default_value = "-85", allow_hyphen_values = true)
+    )]
+    pub afa_threshold: i16,
+
+    /// Minimum listen time prior to each transmission
+    #[cfg_attr(feature="clap", clap(long, default_value="160us", value_parser=crate::duration_from_str))]
+    pub afa_listen: Duration,
+
+    /// Maximum random extension of the listen time
+    #[cfg_attr(feature="clap", clap(long, default_value="5ms", value_parser=crate::duration_from_str))]
+    pub afa_backoff: Duration,
+
+    /// Maximum transmitter on-time for a single transmission
+    #[cfg_attr(feature="clap", clap(long, default_value="1s", value_parser=crate::duration_from_str))]
+    pub afa_max_on: Duration,
+
+    /// Channel assessments (across channels) before failing a transmission
+    #[cfg_attr(feature = "clap", clap(long, default_value = "8"))]
+    pub afa_attempts: u8,
+
+    /// Consecutive busy assessments before a channel is removed from the hop set
+    #[cfg_attr(feature = "clap", clap(long, default_value = "3"))]
+    pub afa_blacklist: u8,
+}
+
+impl Default for AfaOptions {
+    fn default() -> Self {
+        Self {
+            afa: false,
+            afa_threshold: -85,
+            afa_listen: Duration::from_micros(160),
+            afa_backoff: Duration::from_millis(5),
+            afa_max_on: Duration::from_secs(1),
+            afa_attempts: 8,
+            afa_blacklist: 3,
+        }
+    }
+}
+
+impl AfaOptions {
+    /// Blacklist configuration for channels found busy by assessments
+    pub fn blacklist_options(&self) -> BlacklistOptions {
+        BlacklistOptions {
+            threshold_dbm: self.afa_threshold - 1,
+            consecutive: self.afa_blacklist,
+            min_allowed: 1,
+            ..Default::default()
+        }
+    }
+}
+
+/// LBT + AFA statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AfaStats {
+    /// Channel assessments performed
+    pub assessments: u32,
+    /// Assessments finding the channel busy
+    pub busy: u32,
+    /// Changes of channel
+    pub channel_changes: u32,
+    /// Channels removed from the hop set
+    pub blacklisted: u32,
+    /// Transmissions started
+    pub transmissions: u32,
+    /// Transmissions abandoned with all assessed channels busy
+    pub failed: u32,
+    /// Transmissions exceeding the maximum transmitter on-time
+    pub overruns: u32,
+}
+
+impl core::fmt::Display for AfaStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "assessments {} busy {} channel changes {} blacklisted {} transmissions {} failed {} overruns {}",
+            self.assessments,
+            self.busy,
+            self.channel_changes,
+            self.blacklisted,
+            self.transmissions,
+            self.failed,
+            self.overruns
+        )
+    }
+}
+
+/// Afa wraps a radio with LBT + AFA over the channel list `C`, tracking up to `N` channels
+///
+/// Prior to each transmission the current channel is assessed for the minimum listen
+/// time plus a random extension, moving to the next channel in the hop set while busy.
+/// Busy assessments are recorded in a [`ChannelBlacklist`], with blacklisted channels
+/// re-probed (and restored where quiet) once due. Errors are reported as [`CcaError`].
+pub struct Afa<T, C, K, const N: usize> {
+    radio: T,
+    channels: C,
+    clock: K,
+    options: AfaOptions,
+    blacklist: ChannelBlacklist<N>,
+    rng: XorShift32,
+    index: Option<usize>,
+    tx_start: Option<u64>,
+    stats: AfaStats,
+}
+
+impl<T, C, K, E, const N: usize> Afa<T, C, K, N>
+where
+    T: Channel<Error = E>,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+{
+    /// Wrap a radio, seeding the listen time generator (seeds should differ between nodes)
+    ///
+    /// Channels beyond `N` are ignored, the first channel is selected on the first
+    /// transmission.
+    pub fn new(radio: T, channels: C, clock: K, options: AfaOptions, seed: u32) -> Self {
+        let blacklist =
+            ChannelBlacklist::with_count(options.blacklist_options(), channels.as_ref().len());
+
+        Self {
+            radio,
+            channels,
+            clock,
+            options,
+            blacklist,
+            rng: XorShift32::new(seed),
+            index: None,
+            tx_start: None,
+            stats: AfaStats::default(),
+        }
+    }
+
+    /// Index of the current channel, `None` prior to the first transmission
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// Channel blacklist, defining the current hop set
+    pub fn blacklist(&self) -> &ChannelBlacklist<N> {
+        &self.blacklist
+    }
+
+    /// LBT + AFA statistics
+    pub fn stats(&self) -> &AfaStats {
+        &self.stats
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+
+    fn now_ms(&self) -> u32 {
+        (self.clock.now_us() / 1000) as u32
+    }
+
+    /// Select the channel for the next assessment, preferring blacklisted channels due
+    /// for re-probing, then the current channel where clear, then the next allowed channel
+    fn candidate(&self, busy: bool) -> usize {
+        if let Some(i) = self.blacklist.due_for_probe(self.now_ms()) {
+            return i;
+        }
+
+        match self.index {
+            Some(i) if !busy && self.blacklist.is_allowed(i) => i,
+            Some(i) => self
+                .blacklist
+                .allowed()
+                .find(|a| *a > i)
+                .or_else(|| self.blacklist.allowed().next())
+                .unwrap_or(0),
+            None => self.blacklist.allowed().next().unwrap_or(0),
+        }
+    }
+}
+
+impl<T, C, K, E, const N: usize> Afa<T, C, K, N>
+where
+    T: Channel<Error = E> + Receive<Error = E> + Rssi<Error = E> + DelayNs,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+    E: Debug,
+{
+    /// Listen on the current channel for the minimum listen time plus a random
+    /// extension, returning the peak RSSI
+    fn listen(&mut self) -> Result<i16, E> {
+        let listen = self.options.afa_listen.as_micros() as u32
+            + self.rng.below(self.options.afa_backoff.as_micros() as u32);
+
+        self.radio.start_receive()?;
+        let start = self.radio.poll_rssi()?;
+        self.radio.delay_us(listen);
+        let end = self.radio.poll_rssi()?;
+
+        Ok(start.max(end))
+    }
+
+    /// Find a clear channel, returning `CcaError::ChannelBusy` once attempts are exhausted
+    pub fn wait_clear(&mut self) -> Result<(), CcaError<E>> {
+        if self.channels.as_ref().is_empty() {
+            return Err(CcaError::ChannelBusy);
+        }
+
+        let mut busy = false;
+
+        for _ in 0..self.options.afa_attempts {
+            let index = self.candidate(busy);
+
+            if self.index != Some(index) {
+                self.radio
+                    .set_channel(&self.channels.as_ref()[index])
+                    .map_err(CcaError::Radio)?;
+                if self.index.is_some() {
+                    self.stats.channel_changes += 1;
+                }
+                self.index = Some(index);
+            }
+
+            let rssi = self.listen().map_err(CcaError::Radio)?;
+            self.stats.assessments += 1;
+
+            match self.blacklist.record(index, rssi, self.now_ms()) {
+                Some(BlacklistEvent::Added(_i)) => {
+                    #[cfg(any(feature = "log", feature = "defmt"))]
+                    debug!("Channel {} removed from hop set", _i);
+
+                    self.stats.blacklisted += 1;
+                }
+                Some(BlacklistEvent::Removed(_i)) => {
+                    #[cfg(any(feature = "log", feature = "defmt"))]
+                    debug!("Channel {} restored to hop set", _i);
+                }
+                None => (),
+            }
+
+            if rssi < self.options.afa_threshold {
+                return Ok(());
+            }
+
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Channel {} busy (rssi: {})", index, rssi);
+
+            self.stats.busy += 1;
+            busy = true;
+        }
+
+        self.stats.failed += 1;
+
+        Err(CcaError::ChannelBusy)
+    }
+}
+
+impl<T, C, K, E, const N: usize> Transmit for Afa<T, C, K, N>
+where
+    T: Transmit<Error = E> + Channel<Error = E> + Receive<Error = E> + Rssi<Error = E> + DelayNs,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+    E: Debug,
+{
+    type Error = CcaError<E>;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.wait_clear()?;
+        self.radio.start_transmit(data).map_err(CcaError::Radio)?;
+
+        self.tx_start = Some(self.clock.now_us());
+        self.stats.transmissions += 1;
+
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        let done = self.radio.check_transmit().map_err(CcaError::Radio)?;
+
+        if done && let Some(start) = self.tx_start.take() {
+            let on_us = self.clock.elapsed_us(start);
+            if on_us > self.options.afa_max_on.as_micros() as u64 {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                warn!("Transmitter on-time {} us exceeds the AFA limit", on_us);
+
+                self.stats.overruns += 1;
+            }
+        }
+
+        Ok(done)
+    }
+}
+
+impl<T, C, K, E, const N: usize> Receive for Afa<T, C, K, N>
+where
+    T: Receive<Error = E>,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = CcaError<E>;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive().map_err(CcaError::Radio)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio.check_receive(restart).map_err(CcaError::Radio)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff).map_err(CcaError::Radio)
+    }
+}
+
+impl<T, C, K, E, const N: usize> Power for Afa<T, C, K, N>
+where
+    T: Power<Error = E>,
+    E: Debug,
+{
+    type Error = CcaError<E>;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power).map_err(CcaError::Radio)
+    }
+}
+
+impl<T, C, K, E, const N: usize> Rssi for Afa<T, C, K, N>
+where
+    T: Rssi<Error = E>,
+    E: Debug,
+{
+    type Error = CcaError<E>;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi().map_err(CcaError::Radio)
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out).map_err(CcaError::Radio)
+    }
+}
+
+impl<T: DelayNs, C, K, const N: usize> DelayNs for Afa<T, C, K, N> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+    use crate::clock::VirtualClock;
+
+    /// Radio with per-channel noise, counting transmissions per channel and advancing a virtual clock on delays
+    struct NoisyRadio<'a> {
+        clock: &'a VirtualClock,
+        noise: [i16; 3],
+        channel: usize,
+        sent: [u32; 3],
+        tx_time: Duration,
+    }
+
+    impl Transmit for NoisyRadio<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            self.sent[self.channel] += 1;
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            self.clock.advance(self.tx_time);
+            Ok(true)
+        }
+    }
+
+    impl Receive for NoisyRadio<'_> {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Ok((0, BasicInfo::default()))
+        }
+    }
+
+    impl Rssi for NoisyRadio<'_> {
+        type Error = ();
+
+        fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+            Ok(self.noise[self.channel])
+        }
+    }
+
+    impl Channel for NoisyRadio<'_> {
+        type Channel = usize;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+            self.channel = *channel;
+            Ok(())
+        }
+    }
+
+    impl DelayNs for NoisyRadio<'_> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.clock.advance_us(ns as u64 / 1000);
+        }
+    }
+
+    #[test]
+    fn adaptive_frequency_agility() {
+        let clock = VirtualClock::new();
+        let radio = NoisyRadio {
+            clock: &clock,
+            noise: [-60, -110, -110],
+            channel: 0,
+            sent: [0; 3],
+            tx_time: Duration::from_millis(10),
+        };
+        let mut r = Afa::<_, _, _, 4>::new(radio, [0, 1, 2], &clock, AfaOptions::default(), 1);
+
+        // Busy first channel moves transmissions to the next, staying while clear
+        for _ in 0..3 {
+            r.start_transmit(&[1, 2, 3]).unwrap();
+            assert!(r.check_transmit().unwrap());
+        }
+        assert_eq!(r.inner().sent, [0, 3, 0]);
+        assert_eq!(r.stats().busy, 1);
+        assert_eq!(r.stats().channel_changes, 1);
+
+        // Listening takes at least the minimum listen time
+        assert!(clock.now_us() >= 3 * (10_000 + 160));
+
+        // Persistently busy channels are removed from the hop set
+        r.inner().noise = [-60, -60, -110];
+        r.start_transmit(&[1]).unwrap();
+        assert_eq!(r.index(), Some(2));
+        r.inner().noise = [-60, -60, -60];
+        for _ in 0..2 {
+            assert_eq!(r.start_transmit(&[1]), Err(CcaError::ChannelBusy));
+        }
+        assert_eq!(r.stats().failed, 2);
+        assert!(r.blacklist().allowed_count() < 3);
+        assert!(r.blacklist().allowed_count() >= 1);
+
+        // Restored following a quiet re-probe
+        r.inner().noise = [-110, -110, -110];
+        clock.advance(Duration::from_secs(60));
+        r.start_transmit(&[1]).unwrap();
+        assert!(r.stats().blacklisted > 0);
+
+        // Long transmissions exceed the on-time limit
+        r.inner().tx_time = Duration::from_secs(2);
+        r.start_transmit(&[1]).unwrap();
+        r.check_transmit().unwrap();
+        assert_eq!(r.stats().overruns, 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! Software automatic frequency correction (AFC)
+//!
+//! Averages the frequency error reported by [`ReceiveInfo::frequency_error_hz`] over a
+//! window of received frames and nudges the channel frequency to compensate, keeping
+//! nodes with low-cost crystals locked as their reference drifts over temperature.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use crate::{Channel, ReceiveInfo};
+
+/// AFC loop configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AfcOptions {
+    /// Number of frames averaged per correction
+    pub window: u32,
+    /// Fraction of the mean error applied per correction (`0.0..=1.0`)
+    pub gain: f32,
+    /// Mean errors within this deadband are not corrected
+    pub deadband_hz: u32,
+    /// Maximum total correction from the nominal carrier
+    pub max_correction_hz: u32,
+}
+
+impl Default for AfcOptions {
+    fn default() -> Self {
+        Self {
+            window: 8,
+            gain: 0.5,
+            deadband_hz: 200,
+            max_correction_hz: 50_000,
+        }
+    }
+}
+
+/// Automatic frequency correction controller
+///
+/// Channels are constructed from corrected frequencies using the provided function.
+pub struct Afc<F> {
+    carrier_hz: u32,
+    options: AfcOptions,
+    to_channel: F,
+    correction: i32,
+    sum: i64,
+    count: u32,
+}
+
+impl<F> Afc<F> {
+    /// Create a new AFC controller for the provided nominal carrier frequency
+    pub fn new(carrier_hz: u32, options: AfcOptions, to_channel: F) -> Self {
+        Self {
+            carrier_hz,
+            options,
+            to_channel,
+            correction: 0,
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    /// Fetch the current correction in Hz relative to the nominal carrier
+    pub fn correction_hz(&self) -> i32 {
+        self.correction
+    }
+
+    /// Fetch the currently tuned frequency in Hz
+    pub fn frequency_hz(&self) -> u32 {
+        self.carrier_hz.saturating_add_signed(self.correction)
+    }
+
+    /// Clear accumulated error and correction, for example after changing carrier
+    pub fn reset(&mut self, carrier_hz: u32) {
+        self.carrier_hz = carrier_hz;
+        self.correction = 0;
+        self.sum = 0;
+        self.count = 0;
+    }
+
+    /// Record the frequency error for a received frame, retuning the radio where a correction is due
+    ///
+    /// Frames without frequency error information are ignored. Returns the new correction
+    /// where the radio was retuned.
+    pub fn update<T, I>(&mut self, radio: &mut T, info: &I) -> Result<Option<i32>, T::Error>
+    where
+        T: Channel,
+        I: ReceiveInfo,
+        F: FnMut(u32) -> T::Channel,
+    {
+        let err = match info.frequency_error_hz() {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+
+        self.sum += err as i64;
+        self.count += 1;
+
+        if self.count < self.options.window.max(1) {
+            return Ok(None);
+        }
+
+        let mean = (self.sum / self.count as i64) as i32;
+        self.sum = 0;
+        self.count = 0;
+
+        if mean.unsigned_abs() <= self.options.deadband_hz {
+            return Ok(None);
+        }
+
+        // Apply a fraction of the mean error, limited to the maximum correction
+        let max = self.options.max_correction_hz.min(i32::MAX as u32) as i32;
+        let step = (mean as f32 * self.options.gain) as i32;
+        let correction = self.correction.saturating_add(step).clamp(-max, max);
+
+        if correction == self.correction {
+            return Ok(None);
+        }
+
+        let ch = (self.to_channel)(self.carrier_hz.saturating_add_signed(correction));
+        radio.set_channel(&ch)?;
+        self.correction = correction;
+
+        Ok(Some(correction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct FreqInfo(Option<i32>);
+
+    impl ReceiveInfo for FreqInfo {
+        fn rssi(&self) -> i16 {
+            0
+        }
+
+        fn frequency_error_hz(&self) -> Option<i32> {
+            self.0
+        }
+    }
+
+    struct FreqRadio {
+        freq: u32,
+    }
+
+    impl Channel for FreqRadio {
+        type Channel = u32;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+            self.freq = *channel;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn afc_converges() {
+        let carrier = 868_000_000;
+        let offset = 3_000i32;
+
+        let mut radio = FreqRadio { freq: carrier };
+        let mut afc = Afc::new(carrier, AfcOptions::default(), |f| f);
+
+        // Frames without frequency error are ignored
+        assert_eq!(afc.update(&mut radio, &FreqInfo(None)), Ok(None));
+
+        for _ in 0..200 {
+            let err = carrier as i32 + offset - radio.freq as i32;
+            afc.update(&mut radio, &FreqInfo(Some(err))).unwrap();
+        }
+
+        let residual = carrier as i32 + offset - radio.freq as i32;
+        assert!(residual.unsigned_abs() <= AfcOptions::default().deadband_hz);
+        assert_eq!(afc.frequency_hz(), radio.freq);
+    }
+
+    #[test]
+    fn afc_limits_correction() {
+        let opts = AfcOptions {
+            window: 1,
+            gain: 1.0,
+            deadband_hz: 0,
+            max_correction_hz: 1_000,
+        };
+        let mut radio = FreqRadio { freq: 0 };
+        let mut afc = Afc::new(100_000, opts, |f| f);
+
+        assert_eq!(
+            afc.update(&mut radio, &FreqInfo(Some(5_000))),
+            Ok(Some(1_000))
+        );
+        assert_eq!(afc.update(&mut radio, &FreqInfo(Some(5_000))), Ok(None));
+        assert_eq!(radio.freq, 101_000);
+    }
+}
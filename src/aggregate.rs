@@ -0,0 +1,193 @@
+//! Frame aggregation for small payloads
+//!
+//! Radio frames carry fixed overheads (preamble, sync word, headers and CRC, along with
+//! turnaround and channel access time), so chatty traffic of small messages spends most
+//! of its airtime on overhead. [`Aggregator`] packs multiple messages into a single frame
+//! as length-prefixed sub-frames, with [`subframes`] unpacking them on receive.
+//!
+//! Aggregated frames are encoded as `[len, message..]` sub-frames, with messages of
+//! `1..=255` bytes. A zero length byte ends the frame, so padded frames (for example
+//! from [`FixedLength`](crate::fixed::FixedLength) radios) unpack unchanged.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+/// Length of the header prefixing each sub-frame
+pub const SUBFRAME_HEADER_LEN: usize = 1;
+
+/// Maximum length of an aggregated message
+pub const SUBFRAME_MAX_LEN: usize = u8::MAX as usize;
+
+/// Aggregation errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AggregateError {
+    /// Zero length messages cannot be aggregated
+    #[cfg_attr(feature = "thiserror", error("Empty message"))]
+    Empty,
+    /// Message too large for a sub-frame or for the frame buffer
+    #[cfg_attr(feature = "thiserror", error("Message too large"))]
+    TooLarge,
+    /// Insufficient space remaining in the frame, flush and retry
+    #[cfg_attr(feature = "thiserror", error("Frame full"))]
+    Full,
+    /// Sub-frame extends beyond the end of the frame
+    #[cfg_attr(feature = "thiserror", error("Truncated sub-frame"))]
+    Truncated,
+}
+
+/// Aggregator packs messages into the frame buffer `B`
+///
+/// `B` may be any byte buffer (eg. an array or `Vec`), with the buffer length setting the
+/// maximum aggregated frame length.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregator<B> {
+    buff: B,
+    len: usize,
+    count: usize,
+}
+
+impl<B> Aggregator<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Create an empty aggregator using the provided frame buffer
+    pub fn new(buff: B) -> Self {
+        Self {
+            buff,
+            len: 0,
+            count: 0,
+        }
+    }
+
+    /// Append a message to the frame
+    ///
+    /// Returns `AggregateError::Full` where the message would fit an empty frame but not
+    /// the remaining space, in which case the frame should be sent and cleared.
+    pub fn push(&mut self, message: &[u8]) -> Result<(), AggregateError> {
+        let n = message.len();
+        let capacity = self.buff.as_ref().len();
+
+        if n == 0 {
+            return Err(AggregateError::Empty);
+        }
+        if n > SUBFRAME_MAX_LEN || n + SUBFRAME_HEADER_LEN > capacity {
+            return Err(AggregateError::TooLarge);
+        }
+        if self.len + n + SUBFRAME_HEADER_LEN > capacity {
+            return Err(AggregateError::Full);
+        }
+
+        let b = &mut self.buff.as_mut()[self.len..];
+        b[0] = n as u8;
+        b[SUBFRAME_HEADER_LEN..][..n].copy_from_slice(message);
+
+        self.len += n + SUBFRAME_HEADER_LEN;
+        self.count += 1;
+
+        Ok(())
+    }
+
+    /// Aggregated frame, containing all messages pushed since the last clear
+    pub fn frame(&self) -> &[u8] {
+        &self.buff.as_ref()[..self.len]
+    }
+
+    /// Number of messages in the frame
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Check whether the frame is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Remaining space for message data (excluding the sub-frame header)
+    pub fn remaining(&self) -> usize {
+        (self.buff.as_ref().len() - self.len)
+            .saturating_sub(SUBFRAME_HEADER_LEN)
+            .min(SUBFRAME_MAX_LEN)
+    }
+
+    /// Clear the frame, following transmission
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.count = 0;
+    }
+}
+
+/// Iterate over the messages in an aggregated frame
+pub fn subframes(frame: &[u8]) -> SubFrames<'_> {
+    SubFrames { data: frame }
+}
+
+/// Iterator over the messages in an aggregated frame, see [`subframes`]
+///
+/// Truncated sub-frames are returned as `AggregateError::Truncated`, ending iteration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubFrames<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for SubFrames<'a> {
+    type Item = Result<&'a [u8], AggregateError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = match self.data.first() {
+            Some(0) | None => return None,
+            Some(n) => *n as usize,
+        };
+
+        let end = SUBFRAME_HEADER_LEN + n;
+        if end > self.data.len() {
+            self.data = &[];
+            return Some(Err(AggregateError::Truncated));
+        }
+
+        let (s, rest) = self.data.split_at(end);
+        self.data = rest;
+
+        Some(Ok(&s[SUBFRAME_HEADER_LEN..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_subframes() {
+        let mut a = Aggregator::new([0u8; 12]);
+
+        a.push(&[1, 2, 3]).unwrap();
+        a.push(&[4]).unwrap();
+        assert_eq!(a.frame(), &[3, 1, 2, 3, 1, 4]);
+        assert_eq!(a.count(), 2);
+        assert_eq!(a.remaining(), 5);
+
+        // Messages exceeding the remaining space require a flush
+        assert_eq!(a.push(&[0; 6]), Err(AggregateError::Full));
+        assert_eq!(a.push(&[0; 12]), Err(AggregateError::TooLarge));
+        assert_eq!(a.push(&[]), Err(AggregateError::Empty));
+        a.push(&[5; 5]).unwrap();
+        assert_eq!(a.remaining(), 0);
+
+        let mut s = subframes(a.frame());
+        assert_eq!(s.next(), Some(Ok(&[1, 2, 3][..])));
+        assert_eq!(s.next(), Some(Ok(&[4][..])));
+        assert_eq!(s.next(), Some(Ok(&[5; 5][..])));
+        assert_eq!(s.next(), None);
+
+        // Padding ends the frame, truncation is reported
+        assert_eq!(subframes(&[1, 9, 0, 0, 0]).count(), 1);
+        let mut s = subframes(&[1, 9, 4, 1]);
+        assert_eq!(s.next(), Some(Ok(&[9][..])));
+        assert_eq!(s.next(), Some(Err(AggregateError::Truncated)));
+        assert_eq!(s.next(), None);
+
+        a.clear();
+        assert!(a.is_empty());
+        assert!(a.frame().is_empty());
+    }
+}
@@ -0,0 +1,632 @@
+//! Reliable transmission with acknowledgements and retransmission (ARQ)
+//!
+//! [`ReliableLink`] wraps a `Transmit + Receive` radio, prefixing payloads with a frame
+//! type and sequence number. Data frames are acknowledged by the receiver, with the sender
+//! retransmitting unacknowledged frames up to the configured retry count with a doubling
+//! backoff. Duplicate frames (where the acknowledgement was lost) are acknowledged but
+//! not delivered again.
+//!
+//! With keepalive enabled ([`ArqOptions::keepalive`]), [`ReliableLink::keepalive`] probes
+//! the peer once the link has been idle for the keepalive interval, marking the link down
+//! after the configured number of unanswered probes (or failed sends). Only the sending
+//! side should probe, with receivers answering probes from [`ReliableLink::receive`].
+//!
+//! Sessions are (re-)established with a sync frame before the first send (and the first
+//! send after the link goes down), resetting the peer's duplicate detection so the first
+//! frame from a restarted sender is not mistaken for a retransmission of the previous
+//! session's frame with the same sequence number.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::blocking::{BlockingError, BlockingOptions, BlockingReceive, BlockingTransmit};
+use crate::clock::Clock;
+use crate::{Receive, ReceiveInfo, Transmit};
+
+/// Data frame type
+pub const ARQ_DATA: u8 = 0xa1;
+/// Acknowledgement frame type
+pub const ARQ_ACK: u8 = 0xa2;
+/// Keepalive probe frame type
+pub const ARQ_PING: u8 = 0xa3;
+/// Keepalive response frame type
+pub const ARQ_PONG: u8 = 0xa4;
+/// Session sync frame type, acknowledged with [`ARQ_ACK`]
+pub const ARQ_SYNC: u8 = 0xa5;
+/// ARQ header length (frame type and sequence number)
+pub const ARQ_HEADER_LEN: usize = 2;
+
+/// ArqOptions configure reliable transmission
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ArqOptions {
+    /// Enable reliable transmission with acknowledgements and retransmission
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub reliable: bool,
+
+    /// Retransmissions of unacknowledged frames before failing
+    #[cfg_attr(feature = "clap", clap(long, default_value = "3"))]
+    pub arq_retries: u8,
+
+    /// Timeout awaiting acknowledgement of each transmission
+    #[cfg_attr(feature="clap", clap(long, default_value="100ms", value_parser=crate::duration_from_str))]
+    pub arq_timeout: Duration,
+
+    /// Initial backoff before retransmission, doubled on each retry
+    #[cfg_attr(feature="clap", clap(long, default_value="20ms", value_parser=crate::duration_from_str))]
+    pub arq_backoff: Duration,
+
+    /// Probe the peer after this interval without traffic, detecting dead peers and
+    /// re-establishing the session on their return (requires keepalive-aware peers)
+    #[cfg_attr(feature="clap", clap(long, value_parser=crate::duration_from_str))]
+    pub keepalive: Option<Duration>,
+
+    /// Unanswered keepalive probes (or failed sends) before the peer is considered down
+    #[cfg_attr(feature = "clap", clap(long, default_value = "3"))]
+    pub keepalive_misses: u8,
+}
+
+impl Default for ArqOptions {
+    fn default() -> Self {
+        Self {
+            reliable: false,
+            arq_retries: 3,
+            arq_timeout: Duration::from_millis(100),
+            arq_backoff: Duration::from_millis(20),
+            keepalive: None,
+            keepalive_misses: 3,
+        }
+    }
+}
+
+/// Reliable link statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArqStats {
+    /// Frames sent (excluding retransmissions)
+    pub sent: u32,
+    /// Retransmissions
+    pub retransmissions: u32,
+    /// Frames acknowledged
+    pub acked: u32,
+    /// Frames failed after exhausting retries
+    pub failed: u32,
+    /// Frames delivered to the receiver
+    pub received: u32,
+    /// Duplicate frames received (and re-acknowledged)
+    pub duplicates: u32,
+    /// Keepalive probes sent
+    pub keepalives: u32,
+    /// Transitions to [`LinkState::Down`]
+    pub disconnects: u32,
+    /// Transitions back to [`LinkState::Up`]
+    pub reconnects: u32,
+}
+
+impl core::fmt::Display for ArqStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "sent {} acked {} failed {} retransmissions {}, received {} duplicates {}, keepalives {} disconnects {} reconnects {}",
+            self.sent,
+            self.acked,
+            self.failed,
+            self.retransmissions,
+            self.received,
+            self.duplicates,
+            self.keepalives,
+            self.disconnects,
+            self.reconnects
+        )
+    }
+}
+
+/// Peer liveness as tracked by keepalive
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LinkState {
+    /// Peer responding
+    Up,
+    /// Peer not responding to keepalive probes or data
+    Down,
+}
+
+/// Reliable link over a `Transmit + Receive` radio, with frames of up to `N` bytes
+/// (including the [`ARQ_HEADER_LEN`] byte header)
+pub struct ReliableLink<T, const N: usize = 256> {
+    radio: T,
+    options: ArqOptions,
+    blocking: BlockingOptions,
+    seq: u8,
+    last_rx: Option<u8>,
+    stats: ArqStats,
+    state: LinkState,
+    synced: bool,
+    heard: bool,
+    misses: u8,
+    last_activity_us: Option<u64>,
+    buff: [u8; N],
+}
+
+impl<T, I, E, const N: usize> ReliableLink<T, N>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    /// Wrap a radio, using `blocking` for the transmit and receive poll interval and
+    /// transmit timeout
+    pub fn new(radio: T, options: ArqOptions, blocking: BlockingOptions) -> Self {
+        Self {
+            radio,
+            options,
+            blocking,
+            seq: 0,
+            last_rx: None,
+            stats: ArqStats::default(),
+            state: LinkState::Up,
+            synced: false,
+            heard: false,
+            misses: 0,
+            last_activity_us: None,
+            buff: [0u8; N],
+        }
+    }
+
+    /// Link statistics
+    pub fn stats(&self) -> &ArqStats {
+        &self.stats
+    }
+
+    /// Peer liveness, always [`LinkState::Up`] without keepalive
+    pub fn state(&self) -> LinkState {
+        self.state
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+
+    /// Send a payload, retransmitting until acknowledged or retries are exhausted
+    ///
+    /// Returns whether the payload was acknowledged, [`BlockingError::Oversize`] where
+    /// the payload does not fit in a frame, or [`BlockingError::Exhausted`] where the
+    /// session could not be established (so the payload is not sent).
+    pub fn send(&mut self, data: &[u8]) -> Result<bool, BlockingError<E>> {
+        let n = ARQ_HEADER_LEN + data.len();
+        if n > N {
+            return Err(BlockingError::Oversize {
+                len: data.len(),
+                max: N - ARQ_HEADER_LEN,
+            });
+        }
+
+        // (Re-)establish the session ahead of data
+        if !self.synced {
+            self.sync()?;
+        }
+
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        self.stats.sent += 1;
+
+        let ack_options = self.ack_options();
+        let mut backoff = self.options.arq_backoff;
+
+        for attempt in 0..=self.options.arq_retries {
+            if attempt > 0 {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                debug!("Retransmitting frame {} (attempt {})", seq, attempt);
+
+                self.stats.retransmissions += 1;
+                self.radio.delay_us(backoff.as_micros() as u32);
+                backoff *= 2;
+            }
+
+            self.buff[0] = ARQ_DATA;
+            self.buff[1] = seq;
+            self.buff[ARQ_HEADER_LEN..n].copy_from_slice(data);
+            self.radio
+                .do_transmit(&self.buff[..n], self.blocking.clone())?;
+
+            // Await matching acknowledgement
+            match self.radio.do_receive(&mut self.buff, ack_options.clone()) {
+                Ok((2, _)) if self.buff[..2] == [ARQ_ACK, seq] => {
+                    self.stats.acked += 1;
+                    self.heard();
+                    return Ok(true);
+                }
+                Ok(_) | Err(BlockingError::Timeout) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.stats.failed += 1;
+        self.missed();
+
+        Ok(false)
+    }
+
+    /// Probe the peer where the link has been idle for the keepalive interval, returning
+    /// the current link state
+    ///
+    /// Call periodically (eg. between sends or receive timeouts), with traffic acknowledged
+    /// or received since the previous call counting as activity. Returns immediately
+    /// where keepalive is not configured.
+    pub fn keepalive<C: Clock>(&mut self, clock: &C) -> Result<LinkState, BlockingError<E>> {
+        let interval = match self.options.keepalive {
+            Some(i) => i.as_micros() as u64,
+            None => return Ok(self.state),
+        };
+
+        let now = clock.now_us();
+        if core::mem::take(&mut self.heard) {
+            self.last_activity_us = Some(now);
+        }
+        let last = *self.last_activity_us.get_or_insert(now);
+        if now.saturating_sub(last) < interval {
+            return Ok(self.state);
+        }
+
+        self.last_activity_us = Some(now);
+        self.stats.keepalives += 1;
+
+        if self.exchange([ARQ_PING, self.seq], [ARQ_PONG, self.seq])? {
+            self.heard();
+        } else {
+            self.missed();
+        }
+
+        Ok(self.state)
+    }
+
+    /// Receive a payload into the provided buffer, acknowledging data frames
+    ///
+    /// Duplicate and non-ARQ frames are ignored, returning `BlockingError::Timeout` if no
+    /// new payload is received before the blocking timeout.
+    pub fn receive(&mut self, buff: &mut [u8]) -> Result<(usize, I), BlockingError<E>> {
+        let (n, info) = self
+            .radio
+            .do_receive(&mut self.buff, self.blocking.clone())?;
+        if n < ARQ_HEADER_LEN {
+            return Err(BlockingError::Timeout);
+        }
+
+        let seq = self.buff[1];
+        match self.buff[0] {
+            ARQ_DATA => self.heard(),
+            ARQ_PING => {
+                self.heard();
+                self.radio
+                    .do_transmit(&[ARQ_PONG, seq], self.blocking.clone())?;
+                return Err(BlockingError::Timeout);
+            }
+            ARQ_SYNC => {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                debug!("Session sync at frame {}", seq);
+
+                self.heard();
+                self.last_rx = None;
+                self.radio
+                    .do_transmit(&[ARQ_ACK, seq], self.blocking.clone())?;
+                return Err(BlockingError::Timeout);
+            }
+            _ => return Err(BlockingError::Timeout),
+        }
+
+        // Acknowledge all data frames, as acknowledgements may be lost
+        self.radio
+            .do_transmit(&[ARQ_ACK, seq], self.blocking.clone())?;
+
+        if self.last_rx == Some(seq) {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Duplicate frame {}", seq);
+
+            self.stats.duplicates += 1;
+            return Err(BlockingError::Timeout);
+        }
+        self.last_rx = Some(seq);
+        self.stats.received += 1;
+
+        let len = (n - ARQ_HEADER_LEN).min(buff.len());
+        buff[..len].copy_from_slice(&self.buff[ARQ_HEADER_LEN..][..len]);
+
+        Ok((len, info))
+    }
+
+    /// Options for awaiting acknowledgements and keepalive responses
+    fn ack_options(&self) -> BlockingOptions {
+        BlockingOptions {
+            timeout: self.options.arq_timeout,
+            ..self.blocking.clone()
+        }
+    }
+
+    /// Transmit a control frame and await the expected response, returning whether the
+    /// response was received
+    fn exchange(&mut self, frame: [u8; 2], expect: [u8; 2]) -> Result<bool, BlockingError<E>> {
+        self.radio.do_transmit(&frame, self.blocking.clone())?;
+
+        let ack_options = self.ack_options();
+        match self.radio.do_receive(&mut self.buff, ack_options) {
+            Ok((2, _)) => Ok(self.buff[..2] == expect),
+            Ok(_) | Err(BlockingError::Timeout) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reset the peer's duplicate detection ahead of the next data frame, retrying with
+    /// backoff as for data frames and failing once retries are exhausted
+    fn sync(&mut self) -> Result<(), BlockingError<E>> {
+        let seq = self.seq;
+        let mut backoff = self.options.arq_backoff;
+
+        for attempt in 0..=self.options.arq_retries {
+            if attempt > 0 {
+                self.radio.delay_us(backoff.as_micros() as u32);
+                backoff *= 2;
+            }
+
+            if self.exchange([ARQ_SYNC, seq], [ARQ_ACK, seq])? {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                debug!("Session established at frame {}", seq);
+
+                self.heard();
+                self.synced = true;
+                return Ok(());
+            }
+        }
+
+        self.missed();
+
+        Err(BlockingError::Exhausted {
+            attempts: self.options.arq_retries as u32 + 1,
+            error: None,
+        })
+    }
+
+    /// Record traffic from the peer, bringing the link back up where down
+    fn heard(&mut self) {
+        self.heard = true;
+        self.misses = 0;
+
+        if self.state == LinkState::Down {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Peer reconnected");
+
+            self.state = LinkState::Up;
+            self.stats.reconnects += 1;
+        }
+    }
+
+    /// Record an unanswered probe or failed send, taking the link down once the
+    /// configured misses are reached
+    fn missed(&mut self) {
+        if self.options.keepalive.is_none() {
+            return;
+        }
+
+        self.misses = self.misses.saturating_add(1);
+        if self.state == LinkState::Up && self.misses >= self.options.keepalive_misses {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Peer lost after {} misses", self.misses);
+
+            self.state = LinkState::Down;
+            self.stats.disconnects += 1;
+            self.synced = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Loopback radio acting as a reliable peer, dropping the first `drop` frames
+    #[derive(Default)]
+    struct PeerRadio {
+        drop: u32,
+        pending: Option<[u8; 2]>,
+        syncs: u32,
+    }
+
+    impl Transmit for PeerRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            if data[0] == ARQ_SYNC {
+                self.syncs += 1;
+            }
+            let response = match data[0] {
+                ARQ_PING => ARQ_PONG,
+                _ => ARQ_ACK,
+            };
+            match self.drop {
+                0 => self.pending = Some([response, data[1]]),
+                _ => self.drop -= 1,
+            }
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for PeerRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.pending.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.pending.take().unwrap();
+            buff[..2].copy_from_slice(&d);
+            Ok((2, BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for PeerRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn retransmit_until_acked() {
+        let mut radio = PeerRadio {
+            drop: 2,
+            ..Default::default()
+        };
+        let mut link: ReliableLink<_, 32> = ReliableLink::new(
+            &mut radio,
+            ArqOptions::default(),
+            BlockingOptions::default(),
+        );
+
+        // Session sync is retried ahead of the first frame
+        assert_eq!(link.send(&[1, 2, 3]), Ok(true));
+        assert_eq!(link.inner().syncs, 3);
+
+        link.inner().drop = 2;
+        assert_eq!(link.send(&[4]), Ok(true));
+        assert_eq!(link.inner().syncs, 3);
+        assert_eq!(
+            link.stats(),
+            &ArqStats {
+                sent: 2,
+                retransmissions: 2,
+                acked: 2,
+                ..Default::default()
+            }
+        );
+
+        // Fails once retries are exhausted
+        link.inner().drop = 10;
+        assert_eq!(link.send(&[5]), Ok(false));
+        assert_eq!(link.stats().failed, 1);
+        assert_eq!(link.stats().retransmissions, 5);
+    }
+
+    #[test]
+    fn unacknowledged_sync() {
+        let mut radio = PeerRadio {
+            drop: 100,
+            ..Default::default()
+        };
+        let mut link: ReliableLink<_, 32> = ReliableLink::new(
+            &mut radio,
+            ArqOptions::default(),
+            BlockingOptions::default(),
+        );
+
+        // No data is sent on a session the peer never reset
+        assert_eq!(
+            link.send(&[1]),
+            Err(BlockingError::Exhausted {
+                attempts: 4,
+                error: None
+            })
+        );
+        assert_eq!(link.inner().syncs, 4);
+        assert_eq!(link.stats().sent, 0);
+
+        // The session is re-attempted on the next send
+        link.inner().drop = 0;
+        assert_eq!(link.send(&[2]), Ok(true));
+        assert_eq!(link.inner().syncs, 5);
+    }
+
+    #[test]
+    fn oversize_payload() {
+        let mut link: ReliableLink<_, 8> = ReliableLink::new(
+            PeerRadio::default(),
+            ArqOptions::default(),
+            BlockingOptions::default(),
+        );
+
+        // Payloads exceeding the frame buffer are rejected without transmitting
+        assert_eq!(
+            link.send(&[0u8; 9]),
+            Err(BlockingError::Oversize {
+                len: 9,
+                max: 8 - ARQ_HEADER_LEN
+            })
+        );
+        assert_eq!(link.stats().sent, 0);
+        assert_eq!(link.send(&[0u8; 8 - ARQ_HEADER_LEN]), Ok(true));
+    }
+
+    #[test]
+    fn keepalive_reconnect() {
+        let clock = crate::clock::VirtualClock::new();
+        let options = ArqOptions {
+            keepalive: Some(Duration::from_secs(1)),
+            keepalive_misses: 2,
+            ..Default::default()
+        };
+        let mut link: ReliableLink<_, 32> =
+            ReliableLink::new(PeerRadio::default(), options, BlockingOptions::default());
+
+        // Session established ahead of the first send
+        assert_eq!(link.send(&[1]), Ok(true));
+        assert_eq!(link.inner().syncs, 1);
+
+        // Recent traffic defers probing
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(link.keepalive(&clock), Ok(LinkState::Up));
+        assert_eq!(link.stats().keepalives, 0);
+
+        // Unanswered probes take the link down
+        link.inner().drop = 100;
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(link.keepalive(&clock), Ok(LinkState::Up));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(link.keepalive(&clock), Ok(LinkState::Down));
+        assert_eq!(link.stats().disconnects, 1);
+
+        // Answered probes bring it back, with the session re-established on the next send
+        link.inner().drop = 0;
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(link.keepalive(&clock), Ok(LinkState::Up));
+        assert_eq!(link.send(&[2]), Ok(true));
+        assert_eq!(link.inner().syncs, 2);
+        assert_eq!(
+            (
+                link.stats().keepalives,
+                link.stats().reconnects,
+                link.stats().acked
+            ),
+            (3, 1, 2)
+        );
+    }
+}
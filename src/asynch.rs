@@ -0,0 +1,302 @@
+//! Async APIs on top of the base radio traits, for use with async executors (eg. Embassy)
+//!
+//! These mirror the [`blocking`](crate::blocking) APIs, awaiting operation completion
+//! using the radio's [`embedded_hal_async::delay::DelayNs`] implementation between status
+//! polls (rather than busy-waiting), or an interrupt pin future via the `_irq` variants.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::blocking::{BlockingError, BlockingOptions, Phase, record_wait};
+use crate::{Receive, Transmit};
+
+/// AsyncOptions for async radio functions
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AsyncOptions {
+    /// Interval for polling for device state
+    #[cfg_attr(feature="clap", clap(long, default_value="100us", value_parser=crate::duration_from_str))]
+    pub poll_interval: Duration,
+
+    /// Timeout for async operation
+    #[cfg_attr(feature="clap", clap(long, default_value="100ms", value_parser=crate::duration_from_str))]
+    pub timeout: Duration,
+}
+
+impl Default for AsyncOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_micros(100),
+            timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+impl From<BlockingOptions> for AsyncOptions {
+    fn from(o: BlockingOptions) -> Self {
+        Self {
+            poll_interval: o.poll_interval,
+            timeout: o.timeout,
+        }
+    }
+}
+
+/// Async transmit function implemented over `radio::Transmit`, awaiting the radio's async
+/// `DelayNs` implementation between polls for completion
+///
+/// Errors are shared with the blocking APIs, with `BlockingError::Timeout` returned where
+/// the operation does not complete within the configured timeout.
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransmit<E: Debug> {
+    /// Transmit a packet, polling for completion
+    async fn do_transmit(
+        &mut self,
+        data: &[u8],
+        options: AsyncOptions,
+    ) -> Result<(), BlockingError<E>>;
+
+    /// Transmit a packet, awaiting the provided interrupt pin between checks for completion
+    ///
+    /// No timeout is applied, use executor timeouts (eg. `embassy_time::with_timeout`) where required.
+    async fn do_transmit_irq<P: Wait>(
+        &mut self,
+        data: &[u8],
+        irq: &mut P,
+    ) -> Result<(), BlockingError<E>>;
+}
+
+impl<T, E> AsyncTransmit<E> for T
+where
+    T: Transmit<Error = E> + DelayNs,
+    E: Debug,
+{
+    async fn do_transmit(
+        &mut self,
+        data: &[u8],
+        options: AsyncOptions,
+    ) -> Result<(), BlockingError<E>> {
+        // Enter transmit mode
+        self.start_transmit(data)?;
+
+        let t = options.timeout.as_micros();
+        let mut c = 0;
+        loop {
+            // Check for transmit complete
+            if self.check_transmit()? {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                debug!("Async send complete");
+                record_wait(Phase::TxDone, c, t, false);
+                return Ok(());
+            }
+
+            // Update poll time and timeout if overrun
+            c += options.poll_interval.as_micros();
+            if c > t {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                debug!("Async send timeout");
+                record_wait(Phase::TxDone, c, t, true);
+                return Err(BlockingError::Timeout);
+            }
+
+            // Await next poll
+            self.delay_us(options.poll_interval.as_micros() as u32)
+                .await;
+        }
+    }
+
+    async fn do_transmit_irq<P: Wait>(
+        &mut self,
+        data: &[u8],
+        irq: &mut P,
+    ) -> Result<(), BlockingError<E>> {
+        self.start_transmit(data)?;
+
+        while !self.check_transmit()? {
+            // Pin errors are treated as spurious wakes, re-checking radio state
+            let _ = irq.wait_for_high().await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Async receive function implemented over `radio::Receive`, awaiting the radio's async
+/// `DelayNs` implementation between polls for received packets
+#[allow(async_fn_in_trait)]
+pub trait AsyncReceive<I, E> {
+    /// Receive a packet into the provided buffer, polling for reception
+    async fn do_receive(
+        &mut self,
+        buff: &mut [u8],
+        options: AsyncOptions,
+    ) -> Result<(usize, I), BlockingError<E>>;
+
+    /// Receive a packet, awaiting the provided interrupt pin between checks for reception
+    ///
+    /// No timeout is applied, use executor timeouts (eg. `embassy_time::with_timeout`) where required.
+    async fn do_receive_irq<P: Wait>(
+        &mut self,
+        buff: &mut [u8],
+        irq: &mut P,
+    ) -> Result<(usize, I), BlockingError<E>>;
+}
+
+impl<T, I, E> AsyncReceive<I, E> for T
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: Debug,
+    E: Debug,
+{
+    async fn do_receive(
+        &mut self,
+        buff: &mut [u8],
+        options: AsyncOptions,
+    ) -> Result<(usize, I), BlockingError<E>> {
+        // Start receive mode
+        self.start_receive()?;
+
+        let t = options.timeout.as_micros();
+        let mut c = 0;
+        loop {
+            if self.check_receive(true)? {
+                let (n, i) = self.get_received(buff)?;
+                record_wait(Phase::Rx, c, t, false);
+                return Ok((n, i));
+            }
+
+            c += options.poll_interval.as_micros();
+            if c > t {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                debug!("Async receive timeout");
+                record_wait(Phase::Rx, c, t, true);
+                return Err(BlockingError::Timeout);
+            }
+
+            self.delay_us(options.poll_interval.as_micros() as u32)
+                .await;
+        }
+    }
+
+    async fn do_receive_irq<P: Wait>(
+        &mut self,
+        buff: &mut [u8],
+        irq: &mut P,
+    ) -> Result<(usize, I), BlockingError<E>> {
+        self.start_receive()?;
+
+        while !self.check_receive(true)? {
+            let _ = irq.wait_for_high().await;
+        }
+
+        let (n, i) = self.get_received(buff)?;
+        Ok((n, i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Minimal executor, polling the future to completion
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let mut f = pin!(f);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(r) = f.as_mut().poll(&mut cx) {
+                return r;
+            }
+        }
+    }
+
+    /// Radio completing operations after a number of polls, counting awaited delays
+    #[derive(Default)]
+    struct SlowRadio {
+        polls: u32,
+        delays: u32,
+        last: Option<[u8; 2]>,
+    }
+
+    impl Transmit for SlowRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.last = data.try_into().ok();
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            self.polls += 1;
+            Ok(self.polls > 3)
+        }
+    }
+
+    impl Receive for SlowRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            self.polls = 0;
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            self.polls += 1;
+            Ok(self.polls > 3 && self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.unwrap();
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for SlowRadio {
+        async fn delay_ns(&mut self, _ns: u32) {
+            self.delays += 1;
+        }
+    }
+
+    #[test]
+    fn async_transmit_receive() {
+        let mut radio = SlowRadio::default();
+
+        block_on(radio.do_transmit(&[0xaa, 0xbb], AsyncOptions::default())).unwrap();
+        assert_eq!(radio.delays, 3);
+
+        let mut buff = [0u8; 16];
+        let (n, _) = block_on(radio.do_receive(&mut buff, AsyncOptions::default())).unwrap();
+        assert_eq!(&buff[..n], &[0xaa, 0xbb]);
+
+        // Timeout where reception does not complete in time
+        radio.last = None;
+        let o = AsyncOptions {
+            poll_interval: Duration::from_millis(1),
+            timeout: Duration::from_millis(2),
+        };
+        assert_eq!(
+            block_on(radio.do_receive(&mut buff, o)),
+            Err(BlockingError::Timeout)
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Noise-driven channel blacklisting
+//!
+//! Tracks noise floor measurements for a fixed set of channels, blacklisting channels
+//! that are persistently above a noise threshold so they can be excluded from hop sets,
+//! and periodically re-probing blacklisted channels so they can be restored.
+//!
+//! Timestamps are provided by the caller in milliseconds, allowing use in `no_std` environments.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use crate::{Channel, Receive, Rssi};
+
+/// Channel blacklist configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlacklistOptions {
+    /// Noise floor threshold in dBm, channels above this are considered noisy
+    pub threshold_dbm: i16,
+    /// Consecutive noisy measurements before a channel is blacklisted
+    pub consecutive: u8,
+    /// Interval after which blacklisted channels are re-probed
+    pub reprobe_ms: u32,
+    /// Minimum number of channels to keep available, regardless of noise
+    pub min_allowed: usize,
+}
+
+impl Default for BlacklistOptions {
+    fn default() -> Self {
+        Self {
+            threshold_dbm: -90,
+            consecutive: 3,
+            reprobe_ms: 60_000,
+            min_allowed: 2,
+        }
+    }
+}
+
+/// Blacklist state changes resulting from a measurement
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlacklistEvent {
+    /// Channel added to the blacklist
+    Added(usize),
+    /// Channel removed from the blacklist following a quiet re-probe
+    Removed(usize),
+}
+
+/// Per-channel noise state
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelNoise {
+    /// Most recent noise floor measurement in dBm
+    pub last_dbm: Option<i16>,
+    /// Consecutive noisy measurements
+    pub noisy: u8,
+    /// Time the channel was blacklisted (or last re-probed while blacklisted)
+    pub blacklisted_at: Option<u32>,
+}
+
+/// Channel blacklist for up to `N` channels, indexed `0..N`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelBlacklist<const N: usize> {
+    options: BlacklistOptions,
+    channels: [ChannelNoise; N],
+    count: usize,
+}
+
+impl<const N: usize> ChannelBlacklist<N> {
+    /// Create a new blacklist with all channels allowed
+    pub fn new(options: BlacklistOptions) -> Self {
+        Self {
+            options,
+            channels: [ChannelNoise::default(); N],
+            count: N,
+        }
+    }
+
+    /// Create a new blacklist tracking only the first `count` channels (up to `N`),
+    /// for channel lists sized at runtime
+    pub fn with_count(options: BlacklistOptions, count: usize) -> Self {
+        Self {
+            count: count.min(N),
+            ..Self::new(options)
+        }
+    }
+
+    /// Fetch noise state for a channel
+    pub fn channel(&self, index: usize) -> Option<&ChannelNoise> {
+        self.channels[..self.count].get(index)
+    }
+
+    /// Check whether a channel is currently allowed
+    pub fn is_allowed(&self, index: usize) -> bool {
+        self.channel(index)
+            .map(|c| c.blacklisted_at.is_none())
+            .unwrap_or(false)
+    }
+
+    /// Iterate over allowed channel indices, for building hop sets
+    pub fn allowed(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.count).filter(|i| self.is_allowed(*i))
+    }
+
+    /// Number of allowed channels
+    pub fn allowed_count(&self) -> usize {
+        self.allowed().count()
+    }
+
+    /// Fetch the next blacklisted channel due for re-probing
+    pub fn due_for_probe(&self, now_ms: u32) -> Option<usize> {
+        self.channels[..self.count]
+            .iter()
+            .position(|c| match c.blacklisted_at {
+                Some(t) => now_ms.wrapping_sub(t) >= self.options.reprobe_ms,
+                None => false,
+            })
+    }
+
+    /// Record a noise floor measurement for a channel
+    pub fn record(&mut self, index: usize, rssi_dbm: i16, now_ms: u32) -> Option<BlacklistEvent> {
+        let allowed = self.allowed_count();
+        let opts = self.options;
+        let c = self.channels[..self.count].get_mut(index)?;
+
+        let noisy = rssi_dbm > opts.threshold_dbm;
+        c.last_dbm = Some(rssi_dbm);
+        c.noisy = match noisy {
+            true => c.noisy.saturating_add(1),
+            false => 0,
+        };
+
+        match (c.blacklisted_at, noisy) {
+            // Quiet re-probe, restore channel
+            (Some(_), false) => {
+                c.blacklisted_at = None;
+                Some(BlacklistEvent::Removed(index))
+            }
+            // Still noisy, wait for the next re-probe
+            (Some(_), true) => {
+                c.blacklisted_at = Some(now_ms);
+                None
+            }
+            // Persistently noisy, blacklist if enough channels remain
+            (None, true) if c.noisy >= opts.consecutive && allowed > opts.min_allowed => {
+                c.blacklisted_at = Some(now_ms);
+                Some(BlacklistEvent::Added(index))
+            }
+            _ => None,
+        }
+    }
+
+    /// Measure the noise floor on a channel and record the result
+    ///
+    /// This switches to the provided channel and enters receive mode prior to polling RSSI.
+    pub fn measure<T, E>(
+        &mut self,
+        radio: &mut T,
+        index: usize,
+        channel: &<T as Channel>::Channel,
+        now_ms: u32,
+    ) -> Result<Option<BlacklistEvent>, E>
+    where
+        T: Channel<Error = E> + Receive<Error = E> + Rssi<Error = E>,
+    {
+        radio.set_channel(channel)?;
+        radio.start_receive()?;
+        let rssi = radio.poll_rssi()?;
+
+        Ok(self.record(index, rssi, now_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blacklist_noisy_channels() {
+        let mut b = ChannelBlacklist::<4>::new(BlacklistOptions::default());
+
+        // Transient noise does not blacklist
+        assert_eq!(b.record(1, -60, 0), None);
+        assert_eq!(b.record(1, -100, 10), None);
+        assert!(b.is_allowed(1));
+
+        // Persistent noise does
+        assert_eq!(b.record(1, -60, 20), None);
+        assert_eq!(b.record(1, -60, 30), None);
+        assert_eq!(b.record(1, -60, 40), Some(BlacklistEvent::Added(1)));
+        assert!(!b.is_allowed(1));
+        assert!(b.allowed().eq([0, 2, 3]));
+
+        // Re-probe while still noisy resets the timer
+        assert_eq!(b.due_for_probe(1000), None);
+        assert_eq!(b.due_for_probe(60_040), Some(1));
+        assert_eq!(b.record(1, -50, 60_040), None);
+        assert_eq!(b.due_for_probe(60_041), None);
+
+        // Quiet re-probe restores the channel
+        assert_eq!(b.record(1, -110, 120_040), Some(BlacklistEvent::Removed(1)));
+        assert!(b.is_allowed(1));
+    }
+
+    #[test]
+    fn blacklist_keeps_minimum_channels() {
+        let opts = BlacklistOptions {
+            consecutive: 1,
+            ..Default::default()
+        };
+        let mut b = ChannelBlacklist::<3>::new(opts);
+
+        assert_eq!(b.record(0, -40, 0), Some(BlacklistEvent::Added(0)));
+        assert_eq!(b.record(1, -40, 0), None);
+        assert_eq!(b.allowed_count(), 2);
+
+        // Channels beyond the configured count are ignored
+        let b = ChannelBlacklist::<8>::with_count(opts, 3);
+        assert!(b.allowed().eq([0, 1, 2]));
+        assert!(!b.is_allowed(3));
+    }
+}
@@ -7,6 +7,7 @@
 //! ## Copyright 2020-2022 Ryan Kurte
 
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicU32, Ordering};
 use core::time::Duration;
 
 use embedded_hal::delay::DelayNs;
@@ -18,7 +19,7 @@ use defmt::debug;
 use log::debug;
 
 #[cfg(feature = "clap")]
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 use crate::{Receive, State, Transmit};
 
@@ -34,6 +35,18 @@ pub struct BlockingOptions {
     /// Timeout for blocking operation
     #[cfg_attr(feature="clap", clap(long, default_value="100ms", value_parser=crate::duration_from_str))]
     pub timeout: Duration,
+
+    /// Backoff policy for poll intervals and retries, starting from the poll interval
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "fixed"))]
+    pub poll_backoff: Backoff,
+
+    /// Maximum poll (and retry) interval with linear or exponential backoff
+    #[cfg_attr(feature="clap", clap(long, default_value="10ms", value_parser=crate::duration_from_str))]
+    pub max_poll_interval: Duration,
+
+    /// Retries following driver errors or timeouts, before failing with the attempt count
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub max_retries: u32,
 }
 
 impl Default for BlockingOptions {
@@ -41,10 +54,58 @@ impl Default for BlockingOptions {
         Self {
             poll_interval: Duration::from_micros(100),
             timeout: Duration::from_millis(100),
+            poll_backoff: Backoff::Fixed,
+            max_poll_interval: Duration::from_millis(10),
+            max_retries: 0,
         }
     }
 }
 
+impl BlockingOptions {
+    /// Interval before the `n`th poll (or retry), under the configured backoff policy
+    pub fn interval(&self, n: u32) -> Duration {
+        let base = self.poll_interval;
+        let i = match self.poll_backoff {
+            Backoff::Fixed => return base,
+            Backoff::Linear => base.saturating_mul(n.saturating_add(1)),
+            Backoff::Exponential => {
+                // Jitter of up to half the interval avoids synchronised polling and retries
+                let d = base.saturating_mul(1 << n.min(16));
+                d - d.mul_f32(jitter(n) * 0.5)
+            }
+        };
+        i.min(self.max_poll_interval.max(base))
+    }
+}
+
+/// Backoff policy for polling and retries in blocking operations
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Backoff {
+    /// Fixed poll interval
+    #[default]
+    Fixed,
+    /// Poll interval increasing by the base interval each poll
+    Linear,
+    /// Poll interval doubling each poll, with jitter
+    #[cfg_attr(feature = "clap", clap(name = "exp"))]
+    Exponential,
+}
+
+static JITTER: AtomicU32 = AtomicU32::new(0x9e37_79b9);
+
+/// Pseudo-random jitter fraction in `[0, 1)` (xorshift, mixing in the poll count)
+fn jitter(n: u32) -> f32 {
+    let mut x = JITTER.load(Ordering::Relaxed) ^ n.wrapping_mul(0x85eb_ca6b);
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    JITTER.store(x, Ordering::Relaxed);
+
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
 /// BlockingError wraps radio error type to provie a `Timeout` variant
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
@@ -54,6 +115,22 @@ pub enum BlockingError<E> {
     Inner(E),
     #[cfg_attr(feature = "thiserror", error("Timeout"))]
     Timeout,
+    /// Operation failed on every attempt with `--max-retries`, with the error from the
+    /// final attempt (`None` where it timed out)
+    #[cfg_attr(
+        feature = "thiserror",
+        error("Failed after {attempts} attempts (last error: {error:?})")
+    )]
+    Exhausted { attempts: u32, error: Option<E> },
+    /// Payload length exceeds the maximum supported by the operation
+    #[cfg_attr(
+        feature = "thiserror",
+        error("Payload of {len} bytes exceeds maximum of {max} bytes")
+    )]
+    Oversize { len: usize, max: usize },
+    /// Operation requires a capability not supported by the radio (or helper entry point)
+    #[cfg_attr(feature = "thiserror", error("Unsupported: {0}"))]
+    Unsupported(&'static str),
 }
 
 impl<E> From<E> for BlockingError<E> {
@@ -62,6 +139,219 @@ impl<E> From<E> for BlockingError<E> {
     }
 }
 
+impl<E> BlockingError<E> {
+    /// Check whether the operation (or its final attempt) timed out
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            BlockingError::Timeout | BlockingError::Exhausted { error: None, .. }
+        )
+    }
+
+    /// Number of attempts made before failing
+    pub fn attempts(&self) -> u32 {
+        match self {
+            BlockingError::Exhausted { attempts, .. } => *attempts,
+            _ => 1,
+        }
+    }
+
+    /// Map the inner error type
+    pub fn map<F, G: FnMut(E) -> F>(self, mut f: G) -> BlockingError<F> {
+        match self {
+            BlockingError::Inner(e) => BlockingError::Inner(f(e)),
+            BlockingError::Timeout => BlockingError::Timeout,
+            BlockingError::Exhausted { attempts, error } => BlockingError::Exhausted {
+                attempts,
+                error: error.map(f),
+            },
+            BlockingError::Oversize { len, max } => BlockingError::Oversize { len, max },
+            BlockingError::Unsupported(c) => BlockingError::Unsupported(c),
+        }
+    }
+}
+
+/// Run a blocking operation, retrying driver errors and timeouts up to `max_retries`
+/// times with the configured backoff between attempts
+fn with_retries<T, R, E, F>(
+    radio: &mut T,
+    options: &BlockingOptions,
+    mut f: F,
+) -> Result<R, BlockingError<E>>
+where
+    T: DelayNs,
+    F: FnMut(&mut T) -> Result<R, BlockingError<E>>,
+{
+    let attempts = options.max_retries.saturating_add(1);
+    let mut last = None;
+
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Retrying blocking operation (attempt {})", attempt + 1);
+            radio.delay_us(options.interval(attempt - 1).as_micros() as u32);
+        }
+
+        match f(radio) {
+            Ok(r) => return Ok(r),
+            Err(e) if options.max_retries == 0 => return Err(e),
+            Err(BlockingError::Inner(e)) => last = Some(e),
+            Err(BlockingError::Timeout) => last = None,
+            Err(BlockingError::Exhausted { error, .. }) => last = error,
+            // Errors unrelated to the radio are not retried
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(BlockingError::Exhausted {
+        attempts,
+        error: last,
+    })
+}
+
+/// Blocking operation phase, used to break down waits and timeouts
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Phase {
+    /// Waiting for transmit completion
+    TxDone = 0,
+    /// Waiting for a received packet
+    Rx = 1,
+    /// Waiting for an acknowledgement (recorded by protocols layered on blocking receive)
+    Ack = 2,
+    /// Waiting for a state change
+    State = 3,
+}
+
+/// Number of wait time histogram buckets, each covering an equal fraction of the timeout
+pub const WAIT_BUCKETS: usize = 8;
+
+/// Wait statistics for a single blocking phase
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PhaseStats {
+    /// Waits completing before the timeout
+    pub completed: u32,
+    /// Waits ending in a timeout
+    pub timeouts: u32,
+    /// Histogram of completed wait times as a fraction of the timeout
+    pub histogram: [u32; WAIT_BUCKETS],
+}
+
+impl PhaseStats {
+    /// Fraction of waits ending in a timeout
+    pub fn timeout_ratio(&self) -> f32 {
+        self.timeouts as f32 / (self.completed + self.timeouts).max(1) as f32
+    }
+}
+
+/// Blocking operation statistics by phase, distinguishing dead peers (rx / ack timeouts)
+/// from slow drivers (tx-done or state timeouts, or waits approaching the timeout)
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RadioStats {
+    pub tx_done: PhaseStats,
+    pub rx: PhaseStats,
+    pub ack: PhaseStats,
+    pub state: PhaseStats,
+}
+
+impl core::fmt::Display for RadioStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let phases = [
+            ("tx-done", &self.tx_done),
+            ("rx", &self.rx),
+            ("ack", &self.ack),
+            ("state", &self.state),
+        ];
+        for (i, (name, p)) in phases.iter().enumerate() {
+            write!(
+                f,
+                "{:<8} completed {:>6} timeouts {:>6} waits {:?}",
+                name, p.completed, p.timeouts, p.histogram
+            )?;
+            if i < phases.len() - 1 {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct PhaseCounters {
+    completed: AtomicU32,
+    timeouts: AtomicU32,
+    histogram: [AtomicU32; WAIT_BUCKETS],
+}
+
+impl PhaseCounters {
+    const fn new() -> Self {
+        Self {
+            completed: AtomicU32::new(0),
+            timeouts: AtomicU32::new(0),
+            histogram: [const { AtomicU32::new(0) }; WAIT_BUCKETS],
+        }
+    }
+
+    fn load(&self) -> PhaseStats {
+        PhaseStats {
+            completed: self.completed.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            histogram: core::array::from_fn(|i| self.histogram[i].load(Ordering::Relaxed)),
+        }
+    }
+
+    fn record(&self, waited_us: u128, timeout_us: u128, timed_out: bool) {
+        if timed_out {
+            increment(&self.timeouts);
+            return;
+        }
+
+        increment(&self.completed);
+        let b = (waited_us * WAIT_BUCKETS as u128 / timeout_us.max(1)) as usize;
+        increment(&self.histogram[b.min(WAIT_BUCKETS - 1)]);
+    }
+
+    fn reset(&self) {
+        self.completed.store(0, Ordering::Relaxed);
+        self.timeouts.store(0, Ordering::Relaxed);
+        self.histogram
+            .iter()
+            .for_each(|h| h.store(0, Ordering::Relaxed));
+    }
+}
+
+static STATS: [PhaseCounters; 4] = [const { PhaseCounters::new() }; 4];
+
+fn increment(a: &AtomicU32) {
+    #[cfg(target_has_atomic = "32")]
+    a.fetch_add(1, Ordering::Relaxed);
+
+    // Targets without atomic read-modify-write may lose concurrent updates
+    #[cfg(not(target_has_atomic = "32"))]
+    a.store(a.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+}
+
+/// Record the outcome of a blocking wait of `waited_us` against a timeout of `timeout_us`
+pub fn record_wait(phase: Phase, waited_us: u128, timeout_us: u128, timed_out: bool) {
+    STATS[phase as usize].record(waited_us, timeout_us, timed_out);
+}
+
+/// Fetch blocking operation statistics accumulated since startup (or the last reset)
+pub fn radio_stats() -> RadioStats {
+    RadioStats {
+        tx_done: STATS[Phase::TxDone as usize].load(),
+        rx: STATS[Phase::Rx as usize].load(),
+        ack: STATS[Phase::Ack as usize].load(),
+        state: STATS[Phase::State as usize].load(),
+    }
+}
+
+/// Reset blocking operation statistics
+pub fn reset_radio_stats() {
+    STATS.iter().for_each(|c| c.reset());
+}
+
 /// Blocking transmit function implemented over `radio::Transmit` and `radio::Power` using the provided
 /// `BlockingOptions` and radio-internal `DelayUs` impl to poll for completion
 #[cfg_attr(
@@ -107,33 +397,51 @@ where
         data: &[u8],
         tx_options: BlockingOptions,
     ) -> Result<(), BlockingError<E>> {
-        // Enter transmit mode
-        self.start_transmit(data)?;
-
-        let t = tx_options.timeout.as_micros();
-        let mut c = 0;
-        loop {
-            // Check for transmit complete
-            if self.check_transmit()? {
-                #[cfg(any(feature = "log", feature = "defmt"))]
-                debug!("Blocking send complete");
-                break;
-            }
+        with_retries(self, &tx_options, |r| transmit_once(r, data, &tx_options))
+    }
+}
 
-            // Update poll time and timeout if overrun
-            c += tx_options.poll_interval.as_micros();
-            if c > t {
-                #[cfg(any(feature = "log", feature = "defmt"))]
-                debug!("Blocking send timeout");
-                return Err(BlockingError::Timeout);
-            }
+/// Single blocking transmit attempt
+fn transmit_once<T, E>(
+    radio: &mut T,
+    data: &[u8],
+    tx_options: &BlockingOptions,
+) -> Result<(), BlockingError<E>>
+where
+    T: Transmit<Error = E> + DelayNs,
+    E: Debug,
+{
+    // Enter transmit mode
+    radio.start_transmit(data)?;
+
+    let t = tx_options.timeout.as_micros();
+    let mut c = 0;
+    let mut n = 0;
+    loop {
+        // Check for transmit complete
+        if radio.check_transmit()? {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Blocking send complete");
+            record_wait(Phase::TxDone, c, t, false);
+            break;
+        }
 
-            // Wait for next poll
-            self.delay_us(tx_options.poll_interval.as_micros() as u32);
+        // Update poll time and timeout if overrun
+        let interval = tx_options.interval(n);
+        c += interval.as_micros();
+        if c > t {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Blocking send timeout");
+            record_wait(Phase::TxDone, c, t, true);
+            return Err(BlockingError::Timeout);
         }
 
-        Ok(())
+        // Wait for next poll
+        radio.delay_us(interval.as_micros() as u32);
+        n += 1;
     }
+
+    Ok(())
 }
 
 /// Blocking receive function implemented over `radio::Receive` using the provided `BlockingOptions`
@@ -194,26 +502,44 @@ where
         buff: &mut [u8],
         rx_options: BlockingOptions,
     ) -> Result<(usize, I), BlockingError<E>> {
-        // Start receive mode
-        self.start_receive()?;
-
-        let t = rx_options.timeout.as_micros();
-        let mut c = 0;
-        loop {
-            if self.check_receive(true)? {
-                let (n, i) = self.get_received(buff)?;
-                return Ok((n, i));
-            }
+        with_retries(self, &rx_options, |r| receive_once(r, buff, &rx_options))
+    }
+}
 
-            c += rx_options.poll_interval.as_micros();
-            if c > t {
-                #[cfg(any(feature = "log", feature = "defmt"))]
-                debug!("Blocking receive timeout");
-                return Err(BlockingError::Timeout);
-            }
+/// Single blocking receive attempt
+fn receive_once<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    rx_options: &BlockingOptions,
+) -> Result<(usize, I), BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    E: Debug,
+{
+    // Start receive mode
+    radio.start_receive()?;
+
+    let t = rx_options.timeout.as_micros();
+    let mut c = 0;
+    let mut p = 0;
+    loop {
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(buff)?;
+            record_wait(Phase::Rx, c, t, false);
+            return Ok((n, i));
+        }
 
-            self.delay_us(rx_options.poll_interval.as_micros() as u32);
+        let interval = rx_options.interval(p);
+        c += interval.as_micros();
+        if c > t {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Blocking receive timeout");
+            record_wait(Phase::Rx, c, t, true);
+            return Err(BlockingError::Timeout);
         }
+
+        radio.delay_us(interval.as_micros() as u32);
+        p += 1;
     }
 }
 
@@ -242,6 +568,7 @@ where
 
         let t = options.timeout.as_micros();
         let mut c = 0;
+        let mut n = 0;
 
         loop {
             // Fetch state
@@ -249,19 +576,123 @@ where
 
             // Check for expected state
             if state == s {
+                record_wait(Phase::State, c, t, false);
                 return Ok(());
             }
 
             // Timeout eventually
-            c += options.poll_interval.as_micros();
+            let interval = options.interval(n);
+            c += interval.as_micros();
             if c > t {
                 #[cfg(any(feature = "log", feature = "defmt"))]
-                debug!("Blocking receive timeout");
+                debug!("Blocking set state timeout");
+                record_wait(Phase::State, c, t, true);
                 return Err(BlockingError::Timeout);
             }
 
             // Delay before next loop
-            self.delay_us(options.poll_interval.as_micros() as u32);
+            self.delay_us(interval.as_micros() as u32);
+            n += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_histogram() {
+        let c = PhaseCounters::new();
+        c.record(0, 1_000, false);
+        c.record(990, 1_000, false);
+        c.record(1_200, 1_000, false);
+        c.record(1_100, 1_000, true);
+
+        let s = c.load();
+        assert_eq!((s.completed, s.timeouts), (3, 1));
+        assert_eq!(s.histogram, [1, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(s.timeout_ratio(), 0.25);
+
+        c.reset();
+        assert_eq!(c.load(), PhaseStats::default());
+    }
+
+    /// Radio failing a number of transmit starts, completing after a number of polls
+    struct Flaky {
+        failures: u32,
+        polls: u32,
+        delays: [u32; 8],
+        n: usize,
+    }
+
+    impl Transmit for Flaky {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            match self.failures {
+                0 => Ok(()),
+                _ => {
+                    self.failures -= 1;
+                    Err(())
+                }
+            }
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            self.polls = self.polls.saturating_sub(1);
+            Ok(self.polls == 0)
+        }
+    }
+
+    impl DelayNs for Flaky {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delays[self.n] = ns / 1_000;
+            self.n += 1;
+        }
+    }
+
+    #[test]
+    fn backoff_and_retries() {
+        let options = BlockingOptions {
+            poll_backoff: Backoff::Linear,
+            max_poll_interval: Duration::from_micros(250),
+            max_retries: 2,
+            ..Default::default()
+        };
+        let mut radio = Flaky {
+            failures: 1,
+            polls: 4,
+            delays: [0; 8],
+            n: 0,
+        };
+
+        // One retry following the driver error, then linear poll backoff capped at the maximum
+        assert_eq!(radio.do_transmit(&[0xaa], options.clone()), Ok(()));
+        assert_eq!(&radio.delays[..radio.n], &[100, 100, 200, 250]);
+
+        // Attempts are reported once retries are exhausted
+        radio.failures = 3;
+        let e = radio.do_transmit(&[0xaa], options.clone()).unwrap_err();
+        assert_eq!(
+            e,
+            BlockingError::Exhausted {
+                attempts: 3,
+                error: Some(())
+            }
+        );
+        assert_eq!((e.attempts(), e.is_timeout()), (3, false));
+
+        // Exponential backoff doubles up to the maximum, with up to 50% jitter
+        let exp = BlockingOptions {
+            poll_backoff: Backoff::Exponential,
+            max_poll_interval: Duration::from_millis(1),
+            ..options
+        };
+        for n in 0..8 {
+            let full = (100u64 << n).min(1_000);
+            let i = exp.interval(n).as_micros() as u64;
+            assert!(i <= full && i >= full / 2 - 1, "{n}: {i}");
         }
     }
 }
@@ -0,0 +1,377 @@
+//! Clear channel assessment (listen-before-talk)
+//!
+//! Regulatory limits in some bands (eg. 868 MHz) require listen-before-talk. [`CcaTransmit`]
+//! wraps a radio, polling RSSI before each transmission and backing off for a random
+//! interval while the channel is above the configured threshold, so all helpers
+//! transmitting via the wrapper observe LBT.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::blocking::BlockingError;
+use crate::prng::XorShift32;
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// CcaOptions configure listen-before-talk prior to transmission
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CcaOptions {
+    /// Channel busy threshold in dBm, enables listen-before-talk when set
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub cca_threshold: Option<i16>,
+
+    /// Maximum random backoff while the channel is busy
+    #[cfg_attr(feature="clap", clap(long, default_value="10ms", value_parser=crate::duration_from_str))]
+    pub cca_backoff: Duration,
+
+    /// Channel assessments before failing a transmission
+    #[cfg_attr(feature = "clap", clap(long, default_value = "5"))]
+    pub cca_attempts: u8,
+}
+
+impl Default for CcaOptions {
+    fn default() -> Self {
+        Self {
+            cca_threshold: None,
+            cca_backoff: Duration::from_millis(10),
+            cca_attempts: 5,
+        }
+    }
+}
+
+/// Clear channel assessment statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CcaStats {
+    /// Channel assessments performed
+    pub checks: u32,
+    /// Assessments finding the channel busy
+    pub busy: u32,
+    /// Transmissions delayed by at least one backoff
+    pub deferred: u32,
+    /// Transmissions abandoned with the channel busy
+    pub failed: u32,
+}
+
+impl CcaStats {
+    /// Fraction of assessments finding the channel busy
+    pub fn busy_ratio(&self) -> f32 {
+        match self.checks {
+            0 => 0.0,
+            n => self.busy as f32 / n as f32,
+        }
+    }
+}
+
+impl core::fmt::Display for CcaStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "checks {} busy {} ({:.1}%) deferred {} failed {}",
+            self.checks,
+            self.busy,
+            self.busy_ratio() * 100.0,
+            self.deferred,
+            self.failed
+        )
+    }
+}
+
+/// Errors from a radio wrapped in [`CcaTransmit`]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CcaError<E> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(E),
+    /// Channel remained busy for all assessments
+    #[cfg_attr(feature = "thiserror", error("Channel busy"))]
+    ChannelBusy,
+}
+
+impl<E> BlockingError<CcaError<E>> {
+    /// Unwrap CCA errors, mapping busy channels to `BlockingError::Timeout`
+    pub fn flatten(self) -> BlockingError<E> {
+        match self {
+            BlockingError::Inner(CcaError::Radio(e)) => BlockingError::Inner(e),
+            BlockingError::Inner(CcaError::ChannelBusy) | BlockingError::Timeout => {
+                BlockingError::Timeout
+            }
+            BlockingError::Exhausted { attempts, error } => BlockingError::Exhausted {
+                attempts,
+                error: match error {
+                    Some(CcaError::Radio(e)) => Some(e),
+                    _ => None,
+                },
+            },
+            BlockingError::Oversize { len, max } => BlockingError::Oversize { len, max },
+            BlockingError::Unsupported(c) => BlockingError::Unsupported(c),
+        }
+    }
+}
+
+/// CcaTransmit wraps a radio with listen-before-talk
+///
+/// Prior to each transmission the radio is placed in receive mode and RSSI compared with
+/// the configured threshold, backing off for a random interval up to `cca_backoff` while
+/// the channel is busy. Without a threshold transmissions are passed through unchanged.
+pub struct CcaTransmit<T> {
+    radio: T,
+    options: CcaOptions,
+    rng: XorShift32,
+    stats: CcaStats,
+}
+
+impl<T> CcaTransmit<T> {
+    /// Wrap a radio, seeding the backoff generator (seeds should differ between nodes)
+    pub fn new(radio: T, options: CcaOptions, seed: u32) -> Self {
+        Self {
+            radio,
+            options,
+            rng: XorShift32::new(seed),
+            stats: CcaStats::default(),
+        }
+    }
+
+    /// Clear channel assessment statistics
+    pub fn stats(&self) -> &CcaStats {
+        &self.stats
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+}
+
+impl<T, E> CcaTransmit<T>
+where
+    T: Receive<Error = E> + Rssi<Error = E> + DelayNs,
+    E: Debug,
+{
+    /// Wait for a clear channel, returning `CcaError::ChannelBusy` once attempts are exhausted
+    pub fn wait_clear(&mut self) -> Result<(), CcaError<E>> {
+        let threshold = match self.options.cca_threshold {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        self.radio.start_receive().map_err(CcaError::Radio)?;
+
+        for attempt in 0..self.options.cca_attempts {
+            let rssi = self.radio.poll_rssi().map_err(CcaError::Radio)?;
+            self.stats.checks += 1;
+
+            if rssi < threshold {
+                if attempt > 0 {
+                    self.stats.deferred += 1;
+                }
+                return Ok(());
+            }
+
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Channel busy (rssi: {}), backing off", rssi);
+
+            self.stats.busy += 1;
+            let backoff = self.rng.below(self.options.cca_backoff.as_micros() as u32);
+            self.radio.delay_us(backoff);
+        }
+
+        self.stats.failed += 1;
+
+        Err(CcaError::ChannelBusy)
+    }
+}
+
+impl<T, E> Transmit for CcaTransmit<T>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + Rssi<Error = E> + DelayNs,
+    E: Debug,
+{
+    type Error = CcaError<E>;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.wait_clear()?;
+        self.radio.start_transmit(data).map_err(CcaError::Radio)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit().map_err(CcaError::Radio)
+    }
+}
+
+impl<T, E> Receive for CcaTransmit<T>
+where
+    T: Receive<Error = E>,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = CcaError<E>;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive().map_err(CcaError::Radio)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio.check_receive(restart).map_err(CcaError::Radio)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff).map_err(CcaError::Radio)
+    }
+}
+
+impl<T, E> Power for CcaTransmit<T>
+where
+    T: Power<Error = E>,
+    E: Debug,
+{
+    type Error = CcaError<E>;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power).map_err(CcaError::Radio)
+    }
+}
+
+impl<T, E> Rssi for CcaTransmit<T>
+where
+    T: Rssi<Error = E>,
+    E: Debug,
+{
+    type Error = CcaError<E>;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi().map_err(CcaError::Radio)
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out).map_err(CcaError::Radio)
+    }
+}
+
+impl<T, E> Channel for CcaTransmit<T>
+where
+    T: Channel<Error = E>,
+    E: Debug,
+{
+    type Channel = T::Channel;
+    type Error = CcaError<E>;
+
+    fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+        self.radio.set_channel(channel).map_err(CcaError::Radio)
+    }
+}
+
+impl<T: DelayNs> DelayNs for CcaTransmit<T> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio reporting a fixed number of busy RSSI readings before clearing
+    struct BusyRadio {
+        busy: u32,
+        sent: u32,
+    }
+
+    impl Transmit for BusyRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            self.sent += 1;
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for BusyRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Ok((0, BasicInfo::default()))
+        }
+    }
+
+    impl Rssi for BusyRadio {
+        type Error = ();
+
+        fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+            match self.busy {
+                0 => Ok(-110),
+                _ => {
+                    self.busy -= 1;
+                    Ok(-60)
+                }
+            }
+        }
+    }
+
+    impl DelayNs for BusyRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn listen_before_talk() {
+        let options = CcaOptions {
+            cca_threshold: Some(-90),
+            ..Default::default()
+        };
+        let mut r = CcaTransmit::new(BusyRadio { busy: 2, sent: 0 }, options, 1);
+
+        // Transmission is deferred until the channel clears
+        r.start_transmit(&[1, 2, 3]).unwrap();
+        assert_eq!(r.inner().sent, 1);
+        assert_eq!(
+            r.stats(),
+            &CcaStats {
+                checks: 3,
+                busy: 2,
+                deferred: 1,
+                failed: 0
+            }
+        );
+
+        // Transmission fails once attempts are exhausted
+        r.inner().busy = 10;
+        assert_eq!(r.start_transmit(&[1]), Err(CcaError::ChannelBusy));
+        assert_eq!(r.inner().sent, 1);
+        assert_eq!(r.stats().failed, 1);
+    }
+}
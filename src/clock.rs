@@ -0,0 +1,201 @@
+//! Clock abstraction for timing without platform time sources
+//!
+//! Helper and simulation code reads time through [`Clock`] rather than `std::time`,
+//! allowing the [`VirtualClock`] to stand in on targets without a system clock (such as
+//! `wasm32-unknown-unknown`, for browser-based demos) and in deterministic tests, where
+//! time advances only when explicitly stepped or when a delay is requested.
+//! Multi-threaded simulations share a [`ScaledClock`], running at a multiple of real time
+//! so long-running (eg. duty-cycled) behaviour can be soaked in seconds.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::cell::Cell;
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+/// Monotonic clock, in microseconds since an arbitrary epoch
+pub trait Clock {
+    /// Fetch the current time in microseconds
+    fn now_us(&self) -> u64;
+
+    /// Microseconds elapsed since the provided time
+    fn elapsed_us(&self, since_us: u64) -> u64 {
+        self.now_us().saturating_sub(since_us)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now_us(&self) -> u64 {
+        (**self).now_us()
+    }
+}
+
+/// Virtual clock, advanced explicitly or by delays
+///
+/// Delays on a (shared reference to a) virtual clock return immediately, advancing
+/// time by the requested duration.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+    now_us: Cell<u64>,
+}
+
+impl VirtualClock {
+    /// Create a new virtual clock starting at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the current time in microseconds
+    pub fn set_us(&self, now_us: u64) {
+        self.now_us.set(now_us);
+    }
+
+    /// Advance the clock by the provided number of microseconds
+    pub fn advance_us(&self, us: u64) {
+        self.now_us.set(self.now_us.get().saturating_add(us));
+    }
+
+    /// Advance the clock by the provided duration
+    pub fn advance(&self, d: Duration) {
+        self.advance_us(d.as_micros() as u64);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now_us(&self) -> u64 {
+        self.now_us.get()
+    }
+}
+
+impl DelayNs for &VirtualClock {
+    fn delay_ns(&mut self, ns: u32) {
+        self.advance_us((ns as u64).div_ceil(1000));
+    }
+}
+
+impl DelayNs for VirtualClock {
+    fn delay_ns(&mut self, ns: u32) {
+        (&*self).delay_ns(ns)
+    }
+}
+
+/// System monotonic clock (`std::time::Instant`)
+///
+/// Note that this panics on targets without a system clock such as `wasm32-unknown-unknown`,
+/// where a [`VirtualClock`] should be used instead.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StdClock;
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    fn now_us(&self) -> u64 {
+        static EPOCH: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        EPOCH
+            .get_or_init(std::time::Instant::now)
+            .elapsed()
+            .as_micros() as u64
+    }
+}
+
+/// System wall clock (`std::time::SystemTime`), in microseconds since the unix epoch
+///
+/// Used where times must remain comparable across restarts (such as persisted frames),
+/// and like [`StdClock`] panics on targets without a system clock.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now_us(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64
+    }
+}
+
+/// Accelerated clock, running at `scale` times the system monotonic clock
+///
+/// Copies share an epoch, so simulated nodes on separate threads observe the same time,
+/// while delays sleep for the requested duration divided by the scale.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScaledClock {
+    epoch: std::time::Instant,
+    scale: f64,
+}
+
+#[cfg(feature = "std")]
+impl ScaledClock {
+    /// Create a clock starting at zero, advancing `scale` times faster than real time
+    pub fn new(scale: f64) -> Self {
+        Self {
+            epoch: std::time::Instant::now(),
+            scale: scale.max(f64::MIN_POSITIVE),
+        }
+    }
+
+    /// Time scale relative to real time
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for ScaledClock {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for ScaledClock {
+    fn now_us(&self) -> u64 {
+        (self.epoch.elapsed().as_micros() as f64 * self.scale) as u64
+    }
+}
+
+#[cfg(feature = "std")]
+impl DelayNs for ScaledClock {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos((ns as f64 / self.scale) as u64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_clock_delays() {
+        let clock = VirtualClock::new();
+        assert_eq!(clock.now_us(), 0);
+
+        // Delays advance time, rounding up to whole microseconds
+        (&clock).delay_ms(5);
+        (&clock).delay_ns(1);
+        assert_eq!(clock.now_us(), 5_001);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.elapsed_us(5_001), 1_000_000);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn scaled_clock_acceleration() {
+        let mut clock = ScaledClock::new(1000.0);
+        let start = std::time::Instant::now();
+
+        // A virtual second passes in a millisecond of real time
+        clock.delay_ms(1_000);
+        assert!(clock.now_us() >= 1_000_000);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // Copies share the clock epoch
+        let copy = clock;
+        assert!(copy.now_us() >= 1_000_000);
+    }
+}
@@ -0,0 +1,134 @@
+//! LZ4 payload compression
+//!
+//! Compresses payloads for text-heavy telemetry over slow links, with each payload
+//! encoded as `[mode, ..]`: `[RAW, payload..]` where compression does not reduce the
+//! payload length, or `[LZ4, original length (BE u16), LZ4 block..]` otherwise, so
+//! incompressible payloads cost a single byte and receivers handle either form.
+//!
+//! Compression applies to whole payloads ahead of encryption and fragmentation, so
+//! payloads larger than a frame still benefit.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+/// Mode byte for payloads sent uncompressed
+pub const MODE_RAW: u8 = 0x00;
+
+/// Mode byte for LZ4 block compressed payloads
+pub const MODE_LZ4: u8 = 0x01;
+
+/// Header length for LZ4 compressed payloads (mode and original length)
+pub const LZ4_HEADER_LEN: usize = 3;
+
+/// Buffer length required to encode a payload of `len` bytes
+///
+/// This allows for the worst case LZ4 block, encoded payloads never exceed `len + 1` bytes.
+pub const fn max_compressed_len(len: usize) -> usize {
+    LZ4_HEADER_LEN + lz4_flex::block::get_maximum_output_size(len)
+}
+
+/// CompressError describes failures compressing or decompressing payloads
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CompressError {
+    /// Provided buffer is too small for the encoded or decoded payload
+    #[cfg_attr(feature = "thiserror", error("Buffer too small"))]
+    BufferTooSmall,
+    /// Received payload has an unrecognised mode or invalid length
+    #[cfg_attr(feature = "thiserror", error("Invalid payload"))]
+    Invalid,
+    /// LZ4 block failed to decompress
+    #[cfg_attr(feature = "thiserror", error("Corrupt LZ4 block"))]
+    Corrupt,
+}
+
+/// Compress a payload into `out`, returning the encoded length
+///
+/// Payloads that do not compress (or exceed `u16::MAX` bytes) are sent raw, `out` must
+/// be at least [`max_compressed_len`] bytes.
+pub fn compress(payload: &[u8], out: &mut [u8]) -> Result<usize, CompressError> {
+    if out.len() < max_compressed_len(payload.len()) {
+        return Err(CompressError::BufferTooSmall);
+    }
+
+    // Use compressed blocks only where shorter than the raw encoding
+    if payload.len() <= u16::MAX as usize
+        && let Ok(n) = lz4_flex::block::compress_into(payload, &mut out[LZ4_HEADER_LEN..])
+        && n + LZ4_HEADER_LEN <= payload.len()
+    {
+        out[0] = MODE_LZ4;
+        out[1..LZ4_HEADER_LEN].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+        return Ok(n + LZ4_HEADER_LEN);
+    }
+
+    out[0] = MODE_RAW;
+    out[1..][..payload.len()].copy_from_slice(payload);
+    Ok(payload.len() + 1)
+}
+
+/// Decoded length of a compressed payload, for sizing the decompression buffer
+pub fn decompressed_len(data: &[u8]) -> Result<usize, CompressError> {
+    match data {
+        [MODE_RAW, p @ ..] => Ok(p.len()),
+        [MODE_LZ4, a, b, ..] => Ok(u16::from_be_bytes([*a, *b]) as usize),
+        _ => Err(CompressError::Invalid),
+    }
+}
+
+/// Decompress a payload into `out`, returning the decoded length
+pub fn decompress(data: &[u8], out: &mut [u8]) -> Result<usize, CompressError> {
+    let n = decompressed_len(data)?;
+    if n > out.len() {
+        return Err(CompressError::BufferTooSmall);
+    }
+
+    match data[0] {
+        MODE_RAW => out[..n].copy_from_slice(&data[1..]),
+        _ => match lz4_flex::block::decompress_into(&data[LZ4_HEADER_LEN..], &mut out[..n]) {
+            Ok(d) if d == n => (),
+            Ok(_) => return Err(CompressError::Invalid),
+            Err(_) => return Err(CompressError::Corrupt),
+        },
+    }
+
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_payloads() {
+        let mut enc = [0u8; 256];
+        let mut dec = [0u8; 128];
+
+        // Repetitive telemetry compresses
+        let text = b"temp=21.5,temp=21.5,temp=21.5,temp=21.5,temp=21.5,temp=21.5";
+        let n = compress(text, &mut enc).unwrap();
+        assert_eq!(enc[0], MODE_LZ4);
+        assert!(n < text.len());
+        assert_eq!(decompress(&enc[..n], &mut dec), Ok(text.len()));
+        assert_eq!(&dec[..text.len()], text);
+
+        // Short or random payloads are sent raw, with a single byte overhead
+        let n = compress(&[1, 2, 3], &mut enc).unwrap();
+        assert_eq!(&enc[..n], &[MODE_RAW, 1, 2, 3]);
+        assert_eq!(decompress(&enc[..n], &mut dec), Ok(3));
+
+        // Invalid and corrupt payloads are rejected
+        assert_eq!(
+            decompress(&[0x7f, 1], &mut dec),
+            Err(CompressError::Invalid)
+        );
+        assert_eq!(decompress(&[], &mut dec), Err(CompressError::Invalid));
+        assert_eq!(
+            decompress(&[MODE_LZ4, 0, 60, 0xff], &mut dec),
+            Err(CompressError::Corrupt)
+        );
+        assert_eq!(
+            compress(text, &mut [0u8; 64]),
+            Err(CompressError::BufferTooSmall)
+        );
+    }
+}
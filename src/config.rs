@@ -1,8 +1,12 @@
 //! Config provides traits for standard radio configuration
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 /// Radio configuration options
 #[non_exhaustive]
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ConfigOption {
     /// MAC address
     MAC([u8; 6]),
@@ -23,6 +27,14 @@ pub enum ConfigOption {
     MTU(u16),
     /// Transmit power (dBm)
     TXPower(i16),
+    /// Radio channel (driver-specific channel number or frequency in Hz)
+    Channel(u32),
+    /// Carrier frequency in Hz
+    Frequency(u64),
+    /// Sync word (right-aligned, driver-specific length)
+    SyncWord(u64),
+    /// Preamble length (driver-specific units, typically bytes or symbols)
+    PreambleLength(u16),
 
     /// Await Clear Channel before TX (if supported)
     AwaitCCA(bool),
@@ -32,6 +44,15 @@ pub enum ConfigOption {
     AutoAck(bool),
     /// Promiscuous mode (if supported) disables hardware address filtering
     Promiscuous(bool),
+
+    /// (G)FSK bitrate in bits per second
+    Bitrate(u32),
+    /// (G)FSK frequency deviation in Hz
+    Deviation(u32),
+    /// LoRa spreading factor
+    SpreadingFactor(u8),
+    /// LoRa bandwidth in Hz
+    Bandwidth(u32),
 }
 
 /// Radio configuration errors
@@ -47,6 +68,80 @@ pub enum ConfigError<E> {
     Other(E),
 }
 
+/// Maximum number of options captured in a [`RadioState`]
+pub const STATE_OPTIONS: usize = 8;
+
+/// Number of application counters saved in a [`RadioState`]
+pub const STATE_COUNTERS: usize = 4;
+
+/// Radio state snapshot for warm-start, allowing a process restart (or wake from deep
+/// sleep, with the state held in retained memory) to resume without a full reconfiguration
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RadioState {
+    /// Captured configuration options
+    pub options: [Option<ConfigOption>; STATE_OPTIONS],
+    /// Application counters (eg. sequence numbers or frame counters) saved alongside
+    pub counters: [u32; STATE_COUNTERS],
+}
+
+impl RadioState {
+    /// Fetch a captured option matching the variant of the provided option
+    pub fn option(&self, o: &ConfigOption) -> Option<&ConfigOption> {
+        self.options
+            .iter()
+            .flatten()
+            .find(|s| core::mem::discriminant(*s) == core::mem::discriminant(o))
+    }
+}
+
+/// Declarative radio configuration profile, applied with [`Configure::apply_config`]
+///
+/// Unset fields leave the radio configuration unchanged. With the `serde` feature profiles
+/// may be loaded from configuration files (see `ConfigFileOptions` in the helpers).
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct RadioConfig {
+    /// Carrier frequency in Hz
+    pub frequency: Option<u64>,
+    /// Radio channel (driver-specific channel number)
+    pub channel: Option<u32>,
+    /// Transmit power (dBm)
+    pub power: Option<i16>,
+    /// (G)FSK bitrate in bits per second
+    pub bitrate: Option<u32>,
+    /// (G)FSK frequency deviation in Hz
+    pub deviation: Option<u32>,
+    /// LoRa spreading factor
+    pub spreading_factor: Option<u8>,
+    /// LoRa bandwidth in Hz
+    pub bandwidth: Option<u32>,
+    /// Sync word (right-aligned, driver-specific length)
+    pub sync_word: Option<u64>,
+    /// Preamble length (driver-specific units)
+    pub preamble_length: Option<u16>,
+}
+
+impl RadioConfig {
+    /// Configuration options set by this profile, in the order they are applied
+    pub fn options(&self) -> impl Iterator<Item = ConfigOption> {
+        [
+            self.frequency.map(ConfigOption::Frequency),
+            self.channel.map(ConfigOption::Channel),
+            self.bitrate.map(ConfigOption::Bitrate),
+            self.deviation.map(ConfigOption::Deviation),
+            self.spreading_factor.map(ConfigOption::SpreadingFactor),
+            self.bandwidth.map(ConfigOption::Bandwidth),
+            self.sync_word.map(ConfigOption::SyncWord),
+            self.preamble_length.map(ConfigOption::PreambleLength),
+            self.power.map(ConfigOption::TXPower),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
 /// Configure trait implemented by configurable radios
 pub trait Configure {
     /// Radio error
@@ -60,4 +155,111 @@ pub trait Configure {
     /// This will overwrite the value of the provided option enum
     /// Returns Ok(true) on successful get, Ok(false) for unsupported options, Err(Self::Error) for errors
     fn get_option(&mut self, o: &mut ConfigOption) -> Result<(), ConfigError<Self::Error>>;
+
+    /// Capture the provided options (eg. channel, power and sync word) into a [`RadioState`]
+    /// for warm-start, skipping options not supported by the radio
+    ///
+    /// Up to [`STATE_OPTIONS`] supported options are captured.
+    fn save_state(
+        &mut self,
+        options: &[ConfigOption],
+    ) -> Result<RadioState, ConfigError<Self::Error>> {
+        let mut state = RadioState::default();
+        let mut n = 0;
+
+        for o in options {
+            if n == STATE_OPTIONS {
+                break;
+            }
+
+            let mut o = o.clone();
+            match self.get_option(&mut o) {
+                Ok(()) => {
+                    state.options[n] = Some(o);
+                    n += 1;
+                }
+                Err(ConfigError::NotSupported) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Restore options captured with [`Configure::save_state`]
+    fn restore_state(&mut self, state: &RadioState) -> Result<(), ConfigError<Self::Error>> {
+        for o in state.options.iter().flatten() {
+            self.set_option(o)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the options set in a [`RadioConfig`] profile, failing on the first option
+    /// not supported by the radio
+    fn apply_config(&mut self, config: &RadioConfig) -> Result<(), ConfigError<Self::Error>> {
+        for o in config.options() {
+            self.set_option(&o)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Radio supporting channel and power options
+    #[derive(Default)]
+    struct Settings {
+        channel: u32,
+        power: i16,
+    }
+
+    impl Configure for Settings {
+        type Error = ();
+
+        fn set_option(&mut self, o: &ConfigOption) -> Result<(), ConfigError<Self::Error>> {
+            match o {
+                ConfigOption::Channel(c) => self.channel = *c,
+                ConfigOption::TXPower(p) => self.power = *p,
+                _ => return Err(ConfigError::NotSupported),
+            }
+            Ok(())
+        }
+
+        fn get_option(&mut self, o: &mut ConfigOption) -> Result<(), ConfigError<Self::Error>> {
+            *o = match o {
+                ConfigOption::Channel(_) => ConfigOption::Channel(self.channel),
+                ConfigOption::TXPower(_) => ConfigOption::TXPower(self.power),
+                _ => return Err(ConfigError::NotSupported),
+            };
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn warm_start() {
+        let mut a = Settings {
+            channel: 11,
+            power: 10,
+        };
+        let mut s = a
+            .save_state(&[
+                ConfigOption::Channel(0),
+                ConfigOption::TXPower(0),
+                ConfigOption::SyncWord(0),
+            ])
+            .unwrap();
+        s.counters[0] = 42;
+
+        assert_eq!(s.options.iter().flatten().count(), 2);
+        assert_eq!(
+            s.option(&ConfigOption::Channel(0)),
+            Some(&ConfigOption::Channel(11))
+        );
+
+        let mut b = Settings::default();
+        b.restore_state(&s).unwrap();
+        assert_eq!((b.channel, b.power), (11, 10));
+    }
 }
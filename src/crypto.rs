@@ -0,0 +1,272 @@
+//! AES-128-CCM payload encryption
+//!
+//! Encrypts and authenticates payloads for links on shared bands, with each encrypted
+//! payload encoded as `[salt (BE), counter (BE), ciphertext, tag]`. The 13 byte CCM nonce
+//! is derived from a 32-bit per-sender session salt and the 32-bit transmit counter, so
+//! nodes sharing a key must use distinct salts (random for each session, see
+//! [`Cipher::new`]). Counters wrap (so may start at any value), with nonces unique for
+//! 2^32 payloads after which they repeat, so a session must be restarted with a new salt
+//! before sending 2^32 payloads.
+//!
+//! Receivers should reject payloads with counters not above the highest seen for the
+//! sending session (see [`Nonce::follows`]) to prevent replays.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use aes::Aes128;
+use ccm::{
+    AeadInPlace, Ccm, KeyInit,
+    consts::{U8, U13},
+};
+
+/// AES-128 key length in bytes
+pub const KEY_LEN: usize = 16;
+
+/// Session salt length in bytes
+pub const SALT_LEN: usize = 4;
+
+/// Transmit counter length in bytes
+pub const COUNTER_LEN: usize = 4;
+
+/// Nonce header (salt and counter) length in bytes
+pub const NONCE_LEN: usize = SALT_LEN + COUNTER_LEN;
+
+/// Authentication tag length in bytes
+pub const TAG_LEN: usize = 8;
+
+/// Encryption overhead per payload in bytes
+pub const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// AES-128 key
+pub type Key = [u8; KEY_LEN];
+
+type Aes128Ccm = Ccm<Aes128, U8, U13>;
+
+/// CryptoError describes failures encrypting or decrypting payloads
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CryptoError {
+    /// Key is not 32 hex characters
+    #[cfg_attr(feature = "thiserror", error("Invalid key"))]
+    InvalidKey,
+    /// Received data is too short to contain a nonce and tag
+    #[cfg_attr(feature = "thiserror", error("Payload too short"))]
+    TooShort,
+    /// Provided buffer is too small for the encrypted or decrypted payload
+    #[cfg_attr(feature = "thiserror", error("Buffer too small"))]
+    BufferTooSmall,
+    /// Authentication failed, due to corruption or an incorrect key
+    #[cfg_attr(feature = "thiserror", error("Authentication failed"))]
+    Authentication,
+    /// Counter not above the highest received from the sending session
+    #[cfg_attr(feature = "thiserror", error("Replayed payload"))]
+    Replay,
+}
+
+/// Nonce of an encrypted payload, identifying the sending session and payload
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Nonce {
+    /// Sender session salt
+    pub salt: u32,
+    /// Sender transmit counter
+    pub counter: u32,
+}
+
+impl Nonce {
+    /// Check whether the nonce follows the provided (highest seen) counter from the same
+    /// session, allowing for counter wrapping
+    pub fn follows(&self, counter: u32) -> bool {
+        (self.counter.wrapping_sub(counter) as i32) > 0
+    }
+
+    /// Encode the nonce header
+    fn encode(&self) -> [u8; NONCE_LEN] {
+        let mut b = [0u8; NONCE_LEN];
+        b[..SALT_LEN].copy_from_slice(&self.salt.to_be_bytes());
+        b[SALT_LEN..].copy_from_slice(&self.counter.to_be_bytes());
+        b
+    }
+
+    /// Decode a nonce header
+    fn decode(b: &[u8]) -> Self {
+        Self {
+            salt: u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            counter: u32::from_be_bytes([b[4], b[5], b[6], b[7]]),
+        }
+    }
+
+    /// Derive the 13 byte CCM nonce
+    fn ccm(&self) -> [u8; 13] {
+        let mut n = [0u8; 13];
+        n[13 - NONCE_LEN..].copy_from_slice(&self.encode());
+        n
+    }
+}
+
+/// Parse a key from 32 hex characters (optionally `0x` prefixed)
+pub fn parse_key(s: &str) -> Result<Key, CryptoError> {
+    let s = s.trim_start_matches("0x");
+    if s.len() != KEY_LEN * 2 {
+        return Err(CryptoError::InvalidKey);
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    for (i, k) in key.iter_mut().enumerate() {
+        *k = s
+            .get(i * 2..i * 2 + 2)
+            .and_then(|b| u8::from_str_radix(b, 16).ok())
+            .ok_or(CryptoError::InvalidKey)?;
+    }
+
+    Ok(key)
+}
+
+/// AES-128-CCM payload cipher, tracking the session salt and transmit counter
+#[derive(Clone)]
+pub struct Cipher {
+    ccm: Aes128Ccm,
+    nonce: Nonce,
+}
+
+impl Cipher {
+    /// Create a cipher with the provided key, session salt and initial transmit counter
+    ///
+    /// The salt must be unique to the session for all nodes sharing the key (for example
+    /// drawn at random on start) as must the counter where a salt is persisted.
+    pub fn new(key: &Key, salt: u32, counter: u32) -> Self {
+        Self {
+            ccm: Aes128Ccm::new(key.into()),
+            nonce: Nonce { salt, counter },
+        }
+    }
+
+    /// Session salt
+    pub fn salt(&self) -> u32 {
+        self.nonce.salt
+    }
+
+    /// Current transmit counter
+    pub fn counter(&self) -> u32 {
+        self.nonce.counter
+    }
+
+    /// Encrypt a payload into the provided buffer, returning the encoded length
+    pub fn encrypt(&mut self, payload: &[u8], buff: &mut [u8]) -> Result<usize, CryptoError> {
+        let n = payload.len() + OVERHEAD;
+        if buff.len() < n {
+            return Err(CryptoError::BufferTooSmall);
+        }
+
+        buff[..NONCE_LEN].copy_from_slice(&self.nonce.encode());
+
+        let (body, tag) = buff[NONCE_LEN..n].split_at_mut(payload.len());
+        body.copy_from_slice(payload);
+        let t = self
+            .ccm
+            .encrypt_in_place_detached(&self.nonce.ccm().into(), &[], body)
+            .map_err(|_| CryptoError::BufferTooSmall)?;
+        tag.copy_from_slice(&t);
+
+        self.nonce.counter = self.nonce.counter.wrapping_add(1);
+
+        Ok(n)
+    }
+
+    /// Decrypt and authenticate a received payload into the provided buffer, returning
+    /// the payload nonce (for replay checks) and plaintext
+    pub fn decrypt<'a>(
+        &self,
+        data: &[u8],
+        buff: &'a mut [u8],
+    ) -> Result<(Nonce, &'a [u8]), CryptoError> {
+        if data.len() < OVERHEAD {
+            return Err(CryptoError::TooShort);
+        }
+        let n = data.len() - OVERHEAD;
+        if buff.len() < n {
+            return Err(CryptoError::BufferTooSmall);
+        }
+
+        let (nonce, body) = data.split_at(NONCE_LEN);
+        let (body, tag) = body.split_at(n);
+        let nonce = Nonce::decode(nonce);
+
+        let plain = &mut buff[..n];
+        plain.copy_from_slice(body);
+        self.ccm
+            .decrypt_in_place_detached(&nonce.ccm().into(), &[], plain, tag.into())
+            .map_err(|_| CryptoError::Authentication)?;
+
+        Ok((nonce, plain))
+    }
+}
+
+impl core::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Key material is omitted
+        f.debug_struct("Cipher")
+            .field("nonce", &self.nonce)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt() {
+        let key = parse_key("0x000102030405060708090a0b0c0d0e0f").unwrap();
+        assert_eq!(key[15], 0x0f);
+        assert_eq!(parse_key("0011"), Err(CryptoError::InvalidKey));
+
+        let mut tx = Cipher::new(&key, 0x1234_5678, u32::MAX);
+        let rx = Cipher::new(&key, 0x9abc_def0, 0);
+        let mut enc = [0u8; 32];
+        let mut dec = [0u8; 32];
+
+        let n = tx.encrypt(&[1, 2, 3, 4], &mut enc).unwrap();
+        assert_eq!(n, 4 + OVERHEAD);
+        assert_eq!(tx.counter(), 0);
+        assert_ne!(&enc[NONCE_LEN..NONCE_LEN + 4], &[1, 2, 3, 4]);
+        let (nonce, plain) = rx.decrypt(&enc[..n], &mut dec).unwrap();
+        assert_eq!(plain, &[1, 2, 3, 4]);
+        assert_eq!(
+            nonce,
+            Nonce {
+                salt: 0x1234_5678,
+                counter: u32::MAX
+            }
+        );
+
+        // Successive payloads use distinct nonces, following across counter wraps
+        let mut enc2 = [0u8; 32];
+        tx.encrypt(&[1, 2, 3, 4], &mut enc2).unwrap();
+        assert_ne!(enc[..n], enc2[..n]);
+        let (next, _) = rx.decrypt(&enc2[..n], &mut dec).unwrap();
+        assert!(next.follows(nonce.counter));
+        assert!(!nonce.follows(next.counter));
+        assert!(!next.follows(next.counter));
+
+        // Sessions with distinct salts use distinct nonces for the same counter
+        let mut other = Cipher::new(&key, 0x0bad_cafe, u32::MAX);
+        let mut enc3 = [0u8; 32];
+        other.encrypt(&[1, 2, 3, 4], &mut enc3).unwrap();
+        assert_ne!(enc[SALT_LEN..n], enc3[SALT_LEN..n]);
+
+        // Corrupted payloads and incorrect keys fail authentication
+        enc[NONCE_LEN] ^= 0x01;
+        assert_eq!(
+            rx.decrypt(&enc[..n], &mut dec),
+            Err(CryptoError::Authentication)
+        );
+        let other = Cipher::new(&[0xaa; KEY_LEN], 0, 0);
+        assert_eq!(
+            other.decrypt(&enc2[..n], &mut dec),
+            Err(CryptoError::Authentication)
+        );
+        assert_eq!(rx.decrypt(&enc[..4], &mut dec), Err(CryptoError::TooShort));
+    }
+}
@@ -0,0 +1,189 @@
+//! Doppler compensation for moving nodes
+//!
+//! Computes frequency offsets from relative (radial) velocity, either from a
+//! time-indexed [`VelocityProfile`] (for example an orbital pass prediction) or from
+//! live velocity input, and retunes the radio via the [`Channel`] trait.
+//!
+//! Velocities are positive where nodes are approaching (increasing received frequency).
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use crate::Channel;
+
+/// Speed of light in m/s
+pub const SPEED_OF_LIGHT: f32 = 299_792_458.0;
+
+/// Compute the doppler offset in Hz for a carrier at `carrier_hz` and radial velocity in m/s
+pub fn doppler_offset_hz(carrier_hz: u32, velocity_mps: f32) -> i32 {
+    (carrier_hz as f32 * velocity_mps / SPEED_OF_LIGHT) as i32
+}
+
+/// Direction of the link being compensated
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Receive, tune to the expected (shifted) frequency
+    Receive,
+    /// Transmit, pre-compensate so the signal arrives at the nominal frequency
+    Transmit,
+}
+
+/// Velocity profile, a sequence of `(time_ms, velocity_mps)` points sorted by time
+///
+/// Velocities between points are linearly interpolated, and clamped outside the profile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VelocityProfile<'a> {
+    points: &'a [(u32, f32)],
+}
+
+impl<'a> VelocityProfile<'a> {
+    /// Create a new profile from time-sorted `(time_ms, velocity_mps)` points
+    pub fn new(points: &'a [(u32, f32)]) -> Self {
+        Self { points }
+    }
+
+    /// Fetch the interpolated velocity at the provided time
+    pub fn velocity_at(&self, time_ms: u32) -> f32 {
+        let i = self.points.partition_point(|(t, _)| *t <= time_ms);
+
+        match (self.points.get(i.wrapping_sub(1)), self.points.get(i)) {
+            (Some((t0, v0)), Some((t1, v1))) => {
+                let k = (time_ms - t0) as f32 / (t1 - t0) as f32;
+                v0 + (v1 - v0) * k
+            }
+            (Some((_, v)), None) | (None, Some((_, v))) => *v,
+            (None, None) => 0.0,
+        }
+    }
+}
+
+/// Doppler compensator, retuning the radio as the frequency offset changes
+///
+/// Channels are constructed from compensated frequencies using the provided function,
+/// and the radio is only retuned where the offset changes by more than the configured threshold.
+pub struct DopplerCompensator<F> {
+    carrier_hz: u32,
+    threshold_hz: u32,
+    to_channel: F,
+    applied: Option<i32>,
+}
+
+impl<F> DopplerCompensator<F> {
+    /// Create a new compensator for the provided nominal carrier frequency
+    pub fn new(carrier_hz: u32, threshold_hz: u32, to_channel: F) -> Self {
+        Self {
+            carrier_hz,
+            threshold_hz,
+            to_channel,
+            applied: None,
+        }
+    }
+
+    /// Fetch the currently applied offset in Hz (relative to the nominal carrier)
+    pub fn applied_offset_hz(&self) -> Option<i32> {
+        self.applied
+    }
+
+    /// Compute the tuning offset in Hz for the provided velocity and direction
+    pub fn offset_hz(&self, velocity_mps: f32, direction: Direction) -> i32 {
+        let offset = doppler_offset_hz(self.carrier_hz, velocity_mps);
+        match direction {
+            Direction::Receive => offset,
+            Direction::Transmit => -offset,
+        }
+    }
+
+    /// Update compensation using a live velocity input, retuning the radio if required
+    ///
+    /// Returns the new offset where the radio was retuned.
+    pub fn update<T>(
+        &mut self,
+        radio: &mut T,
+        velocity_mps: f32,
+        direction: Direction,
+    ) -> Result<Option<i32>, T::Error>
+    where
+        T: Channel,
+        F: FnMut(u32) -> T::Channel,
+    {
+        let offset = self.offset_hz(velocity_mps, direction);
+
+        // Skip retuning for changes within the threshold
+        if let Some(a) = self.applied
+            && a.abs_diff(offset) <= self.threshold_hz
+        {
+            return Ok(None);
+        }
+
+        let ch = (self.to_channel)(self.carrier_hz.saturating_add_signed(offset));
+        radio.set_channel(&ch)?;
+        self.applied = Some(offset);
+
+        Ok(Some(offset))
+    }
+
+    /// Update compensation from a velocity profile at the provided time
+    pub fn update_from_profile<T>(
+        &mut self,
+        radio: &mut T,
+        profile: &VelocityProfile,
+        time_ms: u32,
+        direction: Direction,
+    ) -> Result<Option<i32>, T::Error>
+    where
+        T: Channel,
+        F: FnMut(u32) -> T::Channel,
+    {
+        self.update(radio, profile.velocity_at(time_ms), direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FreqRadio {
+        freq: Option<u32>,
+    }
+
+    impl Channel for FreqRadio {
+        type Channel = u32;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+            self.freq = Some(*channel);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn profile_interpolation() {
+        let p = VelocityProfile::new(&[(0, -7000.0), (1000, 0.0), (2000, 7000.0)]);
+
+        assert_eq!(p.velocity_at(0), -7000.0);
+        assert_eq!(p.velocity_at(500), -3500.0);
+        assert_eq!(p.velocity_at(1500), 3500.0);
+        assert_eq!(p.velocity_at(5000), 7000.0);
+        assert_eq!(VelocityProfile::new(&[]).velocity_at(10), 0.0);
+    }
+
+    #[test]
+    fn doppler_retuning() {
+        // ~10kHz shift for a LEO pass at 435MHz
+        assert_eq!(doppler_offset_hz(435_000_000, 7000.0) / 100, 101);
+
+        let mut radio = FreqRadio { freq: None };
+        let mut d = DopplerCompensator::new(435_000_000, 500, |f| f);
+
+        let o = d.update(&mut radio, 7000.0, Direction::Receive).unwrap();
+        assert_eq!(o, Some(doppler_offset_hz(435_000_000, 7000.0)));
+        assert_eq!(radio.freq, Some(435_000_000 + o.unwrap() as u32));
+
+        // Small changes do not retune
+        assert_eq!(d.update(&mut radio, 6900.0, Direction::Receive), Ok(None));
+
+        // Transmit pre-compensates in the opposite direction
+        let o = d.update(&mut radio, 7000.0, Direction::Transmit).unwrap();
+        assert_eq!(radio.freq, Some(435_000_000 - o.unwrap().unsigned_abs()));
+    }
+}
@@ -0,0 +1,331 @@
+//! Coordinator downlink queue with receive-window scheduling
+//!
+//! Sleepy end-devices only listen for a short window after each uplink (or in
+//! pre-arranged slots), in the style of LoRaWAN Class A devices. [`DownlinkQueue`]
+//! holds frames per device until the device next opens a receive window, transmitting
+//! at most one frame per window and flagging frames where more are pending so the
+//! device can stay awake (or uplink again) to collect them.
+//!
+//! Timestamps are provided by the caller in milliseconds, allowing use in `no_std` environments.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use crate::Transmit;
+use crate::frame::{self, Address, FrameError, Header};
+
+/// Frame flag set on downlinks where further frames are queued for the device
+pub const PENDING_FLAG: u8 = 0x40;
+
+/// Downlink queue configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DownlinkOptions {
+    /// Delay from the end of an uplink to the device opening its receive window
+    pub rx_delay_ms: u32,
+    /// Duration of each receive window
+    pub rx_window_ms: u32,
+    /// Queued frames older than this are discarded (never expire if unset)
+    pub expiry_ms: Option<u32>,
+}
+
+impl Default for DownlinkOptions {
+    fn default() -> Self {
+        Self {
+            rx_delay_ms: 1_000,
+            rx_window_ms: 100,
+            expiry_ms: None,
+        }
+    }
+}
+
+/// DownlinkError describes failures queueing downlink frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DownlinkError {
+    /// No free queue or window slots
+    #[cfg_attr(feature = "thiserror", error("Downlink queue full"))]
+    Full,
+    /// Encoded frame exceeds the queue slot size
+    #[cfg_attr(feature = "thiserror", error("Frame too large"))]
+    TooLarge,
+}
+
+impl From<FrameError> for DownlinkError {
+    fn from(_: FrameError) -> Self {
+        DownlinkError::TooLarge
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Queued<const M: usize> {
+    dst: Address,
+    order: u32,
+    queued_at: u32,
+    len: usize,
+    data: [u8; M],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Window {
+    dst: Address,
+    opens_at: u32,
+}
+
+/// Downlink queue for up to `N` frames of up to `M` bytes (including the frame header),
+/// tracking up to `N` device receive windows
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownlinkQueue<const N: usize, const M: usize> {
+    options: DownlinkOptions,
+    frames: [Option<Queued<M>>; N],
+    windows: [Option<Window>; N],
+    order: u32,
+}
+
+impl<const N: usize, const M: usize> DownlinkQueue<N, M> {
+    /// Create a new empty downlink queue
+    pub fn new(options: DownlinkOptions) -> Self {
+        Self {
+            options,
+            frames: [None; N],
+            windows: [None; N],
+            order: 0,
+        }
+    }
+
+    /// Queue a frame for the device addressed by `header.dst`
+    pub fn enqueue(
+        &mut self,
+        header: &Header,
+        payload: &[u8],
+        now_ms: u32,
+    ) -> Result<(), DownlinkError> {
+        let slot = self
+            .frames
+            .iter_mut()
+            .find(|f| f.is_none())
+            .ok_or(DownlinkError::Full)?;
+
+        let mut data = [0u8; M];
+        let len = frame::encode(header, payload, &mut data)?;
+
+        *slot = Some(Queued {
+            dst: header.dst,
+            order: self.order,
+            queued_at: now_ms,
+            len,
+            data,
+        });
+        self.order = self.order.wrapping_add(1);
+
+        Ok(())
+    }
+
+    /// Number of frames queued for a device
+    pub fn pending(&self, dst: Address) -> usize {
+        self.frames
+            .iter()
+            .flatten()
+            .filter(|f| f.dst == dst)
+            .count()
+    }
+
+    /// Total number of queued frames
+    pub fn len(&self) -> usize {
+        self.frames.iter().flatten().count()
+    }
+
+    /// Check whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Record an uplink from a device ending at `now_ms`, opening its next receive window
+    pub fn uplink(&mut self, src: Address, now_ms: u32) {
+        self.open_window(src, now_ms.wrapping_add(self.options.rx_delay_ms));
+    }
+
+    /// Schedule a receive window for a device (for devices with pre-arranged slots)
+    pub fn schedule(&mut self, dst: Address, opens_at_ms: u32) {
+        self.open_window(dst, opens_at_ms);
+    }
+
+    /// Open a window, replacing any existing window for the device or the oldest window when full
+    fn open_window(&mut self, dst: Address, opens_at: u32) {
+        let w = Window { dst, opens_at };
+
+        let i = self
+            .windows
+            .iter()
+            .position(|w| matches!(w, Some(w) if w.dst == dst))
+            .or_else(|| self.windows.iter().position(|w| w.is_none()))
+            .or_else(|| (0..N).min_by_key(|i| self.windows[*i].map(|w| w.opens_at).unwrap_or(0)));
+
+        if let Some(i) = i {
+            self.windows[i] = Some(w);
+        }
+    }
+
+    /// Discard expired frames and closed windows, returning the number of frames discarded
+    pub fn expire(&mut self, now_ms: u32) -> usize {
+        let rx_window = self.options.rx_window_ms;
+        for w in self.windows.iter_mut() {
+            if let Some(v) = w
+                && window_state(v.opens_at, rx_window, now_ms) == WindowState::Closed
+            {
+                *w = None;
+            }
+        }
+
+        let expiry = match self.options.expiry_ms {
+            Some(e) => e,
+            None => return 0,
+        };
+
+        let mut n = 0;
+        for f in self.frames.iter_mut() {
+            if let Some(v) = f
+                && now_ms.wrapping_sub(v.queued_at) >= expiry
+            {
+                *f = None;
+                n += 1;
+            }
+        }
+        n
+    }
+
+    /// Fetch the index of the oldest frame for a device with an open receive window
+    fn due(&self, now_ms: u32) -> Option<(usize, usize)> {
+        let rx_window = self.options.rx_window_ms;
+
+        self.windows
+            .iter()
+            .enumerate()
+            .filter_map(|(wi, w)| w.map(|w| (wi, w)))
+            .filter(|(_, w)| window_state(w.opens_at, rx_window, now_ms) == WindowState::Open)
+            .find_map(|(wi, w)| {
+                self.frames
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(fi, f)| f.map(|f| (fi, f)))
+                    .filter(|(_, f)| f.dst == w.dst)
+                    .min_by_key(|(_, f)| f.order.wrapping_sub(self.order))
+                    .map(|(fi, _)| (wi, fi))
+            })
+    }
+
+    /// Transmit the next frame due in an open receive window, returning the destination address
+    ///
+    /// The frame is removed from the queue and the window consumed once transmission has
+    /// started, callers should await transmit completion (`check_transmit`) as usual.
+    pub fn transmit<T: Transmit>(
+        &mut self,
+        radio: &mut T,
+        now_ms: u32,
+    ) -> Result<Option<Address>, T::Error> {
+        self.expire(now_ms);
+
+        let (wi, fi) = match self.due(now_ms) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let mut f = match self.frames[fi] {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        if self.pending(f.dst) > 1 {
+            f.data[0] |= PENDING_FLAG;
+        }
+
+        radio.start_transmit(&f.data[..f.len])?;
+
+        self.frames[fi] = None;
+        self.windows[wi] = None;
+
+        Ok(Some(f.dst))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WindowState {
+    Pending,
+    Open,
+    Closed,
+}
+
+fn window_state(opens_at: u32, rx_window_ms: u32, now_ms: u32) -> WindowState {
+    let elapsed = now_ms.wrapping_sub(opens_at);
+    if elapsed > u32::MAX / 2 {
+        WindowState::Pending
+    } else if elapsed < rx_window_ms {
+        WindowState::Open
+    } else {
+        WindowState::Closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TxRadio;
+
+    #[test]
+    fn downlink_in_receive_windows() {
+        let mut radio = TxRadio::default();
+        let mut q = DownlinkQueue::<4, 16>::new(DownlinkOptions::default());
+
+        q.enqueue(&Header::new(0x01, 0x10, 0), &[0xaa], 0).unwrap();
+        q.enqueue(&Header::new(0x01, 0x10, 1), &[0xbb], 0).unwrap();
+        q.enqueue(&Header::new(0x01, 0x20, 2), &[0xcc], 0).unwrap();
+        assert_eq!(q.pending(0x10), 2);
+
+        // Nothing is sent until a device opens a window
+        assert_eq!(q.transmit(&mut radio, 10), Ok(None));
+
+        q.uplink(0x10, 100);
+        assert_eq!(q.transmit(&mut radio, 500), Ok(None));
+
+        // Oldest frame sent first, flagged as more are pending
+        assert_eq!(q.transmit(&mut radio, 1_100), Ok(Some(0x10)));
+        let (h, payload) = frame::decode(radio.last()).unwrap();
+        assert_eq!(h.flags, PENDING_FLAG);
+        assert_eq!(payload, &[0xaa]);
+
+        // One frame per window
+        assert_eq!(q.transmit(&mut radio, 1_150), Ok(None));
+        assert_eq!(radio.n, 1);
+
+        // Windows close after the window duration
+        q.uplink(0x10, 2_000);
+        assert_eq!(q.transmit(&mut radio, 3_100), Ok(None));
+
+        // Scheduled slot
+        q.schedule(0x10, 4_000);
+        assert_eq!(q.transmit(&mut radio, 4_050), Ok(Some(0x10)));
+        let (h, payload) = frame::decode(radio.last()).unwrap();
+        assert_eq!(h.flags, 0);
+        assert_eq!(payload, &[0xbb]);
+
+        assert_eq!(q.len(), 1);
+        assert_eq!(q.pending(0x20), 1);
+    }
+
+    #[test]
+    fn downlink_limits() {
+        let opts = DownlinkOptions {
+            expiry_ms: Some(1_000),
+            ..Default::default()
+        };
+        let mut q = DownlinkQueue::<1, 8>::new(opts);
+
+        let h = Header::new(0x01, 0x10, 0);
+        assert_eq!(q.enqueue(&h, &[0; 4], 0), Err(DownlinkError::TooLarge));
+        q.enqueue(&h, &[0; 2], 0).unwrap();
+        assert_eq!(q.enqueue(&h, &[0; 2], 0), Err(DownlinkError::Full));
+
+        assert_eq!(q.expire(500), 0);
+        assert_eq!(q.expire(1_000), 1);
+        assert!(q.is_empty());
+    }
+}
@@ -0,0 +1,569 @@
+//! Duty-cycle limiting
+//!
+//! Regulatory limits in some bands (eg. 1% in EU 868 MHz sub-bands) cap the fraction of
+//! time spent transmitting. [`DutyCycleLimiter`] wraps a radio, tracking on-air time
+//! (computed from payload length and data rate) over a sliding window and delaying
+//! transmissions that would exceed the configured duty cycle. With a throttle threshold,
+//! transmissions are paced at the sustained duty cycle once the threshold is reached,
+//! rather than stalling for the window to slide once the budget is exhausted.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::blocking::BlockingError;
+use crate::clock::Clock;
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// DutyCycleOptions configure duty-cycle limiting of transmissions
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DutyCycleOptions {
+    /// Maximum duty cycle in percent (0-100], delaying transmissions that would exceed this
+    #[cfg_attr(feature = "clap", clap(long, requires = "bitrate", value_parser = parse_duty_cycle))]
+    pub duty_cycle: Option<f32>,
+
+    /// Sliding window over which the duty cycle is enforced
+    #[cfg_attr(feature="clap", clap(long, default_value="1h", value_parser=crate::duration_from_str))]
+    pub duty_window: Duration,
+
+    /// Pace transmissions at the sustained duty cycle once this percentage of the budget
+    /// is used, rather than stalling once the budget is exhausted
+    #[cfg_attr(feature = "clap", clap(long, requires = "duty_cycle"))]
+    pub duty_throttle: Option<f32>,
+
+    /// Report the airtime budget at this interval during transmission
+    #[cfg_attr(feature="clap", clap(long, value_parser=crate::duration_from_str))]
+    pub duty_report: Option<Duration>,
+}
+
+/// Parse a duty cycle percentage, rejecting values outside (0, 100]
+#[cfg(feature = "clap")]
+fn parse_duty_cycle(s: &str) -> Result<f32, std::string::String> {
+    let d: f32 = s
+        .trim()
+        .parse()
+        .map_err(|_| std::format!("invalid duty cycle '{}'", s))?;
+    match d > 0.0 && d <= 100.0 {
+        true => Ok(d),
+        false => Err(std::format!(
+            "duty cycle must be in (0, 100] percent, found {}",
+            d
+        )),
+    }
+}
+
+impl Default for DutyCycleOptions {
+    fn default() -> Self {
+        Self {
+            duty_cycle: None,
+            duty_window: Duration::from_secs(3600),
+            duty_throttle: None,
+            duty_report: None,
+        }
+    }
+}
+
+/// Airtime used and remaining within the duty cycle window
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AirtimeBudget {
+    /// On-air time used in the current window, in microseconds
+    pub used_us: u64,
+    /// On-air time allowed per window in microseconds, `None` where unlimited
+    pub budget_us: Option<u64>,
+    /// Window length in microseconds
+    pub window_us: u64,
+}
+
+impl AirtimeBudget {
+    /// Percentage of the window spent transmitting
+    pub fn used_percent(&self) -> f32 {
+        self.used_us as f32 * 100.0 / self.window_us.max(1) as f32
+    }
+
+    /// Remaining on-air time allowed in the current window, in microseconds
+    pub fn remaining_us(&self) -> Option<u64> {
+        self.budget_us.map(|b| b.saturating_sub(self.used_us))
+    }
+
+    /// Percentage of the allowance remaining in the current window
+    pub fn remaining_percent(&self) -> Option<f32> {
+        let b = self.budget_us?;
+        Some(self.remaining_us()? as f32 * 100.0 / b.max(1) as f32)
+    }
+}
+
+impl core::fmt::Display for AirtimeBudget {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "airtime {:.3} s ({:.3}% of window)",
+            self.used_us as f32 / 1e6,
+            self.used_percent()
+        )?;
+        match (self.remaining_us(), self.remaining_percent()) {
+            (Some(us), Some(p)) => write!(
+                f,
+                ", {:.3} s ({:.1}%) of allowance remaining",
+                us as f32 / 1e6,
+                p
+            ),
+            _ => write!(f, ", unlimited"),
+        }
+    }
+}
+
+/// Duty-cycle limiter statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DutyCycleStats {
+    /// Frames transmitted
+    pub frames: u32,
+    /// Total on-air time in microseconds
+    pub airtime_us: u64,
+    /// Frames delayed to remain within the duty cycle
+    pub delayed: u32,
+    /// Total delay in microseconds
+    pub delay_us: u64,
+    /// Frames paced at the sustained duty cycle (with `duty_throttle`)
+    pub throttled: u32,
+}
+
+impl core::fmt::Display for DutyCycleStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "frames {} airtime {:.3} s, delayed {} (throttled {}) for {:.3} s",
+            self.frames,
+            self.airtime_us as f32 / 1e6,
+            self.delayed,
+            self.throttled,
+            self.delay_us as f32 / 1e6
+        )
+    }
+}
+
+/// Errors from a radio wrapped in [`DutyCycleLimiter`]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DutyCycleError<E> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(E),
+    /// Frame on-air time exceeds the duty cycle budget for the entire window
+    #[cfg_attr(feature = "thiserror", error("Frame exceeds duty cycle budget"))]
+    ExceedsBudget,
+}
+
+impl<E> BlockingError<DutyCycleError<E>> {
+    /// Unwrap duty-cycle errors, mapping frames exceeding the budget to `BlockingError::Timeout`
+    pub fn flatten(self) -> BlockingError<E> {
+        match self {
+            BlockingError::Inner(DutyCycleError::Radio(e)) => BlockingError::Inner(e),
+            BlockingError::Inner(DutyCycleError::ExceedsBudget) | BlockingError::Timeout => {
+                BlockingError::Timeout
+            }
+            BlockingError::Exhausted { attempts, error } => BlockingError::Exhausted {
+                attempts,
+                error: match error {
+                    Some(DutyCycleError::Radio(e)) => Some(e),
+                    _ => None,
+                },
+            },
+            BlockingError::Oversize { len, max } => BlockingError::Oversize { len, max },
+            BlockingError::Unsupported(c) => BlockingError::Unsupported(c),
+        }
+    }
+}
+
+/// DutyCycleLimiter wraps a radio, enforcing a maximum duty cycle
+///
+/// On-air time is accumulated in `N` buckets spanning the window, with each transmission
+/// delayed (using the radio's `DelayNs`) until the oldest buckets expire where required.
+pub struct DutyCycleLimiter<T, C, const N: usize = 60> {
+    radio: T,
+    clock: C,
+    budget_us: u64,
+    throttle_us: Option<u64>,
+    window_us: u64,
+    bucket_us: u64,
+    bitrate: u32,
+    buckets: [u64; N],
+    index: usize,
+    bucket_start: u64,
+    stats: DutyCycleStats,
+}
+
+impl<T, C: Clock, const N: usize> DutyCycleLimiter<T, C, N> {
+    /// Wrap a radio transmitting at `bitrate` bits/s, using the provided clock
+    ///
+    /// Without a configured duty cycle on-air time is tracked but not limited.
+    pub fn new(radio: T, clock: C, options: DutyCycleOptions, bitrate: u32) -> Self {
+        let window_us = options.duty_window.as_micros() as u64;
+        let budget_us = match options.duty_cycle {
+            Some(d) => (window_us as f64 * d as f64 / 100.0) as u64,
+            None => u64::MAX,
+        };
+        let throttle_us = match (options.duty_cycle, options.duty_throttle) {
+            (Some(_), Some(t)) => Some((budget_us as f64 * t as f64 / 100.0) as u64),
+            _ => None,
+        };
+        let bucket_start = clock.now_us();
+
+        Self {
+            radio,
+            clock,
+            budget_us,
+            throttle_us,
+            window_us,
+            bucket_us: (window_us / N as u64).max(1),
+            bitrate: bitrate.max(1),
+            buckets: [0; N],
+            index: 0,
+            bucket_start,
+            stats: DutyCycleStats::default(),
+        }
+    }
+
+    /// On-air time used in the current window, in microseconds
+    pub fn used_us(&mut self) -> u64 {
+        self.expire();
+        self.buckets.iter().sum()
+    }
+
+    /// Airtime used and remaining in the current window
+    pub fn budget(&mut self) -> AirtimeBudget {
+        AirtimeBudget {
+            used_us: self.used_us(),
+            budget_us: Some(self.budget_us).filter(|b| *b != u64::MAX),
+            window_us: self.window_us,
+        }
+    }
+
+    /// Duty-cycle statistics
+    pub fn stats(&self) -> &DutyCycleStats {
+        &self.stats
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Fetch the clock timing the limiter
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Release the inner radio and clock
+    pub fn free(self) -> (T, C) {
+        (self.radio, self.clock)
+    }
+
+    /// On-air time for a frame of `len` bytes, in microseconds
+    fn airtime_us(&self, len: usize) -> u64 {
+        len as u64 * 8 * 1_000_000 / self.bitrate as u64
+    }
+
+    /// Expire buckets older than the window
+    fn expire(&mut self) {
+        let now = self.clock.now_us();
+
+        // Clear everything once the window has fully elapsed
+        if now.saturating_sub(self.bucket_start) >= self.bucket_us * N as u64 {
+            self.buckets = [0; N];
+            self.bucket_start = now;
+            return;
+        }
+
+        while now >= self.bucket_start + self.bucket_us {
+            self.index = (self.index + 1) % N;
+            self.buckets[self.index] = 0;
+            self.bucket_start += self.bucket_us;
+        }
+    }
+}
+
+impl<T, C, E, const N: usize> Transmit for DutyCycleLimiter<T, C, N>
+where
+    T: Transmit<Error = E> + DelayNs,
+    C: Clock,
+    E: Debug,
+{
+    type Error = DutyCycleError<E>;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let airtime = self.airtime_us(data.len());
+        if airtime > self.budget_us {
+            return Err(DutyCycleError::ExceedsBudget);
+        }
+
+        // Pace frames at the sustained duty cycle once the throttle threshold is reached,
+        // spacing each by its airtime scaled to the remainder of the window
+        let mut delayed = false;
+        if let Some(t) = self.throttle_us
+            && self.used_us() + airtime > t
+        {
+            let pace =
+                airtime * self.window_us.saturating_sub(self.budget_us) / self.budget_us.max(1);
+
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Duty cycle throttled, pacing {} us", pace);
+
+            self.radio.delay_us(pace.max(1).min(u32::MAX as u64) as u32);
+            self.stats.delay_us += pace;
+            self.stats.throttled += 1;
+            delayed = true;
+        }
+
+        // Wait for buckets to expire until the frame fits within the budget
+        while self.used_us() + airtime > self.budget_us {
+            let wait = (self.bucket_start + self.bucket_us).saturating_sub(self.clock.now_us());
+
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Duty cycle limit reached, delaying {} us", wait);
+
+            self.radio.delay_us(wait.max(1).min(u32::MAX as u64) as u32);
+            self.stats.delay_us += wait;
+            delayed = true;
+        }
+
+        self.radio
+            .start_transmit(data)
+            .map_err(DutyCycleError::Radio)?;
+
+        self.buckets[self.index] += airtime;
+        self.stats.frames += 1;
+        self.stats.airtime_us += airtime;
+        if delayed {
+            self.stats.delayed += 1;
+        }
+
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit().map_err(DutyCycleError::Radio)
+    }
+}
+
+impl<T, C, E, const N: usize> Receive for DutyCycleLimiter<T, C, N>
+where
+    T: Receive<Error = E>,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = DutyCycleError<E>;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive().map_err(DutyCycleError::Radio)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio
+            .check_receive(restart)
+            .map_err(DutyCycleError::Radio)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff).map_err(DutyCycleError::Radio)
+    }
+}
+
+impl<T, C, E, const N: usize> Power for DutyCycleLimiter<T, C, N>
+where
+    T: Power<Error = E>,
+    E: Debug,
+{
+    type Error = DutyCycleError<E>;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power).map_err(DutyCycleError::Radio)
+    }
+}
+
+impl<T, C, E, const N: usize> Rssi for DutyCycleLimiter<T, C, N>
+where
+    T: Rssi<Error = E>,
+    E: Debug,
+{
+    type Error = DutyCycleError<E>;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi().map_err(DutyCycleError::Radio)
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out).map_err(DutyCycleError::Radio)
+    }
+}
+
+impl<T, C, E, const N: usize> Channel for DutyCycleLimiter<T, C, N>
+where
+    T: Channel<Error = E>,
+    E: Debug,
+{
+    type Channel = T::Channel;
+    type Error = DutyCycleError<E>;
+
+    fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+        self.radio
+            .set_channel(channel)
+            .map_err(DutyCycleError::Radio)
+    }
+}
+
+impl<T: DelayNs, C, const N: usize> DelayNs for DutyCycleLimiter<T, C, N> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    /// Radio with delays advancing a virtual clock
+    struct ClockedRadio<'a> {
+        clock: &'a VirtualClock,
+        sent: u32,
+    }
+
+    impl Transmit for ClockedRadio<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            self.sent += 1;
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl DelayNs for ClockedRadio<'_> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.clock.delay_ns(ns)
+        }
+    }
+
+    #[test]
+    fn duty_cycle_limit() {
+        let clock = VirtualClock::new();
+        let radio = ClockedRadio {
+            clock: &clock,
+            sent: 0,
+        };
+        let options = DutyCycleOptions {
+            duty_cycle: Some(1.0),
+            duty_window: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        // 10 byte frames at 8 kbps are 10 ms on air, with a 100 ms budget per 10 s
+        let mut r: DutyCycleLimiter<_, _, 10> =
+            DutyCycleLimiter::new(radio, &clock, options, 8_000);
+        for _ in 0..10 {
+            r.start_transmit(&[0u8; 10]).unwrap();
+        }
+        assert_eq!(r.used_us(), 100_000);
+        assert_eq!(r.stats().delayed, 0);
+        assert_eq!(clock.now_us(), 0);
+
+        // The next frame waits for the window to slide
+        r.start_transmit(&[0u8; 10]).unwrap();
+        assert_eq!(r.stats().delayed, 1);
+        assert_eq!(r.inner().sent, 11);
+        assert!(clock.now_us() >= 10_000_000);
+
+        // Frames larger than the budget are rejected
+        assert_eq!(
+            r.start_transmit(&[0u8; 101]),
+            Err(DutyCycleError::ExceedsBudget)
+        );
+    }
+
+    #[test]
+    fn duty_cycle_throttle() {
+        let clock = VirtualClock::new();
+        let radio = ClockedRadio {
+            clock: &clock,
+            sent: 0,
+        };
+        let options = DutyCycleOptions {
+            duty_cycle: Some(1.0),
+            duty_window: Duration::from_secs(10),
+            duty_throttle: Some(50.0),
+            ..Default::default()
+        };
+
+        // Frames are sent immediately until half the 100 ms budget is used
+        let mut r: DutyCycleLimiter<_, _, 10> =
+            DutyCycleLimiter::new(radio, &clock, options, 8_000);
+        for _ in 0..5 {
+            r.start_transmit(&[0u8; 10]).unwrap();
+        }
+        assert_eq!(clock.now_us(), 0);
+
+        let b = r.budget();
+        assert_eq!((b.used_us, b.remaining_us()), (50_000, Some(50_000)));
+        assert_eq!(b.remaining_percent(), Some(50.0));
+        assert_eq!(b.used_percent(), 0.5);
+
+        // Further frames are paced at the sustained 1% rate (990 ms per 10 ms frame)
+        r.start_transmit(&[0u8; 10]).unwrap();
+        r.start_transmit(&[0u8; 10]).unwrap();
+        assert_eq!(clock.now_us(), 1_980_000);
+        assert_eq!((r.stats().throttled, r.stats().delayed), (2, 2));
+        assert_eq!(r.budget().used_us, 70_000);
+    }
+
+    #[test]
+    fn duty_cycle_over_window() {
+        let clock = VirtualClock::new();
+        let radio = ClockedRadio {
+            clock: &clock,
+            sent: 0,
+        };
+        let options = DutyCycleOptions {
+            duty_cycle: Some(150.0),
+            duty_window: Duration::from_secs(10),
+            duty_throttle: Some(50.0),
+            ..Default::default()
+        };
+
+        // Budgets exceeding the window do not pace throttled frames
+        let mut r: DutyCycleLimiter<_, _, 10> =
+            DutyCycleLimiter::new(radio, &clock, options, 8_000);
+        r.start_transmit(&[0u8; 10_000]).unwrap();
+        assert_eq!(r.stats().throttled, 1);
+        assert_eq!(clock.now_us(), 1);
+    }
+
+    #[cfg(feature = "clap")]
+    #[test]
+    fn duty_cycle_range() {
+        assert_eq!(parse_duty_cycle("1"), Ok(1.0));
+        assert_eq!(parse_duty_cycle("100"), Ok(100.0));
+        assert!(parse_duty_cycle("0").is_err());
+        assert!(parse_duty_cycle("150").is_err());
+        assert!(parse_duty_cycle("one").is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Byte-level airtime fairness scheduler for shared channels
+//!
+//! Where several logical senders (eg. a flood test, beacons and a file transfer) share one
+//! radio, [`FairScheduler`] interleaves their frames with deficit round robin so that each
+//! sender is granted a share of transmitted bytes proportional to its weight, rather than
+//! a busy sender starving the others. Each round a sender with queued frames is credited
+//! [`FairOptions::quantum`] bytes multiplied by its weight, sending frames while its
+//! credit covers the next frame, with credit carried across rounds so large frames are
+//! not penalised. Senders with no queued frames forfeit their credit.
+//!
+//! Airtime is accounted per sender from transmit start to completion, with timestamps
+//! provided by the caller in milliseconds, allowing use in `no_std` environments.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use crate::Transmit;
+
+/// Default bytes credited per round to a sender of weight 1
+pub const DEFAULT_QUANTUM: usize = 64;
+
+/// Fairness scheduler configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FairOptions {
+    /// Bytes credited per round to a sender of weight 1
+    pub quantum: usize,
+}
+
+impl Default for FairOptions {
+    fn default() -> Self {
+        Self {
+            quantum: DEFAULT_QUANTUM,
+        }
+    }
+}
+
+/// FairError describes failures queueing frames for transmission
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FairError {
+    /// No free slots
+    #[cfg_attr(feature = "thiserror", error("Scheduler queue full"))]
+    Full,
+    /// Frame exceeds the queue slot size
+    #[cfg_attr(feature = "thiserror", error("Frame too large"))]
+    TooLarge,
+    /// Sender index out of range
+    #[cfg_attr(feature = "thiserror", error("Unknown sender"))]
+    UnknownSender,
+}
+
+/// Per-sender transmit statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FairStats {
+    /// Frames queued
+    pub queued: u32,
+    /// Frames transmitted to completion
+    pub sent: u32,
+    /// Bytes transmitted to completion
+    pub bytes: u64,
+    /// Airtime used, in milliseconds
+    pub airtime_ms: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Queued<const M: usize> {
+    sender: usize,
+    order: u32,
+    len: usize,
+    data: [u8; M],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct InFlight {
+    sender: usize,
+    len: usize,
+    started_at: u32,
+}
+
+/// Weighted fair transmit scheduler for `S` senders, queueing up to `N` frames of up to
+/// `M` bytes
+#[derive(Clone, Debug, PartialEq)]
+pub struct FairScheduler<const S: usize, const N: usize, const M: usize> {
+    quantum: usize,
+    weights: [u16; S],
+    deficit: [usize; S],
+    current: usize,
+    credited: bool,
+    frames: [Option<Queued<M>>; N],
+    in_flight: Option<InFlight>,
+    order: u32,
+    stats: [FairStats; S],
+}
+
+impl<const S: usize, const N: usize, const M: usize> FairScheduler<S, N, M> {
+    /// Create a new scheduler with the provided sender weights (a weight of 0 is treated
+    /// as 1)
+    pub fn new(options: FairOptions, weights: [u16; S]) -> Self {
+        Self {
+            quantum: options.quantum.max(1),
+            weights: weights.map(|w| w.max(1)),
+            deficit: [0; S],
+            current: 0,
+            credited: false,
+            frames: [None; N],
+            in_flight: None,
+            order: 0,
+            stats: [FairStats::default(); S],
+        }
+    }
+
+    /// Queue a frame for transmission from the provided sender
+    pub fn enqueue(&mut self, sender: usize, data: &[u8]) -> Result<(), FairError> {
+        if sender >= S {
+            return Err(FairError::UnknownSender);
+        }
+        if data.len() > M {
+            return Err(FairError::TooLarge);
+        }
+
+        let slot = self
+            .frames
+            .iter_mut()
+            .find(|f| f.is_none())
+            .ok_or(FairError::Full)?;
+
+        let mut frame = Queued {
+            sender,
+            order: self.order,
+            len: data.len(),
+            data: [0u8; M],
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+
+        *slot = Some(frame);
+        self.order = self.order.wrapping_add(1);
+        self.stats[sender].queued += 1;
+
+        Ok(())
+    }
+
+    /// Index of the oldest frame queued by a sender
+    fn head(&self, sender: usize) -> Option<usize> {
+        let order = self.order;
+        self.frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| f.map(|f| (i, f)))
+            .filter(|(_, f)| f.sender == sender)
+            .min_by_key(|(_, f)| f.order.wrapping_sub(order))
+            .map(|(i, _)| i)
+    }
+
+    /// Move to the next sender in the round
+    fn advance(&mut self) {
+        self.current = (self.current + 1) % S;
+        self.credited = false;
+    }
+
+    /// Index of the next frame to transmit by deficit round robin, debiting the sender
+    fn next(&mut self) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // Terminates as each round credits every sender with queued frames
+        loop {
+            let s = self.current;
+            let i = match self.head(s) {
+                Some(i) => i,
+                None => {
+                    self.deficit[s] = 0;
+                    self.advance();
+                    continue;
+                }
+            };
+
+            if !self.credited {
+                self.deficit[s] += self.quantum * self.weights[s] as usize;
+                self.credited = true;
+            }
+
+            let len = self.frames[i].map(|f| f.len).unwrap_or(0);
+            if len <= self.deficit[s] {
+                self.deficit[s] -= len;
+                return Some(i);
+            }
+
+            self.advance();
+        }
+    }
+
+    /// Service the scheduler at `now_ms`, completing any in-flight transmission and
+    /// starting the next scheduled frame, returning the sender of any transmission
+    /// completed
+    pub fn poll<T: Transmit>(
+        &mut self,
+        radio: &mut T,
+        now_ms: u32,
+    ) -> Result<Option<usize>, T::Error> {
+        let mut completed = None;
+
+        if let Some(f) = self.in_flight {
+            if !radio.check_transmit()? {
+                return Ok(None);
+            }
+
+            let s = &mut self.stats[f.sender];
+            s.sent += 1;
+            s.bytes += f.len as u64;
+            s.airtime_ms += now_ms.wrapping_sub(f.started_at) as u64;
+
+            self.in_flight = None;
+            completed = Some(f.sender);
+        }
+
+        let i = match self.next() {
+            Some(i) => i,
+            None => return Ok(completed),
+        };
+        let frame = match self.frames[i].take() {
+            Some(f) => f,
+            None => return Ok(completed),
+        };
+
+        if let Err(e) = radio.start_transmit(&frame.data[..frame.len]) {
+            // Refund the sender so the frame is retried on the next poll
+            self.deficit[frame.sender] += frame.len;
+            self.frames[i] = Some(frame);
+            return Err(e);
+        }
+        self.in_flight = Some(InFlight {
+            sender: frame.sender,
+            len: frame.len,
+            started_at: now_ms,
+        });
+
+        Ok(completed)
+    }
+
+    /// Sender of the in-flight transmission, if any
+    pub fn in_flight(&self) -> Option<usize> {
+        self.in_flight.map(|f| f.sender)
+    }
+
+    /// Number of frames queued by a sender (excluding any in-flight transmission)
+    pub fn pending(&self, sender: usize) -> usize {
+        self.frames
+            .iter()
+            .flatten()
+            .filter(|f| f.sender == sender)
+            .count()
+    }
+
+    /// Total number of queued frames (excluding any in-flight transmission)
+    pub fn len(&self) -> usize {
+        self.frames.iter().flatten().count()
+    }
+
+    /// Check whether the scheduler is empty (a transmission may still be in flight)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check whether the scheduler is empty with no transmission in flight
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.is_none() && self.is_empty()
+    }
+
+    /// Transmit statistics for a sender
+    pub fn stats(&self, sender: usize) -> &FairStats {
+        &self.stats[sender]
+    }
+
+    /// Fraction of bytes transmitted by a sender
+    pub fn share(&self, sender: usize) -> f32 {
+        match self.stats.iter().map(|s| s.bytes).sum::<u64>() {
+            0 => 0.0,
+            total => self.stats[sender].bytes as f32 / total as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TxRadio;
+
+    #[test]
+    fn weighted_shares() {
+        let mut radio = TxRadio::default();
+        let mut s = FairScheduler::<3, 16, 20>::new(FairOptions { quantum: 10 }, [2, 1, 1]);
+
+        for _ in 0..6 {
+            s.enqueue(0, &[0; 10]).unwrap();
+            s.enqueue(1, &[1; 10]).unwrap();
+        }
+        // Large frames are sent once enough credit accumulates
+        s.enqueue(2, &[2; 20]).unwrap();
+        assert_eq!(s.enqueue(3, &[3]), Err(FairError::UnknownSender));
+        assert_eq!(s.enqueue(2, &[2; 21]), Err(FairError::TooLarge));
+
+        for t in 0..14 {
+            s.poll(&mut radio, t * 10).unwrap();
+        }
+        assert!(s.is_idle());
+        assert_eq!(
+            &radio.started[..13],
+            &[0, 0, 1, 0, 0, 1, 2, 0, 0, 1, 1, 1, 1]
+        );
+
+        assert_eq!(s.stats(0).bytes, 60);
+        assert_eq!(s.stats(2).airtime_ms, 10);
+        assert_eq!(s.share(2), 20.0 / 140.0);
+    }
+}
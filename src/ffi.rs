@@ -0,0 +1,494 @@
+//! C FFI bindings for the helper operations
+//!
+//! Radios are provided by C code as a [`RadioVTable`] of driver callbacks, allowing existing
+//! C test infrastructure to drive radios through the transmit, receive and ping-pong
+//! operations. Driver callbacks return `0` on success or a positive driver-specific error
+//! code, which is passed through to the caller, while failures within this crate are
+//! reported using the negative `RADIO_ERR_*` codes.
+//!
+//! A C header is generated from this module using `cbindgen` (see `cbindgen.toml`):
+//!
+//! ```text
+//! cbindgen --config cbindgen.toml --output include/radio.h
+//! ```
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::ffi::c_void;
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingReceive},
+    helpers::{PingPongOptions, TransmitOptions, do_ping_pong, do_transmit},
+};
+
+/// Operation completed successfully
+pub const RADIO_OK: i32 = 0;
+
+/// Operation timed out
+pub const RADIO_ERR_TIMEOUT: i32 = -1;
+
+/// Invalid (null) argument
+pub const RADIO_ERR_INVALID: i32 = -2;
+
+/// Radio driver interface, implemented by C code
+///
+/// Callbacks returning `i32` must return `0` on success or a positive error code.
+#[repr(C)]
+pub struct RadioVTable {
+    /// Driver context, passed to each callback
+    pub ctx: *mut c_void,
+    /// Start transmitting a packet
+    pub start_transmit: extern "C" fn(ctx: *mut c_void, data: *const u8, len: usize) -> i32,
+    /// Check for transmit completion, setting `done` when complete
+    pub check_transmit: extern "C" fn(ctx: *mut c_void, done: *mut bool) -> i32,
+    /// Enter receive mode
+    pub start_receive: extern "C" fn(ctx: *mut c_void) -> i32,
+    /// Check for a received packet, setting `ready` when available
+    pub check_receive: extern "C" fn(ctx: *mut c_void, restart: bool, ready: *mut bool) -> i32,
+    /// Fetch a received packet, setting the received length and RSSI
+    pub get_received: extern "C" fn(
+        ctx: *mut c_void,
+        buff: *mut u8,
+        len: usize,
+        received: *mut usize,
+        rssi: *mut i16,
+    ) -> i32,
+    /// Set transmit power in dBm
+    pub set_power: extern "C" fn(ctx: *mut c_void, power: i8) -> i32,
+    /// Delay for the provided number of microseconds
+    pub delay_us: extern "C" fn(ctx: *mut c_void, us: u32),
+}
+
+/// Blocking operation options
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadioBlockingOptions {
+    /// Interval for polling for device state in microseconds
+    pub poll_interval_us: u32,
+    /// Timeout for blocking operations in microseconds
+    pub timeout_us: u32,
+}
+
+impl From<RadioBlockingOptions> for BlockingOptions {
+    fn from(o: RadioBlockingOptions) -> Self {
+        Self {
+            poll_interval: Duration::from_micros(o.poll_interval_us as u64),
+            timeout: Duration::from_micros(o.timeout_us as u64),
+            ..Default::default()
+        }
+    }
+}
+
+/// Ping-pong operation options
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadioPingPongOptions {
+    /// Number of rounds to run
+    pub rounds: u32,
+    /// Set transmit power before running
+    pub set_power: bool,
+    /// Transmit power in dBm
+    pub power: i8,
+    /// Delay between rounds in microseconds
+    pub delay_us: u32,
+    /// Parse RSSI from responses (echo server must append info)
+    pub parse_info: bool,
+    /// Payload size in bytes (minimum 4)
+    pub size: usize,
+    /// Blocking options for each transmit and receive
+    pub blocking: RadioBlockingOptions,
+}
+
+/// Ping-pong link test results
+///
+/// Statistics without samples are reported as `NaN`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RadioLinkTestInfo {
+    /// Packets sent
+    pub sent: u32,
+    /// Responses received
+    pub received: u32,
+    /// Mean local RSSI in dBm
+    pub local_rssi_mean: f32,
+    /// Mean remote RSSI in dBm
+    pub remote_rssi_mean: f32,
+    /// Mean round trip time in milliseconds
+    pub rtt_mean_ms: f32,
+    /// Maximum round trip time in milliseconds
+    pub rtt_max_ms: f32,
+    /// Number of loss bursts
+    pub loss_bursts: u32,
+}
+
+impl Default for RadioBlockingOptions {
+    fn default() -> Self {
+        let o = BlockingOptions::default();
+        Self {
+            poll_interval_us: o.poll_interval.as_micros() as u32,
+            timeout_us: o.timeout.as_micros() as u32,
+        }
+    }
+}
+
+impl Default for RadioPingPongOptions {
+    fn default() -> Self {
+        Self {
+            rounds: 100,
+            set_power: false,
+            power: 0,
+            delay_us: 100_000,
+            parse_info: false,
+            size: 4,
+            blocking: RadioBlockingOptions::default(),
+        }
+    }
+}
+
+/// Default blocking options
+#[unsafe(no_mangle)]
+pub extern "C" fn radio_blocking_options_default() -> RadioBlockingOptions {
+    RadioBlockingOptions::default()
+}
+
+/// Default ping-pong options
+#[unsafe(no_mangle)]
+pub extern "C" fn radio_ping_pong_options_default() -> RadioPingPongOptions {
+    RadioPingPongOptions::default()
+}
+
+/// Transmit a packet
+///
+/// # Safety
+/// `radio` must point to a valid vtable and `data` to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn radio_do_transmit(
+    radio: *const RadioVTable,
+    data: *const u8,
+    len: usize,
+    options: RadioBlockingOptions,
+) -> i32 {
+    let (Some(vt), false) = (unsafe { radio.as_ref() }, data.is_null()) else {
+        return RADIO_ERR_INVALID;
+    };
+
+    let options = TransmitOptions {
+        data: unsafe { core::slice::from_raw_parts(data, len) }.to_vec(),
+        blocking_options: options.into(),
+        ..Default::default()
+    };
+
+    result_code(do_transmit(&mut FfiRadio(vt), options).map(|_| ()))
+}
+
+/// Receive a single packet, writing the received length and RSSI
+///
+/// # Safety
+/// `radio` must point to a valid vtable, `buff` to `len` writable bytes,
+/// and `received` and `rssi` must be valid or null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn radio_do_receive(
+    radio: *const RadioVTable,
+    buff: *mut u8,
+    len: usize,
+    received: *mut usize,
+    rssi: *mut i16,
+    options: RadioBlockingOptions,
+) -> i32 {
+    let (Some(vt), false) = (unsafe { radio.as_ref() }, buff.is_null()) else {
+        return RADIO_ERR_INVALID;
+    };
+    let buff = unsafe { core::slice::from_raw_parts_mut(buff, len) };
+
+    let (n, info) = match FfiRadio(vt).do_receive(buff, options.into()) {
+        Ok(r) => r,
+        Err(e) => return error_code(e),
+    };
+
+    unsafe {
+        if let Some(r) = received.as_mut() {
+            *r = n;
+        }
+        if let Some(r) = rssi.as_mut() {
+            *r = info.rssi;
+        }
+    }
+
+    RADIO_OK
+}
+
+/// Run a ping-pong link test against an echo server, writing results to `info`
+///
+/// # Safety
+/// `radio` and `options` must point to valid structures, and `info` must be valid or null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn radio_do_ping_pong(
+    radio: *const RadioVTable,
+    options: *const RadioPingPongOptions,
+    info: *mut RadioLinkTestInfo,
+) -> i32 {
+    let (Some(vt), Some(o)) = (unsafe { radio.as_ref() }, unsafe { options.as_ref() }) else {
+        return RADIO_ERR_INVALID;
+    };
+
+    let options = PingPongOptions {
+        rounds: o.rounds,
+        power: o.set_power.then_some(o.power),
+        delay: Duration::from_micros(o.delay_us as u64).into(),
+        parse_info: o.parse_info,
+        size: o.size,
+        blocking_options: o.blocking.into(),
+        ..Default::default()
+    };
+
+    let r = match do_ping_pong(&mut FfiRadio(vt), options) {
+        Ok(r) => r,
+        Err(e) => return error_code(e),
+    };
+
+    if let Some(info) = unsafe { info.as_mut() } {
+        *info = RadioLinkTestInfo {
+            sent: r.sent,
+            received: r.received,
+            local_rssi_mean: r.local_rssi.mean().unwrap_or(f32::NAN),
+            remote_rssi_mean: r.remote_rssi.mean().unwrap_or(f32::NAN),
+            rtt_mean_ms: r.rtt.mean().unwrap_or(f32::NAN),
+            rtt_max_ms: r.rtt.max().unwrap_or(f32::NAN),
+            loss_bursts: r.loss_bursts.bursts(),
+        };
+    }
+
+    RADIO_OK
+}
+
+fn error_code(e: BlockingError<i32>) -> i32 {
+    match e {
+        BlockingError::Inner(e) | BlockingError::Exhausted { error: Some(e), .. } => e,
+        BlockingError::Timeout | BlockingError::Exhausted { error: None, .. } => RADIO_ERR_TIMEOUT,
+        BlockingError::Oversize { .. } | BlockingError::Unsupported(_) => RADIO_ERR_INVALID,
+    }
+}
+
+fn result_code(r: Result<(), BlockingError<i32>>) -> i32 {
+    match r {
+        Ok(_) => RADIO_OK,
+        Err(e) => error_code(e),
+    }
+}
+
+fn check(code: i32) -> Result<(), i32> {
+    match code {
+        0 => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Receive information from C drivers
+#[derive(Clone, Debug, Default, PartialEq)]
+struct FfiInfo {
+    rssi: i16,
+}
+
+impl ReceiveInfo for FfiInfo {
+    fn rssi(&self) -> i16 {
+        self.rssi
+    }
+}
+
+/// Radio implemented over a C vtable
+struct FfiRadio<'a>(&'a RadioVTable);
+
+impl Transmit for FfiRadio<'_> {
+    type Error = i32;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        check((self.0.start_transmit)(
+            self.0.ctx,
+            data.as_ptr(),
+            data.len(),
+        ))
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        let mut done = false;
+        check((self.0.check_transmit)(self.0.ctx, &mut done))?;
+        Ok(done)
+    }
+}
+
+impl Receive for FfiRadio<'_> {
+    type Error = i32;
+    type Info = FfiInfo;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        check((self.0.start_receive)(self.0.ctx))
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        let mut ready = false;
+        check((self.0.check_receive)(self.0.ctx, restart, &mut ready))?;
+        Ok(ready)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let (mut n, mut rssi) = (0, 0);
+        check((self.0.get_received)(
+            self.0.ctx,
+            buff.as_mut_ptr(),
+            buff.len(),
+            &mut n,
+            &mut rssi,
+        ))?;
+        Ok((n.min(buff.len()), FfiInfo { rssi }))
+    }
+}
+
+impl Power for FfiRadio<'_> {
+    type Error = i32;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        check((self.0.set_power)(self.0.ctx, power))
+    }
+}
+
+impl DelayNs for FfiRadio<'_> {
+    fn delay_ns(&mut self, ns: u32) {
+        (self.0.delay_us)(self.0.ctx, ns.div_ceil(1000))
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        (self.0.delay_us)(self.0.ctx, us)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Echoing driver context, looping transmitted packets back to receive
+    #[derive(Default)]
+    struct Echo {
+        data: Option<Vec<u8>>,
+        power: Option<i8>,
+    }
+
+    fn echo(ctx: *mut c_void) -> &'static mut Echo {
+        unsafe { &mut *(ctx as *mut Echo) }
+    }
+
+    extern "C" fn start_transmit(ctx: *mut c_void, data: *const u8, len: usize) -> i32 {
+        echo(ctx).data = Some(unsafe { core::slice::from_raw_parts(data, len) }.to_vec());
+        0
+    }
+
+    extern "C" fn check_transmit(_ctx: *mut c_void, done: *mut bool) -> i32 {
+        unsafe { *done = true };
+        0
+    }
+
+    extern "C" fn start_receive(_ctx: *mut c_void) -> i32 {
+        0
+    }
+
+    extern "C" fn check_receive(ctx: *mut c_void, _restart: bool, ready: *mut bool) -> i32 {
+        unsafe { *ready = echo(ctx).data.is_some() };
+        0
+    }
+
+    extern "C" fn get_received(
+        ctx: *mut c_void,
+        buff: *mut u8,
+        _len: usize,
+        received: *mut usize,
+        rssi: *mut i16,
+    ) -> i32 {
+        let d = echo(ctx).data.take().unwrap();
+        unsafe {
+            core::ptr::copy_nonoverlapping(d.as_ptr(), buff, d.len());
+            *received = d.len();
+            *rssi = -42;
+        }
+        0
+    }
+
+    extern "C" fn set_power(ctx: *mut c_void, power: i8) -> i32 {
+        echo(ctx).power = Some(power);
+        0
+    }
+
+    extern "C" fn delay_us(_ctx: *mut c_void, _us: u32) {}
+
+    fn vtable(ctx: &mut Echo) -> RadioVTable {
+        RadioVTable {
+            ctx: ctx as *mut Echo as *mut c_void,
+            start_transmit,
+            check_transmit,
+            start_receive,
+            check_receive,
+            get_received,
+            set_power,
+            delay_us,
+        }
+    }
+
+    #[test]
+    fn ffi_transmit_receive() {
+        let mut ctx = Echo::default();
+        let vt = vtable(&mut ctx);
+        let opts = radio_blocking_options_default();
+
+        let data = [0xaa, 0xbb, 0xcc];
+        assert_eq!(
+            unsafe { radio_do_transmit(&vt, data.as_ptr(), data.len(), opts) },
+            RADIO_OK
+        );
+
+        let (mut buff, mut n, mut rssi) = ([0u8; 16], 0, 0);
+        let r = unsafe {
+            radio_do_receive(&vt, buff.as_mut_ptr(), buff.len(), &mut n, &mut rssi, opts)
+        };
+        assert_eq!(r, RADIO_OK);
+        assert_eq!(&buff[..n], &data);
+        assert_eq!(rssi, -42);
+
+        // Nothing left to receive
+        let r = unsafe {
+            radio_do_receive(&vt, buff.as_mut_ptr(), buff.len(), &mut n, &mut rssi, opts)
+        };
+        assert_eq!(r, RADIO_ERR_TIMEOUT);
+
+        assert_eq!(
+            unsafe { radio_do_transmit(core::ptr::null(), data.as_ptr(), 0, opts) },
+            RADIO_ERR_INVALID
+        );
+    }
+
+    #[test]
+    fn ffi_ping_pong() {
+        let mut ctx = Echo::default();
+        let vt = vtable(&mut ctx);
+
+        let options = RadioPingPongOptions {
+            rounds: 5,
+            set_power: true,
+            power: 10,
+            delay_us: 0,
+            ..Default::default()
+        };
+        let mut info = RadioLinkTestInfo::default();
+
+        assert_eq!(
+            unsafe { radio_do_ping_pong(&vt, &options, &mut info) },
+            RADIO_OK
+        );
+        assert_eq!(info.sent, 5);
+        assert_eq!(info.received, 5);
+        assert_eq!(info.local_rssi_mean, -42.0);
+        assert!(info.remote_rssi_mean.is_nan());
+        assert_eq!(ctx.power, Some(10));
+    }
+}
@@ -0,0 +1,552 @@
+//! Frequency-hopping spread spectrum
+//!
+//! [`Hopper`] wraps a radio implementing [`Channel`], cycling through a channel list with
+//! a fixed dwell time per channel. Hops occur between operations: on starting a transmit
+//! or receive once the dwell interval has elapsed, and while receiving (where a frame in
+//! flight at the hop boundary is lost, as with any FHSS system). This allows helpers to
+//! run unchanged across a hop sequence, for example for FCC 15.247 dwell time testing.
+//!
+//! [`HopFollower`] joins a running network with a known hop sequence but unknown timing,
+//! parking on a single channel until a frame is heard, then following the sequence with
+//! the hop timing refined from the arrival time of each received frame.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::debug;
+
+use crate::clock::Clock;
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// Hopper wraps a radio, hopping through the channel list `C` on a fixed schedule
+///
+/// `C` may be any list of channels (eg. an array, slice or `Vec`), with time read from
+/// the provided [`Clock`].
+pub struct Hopper<T, C, K> {
+    radio: T,
+    channels: C,
+    clock: K,
+    interval_us: u64,
+    index: Option<usize>,
+    hop_start: u64,
+    hops: u32,
+}
+
+impl<T, C, K, E> Hopper<T, C, K>
+where
+    T: Channel<Error = E>,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+{
+    /// Wrap a radio, dwelling on each channel for `interval`
+    ///
+    /// The first channel is selected on the first operation.
+    pub fn new(radio: T, channels: C, clock: K, interval: Duration) -> Self {
+        Self {
+            radio,
+            channels,
+            clock,
+            interval_us: interval.as_micros() as u64,
+            index: None,
+            hop_start: 0,
+            hops: 0,
+        }
+    }
+
+    /// Index of the current channel, `None` prior to the first operation
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// Current channel, `None` prior to the first operation
+    pub fn channel(&self) -> Option<&T::Channel> {
+        self.index.map(|i| &self.channels.as_ref()[i])
+    }
+
+    /// Number of hops performed
+    pub fn hops(&self) -> u32 {
+        self.hops
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio, channel list and clock
+    pub fn free(self) -> (T, C, K) {
+        (self.radio, self.channels, self.clock)
+    }
+
+    /// Hop to the next channel if the dwell interval has elapsed, returning whether a
+    /// hop occurred
+    pub fn hop_if_due(&mut self) -> Result<bool, E> {
+        let n = self.channels.as_ref().len();
+        if n == 0 {
+            return Ok(false);
+        }
+
+        let now = self.clock.now_us();
+        let next = match self.index {
+            None => 0,
+            Some(_) if now.saturating_sub(self.hop_start) < self.interval_us => return Ok(false),
+            Some(i) => (i + 1) % n,
+        };
+
+        #[cfg(any(feature = "log", feature = "defmt"))]
+        debug!("Hopping to channel index {}", next);
+
+        self.radio.set_channel(&self.channels.as_ref()[next])?;
+        self.index = Some(next);
+        self.hop_start = now;
+        self.hops += 1;
+
+        Ok(true)
+    }
+}
+
+impl<T, C, K, E> Transmit for Hopper<T, C, K>
+where
+    T: Transmit<Error = E> + Channel<Error = E>,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+    E: core::fmt::Debug,
+{
+    type Error = E;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.hop_if_due()?;
+        self.radio.start_transmit(data)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit()
+    }
+}
+
+impl<T, C, K, E> Receive for Hopper<T, C, K>
+where
+    T: Receive<Error = E> + Channel<Error = E>,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+    E: core::fmt::Debug,
+{
+    type Info = T::Info;
+    type Error = E;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.hop_if_due()?;
+        self.radio.start_receive()
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        // Hop while awaiting frames, restarting receive on the new channel
+        if self.hop_if_due()? {
+            self.radio.start_receive()?;
+            return Ok(false);
+        }
+        self.radio.check_receive(restart)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff)
+    }
+}
+
+impl<T: Power, C, K> Power for Hopper<T, C, K> {
+    type Error = T::Error;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power)
+    }
+}
+
+impl<T: Rssi, C, K> Rssi for Hopper<T, C, K> {
+    type Error = T::Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi()
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out)
+    }
+}
+
+impl<T: DelayNs, C, K> DelayNs for Hopper<T, C, K> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+/// Hop synchronisation state for a [`HopFollower`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HopSync {
+    /// Parked on the sync channel awaiting a frame
+    Searching,
+    /// Following the hop sequence, with the time of a hop to the first channel bounded
+    /// to the window `[lo, hi]` (in clock microseconds)
+    Locked { lo: u64, hi: u64 },
+}
+
+/// HopFollower wraps a receiving radio, synchronising to a running hop sequence `C`
+///
+/// The follower parks on the channel at `sync_index` until a frame is received, which
+/// places the network hop to that channel within the preceding dwell interval. Each
+/// subsequent frame (received on the channel the network is expected to be on) narrows
+/// the window of hop times consistent with all receptions, with the follower hopping at
+/// the window midpoint. Where no frames are heard for `relock` full sequences (or
+/// receptions are inconsistent with the window) the follower returns to searching.
+pub struct HopFollower<T, C, K> {
+    radio: T,
+    channels: C,
+    clock: K,
+    interval_us: u64,
+    sync_index: usize,
+    relock: u32,
+    sync: HopSync,
+    index: Option<usize>,
+    last_rx: u64,
+    hops: u32,
+}
+
+impl<T, C, K, E> HopFollower<T, C, K>
+where
+    T: Channel<Error = E>,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+{
+    /// Wrap a radio following a network dwelling on each channel for `interval`,
+    /// parking on the channel at `sync_index` while searching
+    pub fn new(radio: T, channels: C, clock: K, interval: Duration, sync_index: usize) -> Self {
+        Self {
+            radio,
+            channels,
+            clock,
+            interval_us: interval.as_micros().max(1) as u64,
+            sync_index,
+            relock: 4,
+            sync: HopSync::Searching,
+            index: None,
+            last_rx: 0,
+            hops: 0,
+        }
+    }
+
+    /// Set the number of hop sequences without receptions before searching again
+    pub fn with_relock(mut self, relock: u32) -> Self {
+        self.relock = relock.max(1);
+        self
+    }
+
+    /// Synchronisation state
+    pub fn sync(&self) -> HopSync {
+        self.sync
+    }
+
+    /// Index of the current channel, `None` prior to the first operation
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// Number of hops performed
+    pub fn hops(&self) -> u32 {
+        self.hops
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio, channel list and clock
+    pub fn free(self) -> (T, C, K) {
+        (self.radio, self.channels, self.clock)
+    }
+
+    fn cycle_us(&self) -> u64 {
+        self.interval_us * self.channels.as_ref().len() as u64
+    }
+
+    /// Expected network channel index at the provided time
+    fn expected_index(&self, now: u64) -> usize {
+        let n = self.channels.as_ref().len();
+        match self.sync {
+            HopSync::Searching => self.sync_index % n,
+            HopSync::Locked { lo, hi } => {
+                let start = lo + (hi - lo) / 2;
+                let cycle = self.cycle_us();
+                // Offset into the sequence, allowing for a start in the future
+                let offset = (now + cycle - start % cycle) % cycle;
+                (offset / self.interval_us) as usize
+            }
+        }
+    }
+
+    /// Hop to the expected network channel, returning whether a hop occurred
+    pub fn follow(&mut self) -> Result<bool, E> {
+        if self.channels.as_ref().is_empty() {
+            return Ok(false);
+        }
+
+        let now = self.clock.now_us();
+        if let HopSync::Locked { .. } = self.sync
+            && now.saturating_sub(self.last_rx) > self.cycle_us() * self.relock as u64
+        {
+            #[cfg(any(feature = "log", feature = "defmt"))]
+            debug!("Hop sync lost, searching");
+
+            self.sync = HopSync::Searching;
+        }
+
+        let next = self.expected_index(now);
+        if self.index == Some(next) {
+            return Ok(false);
+        }
+
+        #[cfg(any(feature = "log", feature = "defmt"))]
+        debug!("Following to channel index {}", next);
+
+        self.radio.set_channel(&self.channels.as_ref()[next])?;
+        self.index = Some(next);
+        self.hops += 1;
+
+        Ok(true)
+    }
+
+    /// Update hop timing from a frame received on the current channel
+    fn observe(&mut self, now: u64) {
+        let i = match self.index {
+            Some(i) => i as u64,
+            None => return,
+        };
+        let cycle = self.cycle_us();
+
+        // The network hopped to the first channel within this window
+        let hi_rx = now.saturating_sub(i * self.interval_us);
+        let lo_rx = hi_rx.saturating_sub(self.interval_us);
+
+        self.sync = match self.sync {
+            HopSync::Locked { lo, hi } => {
+                // Align the observation with the current window, by whole sequences
+                let k = (hi_rx.saturating_sub(hi) + cycle / 2) / cycle;
+                let (lo_rx, hi_rx) = (
+                    lo_rx.saturating_sub(k * cycle),
+                    hi_rx.saturating_sub(k * cycle),
+                );
+
+                match (lo.max(lo_rx), hi.min(hi_rx)) {
+                    (l, h) if l <= h => HopSync::Locked { lo: l, hi: h },
+                    _ => HopSync::Locked {
+                        lo: lo_rx,
+                        hi: hi_rx,
+                    },
+                }
+            }
+            HopSync::Searching => HopSync::Locked {
+                lo: lo_rx,
+                hi: hi_rx,
+            },
+        };
+        self.last_rx = now;
+    }
+}
+
+impl<T, C, K, E> Receive for HopFollower<T, C, K>
+where
+    T: Receive<Error = E> + Channel<Error = E>,
+    C: AsRef<[T::Channel]>,
+    K: Clock,
+    E: core::fmt::Debug,
+{
+    type Info = T::Info;
+    type Error = E;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.follow()?;
+        self.radio.start_receive()
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        // Follow the network while awaiting frames, restarting receive on the new channel
+        if self.follow()? {
+            self.radio.start_receive()?;
+            return Ok(false);
+        }
+        self.radio.check_receive(restart)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let res = self.radio.get_received(buff)?;
+        self.observe(self.clock.now_us());
+        Ok(res)
+    }
+}
+
+impl<T: Rssi, C, K> Rssi for HopFollower<T, C, K> {
+    type Error = T::Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi()
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out)
+    }
+}
+
+impl<T: DelayNs, C, K> DelayNs for HopFollower<T, C, K> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    /// Radio recording the channel of each transmission
+    #[derive(Default)]
+    struct ChannelRadio {
+        channel: u8,
+        sent: [u8; 8],
+        count: usize,
+    }
+
+    impl Transmit for ChannelRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            self.sent[self.count] = self.channel;
+            self.count += 1;
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Channel for ChannelRadio {
+        type Channel = u8;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &u8) -> Result<(), Self::Error> {
+            self.channel = *channel;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hop_schedule() {
+        let clock = VirtualClock::new();
+        let mut h = Hopper::new(
+            ChannelRadio::default(),
+            [11, 15, 20],
+            &clock,
+            Duration::from_millis(100),
+        );
+        assert_eq!(h.channel(), None);
+
+        // Two transmissions per 100 ms dwell
+        for _ in 0..8 {
+            h.start_transmit(&[0]).unwrap();
+            clock.advance(Duration::from_millis(50));
+        }
+
+        assert_eq!(h.inner().sent, [11, 11, 15, 15, 20, 20, 11, 11]);
+        assert_eq!(h.hops(), 4);
+        assert_eq!(h.channel(), Some(&11));
+    }
+
+    /// Radio hearing a frame each millisecond on the channel of a hopping network
+    struct NetworkRadio<'a> {
+        clock: &'a VirtualClock,
+        channel: u8,
+        start_us: u64,
+    }
+
+    impl NetworkRadio<'_> {
+        fn network_channel(&self) -> u8 {
+            [11, 15, 20, 25][((self.clock.now_us() - self.start_us) / 10_000 % 4) as usize]
+        }
+    }
+
+    impl Receive for NetworkRadio<'_> {
+        type Error = ();
+        type Info = crate::BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.channel == self.network_channel())
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Ok((1, crate::BasicInfo::default()))
+        }
+    }
+
+    impl Channel for NetworkRadio<'_> {
+        type Channel = u8;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &u8) -> Result<(), Self::Error> {
+            self.channel = *channel;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn late_join_sync() {
+        let clock = VirtualClock::new();
+        clock.set_us(1_000_000);
+
+        // Network started 3.3 dwells before the sniffer
+        let radio = NetworkRadio {
+            clock: &clock,
+            channel: 0,
+            start_us: 1_000_000 - 33_000,
+        };
+        let mut f = HopFollower::new(
+            radio,
+            [11, 15, 20, 25],
+            &clock,
+            Duration::from_millis(10),
+            2,
+        );
+        assert_eq!(f.sync(), HopSync::Searching);
+
+        let mut buff = [0u8; 4];
+        let mut received = [0u32; 2];
+        for t in 0..400 {
+            clock.advance(Duration::from_micros(1_000));
+            if f.check_receive(true).unwrap() {
+                f.get_received(&mut buff).unwrap();
+                received[(t >= 200) as usize] += 1;
+            }
+        }
+
+        // Once locked the hop time window converges to within the frame spacing of the
+        // network timing, with the follower hearing most frames
+        match f.sync() {
+            HopSync::Locked { lo, hi } => {
+                assert!(hi - lo <= 2_000);
+                assert!((lo % 40_000..=hi % 40_000).contains(&(967_000 % 40_000)));
+            }
+            s => panic!("unexpected sync state {:?}", s),
+        }
+        assert!(received[1] >= 170, "received: {:?}", received);
+    }
+}
@@ -0,0 +1,301 @@
+//! Fixed-length frame adaptor
+//!
+//! Some transceivers only support fixed-length frames. [`FixedLength`] wraps such a radio,
+//! padding outgoing payloads to the frame length with a configurable pad byte and an
+//! embedded length field, and stripping both from received frames, so variable length
+//! helpers and framing layers operate unchanged.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+
+use embedded_hal::delay::DelayNs;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// FixedLengthOptions configure padding of fixed-length frames
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedLengthOptions {
+    /// Byte used to pad payloads to the fixed frame length
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub pad_byte: u8,
+
+    /// Omit the embedded length field, received payloads then include padding
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub no_length_field: bool,
+}
+
+/// Errors from a radio wrapped in [`FixedLength`]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FixedLengthError<E> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(E),
+    /// Payload too long for the fixed frame length
+    #[cfg_attr(feature = "thiserror", error("Payload too long"))]
+    TooLong,
+    /// Received frame with an invalid length field
+    #[cfg_attr(feature = "thiserror", error("Invalid length field"))]
+    InvalidLength,
+}
+
+/// FixedLength wraps a radio supporting only `N` byte frames
+///
+/// With the length field enabled, frames are encoded as `[len, payload.., pad..]` allowing
+/// payloads of up to `N - 1` bytes.
+pub struct FixedLength<T, const N: usize> {
+    radio: T,
+    options: FixedLengthOptions,
+    buff: [u8; N],
+}
+
+impl<T, const N: usize> FixedLength<T, N> {
+    /// Wrap a fixed-length radio
+    pub fn new(radio: T, options: FixedLengthOptions) -> Self {
+        Self {
+            radio,
+            options,
+            buff: [0u8; N],
+        }
+    }
+
+    /// Maximum payload length
+    pub fn mtu(&self) -> usize {
+        match self.options.no_length_field {
+            true => N,
+            false => N.saturating_sub(1).min(u8::MAX as usize),
+        }
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+
+    /// Encode a payload into the padded frame buffer
+    fn encode<E>(&mut self, data: &[u8]) -> Result<(), FixedLengthError<E>> {
+        if data.len() > self.mtu() {
+            return Err(FixedLengthError::TooLong);
+        }
+
+        self.buff.fill(self.options.pad_byte);
+
+        let offset = match self.options.no_length_field {
+            true => 0,
+            false => {
+                self.buff[0] = data.len() as u8;
+                1
+            }
+        };
+        self.buff[offset..][..data.len()].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    /// Decode a received frame of length `n` into the provided buffer
+    fn decode<E>(&self, n: usize, buff: &mut [u8]) -> Result<usize, FixedLengthError<E>> {
+        let payload = match self.options.no_length_field {
+            true => &self.buff[..n],
+            false => {
+                let len = *self.buff.first().ok_or(FixedLengthError::InvalidLength)? as usize;
+                self.buff[..n]
+                    .get(1..1 + len)
+                    .ok_or(FixedLengthError::InvalidLength)?
+            }
+        };
+
+        let len = payload.len().min(buff.len());
+        buff[..len].copy_from_slice(&payload[..len]);
+
+        Ok(len)
+    }
+}
+
+impl<T, E, const N: usize> Transmit for FixedLength<T, N>
+where
+    T: Transmit<Error = E>,
+    E: Debug,
+{
+    type Error = FixedLengthError<E>;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.encode(data)?;
+        self.radio
+            .start_transmit(&self.buff)
+            .map_err(FixedLengthError::Radio)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit().map_err(FixedLengthError::Radio)
+    }
+}
+
+impl<T, E, const N: usize> Receive for FixedLength<T, N>
+where
+    T: Receive<Error = E>,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = FixedLengthError<E>;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive().map_err(FixedLengthError::Radio)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio
+            .check_receive(restart)
+            .map_err(FixedLengthError::Radio)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let (n, i) = self
+            .radio
+            .get_received(&mut self.buff)
+            .map_err(FixedLengthError::Radio)?;
+
+        let n = self.decode(n, buff)?;
+        Ok((n, i))
+    }
+}
+
+impl<T, E, const N: usize> Power for FixedLength<T, N>
+where
+    T: Power<Error = E>,
+    E: Debug,
+{
+    type Error = FixedLengthError<E>;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power).map_err(FixedLengthError::Radio)
+    }
+}
+
+impl<T, E, const N: usize> Rssi for FixedLength<T, N>
+where
+    T: Rssi<Error = E>,
+    E: Debug,
+{
+    type Error = FixedLengthError<E>;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi().map_err(FixedLengthError::Radio)
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out).map_err(FixedLengthError::Radio)
+    }
+}
+
+impl<T, E, const N: usize> Channel for FixedLength<T, N>
+where
+    T: Channel<Error = E>,
+    E: Debug,
+{
+    type Channel = T::Channel;
+    type Error = FixedLengthError<E>;
+
+    fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+        self.radio
+            .set_channel(channel)
+            .map_err(FixedLengthError::Radio)
+    }
+}
+
+impl<T: DelayNs, const N: usize> DelayNs for FixedLength<T, N> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Loopback radio accepting only 8 byte frames
+    #[derive(Default)]
+    struct Fixed8 {
+        last: Option<[u8; 8]>,
+    }
+
+    impl Transmit for Fixed8 {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.last = Some(data.try_into().map_err(|_| ())?);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for Fixed8 {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().ok_or(())?;
+            buff[..8].copy_from_slice(&d);
+            Ok((8, BasicInfo::default()))
+        }
+    }
+
+    #[test]
+    fn fixed_length_frames() {
+        let options = FixedLengthOptions {
+            pad_byte: 0xee,
+            no_length_field: false,
+        };
+        let mut r = FixedLength::<_, 8>::new(Fixed8::default(), options);
+        assert_eq!(r.mtu(), 7);
+
+        r.start_transmit(&[1, 2, 3]).unwrap();
+        assert_eq!(r.inner().last, Some([3, 1, 2, 3, 0xee, 0xee, 0xee, 0xee]));
+
+        let mut buff = [0u8; 16];
+        assert_eq!(r.get_received(&mut buff).map(|(n, _)| n), Ok(3));
+        assert_eq!(&buff[..3], &[1, 2, 3]);
+
+        assert_eq!(r.start_transmit(&[0u8; 8]), Err(FixedLengthError::TooLong));
+
+        // Corrupt length fields are rejected
+        r.inner().last = Some([9, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            r.get_received(&mut buff).map(|(n, _)| n),
+            Err(FixedLengthError::InvalidLength)
+        );
+
+        // Without a length field payloads include padding
+        let options = FixedLengthOptions {
+            pad_byte: 0,
+            no_length_field: true,
+        };
+        let mut r = FixedLength::<_, 8>::new(Fixed8::default(), options);
+        r.start_transmit(&[1, 2]).unwrap();
+        assert_eq!(r.get_received(&mut buff).map(|(n, _)| n), Ok(8));
+        assert_eq!(&buff[..8], &[1, 2, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,382 @@
+//! Lightweight packet framing with source and destination addressing
+//!
+//! Frames consist of a fixed-length [`Header`] followed by the payload, allowing
+//! multiple nodes to share a channel and filter on their own address.
+//!
+//! Payloads exceeding the radio MTU may be split with [`fragment`], with each fragment
+//! flagged with [`FRAGMENT_FLAG`] and carrying a fragment header (`[index, count, offset
+//! (BE)]`) ahead of the payload, and reassembled on receipt with a [`Reassembler`].
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+/// Node address
+pub type Address = u16;
+
+/// Broadcast address, frames sent to this address are accepted by all nodes
+pub const BROADCAST: Address = 0xffff;
+
+/// Frame flag set on fragments of payloads exceeding the frame MTU
+pub const FRAGMENT_FLAG: u8 = 0x20;
+
+/// Fragment header length, encoded as `[index, count, offset (BE)]` following the frame header
+pub const FRAGMENT_HEADER_LEN: usize = 4;
+
+/// Maximum number of fragments per payload
+pub const MAX_FRAGMENTS: usize = 64;
+
+/// Frame header, encoded as `[flags, seq, dst (BE), src (BE)]`
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    /// Frame flags
+    pub flags: u8,
+    /// Sequence number
+    pub seq: u8,
+    /// Destination address
+    pub dst: Address,
+    /// Source address
+    pub src: Address,
+}
+
+/// FrameError describes failures encoding or decoding frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    /// Received data is too short to contain a frame header
+    #[cfg_attr(feature = "thiserror", error("Frame too short"))]
+    TooShort,
+    /// Provided buffer is too small for the encoded frame
+    #[cfg_attr(feature = "thiserror", error("Buffer too small"))]
+    BufferTooSmall,
+    /// Payload requires more than [`MAX_FRAGMENTS`] fragments
+    #[cfg_attr(feature = "thiserror", error("Too many fragments"))]
+    TooManyFragments,
+    /// Received fragment header is invalid
+    #[cfg_attr(feature = "thiserror", error("Invalid fragment"))]
+    InvalidFragment,
+}
+
+impl Header {
+    /// Encoded header length in bytes
+    pub const LEN: usize = 6;
+
+    /// Create a new header with the provided addresses and sequence number
+    pub fn new(src: Address, dst: Address, seq: u8) -> Self {
+        Self {
+            flags: 0,
+            seq,
+            dst,
+            src,
+        }
+    }
+
+    /// Create a reply header, swapping source and destination addresses
+    pub fn reply(&self, src: Address) -> Self {
+        Self {
+            flags: self.flags,
+            seq: self.seq,
+            dst: self.src,
+            src,
+        }
+    }
+
+    /// Check whether this frame should be accepted by the provided address
+    pub fn is_for(&self, address: Address) -> bool {
+        self.dst == address || self.dst == BROADCAST
+    }
+
+    /// Encode the header to bytes
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let dst = self.dst.to_be_bytes();
+        let src = self.src.to_be_bytes();
+        [self.flags, self.seq, dst[0], dst[1], src[0], src[1]]
+    }
+
+    /// Decode a header from the start of the provided data
+    pub fn from_bytes(data: &[u8]) -> Result<Self, FrameError> {
+        if data.len() < Self::LEN {
+            return Err(FrameError::TooShort);
+        }
+
+        Ok(Self {
+            flags: data[0],
+            seq: data[1],
+            dst: u16::from_be_bytes([data[2], data[3]]),
+            src: u16::from_be_bytes([data[4], data[5]]),
+        })
+    }
+}
+
+/// Encode a frame with the provided header and payload into `buff`, returning the frame length
+pub fn encode(header: &Header, payload: &[u8], buff: &mut [u8]) -> Result<usize, FrameError> {
+    let n = Header::LEN + payload.len();
+    if buff.len() < n {
+        return Err(FrameError::BufferTooSmall);
+    }
+
+    buff[..Header::LEN].copy_from_slice(&header.to_bytes());
+    buff[Header::LEN..n].copy_from_slice(payload);
+
+    Ok(n)
+}
+
+/// Decode a frame into a header and payload
+pub fn decode(data: &[u8]) -> Result<(Header, &[u8]), FrameError> {
+    let header = Header::from_bytes(data)?;
+    Ok((header, &data[Header::LEN..]))
+}
+
+/// Fragments of a payload, encoded in turn with [`Fragments::encode_next`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fragments<'a> {
+    header: Header,
+    payload: &'a [u8],
+    chunk: usize,
+    count: usize,
+    index: usize,
+}
+
+/// Split a payload into frames of at most `mtu` bytes
+///
+/// Payloads fitting within a single frame are sent unfragmented.
+pub fn fragment<'a>(
+    header: &Header,
+    payload: &'a [u8],
+    mtu: usize,
+) -> Result<Fragments<'a>, FrameError> {
+    if Header::LEN + payload.len() <= mtu {
+        return Ok(Fragments {
+            header: *header,
+            payload,
+            chunk: payload.len(),
+            count: 1,
+            index: 0,
+        });
+    }
+
+    let chunk = mtu.saturating_sub(Header::LEN + FRAGMENT_HEADER_LEN);
+    if chunk == 0 {
+        return Err(FrameError::BufferTooSmall);
+    }
+
+    let count = payload.len().div_ceil(chunk);
+    if count > MAX_FRAGMENTS {
+        return Err(FrameError::TooManyFragments);
+    }
+
+    Ok(Fragments {
+        header: Header {
+            flags: header.flags | FRAGMENT_FLAG,
+            ..*header
+        },
+        payload,
+        chunk,
+        count,
+        index: 0,
+    })
+}
+
+impl Fragments<'_> {
+    /// Number of frames
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Encode the next frame into `buff`, returning the frame length or `None` once all
+    /// frames have been encoded
+    pub fn encode_next(&mut self, buff: &mut [u8]) -> Option<Result<usize, FrameError>> {
+        if self.index >= self.count {
+            return None;
+        }
+
+        let offset = self.index * self.chunk;
+        let data = &self.payload[offset..(offset + self.chunk).min(self.payload.len())];
+        self.index += 1;
+
+        // Unfragmented payloads
+        if self.header.flags & FRAGMENT_FLAG == 0 {
+            return Some(encode(&self.header, data, buff));
+        }
+
+        let n = Header::LEN + FRAGMENT_HEADER_LEN + data.len();
+        if buff.len() < n {
+            return Some(Err(FrameError::BufferTooSmall));
+        }
+
+        let o = (offset as u16).to_be_bytes();
+        buff[..Header::LEN].copy_from_slice(&self.header.to_bytes());
+        buff[Header::LEN..][..FRAGMENT_HEADER_LEN].copy_from_slice(&[
+            (self.index - 1) as u8,
+            self.count as u8,
+            o[0],
+            o[1],
+        ]);
+        buff[Header::LEN + FRAGMENT_HEADER_LEN..n].copy_from_slice(data);
+
+        Some(Ok(n))
+    }
+}
+
+/// Reassembler for fragmented payloads of up to `M` bytes
+///
+/// One payload is reassembled at a time, with incomplete payloads discarded on receipt of a
+/// fragment from a different source or sequence number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reassembler<const M: usize> {
+    active: Option<(Address, u8, u8)>,
+    received: u64,
+    len: usize,
+    buff: [u8; M],
+}
+
+impl<const M: usize> Default for Reassembler<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const M: usize> Reassembler<M> {
+    /// Create a new reassembler
+    pub fn new() -> Self {
+        Self {
+            active: None,
+            received: 0,
+            len: 0,
+            buff: [0u8; M],
+        }
+    }
+
+    /// Push a received frame, returning the complete payload where available
+    ///
+    /// Unfragmented payloads are returned immediately.
+    pub fn push<'a>(
+        &'a mut self,
+        header: &Header,
+        payload: &'a [u8],
+    ) -> Result<Option<&'a [u8]>, FrameError> {
+        if header.flags & FRAGMENT_FLAG == 0 {
+            return Ok(Some(payload));
+        }
+        if payload.len() < FRAGMENT_HEADER_LEN {
+            return Err(FrameError::TooShort);
+        }
+
+        let (index, count) = (payload[0], payload[1]);
+        let offset = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+        if count == 0 || count as usize > MAX_FRAGMENTS || index >= count {
+            return Err(FrameError::InvalidFragment);
+        }
+
+        let data = &payload[FRAGMENT_HEADER_LEN..];
+        let end = offset + data.len();
+        if end > M {
+            return Err(FrameError::BufferTooSmall);
+        }
+
+        // Start a new payload, discarding any incomplete payload
+        let id = (header.src, header.seq, count);
+        if self.active != Some(id) {
+            self.active = Some(id);
+            self.received = 0;
+            self.len = 0;
+        }
+
+        self.buff[offset..end].copy_from_slice(data);
+        self.received |= 1 << index;
+        if index == count - 1 {
+            self.len = end;
+        }
+
+        let mask = u64::MAX >> (64 - count as u32);
+        if self.received != mask {
+            return Ok(None);
+        }
+
+        self.active = None;
+        Ok(Some(&self.buff[..self.len]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_frame() {
+        let h = Header::new(0x0102, 0x0304, 7);
+        let mut buff = [0u8; 16];
+
+        let n = encode(&h, &[0xaa, 0xbb], &mut buff).unwrap();
+        assert_eq!(
+            &buff[..n],
+            &[0x00, 0x07, 0x03, 0x04, 0x01, 0x02, 0xaa, 0xbb]
+        );
+
+        let (d, payload) = decode(&buff[..n]).unwrap();
+        assert_eq!(d, h);
+        assert_eq!(payload, &[0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn frame_errors() {
+        assert_eq!(decode(&[0x00, 0x01]), Err(FrameError::TooShort));
+
+        let mut buff = [0u8; 4];
+        assert_eq!(
+            encode(&Header::default(), &[], &mut buff),
+            Err(FrameError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn frame_addressing() {
+        let h = Header::new(0x0001, 0x0002, 0);
+        assert!(h.is_for(0x0002));
+        assert!(!h.is_for(0x0003));
+        assert!(Header::new(0x0001, BROADCAST, 0).is_for(0x0003));
+
+        let r = h.reply(0x0002);
+        assert_eq!((r.src, r.dst), (0x0002, 0x0001));
+    }
+
+    #[test]
+    fn fragment_reassembly() {
+        let payload: [u8; 20] = core::array::from_fn(|i| i as u8);
+        let header = Header::new(0x0001, 0x0002, 3);
+
+        // 16 byte MTU leaves 6 bytes of payload per fragment
+        let mut f = fragment(&header, &payload, 16).unwrap();
+        assert_eq!(f.count(), 4);
+
+        let mut frames = [[0u8; 16]; 4];
+        let mut lens = [0; 4];
+        for (b, l) in frames.iter_mut().zip(lens.iter_mut()) {
+            *l = f.encode_next(b).unwrap().unwrap();
+        }
+        assert_eq!(f.encode_next(&mut [0u8; 16]), None);
+        assert_eq!(lens, [16, 16, 16, 12]);
+
+        // Fragments may arrive out of order
+        let mut r: Reassembler<64> = Reassembler::new();
+        for i in [1, 3, 0] {
+            let (h, p) = decode(&frames[i][..lens[i]]).unwrap();
+            assert_eq!(r.push(&h, p), Ok(None));
+        }
+        let (h, p) = decode(&frames[2][..lens[2]]).unwrap();
+        assert_eq!(r.push(&h, p), Ok(Some(&payload[..])));
+
+        // Small payloads are not fragmented
+        let mut f = fragment(&header, &payload[..4], 16).unwrap();
+        let mut b = [0u8; 16];
+        let n = f.encode_next(&mut b).unwrap().unwrap();
+        let (h, p) = decode(&b[..n]).unwrap();
+        assert_eq!(h.flags, 0);
+        assert_eq!(r.push(&h, p), Ok(Some(&payload[..4])));
+
+        assert_eq!(
+            fragment(&header, &[0u8; 1024], 16).err(),
+            Some(FrameError::TooManyFragments)
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! External RF front-end (PA / LNA) control
+//!
+//! Boards with external front-end modules must enable the power amplifier while
+//! transmitting and the low-noise amplifier while receiving. [`RfFrontend`] abstracts
+//! this switching, with [`Frontend`] wrapping a radio to key the front-end around
+//! transmit and receive transitions so the blocking helpers work unchanged.
+//! [`GpioFrontend`] provides an implementation for PA / LNA enable lines.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// Front-end operating mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrontendMode {
+    /// PA and LNA disabled
+    Off,
+    /// PA enabled for transmission
+    Tx,
+    /// LNA enabled for reception
+    Rx,
+}
+
+/// RfFrontend trait for controlling external front-end modules
+pub trait RfFrontend {
+    /// Front-end error type
+    type Error: Debug;
+
+    /// Switch the front-end to the provided mode
+    fn set_mode(&mut self, mode: FrontendMode) -> Result<(), Self::Error>;
+}
+
+/// Front-end with PA and LNA enable lines (active high)
+pub struct GpioFrontend<PA, LNA> {
+    pa: PA,
+    lna: LNA,
+}
+
+impl<PA, LNA> GpioFrontend<PA, LNA> {
+    /// Create a front-end from PA and LNA enable lines
+    pub fn new(pa: PA, lna: LNA) -> Self {
+        Self { pa, lna }
+    }
+
+    /// Release the enable lines
+    pub fn free(self) -> (PA, LNA) {
+        (self.pa, self.lna)
+    }
+}
+
+impl<PA, LNA, E> RfFrontend for GpioFrontend<PA, LNA>
+where
+    PA: OutputPin<Error = E>,
+    LNA: OutputPin<Error = E>,
+    E: Debug,
+{
+    type Error = E;
+
+    fn set_mode(&mut self, mode: FrontendMode) -> Result<(), Self::Error> {
+        // Disable before enabling to avoid both amplifiers being active
+        match mode {
+            FrontendMode::Off => {
+                self.pa.set_low()?;
+                self.lna.set_low()
+            }
+            FrontendMode::Tx => {
+                self.lna.set_low()?;
+                self.pa.set_high()
+            }
+            FrontendMode::Rx => {
+                self.pa.set_low()?;
+                self.lna.set_high()
+            }
+        }
+    }
+}
+
+/// Errors from a radio wrapped in [`Frontend`]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrontendError<E, F> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(E),
+    /// Front-end error
+    #[cfg_attr(feature = "thiserror", error("Front-end: {0:?}"))]
+    Frontend(F),
+}
+
+/// Frontend wraps a radio with an external front-end
+///
+/// The PA is enabled when starting a transmission and disabled on completion, and the LNA
+/// is enabled when starting receive mode.
+pub struct Frontend<T, F> {
+    radio: T,
+    frontend: F,
+    mode: FrontendMode,
+}
+
+impl<T, F: RfFrontend> Frontend<T, F> {
+    /// Wrap a radio with the provided front-end
+    pub fn new(radio: T, frontend: F) -> Self {
+        Self {
+            radio,
+            frontend,
+            mode: FrontendMode::Off,
+        }
+    }
+
+    /// Current front-end mode
+    pub fn mode(&self) -> FrontendMode {
+        self.mode
+    }
+
+    /// Switch the front-end mode, skipping redundant switches
+    pub fn set_mode<E>(&mut self, mode: FrontendMode) -> Result<(), FrontendError<E, F::Error>> {
+        if self.mode != mode {
+            self.frontend
+                .set_mode(mode)
+                .map_err(FrontendError::Frontend)?;
+            self.mode = mode;
+        }
+        Ok(())
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio and front-end
+    pub fn free(self) -> (T, F) {
+        (self.radio, self.frontend)
+    }
+}
+
+impl<T, F, E> Transmit for Frontend<T, F>
+where
+    T: Transmit<Error = E>,
+    F: RfFrontend,
+    E: Debug,
+{
+    type Error = FrontendError<E, F::Error>;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.set_mode(FrontendMode::Tx)?;
+        self.radio
+            .start_transmit(data)
+            .map_err(FrontendError::Radio)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        let done = self.radio.check_transmit().map_err(FrontendError::Radio)?;
+        if done {
+            self.set_mode(FrontendMode::Off)?;
+        }
+        Ok(done)
+    }
+}
+
+impl<T, F, E> Receive for Frontend<T, F>
+where
+    T: Receive<Error = E>,
+    F: RfFrontend,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = FrontendError<E, F::Error>;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.set_mode(FrontendMode::Rx)?;
+        self.radio.start_receive().map_err(FrontendError::Radio)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio
+            .check_receive(restart)
+            .map_err(FrontendError::Radio)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff).map_err(FrontendError::Radio)
+    }
+}
+
+impl<T, F, E> Power for Frontend<T, F>
+where
+    T: Power<Error = E>,
+    F: RfFrontend,
+    E: Debug,
+{
+    type Error = FrontendError<E, F::Error>;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power).map_err(FrontendError::Radio)
+    }
+}
+
+impl<T, F, E> Rssi for Frontend<T, F>
+where
+    T: Rssi<Error = E>,
+    F: RfFrontend,
+    E: Debug,
+{
+    type Error = FrontendError<E, F::Error>;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi().map_err(FrontendError::Radio)
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out).map_err(FrontendError::Radio)
+    }
+}
+
+impl<T, F, E> Channel for Frontend<T, F>
+where
+    T: Channel<Error = E>,
+    F: RfFrontend,
+    E: Debug,
+{
+    type Channel = T::Channel;
+    type Error = FrontendError<E, F::Error>;
+
+    fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+        self.radio
+            .set_channel(channel)
+            .map_err(FrontendError::Radio)
+    }
+}
+
+impl<T: DelayNs, F> DelayNs for Frontend<T, F> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::ErrorType;
+
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Enable line with a shared level
+    struct Line<'a>(&'a Cell<bool>);
+
+    impl ErrorType for Line<'_> {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Line<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    /// Radio completing transmissions immediately
+    struct NullRadio;
+
+    impl Transmit for NullRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for NullRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Ok((0, BasicInfo::default()))
+        }
+    }
+
+    #[test]
+    fn frontend_switching() {
+        let (pa, lna) = (Cell::new(false), Cell::new(false));
+        let mut r = Frontend::new(NullRadio, GpioFrontend::new(Line(&pa), Line(&lna)));
+
+        r.start_receive().unwrap();
+        assert_eq!((pa.get(), lna.get()), (false, true));
+
+        r.start_transmit(&[1, 2]).unwrap();
+        assert_eq!((pa.get(), lna.get()), (true, false));
+
+        // PA is disabled on completion
+        assert_eq!(r.check_transmit(), Ok(true));
+        assert_eq!((pa.get(), lna.get()), (false, false));
+        assert_eq!(r.mode(), FrontendMode::Off);
+    }
+}
@@ -1,262 +1,904 @@
 //! Provides common helpers for implementing radio utilities
 //!
+//! Helpers are split by feature, with `helpers-core` providing operations and statistics,
+//! `helpers-cli` adding command line parsing and [`Operation`] dispatch, `helpers-pcap`
+//! adding PCAP capture output, and `helpers-net` adding socket-based services.
+//! The `helpers` feature enables all of these.
+//!
+//! Payload encryption and compression require the `crypto` and `compression` features,
+//! with operations otherwise running unencrypted and uncompressed. Saving and loading
+//! results, tables and JSON output requires the `json` feature (enabled by `helpers-cli`,
+//! `helpers-pcap` and `helpers-net`), and TOML profiles the `toml` feature.
+//!
 //! ## <https://github.com/rust-iot/radio-hal>
 //! ## Copyright 2020-2022 Ryan Kurte
 
-use std::ffi::CString;
-use std::fs::{File, OpenOptions};
+use std::hash::BuildHasher;
 use std::prelude::v1::*;
 use std::string::String;
 use std::time::SystemTime;
 
-use libc::{self};
-
 #[cfg(all(not(feature = "defmt"), feature = "log"))]
-use log::{debug, info};
+use log::{debug, info, warn};
 
 #[cfg(feature = "defmt")]
-use defmt::{debug, info};
+use defmt::{debug, info, warn};
 
-use clap::Parser;
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
 use embedded_hal::delay::DelayNs;
 use humantime::Duration as HumanDuration;
 
 use byteorder::{ByteOrder, NetworkEndian};
-use pcap_file::{
-    DataLink,
-    pcap::{PcapHeader, PcapPacket, PcapWriter},
-};
-use rolling_stats::Stats;
+use serde::{Deserialize, Serialize};
+
+use crypto::{CryptoError, OVERHEAD};
+
+#[cfg(feature = "helpers-cli")]
+mod cli;
+#[cfg(feature = "helpers-cli")]
+pub use cli::*;
+#[cfg(feature = "async")]
+mod asynch;
+#[cfg(feature = "async")]
+pub use asynch::*;
+mod afa;
+pub use afa::*;
+mod alert;
+pub use alert::*;
+mod annotate;
+pub use annotate::*;
+#[cfg(feature = "helpers-pcap")]
+mod anonymize;
+#[cfg(feature = "helpers-pcap")]
+pub use anonymize::*;
+mod beacon;
+pub use beacon::*;
+mod bench;
+pub use bench::*;
+mod ber;
+pub use ber::*;
+mod bridge;
+pub use bridge::*;
+mod budget;
+pub use budget::*;
+mod calibrate;
+pub use calibrate::*;
+mod capability;
+pub use capability::*;
+#[cfg(feature = "helpers-pcap")]
+mod capture;
+#[cfg(feature = "helpers-pcap")]
+pub use capture::*;
+mod channel;
+pub use channel::*;
+mod channel_stats;
+pub use channel_stats::*;
+mod compare;
+pub use compare::*;
+#[cfg_attr(not(feature = "compression"), path = "helpers/compress_disabled.rs")]
+mod compress;
+pub use compress::*;
+#[cfg_attr(not(feature = "crypto"), path = "helpers/crypto_disabled.rs")]
+mod crypto;
+pub use crypto::*;
+mod cw;
+pub use cw::*;
+#[cfg(feature = "helpers-pcap")]
+mod datalink;
+#[cfg(feature = "helpers-pcap")]
+pub use datalink::*;
+mod decode;
+pub use decode::*;
+mod devices;
+pub use devices::*;
+mod diff_rx;
+pub use diff_rx::*;
+mod diversity;
+pub use diversity::*;
+mod dump;
+pub use dump::*;
+mod error;
+pub use error::*;
+mod estimate;
+pub use estimate::*;
+#[cfg(feature = "helpers-cli")]
+mod exit;
+#[cfg(feature = "helpers-cli")]
+pub use exit::*;
+mod fhss;
+pub use fhss::*;
+mod framelog;
+pub use framelog::*;
+mod framing;
+pub use framing::*;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "grpc")]
+pub use grpc::*;
+#[cfg(feature = "helpers-pcap")]
+mod pcap;
+#[cfg(feature = "helpers-pcap")]
+pub use pcap::*;
+mod hil;
+pub use hil::*;
+mod hopping;
+pub use hopping::*;
+mod interfere;
+pub use interfere::*;
+mod journal;
+pub use journal::*;
+mod json;
+mod logging;
+pub use logging::*;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "metrics")]
+pub use metrics::*;
+mod mtu;
+pub use mtu::*;
+mod multi_rx;
+pub use multi_rx::*;
+mod negotiate;
+pub use negotiate::*;
+mod output;
+pub use output::*;
+mod persist;
+pub use persist::*;
+#[cfg(feature = "helpers-cli")]
+mod pipeline;
+#[cfg(feature = "helpers-cli")]
+pub use pipeline::*;
+mod power_sweep;
+pub use power_sweep::*;
+mod profile;
+pub use profile::*;
+mod progress;
+pub use progress::*;
+mod quality;
+pub use quality::*;
+mod rate;
+pub use rate::*;
+mod raw;
+pub use raw::*;
+#[cfg(feature = "helpers-pcap")]
+mod replay;
+#[cfg(feature = "helpers-pcap")]
+pub use replay::*;
+mod relay;
+pub use relay::*;
+mod reliable;
+pub use reliable::*;
+#[cfg(feature = "helpers-net")]
+mod remote;
+#[cfg(feature = "helpers-net")]
+pub use remote::*;
+mod report;
+pub use report::*;
+mod scan;
+pub use scan::*;
+mod sensitivity;
+pub use sensitivity::*;
+mod seqcheck;
+pub use seqcheck::*;
+#[cfg(feature = "helpers-net")]
+mod serve;
+#[cfg(feature = "helpers-net")]
+pub use serve::*;
+mod shutdown;
+pub use shutdown::*;
+mod sink;
+pub use sink::*;
+mod sleep;
+pub use sleep::*;
+mod soak;
+pub use soak::*;
+mod source;
+pub use source::*;
+mod start;
+pub use start::*;
+mod stats;
+pub use stats::*;
+mod status;
+pub use status::*;
+mod stream;
+pub use stream::*;
+mod template;
+pub use template::*;
+mod throughput;
+pub use throughput::*;
+mod timestamp;
+pub use timestamp::*;
+mod trace;
+pub use trace::*;
+mod trigger;
+pub use trigger::*;
+mod traffic;
+pub use traffic::*;
+mod tune;
+pub use tune::*;
+#[cfg(feature = "helpers-net")]
+mod udp;
+#[cfg(feature = "helpers-net")]
+pub use udp::*;
+mod watch;
+pub use watch::*;
+mod worker;
+pub use worker::*;
+#[cfg(feature = "zmq")]
+mod zmq_bus;
+#[cfg(feature = "zmq")]
+pub use zmq_bus::*;
+
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "websocket")]
+pub use websocket::*;
 
 use crate::{
-    Power, Receive, ReceiveInfo, Rssi, Transmit,
+    Interrupts, Power, Receive, ReceiveInfo, Rssi, RxEvent, State, Transmit,
+    afa::AfaOptions,
+    aggregate::subframes,
+    arq::ArqOptions,
     blocking::{BlockingError, BlockingOptions, BlockingReceive, BlockingTransmit},
+    cca::CcaOptions,
+    clock::{Clock, StdClock},
+    duty::DutyCycleOptions,
+    frame::{Address, BROADCAST, Header},
+    prng::XorShift32,
+    reflector::{ReflectorFrame, reflect},
 };
 
-/// Basic operations supported by the helpers package
-#[derive(Clone, Parser, PartialEq, Debug)]
-pub enum Operation {
-    #[clap(name = "tx")]
-    /// Transmit a packet
-    Transmit(TransmitOptions),
+/// Configuration for Transmit operation
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct TransmitOptions {
+    /// Data to be transmitted
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub data: Vec<u8>,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    /// Specify period for repeated transmission (overridden by `--traffic`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub period: Option<HumanDuration>,
+
+    /// Read payloads from a source rather than `--data`
+    /// (`stdin`, `file:PATH`, `gen:SIZE[:COUNT]` or `pcap:PATH`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub source: Option<SourceSpec>,
+
+    /// Generate payloads from a template rather than `--data`, repeated with incrementing
+    /// sequence numbers where `--period` is set (see [`PayloadTemplate`])
+    #[cfg_attr(feature = "clap", clap(long, conflicts_with = "source"))]
+    pub template: Option<PayloadTemplate>,
+
+    /// Pack payloads into aggregated frames of up to SIZE bytes, with a one byte sub-frame
+    /// header per payload (receive with `--aggregated`)
+    #[cfg_attr(feature = "clap", clap(long, value_name = "SIZE"))]
+    pub aggregate: Option<usize>,
+
+    /// Node ID for `{node}` template placeholders
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub node_id: Address,
+
+    /// Log each transmitted frame with a hexdump, time-on-air and power
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub trace_tx: bool,
+
+    /// Bitrate (bits/s) for computing time-on-air, measured transmit time is traced otherwise
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub bitrate: Option<u32>,
+
+    /// Print the expected airtime, duty cycle and completion time without transmitting
+    /// (requires --bitrate)
+    #[cfg_attr(feature = "clap", clap(long, requires = "bitrate"))]
+    pub estimate: bool,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub framing_options: FramingOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub compression_options: CompressionOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub crypto_options: CryptoOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub duty_options: DutyCycleOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub fhss_options: FhssOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub channel_options: ChannelOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub auto_channel_options: AutoChannelOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub arq_options: ArqOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub persist_options: PersistOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub cca_options: CcaOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub afa_options: AfaOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub traffic_options: TrafficOptions,
 
-    #[clap(name = "rx")]
-    /// Receive a packet
-    Receive(ReceiveOptions),
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub sleep_options: SleepOptions,
 
-    #[clap(name = "rssi")]
-    /// Poll RSSI on the configured channel
-    Rssi(RssiOptions),
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
 
-    #[clap(name = "echo")]
-    /// Echo back received messages (useful with Link Test mode)
-    Echo(EchoOptions),
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub negotiate_options: NegotiateOptions,
 
-    #[clap(name = "ping-pong")]
-    /// Link test (ping-pong) mode
-    LinkTest(PingPongOptions),
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub start_options: StartOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
 }
 
-pub fn do_operation<T, I, E>(radio: &mut T, operation: Operation) -> Result<(), BlockingError<E>>
+/// Transmit using the provided configuration, returning the number of payloads sent
+/// (zero where only estimating airtime)
+pub fn do_transmit<T, E>(radio: &mut T, options: TransmitOptions) -> Result<usize, BlockingError<E>>
 where
-    T: Transmit<Error = E>
-        + Power<Error = E>
-        + Receive<Info = I, Error = E>
-        + Rssi<Error = E>
-        + Power<Error = E>
-        + DelayNs,
-    I: ReceiveInfo + Default + std::fmt::Debug,
-    E: std::fmt::Debug,
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
 {
-    let mut buff = [0u8; 1024];
+    // Estimate airtime without accessing the radio
+    if options.estimate {
+        match options.bitrate {
+            Some(b) => {
+                let e = options.estimate(b).expect("Error reading packet source");
+                info!("Transmit estimate: {}", e);
+            }
+            None => warn!("Transmit estimate requires a bitrate"),
+        }
+        return Ok(0);
+    }
 
-    // TODO: the rest
-    match operation {
-        Operation::Transmit(options) => do_transmit(radio, options)?,
-        Operation::Receive(options) => do_receive(radio, &mut buff, options).map(|_| ())?,
-        Operation::Echo(options) => do_echo(radio, &mut buff, options).map(|_| ())?,
-        Operation::Rssi(options) => do_rssi(radio, options).map(|_| ())?,
-        Operation::LinkTest(options) => do_ping_pong(radio, options).map(|_| ())?,
-        //_ => warn!("unsuppored command: {:?}", opts.command),
+    if options.sleep_options.sleep_between {
+        warn!("--sleep-between requires a radio implementing State, see do_operation_state");
     }
 
-    Ok(())
+    let mut source = options.open_source().expect("Error opening packet source");
+
+    do_transmit_from(radio, &mut *source, options)
 }
 
-/// Configuration for Transmit operation
-#[derive(Clone, Parser, PartialEq, Debug)]
-pub struct TransmitOptions {
-    /// Data to be transmitted
-    #[clap(long)]
-    pub data: Vec<u8>,
+/// Transmit using the provided configuration, putting the radio to sleep between
+/// payloads where `--sleep-between` is set
+pub fn do_transmit_sleep<T, E>(
+    radio: &mut T,
+    options: TransmitOptions,
+) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + State<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    if !options.sleep_options.sleep_between || options.estimate {
+        return do_transmit(radio, options);
+    }
 
-    /// Power in dBm (range -18dBm to 13dBm)
-    #[clap(long)]
-    pub power: Option<i8>,
+    let mut source = options.open_source().expect("Error opening packet source");
+    let mut progress = options.progress_options.reporter();
+    let mut sleep = SleepTracker::new();
 
-    /// Specify period for repeated transmission
-    #[clap(long)]
-    pub period: Option<HumanDuration>,
+    let sent = transmit_from(radio, &mut *source, options, &mut *progress, |r, d| {
+        sleep.sleep(r, d)
+    })?;
+    info!("Sleep: {}", sleep.stats());
 
-    #[clap(flatten)]
-    pub blocking_options: BlockingOptions,
+    Ok(sent)
+}
+
+/// Transmit payloads from the provided source until exhausted, waiting for the configured
+/// period between payloads, returning the number of payloads sent
+pub fn do_transmit_from<T, E, S>(
+    radio: &mut T,
+    source: &mut S,
+    options: TransmitOptions,
+) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    S: PacketSource + ?Sized,
+{
+    let mut progress = options.progress_options.reporter();
+    do_transmit_from_with(radio, source, options, &mut *progress)
+}
+
+/// Transmit payloads from the provided source, reporting progress per payload to the
+/// provided reporter
+pub fn do_transmit_from_with<T, E, S, P>(
+    radio: &mut T,
+    source: &mut S,
+    options: TransmitOptions,
+    progress: &mut P,
+) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    S: PacketSource + ?Sized,
+    P: ProgressReporter + ?Sized,
+{
+    transmit_from(radio, source, options, progress, |r, d| {
+        r.delay_us(d.as_micros() as u32);
+        Ok(())
+    })
 }
 
-pub fn do_transmit<T, E>(radio: &mut T, options: TransmitOptions) -> Result<(), BlockingError<E>>
+/// Transmit payloads from the provided source, waiting between payloads with `idle`
+fn transmit_from<T, E, S, P, D>(
+    radio: &mut T,
+    source: &mut S,
+    options: TransmitOptions,
+    progress: &mut P,
+    mut idle: D,
+) -> Result<usize, BlockingError<E>>
 where
     T: Transmit<Error = E> + Power<Error = E> + DelayNs,
     E: core::fmt::Debug,
+    S: PacketSource + ?Sized,
+    P: ProgressReporter + ?Sized,
+    D: FnMut(&mut T, std::time::Duration) -> Result<(), E>,
 {
     // Set output power if specified
     if let Some(p) = options.power {
         radio.set_power(p)?;
     }
 
-    loop {
-        // Transmit packet
-        radio.do_transmit(&options.data, options.blocking_options.clone())?;
+    let mut sent = 0;
+    let mut framer = options.framing_options.framer();
+    let mut compressor = options.compression_options.compressor();
+    let mut cipher = options.crypto_options.cipher();
+    let mut traffic = options.traffic_options.generator();
+    let mut last_len = 0;
+
+    while let Some(data) = source.next_payload().expect("Error reading packet source") {
+        // Delay between transmissions, following the traffic profile where set
+        if sent > 0 {
+            match (&mut traffic, &options.period) {
+                (Some(t), _) => idle(radio, t.next_delay(last_len))?,
+                (None, Some(p)) => idle(radio, **p)?,
+                _ => (),
+            }
+        }
 
-        // Delay for repeated transmission or exit
-        match &options.period {
-            Some(p) => radio.delay_us(p.as_micros() as u32),
-            None => break,
+        // Compress payload where enabled, ahead of encryption and framing
+        let data = match compressor.as_mut() {
+            Some(c) => c.compress(&data),
+            None => data,
+        };
+
+        // Encrypt payload where enabled, ahead of framing
+        let data = match cipher.as_mut().map(|c| c.encrypt(&data)) {
+            Some(Ok(d)) => d,
+            Some(Err(e)) => {
+                warn!("Error encrypting {} byte payload: {:?}", data.len(), e);
+                continue;
+            }
+            None => data,
+        };
+
+        // Transmit packet, framed and fragmented where addressing is enabled
+        let start = std::time::Instant::now();
+        match framer.as_mut().map(|f| f.frames(&data)) {
+            Some(Ok(frames)) => {
+                for f in frames {
+                    radio.do_transmit(&f, options.blocking_options.clone())?;
+                }
+            }
+            Some(Err(e)) => {
+                warn!("Error framing {} byte payload: {:?}", data.len(), e);
+                continue;
+            }
+            None => radio.do_transmit(&data, options.blocking_options.clone())?,
+        }
+        #[cfg(feature = "metrics")]
+        metrics().transmitted(data.len());
+        sent += 1;
+        last_len = data.len();
+        progress.update(&Progress::new("tx", sent as u64, None));
+
+        if options.trace_tx {
+            let t = TxTrace {
+                data: &data,
+                time_on_air: options
+                    .bitrate
+                    .map(|b| time_on_air(data.len(), b))
+                    .unwrap_or_else(|| start.elapsed()),
+                computed: options.bitrate.is_some(),
+                power: options.power,
+            };
+            info!("Transmitted: {}", t);
         }
     }
+    progress.finish(&Progress::new("tx", sent as u64, None));
 
-    Ok(())
+    if let Some(c) = compressor {
+        info!("Compression: {}", c.stats());
+    }
+
+    Ok(sent)
 }
 
 /// Configuration for Receive operation
-#[derive(Clone, Parser, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
 pub struct ReceiveOptions {
     /// Run continuously
-    #[clap(long = "continuous")]
+    #[cfg_attr(feature = "clap", clap(long = "continuous"))]
     pub continuous: bool,
 
-    #[clap(flatten)]
-    pub pcap_options: PcapOptions,
+    /// Print packet, byte and error rates at this interval when running continuously
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub rate_interval: Option<HumanDuration>,
 
-    #[clap(flatten)]
-    pub blocking_options: BlockingOptions,
-}
+    /// Print per-peer statistics (by frame source address) at this interval when running continuously
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub peer_stats: Option<HumanDuration>,
 
-#[derive(Clone, Parser, PartialEq, Debug)]
+    /// Serve Prometheus metrics (packet counts, errors and RSSI) over HTTP on this address
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub metrics_addr: Option<std::net::SocketAddr>,
 
-pub struct PcapOptions {
-    /// Create and write capture output to a PCAP file
-    #[clap(long, group = "1")]
-    pub pcap_file: Option<String>,
+    /// Suppress frames received below this RSSI (dBm), along with zero-length receptions
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub squelch: Option<i16>,
 
-    /// Create and write to a unix pipe for connection to wireshark
-    #[clap(long, group = "1")]
-    pub pcap_pipe: Option<String>,
-}
+    /// Log receive errors and preamble / sync detection events
+    /// (events require a radio implementing [`Interrupts`], see [`do_receive_events`] and `do_operation_diag`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub verbose: bool,
 
-impl PcapOptions {
-    pub fn open(&self) -> Result<Option<PcapWriter<File>>, std::io::Error> {
-        // Open file or pipe if specified
-        let pcap_file = match (&self.pcap_file, &self.pcap_pipe) {
-            // Open as file
-            (Some(file), None) => {
-                let f = File::create(file)?;
-                Some(f)
-            }
-            // Open as pipe
-            #[cfg(target_family = "unix")]
-            (None, Some(pipe)) => {
-                // Ensure file doesn't already exist
-                let _ = std::fs::remove_file(pipe);
+    /// Check sequence numbers in the first N bytes of each frame (big-endian, as sent by
+    /// `gen:` sources and `{seq:N}` templates), logging gaps, reordering and duplicates
+    #[cfg_attr(feature = "clap", clap(long, value_parser = clap::value_parser!(u8).range(1..=4)))]
+    pub seq_check: Option<u8>,
 
-                // Create pipe
-                let n = CString::new(pipe.as_str()).unwrap();
-                let status = unsafe { libc::mkfifo(n.as_ptr(), 0o644) };
+    /// Unpack aggregated frames (as sent with `--aggregate`), handling each sub-frame
+    /// as a separate payload
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub aggregated: bool,
 
-                // Manual status code handling
-                // TODO: return io::Error
-                if status != 0 {
-                    panic!("Error creating fifo: {}", status);
-                }
+    /// Receive all frames, including those failing CRC or address filtering
+    /// (requires a radio implementing [`ReceiveFilter`](crate::ReceiveFilter), see `do_operation_filter`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub promiscuous: bool,
 
-                // Open pipe
-                let f = OpenOptions::new()
-                    .write(true)
-                    .open(pipe)
-                    .expect("Error opening PCAP pipe");
+    /// Shutdown signal, stopping continuous operation cleanly once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
 
-                Some(f)
-            }
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub alert_options: AlertOptions,
 
-            (None, None) => None,
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub framing_options: FramingOptions,
 
-            _ => unimplemented!(),
-        };
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub compression_options: CompressionOptions,
 
-        #[cfg(any(feature = "log", feature = "defmt"))]
-        info!("pcap pipe open, awaiting connection");
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub crypto_options: CryptoOptions,
 
-        // Setup pcap writer and write header
-        // (This is a blocking operation on pipes)
-        let pcap_writer = match pcap_file {
-            None => None,
-            Some(f) => {
-                // Setup pcap header
-                let mut h = PcapHeader::default();
-                h.datalink = DataLink::IEEE802_15_4;
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub fhss_options: FhssOptions,
 
-                // Write header
-                let w = PcapWriter::with_header(f, h).expect("Error writing to PCAP file");
-                Some(w)
-            }
-        };
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub channel_options: ChannelOptions,
 
-        Ok(pcap_writer)
-    }
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub auto_channel_options: AutoChannelOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub output_options: OutputOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub worker_options: WorkerOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub negotiate_options: NegotiateOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub start_options: StartOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
 }
 
 /// Receive from the radio using the provided configuration
 pub fn do_receive<T, I, E>(
     radio: &mut T,
-    mut buff: &mut [u8],
+    buff: &mut [u8],
     options: ReceiveOptions,
 ) -> Result<usize, E>
 where
     T: Receive<Info = I, Error = E> + DelayNs,
-    I: std::fmt::Debug,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    do_receive_with_events(radio, buff, options, |_| None)
+}
+
+/// Receive from the radio, writing received frames to the provided sinks
+/// (in addition to the outputs configured in `options`)
+pub fn do_receive_with_sinks<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: ReceiveOptions,
+    sinks: Vec<Box<dyn PacketSink>>,
+) -> Result<usize, E>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut stack = options
+        .worker_options
+        .sinks()
+        .expect("Error creating receive outputs");
+    for s in sinks {
+        stack.push_boxed(s);
+    }
+
+    let worker =
+        DecodeWorker::with_sinks(options.worker_options.clone(), Default::default(), stack)
+            .expect("Error creating decode pipeline");
+
+    receive_with(radio, buff, options, worker, |_| None)
+}
+
+/// Receive from the radio, logging preamble and sync detection events from the radio
+/// interrupt state where `verbose` is set
+pub fn do_receive_events<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: ReceiveOptions,
+) -> Result<usize, E>
+where
+    T: Receive<Info = I, Error = E> + Interrupts + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    do_receive_with_events(radio, buff, options, |r| {
+        r.get_interrupts(false).ok().and_then(|i| r.rx_event(&i))
+    })
+}
+
+/// Receive from the radio, polling for receive events with the provided function
+pub fn do_receive_with_events<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: ReceiveOptions,
+    events: F,
+) -> Result<usize, E>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(&mut T) -> Option<RxEvent>,
+{
+    // Setup decode pipeline, handling output off the polling loop
+    let worker =
+        DecodeWorker::new(options.worker_options.clone()).expect("Error creating decode pipeline");
+
+    receive_with(radio, buff, options, worker, events)
+}
+
+fn receive_with<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: ReceiveOptions,
+    mut worker: DecodeWorker,
+    mut events: F,
+) -> Result<usize, E>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
     E: std::fmt::Debug,
+    F: FnMut(&mut T) -> Option<RxEvent>,
 {
-    // Create and open pcap file for writing
-    let mut pcap_writer = options
-        .pcap_options
-        .open()
-        .expect("Error opening pcap file / pipe");
+    let mut rates = options.rate_interval.map(|i| RateTracker::new(*i));
+    let mut peers = options.peer_stats.map(PeerReporter::new);
+    let mut tracker = RxEventTracker::default();
+    let mut seq = options.seq_check.map(|w| SeqChecker::new(w as usize));
+    let mut alerts = AlertMonitor::new(options.alert_options.clone());
+    let mut deframer = options.framing_options.deframer();
+    let mut cipher = options.crypto_options.cipher();
+    let mut compressor = options.compression_options.compressor();
+    let mut records = options
+        .output_options
+        .writer()
+        .expect("Error opening record output");
+    let mut summary = RunSummary::default();
+    let mut last = 0;
+    let start = std::time::Instant::now();
+
+    #[cfg(feature = "metrics")]
+    if let Some(a) = options.metrics_addr {
+        MetricsExporter::listen(a).expect("Error starting metrics exporter");
+    }
 
     // Start receive mode
     radio.start_receive()?;
 
     loop {
-        if radio.check_receive(true)? {
-            let (n, i) = radio.get_received(&mut buff)?;
+        // Exit cleanly on shutdown, flushing outputs
+        if options.shutdown.is_triggered() {
+            let stats = worker.finish();
+            debug!("Decode pipeline: {:?}", stats);
+
+            summary.elapsed = start.elapsed();
+            info!("Receive stopped: {}", summary);
+            if let Some(s) = seq.as_ref() {
+                info!("Sequence: {}", s.stats());
+            }
+            return Ok(last);
+        }
 
-            match std::str::from_utf8(&buff[0..n as usize]) {
-                Ok(s) => info!("Received: '{}' info: {:?}", s, i),
-                #[cfg(not(feature = "defmt"))]
-                Err(_) => info!("Received: '{:02x?}' info: {:?}", &buff[0..n as usize], i),
-                #[cfg(feature = "defmt")]
-                Err(_) => info!("Received: '{:?}' info: {:?}", &buff[0..n as usize], i),
+        // Log receive events if enabled
+        if options.verbose
+            && let Some(e) = tracker.update(events(radio))
+        {
+            info!("Receive event: {:?}", e);
+        }
+
+        // Print rates if enabled
+        if let Some(r) = rates.as_mut().and_then(|r| r.poll()) {
+            info!("Receive rate: {}", r);
+            if let Some(s) = seq.as_ref() {
+                info!("Sequence: {}", s.stats());
+            }
+            if let Some(c) = compressor.as_ref() {
+                info!("Compression: {}", c.stats());
+            }
+        }
+        if let Some(p) = peers.as_mut() {
+            p.poll();
+        }
+
+        let received = radio.check_receive(true).and_then(|r| match r {
+            true => radio.get_received(buff).map(Some),
+            false => Ok(None),
+        });
+
+        // Continuous receive counts errors and restarts rather than exiting
+        let received = match (received, options.continuous) {
+            (Ok(r), _) => r,
+            (Err(e), true) => {
+                match (options.verbose, tracker.incomplete()) {
+                    (true, Some(ev)) => info!("Receive error after {:?}: {:?}", ev, e),
+                    (true, None) => info!("Receive error: {:?}", e),
+                    _ => debug!("Receive error: {:?}", e),
+                }
+                tracker.reset();
+                if let Some(r) = rates.as_mut() {
+                    r.error();
+                }
+                #[cfg(feature = "metrics")]
+                metrics().receive_error();
+                radio.start_receive()?;
+                None
+            }
+            (Err(e), false) => return Err(e),
+        };
+
+        // Suppress noise-floor receptions, continuing to receive
+        let received = match received {
+            Some((n, i)) if squelched(options.squelch, i.rssi(), n) => {
+                debug!("Squelched {} byte frame with rssi: {}", n, i.rssi());
+                tracker.reset();
+                radio.start_receive()?;
+                None
             }
+            r => r,
+        };
 
-            if let Some(p) = &mut pcap_writer {
-                let t = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap();
+        if let Some((n, i)) = received {
+            tracker.reset();
+            summary.packet(n, i.rssi());
+            #[cfg(feature = "metrics")]
+            metrics().received(n, i.rssi());
+            if let Some(r) = rates.as_mut() {
+                r.packet(n);
+            }
+            if let Some(w) = records.as_mut()
+                && let Err(e) = w.write(&PacketRecord::new(&buff[..n], i.rssi(), i.lqi()))
+            {
+                warn!("Error writing packet record: {:?}", e);
+            }
+            if let Some(p) = peers.as_mut() {
+                p.update(&buff[..n], i.rssi());
+            }
+            alerts.update_rssi(i.rssi(), StdClock.now_us());
+
+            // Filter by address and reassemble fragments where framing is enabled
+            let payload = match deframer.as_mut() {
+                Some(d) => d.accept(&buff[..n]),
+                None => Some(&buff[..n]),
+            };
+
+            // Decrypt and authenticate where encryption is enabled
+            #[cfg(feature = "metrics")]
+            let accepted = payload.is_some();
+            let payload = match (payload, cipher.as_mut()) {
+                (Some(p), Some(c)) => c.decrypt(p),
+                (p, _) => p,
+            };
+
+            // Decompress where compression is enabled
+            let payload = match (payload, compressor.as_mut()) {
+                (Some(p), Some(c)) => c.decompress(p),
+                (p, _) => p,
+            };
+            let payload = match payload {
+                Some(p) => p,
+                None => {
+                    #[cfg(feature = "metrics")]
+                    if accepted {
+                        metrics().decode_failure();
+                    }
+                    radio.start_receive()?;
+                    continue;
+                }
+            };
+
+            // Unpack sub-frames where aggregation is enabled
+            let messages: Vec<&[u8]> = match options.aggregated {
+                true => subframes(payload)
+                    .filter_map(|m| {
+                        m.map_err(|e| debug!("Error unpacking aggregated frame: {:?}", e))
+                            .ok()
+                    })
+                    .collect(),
+                false => vec![payload],
+            };
+
+            for m in messages {
+                if let Some(s) = seq.as_mut() {
+                    if let Some(e) = s.update(m) {
+                        info!("Sequence {}", e);
+                    }
+                    alerts.update_loss(s.stats());
+                }
 
-                p.write_packet(&PcapPacket::new(t, n as u32, &buff[0..n]))
-                    .expect("Error writing pcap file");
+                match worker.alloc(m) {
+                    Some(data) => {
+                        let frame = ReceivedFrame {
+                            timestamp: SystemTime::now(),
+                            rssi: i.rssi(),
+                            data,
+                            quality: ReceiveQuality::from_info(&i),
+                            info: format!("{:?}", i),
+                        };
+                        if !worker.submit(frame) {
+                            debug!("Decode queue full, dropped frame");
+                        }
+                    }
+                    None => debug!("Receive buffer pool exhausted, dropped frame"),
+                }
             }
 
             if !options.continuous {
-                return Ok(n);
+                let stats = worker.finish();
+                debug!("Decode pipeline: {:?}", stats);
+                return Ok(payload.len());
             }
+            last = payload.len();
 
             radio.start_receive()?;
         }
@@ -265,152 +907,989 @@ where
     }
 }
 
+/// Draw a random seed for operations run without a configured seed, from the (randomly
+/// keyed) hasher state of the process
+pub fn random_seed() -> u32 {
+    let r = std::collections::hash_map::RandomState::new().hash_one(0u8);
+    (r ^ (r >> 32)) as u32
+}
+
+/// Check whether a received frame should be suppressed by the provided squelch threshold,
+/// with zero-length receptions always suppressed where squelch is enabled
+pub fn squelched(threshold: Option<i16>, rssi: i16, len: usize) -> bool {
+    match threshold {
+        Some(t) => len == 0 || rssi < t,
+        None => false,
+    }
+}
+
+/// Receive event tracking, reporting changes in receive stage
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RxEventTracker {
+    current: Option<RxEvent>,
+}
+
+impl RxEventTracker {
+    /// Update with the latest polled event, returning the event where the receive stage has advanced
+    pub fn update(&mut self, event: Option<RxEvent>) -> Option<RxEvent> {
+        match (event, self.current) {
+            (Some(e), Some(c)) if e <= c => None,
+            (Some(e), _) => {
+                self.current = Some(e);
+                Some(e)
+            }
+            (None, _) => None,
+        }
+    }
+
+    /// Receive stage reached by an incomplete reception
+    pub fn incomplete(&self) -> Option<RxEvent> {
+        self.current
+    }
+
+    /// Reset on packet reception or receive restart
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+}
+
 /// Configuration for RSSI operation
-#[derive(Clone, Parser, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
 pub struct RssiOptions {
     /// Specify period for RSSI polling
-    #[clap(long = "period", default_value = "1s")]
+    #[cfg_attr(feature = "clap", clap(long = "period", default_value = "1s"))]
     pub period: HumanDuration,
 
     /// Run continuously
-    #[clap(long = "continuous")]
+    #[cfg_attr(feature = "clap", clap(long = "continuous"))]
     pub continuous: bool,
+
+    /// Number of samples to capture (as a batch) each period, reporting the mean, min and max
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1"))]
+    pub samples: usize,
+
+    /// Shutdown signal, stopping continuous operation cleanly once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub channel_options: ChannelOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub output_options: OutputOptions,
 }
 
-pub fn do_rssi<T, I, E>(radio: &mut T, options: RssiOptions) -> Result<(), E>
+/// Poll RSSI using the provided configuration, returning the last batch of samples
+pub fn do_rssi<T, I, E>(radio: &mut T, options: RssiOptions) -> Result<Vec<i16>, E>
 where
     T: Receive<Info = I, Error = E> + Rssi<Error = E> + DelayNs,
     I: std::fmt::Debug,
     E: std::fmt::Debug,
 {
+    let mut samples = vec![0i16; options.samples.max(1)];
+    let mut rssi = Samples::new();
+    let mut records = options
+        .output_options
+        .writer()
+        .expect("Error opening record output");
+    let mut last = 0;
+    let start = std::time::Instant::now();
+
     // Enter receive mode
     radio.start_receive()?;
 
     // Poll for RSSI
-    loop {
-        let rssi = radio.poll_rssi()?;
+    let n = loop {
+        // Exit cleanly on shutdown, returning the last batch
+        if options.shutdown.is_triggered() {
+            info!(
+                "RSSI stopped: {} samples in {:.1} s, rssi (dBm): {}",
+                rssi.count(),
+                start.elapsed().as_secs_f32(),
+                rssi
+            );
+            break last;
+        }
 
-        info!("rssi: {}", rssi);
+        let n = radio.poll_rssi_n(&mut samples)?;
+        for v in &samples[..n] {
+            rssi.update(*v as f32);
+            if let Some(w) = records.as_mut()
+                && let Err(e) = w.write(&RssiRecord::new(*v))
+            {
+                warn!("Error writing RSSI record: {:?}", e);
+            }
+        }
+        last = n;
+
+        match &samples[..n] {
+            [rssi] => info!("rssi: {}", rssi),
+            s if !s.is_empty() => {
+                let mean = s.iter().map(|v| *v as i32).sum::<i32>() / s.len() as i32;
+                let (min, max) = (s.iter().min().unwrap(), s.iter().max().unwrap());
+                info!("rssi: {} (min {}, max {}, {} samples)", mean, min, max, n);
+            }
+            _ => (),
+        }
 
         radio.check_receive(true)?;
 
         radio.delay_us(options.period.as_micros() as u32);
 
         if !options.continuous {
-            break;
+            break n;
         }
-    }
+    };
 
-    Ok(())
+    samples.truncate(n);
+    Ok(samples)
 }
 
 /// Configuration for Echo operation
-#[derive(Clone, Parser, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
 pub struct EchoOptions {
     /// Run continuously
-    #[clap(long = "continuous")]
+    #[cfg_attr(feature = "clap", clap(long = "continuous"))]
     pub continuous: bool,
 
     /// Power in dBm (range -18dBm to 13dBm)
-    #[clap(long = "power")]
+    #[cfg_attr(feature = "clap", clap(long = "power"))]
     pub power: Option<i8>,
 
     /// Specify delay for response message
-    #[clap(long = "delay", default_value = "100ms")]
+    #[cfg_attr(feature = "clap", clap(long = "delay", default_value = "100ms"))]
     pub delay: HumanDuration,
 
+    /// Set the response delay from a saved turnaround calibration (see `calibrate --save`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub calibration: Option<String>,
+
+    /// Maximum random delay added to the response delay, emulating a slow peer
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub delay_jitter: Option<HumanDuration>,
+
+    /// Probability (`0.0..=1.0`) of dropping a response, emulating a lossy peer
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub drop_probability: f32,
+
+    /// Seed for response drops and delay jitter (defaults to a random seed)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub seed: Option<u32>,
+
     /// Append RSSI and LQI to repeated message
-    #[clap(long = "append-info")]
+    #[cfg_attr(feature = "clap", clap(long = "append-info"))]
     pub append_info: bool,
 
-    #[clap(flatten)]
-    pub blocking_options: BlockingOptions,
-}
+    /// Transform to apply to the payload before responding
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "none"))]
+    pub transform: EchoTransform,
 
-pub fn do_echo<T, I, E>(
-    radio: &mut T,
-    mut buff: &mut [u8],
-    options: EchoOptions,
-) -> Result<usize, BlockingError<E>>
-where
-    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
-    I: ReceiveInfo + std::fmt::Debug,
-    E: std::fmt::Debug,
-{
-    // Set output power if specified
-    if let Some(p) = options.power {
-        radio.set_power(p)?;
-    }
+    /// Maximum response size in bytes (defaults to, and is limited by, the receive buffer size)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub max_size: Option<usize>,
 
-    // Start receive mode
-    radio.start_receive()?;
+    /// Handling for responses exceeding the maximum response size
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "truncate"))]
+    pub oversize: OversizePolicy,
 
-    loop {
-        if radio.check_receive(true)? {
-            // Fetch received packet
-            let (mut n, i) = radio.get_received(&mut buff)?;
+    /// Node address, when set only frames addressed to this node (or broadcast) are echoed
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub address: Option<Address>,
 
-            // Parse out string if possible, otherwise print hex
-            match std::str::from_utf8(&buff[0..n as usize]) {
-                Ok(s) => info!("Received: '{}' info: {:?}", s, i),
-                #[cfg(not(feature = "defmt"))]
-                Err(_) => info!("Received: '{:02x?}' info: {:?}", &buff[0..n as usize], i),
-                #[cfg(feature = "defmt")]
-                Err(_) => info!("Received: '{:?}' info: {:?}", &buff[0..n as usize], i),
-            }
+    /// Print per-peer statistics (by frame source address) at this interval when running continuously
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub peer_stats: Option<HumanDuration>,
 
-            // Append info if provided
-            if options.append_info {
-                NetworkEndian::write_i16(&mut buff[n..], i.rssi());
-                n += 2;
-            }
+    /// Serve Prometheus metrics (packet counts, errors and RSSI) over HTTP on this address
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub metrics_addr: Option<std::net::SocketAddr>,
 
-            // Wait for turnaround delay
-            radio.delay_us(options.delay.as_micros() as u32);
+    /// Shutdown signal, stopping continuous operation cleanly once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
 
-            // Transmit respobnse
-            radio.do_transmit(&buff[..n], options.blocking_options.clone())?;
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub crypto_options: CryptoOptions,
 
-            // Exit if non-continuous
-            if !options.continuous {
-                return Ok(n);
-            }
-        }
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub auto_channel_options: AutoChannelOptions,
 
-        // Wait for poll delay
-        radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
-    }
-}
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub arq_options: ArqOptions,
 
-/// Configuration for Echo operation
-#[derive(Clone, Parser, PartialEq, Debug)]
-pub struct PingPongOptions {
-    /// Specify the number of rounds to tx/rx
-    #[clap(long, default_value = "100")]
-    pub rounds: u32,
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub cca_options: CcaOptions,
 
-    /// Power in dBm (range -18dBm to 13dBm)
-    #[clap(long)]
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub sleep_options: SleepOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub negotiate_options: NegotiateOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for EchoOptions {
+    fn default() -> Self {
+        Self {
+            continuous: false,
+            power: None,
+            delay: std::time::Duration::from_millis(100).into(),
+            calibration: None,
+            delay_jitter: None,
+            drop_probability: 0.0,
+            seed: None,
+            append_info: false,
+            transform: EchoTransform::None,
+            max_size: None,
+            oversize: OversizePolicy::Truncate,
+            address: None,
+            peer_stats: None,
+            #[cfg(feature = "metrics")]
+            metrics_addr: None,
+            shutdown: Shutdown::default(),
+            crypto_options: CryptoOptions::default(),
+            auto_channel_options: AutoChannelOptions::default(),
+            arq_options: ArqOptions::default(),
+            cca_options: CcaOptions::default(),
+            sleep_options: SleepOptions::default(),
+            negotiate_options: NegotiateOptions::default(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+impl EchoOptions {
+    /// Select the delay for a response in microseconds, applying jitter,
+    /// or `None` where the response should be dropped
+    pub fn response_delay_us(&self, rng: &mut XorShift32) -> Option<u32> {
+        if self.drop_probability > 0.0 && rng.next_f32() < self.drop_probability {
+            return None;
+        }
+
+        let jitter = self.delay_jitter.map(|j| j.as_micros() as u32).unwrap_or(0);
+        let delay = self.delay.as_micros() as u32;
+
+        Some(delay.saturating_add(rng.below(jitter.saturating_add(1))))
+    }
+
+    /// Split a response into the frames to be transmitted, applying the size limit
+    /// for the provided receive buffer length
+    pub fn response_frames(&self, response: &[u8], buff_len: usize) -> Vec<Vec<u8>> {
+        let max = self.max_size.unwrap_or(buff_len).min(buff_len);
+
+        self.oversize.apply(response, self.header_len(), max)
+    }
+
+    /// Split a response into encrypted frames to be transmitted, applying the size limit
+    /// to the plaintext (less the encryption overhead) so ciphertext is never truncated
+    pub fn encrypted_response_frames(
+        &self,
+        response: &[u8],
+        buff_len: usize,
+        cipher: &mut PayloadCipher,
+    ) -> Result<Vec<Vec<u8>>, CryptoError> {
+        let max = self.max_size.unwrap_or(buff_len).min(buff_len);
+        let h = self.header_len();
+
+        self.oversize
+            .apply(response, h, max.saturating_sub(OVERHEAD))
+            .into_iter()
+            .filter(|f| f.len() >= h)
+            .map(|mut f| {
+                let enc = cipher.encrypt(&f[h..])?;
+                f.truncate(h);
+                f.extend_from_slice(&enc);
+                Ok(f)
+            })
+            .collect()
+    }
+
+    /// Frame header length preceding payloads, where addressing is enabled
+    pub fn header_len(&self) -> usize {
+        match self.address {
+            Some(_) => Header::LEN,
+            None => 0,
+        }
+    }
+}
+
+/// Space reserved beyond the receive buffer for echo responses to grow
+/// (through transforms or appended info) prior to applying size limits
+pub const ECHO_HEADROOM: usize = 16;
+
+/// Handling for echo responses exceeding the maximum response size
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum OversizePolicy {
+    /// Truncate responses to the maximum size
+    Truncate,
+    /// Split responses over multiple frames, repeating any frame header
+    Split,
+    /// Drop oversize responses
+    Drop,
+}
+
+impl OversizePolicy {
+    /// Apply the policy to a response with a `header_len` byte frame header,
+    /// returning the frames to be transmitted (none where the response is dropped)
+    pub fn apply(&self, response: &[u8], header_len: usize, max: usize) -> Vec<Vec<u8>> {
+        if response.len() <= max {
+            return vec![response.to_vec()];
+        }
+
+        match self {
+            OversizePolicy::Truncate => vec![response[..max].to_vec()],
+            OversizePolicy::Drop => vec![],
+            OversizePolicy::Split if max <= header_len => vec![],
+            OversizePolicy::Split => {
+                let (header, payload) = response.split_at(header_len);
+                payload
+                    .chunks(max - header_len)
+                    .map(|c| [header, c].concat())
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Payload transforms applied by the echo responder prior to replying
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum EchoTransform {
+    /// Respond with the received payload unchanged
+    None,
+    /// Reverse the byte order of the payload
+    Reverse,
+    /// Invert every bit of the payload
+    Invert,
+    /// Append an 8-bit XOR checksum of the payload
+    Checksum,
+}
+
+impl EchoTransform {
+    /// Apply the transform to the first `n` bytes of `buff`, returning the new payload length
+    ///
+    /// Checksums are only appended where `buff` has space.
+    pub fn apply(&self, buff: &mut [u8], n: usize) -> usize {
+        match self {
+            EchoTransform::None => n,
+            EchoTransform::Reverse => {
+                buff[..n].reverse();
+                n
+            }
+            EchoTransform::Invert => {
+                buff[..n].iter_mut().for_each(|b| *b = !*b);
+                n
+            }
+            EchoTransform::Checksum if n >= buff.len() => n,
+            EchoTransform::Checksum => {
+                buff[n] = buff[..n].iter().fold(0, |a, b| a ^ b);
+                n + 1
+            }
+        }
+    }
+}
+
+pub fn do_echo<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: EchoOptions,
+) -> Result<usize, OperationError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let transform = options.transform;
+    do_echo_with(radio, buff, options, |b, n, _i| transform.apply(b, n))
+}
+
+/// Echo received messages, applying the provided transform to each payload before responding
+///
+/// The transform is called with the payload buffer, the received payload length and the receive info,
+/// and returns the length of the (modified) payload to be sent in response. When addressing is
+/// enabled the frame header is stripped prior to calling the transform.
+pub fn do_echo_with<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: EchoOptions,
+    transform: F,
+) -> Result<usize, OperationError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(&mut [u8], usize, &I) -> usize,
+{
+    if options.sleep_options.sleep_between {
+        warn!("--sleep-between requires a radio implementing State, see do_operation_state");
+    }
+
+    echo_with(radio, buff, options, transform, |r, d| {
+        r.delay_us(d.as_micros() as u32);
+        Ok(())
+    })
+}
+
+/// Echo received packets, putting the radio to sleep for the response delay where
+/// `--sleep-between` is set
+pub fn do_echo_sleep<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: EchoOptions,
+) -> Result<usize, OperationError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + State<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    if !options.sleep_options.sleep_between {
+        return do_echo(radio, buff, options);
+    }
+
+    let transform = options.transform;
+    let mut sleep = SleepTracker::new();
+
+    let echoed = echo_with(
+        radio,
+        buff,
+        options,
+        |b, n, _i| transform.apply(b, n),
+        |r, d| sleep.sleep(r, d),
+    )?;
+    info!("Sleep: {}", sleep.stats());
+
+    Ok(echoed)
+}
+
+/// Echo received packets, serving metrics where configured
+fn echo_with<T, I, E, F, D>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: EchoOptions,
+    transform: F,
+    idle: D,
+) -> Result<usize, OperationError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(&mut [u8], usize, &I) -> usize,
+    D: FnMut(&mut T, std::time::Duration) -> Result<(), E>,
+{
+    #[cfg(feature = "metrics")]
+    if let Some(a) = options.metrics_addr {
+        MetricsExporter::listen(a).map_err(OperationError::Io)?;
+    }
+
+    Ok(echo_frames(radio, buff, options, transform, idle)?)
+}
+
+/// Echo received packets, waiting for the response delay with `idle`
+fn echo_frames<T, I, E, F, D>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: EchoOptions,
+    mut transform: F,
+    mut idle: D,
+) -> Result<usize, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(&mut [u8], usize, &I) -> usize,
+    D: FnMut(&mut T, std::time::Duration) -> Result<(), E>,
+{
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut rng = XorShift32::new(options.seed.unwrap_or_else(random_seed));
+
+    // Responses are built in a working buffer, allowing growth beyond the received payload
+    let mut work = vec![0u8; buff.len() + ECHO_HEADROOM];
+
+    let mut peers = options.peer_stats.map(PeerReporter::new);
+    let mut cipher = options.crypto_options.cipher();
+    let mut summary = RunSummary::default();
+    let mut echoed = 0;
+    let start = std::time::Instant::now();
+
+    // Start receive mode
+    radio.start_receive()?;
+
+    loop {
+        // Exit cleanly on shutdown, returning the number of frames echoed
+        if options.shutdown.is_triggered() {
+            summary.elapsed = start.elapsed();
+            info!("Echo stopped: {}, {} echoed", summary, echoed);
+            return Ok(echoed);
+        }
+
+        if let Some(p) = peers.as_mut() {
+            p.poll();
+        }
+
+        if radio.check_receive(true)? {
+            // Fetch received packet
+            let (mut n, i) = radio.get_received(&mut work[..buff.len()])?;
+            summary.packet(n, i.rssi());
+            #[cfg(feature = "metrics")]
+            metrics().received(n, i.rssi());
+
+            if let Some(p) = peers.as_mut() {
+                p.update(&work[..n], i.rssi());
+            }
+
+            // Decrypt payload where enabled, ignoring frames failing authentication
+            if let Some(c) = cipher.as_mut() {
+                n = match c.decrypt_in_place(&mut work, options.header_len(), n) {
+                    Some(n) => n,
+                    None => {
+                        #[cfg(feature = "metrics")]
+                        metrics().decode_failure();
+                        radio.start_receive()?;
+                        continue;
+                    }
+                };
+            }
+
+            // Parse out string if possible, otherwise print hex
+            match std::str::from_utf8(&work[0..n]) {
+                Ok(s) => info!("Received: '{}' info: {:?}", s, i),
+                #[cfg(not(feature = "defmt"))]
+                Err(_) => info!("Received: '{:02x?}' info: {:?}", &work[0..n], i),
+                #[cfg(feature = "defmt")]
+                Err(_) => info!("Received: '{:?}' info: {:?}", &work[0..n], i),
+            }
+
+            // Build response, ignoring frames not addressed to this node
+            n = match echo_response(&mut work, n, &i, &options, &mut transform) {
+                Some(n) => n,
+                None => {
+                    radio.start_receive()?;
+                    continue;
+                }
+            };
+
+            // Drop responses or wait for turnaround delay
+            let delay_us = match options.response_delay_us(&mut rng) {
+                Some(d) => d,
+                None => {
+                    debug!("Dropping response");
+                    radio.start_receive()?;
+                    continue;
+                }
+            };
+
+            // Apply response size limits, encrypting responses where enabled
+            let frames = match cipher.as_mut() {
+                Some(c) => match options.encrypted_response_frames(&work[..n], buff.len(), c) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        debug!("Error encrypting {} byte response: {:?}", n, e);
+                        radio.start_receive()?;
+                        continue;
+                    }
+                },
+                None => options.response_frames(&work[..n], buff.len()),
+            };
+            if frames.is_empty() {
+                debug!("Dropping oversize response ({} bytes)", n);
+                radio.start_receive()?;
+                continue;
+            }
+
+            idle(radio, std::time::Duration::from_micros(delay_us as u64))?;
+
+            // Transmit response
+            for f in &frames {
+                radio.do_transmit(f, options.blocking_options.clone())?;
+                #[cfg(feature = "metrics")]
+                metrics().transmitted(f.len());
+            }
+            echoed += 1;
+
+            // Exit if non-continuous, returning the (final) response
+            if !options.continuous {
+                let r = &frames[frames.len() - 1];
+                buff[..r.len()].copy_from_slice(r);
+                return Ok(r.len());
+            }
+        }
+
+        // Wait for poll delay
+        radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+}
+
+/// Periodic per-peer statistics output for receive loops
+struct PeerReporter {
+    table: PeerTable,
+    interval: std::time::Duration,
+    last: std::time::Instant,
+}
+
+impl PeerReporter {
+    fn new(interval: HumanDuration) -> Self {
+        Self {
+            table: PeerTable::new(),
+            interval: *interval,
+            last: std::time::Instant::now(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8], rssi: i16) {
+        if self.table.update(data, rssi).is_none() {
+            debug!("Peer statistics ignoring invalid frame");
+        }
+    }
+
+    fn poll(&mut self) {
+        if self.last.elapsed() < self.interval || self.table.is_empty() {
+            return;
+        }
+        self.last = std::time::Instant::now();
+
+        info!("Peer statistics:\n{}", self.table);
+    }
+}
+
+/// Build an echo response in place from a received packet of length `n`
+///
+/// This applies address filtering, the payload transform and appended info per the provided
+/// options, returning the response length or `None` if the packet should be ignored.
+pub fn echo_response<I, F>(
+    buff: &mut [u8],
+    n: usize,
+    info: &I,
+    options: &EchoOptions,
+    transform: &mut F,
+) -> Option<usize>
+where
+    I: ReceiveInfo,
+    F: FnMut(&mut [u8], usize, &I) -> usize,
+{
+    // Filter on destination address and prepare reply header if addressing is enabled
+    let offset = match options.address {
+        Some(address) => match Header::from_bytes(&buff[..n]) {
+            Ok(h) if h.is_for(address) => {
+                buff[..Header::LEN].copy_from_slice(&h.reply(address).to_bytes());
+                Header::LEN
+            }
+            Ok(h) => {
+                debug!("Ignoring frame from {:04x} to {:04x}", h.src, h.dst);
+                return None;
+            }
+            Err(e) => {
+                debug!("Ignoring invalid frame: {:?}", e);
+                return None;
+            }
+        },
+        None => 0,
+    };
+
+    // Return reflector frames untransformed, ignoring those returned by other reflectors
+    let mut n = match ReflectorFrame::from_bytes(&buff[offset..n]) {
+        Some(f) if f.is_reflected() => {
+            debug!("Ignoring reflected frame {}", f.seq);
+            return None;
+        }
+        Some(_) => {
+            reflect(&mut buff[offset..n], options.append_info);
+            n
+        }
+        None => offset + transform(&mut buff[offset..], n - offset, info),
+    };
+
+    // Append info if provided and space allows
+    if options.append_info {
+        match ReceiveQuality::from_info(info).encode(info.rssi(), &mut buff[n..]) {
+            Some(i) => n += i,
+            None => {
+                debug!("No space to append info to {} byte response", n);
+                return None;
+            }
+        }
+    }
+
+    Some(n)
+}
+
+/// Configuration for Echo operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct PingPongOptions {
+    /// Specify the number of rounds to tx/rx
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100"))]
+    pub rounds: u32,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
     pub power: Option<i8>,
 
     /// Specify delay for response message
-    #[clap(long, default_value = "100ms")]
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100ms"))]
     pub delay: HumanDuration,
 
+    /// Set the response timeout from a saved turnaround calibration (see `calibrate --save`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub calibration: Option<String>,
+
     /// Parse RSSI and other info from response messages
     /// (echo server must have --append-info set)
-    #[clap(long)]
+    #[cfg_attr(feature = "clap", clap(long))]
     pub parse_info: bool,
 
-    #[clap(flatten)]
+    /// Payload size in bytes (minimum 4 to contain the round index, or 16 with `--reflector`)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "4"))]
+    pub size: usize,
+
+    /// Send reflector frames, taking round-trip times and remote info from responses
+    /// (see [`reflector`](crate::reflector))
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub reflector: bool,
+
+    /// Sweep payload sizes, running the configured number of rounds at each size (min..max:step)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub size_sweep: Option<SizeSweep>,
+
+    /// Symmetric mode, both nodes initiate pings and respond to their peer
+    /// (run with --symmetric on both nodes)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub symmetric: bool,
+
+    /// Initial contention window for symmetric mode, doubled on each collision
+    #[cfg_attr(feature = "clap", clap(long, default_value = "20ms"))]
+    pub backoff: HumanDuration,
+
+    /// Seed for randomised offsets in symmetric mode (defaults to a random seed)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub seed: Option<u32>,
+
+    /// Node address in symmetric mode, distinguishing our pings from our peer's
+    /// (defaults to a random address, independent of `--seed`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub node_address: Option<Address>,
+
+    /// Time to keep responding to peer pings in symmetric mode once our own rounds are
+    /// complete, ending early once the peer's final ping is received
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1s"))]
+    pub linger: HumanDuration,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub crypto_options: CryptoOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub hop_options: HopOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub fhss_options: FhssOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub report_options: ReportOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub start_options: StartOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
     pub blocking_options: BlockingOptions,
 }
 
+impl Default for PingPongOptions {
+    fn default() -> Self {
+        Self {
+            rounds: 100,
+            power: None,
+            delay: std::time::Duration::from_millis(100).into(),
+            calibration: None,
+            parse_info: false,
+            size: 4,
+            reflector: false,
+            size_sweep: None,
+            symmetric: false,
+            backoff: std::time::Duration::from_millis(20).into(),
+            seed: None,
+            node_address: None,
+            linger: std::time::Duration::from_secs(1).into(),
+            crypto_options: CryptoOptions::default(),
+            hop_options: HopOptions::default(),
+            fhss_options: FhssOptions::default(),
+            report_options: ReportOptions::default(),
+            progress_options: ProgressOptions::default(),
+            start_options: StartOptions::default(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+impl PingPongOptions {
+    /// Payload length, large enough to contain the round index (or reflector frame header)
+    pub fn payload_len(&self) -> usize {
+        match self.reflector {
+            true => self.size.max(ReflectorFrame::LEN),
+            false => self.size.max(4),
+        }
+    }
+}
+
+/// Payload size sweep, parsed from `min..max:step` (inclusive of `max`)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SizeSweep {
+    pub min: usize,
+    pub max: usize,
+    pub step: usize,
+}
+
+impl SizeSweep {
+    /// Iterate over the sizes in the sweep
+    pub fn sizes(&self) -> impl Iterator<Item = usize> {
+        (self.min..=self.max).step_by(self.step)
+    }
+}
+
+impl std::str::FromStr for SizeSweep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, step) = s.split_once(':').unwrap_or((s, "1"));
+        let (min, max) = range
+            .split_once("..")
+            .ok_or_else(|| format!("invalid size sweep '{}', expected min..max:step", s))?;
+
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<usize>()
+                .map_err(|e| format!("invalid size '{}': {}", v, e))
+        };
+        let (min, max, step) = (parse(min)?, parse(max)?, parse(step)?);
+
+        if step == 0 || min > max {
+            return Err(format!("invalid size sweep '{}'", s));
+        }
+
+        Ok(Self { min, max, step })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LinkTestInfo {
     pub sent: u32,
     pub received: u32,
-    pub local_rssi: Stats<f32>,
-    pub remote_rssi: Stats<f32>,
+    pub payload_len: usize,
+    pub local_rssi: Samples,
+    pub remote_rssi: Samples,
+    /// Round trip time in milliseconds
+    pub rtt: Samples,
+    pub loss_bursts: BurstLoss,
+    /// Local and remote LQI, where reported by the radios
+    #[serde(default)]
+    pub local_lqi: Samples,
+    #[serde(default)]
+    pub remote_lqi: Samples,
+    /// Local and remote SNR in dB, where reported by the radios
+    #[serde(default)]
+    pub local_snr: Samples,
+    #[serde(default)]
+    pub remote_snr: Samples,
+    /// Local frequency error in Hz, where reported by the radio
+    #[serde(default)]
+    pub frequency_error: Samples,
+}
+
+impl LinkTestInfo {
+    /// Create an empty result for the provided payload length
+    pub fn new(payload_len: usize) -> Self {
+        Self {
+            sent: 0,
+            received: 0,
+            payload_len,
+            local_rssi: Samples::new(),
+            remote_rssi: Samples::new(),
+            rtt: Samples::new(),
+            loss_bursts: BurstLoss::default(),
+            local_lqi: Samples::new(),
+            remote_lqi: Samples::new(),
+            local_snr: Samples::new(),
+            remote_snr: Samples::new(),
+            frequency_error: Samples::new(),
+        }
+    }
+
+    /// Record local receive quality metrics for a response
+    pub fn update_local(&mut self, q: &ReceiveQuality) {
+        if let Some(v) = q.lqi {
+            self.local_lqi.update(v as f32);
+        }
+        if let Some(v) = q.snr_db {
+            self.local_snr.update(v);
+        }
+        if let Some(v) = q.frequency_error_hz {
+            self.frequency_error.update(v as f32);
+        }
+    }
+
+    /// Record remote receive quality metrics parsed from a response
+    pub fn update_remote(&mut self, q: &ReceiveQuality) {
+        if let Some(v) = q.lqi {
+            self.remote_lqi.update(v as f32);
+        }
+        if let Some(v) = q.snr_db {
+            self.remote_snr.update(v);
+        }
+    }
+
+    /// Merge results from another run, used to aggregate repeated tests
+    pub fn merge(&mut self, other: &LinkTestInfo) {
+        self.sent += other.sent;
+        self.received += other.received;
+        self.local_rssi.merge(&other.local_rssi);
+        self.remote_rssi.merge(&other.remote_rssi);
+        self.rtt.merge(&other.rtt);
+        self.loss_bursts.merge(&other.loss_bursts);
+        self.local_lqi.merge(&other.local_lqi);
+        self.remote_lqi.merge(&other.remote_lqi);
+        self.local_snr.merge(&other.local_snr);
+        self.remote_snr.merge(&other.remote_snr);
+        self.frequency_error.merge(&other.frequency_error);
+    }
+}
+
+impl core::fmt::Display for LinkTestInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "received {}/{} ({} byte payload), longest outage {} rounds",
+            self.received, self.sent, self.payload_len, self.loss_bursts.longest
+        )?;
+        writeln!(f, "local rssi (dBm): {}", self.local_rssi)?;
+        writeln!(f, "remote rssi (dBm): {}", self.remote_rssi)?;
+        for (name, s) in [
+            ("local lqi", &self.local_lqi),
+            ("remote lqi", &self.remote_lqi),
+            ("local snr (dB)", &self.local_snr),
+            ("remote snr (dB)", &self.remote_snr),
+            ("frequency error (Hz)", &self.frequency_error),
+        ] {
+            if s.count() > 0 {
+                writeln!(f, "{}: {}", name, s)?;
+            }
+        }
+        write!(f, "rtt (ms): {}", self.rtt)
+    }
 }
 
 pub fn do_ping_pong<T, I, E>(
@@ -422,72 +1901,863 @@ where
     I: ReceiveInfo,
     E: std::fmt::Debug,
 {
+    let mut progress = options.progress_options.reporter();
+    do_ping_pong_with(radio, options, &mut *progress)
+}
+
+/// Run a link test, reporting progress per round to the provided reporter
+pub fn do_ping_pong_with<T, I, E, P>(
+    radio: &mut T,
+    options: PingPongOptions,
+    progress: &mut P,
+) -> Result<LinkTestInfo, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+    P: ProgressReporter + ?Sized,
+{
+    do_ping_pong_clocked(radio, options, &StdClock, progress)
+}
+
+/// Run a link test, timestamping each round with the provided clock to record
+/// round-trip latency (allowing a [`VirtualClock`](crate::clock::VirtualClock) to be
+/// used for deterministic tests)
+pub fn do_ping_pong_clocked<T, I, E, C, P>(
+    radio: &mut T,
+    options: PingPongOptions,
+    clock: &C,
+    progress: &mut P,
+) -> Result<LinkTestInfo, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+    C: Clock + ?Sized,
+    P: ProgressReporter + ?Sized,
+{
+    let len = options.payload_len();
+
     let mut link_info = LinkTestInfo {
         sent: options.rounds,
-        received: 0,
-        local_rssi: Stats::new(),
-        remote_rssi: Stats::new(),
+        ..LinkTestInfo::new(len)
     };
 
-    let mut buff = [0u8; 32];
+    // Allow space for appended info in responses
+    let mut buff = vec![0u8; len.max(256) + 16];
 
     // Set output power if specified
     if let Some(p) = options.power {
         radio.set_power(p)?;
     }
 
+    let mut cipher = options.crypto_options.cipher();
+
     for i in 0..options.rounds {
-        // Encode message
-        NetworkEndian::write_u32(&mut buff[0..], i as u32);
-        let n = 4;
+        ping_pong_round(
+            radio,
+            &mut buff,
+            clock,
+            i,
+            &options,
+            &mut cipher,
+            &mut link_info,
+        )?;
+
+        progress.update(&Progress::new(
+            "ping-pong",
+            i as u64 + 1,
+            Some(options.rounds as u64),
+        ));
+    }
+    progress.finish(&Progress::new(
+        "ping-pong",
+        options.rounds as u64,
+        Some(options.rounds as u64),
+    ));
 
-        #[cfg(any(feature = "log", feature = "defmt"))]
-        debug!("Sending message {}", i);
+    link_info.loss_bursts.finish();
 
-        // Send message
-        radio.do_transmit(&buff[0..n], options.blocking_options.clone())?;
+    info!("Link test complete: {}", link_info);
 
-        // Await response
-        let (n, info) = match radio.do_receive(&mut buff, options.blocking_options.clone()) {
-            Ok(r) => r,
-            Err(BlockingError::Timeout) => {
-                #[cfg(any(feature = "log", feature = "defmt"))]
-                debug!("Timeout awaiting response {}", i);
-                continue;
+    #[cfg(any(feature = "log", feature = "defmt"))]
+    debug!(
+        "Loss bursts: {} (mean length {:.1}, longest {}), histogram: {:?}",
+        link_info.loss_bursts.bursts(),
+        link_info.loss_bursts.mean_length(),
+        link_info.loss_bursts.longest,
+        link_info.loss_bursts.histogram
+    );
+
+    Ok(link_info)
+}
+
+/// Run a single link test round with index `i`, updating the provided results
+///
+/// Where a cipher is provided messages are encrypted, with responses (including any
+/// appended info) decrypted and authenticated prior to parsing. Round-trip latency
+/// is measured with the provided clock.
+fn ping_pong_round<T, I, E, C>(
+    radio: &mut T,
+    buff: &mut [u8],
+    clock: &C,
+    i: u32,
+    options: &PingPongOptions,
+    cipher: &mut Option<PayloadCipher>,
+    link_info: &mut LinkTestInfo,
+) -> Result<(), BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+    C: Clock + ?Sized,
+{
+    let len = options.payload_len();
+
+    // Encode message, padding to the configured payload size
+    match options.reflector {
+        true => {
+            ReflectorFrame::new(i, clock.now_us()).encode(len, buff);
+        }
+        false => {
+            NetworkEndian::write_u32(&mut buff[0..], i);
+            for (j, b) in buff[4..len].iter_mut().enumerate() {
+                *b = j as u8;
             }
-            Err(e) => return Err(e),
-        };
+        }
+    }
+    let n = match cipher.as_mut() {
+        Some(c) => c
+            .encrypt_in_place(buff, 0, len)
+            .expect("Error encrypting message"),
+        None => len,
+    };
+
+    #[cfg(any(feature = "log", feature = "defmt"))]
+    debug!("Sending message {}", i);
 
-        let receive_index = NetworkEndian::read_u32(&buff[0..n]);
-        if receive_index != i {
+    // Send message
+    let sent_at = clock.now_us();
+    radio.do_transmit(&buff[0..n], options.blocking_options.clone())?;
+
+    // Await response
+    let (n, info) = match radio.do_receive(buff, options.blocking_options.clone()) {
+        Ok(r) => r,
+        Err(BlockingError::Timeout) => {
             #[cfg(any(feature = "log", feature = "defmt"))]
-            debug!("Invalid receive index");
-            continue;
+            debug!("Timeout awaiting response {}", i);
+            link_info.loss_bursts.update(false);
+            return Ok(());
         }
+        Err(e) => return Err(e),
+    };
 
-        // Parse info if provided
-        let remote_rssi = match options.parse_info {
-            true => Some(NetworkEndian::read_i16(&buff[4..n])),
-            false => None,
-        };
+    // Decrypt response where enabled, treating authentication failures as lost
+    let n = match cipher.as_mut().map(|c| c.decrypt_in_place(buff, 0, n)) {
+        Some(Some(n)) => n,
+        Some(None) => {
+            link_info.loss_bursts.update(false);
+            return Ok(());
+        }
+        None => n,
+    };
 
+    // Reflector frames carry the send timestamp and flag appended info
+    let (receive_index, sent_at, has_info) = match options.reflector {
+        true => match ReflectorFrame::from_bytes(&buff[..n]) {
+            Some(f) => (f.seq, f.timestamp_us, f.has_info()),
+            None => (u32::MAX, sent_at, false),
+        },
+        false => (
+            NetworkEndian::read_u32(&buff[0..n]),
+            sent_at,
+            options.parse_info,
+        ),
+    };
+    if receive_index != i {
         #[cfg(any(feature = "log", feature = "defmt"))]
-        debug!(
-            "Received response {} with local rssi: {} and remote rssi: {:?}",
-            receive_index,
-            info.rssi(),
-            remote_rssi
+        debug!("Invalid receive index");
+        link_info.loss_bursts.update(false);
+        return Ok(());
+    }
+
+    // Parse info if provided
+    let (remote_rssi, remote) = match has_info {
+        true if n >= len + 2 => ReceiveQuality::decode(&buff[len..n]).unzip(),
+        _ => (None, None),
+    };
+
+    #[cfg(any(feature = "log", feature = "defmt"))]
+    debug!(
+        "Received response {} with local rssi: {} and remote rssi: {:?}",
+        receive_index,
+        info.rssi(),
+        remote_rssi
+    );
+
+    link_info.received += 1;
+    link_info
+        .rtt
+        .update(clock.elapsed_us(sent_at) as f32 / 1000.0);
+    link_info.loss_bursts.update(true);
+    link_info.local_rssi.update(info.rssi() as f32);
+    link_info.update_local(&ReceiveQuality::from_info(&info));
+    if let Some(rssi) = remote_rssi {
+        link_info.remote_rssi.update(rssi as f32);
+    }
+    if let Some(q) = remote {
+        link_info.update_remote(&q);
+    }
+
+    // Wait for send delay
+    radio.delay_us(options.delay.as_micros() as u32);
+
+    Ok(())
+}
+
+/// Run a link test at each payload size in the configured sweep
+pub fn do_ping_pong_sweep<T, I, E>(
+    radio: &mut T,
+    options: PingPongOptions,
+) -> Result<Vec<LinkTestInfo>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+{
+    let sweep = match options.size_sweep {
+        Some(s) => s,
+        None => return do_ping_pong(radio, options).map(|i| vec![i]),
+    };
+
+    let mut results = Vec::new();
+
+    for size in sweep.sizes() {
+        let o = PingPongOptions {
+            size,
+            size_sweep: None,
+            ..options.clone()
+        };
+
+        let r = do_ping_pong(radio, o)?;
+
+        info!(
+            "Size {}: received {}/{} ({:.1}% loss, longest outage {} rounds), rtt mean {:.2} p95 {:.2} ms",
+            r.payload_len,
+            r.received,
+            r.sent,
+            100.0 - r.received as f32 * 100.0 / r.sent.max(1) as f32,
+            r.loss_bursts.longest,
+            r.rtt.mean().unwrap_or_default(),
+            r.rtt.percentile(95.0).unwrap_or_default()
         );
 
-        link_info.received += 1;
-        link_info.local_rssi.update(info.rssi() as f32);
-        if let Some(rssi) = remote_rssi {
-            link_info.remote_rssi.update(rssi as f32);
+        results.push(r);
+    }
+
+    Ok(results)
+}
+
+/// Results from a symmetric link test
+pub struct SymmetricLinkInfo {
+    /// Link statistics for pings initiated by this node
+    pub link: LinkTestInfo,
+    /// Number of peer pings responded to
+    pub responded: u32,
+    /// Number of collisions (unanswered pings) triggering backoff
+    pub collisions: u32,
+    /// Total test duration
+    pub duration: std::time::Duration,
+}
+
+impl SymmetricLinkInfo {
+    /// Completed exchanges (in both directions) per second
+    pub fn exchanges_per_sec(&self) -> f32 {
+        (self.link.received + self.responded) as f32 / self.duration.as_secs_f32()
+    }
+}
+
+const SYMMETRIC_PING: u8 = 0x01;
+const SYMMETRIC_PONG: u8 = 0x02;
+/// Set on a node's final ping, allowing its peer to stop lingering
+const SYMMETRIC_FINAL: u8 = 0x04;
+
+/// Symmetric link test where both nodes initiate pings with randomised offsets,
+/// responding to peer pings and backing off (with a doubling contention window) on collisions
+///
+/// Once its own rounds are complete each node keeps responding to its peer until the
+/// peer's final ping is received, or no pings are received for the linger period.
+/// Nodes are identified by `--node-address` (or a random address), which must differ
+/// between peers and may not be the broadcast address.
+pub fn do_ping_pong_symmetric<T, I, E>(
+    radio: &mut T,
+    options: PingPongOptions,
+) -> Result<SymmetricLinkInfo, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    do_ping_pong_symmetric_clocked(radio, options, &StdClock)
+}
+
+/// Run a symmetric link test, timing offsets, deadlines and round-trip latency with the
+/// provided clock
+pub fn do_ping_pong_symmetric_clocked<T, I, E, C>(
+    radio: &mut T,
+    options: PingPongOptions,
+    clock: &C,
+) -> Result<SymmetricLinkInfo, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    C: Clock + ?Sized,
+{
+    let seed = options.seed.unwrap_or_else(random_seed);
+    let mut rng = XorShift32::new(seed);
+
+    // Addresses are drawn independently of the seed, so peers sharing a seed don't collide
+    let node = match options.node_address {
+        Some(BROADCAST) => {
+            return Err(BlockingError::Unsupported(
+                "symmetric ping-pong requires a non-broadcast node address",
+            ));
         }
+        Some(a) => a,
+        None => loop {
+            match random_seed() as Address {
+                BROADCAST => continue,
+                a => break a,
+            }
+        },
+    };
+    debug!("Symmetric link test as node {:04x}", node);
+
+    let len = Header::LEN + options.size.max(4);
+    let mut buff = vec![0u8; len.max(256) + 16];
 
-        // Wait for send delay
-        radio.delay_us(options.delay.as_micros() as u32);
+    let mut info = SymmetricLinkInfo {
+        link: LinkTestInfo::new(len - Header::LEN),
+        responded: 0,
+        collisions: 0,
+        duration: Default::default(),
+    };
+
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
     }
 
-    Ok(link_info)
+    let poll_us = options.blocking_options.poll_interval.as_micros() as u64;
+    let timeout_us = options.blocking_options.timeout.as_micros() as u64;
+    let min_window = (options.backoff.as_micros() as u64).max(1);
+    let max_window = min_window * 32;
+    let linger_us = options.linger.as_micros() as u64;
+
+    let start = clock.now_us();
+
+    let mut window = min_window;
+    let mut now_us = start;
+    let mut next_tx = now_us + rng.below(window as u32) as u64;
+    let mut awaiting: Option<(u32, u64, u64)> = None;
+    let mut cipher = options.crypto_options.cipher();
+    let (mut peer_done, mut peer_us) = (false, start);
+
+    radio.start_receive()?;
+
+    while info.link.sent < options.rounds
+        || awaiting.is_some()
+        || (!peer_done && now_us.saturating_sub(peer_us) < linger_us)
+    {
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(&mut buff)?;
+
+            match Header::from_bytes(&buff[..n]) {
+                // Respond to pings from our peer
+                Ok(h) if h.flags & !SYMMETRIC_FINAL == SYMMETRIC_PING && h.src != node => {
+                    peer_us = now_us;
+                    peer_done |= h.flags & SYMMETRIC_FINAL != 0;
+
+                    let mut reply = h.reply(node);
+                    reply.flags = SYMMETRIC_PONG;
+                    buff[..Header::LEN].copy_from_slice(&reply.to_bytes());
+
+                    radio.do_transmit(&buff[..n], options.blocking_options.clone())?;
+                    info.responded += 1;
+                }
+                // Match (authenticated, where enabled) responses to our outstanding ping
+                Ok(h) if h.flags == SYMMETRIC_PONG && h.dst == node && n >= Header::LEN + 4 => {
+                    let n = match cipher.as_mut() {
+                        Some(c) => c.decrypt_in_place(&mut buff, Header::LEN, n),
+                        None => Some(n),
+                    };
+                    let index = n
+                        .filter(|n| *n >= Header::LEN + 4)
+                        .map(|_| NetworkEndian::read_u32(&buff[Header::LEN..]));
+                    if let Some((pending, _, sent_at)) =
+                        awaiting.filter(|(p, ..)| Some(*p) == index)
+                    {
+                        debug!("Received response {} with rssi: {}", pending, i.rssi());
+
+                        info.link.received += 1;
+                        info.link
+                            .rtt
+                            .update(clock.elapsed_us(sent_at) as f32 / 1000.0);
+                        info.link.loss_bursts.update(true);
+                        info.link.local_rssi.update(i.rssi() as f32);
+                        awaiting = None;
+                        window = min_window;
+                        next_tx = now_us + rng.below(window as u32) as u64;
+                    }
+                }
+                _ => debug!("Ignoring unexpected frame ({} bytes)", n),
+            }
+
+            // Channel was busy, defer any imminent transmission
+            if awaiting.is_none() && next_tx < now_us + min_window {
+                next_tx = now_us + rng.below(window as u32) as u64;
+            }
+
+            radio.start_receive()?;
+        }
+
+        // Back off on missing responses (assumed collisions)
+        if let Some((index, deadline, _)) = awaiting
+            && now_us > deadline
+        {
+            debug!("Timeout awaiting response {}, backing off", index);
+
+            info.collisions += 1;
+            info.link.loss_bursts.update(false);
+            window = (window * 2).min(max_window);
+            next_tx = now_us + rng.below(window as u32) as u64;
+            awaiting = None;
+        }
+
+        // Send the next ping when our offset expires
+        if awaiting.is_none() && info.link.sent < options.rounds && now_us >= next_tx {
+            let index = info.link.sent;
+            let h = Header {
+                flags: match index + 1 == options.rounds {
+                    true => SYMMETRIC_PING | SYMMETRIC_FINAL,
+                    false => SYMMETRIC_PING,
+                },
+                ..Header::new(node, BROADCAST, index as u8)
+            };
+            buff[..Header::LEN].copy_from_slice(&h.to_bytes());
+            NetworkEndian::write_u32(&mut buff[Header::LEN..], index);
+            for (j, b) in buff[Header::LEN + 4..len].iter_mut().enumerate() {
+                *b = j as u8;
+            }
+
+            // Encrypt payload where enabled, with peers echoing the encrypted ping
+            let n = match cipher.as_mut() {
+                Some(c) => c
+                    .encrypt_in_place(&mut buff, Header::LEN, len)
+                    .expect("Error encrypting message"),
+                None => len,
+            };
+
+            debug!("Sending message {}", index);
+
+            radio.do_transmit(&buff[..n], options.blocking_options.clone())?;
+            radio.start_receive()?;
+
+            info.link.sent += 1;
+            let sent_at = clock.now_us();
+            awaiting = Some((index, sent_at + timeout_us, sent_at));
+        }
+
+        radio.delay_us(poll_us as u32);
+        now_us = clock.now_us();
+    }
+
+    info.duration = std::time::Duration::from_micros(clock.elapsed_us(start));
+    info.link.loss_bursts.finish();
+
+    info!(
+        "Symmetric link test: received {}/{} responses, responded to {} pings, {} collisions, {:.2} exchanges/s",
+        info.link.received,
+        info.link.sent,
+        info.responded,
+        info.collisions,
+        info.exchanges_per_sec()
+    );
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_sweep() {
+        let s: SizeSweep = "16..64:16".parse().unwrap();
+        assert_eq!(s.sizes().collect::<Vec<_>>(), vec![16, 32, 48, 64]);
+
+        let s: SizeSweep = "4..6".parse().unwrap();
+        assert_eq!(s.sizes().collect::<Vec<_>>(), vec![4, 5, 6]);
+
+        assert!("16..8:1".parse::<SizeSweep>().is_err());
+        assert!("16..32:0".parse::<SizeSweep>().is_err());
+        assert!("16".parse::<SizeSweep>().is_err());
+    }
+
+    #[test]
+    fn squelch_threshold() {
+        assert!(!squelched(None, -120, 0));
+        assert!(squelched(Some(-90), -95, 8));
+        assert!(squelched(Some(-90), -60, 0));
+        assert!(!squelched(Some(-90), -90, 8));
+    }
+
+    #[test]
+    fn echo_transforms() {
+        let mut buff = [0x01, 0x02, 0x03, 0x00];
+
+        assert_eq!(EchoTransform::None.apply(&mut buff, 3), 3);
+        assert_eq!(&buff[..3], &[0x01, 0x02, 0x03]);
+
+        assert_eq!(EchoTransform::Reverse.apply(&mut buff, 3), 3);
+        assert_eq!(&buff[..3], &[0x03, 0x02, 0x01]);
+
+        assert_eq!(EchoTransform::Invert.apply(&mut buff, 3), 3);
+        assert_eq!(&buff[..3], &[0xfc, 0xfd, 0xfe]);
+
+        assert_eq!(EchoTransform::Checksum.apply(&mut buff, 3), 4);
+        assert_eq!(&buff[..4], &[0xfc, 0xfd, 0xfe, 0xff]);
+
+        // No space for a checksum
+        assert_eq!(EchoTransform::Checksum.apply(&mut buff, 4), 4);
+    }
+
+    #[test]
+    fn rx_event_tracking() {
+        let mut t = RxEventTracker::default();
+
+        assert_eq!(t.update(None), None);
+        assert_eq!(
+            t.update(Some(RxEvent::PreambleDetected)),
+            Some(RxEvent::PreambleDetected)
+        );
+        assert_eq!(t.update(Some(RxEvent::PreambleDetected)), None);
+        assert_eq!(
+            t.update(Some(RxEvent::SyncDetected)),
+            Some(RxEvent::SyncDetected)
+        );
+        assert_eq!(t.update(Some(RxEvent::PreambleDetected)), None);
+        assert_eq!(t.incomplete(), Some(RxEvent::SyncDetected));
+
+        t.reset();
+        assert_eq!(t.incomplete(), None);
+        assert_eq!(
+            t.update(Some(RxEvent::PreambleDetected)),
+            Some(RxEvent::PreambleDetected)
+        );
+    }
+
+    #[test]
+    fn echo_oversize_policies() {
+        let r = [0xa0, 0xa1, 1, 2, 3, 4, 5];
+
+        assert_eq!(OversizePolicy::Drop.apply(&r, 2, 7), vec![r.to_vec()]);
+        assert!(OversizePolicy::Drop.apply(&r, 2, 6).is_empty());
+        assert_eq!(
+            OversizePolicy::Truncate.apply(&r, 2, 4),
+            vec![vec![0xa0, 0xa1, 1, 2]]
+        );
+        assert_eq!(
+            OversizePolicy::Split.apply(&r, 2, 4),
+            vec![
+                vec![0xa0, 0xa1, 1, 2],
+                vec![0xa0, 0xa1, 3, 4],
+                vec![0xa0, 0xa1, 5]
+            ]
+        );
+        assert!(OversizePolicy::Split.apply(&r, 2, 2).is_empty());
+    }
+
+    fn echo_options() -> EchoOptions {
+        EchoOptions {
+            continuous: true,
+            delay: std::time::Duration::from_millis(10).into(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn echo_encrypted_size_limits() {
+        let mut tx = PayloadCipher::with_session(&[0x33; 16], 1, 0);
+        let mut rx = PayloadCipher::with_session(&[0x33; 16], 2, 0);
+        let response = [0x5a; 24];
+
+        // Plaintext is limited to leave space for encryption, rather than truncating
+        // the ciphertext
+        let options = EchoOptions {
+            max_size: Some(16 + OVERHEAD),
+            ..echo_options()
+        };
+        let frames = options
+            .encrypted_response_frames(&response, 255, &mut tx)
+            .unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].len(), 16 + OVERHEAD);
+        assert_eq!(rx.decrypt(&frames[0]), Some(&response[..16]));
+
+        // Split responses are encrypted per frame
+        let options = EchoOptions {
+            oversize: OversizePolicy::Split,
+            ..options
+        };
+        let frames = options
+            .encrypted_response_frames(&response, 255, &mut tx)
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(rx.decrypt(&frames[1]), Some(&response[16..]));
+    }
+
+    #[test]
+    fn echo_drops_and_jitter() {
+        let mut options = echo_options();
+        let mut rng = XorShift32::new(1);
+
+        assert_eq!(options.response_delay_us(&mut rng), Some(10_000));
+
+        options.delay_jitter = Some(std::time::Duration::from_millis(5).into());
+        options.drop_probability = 0.25;
+
+        let delays: Vec<_> = (0..1000)
+            .map(|_| options.response_delay_us(&mut rng))
+            .collect();
+        let dropped = delays.iter().filter(|d| d.is_none()).count();
+        assert!((200..300).contains(&dropped));
+        assert!(
+            delays
+                .iter()
+                .flatten()
+                .all(|d| (10_000..=15_000).contains(d))
+        );
+        assert!(delays.iter().flatten().any(|d| *d > 12_500));
+
+        options.drop_probability = 1.0;
+        assert_eq!(options.response_delay_us(&mut rng), None);
+    }
+
+    #[test]
+    fn echo_reflector_frames() {
+        let options = EchoOptions {
+            append_info: true,
+            ..echo_options()
+        };
+        let info = crate::BasicInfo::new(-70, 0);
+        let mut invert = |b: &mut [u8], n, _: &crate::BasicInfo| EchoTransform::Invert.apply(b, n);
+        let mut buff = [0u8; 64];
+        let n = ReflectorFrame::new(3, 1_000).encode(20, &mut buff).unwrap();
+
+        // Reflector frames are returned untransformed, with appended info flagged
+        let r = echo_response(&mut buff, n, &info, &options, &mut invert).unwrap();
+        let f = ReflectorFrame::from_bytes(&buff[..r]).unwrap();
+        assert!(f.is_reflected() && f.has_info());
+        assert_eq!((f.seq, f.timestamp_us), (3, 1_000));
+        assert_eq!(
+            ReceiveQuality::decode(&buff[n..r]).map(|(rssi, _)| rssi),
+            Some(-70)
+        );
+
+        // Frames returned by other reflectors are ignored
+        assert_eq!(
+            echo_response(&mut buff, r, &info, &options, &mut invert),
+            None
+        );
+    }
+
+    /// Loopback radio taking a fixed time (on a virtual clock) to return each frame,
+    /// optionally acting as a reflector reporting a fixed RSSI
+    struct ClockedRadio<'a> {
+        clock: &'a crate::clock::VirtualClock,
+        latency_us: u64,
+        last: Option<Vec<u8>>,
+        reflect: bool,
+    }
+
+    impl Transmit for ClockedRadio<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.clock.advance_us(self.latency_us);
+            self.latency_us += 1_000;
+
+            let mut d = data.to_vec();
+            if self.reflect && reflect(&mut d, true).is_some() {
+                d.extend_from_slice(&(-75i16).to_be_bytes());
+            }
+            self.last = Some(d);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for ClockedRadio<'_> {
+        type Error = ();
+        type Info = crate::BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), crate::BasicInfo::new(-60, 0)))
+        }
+    }
+
+    impl Power for ClockedRadio<'_> {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl DelayNs for ClockedRadio<'_> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.clock.advance_us((ns as u64).div_ceil(1000));
+        }
+    }
+
+    #[test]
+    fn ping_pong_latency() {
+        let clock = crate::clock::VirtualClock::new();
+        let mut radio = ClockedRadio {
+            clock: &clock,
+            latency_us: 1_000,
+            last: None,
+            reflect: false,
+        };
+        let mut options = PingPongOptions {
+            rounds: 20,
+            delay: std::time::Duration::from_millis(5).into(),
+            backoff: std::time::Duration::from_millis(1).into(),
+            ..Default::default()
+        };
+
+        // Latencies of 1..=20ms, excluding the response delay
+        let r = do_ping_pong_clocked(&mut radio, options.clone(), &clock, &mut NoProgress).unwrap();
+        assert_eq!(r.received, 20);
+        assert_eq!(r.rtt.min(), Some(1.0));
+        assert_eq!(r.rtt.max(), Some(20.0));
+        assert_eq!(r.rtt.mean(), Some(10.5));
+        assert_eq!(r.rtt.percentile(95.0).map(f32::round), Some(19.0));
+
+        // Reflector frames carry timestamps and remote info
+        radio.latency_us = 1_000;
+        radio.reflect = true;
+        options.reflector = true;
+        let r = do_ping_pong_clocked(&mut radio, options, &clock, &mut NoProgress).unwrap();
+        assert_eq!((r.received, r.payload_len), (20, ReflectorFrame::LEN));
+        assert_eq!(r.rtt.mean(), Some(10.5));
+        assert_eq!(r.remote_rssi.mean(), Some(-75.0));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn ping_pong_symmetric_pair() {
+        use crate::mock::{MediumOptions, SharedMedium};
+
+        let (mut a, mut b) = SharedMedium::pair(MediumOptions::default());
+        let options = PingPongOptions {
+            rounds: 10,
+            symmetric: true,
+            backoff: std::time::Duration::from_millis(5).into(),
+            linger: std::time::Duration::from_secs(5).into(),
+            ..Default::default()
+        };
+
+        // Peers sharing a seed are still assigned distinct addresses
+        let o = PingPongOptions {
+            seed: Some(1),
+            ..options.clone()
+        };
+        let peer = std::thread::spawn(move || do_ping_pong_symmetric(&mut b, o));
+        let started = std::time::Instant::now();
+        let r = do_ping_pong_symmetric(
+            &mut a,
+            PingPongOptions {
+                seed: Some(1),
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        let p = peer.join().unwrap().unwrap();
+
+        // Each node keeps responding until its peer's final ping, without lingering
+        assert_eq!((r.link.sent, r.responded), (10, p.link.sent));
+        assert_eq!((p.link.sent, p.responded), (10, r.link.sent));
+        assert_eq!(r.link.received + r.collisions, 10);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        let o = PingPongOptions {
+            node_address: Some(BROADCAST),
+            ..options
+        };
+        assert!(matches!(
+            do_ping_pong_symmetric(&mut a, o),
+            Err(BlockingError::Unsupported(_))
+        ));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn ping_pong_symmetric_clocked() {
+        use crate::clock::VirtualClock;
+        use crate::mock::{ImpairedRadio, Impairments};
+        use std::rc::Rc;
+
+        // Peer answering each ping after 5 ms in each direction
+        let clock = Rc::new(VirtualClock::new());
+        let impairments = Impairments {
+            latency: std::time::Duration::from_millis(5),
+            ..Default::default()
+        };
+        let mut radio = ImpairedRadio::new(
+            |d| {
+                let mut reply = Header::from_bytes(d).ok()?.reply(0x0002);
+                reply.flags = SYMMETRIC_PONG;
+                let mut r = d.to_vec();
+                r[..Header::LEN].copy_from_slice(&reply.to_bytes());
+                Some(r)
+            },
+            impairments,
+            1,
+        )
+        .with_clock(clock.clone());
+
+        let options = PingPongOptions {
+            rounds: 5,
+            symmetric: true,
+            node_address: Some(0x0001),
+            seed: Some(1),
+            backoff: std::time::Duration::from_millis(5).into(),
+            linger: std::time::Duration::ZERO.into(),
+            ..Default::default()
+        };
+        let r = do_ping_pong_symmetric_clocked(&mut radio, options, &*clock).unwrap();
+
+        // Round trips and the test duration are measured on the virtual clock
+        assert_eq!((r.link.sent, r.link.received, r.collisions), (5, 5, 0));
+        assert_eq!(r.link.rtt.mean(), Some(10.0));
+        assert_eq!(r.duration.as_micros() as u64, clock.now_us());
+        assert!(clock.now_us() >= 50_000);
+    }
 }
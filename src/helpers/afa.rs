@@ -0,0 +1,69 @@
+//! LBT + AFA compliance mode for transmit operations
+//!
+//! With `--afa`, transmissions run through an [`Afa`] wrapper over the `--hop-channels`
+//! list, listening before each transmission and moving between channels as they are
+//! found busy, following ETSI EN 300 220 polite spectrum access rules. Channels are
+//! specified as driver-specific numbers (or frequencies) and mapped to the radio
+//! [`Channel`] type by the caller.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+use embedded_hal::delay::DelayNs;
+
+use super::{TransmitOptions, do_transmit};
+use crate::{
+    Channel, Power, Receive, Rssi, Transmit, afa::Afa, blocking::BlockingError, clock::StdClock,
+};
+
+/// Maximum number of channels managed in AFA mode
+pub const AFA_MAX_CHANNELS: usize = 64;
+
+/// Transmit using the provided configuration with LBT + AFA over the configured hop
+/// channels, returning the number of payloads sent
+///
+/// `to_channel` maps configured channel numbers to the radio channel type.
+pub fn do_transmit_afa<T, E, F>(
+    radio: &mut T,
+    options: TransmitOptions,
+    to_channel: F,
+) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Error = E>
+        + Rssi<Error = E>
+        + Channel<Error = E>
+        + DelayNs,
+    E: core::fmt::Debug,
+    F: FnMut(&u32) -> T::Channel,
+{
+    let channels: Vec<_> = options
+        .fhss_options
+        .hop_channels
+        .iter()
+        .map(to_channel)
+        .collect();
+    let seed = super::random_seed();
+
+    let mut radio: Afa<_, _, _, AFA_MAX_CHANNELS> = Afa::new(
+        &mut *radio,
+        channels,
+        StdClock,
+        options.afa_options.clone(),
+        seed,
+    );
+
+    let res = do_transmit(&mut radio, options);
+
+    info!(
+        "LBT + AFA: {} ({} channels in hop set)",
+        radio.stats(),
+        radio.blacklist().allowed_count()
+    );
+
+    res.map_err(|e| e.flatten())
+}
@@ -0,0 +1,297 @@
+//! Link quality alerting for receive operations
+//!
+//! Alerts are raised where received RSSI remains below `--alert-rssi` for
+//! `--alert-rssi-for`, or where loss (from `--seq-check`) over each `--alert-loss-window`
+//! expected frames exceeds `--alert-loss` percent. Alerts are latched until the metric
+//! recovers (raising a cleared alert), logged, passed to any registered callbacks and
+//! optionally run an `--alert-exec` command, allowing monitoring deployments to page an
+//! operator from the helper itself.
+
+use std::process::Command;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use humantime::Duration as HumanDuration;
+
+use super::SeqStats;
+
+/// Link quality alerting options
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct AlertOptions {
+    /// Alert where received RSSI (dBm) remains below this threshold for `--alert-rssi-for`
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub alert_rssi: Option<i16>,
+
+    /// Duration RSSI must remain below `--alert-rssi` before alerting
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10s"))]
+    pub alert_rssi_for: HumanDuration,
+
+    /// Alert where loss (%) exceeds this threshold, requires `--seq-check`
+    #[cfg_attr(feature = "clap", clap(long, requires = "seq_check"))]
+    pub alert_loss: Option<f32>,
+
+    /// Number of expected frames over which loss is evaluated
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100"))]
+    pub alert_loss_window: u32,
+
+    /// Command run (with `sh -c`) on alerts, with the alert described by the
+    /// `RADIO_ALERT` (kind), `RADIO_ALERT_STATE` (raised or cleared) and
+    /// `RADIO_ALERT_VALUE` environment variables
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub alert_exec: Option<String>,
+}
+
+impl Default for AlertOptions {
+    fn default() -> Self {
+        Self {
+            alert_rssi: None,
+            alert_rssi_for: std::time::Duration::from_secs(10).into(),
+            alert_loss: None,
+            alert_loss_window: 100,
+            alert_exec: None,
+        }
+    }
+}
+
+/// Alerted link quality metric
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlertKind {
+    /// RSSI below threshold, with the value in dBm
+    LowRssi,
+    /// Loss above threshold, with the value in percent
+    HighLoss,
+}
+
+impl AlertKind {
+    /// Alert name, as passed to alert commands
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertKind::LowRssi => "low_rssi",
+            AlertKind::HighLoss => "high_loss",
+        }
+    }
+}
+
+/// Link quality alert
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// Whether the alert was raised (or cleared)
+    pub raised: bool,
+    /// Metric value triggering the alert
+    pub value: f32,
+}
+
+impl core::fmt::Display for Alert {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = match self.raised {
+            true => "raised",
+            false => "cleared",
+        };
+        match self.kind {
+            AlertKind::LowRssi => write!(f, "low rssi {} ({:.0} dBm)", state, self.value),
+            AlertKind::HighLoss => write!(f, "high loss {} ({:.1}%)", state, self.value),
+        }
+    }
+}
+
+type AlertHandler = Box<dyn FnMut(&Alert) + Send>;
+
+/// Link quality monitor, raising alerts as metrics cross the configured thresholds
+pub struct AlertMonitor {
+    options: AlertOptions,
+    /// Time since which RSSI has been below threshold
+    low_since_us: Option<u64>,
+    low_rssi: bool,
+    /// Expected and missing frame counts at the start of the loss window
+    window_start: (u32, u32),
+    high_loss: bool,
+    handlers: Vec<AlertHandler>,
+}
+
+impl AlertMonitor {
+    /// Create a monitor with the provided thresholds
+    pub fn new(options: AlertOptions) -> Self {
+        Self {
+            options,
+            low_since_us: None,
+            low_rssi: false,
+            window_start: (0, 0),
+            high_loss: false,
+            handlers: vec![],
+        }
+    }
+
+    /// Register a callback, called on each alert
+    pub fn on_alert<F: FnMut(&Alert) + Send + 'static>(&mut self, f: F) {
+        self.handlers.push(Box::new(f));
+    }
+
+    /// Update with the RSSI of a received frame at time `now_us`, returning any alert
+    pub fn update_rssi(&mut self, rssi: i16, now_us: u64) -> Option<Alert> {
+        let threshold = self.options.alert_rssi?;
+
+        if rssi >= threshold {
+            self.low_since_us = None;
+            return match self.low_rssi {
+                true => {
+                    self.low_rssi = false;
+                    Some(self.alert(AlertKind::LowRssi, false, rssi as f32))
+                }
+                false => None,
+            };
+        }
+
+        let since = *self.low_since_us.get_or_insert(now_us);
+        let elapsed = now_us.saturating_sub(since);
+        if !self.low_rssi && elapsed >= self.options.alert_rssi_for.as_micros() as u64 {
+            self.low_rssi = true;
+            return Some(self.alert(AlertKind::LowRssi, true, rssi as f32));
+        }
+
+        None
+    }
+
+    /// Update with sequence statistics, evaluating loss at the end of each window and
+    /// returning any alert
+    pub fn update_loss(&mut self, stats: &SeqStats) -> Option<Alert> {
+        let threshold = self.options.alert_loss?;
+
+        let expected = stats.received - stats.duplicates + stats.missing;
+        let (start_expected, start_missing) = self.window_start;
+        let n = expected.saturating_sub(start_expected);
+        if n < self.options.alert_loss_window.max(1) {
+            return None;
+        }
+
+        let loss = stats.missing.saturating_sub(start_missing) as f32 * 100.0 / n as f32;
+        self.window_start = (expected, stats.missing);
+        debug!("Window loss: {}%", loss);
+
+        match (loss > threshold, self.high_loss) {
+            (true, false) | (false, true) => {
+                self.high_loss = !self.high_loss;
+                Some(self.alert(AlertKind::HighLoss, self.high_loss, loss))
+            }
+            _ => None,
+        }
+    }
+
+    /// Log and dispatch an alert
+    fn alert(&mut self, kind: AlertKind, raised: bool, value: f32) -> Alert {
+        let alert = Alert {
+            kind,
+            raised,
+            value,
+        };
+
+        warn!("Link alert: {}", alert);
+
+        for h in self.handlers.iter_mut() {
+            h(&alert);
+        }
+
+        if let Some(cmd) = &self.options.alert_exec {
+            run_alert_command(cmd, &alert);
+        }
+
+        alert
+    }
+}
+
+impl core::fmt::Debug for AlertMonitor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AlertMonitor")
+            .field("options", &self.options)
+            .field("low_rssi", &self.low_rssi)
+            .field("high_loss", &self.high_loss)
+            .finish()
+    }
+}
+
+/// Run an alert command without blocking the receive loop, reaping it on a background thread
+fn run_alert_command(cmd: &str, alert: &Alert) {
+    let state = match alert.raised {
+        true => "raised",
+        false => "cleared",
+    };
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("RADIO_ALERT", alert.kind.name())
+        .env("RADIO_ALERT_STATE", state)
+        .env("RADIO_ALERT_VALUE", alert.value.to_string())
+        .spawn();
+
+    match child {
+        Ok(mut c) => {
+            std::thread::spawn(move || c.wait());
+        }
+        Err(e) => warn!("Error running alert command '{}': {:?}", cmd, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn link_alerts() {
+        let mut m = AlertMonitor::new(AlertOptions {
+            alert_rssi: Some(-90),
+            alert_rssi_for: std::time::Duration::from_secs(5).into(),
+            alert_loss: Some(10.0),
+            alert_loss_window: 10,
+            ..Default::default()
+        });
+        let seen = Arc::new(Mutex::new(vec![]));
+        let s = seen.clone();
+        m.on_alert(move |a| s.lock().unwrap().push(a.clone()));
+
+        // RSSI must remain low for the configured duration, alerting once
+        assert_eq!(m.update_rssi(-95, 0), None);
+        assert_eq!(m.update_rssi(-80, 1_000_000), None);
+        assert_eq!(m.update_rssi(-95, 2_000_000), None);
+        assert_eq!(m.update_rssi(-96, 6_000_000), None);
+        let a = m.update_rssi(-97, 7_000_000).unwrap();
+        assert_eq!(
+            (a.kind, a.raised, a.value),
+            (AlertKind::LowRssi, true, -97.0)
+        );
+        assert_eq!(m.update_rssi(-97, 8_000_000), None);
+        assert!(!m.update_rssi(-70, 9_000_000).unwrap().raised);
+
+        // Loss is evaluated per window of expected frames
+        let mut stats = SeqStats {
+            received: 9,
+            missing: 1,
+            ..Default::default()
+        };
+        assert_eq!(m.update_loss(&stats), None);
+        stats.received = 16;
+        stats.missing = 4;
+        let a = m.update_loss(&stats).unwrap();
+        assert_eq!(
+            (a.kind, a.raised, a.value),
+            (AlertKind::HighLoss, true, 30.0)
+        );
+        stats.received = 26;
+        assert!(!m.update_loss(&stats).unwrap().raised);
+
+        assert_eq!(seen.lock().unwrap().len(), 4);
+        assert_eq!(
+            seen.lock().unwrap()[1].to_string(),
+            "low rssi cleared (-70 dBm)"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Annotated console output, labelling and colour-coding decoded frame fields
+//!
+//! With `rx --annotate`, received frames are printed field by field (for example
+//! `fc=6188 seq=2a dst_pan=cdab dst=0200 ... fcs=0000`) using the fields described by the
+//! selected decoders (see [`Decoder::fields`](super::Decoder::fields)), with addresses,
+//! sequence numbers, payloads and checks highlighted in distinct colours unless
+//! `--no-color` is set. Frames not recognised by any decoder are printed as raw hex.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+use super::{DecodedFrame, DecoderRegistry, Field, FieldKind, PacketSink};
+
+/// ANSI reset sequence
+const RESET: &str = "\x1b[0m";
+
+impl FieldKind {
+    /// ANSI colour sequence used to highlight fields of this kind
+    pub fn color(&self) -> &'static str {
+        match self {
+            FieldKind::Control => "\x1b[2m",
+            FieldKind::Address => "\x1b[36m",
+            FieldKind::Sequence => "\x1b[33m",
+            FieldKind::Payload => "\x1b[32m",
+            FieldKind::Check => "\x1b[35m",
+        }
+    }
+}
+
+/// Frame data formatted with labelled (and optionally coloured) fields, with any bytes
+/// not covered by a field shown as `?=..`
+pub struct Annotated<'a> {
+    pub data: &'a [u8],
+    pub fields: &'a [Field],
+    pub color: bool,
+}
+
+impl core::fmt::Display for Annotated<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let hex = |f: &mut core::fmt::Formatter<'_>, d: &[u8]| {
+            d.iter().try_for_each(|b| write!(f, "{:02x}", b))
+        };
+
+        let mut end = 0;
+        for (i, field) in self.fields.iter().enumerate() {
+            let range =
+                field.range.start.min(self.data.len())..field.range.end.min(self.data.len());
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            if self.color {
+                write!(f, "{}", field.kind.color())?;
+            }
+            write!(f, "{}=", field.label)?;
+            hex(f, &self.data[range.clone()])?;
+            if self.color {
+                write!(f, "{}", RESET)?;
+            }
+            end = end.max(range.end);
+        }
+
+        if end < self.data.len() {
+            if end > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "?=")?;
+            hex(f, &self.data[end..])?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Console sink, logging each frame with annotated fields
+pub struct AnnotatedConsoleSink {
+    registry: DecoderRegistry,
+    selected: Vec<String>,
+    color: bool,
+}
+
+impl AnnotatedConsoleSink {
+    /// Create an annotated sink using the built-in decoders, in order of `selected`
+    /// (or all decoders where none are selected)
+    pub fn new(selected: &[String], color: bool) -> Self {
+        Self::with_registry(DecoderRegistry::default(), selected, color)
+    }
+
+    /// Create an annotated sink using the provided decoder registry
+    pub fn with_registry(registry: DecoderRegistry, selected: &[String], color: bool) -> Self {
+        let selected = match selected.is_empty() {
+            true => vec!["auto".to_string()],
+            false => selected.to_vec(),
+        };
+        Self {
+            registry,
+            selected,
+            color,
+        }
+    }
+
+    /// Format a frame with annotated fields
+    pub fn format(&self, frame: &DecodedFrame) -> String {
+        let (protocol, fields) = self
+            .registry
+            .fields(&self.selected, &frame.data)
+            .unwrap_or_else(|| ("raw".to_string(), vec![]));
+
+        let annotated = Annotated {
+            data: &frame.data,
+            fields: &fields,
+            color: self.color,
+        };
+        match &frame.device {
+            Some(d) => format!("<{}> [{}] {} rssi: {}", d, protocol, annotated, frame.rssi),
+            None => format!("[{}] {} rssi: {}", protocol, annotated, frame.rssi),
+        }
+    }
+}
+
+impl PacketSink for AnnotatedConsoleSink {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        info!("Received: {}", self.format(frame));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotate_fields() {
+        let data = vec![
+            0x61, 0x88, 0x2a, 0xcd, 0xab, 0x02, 0x00, 0x01, 0x00, 0xaa, 0xbb, 0x12, 0x34,
+        ];
+        let frame = DecodedFrame {
+            timestamp_us: 0,
+            rssi: -70,
+            data,
+            text: None,
+            protocol: None,
+            summary: None,
+            device: None,
+            quality: Default::default(),
+            info: String::new(),
+        };
+
+        let sink = AnnotatedConsoleSink::new(&[], false);
+        assert_eq!(
+            sink.format(&frame),
+            "[ieee802154] fc=6188 seq=2a dst_pan=cdab dst=0200 src=0100 payload=aabb fcs=1234 rssi: -70"
+        );
+
+        // Fields are highlighted by kind
+        let sink = AnnotatedConsoleSink::new(&["frame".into()], true);
+        let frame = DecodedFrame {
+            data: vec![0x00, 0x07, 0x00, 0x02, 0x00, 0x01, 0x68, 0x69],
+            ..frame
+        };
+        let s = sink.format(&frame);
+        assert!(s.starts_with("[frame] \x1b[2mflags=00\x1b[0m \x1b[33mseq=07\x1b[0m"));
+        assert!(s.contains("\x1b[32mpayload=6869\x1b[0m"));
+
+        // Unrecognised frames are printed as raw hex
+        let sink = AnnotatedConsoleSink::new(&["lorawan".into()], false);
+        let frame = DecodedFrame {
+            data: vec![0xff, 0x01],
+            ..frame
+        };
+        assert_eq!(sink.format(&frame), "[raw] ?=ff01 rssi: -70");
+    }
+}
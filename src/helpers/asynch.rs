@@ -0,0 +1,162 @@
+//! Async transmit and receive operations
+//!
+//! Async counterparts to [`do_transmit`](super::do_transmit) and
+//! [`do_receive`](super::do_receive), awaiting the radio's async `DelayNs` implementation
+//! between polls rather than busy-waiting.
+
+use std::time::SystemTime;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+use embedded_hal_async::delay::DelayNs;
+
+use super::{
+    DecodeWorker, RateTracker, ReceiveOptions, ReceiveQuality, ReceivedFrame, TransmitOptions,
+    TxTrace, squelched, time_on_air,
+};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit, asynch::AsyncTransmit, blocking::BlockingError,
+};
+
+/// Transmit using the provided configuration, awaiting completion
+pub async fn do_transmit_async<T, E>(
+    radio: &mut T,
+    options: TransmitOptions,
+) -> Result<(), BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    let mut source = options.open_source().expect("Error opening packet source");
+
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut sent = 0;
+
+    while let Some(data) = source.next_payload().expect("Error reading packet source") {
+        // Delay between transmissions
+        if sent > 0
+            && let Some(p) = &options.period
+        {
+            radio.delay_us(p.as_micros() as u32).await;
+        }
+
+        // Transmit packet
+        let start = std::time::Instant::now();
+        AsyncTransmit::do_transmit(radio, &data, options.blocking_options.clone().into()).await?;
+        sent += 1;
+
+        if options.trace_tx {
+            let t = TxTrace {
+                data: &data,
+                time_on_air: options
+                    .bitrate
+                    .map(|b| time_on_air(data.len(), b))
+                    .unwrap_or_else(|| start.elapsed()),
+                computed: options.bitrate.is_some(),
+                power: options.power,
+            };
+            info!("Transmitted: {}", t);
+        }
+    }
+
+    Ok(())
+}
+
+/// Receive using the provided configuration, awaiting between polls for received packets
+pub async fn do_receive_async<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: ReceiveOptions,
+) -> Result<usize, E>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    // Setup decode pipeline, handling output off the polling loop
+    let mut worker =
+        DecodeWorker::new(options.worker_options.clone()).expect("Error creating decode pipeline");
+    let mut rates = options.rate_interval.map(|i| RateTracker::new(*i));
+    let mut deframer = options.framing_options.deframer();
+    let mut cipher = options.crypto_options.cipher();
+
+    // Start receive mode
+    radio.start_receive()?;
+
+    loop {
+        // Print rates if enabled
+        if let Some(r) = rates.as_mut().and_then(|r| r.poll()) {
+            info!("Receive rate: {}", r);
+        }
+
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(buff)?;
+
+            // Suppress noise-floor receptions, continuing to receive
+            if squelched(options.squelch, i.rssi(), n) {
+                debug!("Squelched {} byte frame with rssi: {}", n, i.rssi());
+                radio.start_receive()?;
+                continue;
+            }
+
+            if let Some(r) = rates.as_mut() {
+                r.packet(n);
+            }
+
+            // Filter by address and reassemble fragments where framing is enabled
+            let payload = match deframer.as_mut() {
+                Some(d) => d.accept(&buff[..n]),
+                None => Some(&buff[..n]),
+            };
+
+            // Decrypt and authenticate where encryption is enabled
+            let payload = match (payload, cipher.as_mut()) {
+                (Some(p), Some(c)) => c.decrypt(p),
+                (p, _) => p,
+            };
+            let payload = match payload {
+                Some(p) => p,
+                None => {
+                    radio.start_receive()?;
+                    continue;
+                }
+            };
+
+            match worker.alloc(payload) {
+                Some(data) => {
+                    let frame = ReceivedFrame {
+                        timestamp: SystemTime::now(),
+                        rssi: i.rssi(),
+                        data,
+                        quality: ReceiveQuality::from_info(&i),
+                        info: format!("{:?}", i),
+                    };
+                    if !worker.submit(frame) {
+                        debug!("Decode queue full, dropped frame");
+                    }
+                }
+                None => debug!("Receive buffer pool exhausted, dropped frame"),
+            }
+
+            if !options.continuous {
+                let stats = worker.finish();
+                debug!("Decode pipeline: {:?}", stats);
+                return Ok(payload.len());
+            }
+
+            radio.start_receive()?;
+        }
+
+        radio
+            .delay_us(options.blocking_options.poll_interval.as_micros() as u32)
+            .await;
+    }
+}
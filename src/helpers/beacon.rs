@@ -0,0 +1,373 @@
+//! Periodic beacons with evolving payloads and loss tracking
+//!
+//! The `beacon` operation periodically transmits a structured [`Beacon`] (node id, a
+//! monotonically increasing counter, uptime and optionally the RSSI of the last beacon
+//! heard), listening for beacons from other nodes between transmissions. With `--receive`
+//! beacons are only received. Missed counters are tracked per node, giving loss over long
+//! soak tests without a peer running echo, with node restarts (a counter reset alongside a
+//! lower uptime) distinguished from loss.
+
+use std::time::Instant;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::Shutdown;
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+    frame::Address,
+};
+
+/// Leading byte identifying beacon frames
+pub const BEACON_MAGIC: u8 = 0xb5;
+
+/// Encoded beacon length, excluding the optional RSSI
+pub const BEACON_LEN: usize = 11;
+
+/// Configuration for Beacon operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct BeaconOptions {
+    /// Node ID included in transmitted beacons
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub node_id: Address,
+
+    /// Period between transmitted beacons
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1s"))]
+    pub period: HumanDuration,
+
+    /// Include the RSSI of the last beacon heard in transmitted beacons
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub with_rssi: bool,
+
+    /// Only receive beacons, tracking loss from each transmitting node
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub receive: bool,
+
+    /// Number of beacons to transmit (runs until interrupted if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub count: Option<u32>,
+
+    /// Beacon duration (runs until interrupted if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub duration: Option<HumanDuration>,
+
+    /// Specify transmit power
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    /// Shutdown signal, stopping beacons cleanly once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Structured beacon payload
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Beacon {
+    /// Transmitting node
+    pub node_id: Address,
+    /// Beacon counter, incremented for each beacon transmitted
+    pub counter: u32,
+    /// Time since the transmitting node started beaconing, in seconds
+    pub uptime_s: u32,
+    /// RSSI of the last beacon heard by the transmitting node
+    pub last_rssi: Option<i16>,
+}
+
+impl Beacon {
+    /// Encode the beacon into the provided buffer, returning the encoded length
+    pub fn encode(&self, buff: &mut [u8]) -> usize {
+        buff[0] = BEACON_MAGIC;
+        buff[1..3].copy_from_slice(&self.node_id.to_be_bytes());
+        buff[3..7].copy_from_slice(&self.counter.to_be_bytes());
+        buff[7..11].copy_from_slice(&self.uptime_s.to_be_bytes());
+
+        match self.last_rssi {
+            Some(r) => {
+                buff[11..13].copy_from_slice(&r.to_be_bytes());
+                BEACON_LEN + 2
+            }
+            None => BEACON_LEN,
+        }
+    }
+
+    /// Decode a beacon, `None` where the data is not a beacon frame
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.first() != Some(&BEACON_MAGIC) {
+            return None;
+        }
+
+        let last_rssi = match data.len() {
+            BEACON_LEN => None,
+            n if n == BEACON_LEN + 2 => Some(i16::from_be_bytes([data[11], data[12]])),
+            _ => return None,
+        };
+
+        Some(Self {
+            node_id: Address::from_be_bytes([data[1], data[2]]),
+            counter: u32::from_be_bytes([data[3], data[4], data[5], data[6]]),
+            uptime_s: u32::from_be_bytes([data[7], data[8], data[9], data[10]]),
+            last_rssi,
+        })
+    }
+}
+
+/// Beacon reception statistics for a single node
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BeaconStats {
+    pub node_id: Address,
+    /// Beacons received
+    pub received: u32,
+    /// Beacons missed, from gaps in the received counters
+    pub missed: u32,
+    /// Stale or duplicate beacons (counter not advancing)
+    pub duplicates: u32,
+    /// Node restarts, detected by a counter reset alongside a lower uptime
+    pub restarts: u32,
+    /// Last beacon received from the node
+    pub last: Beacon,
+    /// RSSI of the last beacon received from the node
+    pub rssi: i16,
+}
+
+impl BeaconStats {
+    /// Fraction of beacons missed
+    pub fn loss(&self) -> f32 {
+        match self.received + self.missed {
+            0 => 0.0,
+            n => self.missed as f32 / n as f32,
+        }
+    }
+}
+
+impl core::fmt::Display for BeaconStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "node {:04x}: received {} missed {} ({:.1}%) duplicates {} restarts {}, uptime {} s, rssi {} dBm",
+            self.node_id,
+            self.received,
+            self.missed,
+            self.loss() * 100.0,
+            self.duplicates,
+            self.restarts,
+            self.last.uptime_s,
+            self.rssi
+        )
+    }
+}
+
+/// Beacon operation outcome
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BeaconReport {
+    /// Beacons transmitted
+    pub sent: u32,
+    /// Reception statistics for each node heard
+    pub nodes: Vec<BeaconStats>,
+}
+
+/// Per-node beacon loss tracker
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BeaconTracker {
+    nodes: Vec<BeaconStats>,
+}
+
+impl BeaconTracker {
+    /// Create a new tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a received beacon, returning the number of beacons missed since the
+    /// previous beacon from the same node
+    pub fn update(&mut self, beacon: Beacon, rssi: i16) -> u32 {
+        let s = match self.nodes.iter_mut().find(|s| s.node_id == beacon.node_id) {
+            Some(s) => s,
+            None => {
+                self.nodes.push(BeaconStats {
+                    node_id: beacon.node_id,
+                    received: 1,
+                    missed: 0,
+                    duplicates: 0,
+                    restarts: 0,
+                    last: beacon,
+                    rssi,
+                });
+                return 0;
+            }
+        };
+
+        let missed = match beacon.counter.checked_sub(s.last.counter) {
+            Some(0) => {
+                s.duplicates += 1;
+                return 0;
+            }
+            Some(d) => d - 1,
+            None if beacon.uptime_s < s.last.uptime_s => {
+                s.restarts += 1;
+                0
+            }
+            None => {
+                s.duplicates += 1;
+                return 0;
+            }
+        };
+
+        s.received += 1;
+        s.missed += missed;
+        s.last = beacon;
+        s.rssi = rssi;
+
+        missed
+    }
+
+    /// Statistics for each node heard
+    pub fn nodes(&self) -> &[BeaconStats] {
+        &self.nodes
+    }
+
+    /// RSSI of the most recently received beacon
+    fn last_rssi(&self, node_id: Option<Address>) -> Option<i16> {
+        node_id.and_then(|n| self.nodes.iter().find(|s| s.node_id == n).map(|s| s.rssi))
+    }
+}
+
+/// Transmit periodic beacons (unless `--receive` is set), tracking beacons heard from
+/// other nodes, returning the number of beacons sent and per-node loss statistics
+pub fn do_beacon<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: BeaconOptions,
+) -> Result<BeaconReport, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut tracker = BeaconTracker::new();
+    let mut last_heard = None;
+    let mut sent = 0;
+
+    let start = Instant::now();
+    let mut next_tx = start;
+    let mut frame = [0u8; BEACON_LEN + 2];
+
+    radio.start_receive()?;
+
+    loop {
+        if options.shutdown.is_triggered()
+            || options.duration.is_some_and(|d| start.elapsed() >= *d)
+            || (!options.receive && options.count.is_some_and(|c| sent >= c))
+        {
+            break;
+        }
+
+        // Transmit the next beacon when due, resuming receive afterwards
+        if !options.receive && Instant::now() >= next_tx {
+            let beacon = Beacon {
+                node_id: options.node_id,
+                counter: sent,
+                uptime_s: start.elapsed().as_secs() as u32,
+                last_rssi: match options.with_rssi {
+                    true => tracker.last_rssi(last_heard),
+                    false => None,
+                },
+            };
+            let n = beacon.encode(&mut frame);
+
+            debug!("Beacon transmit: {:?}", beacon);
+            radio.do_transmit(&frame[..n], options.blocking_options.clone())?;
+
+            sent += 1;
+            next_tx += *options.period;
+            radio.start_receive()?;
+        }
+
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(buff)?;
+            match Beacon::decode(&buff[..n]) {
+                Some(b) => {
+                    debug!("Beacon received: {:?} (rssi {})", b, i.rssi());
+                    last_heard = Some(b.node_id);
+
+                    let node_id = b.node_id;
+                    let missed = tracker.update(b, i.rssi());
+                    if missed > 0 {
+                        warn!("Beacon: missed {} from node {:04x}", missed, node_id);
+                    }
+                }
+                None => debug!("Beacon ignoring non-beacon frame ({} bytes)", n),
+            }
+            radio.start_receive()?;
+        }
+
+        radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+
+    info!("Beacon: sent {}", sent);
+    for s in tracker.nodes() {
+        info!("Beacon: {}", s);
+    }
+
+    Ok(BeaconReport {
+        sent,
+        nodes: tracker.nodes().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beacon_loss() {
+        let beacon = |counter, uptime_s| Beacon {
+            node_id: 0x0102,
+            counter,
+            uptime_s,
+            last_rssi: Some(-70),
+        };
+
+        let mut buff = [0u8; 16];
+        let n = beacon(7, 30).encode(&mut buff);
+        assert_eq!(n, BEACON_LEN + 2);
+        assert_eq!(Beacon::decode(&buff[..n]), Some(beacon(7, 30)));
+        assert_eq!(Beacon::decode(&buff[..n - 1]), None);
+        assert_eq!(Beacon::decode(b"hello world"), None);
+
+        let mut t = BeaconTracker::new();
+        assert_eq!(t.update(beacon(0, 0), -80), 0);
+        assert_eq!(t.update(beacon(1, 1), -80), 0);
+        assert_eq!(t.update(beacon(4, 4), -80), 2);
+
+        // Duplicates and restarts aren't counted as loss
+        assert_eq!(t.update(beacon(4, 4), -80), 0);
+        assert_eq!(t.update(beacon(0, 0), -75), 0);
+        assert_eq!(t.update(beacon(2, 2), -75), 1);
+
+        let s = &t.nodes()[0];
+        assert_eq!(
+            (s.received, s.missed, s.duplicates, s.restarts, s.rssi),
+            (5, 3, 1, 1, -75)
+        );
+        assert_eq!(s.loss(), 3.0 / 8.0);
+    }
+}
@@ -0,0 +1,289 @@
+//! Driver conformance benchmarks
+//!
+//! The `bench` operation measures driver-level timings without a peer: the cost of
+//! `start_receive` and `check_receive` calls, transmit time (from `start_transmit` until
+//! `check_transmit` reports completion), transmit to receive turnaround, and the maximum
+//! sustained frame rate. State is polled without delays so timings are not quantised by
+//! the poll interval. Reports may be saved as JSON to catch driver regressions.
+
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::Samples;
+use super::json;
+use crate::{
+    Receive, Transmit,
+    blocking::{BlockingError, BlockingOptions},
+};
+
+/// Configuration for Bench operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct BenchOptions {
+    /// Iterations for each timing measurement
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100"))]
+    pub iterations: u32,
+
+    /// Transmitted frame size in bytes
+    #[cfg_attr(feature = "clap", clap(long, default_value = "16"))]
+    pub size: usize,
+
+    /// Duration of the sustained frame rate measurement
+    #[cfg_attr(feature = "clap", clap(long, default_value = "5s"))]
+    pub rate_duration: HumanDuration,
+
+    /// File to save the benchmark report to (as JSON)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub save: Option<String>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 100,
+            size: 16,
+            rate_duration: Duration::from_secs(5).into(),
+            save: None,
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+/// Driver benchmark report, with timings in microseconds
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchReport {
+    /// Frame size used for transmit measurements
+    pub size: usize,
+    /// `start_receive` call duration
+    pub start_receive: Samples,
+    /// `check_receive` call duration
+    pub check_receive: Samples,
+    /// `start_transmit` call duration
+    pub start_transmit: Samples,
+    /// Time from `start_transmit` until `check_transmit` reports completion
+    pub tx_time: Samples,
+    /// Time from transmit completion until `start_receive` returns
+    pub turnaround: Samples,
+    /// Maximum sustained frame rate, in frames per second
+    pub max_frame_rate: f32,
+}
+
+impl BenchReport {
+    /// Load a saved benchmark report
+    pub fn load(path: &str) -> Result<Self, std::io::Error> {
+        let d = std::fs::read(path)?;
+        json::from_slice(&d)
+    }
+
+    /// Save the benchmark report
+    pub fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let d = json::to_vec_pretty(self)?;
+        std::fs::write(path, d)
+    }
+}
+
+impl core::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let opt = |v: Option<f32>| v.map(|v| format!("{:.1}", v)).unwrap_or("-".into());
+
+        writeln!(
+            f,
+            "{:<16} {:>10} {:>10} {:>10}",
+            "timing (us)", "mean", "p95", "max"
+        )?;
+        for (name, s) in [
+            ("start_receive", &self.start_receive),
+            ("check_receive", &self.check_receive),
+            ("start_transmit", &self.start_transmit),
+            ("tx time", &self.tx_time),
+            ("turnaround", &self.turnaround),
+        ] {
+            writeln!(
+                f,
+                "{:<16} {:>10} {:>10} {:>10}",
+                name,
+                opt(s.mean()),
+                opt(s.percentile(95.0)),
+                opt(s.max())
+            )?;
+        }
+        write!(
+            f,
+            "max frame rate: {:.1} frames/s ({} byte frames)",
+            self.max_frame_rate, self.size
+        )
+    }
+}
+
+fn micros(d: Duration) -> f32 {
+    d.as_nanos() as f32 / 1000.0
+}
+
+/// Transmit a frame, polling for completion without delays, returning the completion time
+fn transmit_spin<T, E>(
+    radio: &mut T,
+    data: &[u8],
+    options: &BlockingOptions,
+) -> Result<Instant, BlockingError<E>>
+where
+    T: Transmit<Error = E>,
+{
+    let start = Instant::now();
+    radio.start_transmit(data)?;
+
+    loop {
+        if radio.check_transmit()? {
+            return Ok(Instant::now());
+        }
+        if start.elapsed() > options.timeout {
+            return Err(BlockingError::Timeout);
+        }
+    }
+}
+
+/// Benchmark driver timings, logging (and optionally saving) the report
+pub fn do_bench<T, E>(radio: &mut T, options: BenchOptions) -> Result<BenchReport, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Error = E>,
+    E: core::fmt::Debug,
+{
+    let mut report = BenchReport {
+        size: options.size,
+        ..Default::default()
+    };
+    let data: Vec<u8> = (0..options.size).map(|i| i as u8).collect();
+
+    // Receive call costs
+    for _ in 0..options.iterations {
+        let t = Instant::now();
+        radio.start_receive()?;
+        report.start_receive.update(micros(t.elapsed()));
+
+        let t = Instant::now();
+        radio.check_receive(false)?;
+        report.check_receive.update(micros(t.elapsed()));
+    }
+
+    // Transmit times and turnaround to receive
+    for _ in 0..options.iterations {
+        let t = Instant::now();
+        radio.start_transmit(&data)?;
+        report.start_transmit.update(micros(t.elapsed()));
+
+        loop {
+            if radio.check_transmit()? {
+                break;
+            }
+            if t.elapsed() > options.blocking_options.timeout {
+                return Err(BlockingError::Timeout);
+            }
+        }
+        let done = Instant::now();
+        report.tx_time.update(micros(done - t));
+
+        radio.start_receive()?;
+        report.turnaround.update(micros(done.elapsed()));
+    }
+
+    // Back-to-back transmissions for the sustained frame rate
+    let start = Instant::now();
+    let mut frames = 0u32;
+    while start.elapsed() < *options.rate_duration {
+        transmit_spin(radio, &data, &options.blocking_options)?;
+        frames += 1;
+    }
+    report.max_frame_rate = frames as f32 / start.elapsed().as_secs_f32();
+
+    info!("Benchmark:\n{}", report);
+
+    if let Some(path) = &options.save {
+        report.save(path).expect("Error saving benchmark report");
+    }
+
+    Ok(report)
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio completing transmissions after a fixed number of polls
+    #[derive(Default)]
+    struct PollRadio {
+        polls: u32,
+        transmitted: u32,
+    }
+
+    impl Transmit for PollRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            self.polls = 3;
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            self.polls = self.polls.saturating_sub(1);
+            if self.polls == 0 {
+                self.transmitted += 1;
+            }
+            Ok(self.polls == 0)
+        }
+    }
+
+    impl Receive for PollRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Err(())
+        }
+    }
+
+    #[test]
+    fn bench_driver_timings() {
+        let path = std::env::temp_dir().join(format!("radio-bench-{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let options = BenchOptions {
+            iterations: 10,
+            rate_duration: Duration::from_millis(10).into(),
+            save: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let mut radio = PollRadio::default();
+        let report = do_bench(&mut radio, options).unwrap();
+
+        assert_eq!(report.start_receive.count(), 10);
+        assert_eq!(report.check_receive.count(), 10);
+        assert_eq!(report.tx_time.count(), 10);
+        assert_eq!(report.turnaround.count(), 10);
+        assert!(report.max_frame_rate > 0.0);
+        assert!(radio.transmitted > 10);
+
+        assert_eq!(BenchReport::load(&path).unwrap(), report);
+        let _ = std::fs::remove_file(&path);
+    }
+}
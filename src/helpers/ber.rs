@@ -0,0 +1,458 @@
+//! Bit and packet error rate testing with PRBS payloads
+//!
+//! The `ber` operation transmits sequence numbered pseudo-random binary sequence
+//! (PRBS-9 or PRBS-15) payloads, with a peer running `ber --receive` regenerating the
+//! expected payload for each sequence number and comparing received payloads bit-by-bit.
+//! Bit error rate (BER) and packet error rate (PER) are reported over the run in a
+//! [`BerInfo`], for characterising drivers at range.
+//!
+//! Received frames require an intact sequence number to be matched, frames with corrupt
+//! sequence numbers are counted as lost. Disable hardware CRC filtering where possible
+//! so corrupted frames are delivered for comparison.
+
+use std::time::Instant;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{Progress, ProgressOptions};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Length of the sequence number prefixing each BER payload
+const SEQ_LEN: usize = 4;
+
+/// Pseudo-random binary sequence
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prbs {
+    /// PRBS-9 (x^9 + x^5 + 1)
+    Prbs9,
+    /// PRBS-15 (x^15 + x^14 + 1)
+    Prbs15,
+}
+
+impl Prbs {
+    /// Register length and feedback taps (as bit offsets from zero)
+    fn taps(&self) -> (u32, u32, u32) {
+        match self {
+            Prbs::Prbs9 => (9, 8, 4),
+            Prbs::Prbs15 => (15, 14, 13),
+        }
+    }
+
+    /// Fill the provided buffer with the sequence starting from a state derived from `seed`
+    pub fn fill(&self, seed: u32, buff: &mut [u8]) {
+        let (len, a, b) = self.taps();
+        let mask = (1u32 << len) - 1;
+
+        // Zero states lock up the register, so seeds map into 1..=mask
+        let mut state = seed % mask + 1;
+
+        for byte in buff.iter_mut() {
+            let mut v = 0u8;
+            for _ in 0..8 {
+                let bit = ((state >> a) ^ (state >> b)) & 1;
+                state = ((state << 1) | bit) & mask;
+                v = (v << 1) | bit as u8;
+            }
+            *byte = v;
+        }
+    }
+}
+
+impl std::str::FromStr for Prbs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prbs9" => Ok(Prbs::Prbs9),
+            "prbs15" => Ok(Prbs::Prbs15),
+            _ => Err(format!(
+                "unrecognised pattern '{}', expected prbs9 or prbs15",
+                s
+            )),
+        }
+    }
+}
+
+/// Configuration for BER operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct BerOptions {
+    /// Receive and compare payloads from a transmitting peer
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub receive: bool,
+
+    /// Number of packets to send (or expect when receiving)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1000"))]
+    pub count: u32,
+
+    /// Packet size in bytes (including a 4 byte sequence number)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "32"))]
+    pub size: usize,
+
+    /// Payload pattern (prbs9 or prbs15)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "prbs9"))]
+    pub pattern: Prbs,
+
+    /// Period between transmitted packets (packets are sent back-to-back otherwise)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub period: Option<HumanDuration>,
+
+    /// Stop receiving after this period without packets
+    #[cfg_attr(feature = "clap", clap(long, default_value = "2s"))]
+    pub idle_timeout: HumanDuration,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for BerOptions {
+    fn default() -> Self {
+        Self {
+            receive: false,
+            count: 1000,
+            size: 32,
+            pattern: Prbs::Prbs9,
+            period: None,
+            idle_timeout: std::time::Duration::from_secs(2).into(),
+            power: None,
+            progress_options: ProgressOptions::default(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+impl BerOptions {
+    /// Encode the payload for the provided sequence number
+    pub fn payload(&self, seq: u32) -> Vec<u8> {
+        let mut data = vec![0u8; self.size.max(SEQ_LEN)];
+        data[..SEQ_LEN].copy_from_slice(&seq.to_be_bytes());
+        self.pattern.fill(seq, &mut data[SEQ_LEN..]);
+        data
+    }
+}
+
+/// Bit and packet error rate results
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BerInfo {
+    /// Packets sent (or expected, when receiving)
+    pub sent: u32,
+    /// Packets received, `None` when transmitting
+    pub received: Option<u32>,
+    /// Received packets containing bit errors
+    pub errored: u32,
+    /// Payload bits compared
+    pub bits: u64,
+    /// Payload bits in error (including bits missing from truncated packets)
+    pub bit_errors: u64,
+}
+
+impl BerInfo {
+    /// Bit error rate over received packets, where receiving
+    pub fn ber(&self) -> Option<f64> {
+        self.received
+            .filter(|_| self.bits > 0)
+            .map(|_| self.bit_errors as f64 / self.bits as f64)
+    }
+
+    /// Packet error rate (lost or errored packets), where receiving
+    pub fn per(&self) -> Option<f64> {
+        self.received.filter(|_| self.sent > 0).map(|r| {
+            let ok = r.saturating_sub(self.errored).min(self.sent);
+            1.0 - ok as f64 / self.sent as f64
+        })
+    }
+}
+
+impl core::fmt::Display for BerInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match (self.received, self.ber(), self.per()) {
+            (Some(r), ber, Some(per)) => write!(
+                f,
+                "received {}/{} ({} errored), {} bit errors in {} bits, BER {:.3e}, PER {:.3e}",
+                r,
+                self.sent,
+                self.errored,
+                self.bit_errors,
+                self.bits,
+                ber.unwrap_or_default(),
+                per
+            ),
+            _ => write!(f, "sent {}", self.sent),
+        }
+    }
+}
+
+/// Count bit errors between received and expected payloads, counting bytes missing from
+/// (or in excess of) the expected payload as errored
+fn bit_errors(received: &[u8], expected: &[u8]) -> u64 {
+    let diff: u64 = received
+        .iter()
+        .zip(expected)
+        .map(|(a, b)| (a ^ b).count_ones() as u64)
+        .sum();
+
+    diff + received.len().abs_diff(expected.len()) as u64 * 8
+}
+
+/// Run a BER test, transmitting or (with `--receive`) comparing PRBS payloads
+pub fn do_ber<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: BerOptions,
+) -> Result<BerInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    let info = match options.receive {
+        true => ber_receive(radio, buff, &options)?,
+        false => ber_transmit(radio, &options)?,
+    };
+
+    info!("BER test complete: {}", info);
+
+    Ok(info)
+}
+
+fn ber_transmit<T, E>(radio: &mut T, options: &BerOptions) -> Result<BerInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut progress = options.progress_options.reporter();
+    let total = Some(options.count as u64);
+
+    for seq in 0..options.count {
+        if seq > 0
+            && let Some(p) = &options.period
+        {
+            radio.delay_us(p.as_micros() as u32);
+        }
+
+        radio.do_transmit(&options.payload(seq), options.blocking_options.clone())?;
+
+        progress.update(&Progress::new("ber", seq as u64 + 1, total));
+    }
+    progress.finish(&Progress::new("ber", options.count as u64, total));
+
+    Ok(BerInfo {
+        sent: options.count,
+        ..Default::default()
+    })
+}
+
+fn ber_receive<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: &BerOptions,
+) -> Result<BerInfo, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    let mut progress = options.progress_options.reporter();
+    let total = Some(options.count as u64);
+    let mut info = BerInfo {
+        sent: options.count,
+        received: Some(0),
+        ..Default::default()
+    };
+    let mut received = 0;
+    let mut seen = vec![false; options.count as usize];
+    let mut last = Instant::now();
+
+    radio.start_receive()?;
+
+    while received < options.count && last.elapsed() < *options.idle_timeout {
+        if !radio.check_receive(true)? {
+            radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+            continue;
+        }
+
+        let (n, i) = radio.get_received(buff)?;
+        radio.start_receive()?;
+        last = Instant::now();
+
+        // Match frames by sequence number, ignoring duplicates and unrelated traffic
+        let seq = match buff.get(..SEQ_LEN) {
+            Some(s) if n >= SEQ_LEN => u32::from_be_bytes([s[0], s[1], s[2], s[3]]),
+            _ => continue,
+        };
+        match seen.get_mut(seq as usize) {
+            Some(s) if !*s => *s = true,
+            _ => {
+                debug!("Ignoring {} byte frame (seq: {})", n, seq);
+                continue;
+            }
+        }
+
+        let expected = options.payload(seq);
+        let errors = bit_errors(&buff[SEQ_LEN..n], &expected[SEQ_LEN..]);
+
+        debug!(
+            "Received {} ({} bytes, rssi: {}) with {} bit errors",
+            seq,
+            n,
+            i.rssi(),
+            errors
+        );
+
+        received += 1;
+        info.bits += ((expected.len() - SEQ_LEN) * 8) as u64;
+        info.bit_errors += errors;
+        if errors > 0 {
+            info.errored += 1;
+        }
+
+        progress.update(&Progress::new("ber", received as u64, total));
+    }
+    progress.finish(&Progress::new("ber", received as u64, total));
+
+    info.received = Some(received);
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Loopback radio flipping bits in selected frames
+    #[derive(Default)]
+    struct NoisyRadio {
+        frames: VecDeque<Vec<u8>>,
+    }
+
+    impl Transmit for NoisyRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let mut d = data.to_vec();
+            match data[3] {
+                // Two bit errors
+                2 => d[10] ^= 0x11,
+                // Truncated by a byte
+                5 => {
+                    d.pop();
+                }
+                // Lost
+                7 => return Ok(()),
+                _ => (),
+            }
+            self.frames.push_back(d);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for NoisyRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(!self.frames.is_empty())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.frames.pop_front().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(-90, 0)))
+        }
+    }
+
+    impl Power for NoisyRadio {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl DelayNs for NoisyRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn prbs_sequences() {
+        // PRBS-9 repeats every 511 bits
+        let mut a = [0u8; 128];
+        Prbs::Prbs9.fill(0, &mut a);
+        let bit = |b: &[u8], i: usize| (b[i / 8] >> (7 - i % 8)) & 1;
+        assert!((0..400).all(|i| bit(&a, i) == bit(&a, i + 511)));
+        assert!((1..511).all(|p| (0..32).any(|i| bit(&a, i) != bit(&a, i + p))));
+
+        // And is balanced (256 ones, 255 zeros)
+        let ones = (0..511).filter(|i| bit(&a, *i) == 1).count();
+        assert_eq!(ones, 256);
+
+        let mut b = [0u8; 16];
+        Prbs::Prbs15.fill(1, &mut b);
+        assert!(b.iter().any(|v| *v != 0));
+        assert_eq!("prbs15".parse(), Ok(Prbs::Prbs15));
+    }
+
+    #[test]
+    fn ber_loopback() {
+        let mut radio = NoisyRadio::default();
+        let mut buff = [0u8; 256];
+        let options = BerOptions {
+            count: 10,
+            size: 20,
+            pattern: Prbs::Prbs15,
+            idle_timeout: std::time::Duration::from_millis(10).into(),
+            ..Default::default()
+        };
+
+        let tx = do_ber(&mut radio, &mut buff, options.clone()).unwrap();
+        assert_eq!(tx.sent, 10);
+        assert_eq!(tx.ber(), None);
+
+        let rx = BerOptions {
+            receive: true,
+            ..options
+        };
+        let rx = do_ber(&mut radio, &mut buff, rx).unwrap();
+        assert_eq!(rx.received, Some(9));
+        assert_eq!(rx.errored, 2);
+        assert_eq!(rx.bits, 9 * 16 * 8);
+        assert_eq!(rx.bit_errors, 2 + 8);
+        assert!((rx.per().unwrap() - 0.3).abs() < 1e-9);
+    }
+}
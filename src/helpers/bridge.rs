@@ -0,0 +1,476 @@
+//! Backpressure-aware bridging between packet sources, radios and packet sinks
+//!
+//! [`do_bridge`] transmits payloads from a [`PacketSource`] and writes received frames to a
+//! [`PacketSink`], with the source and sink each run on their own thread behind a bounded
+//! [`BoundedQueue`] so slow (or blocking) endpoints never stall the radio polling loop.
+//! Where a queue is full frames are dropped (and counted) or the producer is blocked,
+//! according to the configured [`DropPolicy`]. Bridge operations (such as `zmq`) provide
+//! source and sink implementations and reuse this engine.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Instant, SystemTime};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+
+use super::{
+    DecodedFrame, DecoderRegistry, PacketSink, PacketSource, ReceiveQuality, ReceivedFrame,
+    squelched,
+};
+use crate::{
+    Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Behaviour when a bridge queue is full
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum DropPolicy {
+    /// Drop the incoming frame
+    Newest,
+    /// Drop the oldest queued frame to make space
+    Oldest,
+    /// Block the producer until space is available (not applied to the radio)
+    Block,
+}
+
+/// Options for bridging a radio with a packet source and sink
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct BridgeOptions {
+    /// Maximum number of frames queued in each direction
+    #[cfg_attr(feature = "clap", clap(long, default_value = "64"))]
+    pub queue_depth: usize,
+
+    /// Behaviour when a queue is full
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "oldest"))]
+    pub drop_policy: DropPolicy,
+
+    /// Suppress forwarding of frames received below this RSSI (dBm), along with zero-length receptions
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub squelch: Option<i16>,
+
+    /// Print bridge statistics at this interval
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub stats_interval: Option<HumanDuration>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for BridgeOptions {
+    fn default() -> Self {
+        Self {
+            queue_depth: 64,
+            drop_policy: DropPolicy::Oldest,
+            squelch: None,
+            stats_interval: None,
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    dropped: u64,
+}
+
+/// Bounded multi-producer queue applying a [`DropPolicy`] when full
+pub struct BoundedQueue<T> {
+    state: Mutex<QueueState<T>>,
+    cv: Condvar,
+    depth: usize,
+    policy: DropPolicy,
+}
+
+impl<T> BoundedQueue<T> {
+    /// Create a queue holding up to `depth` items
+    pub fn new(depth: usize, policy: DropPolicy) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                items: VecDeque::with_capacity(depth),
+                closed: false,
+                dropped: 0,
+            }),
+            cv: Condvar::new(),
+            depth: depth.max(1),
+            policy,
+        }
+    }
+
+    /// Push an item, returning false where an item was dropped (or the queue is closed)
+    pub fn push(&self, item: T) -> bool {
+        self.push_with(item, self.policy)
+    }
+
+    fn push_with(&self, item: T, policy: DropPolicy) -> bool {
+        let mut s = self.state.lock().unwrap();
+
+        while policy == DropPolicy::Block && !s.closed && s.items.len() >= self.depth {
+            s = self.cv.wait(s).unwrap();
+        }
+        if s.closed {
+            return false;
+        }
+
+        let mut accepted = true;
+        if s.items.len() >= self.depth {
+            s.dropped += 1;
+            match policy {
+                DropPolicy::Oldest => {
+                    s.items.pop_front();
+                    accepted = false;
+                }
+                _ => return false,
+            }
+        }
+
+        s.items.push_back(item);
+        self.cv.notify_all();
+        accepted
+    }
+
+    /// Pop an item without blocking
+    pub fn try_pop(&self) -> Option<T> {
+        let mut s = self.state.lock().unwrap();
+        let item = s.items.pop_front();
+        if item.is_some() {
+            self.cv.notify_all();
+        }
+        item
+    }
+
+    /// Pop an item, blocking until one is available or the queue is closed and empty
+    pub fn pop(&self) -> Option<T> {
+        let mut s = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = s.items.pop_front() {
+                self.cv.notify_all();
+                return Some(item);
+            }
+            if s.closed {
+                return None;
+            }
+            s = self.cv.wait(s).unwrap();
+        }
+    }
+
+    /// Close the queue, waking blocked producers and consumers
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.cv.notify_all();
+    }
+
+    /// Check whether the queue is closed with no remaining items
+    pub fn finished(&self) -> bool {
+        let s = self.state.lock().unwrap();
+        s.closed && s.items.is_empty()
+    }
+
+    /// Number of queued items
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    /// Check whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of items dropped due to a full queue
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+/// Bridge statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BridgeStats {
+    /// Payloads read from the source
+    pub from_source: u64,
+    /// Payloads transmitted by the radio
+    pub transmitted: u64,
+    /// Payloads dropped awaiting transmission
+    pub tx_dropped: u64,
+    /// Frames received by the radio
+    pub received: u64,
+    /// Received frames dropped awaiting the sink
+    pub rx_dropped: u64,
+    /// Frames written to the sink
+    pub written: u64,
+}
+
+impl core::fmt::Display for BridgeStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "tx {}/{} ({} dropped), rx {}/{} ({} dropped)",
+            self.transmitted,
+            self.from_source,
+            self.tx_dropped,
+            self.written,
+            self.received,
+            self.rx_dropped
+        )
+    }
+}
+
+/// Bridge a radio with a packet source and sink, transmitting source payloads and writing
+/// received frames to the sink until the source is exhausted
+pub fn do_bridge<T, I, E, S, K>(
+    radio: &mut T,
+    mut source: S,
+    mut sink: K,
+    options: BridgeOptions,
+) -> Result<BridgeStats, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    S: PacketSource + Send + 'static,
+    K: PacketSink + 'static,
+{
+    let tx_queue = Arc::new(BoundedQueue::<Vec<u8>>::new(
+        options.queue_depth,
+        options.drop_policy,
+    ));
+    let rx_queue = Arc::new(BoundedQueue::<ReceivedFrame>::new(
+        options.queue_depth,
+        options.drop_policy,
+    ));
+    let from_source = Arc::new(AtomicU64::new(0));
+    let written = Arc::new(AtomicU64::new(0));
+
+    // Source thread, blocking on the source and (where configured) the queue
+    let (q, n) = (tx_queue.clone(), from_source.clone());
+    std::thread::spawn(move || {
+        loop {
+            match source.next_payload() {
+                Ok(Some(p)) => {
+                    n.fetch_add(1, Ordering::Relaxed);
+                    q.push(p);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("Bridge source error: {:?}", e);
+                    break;
+                }
+            }
+        }
+        q.close();
+    });
+
+    // Sink thread, decoding and writing received frames
+    let (q, n) = (rx_queue.clone(), written.clone());
+    let sink_thread = std::thread::spawn(move || {
+        let registry = DecoderRegistry::default();
+        let selected = ["auto".to_string()];
+
+        while let Some(f) = q.pop() {
+            let d = DecodedFrame::decode(&f, &registry, &selected);
+            match sink.write(&d) {
+                Ok(_) => n.fetch_add(1, Ordering::Relaxed),
+                Err(e) => {
+                    debug!("Bridge sink error: {:?}", e);
+                    continue;
+                }
+            };
+        }
+        let _ = sink.flush();
+    });
+
+    let mut stats = BridgeStats::default();
+    let mut last_stats = Instant::now();
+    let mut buff = [0u8; 1024];
+
+    let update = |s: &mut BridgeStats| {
+        s.from_source = from_source.load(Ordering::Relaxed);
+        s.tx_dropped = tx_queue.dropped();
+        s.rx_dropped = rx_queue.dropped();
+        s.written = written.load(Ordering::Relaxed);
+    };
+
+    radio.start_receive()?;
+
+    loop {
+        // Queue received frames for the sink, the radio is never blocked on the sink
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(&mut buff)?;
+            stats.received += 1;
+
+            let frame = ReceivedFrame {
+                timestamp: SystemTime::now(),
+                rssi: i.rssi(),
+                data: buff[..n].to_vec(),
+                quality: ReceiveQuality::from_info(&i),
+                info: format!("{:?}", i),
+            };
+            let policy = match options.drop_policy {
+                DropPolicy::Block => DropPolicy::Newest,
+                p => p,
+            };
+            if squelched(options.squelch, frame.rssi, n) {
+                debug!("Squelched {} byte frame with rssi: {}", n, frame.rssi);
+            } else if !rx_queue.push_with(frame, policy) {
+                debug!("Bridge sink queue full, dropped frame");
+            }
+
+            radio.start_receive()?;
+        }
+
+        // Transmit queued payloads
+        match tx_queue.try_pop() {
+            Some(data) => {
+                radio.do_transmit(&data, options.blocking_options.clone())?;
+                stats.transmitted += 1;
+                radio.start_receive()?;
+            }
+            None if tx_queue.finished() => break,
+            None => (),
+        }
+
+        if let Some(i) = options.stats_interval
+            && last_stats.elapsed() >= *i
+        {
+            update(&mut stats);
+            info!("Bridge: {}", stats);
+            last_stats = Instant::now();
+        }
+
+        radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+
+    rx_queue.close();
+    let _ = sink_thread.join();
+
+    update(&mut stats);
+    info!("Bridge complete: {}", stats);
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+    use crate::helpers::GeneratorSource;
+
+    #[test]
+    fn bounded_queue_policies() {
+        let q = BoundedQueue::new(2, DropPolicy::Newest);
+        assert!(q.push(1) && q.push(2));
+        assert!(!q.push(3));
+        assert_eq!(
+            (q.try_pop(), q.try_pop(), q.dropped()),
+            (Some(1), Some(2), 1)
+        );
+
+        let q = BoundedQueue::new(2, DropPolicy::Oldest);
+        assert!(q.push(1) && q.push(2));
+        assert!(!q.push(3));
+        assert_eq!(
+            (q.try_pop(), q.try_pop(), q.dropped()),
+            (Some(2), Some(3), 1)
+        );
+
+        // Blocked producers resume as the consumer drains the queue
+        let q = Arc::new(BoundedQueue::new(1, DropPolicy::Block));
+        let p = q.clone();
+        let t = std::thread::spawn(move || {
+            for i in 0..10 {
+                p.push(i);
+            }
+            p.close();
+        });
+        let items: Vec<_> = std::iter::from_fn(|| q.pop()).collect();
+        t.join().unwrap();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+        assert!(q.finished());
+    }
+
+    /// Loopback radio, receiving each transmitted frame
+    #[derive(Default)]
+    struct LoopRadio {
+        last: Option<Vec<u8>>,
+    }
+
+    impl Transmit for LoopRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.last = Some(data.to_vec());
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for LoopRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().unwrap();
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for LoopRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    struct Collect(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl PacketSink for Collect {
+        fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+            self.0.lock().unwrap().push(frame.data.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bridge_source_to_sink() {
+        let frames = Arc::new(Mutex::new(vec![]));
+        let options = BridgeOptions {
+            drop_policy: DropPolicy::Block,
+            ..Default::default()
+        };
+
+        let stats = do_bridge(
+            &mut LoopRadio::default(),
+            GeneratorSource::new(4, Some(5)),
+            Collect(frames.clone()),
+            options,
+        )
+        .unwrap();
+
+        assert_eq!(
+            (stats.from_source, stats.transmitted, stats.tx_dropped),
+            (5, 5, 0)
+        );
+        assert_eq!((stats.received, stats.written), (5, 5));
+        assert_eq!(frames.lock().unwrap()[4], vec![0, 0, 0, 4]);
+    }
+}
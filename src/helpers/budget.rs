@@ -0,0 +1,174 @@
+//! Live airtime budget display for duty-cycle limited operations
+//!
+//! With `--duty-report`, [`BudgetReport`] wraps a [`DutyCycleLimiter`] and logs the
+//! [`AirtimeBudget`] (percentage of the window used and allowance remaining) at the
+//! configured interval as frames are transmitted, so transmit-heavy operations show how
+//! close they are to being throttled. Report intervals are timed with the limiter's
+//! clock.
+
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::clock::Clock;
+use crate::duty::{AirtimeBudget, DutyCycleLimiter};
+use crate::{Power, Receive, Transmit};
+
+/// Duty-cycle limiter wrapper logging the airtime budget at an interval
+pub struct BudgetReport<T, C, const N: usize = 60> {
+    radio: DutyCycleLimiter<T, C, N>,
+    interval: Option<Duration>,
+    last: Option<u64>,
+}
+
+impl<T, C: Clock, const N: usize> BudgetReport<T, C, N> {
+    /// Wrap a limiter, reporting at the provided interval (or never, where `None`)
+    pub fn new(radio: DutyCycleLimiter<T, C, N>, interval: Option<Duration>) -> Self {
+        Self {
+            radio,
+            interval,
+            last: None,
+        }
+    }
+
+    /// Log the airtime budget where the report interval has elapsed, returning the
+    /// budget where reported
+    pub fn report(&mut self) -> Option<AirtimeBudget> {
+        let interval = self.interval?;
+        let now = self.radio.clock().now_us();
+        if self
+            .last
+            .is_some_and(|t| now.saturating_sub(t) < interval.as_micros() as u64)
+        {
+            return None;
+        }
+        self.last = Some(now);
+
+        let budget = self.radio.budget();
+        info!("Duty cycle: {}", budget);
+        Some(budget)
+    }
+
+    /// Fetch the inner limiter
+    pub fn inner(&mut self) -> &mut DutyCycleLimiter<T, C, N> {
+        &mut self.radio
+    }
+
+    /// Release the inner limiter
+    pub fn free(self) -> DutyCycleLimiter<T, C, N> {
+        self.radio
+    }
+}
+
+impl<T, C: Clock, const N: usize> Transmit for BudgetReport<T, C, N>
+where
+    DutyCycleLimiter<T, C, N>: Transmit,
+{
+    type Error = <DutyCycleLimiter<T, C, N> as Transmit>::Error;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.radio.start_transmit(data)?;
+        self.report();
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit()
+    }
+}
+
+impl<T, C, const N: usize> Receive for BudgetReport<T, C, N>
+where
+    DutyCycleLimiter<T, C, N>: Receive,
+{
+    type Info = <DutyCycleLimiter<T, C, N> as Receive>::Info;
+    type Error = <DutyCycleLimiter<T, C, N> as Receive>::Error;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive()
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio.check_receive(restart)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff)
+    }
+}
+
+impl<T, C, const N: usize> Power for BudgetReport<T, C, N>
+where
+    DutyCycleLimiter<T, C, N>: Power,
+{
+    type Error = <DutyCycleLimiter<T, C, N> as Power>::Error;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power)
+    }
+}
+
+impl<T: DelayNs, C, const N: usize> DelayNs for BudgetReport<T, C, N> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::duty::DutyCycleOptions;
+
+    /// Radio accepting all transmissions
+    struct Sink;
+
+    impl Transmit for Sink {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl DelayNs for Sink {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn budget_report() {
+        let clock = VirtualClock::new();
+        let options = DutyCycleOptions {
+            duty_cycle: Some(1.0),
+            duty_window: Duration::from_secs(10),
+            duty_report: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let limiter: DutyCycleLimiter<_, _, 10> =
+            DutyCycleLimiter::new(Sink, &clock, options.clone(), 8_000);
+        let mut r = BudgetReport::new(limiter, options.duty_report);
+
+        // Reported after the first frame, then only once the interval elapses
+        r.start_transmit(&[0u8; 10]).unwrap();
+        assert!(r.report().is_none());
+        r.start_transmit(&[0u8; 10]).unwrap();
+
+        let b = r.inner().budget();
+        assert_eq!(b.used_us, 20_000);
+        assert_eq!(b.remaining_percent(), Some(80.0));
+        assert_eq!(
+            b.to_string(),
+            "airtime 0.020 s (0.200% of window), 0.080 s (80.0%) of allowance remaining"
+        );
+    }
+}
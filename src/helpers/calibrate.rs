@@ -0,0 +1,389 @@
+//! Turnaround delay calibration for echo and ping-pong
+//!
+//! Echo responders must delay responses long enough for the initiator to switch from
+//! transmit to receive, and initiators must wait long enough for responses to arrive.
+//! Rather than tuning `--delay` and `--timeout` by trial and error, `calibrate` binary
+//! searches the minimum response delay at which probes are reliably echoed by a peer
+//! running `calibrate --respond`, with each probe carrying the delay the responder should
+//! apply. The measured turnaround (plus a margin) can be saved and applied to `echo` and
+//! `ping-pong` with `--calibration`.
+
+use std::time::Instant;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+use byteorder::{ByteOrder, NetworkEndian};
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::json;
+use super::{EchoOptions, PingPongOptions, Shutdown};
+use crate::{
+    Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingReceive, BlockingTransmit},
+};
+
+/// Calibration probe marker
+const PROBE_MAGIC: u8 = 0xca;
+
+/// Calibration probe length (marker, index, response delay in microseconds)
+const PROBE_LEN: usize = 9;
+
+/// Configuration for turnaround calibration
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CalibrateOptions {
+    /// Respond to calibration probes from a peer running `calibrate`
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub respond: bool,
+
+    /// Minimum response delay to probe
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0us"))]
+    pub min: HumanDuration,
+
+    /// Maximum response delay to probe
+    #[cfg_attr(feature = "clap", clap(long, default_value = "50ms"))]
+    pub max: HumanDuration,
+
+    /// Search resolution
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100us"))]
+    pub resolution: HumanDuration,
+
+    /// Probes sent at each delay
+    #[cfg_attr(feature = "clap", clap(long, default_value = "5"))]
+    pub probes: u32,
+
+    /// Echoed probes required for a delay to be considered reliable
+    #[cfg_attr(feature = "clap", clap(long, default_value = "5"))]
+    pub required: u32,
+
+    /// Margin (in percent) added to the measured turnaround and round trip time
+    #[cfg_attr(feature = "clap", clap(long, default_value = "25"))]
+    pub margin: u32,
+
+    /// File to save the calibration to (as JSON), for use with `--calibration`
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub save: Option<String>,
+
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for CalibrateOptions {
+    fn default() -> Self {
+        Self {
+            respond: false,
+            min: std::time::Duration::ZERO.into(),
+            max: std::time::Duration::from_millis(50).into(),
+            resolution: std::time::Duration::from_micros(100).into(),
+            probes: 5,
+            required: 5,
+            margin: 25,
+            save: None,
+            shutdown: Shutdown::default(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+/// Calibrated turnaround timing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationInfo {
+    /// Minimum reliable response delay, in microseconds
+    pub turnaround_us: u64,
+    /// Maximum round trip time at the minimum reliable delay, in microseconds
+    pub rtt_us: u64,
+    /// Echo response delay (turnaround with margin), in microseconds
+    pub echo_delay_us: u64,
+    /// Response timeout for an echo peer using the calibrated delay, in microseconds
+    pub timeout_us: u64,
+    /// Total probes sent during calibration
+    pub probes: u32,
+}
+
+impl CalibrationInfo {
+    /// Compute calibrated delays from the measured turnaround and round trip time
+    pub fn new(turnaround_us: u64, rtt_us: u64, margin: u32, probes: u32) -> Self {
+        let with_margin = |v: u64| v * (100 + margin as u64) / 100;
+        let echo_delay_us = with_margin(turnaround_us);
+
+        Self {
+            turnaround_us,
+            rtt_us,
+            echo_delay_us,
+            // Responses are delayed by the additional margin over the measured turnaround
+            timeout_us: with_margin(rtt_us + echo_delay_us - turnaround_us),
+            probes,
+        }
+    }
+
+    /// Load a saved calibration
+    pub fn load(path: &str) -> Result<Self, std::io::Error> {
+        let d = std::fs::read(path)?;
+        json::from_slice(&d)
+    }
+
+    /// Save the calibration for later use
+    pub fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let d = json::to_vec_pretty(self)?;
+        std::fs::write(path, d)
+    }
+
+    /// Apply the calibrated response delay to echo options
+    pub fn apply_echo(&self, options: &mut EchoOptions) {
+        options.delay = std::time::Duration::from_micros(self.echo_delay_us).into();
+    }
+
+    /// Apply the calibrated response timeout to ping-pong options
+    pub fn apply_ping_pong(&self, options: &mut PingPongOptions) {
+        options.blocking_options.timeout = std::time::Duration::from_micros(self.timeout_us);
+    }
+}
+
+impl core::fmt::Display for CalibrationInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "turnaround: {}us (rtt {}us), echo delay {}us, timeout {}us ({} probes)",
+            self.turnaround_us, self.rtt_us, self.echo_delay_us, self.timeout_us, self.probes
+        )
+    }
+}
+
+/// Parse a calibration probe, returning the probe index and requested delay
+fn parse_probe(data: &[u8]) -> Option<(u32, u32)> {
+    match data {
+        [PROBE_MAGIC, ..] if data.len() == PROBE_LEN => Some((
+            NetworkEndian::read_u32(&data[1..5]),
+            NetworkEndian::read_u32(&data[5..9]),
+        )),
+        _ => None,
+    }
+}
+
+/// Probe a single response delay, returning the maximum round trip time in microseconds
+/// where sufficient probes were echoed
+fn probe_delay<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    delay_us: u32,
+    index: &mut u32,
+    options: &CalibrateOptions,
+) -> Result<Option<u64>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut blocking = options.blocking_options.clone();
+    blocking.timeout += std::time::Duration::from_micros(delay_us as u64);
+
+    let mut echoed = 0;
+    let mut rtt_us = 0;
+
+    for _ in 0..options.probes {
+        let mut probe = [0u8; PROBE_LEN];
+        probe[0] = PROBE_MAGIC;
+        NetworkEndian::write_u32(&mut probe[1..5], *index);
+        NetworkEndian::write_u32(&mut probe[5..9], delay_us);
+        *index = index.wrapping_add(1);
+
+        let sent_at = Instant::now();
+        radio.do_transmit(&probe, options.blocking_options.clone())?;
+
+        match radio.do_receive(buff, blocking.clone()) {
+            Ok((n, _)) if buff[..n] == probe[..] => {
+                echoed += 1;
+                rtt_us = rtt_us.max(sent_at.elapsed().as_micros() as u64);
+            }
+            Ok((n, _)) => debug!("Invalid probe response ({} bytes)", n),
+            Err(BlockingError::Timeout) => debug!("Timeout awaiting probe response"),
+            Err(e) => return Err(e),
+        }
+    }
+
+    match echoed >= options.required {
+        true => Ok(Some(rtt_us)),
+        false => Ok(None),
+    }
+}
+
+/// Calibrate the minimum reliable response delay against a peer running
+/// `calibrate --respond`, returning `None` where probes are not echoed at the maximum delay
+pub fn do_calibrate<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: CalibrateOptions,
+) -> Result<Option<CalibrationInfo>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let step = options.resolution.as_micros().max(1) as u32;
+    let mut lo = options.min.as_micros() as u32 / step;
+    let mut hi = (options.max.as_micros() as u32).div_ceil(step);
+    let mut index = 0;
+
+    // Confirm probes are echoed at all before searching
+    let mut rtt_us = match probe_delay(radio, buff, hi * step, &mut index, &options)? {
+        Some(rtt) => rtt,
+        None => {
+            warn!("No reliable responses at maximum delay {}", options.max);
+            return Ok(None);
+        }
+    };
+
+    // Find the smallest reliable delay, in resolution steps
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let res = probe_delay(radio, buff, mid * step, &mut index, &options)?;
+
+        debug!("Probe delay {}us: {:?}", mid * step, res);
+
+        match res {
+            Some(rtt) => {
+                hi = mid;
+                rtt_us = rtt;
+            }
+            None => lo = mid + 1,
+        }
+
+        if options.shutdown.is_triggered() {
+            break;
+        }
+    }
+
+    let info = CalibrationInfo::new((hi * step) as u64, rtt_us, options.margin, index);
+
+    info!("Calibration complete: {}", info);
+
+    if let Some(path) = &options.save {
+        info.save(path).expect("Error saving calibration");
+    }
+
+    Ok(Some(info))
+}
+
+/// Respond to calibration probes, echoing each after the delay it requests, returning the
+/// number of probes echoed on shutdown
+pub fn do_calibrate_respond<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: CalibrateOptions,
+) -> Result<u32, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut echoed = 0;
+
+    while !options.shutdown.is_triggered() {
+        let n = match radio.do_receive(buff, options.blocking_options.clone()) {
+            Ok((n, _)) => n,
+            Err(BlockingError::Timeout) => continue,
+            Err(e) => return Err(e),
+        };
+
+        let (index, delay_us) = match parse_probe(&buff[..n]) {
+            Some(p) => p,
+            None => {
+                debug!("Ignoring non-probe frame ({} bytes)", n);
+                continue;
+            }
+        };
+
+        debug!("Probe {} (delay {}us)", index, delay_us);
+
+        radio.delay_us(delay_us);
+        radio.do_transmit(&buff[..n], options.blocking_options.clone())?;
+        echoed += 1;
+    }
+
+    info!("Echoed {} calibration probes", echoed);
+
+    Ok(echoed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Loopback radio, echoing probes requesting at least the turnaround delay
+    struct TurnaroundRadio {
+        turnaround_us: u32,
+        last: Option<Vec<u8>>,
+    }
+
+    impl Transmit for TurnaroundRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.last = Some(data.to_vec())
+                .filter(|d| parse_probe(d).is_some_and(|(_, d)| d >= self.turnaround_us));
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for TurnaroundRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().unwrap();
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for TurnaroundRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn turnaround_calibration() {
+        let mut radio = TurnaroundRadio {
+            turnaround_us: 2_050,
+            last: None,
+        };
+        let mut buff = [0u8; 64];
+        let options = CalibrateOptions {
+            max: std::time::Duration::from_millis(10).into(),
+            ..Default::default()
+        };
+
+        let info = do_calibrate(&mut radio, &mut buff, options.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.turnaround_us, 2_100);
+        assert_eq!(info.echo_delay_us, 2_625);
+
+        // No response within the maximum delay
+        radio.turnaround_us = 20_000;
+        assert_eq!(do_calibrate(&mut radio, &mut buff, options), Ok(None));
+    }
+}
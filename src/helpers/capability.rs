@@ -0,0 +1,213 @@
+//! Capability wrapper for radios without [`Power`] or [`Rssi`] support
+//!
+//! Helper operations are bounded on `Power` and `Rssi` in addition to `Transmit` and
+//! `Receive`, so [`Capabilities`] wraps radios lacking either trait, forwarding power and
+//! RSSI only where enabled with [`Capabilities::with_power`] / [`Capabilities::with_rssi`]
+//! and otherwise failing with [`CapabilityError::Unsupported`]. Transmit, receive and echo
+//! run unchanged (unless `--power` is requested), see
+//! [`do_operation_basic`](super::do_operation_basic).
+
+use core::fmt::Debug;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::{Power, Receive, Rssi, Transmit};
+
+/// Errors from a radio wrapped in [`Capabilities`]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum CapabilityError<E> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(E),
+    /// Operation requires a capability (`power` or `rssi`) not supported by the radio
+    #[cfg_attr(feature = "thiserror", error("Unsupported: {0}"))]
+    Unsupported(&'static str),
+}
+
+type SetPower<T, E> = fn(&mut T, i8) -> Result<(), E>;
+type PollRssi<T, E> = fn(&mut T) -> Result<i16, E>;
+
+/// Capabilities wraps a `Transmit + Receive` radio, implementing [`Power`] and [`Rssi`]
+/// where enabled
+pub struct Capabilities<T, E> {
+    radio: T,
+    power: Option<SetPower<T, E>>,
+    rssi: Option<PollRssi<T, E>>,
+}
+
+impl<T, E> Capabilities<T, E>
+where
+    T: Transmit<Error = E> + Receive<Error = E>,
+    E: Debug,
+{
+    /// Wrap a radio without power or RSSI support
+    pub fn new(radio: T) -> Self {
+        Self {
+            radio,
+            power: None,
+            rssi: None,
+        }
+    }
+
+    /// Forward power configuration to the wrapped radio
+    pub fn with_power(mut self) -> Self
+    where
+        T: Power<Error = E>,
+    {
+        self.power = Some(|r, p| r.set_power(p));
+        self
+    }
+
+    /// Forward RSSI polling to the wrapped radio
+    pub fn with_rssi(mut self) -> Self
+    where
+        T: Rssi<Error = E>,
+    {
+        self.rssi = Some(|r| r.poll_rssi());
+        self
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+}
+
+impl<T, E> Transmit for Capabilities<T, E>
+where
+    T: Transmit<Error = E>,
+    E: Debug,
+{
+    type Error = CapabilityError<E>;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.radio
+            .start_transmit(data)
+            .map_err(CapabilityError::Radio)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit().map_err(CapabilityError::Radio)
+    }
+}
+
+impl<T, E> Receive for Capabilities<T, E>
+where
+    T: Receive<Error = E>,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = CapabilityError<E>;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive().map_err(CapabilityError::Radio)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio
+            .check_receive(restart)
+            .map_err(CapabilityError::Radio)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio
+            .get_received(buff)
+            .map_err(CapabilityError::Radio)
+    }
+}
+
+impl<T, E: Debug> Power for Capabilities<T, E> {
+    type Error = CapabilityError<E>;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        match self.power {
+            Some(f) => f(&mut self.radio, power).map_err(CapabilityError::Radio),
+            None => Err(CapabilityError::Unsupported("power")),
+        }
+    }
+}
+
+impl<T, E: Debug> Rssi for Capabilities<T, E> {
+    type Error = CapabilityError<E>;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        match self.rssi {
+            Some(f) => f(&mut self.radio).map_err(CapabilityError::Radio),
+            None => Err(CapabilityError::Unsupported("rssi")),
+        }
+    }
+}
+
+impl<T: DelayNs, E> DelayNs for Capabilities<T, E> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio supporting only transmit, receive and power
+    struct TxRxRadio {
+        power: i8,
+    }
+
+    impl Transmit for TxRxRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for TxRxRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Err(())
+        }
+    }
+
+    impl Power for TxRxRadio {
+        type Error = ();
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            self.power = power;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn optional_capabilities() {
+        let mut r = Capabilities::new(TxRxRadio { power: 0 });
+        assert_eq!(r.set_power(10), Err(CapabilityError::Unsupported("power")));
+        assert_eq!(r.poll_rssi(), Err(CapabilityError::Unsupported("rssi")));
+        assert_eq!(r.check_transmit(), Ok(true));
+
+        let mut r = r.with_power();
+        assert_eq!(r.set_power(10), Ok(()));
+        assert_eq!(r.inner().power, 10);
+        assert_eq!(r.poll_rssi(), Err(CapabilityError::Unsupported("rssi")));
+    }
+}
@@ -0,0 +1,517 @@
+//! Rotated capture files with a persistent index, and index search
+//!
+//! With `--pcap-rotate` captures are written to numbered files (`NAME-0000.EXT`,
+//! `NAME-0001.EXT`, ...) alongside an index (`NAME.idx`) holding one JSON line per
+//! completed file, summarising timestamps, frame counts and RSSI. The `capture ls` and
+//! `capture grep` operations use the index to list capture files and to locate frames
+//! matching filters, only reading files whose summaries may contain matches, while
+//! `capture anonymize` prepares captures for sharing (see [`Anonymizer`](super::Anonymizer)).
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use humantime::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use super::json;
+#[cfg(feature = "clap")]
+use super::parse_hex;
+use super::{
+    CaptureAnonymizeOptions, CaptureReader, CapturedPacket, DecodedFrame, HexDump, PacketSink,
+    PcapOptions,
+};
+
+/// Path of the `n`th rotated capture file for a base capture path
+pub fn rotated_path(base: &Path, n: u32) -> PathBuf {
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match base.extension() {
+        Some(ext) => format!("{}-{:04}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{:04}", stem, n),
+    };
+    base.with_file_name(name)
+}
+
+/// Path of the capture index for a base capture path
+pub fn index_path(base: &Path) -> PathBuf {
+    base.with_extension("idx")
+}
+
+/// Capture index entry, summarising a single capture file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaptureIndexEntry {
+    /// Capture file name, relative to the index
+    pub file: String,
+    /// Number of frames captured
+    pub frames: u64,
+    /// Total frame bytes captured
+    pub bytes: u64,
+    /// Timestamp of the first frame (microseconds since the unix epoch)
+    pub first_us: Option<u64>,
+    /// Timestamp of the last frame (microseconds since the unix epoch)
+    pub last_us: Option<u64>,
+    /// Minimum frame RSSI (dBm)
+    pub rssi_min: Option<i16>,
+    /// Maximum frame RSSI (dBm)
+    pub rssi_max: Option<i16>,
+    /// Mean frame RSSI (dBm)
+    pub rssi_mean: Option<f32>,
+}
+
+impl CaptureIndexEntry {
+    /// Create an empty entry for a capture file
+    pub fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            frames: 0,
+            bytes: 0,
+            first_us: None,
+            last_us: None,
+            rssi_min: None,
+            rssi_max: None,
+            rssi_mean: None,
+        }
+    }
+
+    /// Update the summary with a captured frame
+    pub fn update(&mut self, frame: &DecodedFrame) {
+        self.frames += 1;
+        self.bytes += frame.data.len() as u64;
+
+        self.first_us.get_or_insert(frame.timestamp_us);
+        self.last_us = Some(frame.timestamp_us);
+
+        let rssi = frame.rssi;
+        self.rssi_min = Some(self.rssi_min.map_or(rssi, |r| r.min(rssi)));
+        self.rssi_max = Some(self.rssi_max.map_or(rssi, |r| r.max(rssi)));
+        let mean = self.rssi_mean.unwrap_or_default();
+        self.rssi_mean = Some(mean + (rssi as f32 - mean) / self.frames as f32);
+    }
+}
+
+impl core::fmt::Display for CaptureIndexEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}: {} frames, {} bytes",
+            self.file, self.frames, self.bytes
+        )?;
+
+        if let (Some(first), Some(last)) = (self.first_us, self.last_us) {
+            write!(f, ", {} to {}", format_us(first), format_us(last))?;
+        }
+        if let (Some(min), Some(mean), Some(max)) = (self.rssi_min, self.rssi_mean, self.rssi_max) {
+            write!(f, ", rssi {}/{:.1}/{} dBm", min, mean, max)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a timestamp (microseconds since the unix epoch) as RFC3339
+fn format_us(us: u64) -> humantime::Rfc3339Timestamp {
+    humantime::format_rfc3339_micros(SystemTime::UNIX_EPOCH + std::time::Duration::from_micros(us))
+}
+
+/// Read a capture index
+pub fn read_capture_index<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<CaptureIndexEntry>, std::io::Error> {
+    let r = BufReader::new(File::open(path)?);
+
+    r.lines()
+        .filter(|l| !matches!(l, Ok(l) if l.trim().is_empty()))
+        .map(|l| json::from_str(&l?).map_err(std::io::Error::other))
+        .collect()
+}
+
+/// Rotating capture writer, starting a new capture file at a fixed interval and indexing
+/// each completed file
+///
+/// Files are indexed once complete, on rotation or when the writer is dropped.
+pub struct RotatingPcapSink {
+    options: PcapOptions,
+    base: PathBuf,
+    interval: std::time::Duration,
+    index: File,
+    next: u32,
+    current: Option<(Box<dyn PacketSink>, Instant, CaptureIndexEntry)>,
+}
+
+impl RotatingPcapSink {
+    /// Create a rotating capture for the provided base path, creating (or truncating)
+    /// the capture index
+    pub fn new<P: Into<PathBuf>>(
+        base: P,
+        interval: std::time::Duration,
+        options: PcapOptions,
+    ) -> Result<Self, std::io::Error> {
+        let base = base.into();
+        let index = File::create(index_path(&base))?;
+
+        Ok(Self {
+            options,
+            base,
+            interval,
+            index,
+            next: 0,
+            current: None,
+        })
+    }
+
+    /// Close the current capture file (if any) and start the next
+    fn rotate(&mut self) -> Result<(), std::io::Error> {
+        self.close()?;
+
+        let path = rotated_path(&self.base, self.next);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        debug!("Starting capture file {}", name);
+
+        let entry = CaptureIndexEntry::new(&name);
+        let sink = self.options.sink(File::create(&path)?)?;
+        self.current = Some((sink, Instant::now(), entry));
+        self.next += 1;
+
+        Ok(())
+    }
+
+    /// Close the current capture file, writing its index entry
+    fn close(&mut self) -> Result<(), std::io::Error> {
+        let (mut sink, _, entry) = match self.current.take() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        sink.flush()?;
+
+        json::to_writer(&mut self.index, &entry)?;
+        writeln!(self.index)?;
+        self.index.flush()
+    }
+}
+
+impl PacketSink for RotatingPcapSink {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        // Start a new file if none is open or the rotation interval has elapsed
+        let rotate = match &self.current {
+            Some((_, opened, _)) => opened.elapsed() >= self.interval,
+            None => true,
+        };
+        if rotate {
+            self.rotate()?;
+        }
+
+        match self.current.as_mut() {
+            Some((sink, _, entry)) => {
+                entry.update(frame);
+                sink.write(frame)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        match self.current.as_mut() {
+            Some((sink, _, _)) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RotatingPcapSink {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Configuration for Capture operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CaptureOptions {
+    #[cfg_attr(feature = "clap", clap(subcommand))]
+    pub command: CaptureCommand,
+}
+
+/// Capture index operations
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub enum CaptureCommand {
+    #[cfg_attr(feature = "clap", clap(name = "ls"))]
+    /// List indexed capture files
+    Ls(CaptureLsOptions),
+
+    #[cfg_attr(feature = "clap", clap(name = "grep"))]
+    /// Locate frames matching filters across indexed capture files
+    Grep(CaptureGrepOptions),
+
+    #[cfg_attr(feature = "clap", clap(name = "anonymize"))]
+    /// Anonymize addresses and payloads in a capture file for sharing
+    Anonymize(CaptureAnonymizeOptions),
+}
+
+/// Options for listing capture files
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CaptureLsOptions {
+    /// Capture index file (`NAME.idx`)
+    pub index: String,
+}
+
+/// Options for searching capture files
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CaptureGrepOptions {
+    /// Capture index file (`NAME.idx`)
+    pub index: String,
+
+    /// Only match frames captured at or after this time (RFC3339)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub from: Option<Timestamp>,
+
+    /// Only match frames captured at or before this time (RFC3339)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub to: Option<Timestamp>,
+
+    /// Only match frames with an RSSI at or above this value (dBm, per-frame filtering
+    /// requires PCAP-NG captures)
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub min_rssi: Option<i16>,
+
+    /// Only match frames with an RSSI at or below this value (dBm, per-frame filtering
+    /// requires PCAP-NG captures)
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub max_rssi: Option<i16>,
+
+    /// Only match frames containing this hex sequence
+    #[cfg_attr(feature = "clap", clap(long, value_parser = parse_hex))]
+    pub contains: Option<Vec<u8>>,
+}
+
+/// Frame located by a capture search
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureMatch {
+    /// Capture file name
+    pub file: String,
+    /// Index of the frame within the capture file
+    pub index: u64,
+    /// Capture timestamp (microseconds since the unix epoch)
+    pub timestamp_us: Option<u64>,
+    /// Frame RSSI, where recorded in the capture
+    pub rssi: Option<i16>,
+    pub data: Vec<u8>,
+}
+
+impl core::fmt::Display for CaptureMatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}#{}", self.file, self.index)?;
+        if let Some(t) = self.timestamp_us {
+            write!(f, " {}", format_us(t))?;
+        }
+        if let Some(r) = self.rssi {
+            write!(f, " rssi={}", r)?;
+        }
+        write!(f, " ({} bytes)\n{}", self.data.len(), HexDump(&self.data))
+    }
+}
+
+/// Parse the RSSI from a PCAP-NG packet comment (see [`packet_comment`](super::packet_comment))
+fn comment_rssi(comment: &str) -> Option<i16> {
+    comment
+        .split(' ')
+        .find_map(|f| f.strip_prefix("rssi="))
+        .and_then(|r| r.parse().ok())
+}
+
+fn as_us(t: &Timestamp) -> u64 {
+    t.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+impl CaptureGrepOptions {
+    /// Check whether an indexed file may contain matching frames
+    pub fn may_match(&self, entry: &CaptureIndexEntry) -> bool {
+        if entry.frames == 0 {
+            return false;
+        }
+
+        let before = |a: Option<u64>, b: Option<&Timestamp>| matches!((a, b), (Some(a), Some(b)) if a < as_us(b));
+        let after = |a: Option<u64>, b: Option<&Timestamp>| matches!((a, b), (Some(a), Some(b)) if a > as_us(b));
+        if before(entry.last_us, self.from.as_ref()) || after(entry.first_us, self.to.as_ref()) {
+            return false;
+        }
+
+        if let (Some(max), Some(min)) = (entry.rssi_max, self.min_rssi)
+            && max < min
+        {
+            return false;
+        }
+        if let (Some(min), Some(max)) = (entry.rssi_min, self.max_rssi)
+            && min > max
+        {
+            return false;
+        }
+
+        true
+    }
+
+    /// Check whether a captured packet matches the filters
+    pub fn matches(&self, packet: &CapturedPacket) -> bool {
+        let t = packet.timestamp.map(|t| t.as_micros() as u64);
+        if let (Some(t), Some(from)) = (t, &self.from)
+            && t < as_us(from)
+        {
+            return false;
+        }
+        if let (Some(t), Some(to)) = (t, &self.to)
+            && t > as_us(to)
+        {
+            return false;
+        }
+
+        let rssi = packet.comment.as_deref().and_then(comment_rssi);
+        if let (Some(r), Some(min)) = (rssi, self.min_rssi)
+            && r < min
+        {
+            return false;
+        }
+        if let (Some(r), Some(max)) = (rssi, self.max_rssi)
+            && r > max
+        {
+            return false;
+        }
+
+        match &self.contains {
+            Some(c) if !c.is_empty() => packet.data.windows(c.len()).any(|w| w == &c[..]),
+            _ => true,
+        }
+    }
+}
+
+/// List indexed capture files
+pub fn do_capture_ls(options: &CaptureLsOptions) -> Result<Vec<CaptureIndexEntry>, std::io::Error> {
+    let entries = read_capture_index(&options.index)?;
+
+    for e in &entries {
+        info!("{}", e);
+    }
+
+    Ok(entries)
+}
+
+/// Search indexed capture files, reading only files whose index entries may contain
+/// matching frames
+pub fn do_capture_grep(options: &CaptureGrepOptions) -> Result<Vec<CaptureMatch>, std::io::Error> {
+    let entries = read_capture_index(&options.index)?;
+    let dir = Path::new(&options.index).parent().unwrap_or(Path::new(""));
+
+    let candidates: Vec<_> = entries.iter().filter(|e| options.may_match(e)).collect();
+    info!(
+        "Searching {} of {} capture files",
+        candidates.len(),
+        entries.len()
+    );
+
+    let mut matches = vec![];
+    for e in candidates {
+        let mut r = CaptureReader::open(&dir.join(&e.file).to_string_lossy())?;
+        let mut index = 0;
+
+        while let Some(p) = r.next_packet()? {
+            if options.matches(&p) {
+                let m = CaptureMatch {
+                    file: e.file.clone(),
+                    index,
+                    timestamp_us: p.timestamp.map(|t| t.as_micros() as u64),
+                    rssi: p.comment.as_deref().and_then(comment_rssi),
+                    data: p.data,
+                };
+                info!("{}", m);
+                matches.push(m);
+            }
+            index += 1;
+        }
+    }
+
+    info!("Found {} matching frames", matches.len());
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::PcapFormat;
+
+    #[test]
+    fn rotated_capture_index() {
+        let dir = std::env::temp_dir().join(format!("radio-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("capture.pcapng");
+
+        assert_eq!(rotated_path(&base, 2), dir.join("capture-0002.pcapng"));
+        assert_eq!(index_path(&base), dir.join("capture.idx"));
+
+        let frame = |t: u64, rssi: i16, data: &[u8]| DecodedFrame {
+            timestamp_us: t,
+            rssi,
+            data: data.to_vec(),
+            text: None,
+            protocol: None,
+            summary: None,
+            device: None,
+            quality: Default::default(),
+            info: String::new(),
+        };
+
+        // Rotate on every frame
+        let options = PcapOptions {
+            pcap_format: PcapFormat::Pcapng,
+            ..Default::default()
+        };
+        let mut s = RotatingPcapSink::new(&base, std::time::Duration::ZERO, options).unwrap();
+        s.write(&frame(1_000_000, -60, &[0xaa, 0x01])).unwrap();
+        s.write(&frame(2_000_000, -90, &[0xbb, 0x02])).unwrap();
+        s.write(&frame(3_000_000, -70, &[0xaa, 0x03])).unwrap();
+        drop(s);
+
+        let index = index_path(&base).to_string_lossy().to_string();
+        let entries = do_capture_ls(&CaptureLsOptions {
+            index: index.clone(),
+        })
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].file, "capture-0001.pcapng");
+        assert_eq!(
+            (entries[1].frames, entries[1].rssi_min, entries[1].first_us),
+            (1, Some(-90), Some(2_000_000))
+        );
+
+        // Files are skipped by time range, with frames filtered by RSSI and content
+        let grep = CaptureGrepOptions {
+            index,
+            from: Some((SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1_500)).into()),
+            to: None,
+            min_rssi: Some(-80),
+            max_rssi: None,
+            contains: Some(vec![0xaa]),
+        };
+        assert!(!grep.may_match(&entries[0]));
+        let m = do_capture_grep(&grep).unwrap();
+        assert_eq!(m.len(), 1);
+        assert_eq!(
+            (m[0].file.as_str(), m[0].rssi, &m[0].data[..]),
+            ("capture-0002.pcapng", Some(-70), &[0xaa, 0x03][..])
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
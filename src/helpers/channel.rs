@@ -0,0 +1,76 @@
+//! Fixed channel selection for transmit, receive and RSSI operations
+//!
+//! `--channel` (a driver-specific channel number) or `--frequency` (in Hz, with optional
+//! `k`, `M` or `G` suffix, eg. `868.1M`) switch radios implementing
+//! [`Channel`](crate::Channel) before the operation is run with
+//! [`do_operation_channel`](super::do_operation_channel), so multi-channel tests don't
+//! require wrapper code in each driver's utility binary.
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+/// Channel selection options
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ChannelOptions {
+    /// Switch to this channel before the operation (driver-specific channel number)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub channel: Option<u32>,
+
+    /// Switch to this frequency before the operation, in Hz with optional k/M/G suffix
+    /// (for drivers with channels specified by frequency)
+    #[cfg_attr(feature = "clap", clap(long, conflicts_with = "channel", value_parser = parse_frequency))]
+    pub frequency: Option<u32>,
+}
+
+impl ChannelOptions {
+    /// Selected channel number or frequency, where configured
+    pub fn selected(&self) -> Option<u32> {
+        self.channel.or(self.frequency)
+    }
+}
+
+/// Parse a frequency in Hz, with an optional `k`, `M` or `G` suffix (and `Hz` unit)
+pub fn parse_frequency(s: &str) -> Result<u32, String> {
+    let t = s.trim();
+    let t = t.strip_suffix("Hz").unwrap_or(t);
+
+    let (v, scale) = match t.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&t[..i], 1e3),
+        Some((i, 'M')) => (&t[..i], 1e6),
+        Some((i, 'G')) => (&t[..i], 1e9),
+        _ => (t, 1.0),
+    };
+
+    let hz = v
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("invalid frequency '{}'", s))?
+        * scale;
+    match hz.round() {
+        f if (0.0..=u32::MAX as f64).contains(&f) => Ok(f as u32),
+        _ => Err(format!("frequency '{}' out of range", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequencies() {
+        assert_eq!(parse_frequency("868100000"), Ok(868_100_000));
+        assert_eq!(parse_frequency("868.1M"), Ok(868_100_000));
+        assert_eq!(parse_frequency("2.405GHz"), Ok(2_405_000_000));
+        assert_eq!(parse_frequency("433.92 MHz"), Ok(433_920_000));
+        assert_eq!(parse_frequency("125k"), Ok(125_000));
+        assert!(parse_frequency("5.8G").is_err());
+        assert!(parse_frequency("fast").is_err());
+
+        let options = ChannelOptions {
+            channel: None,
+            frequency: Some(868_100_000),
+        };
+        assert_eq!(options.selected(), Some(868_100_000));
+    }
+}
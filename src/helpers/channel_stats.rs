@@ -0,0 +1,238 @@
+//! Per-channel receive statistics for frequency-hopping receive
+//!
+//! [`ChannelRecorder`] wraps a hopping radio, attributing received frames, bytes, receive
+//! (CRC) errors and RSSI to the channel in use at reception. With `--hop-stats`, the
+//! per-channel report is written as JSON on exit, so spectrum planning decisions can be
+//! based on actual traffic rather than RSSI alone.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+
+use super::Samples;
+use super::json;
+use crate::{Receive, ReceiveInfo};
+
+/// Receive statistics for a single channel
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelRxStats {
+    pub channel: u32,
+    /// Frames received
+    pub frames: u64,
+    /// Bytes received
+    pub bytes: u64,
+    /// Receive (CRC) errors reported by the radio
+    pub errors: u64,
+    /// RSSI of received frames
+    pub rssi: Samples,
+}
+
+impl ChannelRxStats {
+    /// Receive errors as a percentage of receptions
+    pub fn error_rate(&self) -> f32 {
+        self.errors as f32 * 100.0 / (self.frames + self.errors).max(1) as f32
+    }
+}
+
+/// Format a per-channel receive statistics table
+pub struct ChannelRxTable<'a>(pub &'a [ChannelRxStats]);
+
+impl core::fmt::Display for ChannelRxTable<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>8} {:>10} {:>7} {:>9} {:>9} {:>9}",
+            "channel", "frames", "bytes", "errors", "error (%)", "mean rssi", "max rssi"
+        )?;
+        for c in self.0 {
+            let rssi = |m: Option<f32>| m.map(|v| format!("{:.1}", v)).unwrap_or("-".into());
+            writeln!(
+                f,
+                "{:>10} {:>8} {:>10} {:>7} {:>9.1} {:>9} {:>9}",
+                c.channel,
+                c.frames,
+                c.bytes,
+                c.errors,
+                c.error_rate(),
+                rssi(c.rssi.mean()),
+                rssi(c.rssi.max()),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write per-channel receive statistics to a JSON file
+pub fn write_channel_stats(path: &str, stats: &[ChannelRxStats]) -> Result<(), std::io::Error> {
+    let w = BufWriter::new(File::create(path)?);
+    json::to_writer_pretty(w, stats)?;
+    Ok(())
+}
+
+/// Radio wrapper accumulating receive statistics per channel
+///
+/// `channel` returns the configured channel number in use by the wrapped radio
+/// (eg. from [`Hopper::index`](crate::fhss::Hopper::index)), with receptions while no
+/// channel is selected not recorded.
+pub struct ChannelRecorder<T, F> {
+    radio: T,
+    channel: F,
+    stats: BTreeMap<u32, ChannelRxStats>,
+}
+
+impl<T, F> ChannelRecorder<T, F>
+where
+    F: Fn(&T) -> Option<u32>,
+{
+    /// Wrap a radio, resolving the current channel with the provided function
+    pub fn new(radio: T, channel: F) -> Self {
+        Self {
+            radio,
+            channel,
+            stats: BTreeMap::new(),
+        }
+    }
+
+    /// Statistics for the current channel, where selected
+    fn current(&mut self) -> Option<&mut ChannelRxStats> {
+        let channel = (self.channel)(&self.radio)?;
+        Some(self.stats.entry(channel).or_insert_with(|| ChannelRxStats {
+            channel,
+            ..Default::default()
+        }))
+    }
+
+    /// Per-channel statistics, ordered by channel
+    pub fn report(&self) -> Vec<ChannelRxStats> {
+        self.stats.values().cloned().collect()
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+}
+
+impl<T, F, I, E> Receive for ChannelRecorder<T, F>
+where
+    T: Receive<Info = I, Error = E>,
+    F: Fn(&T) -> Option<u32>,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    type Info = I;
+    type Error = E;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive()
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        let res = self.radio.check_receive(restart);
+        if res.is_err()
+            && let Some(s) = self.current()
+        {
+            s.errors += 1;
+        }
+        res
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let res = self.radio.get_received(buff);
+        match (&res, self.current()) {
+            (Ok((n, i)), Some(s)) => {
+                s.frames += 1;
+                s.bytes += *n as u64;
+                s.rssi.update(i.rssi() as f32);
+            }
+            (Err(_), Some(s)) => s.errors += 1,
+            _ => (),
+        }
+        res
+    }
+}
+
+impl<T: DelayNs, F> DelayNs for ChannelRecorder<T, F> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio receiving queued frames (`None` for CRC errors) on a settable channel
+    struct ChannelRadio {
+        channel: Option<u32>,
+        rx: Vec<Option<(usize, i16)>>,
+    }
+
+    impl Receive for ChannelRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            match self.rx.first() {
+                Some(None) => {
+                    self.rx.remove(0);
+                    Err(())
+                }
+                r => Ok(r.is_some()),
+            }
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (n, rssi) = self.rx.remove(0).ok_or(())?;
+            Ok((n, BasicInfo::new(rssi, 0)))
+        }
+    }
+
+    #[test]
+    fn per_channel_stats() {
+        let radio = ChannelRadio {
+            channel: Some(2),
+            rx: vec![Some((10, -60)), None, Some((20, -70))],
+        };
+        let mut r = ChannelRecorder::new(radio, |r: &ChannelRadio| r.channel);
+        let mut buff = [0u8; 32];
+
+        while r.inner().rx.len() > 1 {
+            if let Ok(true) = r.check_receive(true) {
+                r.get_received(&mut buff).unwrap();
+            }
+        }
+        r.inner().channel = Some(1);
+        assert_eq!(r.check_receive(true), Ok(true));
+        r.get_received(&mut buff).unwrap();
+
+        let report = r.report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(
+            (report[0].channel, report[0].frames, report[0].bytes),
+            (1, 1, 20)
+        );
+        assert_eq!(
+            (report[1].channel, report[1].frames, report[1].errors),
+            (2, 1, 1)
+        );
+        assert_eq!(report[1].error_rate(), 50.0);
+        assert_eq!(report[1].rssi.mean(), Some(-60.0));
+
+        let table = ChannelRxTable(&report).to_string();
+        assert_eq!(table.lines().count(), 3);
+    }
+}
@@ -0,0 +1,1323 @@
+//! Command line operations for radio utilities
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::*;
+use crate::{
+    Channel, Interrupts, Power, RawSamples, Receive, ReceiveFilter, ReceiveInfo, ReceiveMode,
+    RegisterDump, Rssi, State, StateQuery, TestMode, Transmit,
+    arq::ArqStats,
+    blocking::{BlockingError, radio_stats},
+    cca::CcaTransmit,
+    clock::StdClock,
+    config::{Configure, RadioConfig},
+    duty::DutyCycleLimiter,
+};
+
+/// Basic operations supported by the helpers package
+#[derive(Clone, Parser, PartialEq, Debug)]
+pub enum Operation {
+    #[clap(name = "tx")]
+    /// Transmit a packet
+    Transmit(TransmitOptions),
+
+    #[clap(name = "rx")]
+    /// Receive a packet
+    Receive(ReceiveOptions),
+
+    #[clap(name = "rssi")]
+    /// Poll RSSI on the configured channel
+    Rssi(RssiOptions),
+
+    #[clap(name = "echo")]
+    /// Echo back received messages (useful with Link Test mode)
+    Echo(EchoOptions),
+
+    #[clap(name = "ping-pong")]
+    /// Link test (ping-pong) mode
+    LinkTest(PingPongOptions),
+
+    #[clap(name = "ber")]
+    /// Bit and packet error rate test with PRBS payloads
+    Ber(BerOptions),
+
+    #[clap(name = "compare")]
+    /// Compare two saved link test reports
+    Compare(CompareOptions),
+
+    #[clap(name = "compare-drivers")]
+    /// Run the same link test plan against two radios and compare the results
+    CompareDrivers(CompareDriversOptions),
+
+    #[clap(name = "relay")]
+    /// Relay frames between two radios (cross-band repeater)
+    Relay(RelayOptions),
+
+    #[clap(name = "multi-rx")]
+    /// Receive concurrently on multiple radios (eg. gateway transceivers on different channels)
+    MultiRx(MultiRxOptions),
+
+    #[clap(name = "diff-rx")]
+    /// Receive on two radios tuned to the same channel, reporting frames seen by only one
+    DiffRx(DiffRxOptions),
+
+    #[clap(name = "soak")]
+    /// Long-duration soak test with periodic summaries
+    Soak(SoakOptions),
+
+    #[clap(name = "throughput")]
+    /// Throughput benchmark, transmitting or counting (with --receive) packets
+    Throughput(ThroughputOptions),
+
+    #[clap(name = "bench")]
+    /// Benchmark driver timings and sustained frame rate
+    Bench(BenchOptions),
+
+    #[clap(name = "stream")]
+    /// Stream stdin over the radio, writing received payloads to stdout
+    Stream(StreamOptions),
+
+    #[clap(name = "power-sweep")]
+    /// Step transmit power across a range for PA and antenna characterisation
+    PowerSweep(PowerSweepOptions),
+
+    #[clap(name = "sensitivity")]
+    /// Step transmit power (or attenuation) down until the echo PER crosses a threshold
+    Sensitivity(SensitivityOptions),
+
+    #[clap(name = "cw")]
+    /// Continuous carrier or modulated transmission for regulatory testing
+    /// (radios implementing TestMode)
+    Cw(CwOptions),
+
+    #[clap(name = "interfere")]
+    /// Generate interference patterns (carrier bursts, random packets or swept carrier)
+    /// for receiver robustness testing
+    Interfere(InterfereOptions),
+
+    #[clap(name = "import")]
+    /// Transmit frames from a raw frame log
+    Import(ImportOptions),
+
+    #[cfg(feature = "helpers-pcap")]
+    #[clap(name = "capture")]
+    /// List and search indexed capture files (see `--pcap-rotate`)
+    Capture(CaptureOptions),
+
+    #[cfg(feature = "helpers-pcap")]
+    #[clap(name = "replay")]
+    /// Retransmit packets from a PCAP or PCAP-NG capture
+    Replay(ReplayOptions),
+
+    #[clap(name = "mtu")]
+    /// Discover the link MTU against a peer running echo
+    Mtu(MtuOptions),
+
+    #[clap(name = "calibrate")]
+    /// Calibrate the echo response delay and ping-pong timeout against a peer
+    Calibrate(CalibrateOptions),
+
+    #[clap(name = "tune")]
+    /// Sweep modulation parameters against a peer (radios implementing Configure)
+    Tune(TuneOptions),
+
+    #[clap(name = "capture-raw")]
+    /// Capture raw samples around receive events (radios implementing RawSamples)
+    CaptureRaw(CaptureRawOptions),
+
+    #[clap(name = "scan")]
+    /// Survey RSSI and occupancy across channels (radios implementing Channel)
+    Scan(ScanOptions),
+
+    #[clap(name = "timestamp")]
+    /// Measure capture timestamp accuracy between two nodes
+    Timestamp(TimestampOptions),
+
+    #[clap(name = "trigger")]
+    /// Transmit a frame on each GPIO edge, measuring trigger-to-air latency
+    Trigger(TriggerOptions),
+
+    #[clap(name = "watch")]
+    /// Monitor peer liveness, reporting peers whose beacons go silent
+    Watch(WatchOptions),
+
+    #[clap(name = "beacon")]
+    /// Transmit periodic beacons with an incrementing counter, tracking loss from other nodes
+    Beacon(BeaconOptions),
+
+    #[clap(name = "dump")]
+    /// Dump raw register or driver state (radios implementing RegisterDump)
+    Dump(DumpOptions),
+
+    #[cfg(feature = "helpers-net")]
+    #[clap(name = "serve")]
+    /// Serve a REST control API over HTTP
+    Serve(ServeOptions),
+
+    #[cfg(feature = "helpers-net")]
+    #[clap(name = "serve-socket")]
+    /// Serve the radio to a remote SocketRadio client over TCP or a Unix socket
+    ServeSocket(SocketServeOptions),
+
+    #[cfg(feature = "zmq")]
+    #[clap(name = "zmq")]
+    /// Bridge received and transmitted frames to a ZeroMQ bus
+    Zmq(ZmqOptions),
+}
+
+impl Operation {
+    /// Remove and return the fixed channel (or frequency) selection where configured
+    pub fn take_channel(&mut self) -> Option<u32> {
+        let options = match self {
+            Operation::Transmit(o) => &mut o.channel_options,
+            Operation::Receive(o) => &mut o.channel_options,
+            Operation::Rssi(o) => &mut o.channel_options,
+            _ => return None,
+        };
+        std::mem::take(options).selected()
+    }
+
+    /// Remove and return automatic channel selection options where enabled
+    pub fn take_auto_channel(&mut self) -> Option<AutoChannelOptions> {
+        let options = match self {
+            Operation::Transmit(o) => &mut o.auto_channel_options,
+            Operation::Receive(o) => &mut o.auto_channel_options,
+            Operation::Echo(o) => &mut o.auto_channel_options,
+            _ => return None,
+        };
+        Some(std::mem::take(options)).filter(|o| o.enabled())
+    }
+
+    /// Coordinated start configuration for transmit, receive and link test operations
+    pub fn start_options(&self) -> Option<(&StartOptions, &BlockingOptions)> {
+        match self {
+            Operation::Transmit(o) => Some((&o.start_options, &o.blocking_options)),
+            Operation::Receive(o) => Some((&o.start_options, &o.blocking_options)),
+            Operation::LinkTest(o) => Some((&o.start_options, &o.blocking_options)),
+            _ => None,
+        }
+        .filter(|(s, _)| s.enabled())
+    }
+
+    /// Apply any saved turnaround calibration (`--calibration`) to echo and ping-pong options
+    pub fn apply_calibration(&mut self) {
+        let path = match self {
+            Operation::Echo(o) => o.calibration.take(),
+            Operation::LinkTest(o) => o.calibration.take(),
+            _ => None,
+        };
+        let info = match path.as_deref().map(CalibrationInfo::load) {
+            Some(Ok(info)) => info,
+            Some(Err(e)) => {
+                warn!("Error loading calibration: {:?}", e);
+                return;
+            }
+            None => return,
+        };
+
+        debug!("Applying calibration: {}", info);
+
+        match self {
+            Operation::Echo(o) => info.apply_echo(o),
+            Operation::LinkTest(o) => info.apply_ping_pong(o),
+            _ => (),
+        }
+    }
+
+    /// Operation name, as used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Transmit(_) => "tx",
+            Operation::Receive(_) => "rx",
+            Operation::Rssi(_) => "rssi",
+            Operation::Echo(_) => "echo",
+            Operation::LinkTest(_) => "ping-pong",
+            Operation::Ber(_) => "ber",
+            Operation::Compare(_) => "compare",
+            Operation::CompareDrivers(_) => "compare-drivers",
+            Operation::Relay(_) => "relay",
+            Operation::MultiRx(_) => "multi-rx",
+            Operation::DiffRx(_) => "diff-rx",
+            Operation::Soak(_) => "soak",
+            Operation::Throughput(_) => "throughput",
+            Operation::Bench(_) => "bench",
+            Operation::Stream(_) => "stream",
+            Operation::PowerSweep(_) => "power-sweep",
+            Operation::Sensitivity(_) => "sensitivity",
+            Operation::Cw(_) => "cw",
+            Operation::Interfere(_) => "interfere",
+            Operation::Import(_) => "import",
+            #[cfg(feature = "helpers-pcap")]
+            Operation::Replay(_) => "replay",
+            #[cfg(feature = "helpers-pcap")]
+            Operation::Capture(_) => "capture",
+            Operation::Mtu(_) => "mtu",
+            Operation::Calibrate(_) => "calibrate",
+            Operation::Tune(_) => "tune",
+            Operation::CaptureRaw(_) => "capture-raw",
+            Operation::Scan(_) => "scan",
+            Operation::Timestamp(_) => "timestamp",
+            Operation::Trigger(_) => "trigger",
+            Operation::Watch(_) => "watch",
+            Operation::Beacon(_) => "beacon",
+            Operation::Dump(_) => "dump",
+            #[cfg(feature = "helpers-net")]
+            Operation::Serve(_) => "serve",
+            #[cfg(feature = "helpers-net")]
+            Operation::ServeSocket(_) => "serve-socket",
+            #[cfg(feature = "zmq")]
+            Operation::Zmq(_) => "zmq",
+        }
+    }
+}
+
+/// Outcome of an operation, returned by [`do_operation`] for automation
+/// (see [`ExitStatus::from_result`] for mapping outcomes to process exit codes)
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OperationResult {
+    /// No results (streams, captures and services)
+    None,
+    /// Number of payloads transmitted
+    Transmit(usize),
+    /// Length of the last received frame
+    Receive(usize),
+    /// Last batch of RSSI samples
+    Rssi(Vec<i16>),
+    /// Number of frames echoed
+    Echo(usize),
+    /// Reliable transmit or echo statistics (`--reliable`)
+    Reliable(ArqStats),
+    /// Link test statistics, for each payload size tested
+    LinkTest(Vec<LinkTestInfo>),
+    /// Bit and packet error rates
+    Ber(BerInfo),
+    /// Link test report comparison, failing on regressions with `--fail-on-regression`
+    Compare {
+        comparison: Comparison,
+        fail_on_regression: bool,
+    },
+    /// Receive statistics, for each radio
+    MultiRx(Vec<RadioRxStats>),
+    /// Differential capture results
+    DiffRx(DiffRxReport),
+    /// Soak test summary
+    Soak(Box<SoakSummary>),
+    /// Throughput benchmark results
+    Throughput(ThroughputInfo),
+    /// Driver benchmark timings
+    Bench(Box<BenchReport>),
+    /// Power sweep results, for each power level
+    PowerSweep(PowerSweepInfo),
+    /// Sensitivity search results
+    Sensitivity(SensitivityInfo),
+    /// Continuous test transmission time in milliseconds
+    Cw(u64),
+    /// Interference generation statistics
+    Interfere(InterferenceStats),
+    /// Number of frames imported
+    Import(usize),
+    /// Number of capture packets replayed
+    Replay(usize),
+    /// Discovered MTU, `None` where no probes transited the link
+    Mtu(Option<MtuInfo>),
+    /// Calibrated turnaround timing, `None` where no probes were echoed
+    Calibrate(Option<CalibrationInfo>),
+    /// Number of raw sample captures
+    CaptureRaw(u32),
+    /// Per-channel RSSI and occupancy
+    Scan(Vec<ChannelScan>),
+    /// Capture timestamp errors
+    Timestamp(TimestampStats),
+    /// Trigger-to-air latency
+    Trigger(TriggerStats),
+    /// Final status of each watched peer
+    Watch(Vec<PeerStatus>),
+    /// Beacons sent and per-node beacon loss
+    Beacon(BeaconReport),
+    /// Dumped register or driver state
+    Dump(Vec<DumpRecord>),
+}
+
+/// Run an operation, recording start and stop events to the journal (see [`install_journal`])
+fn journaled<R, E, F>(operation: &Operation, f: F) -> Result<R, OperationError<E>>
+where
+    E: std::fmt::Debug,
+    F: FnOnce() -> Result<R, OperationError<E>>,
+{
+    let name = operation.name();
+    journal(JournalEvent::Start {
+        operation: name.to_string(),
+        config: format!("{:?}", operation),
+    });
+
+    let start = std::time::Instant::now();
+    let res = f();
+
+    debug!("Blocking wait statistics:\n{}", radio_stats());
+
+    journal(JournalEvent::Stop {
+        operation: name.to_string(),
+        elapsed_ms: start.elapsed().as_millis() as u64,
+        error: res.as_ref().err().map(|e| format!("{:?}", e)),
+    });
+
+    res
+}
+
+/// Run an operation on the provided radio, returning the operation outcome
+///
+/// Operations and options requiring radio capabilities beyond those bounded here (channel
+/// selection, test modes, receive filters etc.) fail with [`BlockingError::Unsupported`],
+/// naming the `do_operation_*` entry point supporting them.
+pub fn do_operation<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + Power<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    journaled(&operation.clone(), || {
+        let mut operation = operation;
+        match &mut operation {
+            Operation::Transmit(o) => negotiate(radio, o)?,
+            Operation::Receive(o) => negotiate(radio, o)?,
+            Operation::Echo(o) => negotiate(radio, o)?,
+            _ => (),
+        }
+        if let Some((start, blocking)) = operation.start_options() {
+            wait_start(radio, start, blocking)?;
+        }
+        run_operation(radio, operation)
+    })
+}
+
+/// Run an operation on a radio without [`Power`] or [`Rssi`] support, wrapping the radio in
+/// [`Capabilities`] so transmit, receive and echo operations run with [`do_operation`]
+///
+/// Operations requiring power control (`--power`) or RSSI (`rssi`, `--cca` etc.) fail with
+/// [`CapabilityError::Unsupported`], wrap radios supporting either trait in [`Capabilities`]
+/// with [`Capabilities::with_power`] or [`Capabilities::with_rssi`] to enable them.
+pub fn do_operation_basic<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<CapabilityError<E>>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    do_operation(&mut Capabilities::new(radio), operation)
+}
+
+/// Run transmit and echo operations with listen-before-talk where configured,
+/// reporting CCA statistics
+fn run_cca<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let cca_options = match &operation {
+        Operation::Transmit(options) => &options.cca_options,
+        Operation::Echo(options) => &options.cca_options,
+        _ => return Ok(OperationResult::None),
+    };
+    if cca_options.cca_threshold.is_none() {
+        return run_duty_cycle(radio, operation);
+    }
+
+    let seed = super::random_seed();
+    let mut radio = CcaTransmit::new(&mut *radio, cca_options.clone(), seed);
+
+    let res = run_duty_cycle(&mut radio, operation);
+
+    info!("Clear channel assessment: {}", radio.stats());
+
+    res.map_err(|e| e.map_radio(|e| e.flatten()))
+}
+
+/// Run transmit and echo operations with duty-cycle limiting where configured,
+/// reporting on-air time
+fn run_duty_cycle<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let (duty_options, bitrate) = match &operation {
+        Operation::Transmit(options) if options.duty_options.duty_cycle.is_some() => {
+            (options.duty_options.clone(), options.bitrate)
+        }
+        _ => return run_transmit_echo(radio, operation),
+    };
+    let bitrate = match bitrate {
+        Some(b) => b,
+        None => {
+            warn!("Duty cycle limiting requires a bitrate");
+            return run_transmit_echo(radio, operation);
+        }
+    };
+
+    let report = duty_options.duty_report;
+    let radio: DutyCycleLimiter<_, _> =
+        DutyCycleLimiter::new(&mut *radio, StdClock, duty_options, bitrate);
+    let mut radio = BudgetReport::new(radio, report);
+
+    let res = run_transmit_echo(&mut radio, operation);
+
+    let radio = radio.inner();
+    let budget = radio.budget();
+    info!("Duty cycle: {}, {}", radio.stats(), budget);
+
+    res.map_err(|e| e.map_radio(|e| e.flatten()))
+}
+
+/// Run transmit and echo operations, with reliable delivery where configured
+fn run_transmit_echo<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut buff = [0u8; 1024];
+
+    let res = match operation {
+        Operation::Transmit(options) if options.arq_options.reliable => {
+            OperationResult::Reliable(do_transmit_reliable(radio, options)?)
+        }
+        Operation::Transmit(options) => OperationResult::Transmit(do_transmit(radio, options)?),
+        Operation::Echo(options) if options.arq_options.reliable => {
+            OperationResult::Reliable(do_echo_reliable(radio, &mut buff, options)?)
+        }
+        Operation::Echo(options) => OperationResult::Echo(do_echo(radio, &mut buff, options)?),
+        _ => OperationResult::None,
+    };
+
+    Ok(res)
+}
+
+/// Frequency hopping requires channel selection, see [`do_operation_channel`]
+const FHSS_UNSUPPORTED: &str =
+    "frequency hopping requires a radio implementing Channel, see do_operation_channel";
+
+fn run_operation<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + Power<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut buff = [0u8; 1024];
+    let mut operation = operation;
+
+    if operation.take_channel().is_some() {
+        return Err(BlockingError::Unsupported(
+            "--channel and --frequency require a radio implementing Channel, see do_operation_channel",
+        ).into());
+    }
+    if operation.take_auto_channel().is_some() {
+        return Err(BlockingError::Unsupported(
+            "--auto-channel requires a radio implementing Channel, see do_operation_channel",
+        )
+        .into());
+    }
+    operation.apply_calibration();
+
+    let res = match operation {
+        Operation::Transmit(options) if options.afa_options.afa => {
+            return Err(BlockingError::Unsupported(
+                "--afa requires a radio implementing Channel, see do_operation_channel",
+            )
+            .into());
+        }
+        Operation::Transmit(options) if options.fhss_options.enabled() => {
+            return Err(BlockingError::Unsupported(FHSS_UNSUPPORTED).into());
+        }
+        Operation::Receive(options) if options.fhss_options.enabled() => {
+            return Err(BlockingError::Unsupported(FHSS_UNSUPPORTED).into());
+        }
+        Operation::LinkTest(options) if options.fhss_options.enabled() => {
+            return Err(BlockingError::Unsupported(FHSS_UNSUPPORTED).into());
+        }
+        Operation::LinkTest(options) if !options.hop_options.channels.is_empty() => {
+            return Err(BlockingError::Unsupported(
+                "link test --channels requires a radio implementing Channel, see do_operation_channel",
+            ).into());
+        }
+        Operation::Receive(options) if options.promiscuous => {
+            return Err(BlockingError::Unsupported(
+                "--promiscuous requires a radio implementing ReceiveFilter, see do_operation_filter",
+            ).into());
+        }
+        operation @ (Operation::Transmit(_) | Operation::Echo(_)) => run_cca(radio, operation)?,
+        Operation::Receive(options) => {
+            OperationResult::Receive(do_receive(radio, &mut buff, options)?)
+        }
+        Operation::Rssi(options) => OperationResult::Rssi(do_rssi(radio, options)?),
+        Operation::LinkTest(options) => {
+            let report_options = options.report_options.clone();
+            let results = match options.symmetric {
+                true => vec![do_ping_pong_symmetric(radio, options)?.link],
+                false => do_ping_pong_sweep(radio, options)?,
+            };
+
+            let report = LinkTestReport { results };
+            report_options
+                .write(&report)
+                .expect("Error writing link test report");
+
+            OperationResult::LinkTest(report.results)
+        }
+        Operation::Ber(options) => OperationResult::Ber(do_ber(radio, &mut buff, options)?),
+        Operation::Compare(options) => {
+            let c = do_compare(&options).expect("Error loading link test reports");
+
+            info!("Link test comparison:\n{}", c);
+
+            OperationResult::Compare {
+                comparison: c,
+                fail_on_regression: options.fail_on_regression,
+            }
+        }
+        Operation::CompareDrivers(_) => {
+            return Err(BlockingError::Unsupported(
+                "compare-drivers requires two radio instances, see do_compare_drivers",
+            )
+            .into());
+        }
+        Operation::Relay(_) => {
+            return Err(BlockingError::Unsupported(
+                "relay requires two radio instances, see do_relay",
+            )
+            .into());
+        }
+        Operation::MultiRx(_) => {
+            return Err(BlockingError::Unsupported(
+                "multi-rx requires multiple radio instances, see do_operation_multi",
+            )
+            .into());
+        }
+        Operation::DiffRx(_) => {
+            return Err(BlockingError::Unsupported(
+                "diff-rx requires two radio instances, see do_operation_multi",
+            )
+            .into());
+        }
+        Operation::Soak(options) => {
+            OperationResult::Soak(Box::new(do_soak(radio, &mut buff, options)?))
+        }
+        Operation::Throughput(options) => {
+            OperationResult::Throughput(do_throughput(radio, &mut buff, options)?)
+        }
+        Operation::Bench(options) => OperationResult::Bench(Box::new(do_bench(radio, options)?)),
+        Operation::Stream(options) => {
+            do_stream(radio, options)?;
+            OperationResult::None
+        }
+        Operation::PowerSweep(options) => {
+            OperationResult::PowerSweep(do_power_sweep(radio, &mut buff, options)?)
+        }
+        Operation::Sensitivity(options) => {
+            OperationResult::Sensitivity(do_sensitivity(radio, &mut buff, options)?)
+        }
+        Operation::Cw(_) => {
+            return Err(BlockingError::Unsupported(
+                "cw requires a radio implementing TestMode, see do_operation_test",
+            )
+            .into());
+        }
+        Operation::Interfere(options) if options.pattern == InterferencePattern::Packets => {
+            OperationResult::Interfere(do_interfere_packets(radio, StdClock, options)?)
+        }
+        Operation::Interfere(_) => {
+            return Err(BlockingError::Unsupported(
+                "interfere bursts and sweeps require a radio implementing TestMode and Channel, see do_operation_interfere",
+            ).into());
+        }
+        Operation::Import(options) => OperationResult::Import(do_import(radio, options)?),
+        #[cfg(feature = "helpers-pcap")]
+        Operation::Replay(options) => OperationResult::Replay(do_replay(radio, options)?),
+        #[cfg(feature = "helpers-pcap")]
+        Operation::Capture(options) => {
+            match options.command {
+                CaptureCommand::Ls(o) => do_capture_ls(&o).map(|_| ()),
+                CaptureCommand::Grep(o) => do_capture_grep(&o).map(|_| ()),
+                CaptureCommand::Anonymize(o) => do_capture_anonymize(&o).map(|_| ()),
+            }
+            .expect("Error reading capture");
+
+            OperationResult::None
+        }
+        Operation::Mtu(options) => {
+            OperationResult::Mtu(do_discover_mtu(radio, &mut buff, options)?)
+        }
+        Operation::Calibrate(options) if options.respond => {
+            OperationResult::Echo(do_calibrate_respond(radio, &mut buff, options)? as usize)
+        }
+        Operation::Calibrate(options) => {
+            OperationResult::Calibrate(do_calibrate(radio, &mut buff, options)?)
+        }
+        Operation::Tune(_) => {
+            return Err(BlockingError::Unsupported(
+                "tune requires a radio implementing Configure, see do_tune",
+            )
+            .into());
+        }
+        Operation::CaptureRaw(_) => {
+            return Err(BlockingError::Unsupported(
+                "capture-raw requires a radio implementing RawSamples, see do_operation_diag",
+            )
+            .into());
+        }
+        Operation::Scan(_) => {
+            return Err(BlockingError::Unsupported(
+                "scan requires a radio implementing Channel, see do_operation_channel",
+            )
+            .into());
+        }
+        Operation::Timestamp(options) => match options.role {
+            TimestampRole::Send => {
+                OperationResult::Transmit(do_timestamp_send(radio, StdClock, options)? as usize)
+            }
+            TimestampRole::Receive => OperationResult::Timestamp(do_timestamp_receive(
+                radio, &mut buff, StdClock, options,
+            )?),
+        },
+        #[cfg(feature = "gpiochip")]
+        Operation::Trigger(options) => {
+            OperationResult::Trigger(do_trigger_gpiochip(radio, options)?)
+        }
+        #[cfg(not(feature = "gpiochip"))]
+        Operation::Trigger(_) => {
+            return Err(BlockingError::Unsupported(
+                "trigger requires a GPIO input (or the gpiochip feature), see do_trigger_tx",
+            )
+            .into());
+        }
+        Operation::Watch(options) => OperationResult::Watch(do_watch(radio, &mut buff, options)?),
+        Operation::Beacon(options) => {
+            OperationResult::Beacon(do_beacon(radio, &mut buff, options)?)
+        }
+        Operation::Dump(_) => {
+            return Err(BlockingError::Unsupported(
+                "dump requires a radio implementing RegisterDump, see do_operation_dump",
+            )
+            .into());
+        }
+        #[cfg(feature = "helpers-net")]
+        Operation::Serve(options) => {
+            do_serve(radio, options)?;
+            OperationResult::None
+        }
+        #[cfg(feature = "helpers-net")]
+        Operation::ServeSocket(options) => {
+            do_socket_serve(radio, options)?;
+            OperationResult::None
+        }
+        #[cfg(feature = "zmq")]
+        Operation::Zmq(options) => {
+            do_zmq(radio, options)?;
+            OperationResult::None
+        }
+    };
+
+    Ok(res)
+}
+
+/// Run an operation on a radio supporting diagnostic interfaces, extending [`do_operation`]
+/// with receive events (`rx --verbose`) and raw sample capture (`capture-raw`)
+pub fn do_operation_diag<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + RawSamples<Error = E>
+        + Interrupts
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut buff = [0u8; 1024];
+
+    match operation.clone() {
+        Operation::Receive(options) => journaled(&operation, || {
+            Ok(OperationResult::Receive(do_receive_events(
+                radio, &mut buff, options,
+            )?))
+        }),
+        Operation::CaptureRaw(options) => journaled(&operation, || {
+            Ok(OperationResult::CaptureRaw(do_capture_raw(
+                radio, &mut buff, options,
+            )?))
+        }),
+        op => do_operation(radio, op),
+    }
+}
+
+/// Run an operation on a radio supporting register dumps, extending [`do_operation`] with
+/// register and driver state dumps (`dump`)
+///
+/// Where another operation fails, driver state is dumped before the error is returned so
+/// failures can be diagnosed after the fact.
+pub fn do_operation_dump<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + RegisterDump<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    match operation.clone() {
+        Operation::Dump(options) => journaled(&operation, || {
+            Ok(OperationResult::Dump(do_dump(radio, &options)?))
+        }),
+        op => {
+            let res = do_operation(radio, op);
+            if res.is_err() {
+                match read_dump(radio) {
+                    Ok(r) => warn!("Operation failed, register dump:\n{}", DumpTable(&r)),
+                    Err(e) => warn!("Operation failed, error reading register dump: {:?}", e),
+                }
+            }
+            res
+        }
+    }
+}
+
+/// Run an operation on a radio supporting test modes, extending [`do_operation`] with
+/// continuous carrier and modulated transmission (`cw`)
+pub fn do_operation_test<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + TestMode<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    match operation.clone() {
+        Operation::Cw(options) => journaled(&operation, || {
+            Ok(OperationResult::Cw(do_cw(radio, options)?))
+        }),
+        op => do_operation(radio, op),
+    }
+}
+
+/// Run an operation on a radio supporting test modes and channel selection, extending
+/// [`do_operation_test`] with all interference patterns (`interfere`)
+///
+/// `to_channel` maps configured sweep channel numbers to the radio channel type.
+pub fn do_operation_interfere<T, I, E, F>(
+    radio: &mut T,
+    operation: Operation,
+    to_channel: F,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + TestMode<Error = E>
+        + Channel<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(u32) -> T::Channel,
+{
+    match operation.clone() {
+        Operation::Interfere(options) => journaled(&operation, || {
+            let stats = match options.pattern {
+                InterferencePattern::Bursts => do_interfere_bursts(radio, StdClock, options)?,
+                InterferencePattern::Packets => do_interfere_packets(radio, StdClock, options)?,
+                InterferencePattern::Sweep => {
+                    do_interfere_sweep(radio, StdClock, options, to_channel)?
+                }
+            };
+            Ok(OperationResult::Interfere(stats))
+        }),
+        op => do_operation_test(radio, op),
+    }
+}
+
+/// Run an operation on a radio supporting state control, extending [`do_operation`] with
+/// sleep between periodic transmit and echo activity (`--sleep-between`)
+pub fn do_operation_state<T, I, E>(
+    radio: &mut T,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + State<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut buff = [0u8; 1024];
+
+    match operation.clone() {
+        Operation::Transmit(options)
+            if options.sleep_options.sleep_between && !options.arq_options.reliable =>
+        {
+            journaled(&operation, || {
+                Ok(OperationResult::Transmit(do_transmit_sleep(
+                    radio, options,
+                )?))
+            })
+        }
+        Operation::Echo(options)
+            if options.sleep_options.sleep_between && !options.arq_options.reliable =>
+        {
+            journaled(&operation, || {
+                do_echo_sleep(radio, &mut buff, options).map(OperationResult::Echo)
+            })
+        }
+        op => do_operation(radio, op),
+    }
+}
+
+/// Run an operation on a radio supporting receive filtering, extending [`do_operation`]
+/// with promiscuous receive (`rx --promiscuous`)
+pub fn do_operation_filter<T, I, E>(
+    radio: &mut T,
+    mut operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + ReceiveFilter<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    if let Operation::Receive(options) = &mut operation
+        && options.promiscuous
+    {
+        debug!("Enabling promiscuous receive");
+        radio.set_receive_mode(ReceiveMode::Promiscuous)?;
+        options.promiscuous = false;
+    }
+
+    do_operation(radio, operation)
+}
+
+/// Run an operation on a configurable radio, applying a radio configuration profile
+/// (see [`ConfigFileOptions`]) before running the operation with [`do_operation`]
+pub fn do_operation_config<T, I, E>(
+    radio: &mut T,
+    config: &RadioConfig,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + Configure<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let applied = apply_radio_config(radio, config)?;
+    debug!("Applied {} config options", applied);
+
+    do_operation(radio, operation)
+}
+
+/// Run an operation on a radio supporting state queries, displaying radio state changes
+/// (`--show-state`) and warning on stuck states (`--stuck-after`) while running the
+/// operation with [`do_operation`]
+pub fn do_operation_status<T, I, E>(
+    radio: &mut T,
+    options: &StatusOptions,
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + StateQuery<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    if !options.enabled() {
+        return do_operation(radio, operation);
+    }
+
+    let mut monitor = StateMonitor::new(radio, options.clone(), operation.name());
+    monitor.update()?;
+
+    let res = do_operation(&mut monitor, operation);
+
+    // Report the state the operation left the radio in
+    if let Err(e) = monitor.update() {
+        debug!("Error querying radio state: {:?}", e);
+    }
+
+    res
+}
+
+/// Run an operation on a set of radios, extending [`do_operation`] with concurrent
+/// receive (`multi-rx`) and differential capture on the first two radios (`diff-rx`),
+/// with other operations run on the first radio
+pub fn do_operation_multi<T, I, E>(
+    radios: &mut [T],
+    operation: Operation,
+) -> Result<OperationResult, OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut buff = [0u8; 1024];
+
+    match (operation.clone(), radios.first_mut()) {
+        (Operation::MultiRx(options), _) => journaled(&operation, || {
+            do_receive_multi(radios, &mut buff, options)
+                .map(OperationResult::MultiRx)
+                .map_err(|e| e.error.into())
+        }),
+        (Operation::DiffRx(options), _) => match radios {
+            [a, b, ..] => journaled(&operation, || {
+                do_receive_diff([a, b], &mut buff, options)
+                    .map(OperationResult::DiffRx)
+                    .map_err(|e| e.error.into())
+            }),
+            _ => Err(BlockingError::Unsupported("diff-rx requires two radios").into()),
+        },
+        (op, Some(radio)) => do_operation(radio, op),
+        (_, None) => Err(BlockingError::Unsupported("no radios provided").into()),
+    }
+}
+
+/// Run an operation on a radio supporting channel selection, extending [`do_operation`]
+/// with fixed channel selection (`--channel` / `--frequency`), automatic channel selection
+/// (`--auto-channel`), frequency hopping (`--hop-channels`), LBT + AFA (`--afa`),
+/// channel hopping link tests (`--channels`) and spectrum scans (`scan`)
+///
+/// `to_channel` maps configured channel numbers (or frequencies) to the radio channel type.
+/// Returns the operation outcome along with the channel selected with `--auto-channel`, if any.
+pub fn do_operation_channel<T, I, E, F>(
+    radio: &mut T,
+    operation: Operation,
+    to_channel: F,
+) -> Result<(OperationResult, Option<u32>), OperationError<E>>
+where
+    T: Transmit<Error = E>
+        + Power<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + Channel<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + Default + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(u32) -> T::Channel,
+{
+    let mut buff = [0u8; 1024];
+    let mut to_channel = to_channel;
+    let mut operation = operation;
+
+    // Switch to the configured channel, ahead of any automatic selection
+    let fixed = operation.take_channel();
+    if let Some(channel) = fixed {
+        debug!("Switching to channel {}", channel);
+        radio.set_channel(&to_channel(channel))?;
+    }
+
+    // Switch to the quietest candidate channel where enabled
+    let selected = match operation.take_auto_channel() {
+        Some(a) => select_channel(radio, &a, &mut to_channel)?,
+        None => None,
+    };
+    if let Some(channel) = selected {
+        journal(JournalEvent::ChannelSelected {
+            operation: operation.name().to_string(),
+            channel,
+        });
+    }
+
+    // Record the selected channel in capture metadata
+    #[cfg(feature = "helpers-pcap")]
+    if let (Some(channel), Operation::Receive(o)) = (selected.or(fixed), &mut operation) {
+        o.worker_options
+            .pcap_options
+            .pcap_channel
+            .get_or_insert(channel);
+    }
+
+    let res = match operation.clone() {
+        Operation::Transmit(options) if options.afa_options.afa => journaled(&operation, || {
+            Ok(OperationResult::Transmit(do_transmit_afa(
+                radio,
+                options,
+                |c| to_channel(*c),
+            )?))
+        }),
+        Operation::Transmit(options) if options.fhss_options.enabled() => {
+            journaled(&operation, || {
+                Ok(OperationResult::Transmit(do_transmit_fhss(
+                    radio,
+                    options,
+                    |c| to_channel(*c),
+                )?))
+            })
+        }
+        Operation::Receive(options) if options.fhss_options.enabled() => {
+            journaled(&operation, || {
+                Ok(OperationResult::Receive(do_receive_fhss(
+                    radio,
+                    &mut buff,
+                    options,
+                    |c| to_channel(*c),
+                )?))
+            })
+        }
+        Operation::LinkTest(options) if options.fhss_options.enabled() => {
+            journaled(&operation, || {
+                let link = do_ping_pong_fhss(radio, options, |c| to_channel(*c))?;
+                Ok(OperationResult::LinkTest(vec![link]))
+            })
+        }
+        Operation::LinkTest(options) if !options.hop_options.channels.is_empty() => {
+            journaled(&operation, || {
+                let results = do_ping_pong_hopping(radio, options, &mut to_channel)?;
+                Ok(OperationResult::LinkTest(
+                    results.into_iter().map(|c| c.link).collect(),
+                ))
+            })
+        }
+        Operation::Scan(options) => journaled(&operation, || {
+            Ok(OperationResult::Scan(do_scan(radio, options, to_channel)?))
+        }),
+        op => do_operation(radio, op),
+    }?;
+
+    Ok((res, selected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio completing transmissions immediately, reporting a fixed RSSI
+    struct NullRadio;
+
+    impl Transmit for NullRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for NullRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Err(())
+        }
+    }
+
+    impl Power for NullRadio {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl Rssi for NullRadio {
+        type Error = ();
+
+        fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+            Ok(-80)
+        }
+    }
+
+    impl DelayNs for NullRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn operation_results() {
+        let run = |args: &[&str]| {
+            let args = std::iter::once("radio").chain(args.iter().copied());
+            do_operation(&mut NullRadio, Operation::try_parse_from(args).unwrap())
+        };
+        let op = |args: &[&str]| run(args).unwrap();
+
+        assert_eq!(
+            op(&["tx", "--data", "1", "--data", "2"]),
+            OperationResult::Transmit(1)
+        );
+        let r = op(&["rssi", "--samples", "2", "--period", "1ms"]);
+        assert_eq!(r, OperationResult::Rssi(vec![-80, -80]));
+
+        assert_eq!(serde_json::to_string(&r).unwrap(), r#"{"rssi":[-80,-80]}"#);
+
+        // Operations requiring capabilities the radio lacks fail rather than succeeding
+        for args in [
+            &["scan", "--channels", "1,2"][..],
+            &["cw"],
+            &["rx", "--promiscuous"],
+            &["tx", "--afa"],
+        ] {
+            assert!(
+                matches!(
+                    run(args),
+                    Err(OperationError::Radio(BlockingError::Unsupported(_)))
+                ),
+                "{:?}",
+                args
+            );
+        }
+
+        // Radios without power control run transmit unless power is requested
+        let basic = |args: &[&str]| {
+            let args = std::iter::once("radio").chain(args.iter().copied());
+            do_operation_basic(&mut NullRadio, Operation::try_parse_from(args).unwrap())
+        };
+        assert_eq!(
+            basic(&["tx", "--data", "1"]),
+            Ok(OperationResult::Transmit(1))
+        );
+        assert_eq!(
+            basic(&["tx", "--data", "1", "--power", "10"]),
+            Err(CapabilityError::Unsupported("power").into())
+        );
+    }
+    #[cfg(feature = "mock")]
+    #[test]
+    fn auto_channel_selection() {
+        use crate::mock::{ImpairedRadio, Impairments};
+
+        let rssi = |rssi| Impairments {
+            rssi,
+            rssi_jitter: 4,
+            ..Default::default()
+        };
+        let radio = || {
+            ImpairedRadio::echo(rssi(-70), 5)
+                .with_channel(1, rssi(-60))
+                .with_channel(2, rssi(-104))
+                .with_channel(3, rssi(-90))
+        };
+        let op = |args: &[&str]| {
+            let args = std::iter::once("radio").chain(args.iter().copied());
+            Operation::try_parse_from(args).unwrap()
+        };
+
+        // The quietest candidate is selected before the operation runs
+        let mut r = radio();
+        let res = do_operation_channel(
+            &mut r,
+            op(&["tx", "--data", "1", "--auto-channel", "1,2,3"]),
+            |c| c as u16,
+        );
+        assert_eq!(res, Ok((OperationResult::Transmit(1), Some(2))));
+        assert_eq!(r.channel(), 2);
+
+        // Automatic selection overrides a fixed channel, while a fixed channel alone is
+        // used as configured
+        let mut r = radio();
+        let res = do_operation_channel(
+            &mut r,
+            op(&[
+                "tx",
+                "--data",
+                "1",
+                "--channel",
+                "1",
+                "--auto-channel",
+                "1,3",
+            ]),
+            |c| c as u16,
+        );
+        assert_eq!(res, Ok((OperationResult::Transmit(1), Some(3))));
+        assert_eq!(r.channel(), 3);
+
+        let mut r = radio();
+        let res = do_operation_channel(&mut r, op(&["tx", "--data", "1", "--channel", "1"]), |c| {
+            c as u16
+        });
+        assert_eq!(res, Ok((OperationResult::Transmit(1), None)));
+        assert_eq!(r.channel(), 1);
+    }
+}
@@ -0,0 +1,487 @@
+//! Link test report serialisation and regression comparison
+//!
+//! Reports may be compared from saved files (`compare`), or generated by running the same
+//! link test plan against two radio instances back-to-back (`compare-drivers`), for
+//! evaluating transceiver modules and drivers side-by-side.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+
+use super::json;
+use super::{LinkTestInfo, PingPongOptions, Samples, do_ping_pong_sweep};
+use crate::{Power, Receive, ReceiveInfo, Transmit, blocking::BlockingError};
+
+/// Serialised link test report, containing results for each payload size tested
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkTestReport {
+    pub results: Vec<LinkTestInfo>,
+}
+
+impl LinkTestReport {
+    /// Load a JSON report from the provided file
+    pub fn load(path: &str) -> Result<Self, std::io::Error> {
+        let f = BufReader::new(File::open(path)?);
+        let r = json::from_reader(f)?;
+        Ok(r)
+    }
+
+    /// Write the report as JSON to the provided file
+    pub fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let f = BufWriter::new(File::create(path)?);
+        json::to_writer_pretty(f, self)?;
+        Ok(())
+    }
+}
+
+/// Configuration for Compare operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CompareOptions {
+    /// Baseline report file
+    pub baseline: String,
+
+    /// Candidate report file
+    pub candidate: String,
+
+    /// Exit with a non-zero status if a significant regression is detected
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub fail_on_regression: bool,
+}
+
+/// Change in a single metric between baseline and candidate reports
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MetricDelta {
+    /// Metric name
+    pub name: String,
+    /// Payload length of the compared results
+    pub payload_len: usize,
+    /// Baseline value
+    pub baseline: f32,
+    /// Candidate value
+    pub candidate: f32,
+    /// Whether the change is statistically significant (p < 0.05)
+    pub significant: bool,
+    /// Whether the change is a significant degradation
+    pub regression: bool,
+}
+
+impl MetricDelta {
+    fn new(
+        name: &'static str,
+        payload_len: usize,
+        baseline: f32,
+        candidate: f32,
+        std_err: f32,
+        higher_is_better: bool,
+    ) -> Self {
+        let delta = candidate - baseline;
+        let significant = std_err > 0.0 && (delta / std_err).abs() > 1.96;
+        let worse = match higher_is_better {
+            true => delta < 0.0,
+            false => delta > 0.0,
+        };
+
+        Self {
+            name: name.to_string(),
+            payload_len,
+            baseline,
+            candidate,
+            significant,
+            regression: significant && worse,
+        }
+    }
+
+    /// Compare sample means using Welch's approximation for the standard error
+    fn means(
+        name: &'static str,
+        payload_len: usize,
+        a: &Samples,
+        b: &Samples,
+        higher_is_better: bool,
+    ) -> Option<Self> {
+        let (ma, mb) = (a.mean()?, b.mean()?);
+        let (sa, sb) = (a.std_dev()?, b.std_dev()?);
+        let std_err = (sa.powi(2) / a.count() as f32 + sb.powi(2) / b.count() as f32).sqrt();
+
+        Some(Self::new(
+            name,
+            payload_len,
+            ma,
+            mb,
+            std_err,
+            higher_is_better,
+        ))
+    }
+}
+
+/// Comparison between two link test reports
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct Comparison {
+    pub deltas: Vec<MetricDelta>,
+}
+
+impl Comparison {
+    /// Check whether any metric significantly regressed
+    pub fn regressed(&self) -> bool {
+        self.deltas.iter().any(|d| d.regression)
+    }
+}
+
+impl core::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{:<18} {:>6} {:>10} {:>10} {:>10}",
+            "metric", "size", "baseline", "candidate", "delta"
+        )?;
+
+        for d in &self.deltas {
+            let flag = match (d.significant, d.regression) {
+                (_, true) => " !",
+                (true, false) => " *",
+                _ => "",
+            };
+            writeln!(
+                f,
+                "{:<18} {:>6} {:>10.2} {:>10.2} {:>+10.2}{}",
+                d.name,
+                d.payload_len,
+                d.baseline,
+                d.candidate,
+                d.candidate - d.baseline,
+                flag
+            )?;
+        }
+
+        write!(f, "(* significant change, ! significant regression)")
+    }
+}
+
+/// Compare link test results with matching payload lengths between two reports
+pub fn compare_reports(baseline: &LinkTestReport, candidate: &LinkTestReport) -> Comparison {
+    let mut c = Comparison::default();
+
+    for a in &baseline.results {
+        let b = match candidate
+            .results
+            .iter()
+            .find(|b| b.payload_len == a.payload_len)
+        {
+            Some(b) => b,
+            None => continue,
+        };
+
+        // Loss rate, using a two-proportion z-test
+        let (na, nb) = (a.sent.max(1) as f32, b.sent.max(1) as f32);
+        let (la, lb) = (1.0 - a.received as f32 / na, 1.0 - b.received as f32 / nb);
+        let pooled = (la * na + lb * nb) / (na + nb);
+        let std_err = (pooled * (1.0 - pooled) * (1.0 / na + 1.0 / nb)).sqrt();
+        c.deltas.push(MetricDelta::new(
+            "loss (%)",
+            a.payload_len,
+            la * 100.0,
+            lb * 100.0,
+            std_err * 100.0,
+            false,
+        ));
+
+        let means = [
+            ("local rssi (dBm)", &a.local_rssi, &b.local_rssi, true),
+            ("remote rssi (dBm)", &a.remote_rssi, &b.remote_rssi, true),
+            ("rtt (ms)", &a.rtt, &b.rtt, false),
+        ];
+        for (name, sa, sb, higher_is_better) in means {
+            if let Some(d) = MetricDelta::means(name, a.payload_len, sa, sb, higher_is_better) {
+                c.deltas.push(d);
+            }
+        }
+    }
+
+    c
+}
+
+/// Load and compare the reports specified in the provided options
+pub fn do_compare(options: &CompareOptions) -> Result<Comparison, std::io::Error> {
+    let baseline = LinkTestReport::load(&options.baseline)?;
+    let candidate = LinkTestReport::load(&options.candidate)?;
+
+    Ok(compare_reports(&baseline, &candidate))
+}
+
+/// Configuration for CompareDrivers operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CompareDriversOptions {
+    /// Label for the first (baseline) radio
+    #[cfg_attr(feature = "clap", clap(long, default_value = "a"))]
+    pub name_a: String,
+
+    /// Label for the second (candidate) radio
+    #[cfg_attr(feature = "clap", clap(long, default_value = "b"))]
+    pub name_b: String,
+
+    /// Write the side-by-side comparison to the provided file
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub output: Option<String>,
+
+    /// Link test plan run against each radio
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub ping_pong_options: PingPongOptions,
+}
+
+/// Side-by-side results from running the same link test plan against two radios
+#[derive(Clone, Debug, PartialEq)]
+pub struct DriverComparison {
+    /// Label for the first (baseline) radio
+    pub name_a: String,
+    /// Label for the second (candidate) radio
+    pub name_b: String,
+    /// Results for the first radio
+    pub a: LinkTestReport,
+    /// Results for the second radio
+    pub b: LinkTestReport,
+    /// Statistical comparison of the second radio against the first
+    pub comparison: Comparison,
+}
+
+impl core::fmt::Display for DriverComparison {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fn opt(v: Option<f32>) -> String {
+            v.map(|v| format!("{:.1}", v)).unwrap_or_else(|| "-".into())
+        }
+
+        let (a, b) = (&self.name_a, &self.name_b);
+        writeln!(
+            f,
+            "{:>6} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "size",
+            format!("loss {}", a),
+            format!("loss {}", b),
+            format!("rssi {}", a),
+            format!("rssi {}", b),
+            format!("rtt {}", a),
+            format!("rtt {}", b),
+        )?;
+
+        for ra in &self.a.results {
+            let rb = self
+                .b
+                .results
+                .iter()
+                .find(|rb| rb.payload_len == ra.payload_len);
+            let loss = |r: &LinkTestInfo| 100.0 - r.received as f32 * 100.0 / r.sent.max(1) as f32;
+
+            writeln!(
+                f,
+                "{:>6} {:>10.1} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                ra.payload_len,
+                loss(ra),
+                opt(rb.map(loss)),
+                opt(ra.local_rssi.mean()),
+                opt(rb.and_then(|r| r.local_rssi.mean())),
+                opt(ra.rtt.mean()),
+                opt(rb.and_then(|r| r.rtt.mean())),
+            )?;
+        }
+
+        writeln!(f)?;
+        write!(f, "{}", self.comparison)
+    }
+}
+
+/// Error running a driver comparison, identifying the radio that failed
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompareDriversError<A, B> {
+    /// Error from the first radio
+    A(BlockingError<A>),
+    /// Error from the second radio
+    B(BlockingError<B>),
+}
+
+/// Run the same link test plan against two radios back-to-back, comparing the results
+pub fn do_compare_drivers<A, IA, EA, B, IB, EB>(
+    radio_a: &mut A,
+    radio_b: &mut B,
+    options: CompareDriversOptions,
+) -> Result<DriverComparison, CompareDriversError<EA, EB>>
+where
+    A: Receive<Info = IA, Error = EA> + Transmit<Error = EA> + Power<Error = EA> + DelayNs,
+    IA: ReceiveInfo,
+    EA: std::fmt::Debug,
+    B: Receive<Info = IB, Error = EB> + Transmit<Error = EB> + Power<Error = EB> + DelayNs,
+    IB: ReceiveInfo,
+    EB: std::fmt::Debug,
+{
+    info!("Running link test plan on {}", options.name_a);
+    let a = do_ping_pong_sweep(radio_a, options.ping_pong_options.clone())
+        .map_err(CompareDriversError::A)?;
+
+    info!("Running link test plan on {}", options.name_b);
+    let b = do_ping_pong_sweep(radio_b, options.ping_pong_options.clone())
+        .map_err(CompareDriversError::B)?;
+
+    let (a, b) = (LinkTestReport { results: a }, LinkTestReport { results: b });
+    let c = DriverComparison {
+        comparison: compare_reports(&a, &b),
+        name_a: options.name_a,
+        name_b: options.name_b,
+        a,
+        b,
+    };
+
+    info!("Driver comparison:\n{}", c);
+
+    if let Some(path) = &options.output {
+        std::fs::write(path, c.to_string()).expect("Error writing driver comparison");
+    }
+
+    Ok(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+    use crate::helpers::BurstLoss;
+
+    fn info(received: u32, rssi: f32) -> LinkTestInfo {
+        let mut local_rssi = Samples::new();
+        for i in 0..received {
+            local_rssi.update(rssi + (i % 3) as f32);
+        }
+
+        LinkTestInfo {
+            sent: 100,
+            received,
+            payload_len: 4,
+            local_rssi,
+            remote_rssi: Samples::new(),
+            rtt: Samples::new(),
+            loss_bursts: BurstLoss::default(),
+            ..LinkTestInfo::new(4)
+        }
+    }
+
+    #[test]
+    fn compare_detects_regressions() {
+        let a = LinkTestReport {
+            results: vec![info(98, -60.0)],
+        };
+
+        // Equivalent results are not flagged
+        let c = compare_reports(&a, &a);
+        assert!(!c.regressed());
+        assert_eq!(c.deltas.len(), 2);
+
+        // Significantly increased loss and reduced RSSI are regressions
+        let b = LinkTestReport {
+            results: vec![info(70, -80.0)],
+        };
+        let c = compare_reports(&a, &b);
+        assert!(c.regressed());
+        assert!(c.deltas.iter().all(|d| d.regression));
+
+        // While improvements are significant but not regressions
+        let c = compare_reports(&b, &a);
+        assert!(!c.regressed());
+        assert!(c.deltas.iter().all(|d| d.significant));
+    }
+
+    /// Loopback radio dropping every `drop_every`th frame
+    struct LoopRadio {
+        drop_every: u32,
+        sent: u32,
+        last: Option<Vec<u8>>,
+    }
+
+    impl Transmit for LoopRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.sent += 1;
+            self.last = Some(data.to_vec()).filter(|_| !self.sent.is_multiple_of(self.drop_every));
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for LoopRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().unwrap();
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::default()))
+        }
+    }
+
+    impl Power for LoopRadio {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl DelayNs for LoopRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn compare_drivers_side_by_side() {
+        let options = CompareDriversOptions {
+            name_a: "good".into(),
+            name_b: "lossy".into(),
+            output: None,
+            ping_pong_options: PingPongOptions {
+                delay: std::time::Duration::from_millis(0).into(),
+                size_sweep: Some("4..8:4".parse().unwrap()),
+                backoff: std::time::Duration::from_millis(1).into(),
+                ..Default::default()
+            },
+        };
+
+        let mut a = LoopRadio {
+            drop_every: u32::MAX,
+            sent: 0,
+            last: None,
+        };
+        let mut b = LoopRadio {
+            drop_every: 2,
+            sent: 0,
+            last: None,
+        };
+        let c = do_compare_drivers(&mut a, &mut b, options).unwrap();
+
+        assert_eq!(c.a.results.len(), 2);
+        assert_eq!(c.b.results[0].received, 50);
+        assert!(c.comparison.regressed());
+
+        let s = c.to_string();
+        assert!(s.contains("loss lossy"));
+        assert!(s.lines().nth(1).unwrap().contains("50.0"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Compressed payload options for transmit and receive operations
+//!
+//! With `--compress`, transmitted payloads are LZ4 compressed (see [`crate::compress`])
+//! ahead of encryption and fragmentation, with receivers decompressing after reassembly
+//! and decryption. Incompressible payloads are sent raw, so the achieved ratio is
+//! tracked in [`CompressionStats`] for evaluating links carrying telemetry.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::warn;
+
+#[cfg(feature = "defmt")]
+use defmt::warn;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use super::CompressionStats;
+use crate::compress::{compress, decompress, decompressed_len, max_compressed_len};
+
+/// Payload compression options
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CompressionOptions {
+    /// Compress payloads with LZ4 (peers must also enable `--compress`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub compress: bool,
+}
+
+impl CompressionOptions {
+    /// Check whether compression is available, always the case where compiled in
+    pub fn available(&self) -> bool {
+        true
+    }
+
+    /// Check whether compression is enabled
+    pub fn enabled(&self) -> bool {
+        self.compress
+    }
+
+    /// Enable or disable compression, as selected by negotiation
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.compress = enabled;
+    }
+
+    /// Create a payload compressor, `None` where compression is disabled
+    pub fn compressor(&self) -> Option<PayloadCompressor> {
+        self.compress.then(PayloadCompressor::default)
+    }
+}
+
+/// Payload compressor, tracking the achieved compression ratio
+#[derive(Debug, Default)]
+pub struct PayloadCompressor {
+    buff: Vec<u8>,
+    stats: CompressionStats,
+}
+
+impl PayloadCompressor {
+    /// Compress a payload for transmission
+    pub fn compress(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut enc = vec![0u8; max_compressed_len(payload.len())];
+        let n = compress(payload, &mut enc).expect("compression buffer sized for payload");
+        enc.truncate(n);
+
+        self.stats.payloads += 1;
+        self.stats.raw_bytes += payload.len() as u64;
+        self.stats.compressed_bytes += n as u64;
+
+        enc
+    }
+
+    /// Decompress a received payload, returning `None` (and counting the failure) where
+    /// the payload is invalid
+    pub fn decompress(&mut self, data: &[u8]) -> Option<&[u8]> {
+        let res = decompressed_len(data).and_then(|n| {
+            self.buff.resize(n, 0);
+            decompress(data, &mut self.buff)
+        });
+
+        match res {
+            Ok(n) => {
+                self.stats.payloads += 1;
+                self.stats.raw_bytes += n as u64;
+                self.stats.compressed_bytes += data.len() as u64;
+                Some(&self.buff[..n])
+            }
+            Err(e) => {
+                self.stats.failures += 1;
+                warn!(
+                    "Decompression failed for {} byte payload: {:?} ({} failures)",
+                    data.len(),
+                    e,
+                    self.stats.failures
+                );
+                None
+            }
+        }
+    }
+
+    /// Compression statistics
+    pub fn stats(&self) -> &CompressionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_compressor() {
+        let options = CompressionOptions { compress: true };
+        let mut tx = options.compressor().unwrap();
+        let mut rx = options.compressor().unwrap();
+        assert!(CompressionOptions::default().compressor().is_none());
+
+        let telemetry = b"{\"temp\":21.5,\"humidity\":40.1,\"temp\":21.5,\"humidity\":40.1}";
+        let enc = tx.compress(telemetry);
+        assert!(enc.len() < telemetry.len());
+        assert_eq!(rx.decompress(&enc), Some(&telemetry[..]));
+        assert!(tx.stats().ratio() > 1.0);
+        assert_eq!(tx.stats(), rx.stats());
+
+        // Failures are counted rather than returned
+        assert_eq!(rx.decompress(&[0x55, 1, 2]), None);
+        assert_eq!(rx.stats().failures, 1);
+    }
+}
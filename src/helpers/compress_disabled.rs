@@ -0,0 +1,57 @@
+//! Compressed payload options for builds without the `compression` feature
+//!
+//! No compression options are accepted and [`CompressionOptions::compressor`] never
+//! returns a compressor, so payloads are sent raw and compression is never negotiated.
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use super::CompressionStats;
+
+/// Payload compression options (requires the `compression` feature)
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CompressionOptions {}
+
+impl CompressionOptions {
+    /// Check whether compression is available, never the case without the `compression`
+    /// feature
+    pub fn available(&self) -> bool {
+        false
+    }
+
+    /// Check whether compression is enabled
+    pub fn enabled(&self) -> bool {
+        false
+    }
+
+    /// Enable or disable compression as selected by negotiation, ignored as compression
+    /// is never available
+    pub fn set_enabled(&mut self, _enabled: bool) {}
+
+    /// Create a payload compressor, always `None` without the `compression` feature
+    pub fn compressor(&self) -> Option<PayloadCompressor> {
+        None
+    }
+}
+
+/// Payload compressor, which can not be created without the `compression` feature
+#[derive(Debug)]
+pub enum PayloadCompressor {}
+
+impl PayloadCompressor {
+    /// Compress a payload for transmission
+    pub fn compress(&mut self, _payload: &[u8]) -> Vec<u8> {
+        match *self {}
+    }
+
+    /// Decompress a received payload
+    pub fn decompress(&mut self, _data: &[u8]) -> Option<&[u8]> {
+        match *self {}
+    }
+
+    /// Compression statistics
+    pub fn stats(&self) -> &CompressionStats {
+        match *self {}
+    }
+}
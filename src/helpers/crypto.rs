@@ -0,0 +1,305 @@
+//! Encrypted payload options for transmit and receive operations
+//!
+//! With `--encrypt`, transmitted payloads are encrypted and authenticated with AES-128-CCM
+//! (see [`crate::crypto`]) using the `--key` provided, while received payloads failing
+//! authentication are counted and logged rather than passed on for output. Alternatively
+//! `--secure` draws the key for a named device from a [`DeviceRegistry`] table, as used to
+//! run link tests over encrypted links without keys on the command line.
+//!
+//! Each [`PayloadCipher`] draws a random session salt and initial counter, so nonces are
+//! not reused across nodes or restarts, and rejects payloads replaying counters already
+//! received from a sending session.
+
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::path::PathBuf;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::warn;
+
+#[cfg(feature = "defmt")]
+use defmt::warn;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use super::DeviceRegistry;
+use crate::crypto::{Cipher, Key, Nonce, parse_key};
+pub(crate) use crate::crypto::{CryptoError, OVERHEAD};
+
+/// Number of sending sessions tracked for replay rejection
+const REPLAY_SESSIONS: usize = 64;
+
+/// Payload encryption options
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CryptoOptions {
+    /// AES-128 key (32 hex characters)
+    #[cfg_attr(feature = "clap", clap(long, value_parser = parse_key_arg))]
+    pub key: Option<Key>,
+
+    /// Encrypt and authenticate payloads with AES-128-CCM using the provided key
+    #[cfg_attr(feature = "clap", clap(long, requires = "key"))]
+    pub encrypt: bool,
+
+    /// Encrypt and authenticate payloads with AES-128-CCM using the key registered for
+    /// this device (by name) in the `--key-store` device table
+    #[cfg_attr(
+        feature = "clap",
+        clap(
+            long,
+            value_name = "DEVICE",
+            requires = "key_store",
+            conflicts_with = "encrypt"
+        )
+    )]
+    pub secure: Option<String>,
+
+    /// Device table (JSON) holding keys for `--secure`
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub key_store: Option<PathBuf>,
+}
+
+/// Parse a key argument, for use as a clap value parser
+pub fn parse_key_arg(s: &str) -> Result<Key, String> {
+    parse_key(s).map_err(|_| format!("invalid key '{}', expected 32 hex characters", s))
+}
+
+impl CryptoOptions {
+    /// Check whether encryption is available, requiring a configured key
+    pub fn available(&self) -> bool {
+        self.key.is_some() || self.secure.is_some()
+    }
+
+    /// Check whether encryption is enabled
+    pub fn enabled(&self) -> bool {
+        self.encrypt || self.secure.is_some()
+    }
+
+    /// Enable or disable encryption as selected by negotiation, enabling encryption with
+    /// a configured key where not already enabled
+    pub fn set_enabled(&mut self, enabled: bool) {
+        match enabled {
+            true if !self.enabled() => self.encrypt = self.key.is_some(),
+            true => (),
+            false => {
+                self.encrypt = false;
+                self.secure = None;
+            }
+        }
+    }
+
+    /// Resolve the configured key, `None` where encryption is disabled
+    pub fn key(&self) -> Result<Option<Key>, std::io::Error> {
+        let name = match &self.secure {
+            Some(n) => n,
+            None if self.encrypt => return Ok(self.key),
+            None => return Ok(None),
+        };
+
+        let path = self
+            .key_store
+            .as_ref()
+            .ok_or_else(|| std::io::Error::other("--secure requires --key-store"))?;
+        let registry = DeviceRegistry::open(path)?;
+
+        let key = registry
+            .find(name)
+            .and_then(|d| d.key.as_deref())
+            .ok_or_else(|| std::io::Error::other(format!("no key registered for '{}'", name)))?;
+
+        Key::try_from(key)
+            .map(Some)
+            .map_err(|_| std::io::Error::other(format!("key for '{}' must be 16 bytes", name)))
+    }
+
+    /// Create a payload cipher, `None` where encryption is disabled
+    pub fn cipher(&self) -> Option<PayloadCipher> {
+        self.key()
+            .expect("Error loading key")
+            .map(|k| PayloadCipher::new(&k))
+    }
+}
+
+/// Payload cipher, rejecting replayed payloads and counting payloads failing decryption
+#[derive(Debug)]
+pub struct PayloadCipher {
+    cipher: Cipher,
+    buff: Vec<u8>,
+    /// Highest counter received for recent sending sessions (by salt), most recent last
+    seen: VecDeque<Nonce>,
+    failures: u32,
+}
+
+impl PayloadCipher {
+    /// Create a payload cipher with the provided key, drawing a random session salt and
+    /// initial counter
+    pub fn new(key: &Key) -> Self {
+        // Hasher keys are randomly seeded per process
+        let r = std::collections::hash_map::RandomState::new().hash_one(0u8);
+        Self::with_session(key, (r >> 32) as u32, r as u32)
+    }
+
+    /// Create a payload cipher with the provided key, session salt and initial counter
+    pub fn with_session(key: &Key, salt: u32, counter: u32) -> Self {
+        Self {
+            cipher: Cipher::new(key, salt, counter),
+            buff: vec![],
+            seen: VecDeque::new(),
+            failures: 0,
+        }
+    }
+
+    /// Check a received nonce follows the highest counter seen from the sending session,
+    /// recording it where it does
+    fn check_replay(&mut self, nonce: Nonce) -> Result<(), CryptoError> {
+        if let Some(i) = self.seen.iter().position(|n| n.salt == nonce.salt) {
+            if !nonce.follows(self.seen[i].counter) {
+                return Err(CryptoError::Replay);
+            }
+            self.seen.remove(i);
+        } else if self.seen.len() >= REPLAY_SESSIONS {
+            self.seen.pop_front();
+        }
+
+        self.seen.push_back(nonce);
+        Ok(())
+    }
+
+    /// Encrypt a payload for transmission
+    pub fn encrypt(&mut self, payload: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut enc = vec![0u8; payload.len() + OVERHEAD];
+        let n = self.cipher.encrypt(payload, &mut enc)?;
+        enc.truncate(n);
+        Ok(enc)
+    }
+
+    /// Decrypt a received payload, returning `None` (and counting the failure) where
+    /// authentication fails or the payload is replayed
+    pub fn decrypt(&mut self, data: &[u8]) -> Option<&[u8]> {
+        self.buff.resize(data.len(), 0);
+
+        let r = self
+            .cipher
+            .decrypt(data, &mut self.buff)
+            .map(|(nonce, p)| (nonce, p.len()));
+
+        match r.and_then(|(nonce, n)| self.check_replay(nonce).map(|_| n)) {
+            Ok(n) => Some(&self.buff[..n]),
+            Err(e) => {
+                self.failures += 1;
+                warn!(
+                    "Decryption failed for {} byte payload: {:?} ({} failures)",
+                    data.len(),
+                    e,
+                    self.failures
+                );
+                None
+            }
+        }
+    }
+
+    /// Encrypt the payload in `buff[offset..n]` in place, leaving any preceding (frame)
+    /// header in the clear, returning the encrypted length including the header
+    pub fn encrypt_in_place(
+        &mut self,
+        buff: &mut [u8],
+        offset: usize,
+        n: usize,
+    ) -> Result<usize, CryptoError> {
+        let enc = self.encrypt(&buff[offset..n])?;
+        let n = offset + enc.len();
+        if n > buff.len() {
+            return Err(CryptoError::BufferTooSmall);
+        }
+
+        buff[offset..n].copy_from_slice(&enc);
+        Ok(n)
+    }
+
+    /// Decrypt the payload in `buff[offset..n]` in place, returning the decrypted length
+    /// including the header or `None` (counting the failure) where authentication fails
+    /// or the payload is replayed
+    pub fn decrypt_in_place(&mut self, buff: &mut [u8], offset: usize, n: usize) -> Option<usize> {
+        let p = self.decrypt(buff.get(offset..n)?)?;
+        let n = offset + p.len();
+
+        buff[offset..n].copy_from_slice(p);
+        Some(n)
+    }
+
+    /// Number of received payloads failing decryption or replayed
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::Device;
+
+    #[test]
+    fn payload_cipher() {
+        let options = CryptoOptions {
+            key: Some([0x11; 16]),
+            encrypt: true,
+            ..Default::default()
+        };
+        let mut tx = options.cipher().unwrap();
+        let mut rx = options.cipher().unwrap();
+
+        let enc = tx.encrypt(b"hello").unwrap();
+        assert_eq!(rx.decrypt(&enc), Some(&b"hello"[..]));
+
+        // Sessions draw distinct salts, with replayed payloads rejected
+        assert_ne!(enc[..4], rx.encrypt(b"hello").unwrap()[..4]);
+        assert_eq!(rx.decrypt(&enc), None);
+        assert_eq!(rx.failures(), 1);
+
+        // In place operation leaves headers in the clear
+        let mut buff = [0u8; 32];
+        buff[..7].copy_from_slice(b"HDhello");
+        let n = tx.encrypt_in_place(&mut buff, 2, 7).unwrap();
+        assert_eq!((n, &buff[..2]), (7 + OVERHEAD, &b"HD"[..]));
+        assert_eq!(rx.decrypt_in_place(&mut buff, 2, n), Some(7));
+        assert_eq!(&buff[..7], b"HDhello");
+
+        // Garbage is counted rather than returned
+        assert_eq!(rx.decrypt(b"not encrypted at all"), None);
+        assert_eq!(rx.decrypt(&[0x01]), None);
+        assert_eq!(rx.failures(), 3);
+
+        assert!(
+            CryptoOptions {
+                encrypt: false,
+                ..options
+            }
+            .cipher()
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn secure_key_store() {
+        let path = std::env::temp_dir().join(format!("radio-keys-{}.json", std::process::id()));
+        let mut r = DeviceRegistry::open(&path).unwrap();
+        let mut d = Device::new(0x0001, "node-a");
+        d.key = Some(vec![0x22; 16]);
+        r.insert(d);
+        r.insert(Device::new(0x0002, "node-b"));
+        r.save().unwrap();
+
+        let options = |name: &str| CryptoOptions {
+            secure: Some(name.to_string()),
+            key_store: Some(path.clone()),
+            ..Default::default()
+        };
+        assert_eq!(options("node-a").key().unwrap(), Some([0x22; 16]));
+        assert!(options("node-b").key().is_err());
+        assert!(options("node-c").key().is_err());
+        assert_eq!(CryptoOptions::default().key().unwrap(), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
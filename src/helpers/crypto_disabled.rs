@@ -0,0 +1,82 @@
+//! Encrypted payload options for builds without the `crypto` feature
+//!
+//! No encryption options are accepted and [`CryptoOptions::cipher`] never returns a
+//! cipher, so operations run unencrypted and never negotiate encryption with peers.
+
+use core::convert::Infallible;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+/// Encryption error, never raised without encryption
+pub(crate) type CryptoError = Infallible;
+
+/// Encryption overhead in bytes, with payloads never encrypted
+pub(crate) const OVERHEAD: usize = 0;
+
+/// Payload encryption options (requires the `crypto` feature)
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CryptoOptions {}
+
+impl CryptoOptions {
+    /// Check whether encryption is available, never the case without the `crypto` feature
+    pub fn available(&self) -> bool {
+        false
+    }
+
+    /// Check whether encryption is enabled
+    pub fn enabled(&self) -> bool {
+        false
+    }
+
+    /// Enable or disable encryption as selected by negotiation, ignored as encryption is
+    /// never available
+    pub fn set_enabled(&mut self, _enabled: bool) {}
+
+    /// Create a payload cipher, always `None` without the `crypto` feature
+    pub fn cipher(&self) -> Option<PayloadCipher> {
+        None
+    }
+}
+
+/// Payload cipher, which can not be created without the `crypto` feature
+#[derive(Debug)]
+pub enum PayloadCipher {}
+
+impl PayloadCipher {
+    /// Encrypt a payload for transmission
+    pub fn encrypt(&mut self, _payload: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        match *self {}
+    }
+
+    /// Decrypt a received payload
+    pub fn decrypt(&mut self, _data: &[u8]) -> Option<&[u8]> {
+        match *self {}
+    }
+
+    /// Encrypt the payload in `buff[offset..n]` in place
+    pub fn encrypt_in_place(
+        &mut self,
+        _buff: &mut [u8],
+        _offset: usize,
+        _n: usize,
+    ) -> Result<usize, CryptoError> {
+        match *self {}
+    }
+
+    /// Decrypt the payload in `buff[offset..n]` in place
+    pub fn decrypt_in_place(
+        &mut self,
+        _buff: &mut [u8],
+        _offset: usize,
+        _n: usize,
+    ) -> Option<usize> {
+        match *self {}
+    }
+
+    /// Number of received payloads failing decryption or replayed
+    pub fn failures(&self) -> u32 {
+        match *self {}
+    }
+}
@@ -0,0 +1,155 @@
+//! Continuous carrier and modulated transmission for regulatory testing
+//!
+//! The `cw` operation places radios implementing [`TestMode`] into a continuous
+//! transmission mode for a fixed duration, emitting either an unmodulated carrier or
+//! (with `--modulated`) a continuous modulated signal, as required for EMC and
+//! regulatory lab measurements.
+
+use std::time::Instant;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+
+use crate::{Power, TestMode, TestSignal};
+
+/// Continuous transmission test options
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CwOptions {
+    /// Transmit a continuous modulated signal rather than an unmodulated carrier
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub modulated: bool,
+
+    /// Duration of the continuous transmission
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10s"))]
+    pub duration: HumanDuration,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+}
+
+impl Default for CwOptions {
+    fn default() -> Self {
+        Self {
+            modulated: false,
+            duration: std::time::Duration::from_secs(10).into(),
+            power: None,
+        }
+    }
+}
+
+impl CwOptions {
+    /// Test signal selected by these options
+    pub fn signal(&self) -> TestSignal {
+        match self.modulated {
+            true => TestSignal::Modulated,
+            false => TestSignal::Carrier,
+        }
+    }
+}
+
+/// Transmit a continuous carrier or modulated signal for the configured duration,
+/// returning the elapsed transmission time in milliseconds
+pub fn do_cw<T, E>(radio: &mut T, options: CwOptions) -> Result<u64, E>
+where
+    T: TestMode<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let signal = options.signal();
+
+    info!(
+        "Starting {:?} test transmission for {}",
+        signal, options.duration
+    );
+
+    let start = Instant::now();
+    radio.start_test_mode(signal)?;
+
+    radio.delay_ms(options.duration.as_millis() as u32);
+
+    let res = radio.stop_test_mode();
+    let elapsed = start.elapsed().as_millis() as u64;
+
+    info!(
+        "Stopped {:?} test transmission after {} ms",
+        signal, elapsed
+    );
+
+    res.map(|_| elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Radio recording test mode changes and requested delays
+    #[derive(Default)]
+    struct TestRadio {
+        power: Option<i8>,
+        signal: Option<TestSignal>,
+        stopped: bool,
+        delay_ms: u64,
+    }
+
+    impl TestMode for TestRadio {
+        type Error = ();
+
+        fn start_test_mode(&mut self, signal: TestSignal) -> Result<(), Self::Error> {
+            self.signal = Some(signal);
+            Ok(())
+        }
+
+        fn stop_test_mode(&mut self) -> Result<(), Self::Error> {
+            self.stopped = self.signal.is_some();
+            Ok(())
+        }
+    }
+
+    impl Power for TestRadio {
+        type Error = ();
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            self.power = Some(power);
+            Ok(())
+        }
+    }
+
+    impl DelayNs for TestRadio {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delay_ms += ns as u64 / 1_000_000;
+        }
+    }
+
+    #[test]
+    fn continuous_transmission() {
+        let mut radio = TestRadio::default();
+        let options = CwOptions {
+            modulated: true,
+            duration: std::time::Duration::from_secs(30).into(),
+            power: Some(10),
+        };
+
+        do_cw(&mut radio, options).unwrap();
+
+        assert_eq!(radio.signal, Some(TestSignal::Modulated));
+        assert_eq!(radio.power, Some(10));
+        assert_eq!(radio.delay_ms, 30_000);
+        assert!(radio.stopped);
+
+        assert_eq!(CwOptions::default().signal(), TestSignal::Carrier);
+    }
+}
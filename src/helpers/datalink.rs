@@ -0,0 +1,191 @@
+//! Capture datalink selection and LoRaTap encapsulation
+//!
+//! Captures default to IEEE 802.15.4, with `--pcap-datalink` selecting other link-layer
+//! header types so Wireshark can dissect LoRa, BLE and raw captures. With `lora-tap`
+//! each packet is prefixed with a LoRaTap (v0) header carrying the frame RSSI and the
+//! `--loratap-*` radio configuration.
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use super::{DecodedFrame, LINKTYPE_IEEE802_15_4, PacketSink};
+
+/// Capture link-layer header type
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PcapDatalink {
+    /// IEEE 802.15.4 without FCS (`ieee802154`)
+    Ieee802154,
+    /// IEEE 802.15.4 with FCS (`ieee802154-fcs`)
+    Ieee802154Fcs,
+    /// LoRa with LoRaTap headers (`lora-tap`)
+    LoRaTap,
+    /// Bluetooth LE link layer (`ble-ll`)
+    BleLl,
+    /// Raw payloads, as user-defined protocol 0 (`raw`)
+    Raw,
+    /// Other tcpdump LINKTYPE number
+    Other(u32),
+}
+
+impl PcapDatalink {
+    /// tcpdump LINKTYPE number
+    pub fn linktype(&self) -> u32 {
+        match self {
+            PcapDatalink::Ieee802154 => 230,
+            PcapDatalink::Ieee802154Fcs => LINKTYPE_IEEE802_15_4,
+            PcapDatalink::LoRaTap => 270,
+            PcapDatalink::BleLl => 251,
+            PcapDatalink::Raw => 147,
+            PcapDatalink::Other(l) => *l,
+        }
+    }
+}
+
+impl std::str::FromStr for PcapDatalink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ieee802154" => Ok(PcapDatalink::Ieee802154),
+            "ieee802154-fcs" => Ok(PcapDatalink::Ieee802154Fcs),
+            "lora-tap" => Ok(PcapDatalink::LoRaTap),
+            "ble-ll" => Ok(PcapDatalink::BleLl),
+            "raw" => Ok(PcapDatalink::Raw),
+            _ => s.parse().map(PcapDatalink::Other).map_err(|_| {
+                format!(
+                    "unrecognised datalink '{}', expected ieee802154, ieee802154-fcs, lora-tap, ble-ll, raw or a LINKTYPE number",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+impl core::fmt::Display for PcapDatalink {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PcapDatalink::Ieee802154 => write!(f, "ieee802154"),
+            PcapDatalink::Ieee802154Fcs => write!(f, "ieee802154-fcs"),
+            PcapDatalink::LoRaTap => write!(f, "lora-tap"),
+            PcapDatalink::BleLl => write!(f, "ble-ll"),
+            PcapDatalink::Raw => write!(f, "raw"),
+            PcapDatalink::Other(l) => write!(f, "{}", l),
+        }
+    }
+}
+
+/// LoRaTap header length in bytes
+pub const LORATAP_LEN: usize = 15;
+
+/// Radio configuration recorded in LoRaTap headers
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct LoRaTapOptions {
+    /// Carrier frequency (Hz) recorded in LoRaTap headers
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub loratap_frequency: u32,
+
+    /// Bandwidth (kHz) recorded in LoRaTap headers
+    #[cfg_attr(feature = "clap", clap(long, default_value = "125"))]
+    pub loratap_bandwidth: u32,
+
+    /// Spreading factor recorded in LoRaTap headers
+    #[cfg_attr(feature = "clap", clap(long, default_value = "7"))]
+    pub loratap_sf: u8,
+
+    /// Sync word recorded in LoRaTap headers (52, or 0x34, for public networks)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "52"))]
+    pub loratap_sync_word: u8,
+}
+
+impl Default for LoRaTapOptions {
+    fn default() -> Self {
+        Self {
+            loratap_frequency: 0,
+            loratap_bandwidth: 125,
+            loratap_sf: 7,
+            loratap_sync_word: 0x34,
+        }
+    }
+}
+
+impl LoRaTapOptions {
+    /// Encode a LoRaTap (v0) header for a received frame
+    ///
+    /// SNR is not reported by [`ReceiveInfo`](crate::ReceiveInfo) and is recorded as zero.
+    pub fn header(&self, rssi: i16) -> [u8; LORATAP_LEN] {
+        // RSSI is encoded as an offset from -139 dBm
+        let rssi = (rssi + 139).clamp(0, u8::MAX as i16) as u8;
+
+        let mut h = [0u8; LORATAP_LEN];
+        // Version (0) and padding
+        h[2..4].copy_from_slice(&(LORATAP_LEN as u16).to_be_bytes());
+        h[4..8].copy_from_slice(&self.loratap_frequency.to_be_bytes());
+        // Bandwidth in 125 kHz steps
+        h[8] = (self.loratap_bandwidth / 125) as u8;
+        h[9] = self.loratap_sf;
+        // Packet, maximum and current RSSI, then SNR
+        h[10] = rssi;
+        h[11] = rssi;
+        h[12] = rssi;
+        h[13] = 0;
+        h[14] = self.loratap_sync_word;
+        h
+    }
+}
+
+/// Capture sink prefixing each packet with a LoRaTap header
+pub struct LoRaTapSink {
+    inner: Box<dyn PacketSink>,
+    options: LoRaTapOptions,
+}
+
+impl LoRaTapSink {
+    /// Wrap a capture sink, encapsulating packets with LoRaTap headers
+    pub fn new(inner: Box<dyn PacketSink>, options: LoRaTapOptions) -> Self {
+        Self { inner, options }
+    }
+}
+
+impl PacketSink for LoRaTapSink {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        let mut data = self.options.header(frame.rssi).to_vec();
+        data.extend_from_slice(&frame.data);
+
+        self.inner.write(&DecodedFrame {
+            data,
+            ..frame.clone()
+        })
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lora_tap_headers() {
+        assert_eq!("lora-tap".parse(), Ok(PcapDatalink::LoRaTap));
+        assert_eq!("147".parse(), Ok(PcapDatalink::Other(147)));
+        assert!("lora".parse::<PcapDatalink>().is_err());
+        assert_eq!(PcapDatalink::LoRaTap.linktype(), 270);
+        assert_eq!(PcapDatalink::BleLl.to_string(), "ble-ll");
+
+        let options = LoRaTapOptions {
+            loratap_frequency: 868_100_000,
+            loratap_bandwidth: 250,
+            loratap_sf: 9,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.header(-100),
+            [
+                0x00, 0x00, 0x00, 0x0f, 0x33, 0xbe, 0x27, 0xa0, 0x02, 0x09, 39, 39, 39, 0x00, 0x34
+            ]
+        );
+    }
+}
@@ -0,0 +1,460 @@
+//! Pluggable protocol decoders for receive output
+//!
+//! Decoders summarise recognised frames for display, allowing `rx` to pretty-print
+//! protocols inline rather than printing raw hex. Built-in decoders cover IEEE 802.15.4
+//! MAC frames, this crate's [`frame`](crate::frame) headers and LoRaWAN PHY payloads, and
+//! applications may register their own via [`DecoderRegistry::register`]. Decoders may
+//! also describe the [`Field`]s of a frame, used for annotated console output (see
+//! [`AnnotatedConsoleSink`](super::AnnotatedConsoleSink)).
+
+use std::fmt::Write as _;
+use std::ops::Range;
+
+/// Kind of a decoded frame field, used to select highlighting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Frame control, flags and headers
+    Control,
+    /// Source, destination and network addresses
+    Address,
+    /// Sequence numbers and frame counters
+    Sequence,
+    /// Frame payload
+    Payload,
+    /// CRC, FCS or MIC
+    Check,
+}
+
+/// Labelled byte range of a decoded frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub label: &'static str,
+    pub kind: FieldKind,
+    pub range: Range<usize>,
+}
+
+impl Field {
+    /// Create a field covering the provided byte range
+    pub fn new(label: &'static str, kind: FieldKind, range: Range<usize>) -> Self {
+        Self { label, kind, range }
+    }
+}
+
+/// Decoder trait for protocol pretty-printers
+pub trait Decoder: Send + Sync {
+    /// Decoder name, used for selection with `--decode`
+    fn name(&self) -> &str;
+
+    /// Decode a frame to a human readable summary, returning `None` if the frame is not recognised
+    fn decode(&self, data: &[u8]) -> Option<String>;
+
+    /// Describe the fields of a frame in order, returning `None` where the frame is not
+    /// recognised or the decoder does not support field annotation
+    fn fields(&self, _data: &[u8]) -> Option<Vec<Field>> {
+        None
+    }
+}
+
+/// Registry of available decoders
+pub struct DecoderRegistry {
+    decoders: Vec<Box<dyn Decoder>>,
+}
+
+impl Default for DecoderRegistry {
+    fn default() -> Self {
+        let mut r = Self::new();
+        r.register(Box::new(Ieee802154Decoder));
+        r.register(Box::new(FrameDecoder));
+        r.register(Box::new(LoRaWanDecoder));
+        r.register(Box::new(TextDecoder));
+        r
+    }
+}
+
+impl DecoderRegistry {
+    /// Create an empty registry, see [`DecoderRegistry::default`] for built-in decoders
+    pub fn new() -> Self {
+        Self { decoders: vec![] }
+    }
+
+    /// Register a decoder, replacing any existing decoder with the same name
+    pub fn register(&mut self, decoder: Box<dyn Decoder>) {
+        self.decoders.retain(|d| d.name() != decoder.name());
+        self.decoders.push(decoder);
+    }
+
+    /// Names of registered decoders
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.decoders.iter().map(|d| d.name())
+    }
+
+    /// Fetch a decoder by name
+    pub fn get(&self, name: &str) -> Option<&dyn Decoder> {
+        self.decoders
+            .iter()
+            .find(|d| d.name() == name)
+            .map(|d| d.as_ref())
+    }
+
+    /// Decode using the selected decoders in order (or all registered decoders for `auto`),
+    /// returning the name of the first matching decoder and its summary
+    pub fn decode(&self, selected: &[String], data: &[u8]) -> Option<(String, String)> {
+        let try_decode = |d: &dyn Decoder| d.decode(data).map(|s| (d.name().to_string(), s));
+
+        match selected.iter().any(|s| s == "auto") {
+            true => self.decoders.iter().find_map(|d| try_decode(d.as_ref())),
+            false => selected
+                .iter()
+                .filter_map(|s| self.get(s))
+                .find_map(try_decode),
+        }
+    }
+
+    /// Describe frame fields using the selected decoders in order (or all registered
+    /// decoders for `auto`), returning the name of the first matching decoder and its fields
+    pub fn fields(&self, selected: &[String], data: &[u8]) -> Option<(String, Vec<Field>)> {
+        let try_fields = |d: &dyn Decoder| d.fields(data).map(|f| (d.name().to_string(), f));
+
+        match selected.iter().any(|s| s == "auto") {
+            true => self.decoders.iter().find_map(|d| try_fields(d.as_ref())),
+            false => selected
+                .iter()
+                .filter_map(|s| self.get(s))
+                .find_map(try_fields),
+        }
+    }
+}
+
+/// IEEE 802.15.4 MAC frame decoder
+pub struct Ieee802154Decoder;
+
+impl Decoder for Ieee802154Decoder {
+    fn name(&self) -> &str {
+        "ieee802154"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<String> {
+        if data.len() < 3 {
+            return None;
+        }
+
+        let fc = u16::from_le_bytes([data[0], data[1]]);
+        let kind = match fc & 0x07 {
+            0 => "beacon",
+            1 => "data",
+            2 => "ack",
+            3 => "command",
+            _ => return None,
+        };
+        let (dst_mode, version, src_mode) =
+            ((fc >> 10) & 0x03, (fc >> 12) & 0x03, (fc >> 14) & 0x03);
+        let pan_compression = fc & (1 << 6) != 0;
+
+        // Reserved addressing mode and version values
+        if dst_mode == 1 || src_mode == 1 || version == 3 {
+            return None;
+        }
+
+        let mut s = format!("{} seq={}", kind, data[2]);
+        let mut i = 3;
+
+        let mut addr = |mode: u16, pan: bool, s: &mut String, label: &str| -> Option<()> {
+            if mode == 0 {
+                return Some(());
+            }
+            if pan {
+                let p = data.get(i..i + 2)?;
+                let _ = write!(s, " {}_pan={:04x}", label, u16::from_le_bytes([p[0], p[1]]));
+                i += 2;
+            }
+            let len = if mode == 2 { 2 } else { 8 };
+            let a = data.get(i..i + len)?;
+            let _ = write!(s, " {}=", label);
+            a.iter().rev().for_each(|b| {
+                let _ = write!(s, "{:02x}", b);
+            });
+            i += len;
+            Some(())
+        };
+
+        addr(dst_mode, true, &mut s, "dst")?;
+        addr(src_mode, !pan_compression || dst_mode == 0, &mut s, "src")?;
+
+        if fc & (1 << 3) != 0 {
+            s.push_str(" secured");
+        }
+        if fc & (1 << 5) != 0 {
+            s.push_str(" ack_req");
+        }
+
+        // Payload length includes the FCS where present
+        let _ = write!(s, " payload={}B", data.len().saturating_sub(i));
+
+        Some(s)
+    }
+
+    fn fields(&self, data: &[u8]) -> Option<Vec<Field>> {
+        // Validate the frame, as for the summary
+        self.decode(data)?;
+
+        let fc = u16::from_le_bytes([data[0], data[1]]);
+        let (dst_mode, src_mode) = ((fc >> 10) & 0x03, (fc >> 14) & 0x03);
+        let pan_compression = fc & (1 << 6) != 0;
+
+        let mut f = vec![
+            Field::new("fc", FieldKind::Control, 0..2),
+            Field::new("seq", FieldKind::Sequence, 2..3),
+        ];
+        let mut i = 3;
+
+        let mut addr = |mode: u16, pan: bool, labels: (&'static str, &'static str)| {
+            if mode == 0 {
+                return;
+            }
+            if pan {
+                f.push(Field::new(labels.0, FieldKind::Address, i..i + 2));
+                i += 2;
+            }
+            let len = if mode == 2 { 2 } else { 8 };
+            f.push(Field::new(labels.1, FieldKind::Address, i..i + len));
+            i += len;
+        };
+        addr(dst_mode, true, ("dst_pan", "dst"));
+        addr(
+            src_mode,
+            !pan_compression || dst_mode == 0,
+            ("src_pan", "src"),
+        );
+
+        // Trailing FCS, where the frame is long enough to carry one
+        let fcs = match data.len() >= i + 2 {
+            true => data.len() - 2,
+            false => data.len(),
+        };
+        if fcs > i {
+            f.push(Field::new("payload", FieldKind::Payload, i..fcs));
+        }
+        if fcs < data.len() {
+            f.push(Field::new("fcs", FieldKind::Check, fcs..data.len()));
+        }
+
+        Some(f)
+    }
+}
+
+/// Decoder for this crate's [`frame`](crate::frame) headers
+pub struct FrameDecoder;
+
+impl Decoder for FrameDecoder {
+    fn name(&self) -> &str {
+        "frame"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<String> {
+        let (h, payload) = crate::frame::decode(data).ok()?;
+
+        Some(format!(
+            "flags={:02x} seq={} {:04x} -> {:04x} payload={}B",
+            h.flags,
+            h.seq,
+            h.src,
+            h.dst,
+            payload.len()
+        ))
+    }
+
+    fn fields(&self, data: &[u8]) -> Option<Vec<Field>> {
+        crate::frame::decode(data).ok()?;
+
+        let mut f = vec![
+            Field::new("flags", FieldKind::Control, 0..1),
+            Field::new("seq", FieldKind::Sequence, 1..2),
+            Field::new("dst", FieldKind::Address, 2..4),
+            Field::new("src", FieldKind::Address, 4..6),
+        ];
+        if data.len() > 6 {
+            f.push(Field::new("payload", FieldKind::Payload, 6..data.len()));
+        }
+
+        Some(f)
+    }
+}
+
+/// LoRaWAN PHY payload decoder
+pub struct LoRaWanDecoder;
+
+impl Decoder for LoRaWanDecoder {
+    fn name(&self) -> &str {
+        "lorawan"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<String> {
+        // MHDR + MIC at minimum
+        let mhdr = *data.first()?;
+        if mhdr & 0x03 != 0 || data.len() < 5 {
+            return None;
+        }
+
+        let kind = match mhdr >> 5 {
+            0 => "join-request",
+            1 => "join-accept",
+            2 => "unconfirmed-up",
+            3 => "unconfirmed-down",
+            4 => "confirmed-up",
+            5 => "confirmed-down",
+            6 => "rejoin-request",
+            _ => "proprietary",
+        };
+
+        let mut s = kind.to_string();
+        match mhdr >> 5 {
+            // Data frames, MHDR | DevAddr | FCtrl | FCnt | FOpts | [FPort | FRMPayload] | MIC
+            2..=5 => {
+                if data.len() < 12 {
+                    return None;
+                }
+                let dev_addr = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+                let fctrl = data[5];
+                let fcnt = u16::from_le_bytes([data[6], data[7]]);
+                let fopts = (fctrl & 0x0f) as usize;
+
+                let _ = write!(s, " dev_addr={:08x} fcnt={}", dev_addr, fcnt);
+                if fctrl & 0x80 != 0 {
+                    s.push_str(" adr");
+                }
+                if fctrl & 0x20 != 0 {
+                    s.push_str(" ack");
+                }
+
+                let port = 8 + fopts;
+                if data.len() > port + 4 {
+                    let _ = write!(
+                        s,
+                        " fport={} payload={}B",
+                        data[port],
+                        data.len() - port - 5
+                    );
+                }
+            }
+            // Join request, MHDR | JoinEUI | DevEUI | DevNonce | MIC
+            0 if data.len() == 23 => {
+                let eui = |d: &[u8]| d.iter().rev().fold(0u64, |a, b| a << 8 | *b as u64);
+                let _ = write!(
+                    s,
+                    " join_eui={:016x} dev_eui={:016x}",
+                    eui(&data[1..9]),
+                    eui(&data[9..17])
+                );
+            }
+            0 => return None,
+            _ => {
+                let _ = write!(s, " len={}", data.len());
+            }
+        }
+
+        Some(s)
+    }
+
+    fn fields(&self, data: &[u8]) -> Option<Vec<Field>> {
+        self.decode(data)?;
+
+        let mic = data.len() - 4;
+        let mut f = vec![Field::new("mhdr", FieldKind::Control, 0..1)];
+        let mut i = 1;
+
+        // Data frame headers, as for the summary
+        if (2..=5).contains(&(data[0] >> 5)) {
+            let fopts = (data[5] & 0x0f) as usize;
+            f.push(Field::new("dev_addr", FieldKind::Address, 1..5));
+            f.push(Field::new("fctrl", FieldKind::Control, 5..6));
+            f.push(Field::new("fcnt", FieldKind::Sequence, 6..8));
+            i = 8;
+            if fopts > 0 && i + fopts <= mic {
+                f.push(Field::new("fopts", FieldKind::Control, i..i + fopts));
+                i += fopts;
+            }
+            if i < mic {
+                f.push(Field::new("fport", FieldKind::Control, i..i + 1));
+                i += 1;
+            }
+        }
+
+        if i < mic {
+            f.push(Field::new("payload", FieldKind::Payload, i..mic));
+        }
+        f.push(Field::new("mic", FieldKind::Check, mic..data.len()));
+
+        Some(f)
+    }
+}
+
+/// UTF-8 text decoder
+pub struct TextDecoder;
+
+impl Decoder for TextDecoder {
+    fn name(&self) -> &str {
+        "text"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<String> {
+        std::str::from_utf8(data).ok().map(|s| format!("'{}'", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_ieee802154() {
+        // Data frame, PAN ID compression, short addresses, ack requested
+        let data = [
+            0x61, 0x88, 0x2a, 0xcd, 0xab, 0x02, 0x00, 0x01, 0x00, 0xaa, 0xbb, 0x00, 0x00,
+        ];
+        assert_eq!(
+            Ieee802154Decoder.decode(&data).unwrap(),
+            "data seq=42 dst_pan=abcd dst=0002 src=0001 ack_req payload=4B"
+        );
+        assert_eq!(Ieee802154Decoder.decode(&[0x07, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn decode_lorawan() {
+        let data = [
+            0x40, 0x04, 0x03, 0x02, 0x01, 0x80, 0x05, 0x00, 0x0a, 0x11, 0x22, 0x01, 0x02, 0x03,
+            0x04,
+        ];
+        assert_eq!(
+            LoRaWanDecoder.decode(&data).unwrap(),
+            "unconfirmed-up dev_addr=01020304 fcnt=5 adr fport=10 payload=2B"
+        );
+    }
+
+    #[test]
+    fn decoder_registry() {
+        struct Custom;
+        impl Decoder for Custom {
+            fn name(&self) -> &str {
+                "custom"
+            }
+            fn decode(&self, data: &[u8]) -> Option<String> {
+                (data.first() == Some(&0xfe)).then(|| "custom frame".into())
+            }
+        }
+
+        let mut r = DecoderRegistry::default();
+        r.register(Box::new(Custom));
+        assert!(r.names().any(|n| n == "custom"));
+
+        let sel = vec!["custom".to_string(), "text".to_string()];
+        assert_eq!(
+            r.decode(&sel, &[0xfe]),
+            Some(("custom".into(), "custom frame".into()))
+        );
+        assert_eq!(
+            r.decode(&sel, b"hello"),
+            Some(("text".into(), "'hello'".into()))
+        );
+        assert_eq!(r.decode(&["frame".into()], &[0x01]), None);
+    }
+}
@@ -0,0 +1,298 @@
+//! Device registry, mapping node addresses to names, keys and last-seen statistics
+//!
+//! The registry labels received frames with friendly device names and tracks when (and
+//! how strongly) each device was last heard. Registries are held in memory and persisted
+//! through a pluggable [`DeviceStore`], with [`JsonFileStore`] provided for device tables
+//! maintained alongside captures.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::json;
+use super::parse_hex;
+use crate::frame::{self, Address};
+
+/// Registered device
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    /// Node address
+    pub address: Address,
+    /// Human readable name
+    pub name: String,
+    /// Device key, hex encoded in persisted tables
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "hex_key")]
+    pub key: Option<Vec<u8>>,
+    /// Time the device was last heard, in microseconds since the unix epoch
+    #[serde(default)]
+    pub last_seen_us: Option<u64>,
+    /// RSSI of the most recently received frame
+    #[serde(default)]
+    pub last_rssi: Option<i16>,
+    /// Number of frames received from the device
+    #[serde(default)]
+    pub frames: u64,
+}
+
+impl Device {
+    /// Create a new device with the provided address and name
+    pub fn new(address: Address, name: &str) -> Self {
+        Self {
+            address,
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+mod hex_key {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(key: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        let hex: Option<String> = key
+            .as_ref()
+            .map(|k| k.iter().map(|b| format!("{:02x}", b)).collect());
+        match hex {
+            Some(h) => s.serialize_str(&h),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|h| super::parse_hex(&h).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Persistence backend for device registries
+pub trait DeviceStore: Send {
+    /// Load persisted devices
+    fn load(&mut self) -> Result<Vec<Device>, std::io::Error>;
+
+    /// Persist the provided devices
+    fn save(&mut self, devices: &[Device]) -> Result<(), std::io::Error>;
+}
+
+/// In-memory store, devices are not persisted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryStore;
+
+impl DeviceStore for MemoryStore {
+    fn load(&mut self) -> Result<Vec<Device>, std::io::Error> {
+        Ok(vec![])
+    }
+
+    fn save(&mut self, _devices: &[Device]) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+/// JSON file store, persisting devices as a JSON array
+///
+/// Missing files are treated as an empty registry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    /// Create a store backed by the provided file
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl DeviceStore for JsonFileStore {
+    fn load(&mut self) -> Result<Vec<Device>, std::io::Error> {
+        match std::fs::read(&self.path) {
+            Ok(d) => json::from_slice(&d),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&mut self, devices: &[Device]) -> Result<(), std::io::Error> {
+        let d = json::to_vec_pretty(devices)?;
+        std::fs::write(&self.path, d)
+    }
+}
+
+/// Device registry
+pub struct DeviceRegistry {
+    devices: BTreeMap<Address, Device>,
+    store: Box<dyn DeviceStore>,
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        Self {
+            devices: BTreeMap::new(),
+            store: Box::new(MemoryStore),
+        }
+    }
+}
+
+impl core::fmt::Debug for DeviceRegistry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DeviceRegistry")
+            .field("devices", &self.devices)
+            .finish()
+    }
+}
+
+impl DeviceRegistry {
+    /// Create an empty in-memory registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry using the provided store, loading persisted devices
+    pub fn with_store(mut store: Box<dyn DeviceStore>) -> Result<Self, std::io::Error> {
+        let devices = store.load()?.into_iter().map(|d| (d.address, d)).collect();
+        Ok(Self { devices, store })
+    }
+
+    /// Open a registry backed by a JSON file
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        Self::with_store(Box::new(JsonFileStore::new(path)))
+    }
+
+    /// Add or replace a device
+    pub fn insert(&mut self, device: Device) -> Option<Device> {
+        self.devices.insert(device.address, device)
+    }
+
+    /// Remove a device
+    pub fn remove(&mut self, address: Address) -> Option<Device> {
+        self.devices.remove(&address)
+    }
+
+    /// Fetch a device by address
+    pub fn get(&self, address: Address) -> Option<&Device> {
+        self.devices.get(&address)
+    }
+
+    /// Fetch a device by name
+    pub fn find(&self, name: &str) -> Option<&Device> {
+        self.devices.values().find(|d| d.name == name)
+    }
+
+    /// Iterate over registered devices in address order
+    pub fn iter(&self) -> impl Iterator<Item = &Device> {
+        self.devices.values()
+    }
+
+    /// Number of registered devices
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Check whether the registry is empty
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Label an address with the device name where registered, or the hex address otherwise
+    pub fn label(&self, address: Address) -> String {
+        match self.devices.get(&address) {
+            Some(d) => d.name.clone(),
+            None => format!("{:04x}", address),
+        }
+    }
+
+    /// Record a frame received from a registered device, returning the device where registered
+    pub fn seen(&mut self, address: Address, rssi: i16, timestamp_us: u64) -> Option<&Device> {
+        let d = self.devices.get_mut(&address)?;
+        d.last_seen_us = Some(timestamp_us);
+        d.last_rssi = Some(rssi);
+        d.frames += 1;
+        Some(d)
+    }
+
+    /// Record a received frame by its [`frame`](crate::frame) header source address,
+    /// returning the source label where the frame has a valid header
+    pub fn seen_frame(&mut self, data: &[u8], rssi: i16, timestamp_us: u64) -> Option<String> {
+        let (h, _) = frame::decode(data).ok()?;
+        self.seen(h.src, rssi, timestamp_us);
+        Some(self.label(h.src))
+    }
+
+    /// Persist the registry to its store
+    pub fn save(&mut self) -> Result<(), std::io::Error> {
+        let devices: Vec<_> = self.devices.values().cloned().collect();
+        self.store.save(&devices)
+    }
+}
+
+/// Parse a `ADDRESS=NAME` device entry, with a hex address (eg. `0x0102=sensor-1`)
+pub fn parse_device(s: &str) -> Result<Device, String> {
+    let (a, n) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected ADDRESS=NAME, found '{}'", s))?;
+    let a = parse_hex(a)?;
+    let address = match a.as_slice() {
+        [h, l] => u16::from_be_bytes([*h, *l]),
+        _ => return Err(format!("address '{:02x?}' must be two bytes", a)),
+    };
+
+    Ok(Device::new(address, n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Header;
+
+    #[test]
+    fn device_registry_labels() {
+        let mut r = DeviceRegistry::new();
+        r.insert(parse_device("0x0102=sensor-1").unwrap());
+        assert_eq!(r.label(0x0102), "sensor-1");
+        assert_eq!(r.label(0x0103), "0103");
+        assert_eq!(r.find("sensor-1").map(|d| d.address), Some(0x0102));
+
+        let mut buff = [0u8; 8];
+        let n = frame::encode(&Header::new(0x0102, 0x0001, 0), &[0xaa], &mut buff).unwrap();
+        assert_eq!(
+            r.seen_frame(&buff[..n], -70, 10).as_deref(),
+            Some("sensor-1")
+        );
+        assert_eq!(r.seen_frame(&[0x00], -70, 10), None);
+
+        let d = r.get(0x0102).unwrap();
+        assert_eq!(
+            (d.last_seen_us, d.last_rssi, d.frames),
+            (Some(10), Some(-70), 1)
+        );
+
+        assert!(parse_device("0102").is_err());
+        assert!(parse_device("01=x").is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn device_registry_persistence() {
+        let path = std::env::temp_dir().join(format!("radio-devices-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut r = DeviceRegistry::open(&path).unwrap();
+        assert!(r.is_empty());
+
+        let mut d = Device::new(0x0010, "node");
+        d.key = Some(vec![0xde, 0xad]);
+        r.insert(d.clone());
+        r.seen(0x0010, -50, 5);
+        r.save().unwrap();
+
+        let s = std::fs::read_to_string(&path).unwrap();
+        assert!(s.contains("\"dead\""));
+
+        let r = DeviceRegistry::open(&path).unwrap();
+        assert_eq!(r.get(0x0010).unwrap().key, d.key);
+        assert_eq!(r.get(0x0010).unwrap().frames, 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+//! Differential capture comparing two receivers
+//!
+//! [`do_receive_diff`] receives on two radios tuned to the same channel, matching frames
+//! received by both (by payload, within `--match-window`) and reporting frames seen by
+//! only one of them. The RSSI delta between receivers is tracked over matched frames, and
+//! frames missed by one receiver are reported with the RSSI expected at that receiver
+//! (the received RSSI less the mean delta), isolating receiver sensitivity or antenna
+//! problems from transmitter issues.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{MultiRxError, Samples, Shutdown, squelched};
+use crate::{Receive, ReceiveInfo, blocking::BlockingOptions};
+
+/// Configuration for differential capture
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct DiffRxOptions {
+    /// Maximum interval between receptions of the same frame by both radios
+    #[cfg_attr(feature = "clap", clap(long, default_value = "50ms"))]
+    pub match_window: HumanDuration,
+
+    /// Run for this duration (runs until interrupted if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub duration: Option<HumanDuration>,
+
+    /// Print differential statistics at this interval
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub stats_interval: Option<HumanDuration>,
+
+    /// Suppress frames received below this RSSI (dBm), along with zero-length receptions
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub squelch: Option<i16>,
+
+    /// Shutdown signal, stopping capture cleanly once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for DiffRxOptions {
+    fn default() -> Self {
+        Self {
+            match_window: Duration::from_millis(50).into(),
+            duration: None,
+            stats_interval: None,
+            squelch: None,
+            shutdown: Shutdown::global(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+/// Frame received by only one of the radios
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MissedFrame {
+    /// Index of the radio receiving the frame
+    pub radio: usize,
+    pub data: Vec<u8>,
+    /// RSSI at the receiving radio
+    pub rssi: i16,
+    /// RSSI expected at the other radio, from the mean delta over matched frames
+    pub expected_rssi: Option<f32>,
+}
+
+impl core::fmt::Display for MissedFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "only radio={}: {} bytes rssi {}",
+            self.radio,
+            self.data.len(),
+            self.rssi
+        )?;
+        if let Some(e) = self.expected_rssi {
+            write!(f, " (expected {:.1} at radio={})", e, 1 - self.radio)?;
+        }
+        Ok(())
+    }
+}
+
+/// Differential capture results
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiffRxReport {
+    /// Frames received by both radios
+    pub matched: u64,
+    /// Frames received by only the first radio
+    pub only_a: u64,
+    /// Frames received by only the second radio
+    pub only_b: u64,
+    /// RSSI delta (first - second radio) over matched frames
+    pub rssi_delta: Samples,
+    /// Frames received by only one radio
+    pub missed: Vec<MissedFrame>,
+}
+
+impl DiffRxReport {
+    /// Record a frame received by only one radio
+    fn miss(&mut self, radio: usize, data: Vec<u8>, rssi: i16) -> &MissedFrame {
+        let delta = self.rssi_delta.mean();
+        let expected_rssi = match radio {
+            0 => delta.map(|d| rssi as f32 - d),
+            _ => delta.map(|d| rssi as f32 + d),
+        };
+        match radio {
+            0 => self.only_a += 1,
+            _ => self.only_b += 1,
+        }
+
+        self.missed.push(MissedFrame {
+            radio,
+            data,
+            rssi,
+            expected_rssi,
+        });
+        self.missed.last().unwrap()
+    }
+}
+
+impl core::fmt::Display for DiffRxReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} matched, {} only radio=0, {} only radio=1",
+            self.matched, self.only_a, self.only_b
+        )?;
+        if let (Some(m), Some(s)) = (self.rssi_delta.mean(), self.rssi_delta.std_dev()) {
+            write!(f, ", rssi delta {:.1} (std dev {:.1})", m, s)?;
+        }
+        Ok(())
+    }
+}
+
+/// Frame awaiting reception by the other radio
+struct Pending {
+    radio: usize,
+    data: Vec<u8>,
+    rssi: i16,
+    received: Instant,
+}
+
+/// Receive on two radios tuned to the same channel, reporting frames received by only one
+///
+/// Receive errors are logged and receive restarted, with failures to restart receive
+/// returned with the failing radio index.
+pub fn do_receive_diff<T, I, E>(
+    mut radios: [&mut T; 2],
+    buff: &mut [u8],
+    options: DiffRxOptions,
+) -> Result<DiffRxReport, MultiRxError<E>>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut report = DiffRxReport::default();
+    let mut pending = VecDeque::<Pending>::new();
+
+    for (i, r) in radios.iter_mut().enumerate() {
+        r.start_receive()
+            .map_err(|error| MultiRxError { radio: i, error })?;
+    }
+
+    info!("Differential capture on 2 radios");
+
+    let start = Instant::now();
+    let mut last_stats = start;
+
+    loop {
+        if options.shutdown.is_triggered()
+            || options.duration.is_some_and(|d| start.elapsed() >= *d)
+        {
+            break;
+        }
+
+        for (i, r) in radios.iter_mut().enumerate() {
+            let received = r.check_receive(true).and_then(|ok| match ok {
+                true => r.get_received(buff).map(Some),
+                false => Ok(None),
+            });
+            let (n, info) = match received {
+                Ok(Some(v)) => v,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("Receive error (radio={}): {:?}", i, e);
+                    r.start_receive()
+                        .map_err(|error| MultiRxError { radio: i, error })?;
+                    continue;
+                }
+            };
+            r.start_receive()
+                .map_err(|error| MultiRxError { radio: i, error })?;
+
+            if squelched(options.squelch, info.rssi(), n) {
+                continue;
+            }
+
+            // Match against the oldest pending frame from the other radio
+            let data = &buff[..n];
+            match pending
+                .iter()
+                .position(|p| p.radio != i && p.data == data)
+                .and_then(|p| pending.remove(p))
+            {
+                Some(p) => {
+                    let (a, b) = match i {
+                        0 => (info.rssi(), p.rssi),
+                        _ => (p.rssi, info.rssi()),
+                    };
+                    report.matched += 1;
+                    report.rssi_delta.update(a as f32 - b as f32);
+                    debug!("Matched {} bytes (rssi {} / {})", n, a, b);
+                }
+                None => pending.push_back(Pending {
+                    radio: i,
+                    data: data.to_vec(),
+                    rssi: info.rssi(),
+                    received: Instant::now(),
+                }),
+            }
+        }
+
+        // Frames unmatched within the window were missed by the other radio
+        while let Some(p) = pending.front()
+            && p.received.elapsed() > *options.match_window
+        {
+            let p = pending.pop_front().unwrap();
+            let m = report.miss(p.radio, p.data, p.rssi);
+            info!("Missed frame, {}", m);
+        }
+
+        if let Some(i) = options.stats_interval
+            && last_stats.elapsed() >= *i
+        {
+            info!("Differential capture: {}", report);
+            last_stats = Instant::now();
+        }
+
+        radios[0].delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+
+    for p in pending {
+        let m = report.miss(p.radio, p.data, p.rssi);
+        info!("Missed frame, {}", m);
+    }
+
+    info!("Differential capture complete, {}", report);
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio receiving queued frames
+    #[derive(Default)]
+    struct QueueRadio {
+        rx: VecDeque<(Vec<u8>, i16)>,
+    }
+
+    impl Receive for QueueRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(!self.rx.is_empty())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (d, rssi) = self.rx.pop_front().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(rssi, 0)))
+        }
+    }
+
+    impl DelayNs for QueueRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn differential_capture() {
+        let mut a = QueueRadio::default();
+        let mut b = QueueRadio::default();
+        a.rx.extend([(vec![1], -60), (vec![2], -70), (vec![3], -64)]);
+        b.rx.extend([(vec![1], -66), (vec![3], -70), (vec![4], -90)]);
+
+        let options = DiffRxOptions {
+            duration: Some(Duration::from_millis(10).into()),
+            shutdown: Shutdown::default(),
+            ..Default::default()
+        };
+        let mut buff = [0u8; 16];
+        let r = do_receive_diff([&mut a, &mut b], &mut buff, options).unwrap();
+
+        assert_eq!((r.matched, r.only_a, r.only_b), (2, 1, 1));
+        assert_eq!(r.rssi_delta.mean(), Some(6.0));
+
+        // Missed frames report the RSSI expected at the other radio
+        assert_eq!(
+            r.missed.iter().map(|m| m.to_string()).collect::<Vec<_>>(),
+            vec![
+                "only radio=0: 1 bytes rssi -70 (expected -76.0 at radio=1)",
+                "only radio=1: 1 bytes rssi -90 (expected -84.0 at radio=0)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Antenna diversity link tests
+//!
+//! Runs a ping-pong link test on each antenna of a radio implementing [`AntennaSelect`],
+//! reporting loss and RSSI per antenna, and optionally selecting the best antenna for
+//! subsequent operations.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+
+use super::{LinkTestInfo, PingPongOptions, do_ping_pong};
+use crate::{AntennaSelect, Power, Receive, ReceiveInfo, Transmit, blocking::BlockingError};
+
+/// Link test results for a single antenna
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AntennaLinkInfo {
+    pub antenna: u8,
+    pub link: LinkTestInfo,
+}
+
+impl AntennaLinkInfo {
+    /// Packet loss in percent
+    pub fn loss(&self) -> f32 {
+        100.0 - self.link.received as f32 * 100.0 / self.link.sent.max(1) as f32
+    }
+}
+
+/// Select the best antenna from diversity results, preferring the fewest lost packets
+/// then the highest mean local RSSI
+pub fn best_antenna(results: &[AntennaLinkInfo]) -> Option<u8> {
+    let rssi = |r: &AntennaLinkInfo| r.link.local_rssi.mean().unwrap_or(f32::MIN);
+
+    results
+        .iter()
+        .max_by(|a, b| {
+            a.link
+                .received
+                .cmp(&b.link.received)
+                .then(rssi(a).total_cmp(&rssi(b)))
+        })
+        .map(|r| r.antenna)
+}
+
+/// Run a link test on each antenna in turn, returning results per antenna
+pub fn do_antenna_diversity<T, I, E>(
+    radio: &mut T,
+    options: PingPongOptions,
+) -> Result<Vec<AntennaLinkInfo>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + AntennaSelect<Error = E>
+        + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+{
+    let mut results = vec![];
+
+    for antenna in 0..radio.antenna_count() {
+        debug!("Testing antenna {}", antenna);
+
+        radio.set_antenna(antenna)?;
+        let link = do_ping_pong(radio, options.clone())?;
+
+        results.push(AntennaLinkInfo { antenna, link });
+    }
+
+    Ok(results)
+}
+
+/// Run a link test on each antenna and select the best, returning the per-antenna results
+pub fn do_antenna_select<T, I, E>(
+    radio: &mut T,
+    options: PingPongOptions,
+) -> Result<Vec<AntennaLinkInfo>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + AntennaSelect<Error = E>
+        + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+{
+    let results = do_antenna_diversity(radio, options)?;
+
+    if let Some(a) = best_antenna(&results) {
+        info!("Selected antenna {}", a);
+        radio.set_antenna(a)?;
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn best_antenna_selection() {
+        let result = |antenna, received, rssi: &[f32]| {
+            let mut link = LinkTestInfo::new(4);
+            link.sent = 10;
+            link.received = received;
+            for r in rssi {
+                link.local_rssi.update(*r);
+            }
+            AntennaLinkInfo { antenna, link }
+        };
+
+        // Fewest losses wins, with RSSI breaking ties
+        let results = [
+            result(0, 8, &[-60.0]),
+            result(1, 10, &[-80.0]),
+            result(2, 10, &[-70.0]),
+        ];
+        assert_eq!(best_antenna(&results), Some(2));
+        assert_eq!(results[0].loss(), 20.0);
+        assert_eq!(best_antenna(&[]), None);
+    }
+}
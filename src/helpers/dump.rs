@@ -0,0 +1,183 @@
+//! Register and driver state dumps for radios implementing [`RegisterDump`]
+//!
+//! The `dump` operation prints raw register or driver state as a hex table (by address),
+//! or as named fields where drivers provide field names, so driver state can be captured
+//! alongside failing link tests without per-chip tooling.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::{DumpEntry, RegisterDump};
+
+/// Values per hex table row
+const ROW_LEN: u16 = 16;
+
+/// Register dump output format
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum DumpFormat {
+    /// Hex table by address
+    #[default]
+    Hex,
+    /// Named fields, one per line
+    Fields,
+}
+
+/// Register dump options
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct DumpOptions {
+    /// Dump output format
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "hex"))]
+    pub format: DumpFormat,
+}
+
+/// Dumped register or driver state value
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DumpRecord {
+    pub address: u16,
+    /// Register or field name, where provided by the driver
+    pub name: Option<String>,
+    pub value: u32,
+    /// Value width in bytes
+    pub width: u8,
+}
+
+impl From<DumpEntry> for DumpRecord {
+    fn from(e: DumpEntry) -> Self {
+        Self {
+            address: e.address,
+            name: e.name.map(String::from),
+            value: e.value,
+            width: e.width,
+        }
+    }
+}
+
+/// Format dumped values as a hex table, with absent addresses shown as `--`
+pub struct DumpTable<'a>(pub &'a [DumpRecord]);
+
+impl core::fmt::Display for DumpTable<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let digits = self.0.iter().map(|r| r.width.max(1)).max().unwrap_or(1) as usize * 2;
+
+        let mut row = None;
+        let mut next = 0;
+        for r in self.0 {
+            let start = r.address - r.address % ROW_LEN;
+            if row != Some(start) {
+                if row.is_some() {
+                    writeln!(f)?;
+                }
+                write!(f, "{:04x}:", start)?;
+                row = Some(start);
+                next = start;
+            }
+            for _ in next..r.address {
+                write!(f, " {:>digits$}", "--")?;
+            }
+            write!(f, " {:0digits$x}", r.value)?;
+            next = r.address + 1;
+        }
+        if row.is_some() {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format dumped values as named fields
+pub struct DumpFields<'a>(pub &'a [DumpRecord]);
+
+impl core::fmt::Display for DumpFields<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for r in self.0 {
+            let digits = r.width.max(1) as usize * 2;
+            writeln!(
+                f,
+                "{:04x} {:<24} 0x{:0digits$x} ({})",
+                r.address,
+                r.name.as_deref().unwrap_or("-"),
+                r.value,
+                r.value,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetch raw register or driver state from the radio
+pub fn read_dump<T, E>(radio: &mut T) -> Result<Vec<DumpRecord>, E>
+where
+    T: RegisterDump<Error = E>,
+{
+    let mut records = Vec::new();
+    radio.dump_registers(&mut |e| records.push(e.into()))?;
+    Ok(records)
+}
+
+/// Dump raw register or driver state, logging it in the configured format
+pub fn do_dump<T, E>(radio: &mut T, options: &DumpOptions) -> Result<Vec<DumpRecord>, E>
+where
+    T: RegisterDump<Error = E>,
+{
+    let records = read_dump(radio)?;
+
+    match options.format {
+        DumpFormat::Hex => info!("Register dump:\n{}", DumpTable(&records)),
+        DumpFormat::Fields => info!("Register dump:\n{}", DumpFields(&records)),
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Regs;
+
+    impl RegisterDump for Regs {
+        type Error = ();
+
+        fn dump_registers(&mut self, f: &mut dyn FnMut(DumpEntry)) -> Result<(), Self::Error> {
+            for (address, name, value) in [
+                (0x00, Some("OPMODE"), 0x81),
+                (0x01, None, 0x1a),
+                (0x03, Some("FRF_MSB"), 0xd9),
+                (0x12, Some("IRQ_FLAGS"), 0x40),
+            ] {
+                f(DumpEntry {
+                    address,
+                    name,
+                    value,
+                    width: 1,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dump_formats() {
+        let records = do_dump(&mut Regs, &DumpOptions::default()).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].name.as_deref(), Some("OPMODE"));
+
+        assert_eq!(
+            DumpTable(&records).to_string(),
+            "0000: 81 1a -- d9\n0010: -- -- 40\n"
+        );
+        assert_eq!(
+            DumpFields(&records[1..2]).to_string(),
+            format!("0001 {:<24} 0x1a (26)\n", "-")
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Helper operation errors
+//!
+//! Helper operations fail with radio errors (as [`BlockingError`]s), or with IO errors
+//! from the host (eg. binding the metrics listener or writing the queue store), which are
+//! kept out of the `no_std` [`BlockingError`] so its variants do not depend on features.
+//!
+//! Radio errors convert with `?`, while IO errors are wrapped explicitly with
+//! [`OperationError::Io`] as radios may themselves report [`std::io::Error`]s.
+
+use crate::blocking::BlockingError;
+
+/// OperationError describes failures running helper operations
+#[derive(Debug)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum OperationError<E: core::fmt::Debug> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(BlockingError<E>),
+    /// Host IO error
+    #[cfg_attr(feature = "thiserror", error("IO: {0}"))]
+    Io(std::io::Error),
+}
+
+impl<E: core::fmt::Debug> OperationError<E> {
+    /// Fetch the radio error, where the operation failed on the radio
+    pub fn radio(&self) -> Option<&BlockingError<E>> {
+        match self {
+            OperationError::Radio(e) => Some(e),
+            OperationError::Io(_) => None,
+        }
+    }
+
+    /// Check whether the operation timed out awaiting the radio or a peer
+    pub fn is_timeout(&self) -> bool {
+        self.radio().is_some_and(|e| e.is_timeout())
+    }
+
+    /// Map the radio error, leaving IO errors unchanged
+    pub fn map_radio<F, G>(self, f: G) -> OperationError<F>
+    where
+        F: core::fmt::Debug,
+        G: FnOnce(BlockingError<E>) -> BlockingError<F>,
+    {
+        match self {
+            OperationError::Radio(e) => OperationError::Radio(f(e)),
+            OperationError::Io(e) => OperationError::Io(e),
+        }
+    }
+}
+
+/// Operation errors compare IO errors by kind
+impl<E: core::fmt::Debug + PartialEq> PartialEq for OperationError<E> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (OperationError::Radio(a), OperationError::Radio(b)) => a == b,
+            (OperationError::Io(a), OperationError::Io(b)) => a.kind() == b.kind(),
+            _ => false,
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> From<BlockingError<E>> for OperationError<E> {
+    fn from(e: BlockingError<E>) -> Self {
+        OperationError::Radio(e)
+    }
+}
+
+impl<E: core::fmt::Debug> From<E> for OperationError<E> {
+    fn from(e: E) -> Self {
+        OperationError::Radio(BlockingError::Inner(e))
+    }
+}
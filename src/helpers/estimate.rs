@@ -0,0 +1,131 @@
+//! Dry-run airtime estimation
+//!
+//! With `--estimate`, transmit operations compute the expected airtime, duty-cycle and
+//! completion time for the configured payloads and period without touching the radio,
+//! for planning tests within regulatory duty-cycle limits.
+
+use core::time::Duration;
+
+use super::{SourceSpec, TransmitOptions, time_on_air};
+
+/// Airtime estimate for a transmit operation
+#[derive(Clone, Debug, PartialEq)]
+pub struct AirtimeEstimate {
+    /// Number of frames, `None` where transmission repeats indefinitely
+    pub frames: Option<u64>,
+    /// Total payload bytes (per frame where repeating indefinitely)
+    pub bytes: u64,
+    /// Total airtime (per frame where repeating indefinitely)
+    pub airtime: Duration,
+    /// Fraction of time spent transmitting
+    pub duty_cycle: f32,
+    /// Expected completion time, `None` where transmission repeats indefinitely
+    pub completion: Option<Duration>,
+}
+
+impl core::fmt::Display for AirtimeEstimate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.frames {
+            Some(n) => write!(
+                f,
+                "{} frames, {} bytes, airtime {:.3} s",
+                n,
+                self.bytes,
+                self.airtime.as_secs_f32()
+            )?,
+            None => write!(
+                f,
+                "repeating {} byte frames, airtime {:.3} ms per frame",
+                self.bytes,
+                self.airtime.as_secs_f32() * 1000.0
+            )?,
+        }
+        write!(f, ", duty cycle {:.2}%", self.duty_cycle * 100.0)?;
+        match self.completion {
+            Some(c) => write!(f, ", completion in {:.3} s", c.as_secs_f32()),
+            None => write!(f, ", runs until stopped"),
+        }
+    }
+}
+
+/// Estimate airtime for the frame sizes in `sizes`, sent with the provided period between
+/// frames at `bitrate` bits/s, with `repeat` set where the sequence repeats indefinitely
+pub fn estimate_airtime(
+    sizes: &[usize],
+    bitrate: u32,
+    period: Option<Duration>,
+    repeat: bool,
+) -> AirtimeEstimate {
+    let period = period.unwrap_or_default();
+    let bytes = sizes.iter().map(|s| *s as u64).sum();
+    let airtime: Duration = sizes.iter().map(|s| time_on_air(*s, bitrate)).sum();
+    let gaps = period * sizes.len().saturating_sub(1) as u32;
+
+    // Repeating sequences include the gap before the next repetition
+    let elapsed = match repeat {
+        true => airtime + period * sizes.len() as u32,
+        false => airtime + gaps,
+    };
+    let duty_cycle = match elapsed.is_zero() {
+        true => 0.0,
+        false => airtime.as_secs_f32() / elapsed.as_secs_f32(),
+    };
+
+    AirtimeEstimate {
+        frames: (!repeat).then_some(sizes.len() as u64),
+        bytes,
+        airtime,
+        duty_cycle,
+        completion: (!repeat).then_some(elapsed),
+    }
+}
+
+impl TransmitOptions {
+    /// Estimate airtime for the configured transmission at the provided bitrate,
+    /// reading payloads from the configured source (without accessing the radio)
+    pub fn estimate(&self, bitrate: u32) -> Result<AirtimeEstimate, std::io::Error> {
+        let period = self.period.map(|p| *p);
+
+        // Indefinite sources are estimated from a single frame
+        let sizes = match &self.source {
+            None => vec![self.template.as_ref().map_or(self.data.len(), |t| t.len())],
+            Some(SourceSpec::Generator { size, count: None }) => vec![(*size).max(4)],
+            Some(s) => {
+                let mut source = s.open()?;
+                std::iter::from_fn(|| source.next_payload().transpose())
+                    .map(|p| p.map(|p| p.len()))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+        let repeat = match &self.source {
+            None => self.period.is_some(),
+            Some(SourceSpec::Generator { count, .. }) => count.is_none(),
+            Some(_) => false,
+        };
+
+        Ok(estimate_airtime(&sizes, bitrate, period, repeat))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn airtime_estimates() {
+        // 10 x 25 byte frames at 50 kbps (4 ms each) with 36 ms gaps
+        let e = estimate_airtime(&[25; 10], 50_000, Some(Duration::from_millis(36)), false);
+        assert_eq!(e.frames, Some(10));
+        assert_eq!(e.airtime, Duration::from_millis(40));
+        assert_eq!(e.completion, Some(Duration::from_millis(40 + 9 * 36)));
+
+        // Repeating frames every 396 ms gives 1% duty cycle
+        let e = estimate_airtime(&[25], 50_000, Some(Duration::from_millis(396)), true);
+        assert_eq!((e.frames, e.completion), (None, None));
+        assert!((e.duty_cycle - 0.01).abs() < 1e-6);
+        assert_eq!(
+            e.to_string(),
+            "repeating 25 byte frames, airtime 4.000 ms per frame, duty cycle 1.00%, runs until stopped"
+        );
+    }
+}
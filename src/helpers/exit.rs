@@ -0,0 +1,188 @@
+//! Operation exit statuses for shell-driven test harnesses
+//!
+//! Operation outcomes are mapped to process exit codes with [`ExitStatus::from_result`],
+//! so scripts can branch on the result of an operation:
+//!
+//! - `0` ([`ExitStatus::Success`]): operation completed
+//! - `1` ([`ExitStatus::Failure`]): operation failed (including IO errors and
+//!   `compare --fail-on-regression`)
+//! - `3` ([`ExitStatus::Timeout`]): timeout awaiting the radio or a peer
+//! - `4` ([`ExitStatus::BelowThreshold`]): link quality below `--fail-loss` / `--fail-rssi`,
+//!   or watched peers silent at the end of `watch`
+//! - `5` ([`ExitStatus::Hardware`]): radio (driver or hardware) error
+//! - `6` ([`ExitStatus::Unsupported`]): operation or option unsupported by the radio
+//!
+//! Code 2 is reserved for command line parsing errors, as reported by clap.
+
+use clap::Parser;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{LinkTestInfo, OperationError, OperationResult};
+use crate::blocking::BlockingError;
+
+/// Link quality thresholds applied when mapping operation results to exit statuses
+#[derive(Clone, PartialEq, Debug, Default, Parser)]
+pub struct ExitOptions {
+    /// Exit with status 4 where link test packet loss (%) exceeds this value
+    #[clap(long)]
+    pub fail_loss: Option<f32>,
+
+    /// Exit with status 4 where mean link test local RSSI (dBm) is below this value
+    #[clap(long, allow_hyphen_values = true)]
+    pub fail_rssi: Option<i16>,
+}
+
+impl ExitOptions {
+    /// Check whether link test results meet the configured thresholds
+    pub fn link_ok(&self, info: &LinkTestInfo) -> bool {
+        let loss = 100.0 - info.received as f32 * 100.0 / info.sent.max(1) as f32;
+        if let Some(max) = self.fail_loss
+            && loss > max
+        {
+            return false;
+        }
+
+        match (self.fail_rssi, info.local_rssi.mean()) {
+            (Some(min), Some(rssi)) => rssi >= min as f32,
+            (Some(_), None) => info.sent == 0,
+            (None, _) => true,
+        }
+    }
+}
+
+/// Structured operation outcome, mapped to a documented process exit code
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ExitStatus {
+    /// Operation completed
+    Success,
+    /// Operation failed
+    Failure,
+    /// Timeout awaiting the radio or a peer
+    Timeout,
+    /// Link quality below the configured thresholds
+    BelowThreshold,
+    /// Radio (driver or hardware) error
+    Hardware,
+    /// Operation or option requires a capability the radio does not support
+    Unsupported,
+}
+
+impl ExitStatus {
+    /// Process exit code for the status
+    pub fn code(&self) -> u8 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::Failure => 1,
+            ExitStatus::Timeout => 3,
+            ExitStatus::BelowThreshold => 4,
+            ExitStatus::Hardware => 5,
+            ExitStatus::Unsupported => 6,
+        }
+    }
+
+    /// Map an operation result to an exit status, applying the provided thresholds
+    pub fn from_result<E: core::fmt::Debug>(
+        res: &Result<OperationResult, OperationError<E>>,
+        options: &ExitOptions,
+    ) -> Self {
+        match res {
+            Ok(r) => r.status(options),
+            Err(e) if e.is_timeout() => ExitStatus::Timeout,
+            Err(OperationError::Io(_)) => ExitStatus::Failure,
+            Err(OperationError::Radio(BlockingError::Oversize { .. })) => ExitStatus::Failure,
+            Err(OperationError::Radio(BlockingError::Unsupported(_))) => ExitStatus::Unsupported,
+            Err(_) => ExitStatus::Hardware,
+        }
+    }
+}
+
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(s: ExitStatus) -> Self {
+        std::process::ExitCode::from(s.code())
+    }
+}
+
+impl OperationResult {
+    /// Exit status for a completed operation, applying the provided thresholds
+    pub fn status(&self, options: &ExitOptions) -> ExitStatus {
+        match self {
+            OperationResult::LinkTest(results) if !results.iter().all(|r| options.link_ok(r)) => {
+                ExitStatus::BelowThreshold
+            }
+            OperationResult::Compare {
+                comparison,
+                fail_on_regression: true,
+            } if comparison.regressed() => ExitStatus::Failure,
+            // No probes transited the link
+            OperationResult::Mtu(None) => ExitStatus::Timeout,
+            OperationResult::Watch(peers) if peers.iter().any(|p| !p.alive) => {
+                ExitStatus::BelowThreshold
+            }
+            _ => ExitStatus::Success,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{Comparison, MetricDelta};
+
+    #[test]
+    fn exit_statuses() {
+        let options = ExitOptions {
+            fail_loss: Some(10.0),
+            fail_rssi: Some(-90),
+        };
+
+        let mut link = LinkTestInfo::new(16);
+        link.sent = 10;
+        link.received = 9;
+        link.local_rssi.update(-80.0);
+        let ok: Result<_, OperationError<()>> = Ok(OperationResult::LinkTest(vec![link.clone()]));
+        assert_eq!(ExitStatus::from_result(&ok, &options), ExitStatus::Success);
+
+        link.received = 8;
+        let lossy = OperationResult::LinkTest(vec![link.clone()]);
+        assert_eq!(lossy.status(&options), ExitStatus::BelowThreshold);
+        assert_eq!(lossy.status(&ExitOptions::default()), ExitStatus::Success);
+
+        link.received = 10;
+        link.local_rssi.update(-110.0);
+        let weak = OperationResult::LinkTest(vec![link]);
+        assert_eq!(weak.status(&options).code(), 4);
+
+        let regressed = Comparison {
+            deltas: vec![MetricDelta {
+                name: "rtt_ms".to_string(),
+                payload_len: 16,
+                baseline: 10.0,
+                candidate: 20.0,
+                significant: true,
+                regression: true,
+            }],
+        };
+        let compare = |fail_on_regression| OperationResult::Compare {
+            comparison: regressed.clone(),
+            fail_on_regression,
+        };
+        assert_eq!(compare(false).status(&options), ExitStatus::Success);
+        assert_eq!(compare(true).status(&options).code(), 1);
+
+        let timeout: Result<OperationResult, OperationError<()>> =
+            Err(BlockingError::Timeout.into());
+        assert_eq!(ExitStatus::from_result(&timeout, &options).code(), 3);
+        let hw: Result<OperationResult, OperationError<()>> = Err(().into());
+        assert_eq!(ExitStatus::from_result(&hw, &options).code(), 5);
+        let unsupported: Result<OperationResult, OperationError<()>> =
+            Err(BlockingError::Unsupported("channel").into());
+        assert_eq!(ExitStatus::from_result(&unsupported, &options).code(), 6);
+        let io: Result<OperationResult, OperationError<()>> = Err(OperationError::Io(
+            std::io::Error::from(std::io::ErrorKind::AddrInUse),
+        ));
+        assert_eq!(ExitStatus::from_result(&io, &options).code(), 1);
+    }
+}
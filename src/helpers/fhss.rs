@@ -0,0 +1,216 @@
+//! Frequency-hopping transmit, receive and link test operations
+//!
+//! With `--hop-channels`, operations run through a [`Hopper`], dwelling on each channel
+//! for `--hop-interval` before moving to the next. Channels are specified as
+//! driver-specific numbers (or frequencies) and mapped to the radio [`Channel`] type by
+//! the caller, as for [`do_ping_pong_hopping`](super::do_ping_pong_hopping).
+//!
+//! With `--hop-sync`, receivers join a running network rather than hopping on their own
+//! schedule, parking on the specified channel until a frame is heard and then following
+//! the sequence (see [`HopFollower`]).
+//!
+//! Receive statistics are accumulated per channel (see [`ChannelRecorder`]) and reported
+//! on exit, with `--hop-stats` writing the per-channel report to a JSON file.
+
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+
+use super::{
+    ChannelRecorder, ChannelRxStats, ChannelRxTable, LinkTestInfo, PingPongOptions, ReceiveOptions,
+    TransmitOptions, do_ping_pong, do_receive, do_transmit,
+};
+use crate::{
+    Channel, Power, Receive, ReceiveInfo, Transmit,
+    blocking::BlockingError,
+    clock::StdClock,
+    fhss::{HopFollower, Hopper},
+};
+
+/// Frequency-hopping options
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct FhssOptions {
+    /// Channels to hop between on a fixed schedule, comma separated
+    /// (driver-specific channel numbers or frequencies)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub hop_channels: Vec<u32>,
+
+    /// Dwell time on each channel before hopping
+    #[cfg_attr(feature="clap", clap(long, default_value="400ms", value_parser=crate::duration_from_str))]
+    pub hop_interval: Duration,
+
+    /// Synchronise receive to a running hop sequence, parking on the channel at this
+    /// index (into `--hop-channels`) until a frame is received
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub hop_sync: Option<usize>,
+
+    /// Write per-channel receive statistics to this JSON file on exit
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub hop_stats: Option<String>,
+}
+
+impl Default for FhssOptions {
+    fn default() -> Self {
+        Self {
+            hop_channels: vec![],
+            hop_interval: Duration::from_millis(400),
+            hop_sync: None,
+            hop_stats: None,
+        }
+    }
+}
+
+impl FhssOptions {
+    /// Check whether frequency hopping is enabled
+    pub fn enabled(&self) -> bool {
+        !self.hop_channels.is_empty()
+    }
+
+    /// Wrap a radio with a [`Hopper`] following these options
+    pub fn hopper<T, F>(&self, radio: T, to_channel: F) -> Hopper<T, Vec<T::Channel>, StdClock>
+    where
+        T: Channel,
+        F: FnMut(&u32) -> T::Channel,
+    {
+        let channels = self.hop_channels.iter().map(to_channel).collect();
+        Hopper::new(radio, channels, StdClock, self.hop_interval)
+    }
+
+    /// Wrap a radio with a [`HopFollower`] synchronising to a running hop sequence,
+    /// `None` where `hop_sync` is not set
+    pub fn follower<T, F>(
+        &self,
+        radio: T,
+        to_channel: F,
+    ) -> Option<HopFollower<T, Vec<T::Channel>, StdClock>>
+    where
+        T: Channel,
+        F: FnMut(&u32) -> T::Channel,
+    {
+        let sync_index = self.hop_sync?;
+        let channels = self.hop_channels.iter().map(to_channel).collect();
+        Some(HopFollower::new(
+            radio,
+            channels,
+            StdClock,
+            self.hop_interval,
+            sync_index,
+        ))
+    }
+}
+
+/// Transmit using the provided configuration, hopping between the configured channels,
+/// returning the number of payloads sent
+///
+/// `to_channel` maps configured channel numbers to the radio channel type.
+pub fn do_transmit_fhss<T, E, F>(
+    radio: &mut T,
+    options: TransmitOptions,
+    to_channel: F,
+) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + Channel<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    F: FnMut(&u32) -> T::Channel,
+{
+    let mut radio = options.fhss_options.hopper(&mut *radio, to_channel);
+
+    let res = do_transmit(&mut radio, options);
+
+    info!("Frequency hopping transmit: {} hops", radio.hops());
+
+    res
+}
+
+/// Receive using the provided configuration, hopping between the configured channels
+///
+/// `to_channel` maps configured channel numbers to the radio channel type.
+pub fn do_receive_fhss<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: ReceiveOptions,
+    mut to_channel: F,
+) -> Result<usize, E>
+where
+    T: Receive<Info = I, Error = E> + Channel<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(&u32) -> T::Channel,
+{
+    let channels = options.fhss_options.hop_channels.clone();
+    let channel = |i: Option<usize>| i.and_then(|i| channels.get(i).copied());
+    let stats = options.fhss_options.hop_stats.clone();
+
+    // Late join, following a running network
+    if let Some(radio) = options.fhss_options.follower(&mut *radio, &mut to_channel) {
+        let mut radio = ChannelRecorder::new(radio, |r| channel(r.index()));
+        let res = do_receive(&mut radio, buff, options);
+
+        report_channels(stats.as_deref(), &radio.report());
+        let radio = radio.inner();
+        info!(
+            "Frequency hopping receive: {} hops, sync {:?}",
+            radio.hops(),
+            radio.sync()
+        );
+
+        return res;
+    }
+
+    let radio = options.fhss_options.hopper(&mut *radio, to_channel);
+    let mut radio = ChannelRecorder::new(radio, |r| channel(r.index()));
+
+    let res = do_receive(&mut radio, buff, options);
+
+    report_channels(stats.as_deref(), &radio.report());
+    info!("Frequency hopping receive: {} hops", radio.inner().hops());
+
+    res
+}
+
+/// Log per-channel receive statistics, writing them to `path` where provided
+fn report_channels(path: Option<&str>, stats: &[ChannelRxStats]) {
+    info!("Per-channel receive statistics:\n{}", ChannelRxTable(stats));
+
+    if let Some(p) = path
+        && let Err(e) = super::write_channel_stats(p, stats)
+    {
+        warn!("Error writing channel statistics: {:?}", e);
+    }
+}
+
+/// Run a link test, hopping between the configured channels
+///
+/// `to_channel` maps configured channel numbers to the radio channel type.
+pub fn do_ping_pong_fhss<T, I, E, F>(
+    radio: &mut T,
+    options: PingPongOptions,
+    to_channel: F,
+) -> Result<LinkTestInfo, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + Channel<Error = E>
+        + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+    F: FnMut(&u32) -> T::Channel,
+{
+    let mut radio = options.fhss_options.hopper(&mut *radio, to_channel);
+
+    let res = do_ping_pong(&mut radio, options);
+
+    info!("Frequency hopping link test: {} hops", radio.hops());
+
+    res
+}
@@ -0,0 +1,255 @@
+//! Compact raw frame log capture format and import (replay) operation
+//!
+//! Frame logs start with a magic and version header, followed by length-prefixed records
+//! containing frame metadata and payload, all little-endian:
+//!
+//! ```text
+//! header: "RFLG" | version (u8)
+//! record: length (u16, metadata + payload) | timestamp_us (u64) | rssi (i16) | payload
+//! ```
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+
+use crate::{
+    Power, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Frame log file magic
+pub const FRAME_LOG_MAGIC: [u8; 4] = *b"RFLG";
+
+/// Frame log format version
+pub const FRAME_LOG_VERSION: u8 = 1;
+
+/// Length of record metadata (timestamp and rssi)
+const META_LEN: usize = 10;
+
+/// Frame log record
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameRecord {
+    /// Receive time in microseconds since the unix epoch
+    pub timestamp_us: u64,
+    /// Received signal strength in dBm
+    pub rssi: i16,
+    /// Frame payload
+    pub data: Vec<u8>,
+}
+
+/// Frame log writer
+pub struct FrameLogWriter<W: Write> {
+    w: W,
+}
+
+impl FrameLogWriter<BufWriter<File>> {
+    /// Create a new frame log file
+    pub fn create(path: &str) -> Result<Self, std::io::Error> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> FrameLogWriter<W> {
+    /// Create a new frame log writer, writing the file header
+    pub fn new(mut w: W) -> Result<Self, std::io::Error> {
+        w.write_all(&FRAME_LOG_MAGIC)?;
+        w.write_all(&[FRAME_LOG_VERSION])?;
+        Ok(Self { w })
+    }
+
+    /// Write a frame record
+    pub fn write(&mut self, r: &FrameRecord) -> Result<(), std::io::Error> {
+        let len = u16::try_from(META_LEN + r.data.len())
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "frame too long"))?;
+
+        self.w.write_all(&len.to_le_bytes())?;
+        self.w.write_all(&r.timestamp_us.to_le_bytes())?;
+        self.w.write_all(&r.rssi.to_le_bytes())?;
+        self.w.write_all(&r.data)
+    }
+
+    /// Flush buffered records
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.w.flush()
+    }
+}
+
+/// Frame log reader, iterating over records
+pub struct FrameLogReader<R: Read> {
+    r: R,
+}
+
+impl FrameLogReader<BufReader<File>> {
+    /// Open a frame log file
+    pub fn open(path: &str) -> Result<Self, std::io::Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> FrameLogReader<R> {
+    /// Create a new frame log reader, validating the file header
+    pub fn new(mut r: R) -> Result<Self, std::io::Error> {
+        let mut h = [0u8; 5];
+        r.read_exact(&mut h)?;
+
+        if h[..4] != FRAME_LOG_MAGIC || h[4] != FRAME_LOG_VERSION {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid frame log header",
+            ));
+        }
+
+        Ok(Self { r })
+    }
+
+    fn read_record(&mut self) -> Result<Option<FrameRecord>, std::io::Error> {
+        let mut len = [0u8; 2];
+        match self.r.read_exact(&mut len) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let len = u16::from_le_bytes(len) as usize;
+        if len < META_LEN {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid record length",
+            ));
+        }
+
+        let mut b = vec![0u8; len];
+        self.r.read_exact(&mut b)?;
+
+        Ok(Some(FrameRecord {
+            timestamp_us: u64::from_le_bytes(b[0..8].try_into().unwrap()),
+            rssi: i16::from_le_bytes([b[8], b[9]]),
+            data: b.split_off(META_LEN),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for FrameLogReader<R> {
+    type Item = Result<FrameRecord, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Configuration for Import operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ImportOptions {
+    /// Frame log file to transmit frames from
+    pub file: String,
+
+    /// Preserve the original inter-frame timing from the capture
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub preserve_timing: bool,
+
+    /// Delay between frames when not preserving timing
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100ms"))]
+    pub delay: HumanDuration,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Transmit frames from a frame log, returning the number of frames sent
+pub fn do_import<T, E>(radio: &mut T, options: ImportOptions) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    let log = FrameLogReader::open(&options.file).expect("Error opening frame log");
+
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut last = None;
+    let mut sent = 0;
+
+    for r in log {
+        let r = r.expect("Error reading frame log");
+
+        // Wait between frames
+        let delay_us = match (options.preserve_timing, last) {
+            (true, Some(t)) => r.timestamp_us.saturating_sub(t),
+            (false, Some(_)) => options.delay.as_micros() as u64,
+            (_, None) => 0,
+        };
+        radio.delay_us(delay_us.min(u32::MAX as u64) as u32);
+        last = Some(r.timestamp_us);
+
+        debug!("Sending frame {} ({} bytes)", sent, r.data.len());
+
+        radio.do_transmit(&r.data, options.blocking_options.clone())?;
+        sent += 1;
+    }
+
+    info!("Import complete, sent {} frames", sent);
+
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_log_round_trip() {
+        let records = vec![
+            FrameRecord {
+                timestamp_us: 1_000,
+                rssi: -70,
+                data: vec![0x01, 0x02, 0x03],
+            },
+            FrameRecord {
+                timestamp_us: 2_500,
+                rssi: -90,
+                data: vec![],
+            },
+        ];
+
+        let mut w = FrameLogWriter::new(vec![]).unwrap();
+        for r in &records {
+            w.write(r).unwrap();
+        }
+        let b = w.w;
+
+        assert_eq!(&b[..5], b"RFLG\x01");
+
+        let decoded: Vec<_> = FrameLogReader::new(&b[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(decoded, records);
+
+        // Truncated records and invalid headers are errors
+        assert!(
+            FrameLogReader::new(&b[..b.len() - 1])
+                .unwrap()
+                .last()
+                .unwrap()
+                .is_err()
+        );
+        assert!(FrameLogReader::new(&b"PCAP\x01"[..]).is_err());
+    }
+}
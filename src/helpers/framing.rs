@@ -0,0 +1,165 @@
+//! Addressed framing for transmit and receive operations
+//!
+//! With `--address`, transmitted payloads are framed with source and destination addresses
+//! (see [`crate::frame`]) and fragmented where they exceed `--frame-mtu`, while received
+//! frames are filtered by destination address and fragmented payloads reassembled.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::debug;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::frame::{self, Address, BROADCAST, FrameError, Header, Reassembler};
+
+/// Maximum reassembled payload length
+pub const MAX_REASSEMBLED: usize = 16 * 1024;
+
+/// Addressed framing options
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct FramingOptions {
+    /// Node address, framing payloads with source and destination addresses (and only
+    /// accepting frames for this node or broadcast on receive) when set
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub address: Option<Address>,
+
+    /// Destination address for transmitted frames (broadcast by default)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "65535"))]
+    pub dest: Address,
+
+    /// Maximum frame length, with longer payloads fragmented
+    #[cfg_attr(feature = "clap", clap(long, default_value = "255"))]
+    pub frame_mtu: usize,
+}
+
+impl Default for FramingOptions {
+    fn default() -> Self {
+        Self {
+            address: None,
+            dest: BROADCAST,
+            frame_mtu: 255,
+        }
+    }
+}
+
+impl FramingOptions {
+    /// Create a framer for transmitted payloads, `None` where framing is disabled
+    pub fn framer(&self) -> Option<Framer> {
+        self.address.map(|address| Framer {
+            address,
+            dest: self.dest,
+            mtu: self.frame_mtu,
+            seq: 0,
+        })
+    }
+
+    /// Create a deframer for received frames, `None` where framing is disabled
+    pub fn deframer(&self) -> Option<Deframer> {
+        self.address.map(|address| Deframer {
+            address,
+            reassembler: Box::default(),
+        })
+    }
+}
+
+/// Transmit-side framing, with sequence numbers incrementing per payload
+pub struct Framer {
+    address: Address,
+    dest: Address,
+    mtu: usize,
+    seq: u8,
+}
+
+impl Framer {
+    /// Encode a payload into one or more frames
+    pub fn frames(&mut self, payload: &[u8]) -> Result<Vec<Vec<u8>>, FrameError> {
+        let header = Header::new(self.address, self.dest, self.seq);
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut fragments = frame::fragment(&header, payload, self.mtu)?;
+        let mut frames = Vec::with_capacity(fragments.count());
+        let mut buff = vec![0u8; self.mtu];
+
+        while let Some(n) = fragments.encode_next(&mut buff) {
+            frames.push(buff[..n?].to_vec());
+        }
+
+        Ok(frames)
+    }
+}
+
+/// Receive-side address filtering and reassembly
+pub struct Deframer {
+    address: Address,
+    reassembler: Box<Reassembler<MAX_REASSEMBLED>>,
+}
+
+impl Deframer {
+    /// Accept a received frame, returning the payload where addressed to this node and
+    /// complete
+    pub fn accept<'a>(&'a mut self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let (header, payload) = match frame::decode(data) {
+            Ok(f) => f,
+            Err(e) => {
+                debug!("Invalid frame: {:?}", e);
+                return None;
+            }
+        };
+
+        if !header.is_for(self.address) {
+            debug!("Ignoring frame for {}", header.dst);
+            return None;
+        }
+
+        match self.reassembler.push(&header, payload) {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("Invalid fragment from {}: {:?}", header.src, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framed_transfer() {
+        let options = FramingOptions {
+            address: Some(1),
+            dest: 2,
+            frame_mtu: 32,
+        };
+        let mut framer = options.framer().unwrap();
+        let payload: Vec<u8> = (0..100).collect();
+
+        let frames = framer.frames(&payload).unwrap();
+        assert_eq!(frames.len(), 5);
+
+        // Frames for other nodes are ignored
+        let mut other = FramingOptions {
+            address: Some(3),
+            ..options.clone()
+        }
+        .deframer()
+        .unwrap();
+        assert!(frames.iter().all(|f| other.accept(f).is_none()));
+
+        let mut deframer = FramingOptions {
+            address: Some(2),
+            ..options
+        }
+        .deframer()
+        .unwrap();
+        for f in &frames[..4] {
+            assert_eq!(deframer.accept(f), None);
+        }
+        assert_eq!(deframer.accept(&frames[4]), Some(&payload[..]));
+    }
+}
@@ -0,0 +1,346 @@
+//! gRPC remote radio protocol, proxying the core radio traits over the network
+//!
+//! [`RadioService`] wraps a local radio to serve the `radio.Radio` service defined in
+//! `proto/radio.proto`, and [`RemoteRadio`] implements [`Transmit`], [`Receive`], [`Power`]
+//! and [`Rssi`] over a connection to a remote service, so existing helpers can drive
+//! radios at other sites for distributed link testing.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use tonic::{Request, Response, Status};
+
+use crate::{BasicInfo, Power, Receive, ReceiveInfo, Rssi, Transmit};
+
+/// Generated protocol types
+pub mod proto {
+    tonic::include_proto!("radio");
+}
+
+use proto::{
+    CheckReceiveRequest, CheckResponse, Empty, PowerRequest, ReceivedResponse, RssiResponse,
+    TransmitRequest,
+    radio_client::RadioClient,
+    radio_server::{Radio as RadioRpc, RadioServer},
+};
+
+/// Maximum received packet size
+const MAX_PACKET: usize = 2048;
+
+/// Configuration for gRPC server operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct GrpcServeOptions {
+    /// Address to listen for gRPC connections
+    #[cfg_attr(feature = "clap", clap(long, default_value = "127.0.0.1:50051"))]
+    pub listen: std::net::SocketAddr,
+}
+
+fn status<E: core::fmt::Debug>(e: E) -> Status {
+    Status::internal(format!("{:?}", e))
+}
+
+/// gRPC service wrapping a local radio
+pub struct RadioService<T> {
+    radio: Mutex<T>,
+}
+
+impl<T> RadioService<T> {
+    /// Create a new service for the provided radio
+    pub fn new(radio: T) -> Self {
+        Self {
+            radio: Mutex::new(radio),
+        }
+    }
+
+    fn radio(&self) -> std::sync::MutexGuard<'_, T> {
+        // Radio state remains usable after a panicked request
+        self.radio.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[tonic::async_trait]
+impl<T, I, E> RadioRpc for RadioService<T>
+where
+    T: Transmit<Error = E>
+        + Receive<Info = I, Error = E>
+        + Power<Error = E>
+        + Rssi<Error = E>
+        + Send
+        + 'static,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    async fn start_transmit(
+        &self,
+        req: Request<TransmitRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        self.radio()
+            .start_transmit(&req.into_inner().data)
+            .map_err(status)?;
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn check_transmit(
+        &self,
+        _req: Request<Empty>,
+    ) -> Result<Response<CheckResponse>, Status> {
+        let ready = self.radio().check_transmit().map_err(status)?;
+        Ok(Response::new(CheckResponse { ready }))
+    }
+
+    async fn start_receive(&self, _req: Request<Empty>) -> Result<Response<Empty>, Status> {
+        self.radio().start_receive().map_err(status)?;
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn check_receive(
+        &self,
+        req: Request<CheckReceiveRequest>,
+    ) -> Result<Response<CheckResponse>, Status> {
+        let ready = self
+            .radio()
+            .check_receive(req.into_inner().restart)
+            .map_err(status)?;
+        Ok(Response::new(CheckResponse { ready }))
+    }
+
+    async fn get_received(
+        &self,
+        _req: Request<Empty>,
+    ) -> Result<Response<ReceivedResponse>, Status> {
+        let mut buff = vec![0u8; MAX_PACKET];
+        let (n, i) = self.radio().get_received(&mut buff).map_err(status)?;
+        buff.truncate(n);
+
+        Ok(Response::new(ReceivedResponse {
+            data: buff,
+            rssi: i.rssi() as i32,
+        }))
+    }
+
+    async fn set_power(&self, req: Request<PowerRequest>) -> Result<Response<Empty>, Status> {
+        let power = i8::try_from(req.into_inner().power)
+            .map_err(|_| Status::invalid_argument("power out of range"))?;
+        self.radio().set_power(power).map_err(status)?;
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn poll_rssi(&self, _req: Request<Empty>) -> Result<Response<RssiResponse>, Status> {
+        let rssi = self.radio().poll_rssi().map_err(status)?;
+        Ok(Response::new(RssiResponse { rssi: rssi as i32 }))
+    }
+}
+
+/// Serve a local radio over gRPC until the server exits
+pub fn do_grpc_serve<T, I, E>(radio: T, options: GrpcServeOptions) -> Result<(), RemoteError>
+where
+    T: Transmit<Error = E>
+        + Receive<Info = I, Error = E>
+        + Power<Error = E>
+        + Rssi<Error = E>
+        + Send
+        + 'static,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    let rt = tokio::runtime::Runtime::new()?;
+
+    info!("Serving radio over gRPC on {}", options.listen);
+
+    rt.block_on(
+        tonic::transport::Server::builder()
+            .add_service(RadioServer::new(RadioService::new(radio)))
+            .serve(options.listen),
+    )?;
+
+    Ok(())
+}
+
+/// Errors from remote radio operations
+#[derive(Debug)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum RemoteError {
+    /// Error returned by the remote service
+    #[cfg_attr(feature = "thiserror", error("Remote: {0}"))]
+    Status(Box<Status>),
+    /// Connection error
+    #[cfg_attr(feature = "thiserror", error("Transport: {0}"))]
+    Transport(tonic::transport::Error),
+    /// Local runtime error
+    #[cfg_attr(feature = "thiserror", error("IO: {0}"))]
+    Io(std::io::Error),
+}
+
+impl From<Status> for RemoteError {
+    fn from(e: Status) -> Self {
+        RemoteError::Status(Box::new(e))
+    }
+}
+
+impl From<tonic::transport::Error> for RemoteError {
+    fn from(e: tonic::transport::Error) -> Self {
+        RemoteError::Transport(e)
+    }
+}
+
+impl From<std::io::Error> for RemoteError {
+    fn from(e: std::io::Error) -> Self {
+        RemoteError::Io(e)
+    }
+}
+
+/// Radio implemented over a connection to a remote gRPC service
+pub struct RemoteRadio {
+    client: RadioClient<tonic::transport::Channel>,
+    rt: tokio::runtime::Runtime,
+}
+
+impl RemoteRadio {
+    /// Connect to a remote radio service (eg. `http://127.0.0.1:50051`)
+    pub fn connect(endpoint: &str) -> Result<Self, RemoteError> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let client = rt.block_on(RadioClient::connect(endpoint.to_string()))?;
+
+        Ok(Self { client, rt })
+    }
+}
+
+impl Transmit for RemoteRadio {
+    type Error = RemoteError;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let req = TransmitRequest {
+            data: data.to_vec(),
+        };
+        self.rt.block_on(self.client.start_transmit(req))?;
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        let r = self.rt.block_on(self.client.check_transmit(Empty {}))?;
+        Ok(r.into_inner().ready)
+    }
+}
+
+impl Receive for RemoteRadio {
+    type Error = RemoteError;
+    type Info = BasicInfo;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.rt.block_on(self.client.start_receive(Empty {}))?;
+        Ok(())
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        let req = CheckReceiveRequest { restart };
+        let r = self.rt.block_on(self.client.check_receive(req))?;
+        Ok(r.into_inner().ready)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let r = self
+            .rt
+            .block_on(self.client.get_received(Empty {}))?
+            .into_inner();
+
+        let n = r.data.len().min(buff.len());
+        buff[..n].copy_from_slice(&r.data[..n]);
+
+        Ok((n, BasicInfo::new(r.rssi as i16, 0)))
+    }
+}
+
+impl Power for RemoteRadio {
+    type Error = RemoteError;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        let req = PowerRequest {
+            power: power as i32,
+        };
+        self.rt.block_on(self.client.set_power(req))?;
+        Ok(())
+    }
+}
+
+impl Rssi for RemoteRadio {
+    type Error = RemoteError;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        let r = self.rt.block_on(self.client.poll_rssi(Empty {}))?;
+        Ok(r.into_inner().rssi as i16)
+    }
+}
+
+impl DelayNs for RemoteRadio {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(ns as u64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::{BlockingOptions, BlockingReceive, BlockingTransmit};
+    use crate::helpers::UdpRadio;
+
+    #[test]
+    fn remote_radio() {
+        let any = "127.0.0.1:0".parse().unwrap();
+        let mut peer = UdpRadio::new(any, any).unwrap();
+        let mut local = UdpRadio::new(any, peer.local_addr().unwrap()).unwrap();
+        local.set_rssi(-55);
+
+        let local_addr = local.local_addr().unwrap();
+
+        // Find a free port for the service
+        let addr = std::net::TcpListener::bind(any)
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(
+            tonic::transport::Server::builder()
+                .add_service(RadioServer::new(RadioService::new(local)))
+                .serve(addr),
+        );
+
+        // Retry until the server is listening
+        let endpoint = format!("http://{}", addr);
+        let mut remote = (0..50)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                RemoteRadio::connect(&endpoint).ok()
+            })
+            .unwrap();
+        let opts = BlockingOptions::default();
+
+        // Transmit via the remote radio
+        remote.do_transmit(&[1, 2, 3], opts.clone()).unwrap();
+        let mut buff = [0u8; 16];
+        let (n, _) = peer.do_receive(&mut buff, opts.clone()).unwrap();
+        assert_eq!(&buff[..n], &[1, 2, 3]);
+
+        // Receive via the remote radio
+        peer.set_peer(local_addr);
+        peer.do_transmit(&[4, 5], opts.clone()).unwrap();
+        let (n, i) = remote.do_receive(&mut buff, opts).unwrap();
+        assert_eq!(&buff[..n], &[4, 5]);
+        assert_eq!(i.rssi(), -55);
+
+        remote.set_power(7).unwrap();
+        assert_eq!(remote.poll_rssi().unwrap(), -55);
+    }
+}
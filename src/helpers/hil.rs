@@ -0,0 +1,458 @@
+//! Hardware-in-the-loop test harness helpers
+//!
+//! Supports running real-RF tests of this crate's operations in CI racks with two attached
+//! radios. [`discover_devices`] lists attached devices (by default from
+//! `/dev/serial/by-id`) so the radios under test can be selected by ID or serial number,
+//! and [`HilSuite`] runs named test cases against the pair, supervising each with a timeout
+//! and writing JUnit-style XML results for CI reporting.
+//!
+//! Cases are provided a per-case [`Shutdown`] signal, triggered once the case timeout
+//! elapses, which should be threaded through to operation options so that hung cases exit
+//! and are reported as timed out.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::Shutdown;
+
+/// Options for hardware-in-the-loop test runs
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct HilOptions {
+    /// Directory listing attached devices
+    #[cfg_attr(feature = "clap", clap(long, default_value = "/dev/serial/by-id"))]
+    pub hil_devices: String,
+
+    /// ID or serial number of the first radio under test
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub hil_a: Option<String>,
+
+    /// ID or serial number of the second radio under test
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub hil_b: Option<String>,
+
+    /// Timeout for each test case
+    #[cfg_attr(feature = "clap", clap(long, default_value = "30s"))]
+    pub case_timeout: HumanDuration,
+
+    /// Test suite name for reported results
+    #[cfg_attr(feature = "clap", clap(long, default_value = "radio-hil"))]
+    pub suite: String,
+
+    /// Write JUnit XML results to the provided file
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub junit: Option<String>,
+
+    #[cfg_attr(feature="clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+}
+
+impl Default for HilOptions {
+    fn default() -> Self {
+        Self {
+            hil_devices: "/dev/serial/by-id".to_string(),
+            hil_a: None,
+            hil_b: None,
+            case_timeout: Duration::from_secs(30).into(),
+            suite: "radio-hil".to_string(),
+            junit: None,
+            shutdown: Shutdown::global(),
+        }
+    }
+}
+
+/// Attached device available for testing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HilDevice {
+    /// Device ID (the device entry name)
+    pub id: String,
+    /// Path to the device
+    pub path: PathBuf,
+}
+
+impl HilDevice {
+    /// Serial number, parsed from `usb-<vendor>_<product>_<serial>-if<n>` style IDs
+    pub fn serial(&self) -> Option<&str> {
+        let id = self.id.strip_prefix("usb-").unwrap_or(&self.id);
+        let id = id.rsplit_once("-if").map_or(id, |(d, _)| d);
+        id.rsplit_once('_')
+            .map(|(_, s)| s)
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Check whether the device matches an ID or serial number
+    pub fn matches(&self, id: &str) -> bool {
+        self.id == id || self.serial() == Some(id)
+    }
+}
+
+/// List attached devices in the provided directory, sorted by ID
+pub fn discover_devices(dir: impl AsRef<Path>) -> Result<Vec<HilDevice>, std::io::Error> {
+    let mut devices = Vec::new();
+    for e in std::fs::read_dir(dir)? {
+        let e = e?;
+        devices.push(HilDevice {
+            id: e.file_name().to_string_lossy().into_owned(),
+            path: e.path(),
+        });
+    }
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(devices)
+}
+
+/// Select a device by ID or serial number, falling back to a unique partial ID match
+pub fn select_device<'a>(devices: &'a [HilDevice], id: &str) -> Option<&'a HilDevice> {
+    if let Some(d) = devices.iter().find(|d| d.matches(id)) {
+        return Some(d);
+    }
+    let mut partial = devices.iter().filter(|d| d.id.contains(id));
+    match (partial.next(), partial.next()) {
+        (Some(d), None) => Some(d),
+        _ => None,
+    }
+}
+
+impl HilOptions {
+    /// Discover attached devices and select the pair under test, defaulting to the first
+    /// two devices where IDs are not provided
+    pub fn select(&self) -> Result<(HilDevice, HilDevice), std::io::Error> {
+        let devices = discover_devices(&self.hil_devices)?;
+        let not_found = |id: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No device matching '{}' in {}", id, self.hil_devices),
+            )
+        };
+
+        let find = |id: &Option<String>| match id {
+            Some(id) => select_device(&devices, id)
+                .map(Some)
+                .ok_or_else(|| not_found(id)),
+            None => Ok(None),
+        };
+        let (a, b) = (find(&self.hil_a)?, find(&self.hil_b)?);
+
+        // Default to the first devices not otherwise selected
+        let mut unused = devices
+            .iter()
+            .filter(|d| Some(d.id.as_str()) != a.or(b).map(|d| d.id.as_str()));
+        let a = match a {
+            Some(a) => a,
+            None => unused.next().ok_or_else(|| not_found("*"))?,
+        };
+        let b = match b {
+            Some(b) => b,
+            None => unused.next().ok_or_else(|| not_found("*"))?,
+        };
+
+        info!("HIL devices: {} / {}", a.id, b.id);
+
+        Ok((a.clone(), b.clone()))
+    }
+}
+
+/// Test case outcome
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HilOutcome {
+    Passed,
+    /// Case failed with the provided message
+    Failed(String),
+    /// Case did not complete within the case timeout
+    TimedOut,
+    /// Case was not run as the suite was shut down
+    Skipped,
+}
+
+/// Test case result
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HilCaseResult {
+    pub name: String,
+    pub outcome: HilOutcome,
+    /// Case duration in seconds
+    pub duration_s: f64,
+}
+
+/// Test suite results
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HilReport {
+    pub suite: String,
+    pub cases: Vec<HilCaseResult>,
+}
+
+impl HilReport {
+    /// Check whether all cases passed
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(|c| c.outcome == HilOutcome::Passed)
+    }
+
+    /// Count cases with outcomes matching the provided predicate
+    fn count(&self, f: impl Fn(&HilOutcome) -> bool) -> usize {
+        self.cases.iter().filter(|c| f(&c.outcome)).count()
+    }
+
+    /// Render results as JUnit XML
+    pub fn to_junit(&self) -> String {
+        use std::fmt::Write as _;
+
+        let time: f64 = self.cases.iter().map(|c| c.duration_s).sum();
+        let failures = self.count(|o| matches!(o, HilOutcome::Failed(_)));
+        let errors = self.count(|o| matches!(o, HilOutcome::TimedOut));
+        let skipped = self.count(|o| matches!(o, HilOutcome::Skipped));
+
+        let mut s = String::new();
+        let _ = writeln!(s, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        let _ = writeln!(
+            s,
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            xml_escape(&self.suite),
+            self.cases.len(),
+            failures,
+            errors,
+            skipped,
+            time
+        );
+        for c in &self.cases {
+            let _ = write!(
+                s,
+                "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                xml_escape(&self.suite),
+                xml_escape(&c.name),
+                c.duration_s
+            );
+            match &c.outcome {
+                HilOutcome::Passed => {
+                    let _ = writeln!(s, "/>");
+                }
+                HilOutcome::Failed(m) => {
+                    let _ = writeln!(
+                        s,
+                        ">\n    <failure message=\"{}\"/>\n  </testcase>",
+                        xml_escape(m)
+                    );
+                }
+                HilOutcome::TimedOut => {
+                    let _ = writeln!(
+                        s,
+                        ">\n    <error message=\"timed out after {:.3} s\"/>\n  </testcase>",
+                        c.duration_s
+                    );
+                }
+                HilOutcome::Skipped => {
+                    let _ = writeln!(s, ">\n    <skipped/>\n  </testcase>");
+                }
+            }
+        }
+        let _ = writeln!(s, "</testsuite>");
+
+        s
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Hardware-in-the-loop test suite, running timeout-supervised cases against two radios
+pub struct HilSuite<A, B> {
+    radio_a: A,
+    radio_b: B,
+    options: HilOptions,
+    cases: Vec<HilCaseResult>,
+}
+
+impl<A, B> HilSuite<A, B> {
+    /// Create a suite for the provided radios
+    pub fn new(radio_a: A, radio_b: B, options: HilOptions) -> Self {
+        Self {
+            radio_a,
+            radio_b,
+            options,
+            cases: Vec::new(),
+        }
+    }
+
+    /// Run a test case, returning its result
+    ///
+    /// The case is provided a shutdown signal which is triggered once the case timeout
+    /// elapses (or the suite is shut down). Cases returning after the timeout are reported
+    /// as timed out.
+    pub fn case<F>(&mut self, name: &str, f: F) -> &HilCaseResult
+    where
+        F: FnOnce(&mut A, &mut B, Shutdown) -> Result<(), String>,
+    {
+        if self.options.shutdown.is_triggered() {
+            self.cases.push(HilCaseResult {
+                name: name.to_string(),
+                outcome: HilOutcome::Skipped,
+                duration_s: 0.0,
+            });
+            return self.cases.last().unwrap();
+        }
+
+        info!("HIL case: {}", name);
+
+        // Watchdog triggers the case shutdown signal on timeout or suite shutdown
+        let case_shutdown = Shutdown::default();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let watchdog = {
+            let (case_shutdown, suite_shutdown) =
+                (case_shutdown.clone(), self.options.shutdown.clone());
+            let timeout = *self.options.case_timeout;
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                loop {
+                    match done_rx.recv_timeout(Duration::from_millis(10)) {
+                        Err(mpsc::RecvTimeoutError::Timeout)
+                            if start.elapsed() < timeout && !suite_shutdown.is_triggered() => {}
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            case_shutdown.trigger();
+                            return;
+                        }
+                        _ => return,
+                    }
+                }
+            })
+        };
+
+        let start = Instant::now();
+        let res = f(&mut self.radio_a, &mut self.radio_b, case_shutdown.clone());
+        let duration = start.elapsed();
+
+        let _ = done_tx.send(());
+        let _ = watchdog.join();
+
+        let outcome = match res {
+            _ if duration >= *self.options.case_timeout => HilOutcome::TimedOut,
+            Ok(()) => HilOutcome::Passed,
+            Err(e) => HilOutcome::Failed(e),
+        };
+
+        match &outcome {
+            HilOutcome::Passed => info!("HIL case {} passed ({:?})", name, duration),
+            o => warn!("HIL case {} {:?} ({:?})", name, o, duration),
+        }
+
+        self.cases.push(HilCaseResult {
+            name: name.to_string(),
+            outcome,
+            duration_s: duration.as_secs_f64(),
+        });
+        self.cases.last().unwrap()
+    }
+
+    /// Fetch suite results
+    pub fn report(&self) -> HilReport {
+        HilReport {
+            suite: self.options.suite.clone(),
+            cases: self.cases.clone(),
+        }
+    }
+
+    /// Complete the suite, writing JUnit results where configured and releasing the radios
+    pub fn finish(self) -> Result<(HilReport, A, B), std::io::Error> {
+        let report = self.report();
+
+        info!(
+            "HIL suite {}: {} of {} cases passed",
+            report.suite,
+            report.count(|o| *o == HilOutcome::Passed),
+            report.cases.len()
+        );
+
+        if let Some(path) = &self.options.junit {
+            std::fs::write(path, report.to_junit())?;
+        }
+
+        Ok((report, self.radio_a, self.radio_b))
+    }
+}
+
+/// Run operations on both radios concurrently (for example a transmit against a receive),
+/// returning both results
+pub fn run_pair<A, B, RA, RB, FA, FB>(radio_a: &mut A, radio_b: &mut B, fa: FA, fb: FB) -> (RA, RB)
+where
+    A: Send,
+    B: Send,
+    RA: Send,
+    RB: Send,
+    FA: FnOnce(&mut A) -> RA + Send,
+    FB: FnOnce(&mut B) -> RB + Send,
+{
+    std::thread::scope(|s| {
+        let b = s.spawn(|| fb(radio_b));
+        let a = fa(radio_a);
+        (a, b.join().expect("HIL radio thread panicked"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hil_suite() {
+        let dir = std::env::temp_dir().join(format!("radio-hil-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for id in [
+            "usb-Acme_Radio_A1B2C3-if00-port0",
+            "usb-Acme_Radio_D4E5F6-if00-port0",
+        ] {
+            std::fs::write(dir.join(id), "").unwrap();
+        }
+
+        let junit = dir.join("results.xml");
+        let options = HilOptions {
+            hil_devices: dir.to_string_lossy().into_owned(),
+            hil_b: Some("A1B2C3".to_string()),
+            case_timeout: Duration::from_millis(50).into(),
+            junit: Some(junit.to_string_lossy().into_owned()),
+            shutdown: Shutdown::default(),
+            ..Default::default()
+        };
+
+        // Devices are selected by serial, defaulting to the first other device
+        let (a, b) = options.select().unwrap();
+        assert_eq!(b.serial(), Some("A1B2C3"));
+        assert_eq!(a.serial(), Some("D4E5F6"));
+
+        let mut suite = HilSuite::new(0u32, 0u32, options);
+        suite.case("pass", |a, b, _| {
+            run_pair(a, b, |a| *a += 1, |b| *b += 2);
+            Ok(())
+        });
+        suite.case("fail <rx>", |_, _, _| Err("no \"response\"".to_string()));
+        let timed_out = suite.case("hang", |_, _, shutdown| {
+            while !shutdown.is_triggered() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Ok(())
+        });
+        assert_eq!(timed_out.outcome, HilOutcome::TimedOut);
+
+        let (report, a, b) = suite.finish().unwrap();
+        assert_eq!((a, b), (1, 2));
+        assert!(!report.passed());
+
+        let xml = std::fs::read_to_string(&junit).unwrap();
+        assert!(xml.contains("tests=\"3\" failures=\"1\" errors=\"1\" skipped=\"0\""));
+        assert!(xml.contains("name=\"fail &lt;rx&gt;\""));
+        assert!(xml.contains("<failure message=\"no &quot;response&quot;\"/>"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
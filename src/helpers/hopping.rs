@@ -0,0 +1,411 @@
+//! Channel hopping link tests
+//!
+//! Runs ping-pong rounds (or blocks of rounds) on each channel in a list, reporting loss
+//! and RSSI per channel to reveal frequency-selective fading at a site. Channels are
+//! specified as driver-specific numbers (or frequencies) and mapped to the radio
+//! [`Channel`] type by the caller. The peer follows the same hop schedule with
+//! [`do_echo_hopping`], hopping on the round index of each ping (or on receive timeout
+//! where a ping is lost).
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+use byteorder::{ByteOrder, NetworkEndian};
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ECHO_HEADROOM, EchoOptions, LinkTestInfo, PingPongOptions, echo_response, ping_pong_round,
+};
+use crate::{
+    Channel, Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingReceive, BlockingTransmit},
+    clock::StdClock,
+};
+
+/// Channel hopping options for link tests
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct HopOptions {
+    /// Channels to hop between, comma separated (driver-specific channel numbers or frequencies)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub channels: Vec<u32>,
+
+    /// Consecutive rounds on each channel before hopping
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1"))]
+    pub hop_rounds: u32,
+}
+
+impl Default for HopOptions {
+    fn default() -> Self {
+        Self {
+            channels: vec![],
+            hop_rounds: 1,
+        }
+    }
+}
+
+impl HopOptions {
+    /// Channel for the provided round index, or `None` where hopping is disabled
+    pub fn channel(&self, round: u32) -> Option<u32> {
+        if self.channels.is_empty() {
+            return None;
+        }
+        let block = round / self.hop_rounds.max(1);
+        Some(self.channels[block as usize % self.channels.len()])
+    }
+}
+
+/// Link test results for a single channel
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelLinkInfo {
+    pub channel: u32,
+    pub link: LinkTestInfo,
+}
+
+impl ChannelLinkInfo {
+    /// Packet loss in percent
+    pub fn loss(&self) -> f32 {
+        100.0 - self.link.received as f32 * 100.0 / self.link.sent.max(1) as f32
+    }
+}
+
+/// Format a per-channel loss and RSSI table
+pub struct ChannelTable<'a>(pub &'a [ChannelLinkInfo]);
+
+impl core::fmt::Display for ChannelTable<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>9} {:>8} {:>11} {:>12}",
+            "channel", "received", "loss (%)", "local rssi", "remote rssi"
+        )?;
+        for c in self.0 {
+            let rssi = |m: Option<f32>| m.map(|v| format!("{:.1}", v)).unwrap_or("-".into());
+            writeln!(
+                f,
+                "{:>10} {:>9} {:>8.1} {:>11} {:>12}",
+                c.channel,
+                format!("{}/{}", c.link.received, c.link.sent),
+                c.loss(),
+                rssi(c.link.local_rssi.mean()),
+                rssi(c.link.remote_rssi.mean()),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a link test hopping between the configured channels, returning results per channel
+///
+/// `to_channel` maps configured channel numbers to the radio channel type. Where no
+/// channels are configured the test runs on the current channel, reported as channel 0.
+pub fn do_ping_pong_hopping<T, I, E, F>(
+    radio: &mut T,
+    options: PingPongOptions,
+    mut to_channel: F,
+) -> Result<Vec<ChannelLinkInfo>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + Channel<Error = E>
+        + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+    F: FnMut(u32) -> T::Channel,
+{
+    let len = options.payload_len();
+
+    // Allow space for appended info in responses
+    let mut buff = vec![0u8; len.max(256) + 16];
+
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut results: Vec<ChannelLinkInfo> = vec![];
+    let mut current = None;
+    let mut cipher = options.crypto_options.cipher();
+
+    for i in 0..options.rounds {
+        let channel = options.hop_options.channel(i);
+
+        if channel != current
+            && let Some(c) = channel
+        {
+            debug!("Hopping to channel {}", c);
+            radio.set_channel(&to_channel(c))?;
+            current = channel;
+        }
+
+        let channel = channel.unwrap_or(0);
+        let r = match results.iter_mut().position(|r| r.channel == channel) {
+            Some(n) => &mut results[n],
+            None => {
+                results.push(ChannelLinkInfo {
+                    channel,
+                    link: LinkTestInfo::new(len),
+                });
+                results.last_mut().unwrap()
+            }
+        };
+
+        r.link.sent += 1;
+        ping_pong_round(
+            radio,
+            &mut buff,
+            &StdClock,
+            i,
+            &options,
+            &mut cipher,
+            &mut r.link,
+        )?;
+    }
+
+    for r in results.iter_mut() {
+        r.link.loss_bursts.finish();
+    }
+
+    info!(
+        "Channel hopping link test complete:\n{}",
+        ChannelTable(&results)
+    );
+
+    Ok(results)
+}
+
+/// Echo link test pings, following the initiator's channel hopping schedule
+///
+/// After each ping the radio hops to the channel for the following round. Where no ping
+/// is received before the blocking timeout the initiator is assumed to have moved on a
+/// round, so the timeout should be similar to that of the initiator (and exceed its
+/// inter-round delay). Returns the number of responses sent once `options.continuous`
+/// is unset and a full hop cycle has elapsed.
+pub fn do_echo_hopping<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: EchoOptions,
+    hop_options: &HopOptions,
+    mut to_channel: F,
+) -> Result<u32, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + Channel<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    F: FnMut(u32) -> T::Channel,
+{
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut work = vec![0u8; buff.len() + ECHO_HEADROOM];
+    let cycle = hop_options.channels.len() as u32 * hop_options.hop_rounds.max(1);
+
+    let mut round = 0;
+    let mut current = None;
+    let mut responses = 0;
+    let mut cipher = options.crypto_options.cipher();
+
+    loop {
+        let channel = hop_options.channel(round);
+        if channel != current
+            && let Some(c) = channel
+        {
+            debug!("Hopping to channel {} for round {}", c, round);
+            radio.set_channel(&to_channel(c))?;
+            current = channel;
+        }
+
+        if !options.continuous && round >= cycle.max(1) {
+            return Ok(responses);
+        }
+
+        let (mut n, i) =
+            match radio.do_receive(&mut work[..buff.len()], options.blocking_options.clone()) {
+                Ok(r) => r,
+                Err(BlockingError::Timeout) => {
+                    round += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+        // Decrypt payload where enabled, ignoring pings failing authentication
+        if let Some(c) = cipher.as_mut() {
+            n = match c.decrypt_in_place(&mut work, options.header_len(), n) {
+                Some(n) => n,
+                None => continue,
+            };
+        }
+
+        if n < 4 {
+            continue;
+        }
+        let index = NetworkEndian::read_u32(&work[..n]);
+
+        // Respond on the current channel, then follow the initiator to the next round
+        let transform = options.transform;
+        let response = echo_response(&mut work, n, &i, &options, &mut |b, n, _| {
+            transform.apply(b, n)
+        });
+        let frames = match (response, cipher.as_mut()) {
+            (Some(n), Some(c)) => options
+                .encrypted_response_frames(&work[..n], buff.len(), c)
+                .ok(),
+            (Some(n), None) => Some(options.response_frames(&work[..n], buff.len())),
+            (None, _) => None,
+        };
+        if let Some(frames) = frames {
+            radio.delay_us(options.delay.as_micros() as u32);
+            for f in frames {
+                radio.do_transmit(&f, options.blocking_options.clone())?;
+            }
+            responses += 1;
+        }
+
+        round = index.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+    #[cfg(feature = "crypto")]
+    use crate::helpers::CryptoOptions;
+
+    /// Loopback radio dropping frames on a faded channel
+    struct FadingRadio {
+        channel: u32,
+        faded: u32,
+        last: Option<Vec<u8>>,
+    }
+
+    impl Transmit for FadingRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.last = Some(data.to_vec()).filter(|_| self.channel != self.faded);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for FadingRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().unwrap();
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(-40 - self.channel as i16, 0)))
+        }
+    }
+
+    impl Power for FadingRadio {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl Channel for FadingRadio {
+        type Channel = u32;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &u32) -> Result<(), Self::Error> {
+            self.channel = *channel;
+            Ok(())
+        }
+    }
+
+    impl DelayNs for FadingRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    fn link_options(rounds: u32, hop_options: HopOptions) -> PingPongOptions {
+        PingPongOptions {
+            rounds,
+            delay: std::time::Duration::from_millis(0).into(),
+            backoff: std::time::Duration::from_millis(1).into(),
+            hop_options,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn channel_hopping_link_test() {
+        let hop = HopOptions {
+            channels: vec![11, 12, 13],
+            hop_rounds: 2,
+        };
+        assert_eq!(
+            (0..8).map(|i| hop.channel(i).unwrap()).collect::<Vec<_>>(),
+            vec![11, 11, 12, 12, 13, 13, 11, 11]
+        );
+        assert_eq!(HopOptions::default().channel(3), None);
+
+        let options = link_options(12, hop);
+
+        let mut radio = FadingRadio {
+            channel: 0,
+            faded: 12,
+            last: None,
+        };
+        let r = do_ping_pong_hopping(&mut radio, options, |c| c).unwrap();
+
+        let summary: Vec<_> = r
+            .iter()
+            .map(|c| (c.channel, c.link.sent, c.link.received))
+            .collect();
+        assert_eq!(summary, vec![(11, 4, 4), (12, 4, 0), (13, 4, 4)]);
+        assert_eq!(r[1].loss(), 100.0);
+        assert_eq!(r[2].link.local_rssi.mean(), Some(-53.0));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn encrypted_link_test() {
+        let options = PingPongOptions {
+            size: 8,
+            crypto_options: CryptoOptions {
+                key: Some([0x5a; 16]),
+                encrypt: true,
+                ..Default::default()
+            },
+            ..link_options(4, HopOptions::default())
+        };
+
+        let mut radio = FadingRadio {
+            channel: 0,
+            faded: 12,
+            last: None,
+        };
+        let r = do_ping_pong_hopping(&mut radio, options, |c| c).unwrap();
+
+        assert_eq!((r[0].link.sent, r[0].link.received), (4, 4));
+        assert_eq!(r[0].link.payload_len, 8);
+    }
+}
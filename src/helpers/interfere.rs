@@ -0,0 +1,438 @@
+//! Interference generator for receiver robustness testing
+//!
+//! The `interfere` operation runs on a second radio alongside a receiver under test,
+//! generating one of a set of canned interference patterns:
+//!
+//! - `bursts`: periodic continuous-wave (or modulated, with `--modulated`) bursts, using
+//!   [`TestMode`]
+//! - `packets`: random packets at a target duty cycle, with randomised gaps
+//! - `sweep`: a carrier stepped across `--sweep-channels`, dwelling on each for
+//!   `--sweep-dwell`, using [`TestMode`] and [`Channel`]
+//!
+//! Packet interference runs on any radio (see [`do_interfere_packets`]), with bursts and
+//! sweeps requiring test mode support (see
+//! [`do_operation_interfere`](super::do_operation_interfere)).
+
+use core::fmt::Debug;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::Shutdown;
+use crate::{
+    Channel, Power, TestMode, TestSignal, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+    clock::Clock,
+    prng::XorShift32,
+};
+
+/// Interference pattern
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum InterferencePattern {
+    /// Periodic carrier bursts
+    Bursts,
+    /// Random packets at a duty cycle
+    Packets,
+    /// Carrier swept across a set of channels
+    Sweep,
+}
+
+/// Configuration for interference generation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct InterfereOptions {
+    /// Interference pattern
+    #[cfg_attr(feature = "clap", clap(value_enum))]
+    pub pattern: InterferencePattern,
+
+    /// Duration of interference generation
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10s"))]
+    pub duration: HumanDuration,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    /// Transmit a modulated signal rather than an unmodulated carrier for bursts and sweeps
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub modulated: bool,
+
+    /// Duration of each burst
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10ms"))]
+    pub burst_length: HumanDuration,
+
+    /// Interval between the start of each burst
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100ms"))]
+    pub burst_period: HumanDuration,
+
+    /// Random packet length in bytes
+    #[cfg_attr(feature = "clap", clap(long, default_value = "32"))]
+    pub packet_size: usize,
+
+    /// Target packet duty cycle in percent
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10"))]
+    pub duty: f32,
+
+    /// Channels to sweep, comma separated (driver-specific channel numbers or frequencies)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub sweep_channels: Vec<u32>,
+
+    /// Dwell time on each swept channel
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10ms"))]
+    pub sweep_dwell: HumanDuration,
+
+    /// Seed for packet contents and gaps (defaults to a random seed)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub seed: Option<u32>,
+
+    /// Shutdown signal, stopping interference once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for InterfereOptions {
+    fn default() -> Self {
+        Self {
+            pattern: InterferencePattern::Bursts,
+            duration: std::time::Duration::from_secs(10).into(),
+            power: None,
+            modulated: false,
+            burst_length: std::time::Duration::from_millis(10).into(),
+            burst_period: std::time::Duration::from_millis(100).into(),
+            packet_size: 32,
+            duty: 10.0,
+            sweep_channels: vec![],
+            sweep_dwell: std::time::Duration::from_millis(10).into(),
+            seed: None,
+            shutdown: Shutdown::global(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+impl InterfereOptions {
+    /// Test signal for bursts and sweeps
+    pub fn signal(&self) -> TestSignal {
+        match self.modulated {
+            true => TestSignal::Modulated,
+            false => TestSignal::Carrier,
+        }
+    }
+
+    /// Check whether generation should stop at `elapsed_us`
+    fn done(&self, elapsed_us: u64) -> bool {
+        self.shutdown.is_triggered() || elapsed_us >= self.duration.as_micros() as u64
+    }
+}
+
+/// Interference generation statistics
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InterferenceStats {
+    /// Carrier bursts transmitted
+    pub bursts: u32,
+    /// Packets transmitted
+    pub packets: u32,
+    /// Bytes transmitted in packets
+    pub bytes: u64,
+    /// Channels stepped to while sweeping
+    pub hops: u32,
+    /// Time spent transmitting, in milliseconds
+    pub on_air_ms: u64,
+    /// Total generation time, in milliseconds
+    pub elapsed_ms: u64,
+}
+
+impl InterferenceStats {
+    /// Percentage of time spent transmitting
+    pub fn duty(&self) -> f32 {
+        self.on_air_ms as f32 * 100.0 / self.elapsed_ms.max(1) as f32
+    }
+}
+
+impl core::fmt::Display for InterferenceStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} bursts, {} packets ({} bytes), {} hops, {} ms on air over {} ms ({:.1}% duty)",
+            self.bursts,
+            self.packets,
+            self.bytes,
+            self.hops,
+            self.on_air_ms,
+            self.elapsed_ms,
+            self.duty()
+        )
+    }
+}
+
+/// Transmit random packets at the configured duty cycle
+///
+/// Gaps between packets are drawn uniformly from zero to twice the mean gap required to
+/// meet the duty cycle given the measured transmit time of each packet.
+pub fn do_interfere_packets<T, C, E>(
+    radio: &mut T,
+    clock: C,
+    options: InterfereOptions,
+) -> Result<InterferenceStats, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    C: Clock,
+    E: Debug,
+{
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut rng = XorShift32::new(options.seed.unwrap_or_else(super::random_seed));
+    let mut payload = vec![0u8; options.packet_size];
+    let duty = options.duty.clamp(0.1, 100.0);
+    let mut stats = InterferenceStats::default();
+
+    info!(
+        "Generating {} byte packets at {:.1}% duty for {}",
+        options.packet_size, duty, options.duration
+    );
+
+    let start = clock.now_us();
+    while !options.done(clock.elapsed_us(start)) {
+        rng.fill(&mut payload);
+
+        let t0 = clock.now_us();
+        radio.do_transmit(&payload, options.blocking_options.clone())?;
+        let airtime_us = clock.elapsed_us(t0);
+
+        stats.packets += 1;
+        stats.bytes += payload.len() as u64;
+        stats.on_air_ms += airtime_us / 1000;
+
+        let gap_us = (airtime_us as f32 * (100.0 / duty - 1.0)) as u32;
+        radio.delay_us(rng.below(gap_us.saturating_mul(2).saturating_add(1)));
+    }
+    stats.elapsed_ms = clock.elapsed_us(start) / 1000;
+
+    info!("Interference complete: {}", stats);
+
+    Ok(stats)
+}
+
+/// Transmit periodic carrier bursts
+pub fn do_interfere_bursts<T, C, E>(
+    radio: &mut T,
+    clock: C,
+    options: InterfereOptions,
+) -> Result<InterferenceStats, BlockingError<E>>
+where
+    T: TestMode<Error = E> + Power<Error = E> + DelayNs,
+    C: Clock,
+    E: Debug,
+{
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let length_us = options.burst_length.as_micros() as u64;
+    let period_us = (options.burst_period.as_micros() as u64).max(length_us);
+    let mut stats = InterferenceStats::default();
+
+    info!(
+        "Generating {} bursts every {} for {}",
+        options.burst_length, options.burst_period, options.duration
+    );
+
+    let start = clock.now_us();
+    while !options.done(clock.elapsed_us(start)) {
+        let t0 = clock.now_us();
+
+        radio.start_test_mode(options.signal())?;
+        radio.delay_us(length_us as u32);
+        radio.stop_test_mode()?;
+
+        stats.bursts += 1;
+        stats.on_air_ms += clock.elapsed_us(t0) / 1000;
+
+        let elapsed = clock.elapsed_us(t0);
+        radio.delay_us(period_us.saturating_sub(elapsed) as u32);
+    }
+    stats.elapsed_ms = clock.elapsed_us(start) / 1000;
+
+    info!("Interference complete: {}", stats);
+
+    Ok(stats)
+}
+
+/// Sweep a carrier across the configured channels, dwelling on each in turn
+///
+/// `to_channel` maps configured channel numbers to the radio channel type.
+pub fn do_interfere_sweep<T, C, E, F>(
+    radio: &mut T,
+    clock: C,
+    options: InterfereOptions,
+    mut to_channel: F,
+) -> Result<InterferenceStats, BlockingError<E>>
+where
+    T: TestMode<Error = E> + Channel<Error = E> + Power<Error = E> + DelayNs,
+    C: Clock,
+    E: Debug,
+    F: FnMut(u32) -> T::Channel,
+{
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut stats = InterferenceStats::default();
+    if options.sweep_channels.is_empty() {
+        info!("No sweep channels configured");
+        return Ok(stats);
+    }
+
+    info!(
+        "Sweeping {} channels ({} dwell) for {}",
+        options.sweep_channels.len(),
+        options.sweep_dwell,
+        options.duration
+    );
+
+    let start = clock.now_us();
+    for c in options.sweep_channels.iter().cycle() {
+        if options.done(clock.elapsed_us(start)) {
+            break;
+        }
+
+        debug!("Sweeping channel {}", c);
+        radio.set_channel(&to_channel(*c))?;
+
+        let t0 = clock.now_us();
+        radio.start_test_mode(options.signal())?;
+        radio.delay_us(options.sweep_dwell.as_micros() as u32);
+        radio.stop_test_mode()?;
+
+        stats.hops += 1;
+        stats.on_air_ms += clock.elapsed_us(t0) / 1000;
+    }
+    stats.elapsed_ms = clock.elapsed_us(start) / 1000;
+
+    info!("Interference complete: {}", stats);
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    /// Interferer radio advancing a virtual clock, taking 1 ms to transmit each packet
+    struct Interferer<'a> {
+        clock: &'a VirtualClock,
+        channels: Vec<u32>,
+        carrier: bool,
+    }
+
+    impl Transmit for Interferer<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            self.clock.advance_us(1_000);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl TestMode for Interferer<'_> {
+        type Error = ();
+
+        fn start_test_mode(&mut self, _signal: TestSignal) -> Result<(), Self::Error> {
+            self.carrier = true;
+            Ok(())
+        }
+
+        fn stop_test_mode(&mut self) -> Result<(), Self::Error> {
+            match self.carrier {
+                true => self.carrier = false,
+                false => return Err(()),
+            }
+            Ok(())
+        }
+    }
+
+    impl Channel for Interferer<'_> {
+        type Channel = u32;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &u32) -> Result<(), Self::Error> {
+            self.channels.push(*channel);
+            Ok(())
+        }
+    }
+
+    impl Power for Interferer<'_> {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl DelayNs for Interferer<'_> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.clock.advance_us((ns as u64).div_ceil(1000));
+        }
+    }
+
+    #[test]
+    fn interference_patterns() {
+        let clock = VirtualClock::new();
+        let mut radio = Interferer {
+            clock: &clock,
+            channels: vec![],
+            carrier: false,
+        };
+        let options = InterfereOptions {
+            duration: std::time::Duration::from_secs(10).into(),
+            seed: Some(1),
+            shutdown: Shutdown::default(),
+            ..Default::default()
+        };
+
+        // 10 ms bursts every 100 ms
+        let s = do_interfere_bursts(&mut radio, &clock, options.clone()).unwrap();
+        assert_eq!((s.bursts, s.on_air_ms, s.elapsed_ms), (100, 1_000, 10_000));
+
+        // Random 1 ms packets at around 10% duty
+        let o = InterfereOptions {
+            pattern: InterferencePattern::Packets,
+            ..options.clone()
+        };
+        let s = do_interfere_packets(&mut radio, &clock, o).unwrap();
+        assert!((900..1100).contains(&s.packets), "{}", s);
+        assert!((9.0..11.0).contains(&s.duty()), "{}", s);
+
+        // Swept carrier cycling through channels
+        let o = InterfereOptions {
+            pattern: InterferencePattern::Sweep,
+            duration: std::time::Duration::from_millis(50).into(),
+            sweep_channels: vec![1, 2, 3],
+            ..options
+        };
+        let s = do_interfere_sweep(&mut radio, &clock, o, |c| c).unwrap();
+        assert_eq!(s.hops, 5);
+        assert_eq!(radio.channels, vec![1, 2, 3, 1, 2]);
+        assert!(!radio.carrier);
+    }
+}
@@ -0,0 +1,172 @@
+//! Timestamped operation journal
+//!
+//! The journal records operation start / stop (with configuration), errors and recovery
+//! events to an append-only JSON lines file, so long-running (soak) tests can be
+//! reconstructed after the fact. A process-wide journal is installed with
+//! [`install_journal`], after which [`journal`] records events from any operation
+//! (events are discarded where no journal is installed).
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::warn;
+
+#[cfg(feature = "defmt")]
+use defmt::warn;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use super::json;
+
+/// Options for the operation journal
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct JournalOptions {
+    /// Append operation start / stop, error and recovery events to this file as JSON lines
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub journal: Option<String>,
+}
+
+impl JournalOptions {
+    /// Open and install the configured journal, if any
+    pub fn install(&self) -> Result<(), std::io::Error> {
+        if let Some(path) = &self.journal {
+            install_journal(Journal::open(path)?);
+        }
+        Ok(())
+    }
+}
+
+/// Journal events
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEvent {
+    /// Operation started with the provided configuration
+    Start { operation: String, config: String },
+    /// Operation stopped, with the error where the operation failed
+    Stop {
+        operation: String,
+        elapsed_ms: u64,
+        error: Option<String>,
+    },
+    /// Error reported during an operation
+    Error { operation: String, error: String },
+    /// Recovery from a driver error
+    Recovery { operation: String, error: String },
+    /// Channel selected prior to an operation (with `--auto-channel`)
+    ChannelSelected { operation: String, channel: u32 },
+    /// Radio state changed (with `--show-state`), or remained unchanged beyond
+    /// `--stuck-after` where `stuck` is set
+    State {
+        operation: String,
+        state: String,
+        elapsed_ms: u64,
+        stuck: bool,
+    },
+}
+
+/// Journal entry, as written to the journal file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Event time in microseconds since the unix epoch
+    pub timestamp_us: u64,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+/// Append-only journal file
+pub struct Journal {
+    f: File,
+}
+
+impl Journal {
+    /// Open a journal file, appending to any existing entries
+    pub fn open(path: &str) -> Result<Self, std::io::Error> {
+        let f = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { f })
+    }
+
+    /// Record an event, flushing immediately so entries survive crashes
+    pub fn record(&mut self, event: JournalEvent) -> Result<(), std::io::Error> {
+        let entry = JournalEntry {
+            timestamp_us: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+            event,
+        };
+
+        let mut line = json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.f.write_all(&line)?;
+        self.f.flush()
+    }
+}
+
+static JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+
+/// Install the process-wide journal, replacing any existing journal
+pub fn install_journal(journal: Journal) {
+    *JOURNAL.lock().unwrap() = Some(journal);
+}
+
+/// Record an event to the process-wide journal, if installed
+pub fn journal(event: JournalEvent) {
+    if let Some(j) = JOURNAL.lock().unwrap().as_mut()
+        && let Err(e) = j.record(event)
+    {
+        warn!("Error writing journal: {:?}", e);
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_entries() {
+        let path = std::env::temp_dir().join(format!("radio-journal-{}.log", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&path);
+
+        let events = vec![
+            JournalEvent::Start {
+                operation: "soak".into(),
+                config: "Soak(..)".into(),
+            },
+            JournalEvent::Recovery {
+                operation: "soak".into(),
+                error: "Timeout".into(),
+            },
+            JournalEvent::Stop {
+                operation: "soak".into(),
+                elapsed_ms: 10,
+                error: None,
+            },
+        ];
+
+        // Entries are appended across journal instances
+        let mut j = Journal::open(&path).unwrap();
+        j.record(events[0].clone()).unwrap();
+        drop(j);
+        let mut j = Journal::open(&path).unwrap();
+        j.record(events[1].clone()).unwrap();
+        j.record(events[2].clone()).unwrap();
+
+        let s = std::fs::read_to_string(&path).unwrap();
+        assert!(s.lines().nth(1).unwrap().contains("\"event\":\"recovery\""));
+
+        let entries: Vec<JournalEntry> = s.lines().map(|l| json::from_str(l).unwrap()).collect();
+        assert_eq!(
+            entries.into_iter().map(|e| e.event).collect::<Vec<_>>(),
+            events
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+//! JSON encoding for saved results, tables and output streams
+//!
+//! Thin wrappers over `serde_json` returning [`std::io::Error`]s, so helpers persisting
+//! or emitting JSON build without the `json` feature and fail at runtime with
+//! [`ErrorKind::Unsupported`] instead.
+
+#[cfg(not(feature = "json"))]
+use std::io::{Error, ErrorKind};
+use std::io::{Read, Write};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+#[cfg(not(feature = "json"))]
+fn unsupported() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "JSON support requires the `json` feature",
+    )
+}
+
+/// Encode a value as JSON
+pub(crate) fn to_vec<T: Serialize + ?Sized>(v: &T) -> Result<Vec<u8>, std::io::Error> {
+    #[cfg(feature = "json")]
+    return Ok(serde_json::to_vec(v)?);
+    #[cfg(not(feature = "json"))]
+    return {
+        let _ = v;
+        Err(unsupported())
+    };
+}
+
+/// Encode a value as pretty-printed JSON
+pub(crate) fn to_vec_pretty<T: Serialize + ?Sized>(v: &T) -> Result<Vec<u8>, std::io::Error> {
+    #[cfg(feature = "json")]
+    return Ok(serde_json::to_vec_pretty(v)?);
+    #[cfg(not(feature = "json"))]
+    return {
+        let _ = v;
+        Err(unsupported())
+    };
+}
+
+/// Write a value as JSON
+pub(crate) fn to_writer<W: Write, T: Serialize + ?Sized>(
+    w: W,
+    v: &T,
+) -> Result<(), std::io::Error> {
+    #[cfg(feature = "json")]
+    return Ok(serde_json::to_writer(w, v)?);
+    #[cfg(not(feature = "json"))]
+    return {
+        let _ = (w, v);
+        Err(unsupported())
+    };
+}
+
+/// Write a value as pretty-printed JSON
+pub(crate) fn to_writer_pretty<W: Write, T: Serialize + ?Sized>(
+    w: W,
+    v: &T,
+) -> Result<(), std::io::Error> {
+    #[cfg(feature = "json")]
+    return Ok(serde_json::to_writer_pretty(w, v)?);
+    #[cfg(not(feature = "json"))]
+    return {
+        let _ = (w, v);
+        Err(unsupported())
+    };
+}
+
+/// Decode a value from JSON
+pub(crate) fn from_slice<T: DeserializeOwned>(d: &[u8]) -> Result<T, std::io::Error> {
+    #[cfg(feature = "json")]
+    return Ok(serde_json::from_slice(d)?);
+    #[cfg(not(feature = "json"))]
+    return {
+        let _ = d;
+        Err(unsupported())
+    };
+}
+
+/// Decode a value from a JSON string
+pub(crate) fn from_str<T: DeserializeOwned>(s: &str) -> Result<T, std::io::Error> {
+    from_slice(s.as_bytes())
+}
+
+/// Decode a value from a JSON reader
+pub(crate) fn from_reader<R: Read, T: DeserializeOwned>(r: R) -> Result<T, std::io::Error> {
+    #[cfg(feature = "json")]
+    return Ok(serde_json::from_reader(r)?);
+    #[cfg(not(feature = "json"))]
+    return {
+        let _ = r;
+        Err(unsupported())
+    };
+}
@@ -0,0 +1,276 @@
+//! Runtime log verbosity, per-module filters and rate limiting
+//!
+//! [`LogOptions`] selects the log level (`--verbose` / `--quiet`), per-module levels
+//! (`--log-filter radio::helpers::worker=debug,radio::blocking=warn`) and a per-module
+//! rate limit (`--log-rate`), so high-rate captures don't drown the console. Options are
+//! applied to a [`FilteredLogger`] wrapping the application log backend, installed with
+//! [`LogOptions::install`], and may be changed at runtime (for example before each
+//! operation) with [`LogOptions::apply`].
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "clap")]
+use clap::{ArgAction, Parser};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// Active log configuration, applied by [`FilteredLogger`]
+static CONFIG: RwLock<LogConfig> = RwLock::new(LogConfig {
+    level: LevelFilter::Info,
+    directives: Vec::new(),
+    rate: None,
+});
+
+/// Log level for a module (and its submodules)
+#[derive(Clone, PartialEq, Debug)]
+pub struct LogDirective {
+    /// Module path, applying to all modules where unset
+    pub module: Option<String>,
+    pub level: LevelFilter,
+}
+
+impl FromStr for LogDirective {
+    type Err = String;
+
+    /// Parse a directive in the form `module=level` or `level`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (module, level) = match s.split_once('=') {
+            Some((m, l)) => (Some(m.trim().to_string()), l),
+            None => (None, s),
+        };
+        let level = LevelFilter::from_str(level.trim())
+            .map_err(|_| format!("invalid log level in '{}'", s))?;
+
+        Ok(Self { module, level })
+    }
+}
+
+/// Options for log verbosity, filtering and rate limiting
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct LogOptions {
+    /// Increase log verbosity (`-v` for debug, `-vv` for trace)
+    #[cfg_attr(feature = "clap", clap(short = 'v', long = "verbose", action = ArgAction::Count))]
+    pub verbose: u8,
+
+    /// Only log warnings and errors
+    #[cfg_attr(feature = "clap", clap(short = 'q', long, conflicts_with = "verbose"))]
+    pub quiet: bool,
+
+    /// Per-module log levels (`module=level`, comma separated)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub log_filter: Vec<LogDirective>,
+
+    /// Limit info and lower priority records to this many per second per module,
+    /// summarising suppressed records
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub log_rate: Option<u32>,
+}
+
+impl LogOptions {
+    /// Default log level selected by `--verbose` / `--quiet`
+    pub fn level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Warn,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+
+    /// Apply these options to the active log configuration
+    pub fn apply(&self) {
+        let config = LogConfig {
+            level: self.level(),
+            directives: self.log_filter.clone(),
+            rate: self.log_rate,
+        };
+
+        log::set_max_level(config.max_level());
+        *CONFIG.write().unwrap() = config;
+    }
+
+    /// Install a [`FilteredLogger`] wrapping the provided backend, applying these options
+    pub fn install(&self, inner: Box<dyn Log>) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(FilteredLogger::new(inner)))?;
+        self.apply();
+        Ok(())
+    }
+}
+
+/// Log level and filter configuration
+#[derive(Clone, PartialEq, Debug)]
+pub struct LogConfig {
+    /// Default level, where no directive matches
+    pub level: LevelFilter,
+    /// Per-module levels, the longest matching module taking precedence
+    pub directives: Vec<LogDirective>,
+    /// Maximum info and lower priority records per second per module
+    pub rate: Option<u32>,
+}
+
+impl LogConfig {
+    /// Level applying to the provided target (module path)
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let matches =
+            |m: &str| target == m || (target.starts_with(m) && target[m.len()..].starts_with("::"));
+
+        self.directives
+            .iter()
+            .filter_map(|d| match &d.module {
+                Some(m) if matches(m) => Some((m.len(), d.level)),
+                Some(_) => None,
+                None => Some((0, d.level)),
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, l)| l)
+            .unwrap_or(self.level)
+    }
+
+    /// Most verbose level enabled for any module
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|d| d.level)
+            .fold(self.level, |a, b| a.max(b))
+    }
+}
+
+/// Per-module log rate limiter, with one second windows
+#[derive(Debug, Default)]
+pub struct LogRateLimiter {
+    windows: HashMap<String, (Instant, u32, u64)>,
+}
+
+impl LogRateLimiter {
+    /// Check whether a record for the provided target may be logged at `now`, returning
+    /// whether the record is allowed and the number of records suppressed in the previous
+    /// window where a new window has started
+    pub fn check(&mut self, target: &str, rate: u32, now: Instant) -> (bool, Option<u64>) {
+        let w = self
+            .windows
+            .entry(target.to_string())
+            .or_insert((now, 0, 0));
+
+        let mut suppressed = None;
+        if now.duration_since(w.0) >= Duration::from_secs(1) {
+            suppressed = Some(w.2).filter(|s| *s > 0);
+            *w = (now, 0, 0);
+        }
+
+        match w.1 < rate {
+            true => {
+                w.1 += 1;
+                (true, suppressed)
+            }
+            false => {
+                w.2 += 1;
+                (false, suppressed)
+            }
+        }
+    }
+}
+
+/// Logger applying the active [`LogConfig`] before passing records to a backend
+pub struct FilteredLogger {
+    inner: Box<dyn Log>,
+    limiter: Mutex<LogRateLimiter>,
+}
+
+impl FilteredLogger {
+    /// Wrap the provided log backend
+    pub fn new(inner: Box<dyn Log>) -> Self {
+        Self {
+            inner,
+            limiter: Mutex::new(LogRateLimiter::default()),
+        }
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let config = CONFIG.read().unwrap();
+        metadata.level() <= config.level_for(metadata.target()) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Warnings and errors are never rate limited
+        let rate = CONFIG.read().unwrap().rate;
+        if let Some(rate) = rate
+            && record.level() >= Level::Info
+        {
+            let (allowed, suppressed) =
+                self.limiter
+                    .lock()
+                    .unwrap()
+                    .check(record.target(), rate, Instant::now());
+
+            if let Some(n) = suppressed {
+                self.inner.log(
+                    &Record::builder()
+                        .args(format_args!("{} log records suppressed", n))
+                        .level(Level::Warn)
+                        .target(record.target())
+                        .build(),
+                );
+            }
+            if !allowed {
+                return;
+            }
+        }
+
+        self.inner.log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_filters() {
+        let options = LogOptions {
+            verbose: 0,
+            quiet: true,
+            log_filter: vec![
+                "radio::helpers=debug".parse().unwrap(),
+                "radio::helpers::worker=off".parse().unwrap(),
+            ],
+            log_rate: Some(2),
+        };
+        assert!("radio::helpers=loud".parse::<LogDirective>().is_err());
+
+        let config = LogConfig {
+            level: options.level(),
+            directives: options.log_filter.clone(),
+            rate: options.log_rate,
+        };
+        assert_eq!(config.level_for("radio::blocking"), LevelFilter::Warn);
+        assert_eq!(config.level_for("radio::helpers"), LevelFilter::Debug);
+        assert_eq!(config.level_for("radio::helpers::cli"), LevelFilter::Debug);
+        assert_eq!(config.level_for("radio::helpers::worker"), LevelFilter::Off);
+        assert_eq!(config.level_for("radio::helpersx"), LevelFilter::Warn);
+        assert_eq!(config.max_level(), LevelFilter::Debug);
+
+        // Records beyond the rate are suppressed, and reported in the next window
+        let mut limiter = LogRateLimiter::default();
+        let t = Instant::now();
+        let allowed: Vec<_> = (0..5).map(|_| limiter.check("a", 2, t).0).collect();
+        assert_eq!(allowed, vec![true, true, false, false, false]);
+        assert_eq!(limiter.check("b", 2, t), (true, None));
+        assert_eq!(
+            limiter.check("a", 2, t + Duration::from_secs(1)),
+            (true, Some(3))
+        );
+    }
+}
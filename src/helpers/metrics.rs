@@ -0,0 +1,265 @@
+//! Prometheus metrics for long-running receive and echo nodes
+//!
+//! The `rx` and `echo` loops (and transmit operations) update process-wide counters and
+//! gauges (see [`metrics`]) for packets, bytes, receive and decode failures, and RSSI.
+//! With `--metrics-addr`, a [`MetricsExporter`] serves these in the Prometheus text
+//! exposition format over HTTP, so fleet health can be scraped without parsing logs.
+
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+/// Upper bounds (dBm) of RSSI histogram buckets, excluding `+Inf`
+pub const RSSI_BUCKETS: [i16; 9] = [-120, -110, -100, -90, -80, -70, -60, -50, -40];
+
+/// Process-wide metrics
+static METRICS: Metrics = Metrics::new();
+
+/// Process-wide metrics, updated by helper loops
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Radio counters and gauges
+#[derive(Debug)]
+pub struct Metrics {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    rx_errors: AtomicU64,
+    decode_failures: AtomicU64,
+    last_rssi: AtomicI64,
+    rssi_buckets: [AtomicU64; RSSI_BUCKETS.len() + 1],
+    rssi_sum: AtomicI64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Create a new (zeroed) metrics set
+    pub const fn new() -> Self {
+        Self {
+            rx_packets: AtomicU64::new(0),
+            rx_bytes: AtomicU64::new(0),
+            tx_packets: AtomicU64::new(0),
+            tx_bytes: AtomicU64::new(0),
+            rx_errors: AtomicU64::new(0),
+            decode_failures: AtomicU64::new(0),
+            last_rssi: AtomicI64::new(i64::MIN),
+            rssi_buckets: [const { AtomicU64::new(0) }; RSSI_BUCKETS.len() + 1],
+            rssi_sum: AtomicI64::new(0),
+        }
+    }
+
+    /// Record a received packet
+    pub fn received(&self, len: usize, rssi: i16) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.last_rssi.store(rssi as i64, Ordering::Relaxed);
+        self.rssi_sum.fetch_add(rssi as i64, Ordering::Relaxed);
+
+        let bucket = RSSI_BUCKETS
+            .iter()
+            .position(|b| rssi <= *b)
+            .unwrap_or(RSSI_BUCKETS.len());
+        self.rssi_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a transmitted packet
+    pub fn transmitted(&self, len: usize) {
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+        self.tx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Record a receive (CRC) error reported by the radio
+    pub fn receive_error(&self) {
+        self.rx_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a received frame failing decryption, decompression or decoding
+    pub fn decode_failure(&self) {
+        self.decode_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// RSSI of the most recently received packet
+    pub fn last_rssi(&self) -> Option<i16> {
+        match self.last_rssi.load(Ordering::Relaxed) {
+            i64::MIN => None,
+            r => Some(r as i16),
+        }
+    }
+
+    /// Render metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+        let mut counter = |name: &str, help: &str, v: &AtomicU64| {
+            let _ = writeln!(s, "# HELP {} {}", name, help);
+            let _ = writeln!(s, "# TYPE {} counter", name);
+            let _ = writeln!(s, "{} {}", name, v.load(Ordering::Relaxed));
+        };
+
+        counter(
+            "radio_rx_packets_total",
+            "Packets received",
+            &self.rx_packets,
+        );
+        counter("radio_rx_bytes_total", "Bytes received", &self.rx_bytes);
+        counter(
+            "radio_tx_packets_total",
+            "Packets transmitted",
+            &self.tx_packets,
+        );
+        counter("radio_tx_bytes_total", "Bytes transmitted", &self.tx_bytes);
+        counter(
+            "radio_rx_errors_total",
+            "Receive (CRC) errors reported by the radio",
+            &self.rx_errors,
+        );
+        counter(
+            "radio_decode_failures_total",
+            "Received frames failing decryption, decompression or decoding",
+            &self.decode_failures,
+        );
+
+        if let Some(r) = self.last_rssi() {
+            let _ = writeln!(
+                s,
+                "# HELP radio_last_rssi_dbm RSSI of the last received packet"
+            );
+            let _ = writeln!(s, "# TYPE radio_last_rssi_dbm gauge");
+            let _ = writeln!(s, "radio_last_rssi_dbm {}", r);
+        }
+
+        let _ = writeln!(s, "# HELP radio_rssi_dbm RSSI of received packets");
+        let _ = writeln!(s, "# TYPE radio_rssi_dbm histogram");
+        let mut count = 0;
+        for (i, b) in self.rssi_buckets.iter().enumerate() {
+            count += b.load(Ordering::Relaxed);
+            let _ = match RSSI_BUCKETS.get(i) {
+                Some(le) => writeln!(s, "radio_rssi_dbm_bucket{{le=\"{}\"}} {}", le, count),
+                None => writeln!(s, "radio_rssi_dbm_bucket{{le=\"+Inf\"}} {}", count),
+            };
+        }
+        let _ = writeln!(
+            s,
+            "radio_rssi_dbm_sum {}",
+            self.rssi_sum.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(s, "radio_rssi_dbm_count {}", count);
+
+        s
+    }
+}
+
+/// HTTP exporter serving the process-wide metrics at `/metrics`
+pub struct MetricsExporter {
+    addr: SocketAddr,
+}
+
+impl MetricsExporter {
+    /// Serve metrics on the provided address, handling requests in the background
+    pub fn listen(addr: SocketAddr) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+
+        info!("Serving metrics on http://{}/metrics", addr);
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = respond(stream, metrics()) {
+                    debug!("Metrics request error: {:?}", e);
+                }
+            }
+        });
+
+        Ok(Self { addr })
+    }
+
+    /// Bound listener address
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// Respond to a single HTTP request
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> Result<(), std::io::Error> {
+    let mut buff = [0u8; 1024];
+    let n = stream.read(&mut buff)?;
+    let request = String::from_utf8_lossy(&buff[..n]);
+    let path = request.split(' ').nth(1).unwrap_or("/");
+
+    let (status, body) = match path {
+        "/" | "/metrics" => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_render() {
+        let m = Metrics::new();
+        assert_eq!(m.last_rssi(), None);
+
+        m.received(10, -95);
+        m.received(20, -30);
+        m.transmitted(4);
+        m.receive_error();
+        m.decode_failure();
+        assert_eq!(m.last_rssi(), Some(-30));
+
+        let s = m.render();
+        for line in [
+            "radio_rx_packets_total 2",
+            "radio_rx_bytes_total 30",
+            "radio_tx_bytes_total 4",
+            "radio_rx_errors_total 1",
+            "radio_decode_failures_total 1",
+            "radio_last_rssi_dbm -30",
+            "radio_rssi_dbm_bucket{le=\"-100\"} 0",
+            "radio_rssi_dbm_bucket{le=\"-90\"} 1",
+            "radio_rssi_dbm_bucket{le=\"-40\"} 1",
+            "radio_rssi_dbm_bucket{le=\"+Inf\"} 2",
+            "radio_rssi_dbm_sum -125",
+            "radio_rssi_dbm_count 2",
+        ] {
+            assert!(
+                s.lines().any(|l| l == line),
+                "missing '{}' in:\n{}",
+                line,
+                s
+            );
+        }
+
+        // Process-wide metrics are served over HTTP
+        let exporter = MetricsExporter::listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut stream = TcpStream::connect(exporter.local_addr()).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE radio_rssi_dbm histogram"));
+    }
+}
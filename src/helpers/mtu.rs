@@ -0,0 +1,283 @@
+//! Link MTU discovery
+//!
+//! Binary-searches the largest payload that transits the link reliably against a peer
+//! running `echo`, bounded by the driver limit (the provided buffer) and the configured
+//! maximum. The discovered MTU (less any FEC overhead) can be saved for use when
+//! fragmenting larger messages.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+use byteorder::{ByteOrder, NetworkEndian};
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+
+use super::json;
+
+use crate::{
+    Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingReceive, BlockingTransmit},
+};
+
+/// Configuration for MTU discovery operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct MtuOptions {
+    /// Minimum payload size to probe (must contain the 4-byte probe index)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "8"))]
+    pub min: usize,
+
+    /// Maximum payload size to probe, further limited by the driver buffer
+    #[cfg_attr(feature = "clap", clap(long, default_value = "255"))]
+    pub max: usize,
+
+    /// Probes sent at each size
+    #[cfg_attr(feature = "clap", clap(long, default_value = "5"))]
+    pub probes: u32,
+
+    /// Echoed probes required for a size to be considered reliable
+    #[cfg_attr(feature = "clap", clap(long, default_value = "4"))]
+    pub required: u32,
+
+    /// Bytes reserved for forward error correction, deducted from the discovered MTU
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub fec_overhead: usize,
+
+    /// File to save the discovered MTU to (as JSON)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub save: Option<String>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Discovered link MTU
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MtuInfo {
+    /// Largest payload reliably transiting the link
+    pub frame_size: usize,
+    /// Bytes reserved for forward error correction
+    pub fec_overhead: usize,
+    /// Usable MTU (frame size less FEC overhead)
+    pub mtu: usize,
+    /// Total probes sent during discovery
+    pub probes: u32,
+}
+
+impl MtuInfo {
+    /// Load a saved MTU
+    pub fn load(path: &str) -> Result<Self, std::io::Error> {
+        let d = std::fs::read(path)?;
+        json::from_slice(&d)
+    }
+
+    /// Save the MTU for later use
+    pub fn save(&self, path: &str) -> Result<(), std::io::Error> {
+        let d = json::to_vec_pretty(self)?;
+        std::fs::write(path, d)
+    }
+}
+
+impl core::fmt::Display for MtuInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "mtu: {} (frame size {}, fec overhead {}, {} probes)",
+            self.mtu, self.frame_size, self.fec_overhead, self.probes
+        )
+    }
+}
+
+/// Probe a single payload size, returning whether sufficient probes were echoed
+fn probe_size<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    size: usize,
+    index: &mut u32,
+    options: &MtuOptions,
+) -> Result<bool, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut probe = vec![0u8; size];
+    let mut echoed = 0;
+
+    for _ in 0..options.probes {
+        NetworkEndian::write_u32(&mut probe, *index);
+        for (j, b) in probe[4..].iter_mut().enumerate() {
+            *b = (j as u32).wrapping_add(*index) as u8;
+        }
+        *index = index.wrapping_add(1);
+
+        radio.do_transmit(&probe, options.blocking_options.clone())?;
+
+        // Echo responses may append info, so only the probe prefix is compared
+        match radio.do_receive(buff, options.blocking_options.clone()) {
+            Ok((n, _)) if n >= size && buff[..size] == probe[..] => echoed += 1,
+            Ok((n, _)) => debug!("Invalid probe response ({} bytes)", n),
+            Err(BlockingError::Timeout) => debug!("Timeout awaiting probe response"),
+            Err(e) => return Err(e),
+        }
+
+        if echoed >= options.required {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Discover the link MTU against a peer running `echo`, returning `None` if no probes
+/// transit the link at the minimum size
+pub fn do_discover_mtu<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: MtuOptions,
+) -> Result<Option<MtuInfo>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut index = 0;
+
+    let mut lo = options.min.max(4);
+    let mut hi = options.max.min(buff.len());
+    if lo > hi {
+        warn!("Minimum probe size {} exceeds limit {}", lo, hi);
+        return Ok(None);
+    }
+
+    if !probe_size(radio, buff, lo, &mut index, &options)? {
+        warn!("No probes echoed at minimum size {}", lo);
+        return Ok(None);
+    }
+
+    while lo < hi {
+        let size = lo + (hi - lo).div_ceil(2);
+        let ok = probe_size(radio, buff, size, &mut index, &options)?;
+
+        debug!("Probe size {}: {}", size, if ok { "ok" } else { "failed" });
+
+        match ok {
+            true => lo = size,
+            false => hi = size - 1,
+        }
+    }
+
+    let info = MtuInfo {
+        frame_size: lo,
+        fec_overhead: options.fec_overhead,
+        mtu: lo.saturating_sub(options.fec_overhead),
+        probes: index,
+    };
+
+    info!("MTU discovery complete: {}", info);
+
+    if let Some(path) = &options.save {
+        info.save(path).expect("Error saving MTU");
+    }
+
+    Ok(Some(info))
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Loopback radio, echoing frames up to a size limit
+    struct LimitRadio {
+        limit: usize,
+        last: Option<Vec<u8>>,
+    }
+
+    impl Transmit for LimitRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.last = Some(data.to_vec()).filter(|d| d.len() <= self.limit);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for LimitRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().unwrap();
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for LimitRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn mtu_discovery() {
+        let path = std::env::temp_dir().join(format!("radio-mtu-{}.json", std::process::id()));
+        let options = MtuOptions {
+            min: 8,
+            max: 255,
+            probes: 2,
+            required: 1,
+            fec_overhead: 16,
+            save: Some(path.to_string_lossy().to_string()),
+            blocking_options: BlockingOptions::default(),
+        };
+
+        let mut radio = LimitRadio {
+            limit: 100,
+            last: None,
+        };
+        let mut buff = [0u8; 1024];
+        let info = do_discover_mtu(&mut radio, &mut buff, options.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!((info.frame_size, info.mtu), (100, 84));
+        assert_eq!(MtuInfo::load(&path.to_string_lossy()).unwrap(), info);
+        std::fs::remove_file(&path).unwrap();
+
+        // Limited by the driver buffer
+        let mut radio = LimitRadio {
+            limit: 1_000,
+            last: None,
+        };
+        let mut buff = [0u8; 64];
+        let o = MtuOptions {
+            save: None,
+            ..options.clone()
+        };
+        let info = do_discover_mtu(&mut radio, &mut buff, o.clone()).unwrap();
+        assert_eq!(info.map(|i| i.frame_size), Some(64));
+
+        // No link
+        let mut radio = LimitRadio {
+            limit: 4,
+            last: None,
+        };
+        assert_eq!(do_discover_mtu(&mut radio, &mut buff, o), Ok(None));
+    }
+}
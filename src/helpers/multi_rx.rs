@@ -0,0 +1,317 @@
+//! Concurrent receive across multiple radios
+//!
+//! [`do_receive_multi`] polls a set of radios in turn (typically gateway transceivers
+//! listening on different channels), passing frames from all radios through a single
+//! decode pipeline. Frames are tagged with the index (and where configured, `--channels`)
+//! of the receiving radio in logs and outputs, including PCAP-NG packet comments, and
+//! statistics are aggregated per radio.
+
+use std::time::{Instant, SystemTime};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{DecodeWorker, ReceiveQuality, ReceivedFrame, Samples, WorkerOptions, squelched};
+use crate::{Receive, ReceiveInfo, blocking::BlockingOptions};
+
+/// Configuration for MultiRx operation
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct MultiRxOptions {
+    /// Channel of each radio (in order), recorded with received frames
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub channels: Vec<u32>,
+
+    /// Run continuously
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub continuous: bool,
+
+    /// Run for this duration when running continuously (runs until interrupted if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub duration: Option<HumanDuration>,
+
+    /// Print per-radio statistics at this interval
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub stats_interval: Option<HumanDuration>,
+
+    /// Suppress frames received below this RSSI (dBm), along with zero-length receptions
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub squelch: Option<i16>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub worker_options: WorkerOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Receive statistics for a single radio
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RadioRxStats {
+    /// Index of the radio
+    pub radio: usize,
+    /// Configured channel, where specified
+    pub channel: Option<u32>,
+    /// Frames received
+    pub received: u64,
+    /// Bytes received
+    pub bytes: u64,
+    /// Frames suppressed by squelch
+    pub squelched: u64,
+    /// Receive errors
+    pub errors: u64,
+    /// RSSI of received frames
+    pub rssi: Samples,
+}
+
+impl RadioRxStats {
+    /// Tag identifying the radio (and channel) in logs and frame metadata
+    pub fn tag(&self) -> String {
+        match self.channel {
+            Some(c) => format!("radio={} channel={}", self.radio, c),
+            None => format!("radio={}", self.radio),
+        }
+    }
+}
+
+impl core::fmt::Display for RadioRxStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}: {} frames ({} bytes, {} squelched, {} errors)",
+            self.tag(),
+            self.received,
+            self.bytes,
+            self.squelched,
+            self.errors
+        )?;
+        if let Some(m) = self.rssi.mean() {
+            write!(f, " mean rssi {:.1}", m)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error receiving from one of a set of radios
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiRxError<E> {
+    /// Index of the radio that failed
+    pub radio: usize,
+    pub error: E,
+}
+
+/// Receive concurrently from a set of radios, returning statistics for each radio
+///
+/// Without `continuous` this returns once any radio has received a frame. Receive errors
+/// are counted and receive restarted when running continuously, and returned (with the
+/// failing radio index) otherwise.
+pub fn do_receive_multi<T, I, E>(
+    radios: &mut [T],
+    buff: &mut [u8],
+    options: MultiRxOptions,
+) -> Result<Vec<RadioRxStats>, MultiRxError<E>>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut stats: Vec<_> = (0..radios.len())
+        .map(|i| RadioRxStats {
+            radio: i,
+            channel: options.channels.get(i).copied(),
+            ..Default::default()
+        })
+        .collect();
+
+    // Frames from all radios share a single decode pipeline
+    let mut worker =
+        DecodeWorker::new(options.worker_options.clone()).expect("Error creating decode pipeline");
+
+    for (i, r) in radios.iter_mut().enumerate() {
+        r.start_receive()
+            .map_err(|error| MultiRxError { radio: i, error })?;
+    }
+
+    info!("Receiving on {} radios", radios.len());
+
+    let start = Instant::now();
+    let mut last_stats = start;
+    let mut done = false;
+
+    while !done {
+        if let Some(d) = options.duration
+            && start.elapsed() >= *d
+        {
+            break;
+        }
+
+        for (i, r) in radios.iter_mut().enumerate() {
+            let s = &mut stats[i];
+
+            let received = r.check_receive(true).and_then(|ok| match ok {
+                true => r.get_received(buff).map(Some),
+                false => Ok(None),
+            });
+
+            let (n, info) = match (received, options.continuous) {
+                (Ok(Some(v)), _) => v,
+                (Ok(None), _) => continue,
+                (Err(e), true) => {
+                    debug!("Receive error ({}): {:?}", s.tag(), e);
+                    s.errors += 1;
+                    r.start_receive()
+                        .map_err(|error| MultiRxError { radio: i, error })?;
+                    continue;
+                }
+                (Err(error), false) => return Err(MultiRxError { radio: i, error }),
+            };
+
+            r.start_receive()
+                .map_err(|error| MultiRxError { radio: i, error })?;
+
+            if squelched(options.squelch, info.rssi(), n) {
+                debug!("Squelched {} byte frame ({})", n, s.tag());
+                s.squelched += 1;
+                continue;
+            }
+
+            s.received += 1;
+            s.bytes += n as u64;
+            s.rssi.update(info.rssi() as f32);
+            debug!("Received {} bytes ({}, rssi: {})", n, s.tag(), info.rssi());
+
+            match worker.alloc(&buff[..n]) {
+                Some(data) => {
+                    let frame = ReceivedFrame {
+                        timestamp: SystemTime::now(),
+                        rssi: info.rssi(),
+                        data,
+                        quality: ReceiveQuality::from_info(&info),
+                        info: format!("{} {:?}", s.tag(), info),
+                    };
+                    if !worker.submit(frame) {
+                        debug!("Decode queue full, dropped frame");
+                    }
+                }
+                None => debug!("Receive buffer pool exhausted, dropped frame"),
+            }
+
+            if !options.continuous {
+                done = true;
+                break;
+            }
+        }
+
+        if let Some(i) = options.stats_interval
+            && last_stats.elapsed() >= *i
+        {
+            for s in &stats {
+                info!("Receive {}", s);
+            }
+            last_stats = Instant::now();
+        }
+
+        if let (false, Some(r)) = (done, radios.first_mut()) {
+            r.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+        }
+    }
+
+    let worker_stats = worker.finish();
+    debug!("Decode pipeline: {:?}", worker_stats);
+
+    for s in &stats {
+        info!("Receive complete, {}", s);
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio receiving queued frames, failing where a frame is empty
+    #[derive(Default)]
+    struct QueueRadio {
+        rx: VecDeque<(Vec<u8>, i16)>,
+    }
+
+    impl Receive for QueueRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            match self.rx.front() {
+                Some((d, _)) if d.is_empty() => {
+                    self.rx.pop_front();
+                    Err(())
+                }
+                f => Ok(f.is_some()),
+            }
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (d, rssi) = self.rx.pop_front().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(rssi, 0)))
+        }
+    }
+
+    impl DelayNs for QueueRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn multi_radio_receive() {
+        let mut radios = vec![QueueRadio::default(), QueueRadio::default()];
+        radios[0]
+            .rx
+            .extend([(vec![1, 2, 3], -60), (vec![], 0), (vec![4], -110)]);
+        radios[1].rx.extend([(vec![5, 6], -80), (vec![7], -70)]);
+
+        let options = MultiRxOptions {
+            channels: vec![11, 26],
+            continuous: true,
+            duration: Some(std::time::Duration::from_millis(10).into()),
+            squelch: Some(-100),
+            ..Default::default()
+        };
+        let mut buff = [0u8; 64];
+        let stats = do_receive_multi(&mut radios, &mut buff, options).unwrap();
+
+        assert_eq!(stats[0].tag(), "radio=0 channel=11");
+        assert_eq!(
+            (stats[0].received, stats[0].squelched, stats[0].errors),
+            (1, 1, 1)
+        );
+        assert_eq!((stats[1].received, stats[1].bytes), (2, 3));
+        assert_eq!(stats[1].rssi.mean(), Some(-75.0));
+
+        // Single receptions report the failing radio
+        radios[1].rx.push_back((vec![], 0));
+        let err = do_receive_multi(&mut radios, &mut buff, MultiRxOptions::default());
+        assert_eq!(
+            err,
+            Err(MultiRxError {
+                radio: 1,
+                error: ()
+            })
+        );
+    }
+}
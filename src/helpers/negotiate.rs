@@ -0,0 +1,380 @@
+//! Capability negotiation handshake between peers
+//!
+//! With `--negotiate`, `tx`, `rx` and `echo` exchange [`CapabilityDescriptor`]s with
+//! their peer before starting, selecting the common feature set (compression,
+//! encryption, aggregation and ARQ) and maximum payload length and applying it to the
+//! operation options. Features enabled on either node are used where both support them,
+//! so only one node needs the feature flags, with requested features the peer cannot
+//! support disabled (and logged) rather than failing mid-operation.
+//!
+//! Each node broadcasts offers at `--negotiate-interval`, answering any received offer
+//! and completing on the first offer or answer from the peer.
+
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+
+use super::{EchoOptions, ReceiveOptions, TransmitOptions};
+use crate::{
+    Receive, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+    clock::{Clock, StdClock},
+    negotiate::{CapabilityDescriptor, CapsKind, Features, Negotiated},
+};
+
+/// Configuration for capability negotiation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct NegotiateOptions {
+    /// Negotiate a common feature set with the peer before starting
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub negotiate: bool,
+
+    /// Interval between capability offers
+    #[cfg_attr(feature = "clap", clap(long, default_value = "200ms"))]
+    pub negotiate_interval: HumanDuration,
+
+    /// Timeout awaiting the peer's capabilities
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10s"))]
+    pub negotiate_timeout: HumanDuration,
+}
+
+impl Default for NegotiateOptions {
+    fn default() -> Self {
+        Self {
+            negotiate: false,
+            negotiate_interval: Duration::from_millis(200).into(),
+            negotiate_timeout: Duration::from_secs(10).into(),
+        }
+    }
+}
+
+/// Operation options supporting capability negotiation
+pub trait Negotiable {
+    /// Negotiation and blocking configuration
+    fn negotiate_options(&self) -> (&NegotiateOptions, &BlockingOptions);
+
+    /// Capabilities supported and requested by the configured operation
+    fn capabilities(&self) -> CapabilityDescriptor;
+
+    /// Apply a negotiated configuration to the options
+    fn apply_capabilities(&mut self, negotiated: &Negotiated);
+}
+
+/// Exchange capabilities with the peer, returning the selected configuration
+pub fn do_negotiate<T, E>(
+    radio: &mut T,
+    local: &CapabilityDescriptor,
+    options: &NegotiateOptions,
+    blocking: &BlockingOptions,
+) -> Result<Negotiated, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    do_negotiate_clocked(radio, local, options, blocking, &StdClock)
+}
+
+/// Exchange capabilities with the peer, timing offers with the provided clock
+pub fn do_negotiate_clocked<T, E, C>(
+    radio: &mut T,
+    local: &CapabilityDescriptor,
+    options: &NegotiateOptions,
+    blocking: &BlockingOptions,
+    clock: &C,
+) -> Result<Negotiated, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    C: Clock,
+{
+    let mut buff = [0u8; 255];
+    let timeout_us = options.negotiate_timeout.as_micros() as u64;
+    let interval_us = options.negotiate_interval.as_micros() as u64;
+    let start = clock.now_us();
+    let mut last_offer: Option<u64> = None;
+
+    info!(
+        "Negotiating capabilities (supported: {}, requested: {})",
+        local.supported, local.requested
+    );
+
+    loop {
+        if clock.elapsed_us(start) > timeout_us {
+            return Err(BlockingError::Timeout);
+        }
+
+        if last_offer.is_none_or(|t| clock.elapsed_us(t) >= interval_us) {
+            radio.do_transmit(&local.encode(CapsKind::Offer), blocking.clone())?;
+            radio.start_receive()?;
+            last_offer = Some(clock.now_us());
+        }
+
+        if radio.check_receive(true)? {
+            let (n, _) = radio.get_received(&mut buff)?;
+
+            match CapabilityDescriptor::decode(&buff[..n]) {
+                Some((kind, peer)) => {
+                    debug!("Received {:?}: {:?}", kind, peer);
+
+                    if kind == CapsKind::Offer {
+                        radio.do_transmit(&local.encode(CapsKind::Answer), blocking.clone())?;
+                    }
+                    return Ok(local.select(&peer));
+                }
+                None => radio.start_receive()?,
+            }
+        }
+
+        radio.delay_us(blocking.poll_interval.as_micros() as u32);
+    }
+}
+
+/// Negotiate capabilities where enabled in the provided options, applying the selected
+/// configuration
+pub fn negotiate<T, E, O>(radio: &mut T, options: &mut O) -> Result<(), BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    O: Negotiable,
+{
+    negotiate_clocked(radio, options, &StdClock)
+}
+
+/// Negotiate capabilities where enabled in the provided options, timing offers with the
+/// provided clock
+pub fn negotiate_clocked<T, E, O, C>(
+    radio: &mut T,
+    options: &mut O,
+    clock: &C,
+) -> Result<(), BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    O: Negotiable,
+    C: Clock,
+{
+    let (n, b) = options.negotiate_options();
+    if !n.negotiate {
+        return Ok(());
+    }
+
+    let negotiated = do_negotiate_clocked(radio, &options.capabilities(), n, b, clock)?;
+
+    info!(
+        "Negotiated features: {} (max payload {} bytes)",
+        negotiated.features, negotiated.max_payload
+    );
+    if !negotiated.dropped.is_empty() {
+        warn!(
+            "Requested features unsupported by peer: {}",
+            negotiated.dropped
+        );
+    }
+
+    options.apply_capabilities(&negotiated);
+
+    Ok(())
+}
+
+impl Negotiable for TransmitOptions {
+    fn negotiate_options(&self) -> (&NegotiateOptions, &BlockingOptions) {
+        (&self.negotiate_options, &self.blocking_options)
+    }
+
+    fn capabilities(&self) -> CapabilityDescriptor {
+        let mut supported = Features::AGGREGATE | Features::ARQ;
+        supported.set(Features::COMPRESS, self.compression_options.available());
+        supported.set(Features::CRYPTO, self.crypto_options.available());
+
+        let mut requested = Features::NONE;
+        requested.set(Features::COMPRESS, self.compression_options.enabled());
+        requested.set(Features::CRYPTO, self.crypto_options.enabled());
+        requested.set(Features::AGGREGATE, self.aggregate.is_some());
+        requested.set(Features::ARQ, self.arq_options.reliable);
+
+        let max = self.framing_options.frame_mtu.min(u16::MAX as usize) as u16;
+        CapabilityDescriptor::new(max, supported, requested)
+    }
+
+    fn apply_capabilities(&mut self, n: &Negotiated) {
+        let max = n.max_payload as usize;
+
+        self.compression_options
+            .set_enabled(n.features.contains(Features::COMPRESS));
+        self.crypto_options
+            .set_enabled(n.features.contains(Features::CRYPTO));
+        self.aggregate = match n.features.contains(Features::AGGREGATE) {
+            true => Some(self.aggregate.unwrap_or(max).min(max)),
+            false => None,
+        };
+        self.arq_options.reliable = n.features.contains(Features::ARQ);
+        self.framing_options.frame_mtu = self.framing_options.frame_mtu.min(max);
+    }
+}
+
+impl Negotiable for ReceiveOptions {
+    fn negotiate_options(&self) -> (&NegotiateOptions, &BlockingOptions) {
+        (&self.negotiate_options, &self.blocking_options)
+    }
+
+    fn capabilities(&self) -> CapabilityDescriptor {
+        let mut supported = Features::AGGREGATE;
+        supported.set(Features::COMPRESS, self.compression_options.available());
+        supported.set(Features::CRYPTO, self.crypto_options.available());
+
+        let mut requested = Features::NONE;
+        requested.set(Features::COMPRESS, self.compression_options.enabled());
+        requested.set(Features::CRYPTO, self.crypto_options.enabled());
+        requested.set(Features::AGGREGATE, self.aggregated);
+
+        let max = self.framing_options.frame_mtu.min(u16::MAX as usize) as u16;
+        CapabilityDescriptor::new(max, supported, requested)
+    }
+
+    fn apply_capabilities(&mut self, n: &Negotiated) {
+        self.compression_options
+            .set_enabled(n.features.contains(Features::COMPRESS));
+        self.crypto_options
+            .set_enabled(n.features.contains(Features::CRYPTO));
+        self.aggregated = n.features.contains(Features::AGGREGATE);
+    }
+}
+
+impl Negotiable for EchoOptions {
+    fn negotiate_options(&self) -> (&NegotiateOptions, &BlockingOptions) {
+        (&self.negotiate_options, &self.blocking_options)
+    }
+
+    fn capabilities(&self) -> CapabilityDescriptor {
+        let mut supported = Features::ARQ;
+        supported.set(Features::CRYPTO, self.crypto_options.available());
+
+        let mut requested = Features::NONE;
+        requested.set(Features::CRYPTO, self.crypto_options.enabled());
+        requested.set(Features::ARQ, self.arq_options.reliable);
+
+        let max = self.max_size.unwrap_or(255).min(u16::MAX as usize) as u16;
+        CapabilityDescriptor::new(max, supported, requested)
+    }
+
+    fn apply_capabilities(&mut self, n: &Negotiated) {
+        self.crypto_options
+            .set_enabled(n.features.contains(Features::CRYPTO));
+        self.arq_options.reliable = n.features.contains(Features::ARQ);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{Receiver, Sender, channel};
+
+    /// Radio connected to a peer over channels
+    struct Pipe {
+        tx: Sender<Vec<u8>>,
+        rx: Receiver<Vec<u8>>,
+        pending: Option<Vec<u8>>,
+    }
+
+    fn pipes() -> (Pipe, Pipe) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        let pipe = |tx, rx| Pipe {
+            tx,
+            rx,
+            pending: None,
+        };
+        (pipe(a_tx, a_rx), pipe(b_tx, b_rx))
+    }
+
+    impl Transmit for Pipe {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let _ = self.tx.send(data.to_vec());
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for Pipe {
+        type Error = ();
+        type Info = crate::BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            if self.pending.is_none() {
+                self.pending = self.rx.try_recv().ok();
+            }
+            Ok(self.pending.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.pending.take().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), Default::default()))
+        }
+    }
+
+    impl DelayNs for Pipe {
+        fn delay_ns(&mut self, ns: u32) {
+            std::thread::sleep(Duration::from_nanos(ns as u64));
+        }
+    }
+
+    #[test]
+    fn negotiate_handshake() {
+        let (mut a, mut b) = pipes();
+        let options = NegotiateOptions {
+            negotiate: true,
+            negotiate_interval: Duration::from_millis(10).into(),
+            ..Default::default()
+        };
+
+        // Transmitter requests compression and ARQ, the receiver only negotiates
+        let tx = CapabilityDescriptor::new(
+            255,
+            Features::COMPRESS | Features::AGGREGATE | Features::ARQ,
+            Features::COMPRESS | Features::ARQ,
+        );
+        let rx = CapabilityDescriptor::new(64, Features::COMPRESS, Features::NONE);
+
+        let o = options.clone();
+        let t = std::thread::spawn(move || {
+            do_negotiate(&mut b, &rx, &o, &BlockingOptions::default()).unwrap()
+        });
+        let n = do_negotiate(&mut a, &tx, &options, &BlockingOptions::default()).unwrap();
+
+        // Both ends select compression, with ARQ unsupported by the receiver
+        assert_eq!(n, t.join().unwrap());
+        assert_eq!((n.features, n.dropped), (Features::COMPRESS, Features::ARQ));
+        assert_eq!(n.max_payload, 64);
+
+        // Negotiation times out without a peer
+        let (mut a, _b) = pipes();
+        let options = NegotiateOptions {
+            negotiate_timeout: Duration::from_millis(20).into(),
+            ..options
+        };
+        assert_eq!(
+            do_negotiate(&mut a, &tx, &options, &BlockingOptions::default()),
+            Err(BlockingError::Timeout)
+        );
+    }
+}
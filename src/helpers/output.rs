@@ -0,0 +1,230 @@
+//! Machine-readable record output for receive and RSSI operations
+//!
+//! With `--format json` or `--format csv`, `rx` writes one [`PacketRecord`] per received
+//! frame and `rssi` one [`RssiRecord`] per sample, to stdout or the `--output` file, for
+//! automated range testing. JSON output is written as JSON lines, and CSV output starts
+//! with a header row. The default `text` format logs as usual, without records.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::SystemTime;
+
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use super::json;
+
+/// Record output format
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum OutputFormat {
+    /// Log output only
+    #[default]
+    Text,
+    /// JSON lines
+    Json,
+    /// Comma separated values, with a header row
+    Csv,
+}
+
+/// Options for machine-readable record output
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct OutputOptions {
+    /// Record output format, writing one record per packet or sample
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "text"))]
+    pub format: OutputFormat,
+
+    /// Write records to this file rather than stdout
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub output: Option<String>,
+}
+
+impl OutputOptions {
+    /// Open the configured record output, `None` for text output
+    pub fn writer(&self) -> Result<Option<RecordWriter>, std::io::Error> {
+        let w: Box<dyn Write + Send> = match (self.format, &self.output) {
+            (OutputFormat::Text, _) => return Ok(None),
+            (_, Some(path)) => Box::new(BufWriter::new(File::create(path)?)),
+            (_, None) => Box::new(std::io::stdout()),
+        };
+
+        Ok(Some(RecordWriter::new(self.format, w)))
+    }
+}
+
+/// Record written as JSON or CSV
+pub trait Record: Serialize {
+    /// CSV header row
+    const HEADER: &'static str;
+
+    /// CSV row (without line ending)
+    fn csv(&self) -> String;
+}
+
+/// Received packet record
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PacketRecord {
+    /// Receive time in microseconds since the unix epoch
+    pub timestamp_us: u64,
+    pub len: usize,
+    /// Payload as a hex string
+    pub data: String,
+    pub rssi: i16,
+    /// Link quality, where reported by the radio
+    pub lqi: Option<u16>,
+}
+
+impl PacketRecord {
+    /// Create a record for a packet received now
+    pub fn new(data: &[u8], rssi: i16, lqi: Option<u16>) -> Self {
+        Self {
+            timestamp_us: now_us(),
+            len: data.len(),
+            data: data.iter().map(|b| format!("{:02x}", b)).collect(),
+            rssi,
+            lqi,
+        }
+    }
+}
+
+impl Record for PacketRecord {
+    const HEADER: &'static str = "timestamp_us,len,data,rssi,lqi";
+
+    fn csv(&self) -> String {
+        let lqi = self.lqi.map(|l| l.to_string()).unwrap_or_default();
+        format!(
+            "{},{},{},{},{}",
+            self.timestamp_us, self.len, self.data, self.rssi, lqi
+        )
+    }
+}
+
+/// RSSI sample record
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RssiRecord {
+    /// Sample time in microseconds since the unix epoch
+    pub timestamp_us: u64,
+    pub rssi: i16,
+}
+
+impl RssiRecord {
+    /// Create a record for a sample taken now
+    pub fn new(rssi: i16) -> Self {
+        Self {
+            timestamp_us: now_us(),
+            rssi,
+        }
+    }
+}
+
+impl Record for RssiRecord {
+    const HEADER: &'static str = "timestamp_us,rssi";
+
+    fn csv(&self) -> String {
+        format!("{},{}", self.timestamp_us, self.rssi)
+    }
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Writer for JSON lines or CSV records
+pub struct RecordWriter {
+    format: OutputFormat,
+    w: Box<dyn Write + Send>,
+    header: bool,
+}
+
+impl RecordWriter {
+    /// Create a record writer in the provided format
+    pub fn new(format: OutputFormat, w: Box<dyn Write + Send>) -> Self {
+        Self {
+            format,
+            w,
+            header: false,
+        }
+    }
+
+    /// Write a record, preceded by the header row for the first CSV record
+    pub fn write<R: Record>(&mut self, r: &R) -> Result<(), std::io::Error> {
+        match self.format {
+            OutputFormat::Json => {
+                json::to_writer(&mut self.w, r)?;
+                writeln!(self.w)
+            }
+            OutputFormat::Csv => {
+                if !self.header {
+                    writeln!(self.w, "{}", R::HEADER)?;
+                    self.header = true;
+                }
+                writeln!(self.w, "{}", r.csv())
+            }
+            OutputFormat::Text => Ok(()),
+        }
+    }
+
+    /// Flush buffered records
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.w.flush()
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Shared buffer, inspected after writing
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_formats() {
+        let mut r = PacketRecord::new(&[0xde, 0xad], -70, Some(200));
+        r.timestamp_us = 10;
+
+        let buff = Shared::default();
+        let mut w = RecordWriter::new(OutputFormat::Csv, Box::new(buff.clone()));
+        w.write(&r).unwrap();
+        w.write(&PacketRecord {
+            lqi: None,
+            ..r.clone()
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buff.0.lock().unwrap().clone()).unwrap(),
+            "timestamp_us,len,data,rssi,lqi\n10,2,dead,-70,200\n10,2,dead,-70,\n"
+        );
+
+        let buff = Shared::default();
+        let mut w = RecordWriter::new(OutputFormat::Json, Box::new(buff.clone()));
+        w.write(&RssiRecord {
+            timestamp_us: 5,
+            rssi: -90,
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buff.0.lock().unwrap().clone()).unwrap(),
+            "{\"timestamp_us\":5,\"rssi\":-90}\n"
+        );
+
+        assert!(OutputOptions::default().writer().unwrap().is_none());
+    }
+}
@@ -0,0 +1,479 @@
+//! PCAP capture output to files, named pipes and TCP connections
+//!
+//! Captures are written as legacy PCAP or PCAP-NG, with PCAP-NG captures describing the
+//! radio interface and annotating each packet with receive metadata (RSSI, channel and the
+//! driver [`ReceiveInfo`](crate::ReceiveInfo), including LQI where reported) as packet
+//! comments. With `--anonymize-key` captured frames are anonymized before writing (see
+//! [`Anonymizer`](super::Anonymizer)).
+
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{info, warn};
+
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use humantime::Duration as HumanDuration;
+use pcap_file::{
+    DataLink, PcapResult,
+    pcap::{PcapHeader, PcapWriter},
+    pcapng::{
+        PcapNgWriter,
+        blocks::{
+            enhanced_packet::{EnhancedPacketBlock, EnhancedPacketOption},
+            interface_description::{InterfaceDescriptionBlock, InterfaceDescriptionOption},
+        },
+    },
+};
+
+use super::{
+    AnonymizeOptions, AnonymizingSink, DecodedFrame, LoRaTapOptions, LoRaTapSink, PacketSink,
+    PcapDatalink, RotatingPcapSink,
+};
+
+/// IEEE 802.15.4 (with FCS) link-layer header type, the default capture datalink
+pub const LINKTYPE_IEEE802_15_4: u32 = 195;
+
+/// Capture file format
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum PcapFormat {
+    /// Legacy PCAP, without per-packet metadata
+    Pcap,
+    /// PCAP-NG, with receive metadata as packet comments
+    Pcapng,
+}
+
+/// Options for PCAP capture output
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct PcapOptions {
+    /// Create and write capture output to a PCAP file
+    #[cfg_attr(feature = "clap", clap(long, group = "1"))]
+    pub pcap_file: Option<String>,
+
+    /// Create and write to a unix pipe for connection to wireshark
+    #[cfg_attr(feature = "clap", clap(long, group = "1"))]
+    pub pcap_pipe: Option<String>,
+
+    /// Listen for a TCP connection on this address (eg. `0.0.0.0:19000`) and stream captures
+    /// to it, for remote Wireshark sessions (`TCP@host:port`)
+    #[cfg_attr(feature = "clap", clap(long, group = "1"))]
+    pub pcap_tcp: Option<String>,
+
+    /// Rotate `--pcap-file` captures at this interval, writing numbered files
+    /// (`NAME-0000.EXT`, ...) along with an index (`NAME.idx`) for `capture ls` and
+    /// `capture grep`
+    #[cfg_attr(feature = "clap", clap(long, requires = "pcap_file"))]
+    pub pcap_rotate: Option<HumanDuration>,
+
+    /// Capture file format
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "pcap"))]
+    pub pcap_format: PcapFormat,
+
+    /// Link-layer header type for captured packets (`ieee802154`, `ieee802154-fcs`,
+    /// `lora-tap`, `ble-ll`, `raw` or a tcpdump LINKTYPE number)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "ieee802154-fcs"))]
+    pub pcap_datalink: PcapDatalink,
+
+    /// Channel recorded in PCAP-NG capture metadata (set automatically where selected with
+    /// `--auto-channel`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub pcap_channel: Option<u32>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub lora_tap_options: LoRaTapOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub anonymize_options: AnonymizeOptions,
+}
+
+impl Default for PcapOptions {
+    fn default() -> Self {
+        Self {
+            pcap_file: None,
+            pcap_pipe: None,
+            pcap_tcp: None,
+            pcap_rotate: None,
+            pcap_format: PcapFormat::Pcap,
+            pcap_datalink: PcapDatalink::Ieee802154Fcs,
+            pcap_channel: None,
+            lora_tap_options: LoRaTapOptions::default(),
+            anonymize_options: AnonymizeOptions::default(),
+        }
+    }
+}
+
+impl PcapOptions {
+    /// Open the configured capture output, writing capture headers
+    pub fn open(&self) -> Result<Option<Box<dyn PacketSink>>, std::io::Error> {
+        // Listen for remote capture connections
+        if let Some(addr) = &self.pcap_tcp {
+            let s = TcpPcapSink::bind(addr, self.clone())?;
+            return Ok(Some(Box::new(s)));
+        }
+
+        // Rotate and index capture files
+        if let (Some(file), Some(interval)) = (&self.pcap_file, &self.pcap_rotate) {
+            let s = RotatingPcapSink::new(file, **interval, self.clone())?;
+            return Ok(Some(Box::new(s)));
+        }
+
+        // Open file or pipe if specified
+        let pcap_file = match (&self.pcap_file, &self.pcap_pipe) {
+            // Open as file
+            (Some(file), None) => {
+                let f = File::create(file)?;
+                Some(f)
+            }
+            // Open as pipe
+            #[cfg(target_family = "unix")]
+            (None, Some(pipe)) => {
+                // Ensure file doesn't already exist
+                let _ = std::fs::remove_file(pipe);
+
+                // Create pipe
+                let n = CString::new(pipe.as_str()).unwrap();
+                let status = unsafe { libc::mkfifo(n.as_ptr(), 0o644) };
+
+                // Manual status code handling
+                // TODO: return io::Error
+                if status != 0 {
+                    panic!("Error creating fifo: {}", status);
+                }
+
+                // Open pipe
+                let f = OpenOptions::new()
+                    .write(true)
+                    .open(pipe)
+                    .expect("Error opening PCAP pipe");
+
+                Some(f)
+            }
+
+            (None, None) => None,
+
+            _ => unimplemented!(),
+        };
+
+        #[cfg(any(feature = "log", feature = "defmt"))]
+        info!("pcap pipe open, awaiting connection");
+
+        // Setup pcap writer and write header
+        // (This is a blocking operation on pipes)
+        pcap_file.map(|f| self.sink(f)).transpose()
+    }
+
+    /// Create a capture sink writing to the provided writer in the configured format,
+    /// writing capture headers
+    pub fn sink<W: Write + Send + 'static>(
+        &self,
+        w: W,
+    ) -> Result<Box<dyn PacketSink>, std::io::Error> {
+        let datalink = DataLink::from(self.pcap_datalink.linktype());
+
+        let s: Box<dyn PacketSink> = match self.pcap_format {
+            PcapFormat::Pcap => {
+                // Setup pcap header
+                let h = PcapHeader {
+                    datalink,
+                    ..Default::default()
+                };
+
+                // Write header
+                Box::new(PcapWriter::with_header(w, h).map_err(std::io::Error::other)?)
+            }
+            PcapFormat::Pcapng => Box::new(
+                PcapNgSink::new(w, datalink, self.pcap_channel).map_err(std::io::Error::other)?,
+            ),
+        };
+
+        // Encapsulate LoRa captures with radio metadata
+        let s: Box<dyn PacketSink> = match self.pcap_datalink {
+            PcapDatalink::LoRaTap => Box::new(LoRaTapSink::new(s, self.lora_tap_options.clone())),
+            _ => s,
+        };
+
+        // Anonymize frames ahead of encapsulation
+        match &self.anonymize_options.anonymize_key {
+            Some(k) => Ok(Box::new(AnonymizingSink::new(s, k))),
+            None => Ok(s),
+        }
+    }
+}
+
+/// TCP capture stream, accepting a single remote capture connection at a time
+///
+/// Capture headers are written on each new connection, with packets dropped while no
+/// client is connected. Where a connection fails (eg. a Wireshark session is closed) it
+/// is dropped and the next connection accepted, so remote sessions do not abort capture.
+pub struct TcpPcapSink {
+    listener: TcpListener,
+    options: PcapOptions,
+    client: Option<Box<dyn PacketSink>>,
+}
+
+impl TcpPcapSink {
+    /// Listen for capture connections on the provided address
+    pub fn bind<A: ToSocketAddrs>(addr: A, options: PcapOptions) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        info!("pcap listening on {}", listener.local_addr()?);
+
+        Ok(Self {
+            listener,
+            options,
+            client: None,
+        })
+    }
+
+    /// Address the sink is listening on
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, std::io::Error> {
+        self.listener.local_addr()
+    }
+
+    /// Accept a pending connection where no client is connected, returning whether a
+    /// client is connected
+    pub fn poll_accept(&mut self) -> bool {
+        if self.client.is_some() {
+            return true;
+        }
+
+        let (stream, peer) = match self.listener.accept() {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return false,
+            Err(e) => {
+                warn!("Error accepting pcap connection: {:?}", e);
+                return false;
+            }
+        };
+
+        let sink = stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_nodelay(true))
+            .and_then(|_| self.options.sink(stream));
+        match sink {
+            Ok(s) => {
+                info!("pcap client connected from {}", peer);
+                self.client = Some(s);
+            }
+            Err(e) => warn!("Error starting pcap stream to {}: {:?}", peer, e),
+        }
+
+        self.client.is_some()
+    }
+}
+
+impl PacketSink for TcpPcapSink {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        if !self.poll_accept() {
+            return Ok(());
+        }
+
+        // Drop failed connections, awaiting reconnection
+        if let Some(c) = self.client.as_mut()
+            && let Err(e) = c.write(frame).and_then(|_| c.flush())
+        {
+            warn!("pcap client disconnected: {:?}", e);
+            self.client = None;
+        }
+
+        Ok(())
+    }
+}
+
+/// PCAP-NG capture writer, describing the radio interface and annotating packets with
+/// receive metadata
+pub struct PcapNgSink<W: Write> {
+    w: PcapNgWriter<W>,
+    channel: Option<u32>,
+}
+
+impl<W: Write> PcapNgSink<W> {
+    /// Create a PCAP-NG capture, writing the section header and interface description
+    pub fn new(w: W, datalink: DataLink, channel: Option<u32>) -> PcapResult<Self> {
+        let mut w = PcapNgWriter::new(w)?;
+
+        // Packet timestamps are written with nanosecond resolution
+        let mut options = vec![
+            InterfaceDescriptionOption::IfName(Cow::Borrowed("radio")),
+            InterfaceDescriptionOption::IfTsResol(9),
+        ];
+        if let Some(c) = channel {
+            options.push(InterfaceDescriptionOption::IfDescription(Cow::Owned(
+                format!("channel {}", c),
+            )));
+        }
+
+        w.write_pcapng_block(InterfaceDescriptionBlock {
+            linktype: datalink,
+            snaplen: 0xffff,
+            options,
+        })?;
+
+        Ok(Self { w, channel })
+    }
+
+    /// Release the underlying writer
+    pub fn into_inner(self) -> W {
+        self.w.into_inner()
+    }
+}
+
+/// Format receive metadata for a PCAP-NG packet comment
+pub fn packet_comment(frame: &DecodedFrame, channel: Option<u32>) -> String {
+    let mut s = format!("rssi={}", frame.rssi);
+    if !frame.quality.is_empty() {
+        s.push_str(&format!(" {}", frame.quality));
+    }
+    if let Some(c) = channel {
+        s.push_str(&format!(" channel={}", c));
+    }
+    if let Some(d) = &frame.device {
+        s.push_str(&format!(" device={}", d));
+    }
+    s.push_str(&format!(" info={}", frame.info));
+    s
+}
+
+impl<W: Write + Send> PacketSink for PcapNgSink<W> {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        let comment = packet_comment(frame, self.channel);
+
+        self.w
+            .write_pcapng_block(EnhancedPacketBlock {
+                interface_id: 0,
+                timestamp: std::time::Duration::from_micros(frame.timestamp_us),
+                original_len: frame.data.len() as u32,
+                data: Cow::Borrowed(&frame.data),
+                options: vec![EnhancedPacketOption::Comment(Cow::Owned(comment))],
+            })
+            .map(|_| ())
+            .map_err(std::io::Error::other)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.w.get_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pcap_file::pcapng::Block;
+
+    use super::*;
+
+    #[test]
+    fn pcapng_packet_comments() {
+        let frame = DecodedFrame {
+            timestamp_us: 1,
+            rssi: -70,
+            data: vec![0x61],
+            text: None,
+            protocol: None,
+            summary: None,
+            device: Some("sensor-1".into()),
+            quality: Default::default(),
+            info: "BasicInfo { rssi: -70, lqi: 20 }".into(),
+        };
+
+        assert_eq!(
+            packet_comment(&frame, Some(11)),
+            "rssi=-70 channel=11 device=sensor-1 info=BasicInfo { rssi: -70, lqi: 20 }"
+        );
+        assert_eq!(
+            packet_comment(&frame, None),
+            "rssi=-70 device=sensor-1 info=BasicInfo { rssi: -70, lqi: 20 }"
+        );
+        assert_eq!(
+            PcapOptions::default().pcap_datalink.linktype(),
+            LINKTYPE_IEEE802_15_4
+        );
+
+        // Write and read back a capture
+        let mut w = PcapNgSink::new(vec![], DataLink::from(147), Some(11)).unwrap();
+        w.write(&frame).unwrap();
+        let capture = w.into_inner();
+
+        let mut r = pcap_file::pcapng::PcapNgReader::new(&capture[..]).unwrap();
+        match r.next_block().unwrap().unwrap() {
+            Block::InterfaceDescription(i) => {
+                assert_eq!(i.linktype, DataLink::USER0);
+                assert!(
+                    i.options
+                        .contains(&InterfaceDescriptionOption::IfTsResol(9))
+                );
+            }
+            b => panic!("unexpected block {:?}", b),
+        }
+        match r.next_block().unwrap().unwrap() {
+            Block::EnhancedPacket(p) => {
+                assert_eq!(p.timestamp, std::time::Duration::from_micros(1));
+                assert_eq!(&p.data[..], &[0x61]);
+                assert_eq!(
+                    p.options,
+                    vec![EnhancedPacketOption::Comment(
+                        packet_comment(&frame, Some(11)).into()
+                    )]
+                );
+            }
+            b => panic!("unexpected block {:?}", b),
+        }
+    }
+
+    #[test]
+    fn tcp_capture_reconnect() {
+        use std::net::TcpStream;
+
+        let frame = |data: u8| DecodedFrame {
+            timestamp_us: 1,
+            rssi: -70,
+            data: vec![data],
+            text: None,
+            protocol: None,
+            summary: None,
+            device: None,
+            quality: Default::default(),
+            info: String::new(),
+        };
+
+        let mut s = TcpPcapSink::bind("127.0.0.1:0", PcapOptions::default()).unwrap();
+        let addr = s.local_addr().unwrap();
+
+        // Packets are dropped while no client is connected
+        s.write(&frame(1)).unwrap();
+        assert!(!s.poll_accept());
+
+        // Clients receive the capture header followed by packets
+        let c = TcpStream::connect(addr).unwrap();
+        s.write(&frame(2)).unwrap();
+        let mut r = pcap_file::pcap::PcapReader::new(&c).unwrap();
+        assert_eq!(r.header().datalink, DataLink::IEEE802_15_4);
+        assert_eq!(&r.next_packet().unwrap().unwrap().data[..], &[2]);
+
+        // Disconnection is detected on write, with later clients accepted
+        drop(r);
+        drop(c);
+        for _ in 0..100 {
+            s.write(&frame(3)).unwrap();
+            if s.client.is_none() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(s.client.is_none());
+
+        let c = TcpStream::connect(addr).unwrap();
+        s.write(&frame(4)).unwrap();
+        let mut r = pcap_file::pcap::PcapReader::new(&c).unwrap();
+        assert_eq!(&r.next_packet().unwrap().unwrap().data[..], &[4]);
+    }
+}
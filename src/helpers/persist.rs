@@ -0,0 +1,461 @@
+//! On-disk persistence for queued and unacknowledged frames
+//!
+//! With `--queue-store`, frames awaiting transmission are persisted to a JSON file so a
+//! gateway process restart doesn't lose queued downlinks. [`QueueStore`] records
+//! [`TxQueue`] contents (via [`QueueStore::snapshot_tx`]) and frames sent through a
+//! [`ReliableLink`] until acknowledged, replaying both on startup. Frames older than
+//! `--queue-retention` are discarded when the store is opened and as frames are added,
+//! with at most `--queue-max` (most recently queued) frames retained.
+//!
+//! The store file is replaced atomically on each update, writing and syncing a temporary
+//! file before renaming it over the store.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{OperationError, json};
+
+use crate::arq::ReliableLink;
+use crate::blocking::BlockingError;
+use crate::clock::{Clock, SystemClock};
+use crate::txqueue::{TxPriority, TxQueue, TxQueueError};
+use crate::{Receive, ReceiveInfo, Transmit};
+
+/// Configuration for queue persistence
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct PersistOptions {
+    /// Persist queued and unacknowledged frames to this file, replaying them on startup
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub queue_store: Option<String>,
+
+    /// Discard persisted frames queued longer than this
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1h"))]
+    pub queue_retention: HumanDuration,
+
+    /// Maximum number of persisted frames, discarding the oldest
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1000"))]
+    pub queue_max: usize,
+}
+
+impl Default for PersistOptions {
+    fn default() -> Self {
+        Self {
+            queue_store: None,
+            queue_retention: Duration::from_secs(60 * 60).into(),
+            queue_max: 1000,
+        }
+    }
+}
+
+impl PersistOptions {
+    /// Open the configured queue store, if any
+    pub fn open(&self) -> Result<Option<QueueStore>, std::io::Error> {
+        match &self.queue_store {
+            Some(p) => QueueStore::open(p, *self.queue_retention, self.queue_max).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Kind of persisted frame
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoredKind {
+    /// Frame queued for transmission in a [`TxQueue`] class
+    Tx {
+        #[serde(with = "priority")]
+        priority: TxPriority,
+    },
+    /// Frame sent through a [`ReliableLink`] and not yet acknowledged
+    Arq,
+}
+
+/// Persisted frame
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredFrame {
+    pub kind: StoredKind,
+    pub data: Vec<u8>,
+    /// Time the frame was queued, in microseconds since the unix epoch
+    pub queued_us: u64,
+}
+
+/// File-backed store of queued and unacknowledged frames, timestamped with a wall clock
+/// so retention applies across restarts
+#[derive(Debug)]
+pub struct QueueStore<C = SystemClock> {
+    path: PathBuf,
+    retention: Duration,
+    max: usize,
+    frames: Vec<StoredFrame>,
+    clock: C,
+}
+
+impl QueueStore {
+    /// Open a store, loading persisted frames and discarding those older than
+    /// `retention` or in excess of `max`
+    pub fn open(
+        path: impl Into<PathBuf>,
+        retention: Duration,
+        max: usize,
+    ) -> Result<Self, std::io::Error> {
+        Self::open_clocked(path, retention, max, SystemClock)
+    }
+}
+
+impl<C: Clock> QueueStore<C> {
+    /// Open a store timestamping frames with the provided clock, in microseconds since
+    /// the unix epoch
+    pub fn open_clocked(
+        path: impl Into<PathBuf>,
+        retention: Duration,
+        max: usize,
+        clock: C,
+    ) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        let frames: Vec<StoredFrame> = match std::fs::read(&path) {
+            Ok(d) => json::from_slice(&d)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => Err(e)?,
+        };
+
+        let loaded = frames.len();
+        let mut s = Self {
+            path,
+            retention,
+            max,
+            frames,
+            clock,
+        };
+        s.prune();
+
+        info!(
+            "Loaded {} persisted frames ({} discarded)",
+            s.frames.len(),
+            loaded - s.frames.len()
+        );
+
+        if s.frames.len() != loaded {
+            s.save()?;
+        }
+        Ok(s)
+    }
+
+    /// Discard frames older than the retention period or in excess of the maximum,
+    /// oldest first
+    fn prune(&mut self) {
+        let cutoff = self
+            .clock
+            .now_us()
+            .saturating_sub(self.retention.as_micros() as u64);
+        self.frames.retain(|f| f.queued_us >= cutoff);
+
+        if self.frames.len() > self.max {
+            debug!(
+                "Discarding {} persisted frames",
+                self.frames.len() - self.max
+            );
+
+            self.frames.sort_by_key(|f| f.queued_us);
+            self.frames.drain(..self.frames.len() - self.max);
+        }
+    }
+
+    /// Persisted frames
+    pub fn frames(&self) -> &[StoredFrame] {
+        &self.frames
+    }
+
+    /// Write persisted frames, replacing the store file atomically
+    pub fn save(&mut self) -> Result<(), std::io::Error> {
+        let d = json::to_vec_pretty(&self.frames)?;
+
+        // Sync the data before renaming so a crash can't leave an empty or partial store
+        let tmp = self.path.with_extension("tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(&d)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        // Then the directory, persisting the rename
+        #[cfg(unix)]
+        match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => File::open(p)?.sync_all()?,
+            _ => File::open(".")?.sync_all()?,
+        }
+
+        Ok(())
+    }
+
+    /// Persist the contents of a transmit queue, replacing previously persisted
+    /// queue frames while retaining the times frames were first queued
+    pub fn snapshot_tx<const N: usize, const M: usize>(
+        &mut self,
+        queue: &TxQueue<N, M>,
+    ) -> Result<(), std::io::Error> {
+        let (mut prev, mut frames): (Vec<_>, Vec<_>) = self
+            .frames
+            .drain(..)
+            .partition(|f| matches!(f.kind, StoredKind::Tx { .. }));
+
+        let now = self.clock.now_us();
+        for (priority, data) in queue.frames() {
+            let kind = StoredKind::Tx { priority };
+            let queued_us = match prev.iter().position(|f| f.kind == kind && f.data == data) {
+                Some(i) => prev.remove(i).queued_us,
+                None => now,
+            };
+            frames.push(StoredFrame {
+                kind,
+                data: data.to_vec(),
+                queued_us,
+            });
+        }
+
+        self.frames = frames;
+        self.prune();
+        self.save()
+    }
+
+    /// Requeue persisted frames into a transmit queue, returning the number of
+    /// frames queued
+    pub fn replay_tx<const N: usize, const M: usize>(
+        &self,
+        queue: &mut TxQueue<N, M>,
+    ) -> Result<usize, TxQueueError> {
+        let mut n = 0;
+        for f in &self.frames {
+            if let StoredKind::Tx { priority } = f.kind {
+                queue.enqueue(priority, &f.data)?;
+                n += 1;
+            }
+        }
+
+        debug!("Replayed {} queued frames", n);
+
+        Ok(n)
+    }
+
+    /// Send a frame through a reliable link, persisting the frame until acknowledged
+    ///
+    /// Frames remaining unacknowledged once retries are exhausted are retained for
+    /// replay (see [`QueueStore::replay_arq`]).
+    pub fn send<T, I, E, const N: usize>(
+        &mut self,
+        link: &mut ReliableLink<T, N>,
+        data: &[u8],
+    ) -> Result<bool, PersistError<E>>
+    where
+        T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+        I: ReceiveInfo,
+        E: core::fmt::Debug,
+    {
+        self.frames.push(StoredFrame {
+            kind: StoredKind::Arq,
+            data: data.to_vec(),
+            queued_us: self.clock.now_us(),
+        });
+        self.prune();
+        self.save()?;
+
+        // The newest frame is only discarded where no frames may be retained
+        match self.frames.len() {
+            0 => Ok(link.send(data)?),
+            n => self.deliver(link, n - 1),
+        }
+    }
+
+    /// Resend persisted unacknowledged frames through a reliable link, returning the
+    /// number of frames acknowledged
+    pub fn replay_arq<T, I, E, const N: usize>(
+        &mut self,
+        link: &mut ReliableLink<T, N>,
+    ) -> Result<usize, PersistError<E>>
+    where
+        T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+        I: ReceiveInfo,
+        E: core::fmt::Debug,
+    {
+        let (mut acked, mut i, mut n) = (0, 0, 0);
+        let pending = self
+            .frames
+            .iter()
+            .filter(|f| f.kind == StoredKind::Arq)
+            .count();
+
+        // Frames are removed once acknowledged, otherwise moved past
+        while n < pending {
+            match self.frames[i].kind {
+                StoredKind::Arq => {
+                    n += 1;
+                    match self.deliver(link, i)? {
+                        true => acked += 1,
+                        false => i += 1,
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+
+        info!(
+            "Replayed {} unacknowledged frames, {} acknowledged",
+            n, acked
+        );
+
+        Ok(acked)
+    }
+
+    /// Send the persisted frame at index `i`, removing it once acknowledged
+    fn deliver<T, I, E, const N: usize>(
+        &mut self,
+        link: &mut ReliableLink<T, N>,
+        i: usize,
+    ) -> Result<bool, PersistError<E>>
+    where
+        T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+        I: ReceiveInfo,
+        E: core::fmt::Debug,
+    {
+        if !link.send(&self.frames[i].data)? {
+            return Ok(false);
+        }
+
+        self.frames.remove(i);
+        self.save()?;
+        Ok(true)
+    }
+}
+
+/// PersistError describes failures sending persisted frames
+#[derive(Debug)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum PersistError<E: core::fmt::Debug> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(BlockingError<E>),
+    /// Failure writing the queue store
+    #[cfg_attr(feature = "thiserror", error("Queue store: {0}"))]
+    Io(std::io::Error),
+}
+
+impl<E: core::fmt::Debug> From<BlockingError<E>> for PersistError<E> {
+    fn from(e: BlockingError<E>) -> Self {
+        PersistError::Radio(e)
+    }
+}
+
+impl<E: core::fmt::Debug> From<std::io::Error> for PersistError<E> {
+    fn from(e: std::io::Error) -> Self {
+        PersistError::Io(e)
+    }
+}
+
+impl<E: core::fmt::Debug> From<PersistError<E>> for OperationError<E> {
+    fn from(e: PersistError<E>) -> Self {
+        match e {
+            PersistError::Radio(e) => OperationError::Radio(e),
+            PersistError::Io(e) => OperationError::Io(e),
+        }
+    }
+}
+
+/// Serialise priority classes by name
+mod priority {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    use crate::txqueue::TxPriority;
+
+    const NAMES: [&str; 4] = ["ack", "beacon", "join", "bulk"];
+
+    pub fn serialize<S: Serializer>(p: &TxPriority, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(NAMES[p.index()])
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<TxPriority, D::Error> {
+        let n = String::deserialize(d)?;
+        NAMES
+            .iter()
+            .position(|v| *v == n)
+            .map(|i| TxPriority::ALL[i])
+            .ok_or_else(|| D::Error::custom(format!("unknown priority class '{}'", n)))
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::txqueue::TxQueueOptions;
+
+    #[test]
+    fn persist_tx_queue() {
+        let path = std::env::temp_dir().join(format!("radio-queue-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut q = TxQueue::<4, 8>::new(TxQueueOptions::default());
+        q.enqueue(TxPriority::Bulk, &[1]).unwrap();
+        q.enqueue(TxPriority::Beacon, &[2]).unwrap();
+
+        let clock = VirtualClock::new();
+        clock.set_us(1_700_000_000_000_000);
+        let open = |retention, max| QueueStore::open_clocked(&path, retention, max, &clock);
+
+        let mut s = open(Duration::from_secs(60), 10).unwrap();
+        s.snapshot_tx(&q).unwrap();
+        let queued_us = s.frames()[0].queued_us;
+
+        // Times frames were first queued are retained across snapshots
+        q.enqueue(TxPriority::Bulk, &[3]).unwrap();
+        clock.advance(Duration::from_millis(2));
+        s.snapshot_tx(&q).unwrap();
+        assert_eq!(s.frames()[0].queued_us, queued_us);
+
+        // Frames are replayed in transmit order following a restart
+        let s = open(Duration::from_secs(60), 10).unwrap();
+        let mut replayed = TxQueue::<4, 8>::new(TxQueueOptions::default());
+        assert_eq!(s.replay_tx(&mut replayed), Ok(3));
+        assert_eq!(
+            replayed.frames().collect::<Vec<_>>(),
+            q.frames().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            replayed.frames().next(),
+            Some((TxPriority::Beacon, &[2u8][..]))
+        );
+
+        // Excess frames are discarded, oldest first, on opening and on updates
+        let data = |s: &QueueStore<_>| s.frames().iter().map(|f| f.data[0]).collect::<Vec<_>>();
+        let mut s = open(Duration::from_secs(60), 2).unwrap();
+        assert_eq!(data(&s), vec![1, 3]);
+        q.enqueue(TxPriority::Bulk, &[4]).unwrap();
+        clock.advance(Duration::from_millis(2));
+        s.snapshot_tx(&q).unwrap();
+        assert_eq!(data(&s), vec![2, 4]);
+
+        let s = open(Duration::from_secs(60), 1).unwrap();
+        assert_eq!(s.frames().len(), 1);
+        assert_eq!(s.frames()[0].data, vec![4]);
+        assert!(!path.with_extension("tmp").exists());
+
+        // As are expired frames
+        clock.advance(Duration::from_millis(2));
+        let s = open(Duration::from_millis(1), 10).unwrap();
+        assert!(s.frames().is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,369 @@
+//! Named pipelines of operations running concurrently
+//!
+//! [`Pipelines`] runs operations on multiple radios (or in multiple roles) in a single
+//! process, for example `echo` on one radio while receiving on another. Each stage runs
+//! on its own thread with its radio wrapped in [`Stoppable`], so a shared [`Shutdown`]
+//! (triggered by the caller, or by any stage failing) stops all stages at their next
+//! radio access, after which a combined summary is reported.
+
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{info, warn};
+
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+
+use super::{Operation, OperationError, Shutdown, do_operation};
+use crate::{Power, Receive, ReceiveInfo, Rssi, Transmit, blocking::BlockingError};
+
+/// Error from a radio wrapped in [`Stoppable`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum StageError<E> {
+    /// Underlying radio error
+    Radio(E),
+    /// Shutdown requested
+    Shutdown,
+}
+
+/// Radio wrapper failing all radio operations once shutdown is requested, causing the
+/// running operation to exit
+pub struct Stoppable<T> {
+    radio: T,
+    shutdown: Shutdown,
+}
+
+impl<T> Stoppable<T> {
+    /// Wrap a radio with the provided shutdown signal
+    pub fn new(radio: T, shutdown: Shutdown) -> Self {
+        Self { radio, shutdown }
+    }
+
+    /// Release the wrapped radio
+    pub fn into_inner(self) -> T {
+        self.radio
+    }
+
+    fn check<E>(&self) -> Result<(), StageError<E>> {
+        match self.shutdown.is_triggered() {
+            true => Err(StageError::Shutdown),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<T: Transmit> Transmit for Stoppable<T> {
+    type Error = StageError<T::Error>;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.check()?;
+        self.radio.start_transmit(data).map_err(StageError::Radio)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.check()?;
+        self.radio.check_transmit().map_err(StageError::Radio)
+    }
+}
+
+impl<T: Receive> Receive for Stoppable<T> {
+    type Error = StageError<T::Error>;
+    type Info = T::Info;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.check()?;
+        self.radio.start_receive().map_err(StageError::Radio)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.check()?;
+        self.radio.check_receive(restart).map_err(StageError::Radio)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.check()?;
+        self.radio.get_received(buff).map_err(StageError::Radio)
+    }
+}
+
+impl<T: Power> Power for Stoppable<T> {
+    type Error = StageError<T::Error>;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.check()?;
+        self.radio.set_power(power).map_err(StageError::Radio)
+    }
+}
+
+impl<T: Rssi> Rssi for Stoppable<T> {
+    type Error = StageError<T::Error>;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.check()?;
+        self.radio.poll_rssi().map_err(StageError::Radio)
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.check()?;
+        self.radio.poll_rssi_n(out).map_err(StageError::Radio)
+    }
+}
+
+impl<T: DelayNs> DelayNs for Stoppable<T> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+/// Outcome of a pipeline stage
+#[derive(Clone, Debug, PartialEq)]
+pub enum StageOutcome {
+    /// Operation completed
+    Complete,
+    /// Operation stopped by shutdown
+    Stopped,
+    /// Operation failed with the provided error
+    Failed(String),
+}
+
+/// Summary of a pipeline stage
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageSummary {
+    /// Stage name
+    pub name: String,
+    /// Operation run by the stage
+    pub operation: &'static str,
+    /// Stage run time
+    pub elapsed: Duration,
+    /// Stage outcome
+    pub outcome: StageOutcome,
+}
+
+/// Combined summary of all pipeline stages
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineSummary {
+    pub stages: Vec<StageSummary>,
+}
+
+impl PipelineSummary {
+    /// Check whether any stage failed
+    pub fn failed(&self) -> bool {
+        self.stages
+            .iter()
+            .any(|s| matches!(s.outcome, StageOutcome::Failed(_)))
+    }
+}
+
+impl core::fmt::Display for PipelineSummary {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:<16} {:>10}  outcome",
+            "stage", "operation", "time (s)"
+        )?;
+        for s in &self.stages {
+            let outcome = match &s.outcome {
+                StageOutcome::Complete => "complete".to_string(),
+                StageOutcome::Stopped => "stopped".to_string(),
+                StageOutcome::Failed(e) => format!("failed: {}", e),
+            };
+            writeln!(
+                f,
+                "{:<12} {:<16} {:>10.1}  {}",
+                s.name,
+                s.operation,
+                s.elapsed.as_secs_f32(),
+                outcome
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Concurrently running named operations with a shared shutdown
+#[derive(Default)]
+pub struct Pipelines {
+    shutdown: Shutdown,
+    stages: Vec<JoinHandle<StageSummary>>,
+}
+
+impl Pipelines {
+    /// Create an empty set of pipelines
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shutdown signal shared by all stages (for example to trigger on ctrl-c)
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Start an operation on the provided radio, failures trigger shutdown of all stages
+    pub fn spawn<T, I, E>(&mut self, name: &str, radio: T, operation: Operation)
+    where
+        T: Transmit<Error = E>
+            + Power<Error = E>
+            + Receive<Info = I, Error = E>
+            + Rssi<Error = E>
+            + DelayNs
+            + Send
+            + 'static,
+        I: ReceiveInfo + Default + std::fmt::Debug,
+        E: std::fmt::Debug,
+    {
+        let name = name.to_string();
+        let shutdown = self.shutdown.clone();
+
+        info!("Starting pipeline {}: {}", name, operation.name());
+
+        self.stages.push(std::thread::spawn(move || {
+            let mut radio = Stoppable::new(radio, shutdown.clone());
+            let op = operation.name();
+            let start = Instant::now();
+
+            let outcome = match do_operation(&mut radio, operation) {
+                Ok(_) => StageOutcome::Complete,
+                Err(OperationError::Radio(BlockingError::Inner(StageError::Shutdown))) => {
+                    StageOutcome::Stopped
+                }
+                Err(e) => {
+                    warn!("Pipeline {} failed: {:?}, stopping all pipelines", name, e);
+                    shutdown.trigger();
+                    StageOutcome::Failed(format!("{:?}", e))
+                }
+            };
+
+            StageSummary {
+                name,
+                operation: op,
+                elapsed: start.elapsed(),
+                outcome,
+            }
+        }));
+    }
+
+    /// Wait for all stages to exit, returning the combined summary
+    pub fn join(self) -> PipelineSummary {
+        let stages = self
+            .stages
+            .into_iter()
+            .filter_map(|h| h.join().ok())
+            .collect();
+
+        let s = PipelineSummary { stages };
+        info!("Pipelines complete:\n{}", s);
+        s
+    }
+}
+
+/// Parse a named pipeline stage from `NAME=OPERATION [OPTIONS..]`
+/// (eg. `a=echo --continuous`), with options separated by whitespace
+pub fn parse_stage(s: &str) -> Result<(String, Operation), String> {
+    let (name, op) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=OPERATION, found '{}'", s))?;
+
+    let args = std::iter::once("pipeline").chain(op.split_whitespace());
+    let op = Operation::try_parse_from(args).map_err(|e| e.to_string())?;
+
+    Ok((name.to_string(), op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio that never receives, optionally failing transmissions
+    struct IdleRadio {
+        fail_tx: bool,
+    }
+
+    impl Transmit for IdleRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            match self.fail_tx {
+                true => Err(()),
+                false => Ok(()),
+            }
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for IdleRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Err(())
+        }
+    }
+
+    impl Power for IdleRadio {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl Rssi for IdleRadio {
+        type Error = ();
+
+        fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+            Ok(-100)
+        }
+    }
+
+    impl DelayNs for IdleRadio {
+        fn delay_ns(&mut self, _ns: u32) {
+            std::thread::sleep(Duration::from_micros(10));
+        }
+    }
+
+    #[test]
+    fn pipelines_shared_shutdown() {
+        let (_, rx) = parse_stage("b=rx --continuous").unwrap();
+        let (_, tx) = parse_stage("a=tx --data 1").unwrap();
+        assert!(parse_stage("rx").is_err());
+        assert!(parse_stage("a=unknown").is_err());
+
+        // External shutdown stops running stages
+        let mut p = Pipelines::new();
+        p.spawn("a", IdleRadio { fail_tx: false }, tx.clone());
+        p.spawn("b", IdleRadio { fail_tx: false }, rx.clone());
+        std::thread::sleep(Duration::from_millis(10));
+        p.shutdown().trigger();
+
+        let s = p.join();
+        assert_eq!(s.stages[0].outcome, StageOutcome::Complete);
+        assert_eq!(s.stages[1].outcome, StageOutcome::Stopped);
+        assert!(!s.failed());
+
+        // Failing stages stop all stages
+        let mut p = Pipelines::new();
+        p.spawn("b", IdleRadio { fail_tx: false }, rx);
+        p.spawn("a", IdleRadio { fail_tx: true }, tx);
+
+        let s = p.join();
+        assert_eq!(s.stages[0].outcome, StageOutcome::Stopped);
+        assert!(matches!(s.stages[1].outcome, StageOutcome::Failed(_)));
+        assert!(s.failed());
+    }
+}
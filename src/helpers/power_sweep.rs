@@ -0,0 +1,365 @@
+//! Transmit power sweep for antenna and PA characterisation
+//!
+//! The `power-sweep` operation steps the output power from `--start` to `--stop` dBm
+//! in `--step` increments, transmitting a test pattern for `--dwell` at each level.
+//! With `--echo`, each packet awaits a response from a peer running `echo --append-info`,
+//! correlating the RSSI reported by the peer with the configured power. A linear PA and
+//! well matched antenna give a 1 dB/dB slope, with compression showing as a reduced
+//! slope at the upper levels (see [`PowerSweepInfo::slope`]).
+
+use std::time::Instant;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{Progress, ProgressOptions, Samples};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingReceive, BlockingTransmit},
+};
+
+/// Length of the sequence number and power level prefixing each sweep packet
+const HEADER_LEN: usize = 5;
+
+/// Configuration for PowerSweep operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct PowerSweepOptions {
+    /// First power level in dBm
+    #[cfg_attr(
+        feature = "clap",
+        clap(long, default_value = "-18", allow_hyphen_values = true)
+    )]
+    pub start: i8,
+
+    /// Last power level in dBm (sweeping downwards where below `--start`)
+    #[cfg_attr(
+        feature = "clap",
+        clap(long, default_value = "13", allow_hyphen_values = true)
+    )]
+    pub stop: i8,
+
+    /// Power step in dB
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1", value_parser = clap::value_parser!(u8).range(1..)))]
+    pub step: u8,
+
+    /// Time spent transmitting at each power level
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1s"))]
+    pub dwell: HumanDuration,
+
+    /// Period between packets at each power level
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100ms"))]
+    pub period: HumanDuration,
+
+    /// Packet size in bytes (including a 5 byte sequence number and power header)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "16"))]
+    pub size: usize,
+
+    /// Await responses from a peer running `echo --append-info`, recording remote RSSI
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub echo: bool,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for PowerSweepOptions {
+    fn default() -> Self {
+        Self {
+            start: -18,
+            stop: 13,
+            step: 1,
+            dwell: std::time::Duration::from_secs(1).into(),
+            period: std::time::Duration::from_millis(100).into(),
+            size: 16,
+            echo: false,
+            progress_options: ProgressOptions::default(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+impl PowerSweepOptions {
+    /// Power levels in the sweep, from `start` towards `stop`
+    pub fn levels(&self) -> Vec<i8> {
+        let step = self.step.max(1) as usize;
+        match self.start <= self.stop {
+            true => (self.start..=self.stop).step_by(step).collect(),
+            false => (self.stop..=self.start).rev().step_by(step).collect(),
+        }
+    }
+}
+
+/// Results at a single power level
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PowerLevel {
+    /// Configured power in dBm
+    pub power: i8,
+    /// Packets sent
+    pub sent: u32,
+    /// Responses received, where awaiting responses
+    pub received: Option<u32>,
+    /// RSSI of responses at the local radio
+    pub local_rssi: Samples,
+    /// RSSI reported by the peer
+    pub remote_rssi: Samples,
+}
+
+/// Power sweep results
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PowerSweepInfo {
+    pub levels: Vec<PowerLevel>,
+}
+
+impl PowerSweepInfo {
+    /// Least-squares slope (dB/dB) of mean remote RSSI against configured power,
+    /// where at least two levels have remote RSSI reports
+    pub fn slope(&self) -> Option<f32> {
+        let points: Vec<_> = self
+            .levels
+            .iter()
+            .filter_map(|l| l.remote_rssi.mean().map(|r| (l.power as f32, r)))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let n = points.len() as f32;
+        let mean_p = points.iter().map(|(p, _)| p).sum::<f32>() / n;
+        let mean_r = points.iter().map(|(_, r)| r).sum::<f32>() / n;
+        let cov: f32 = points
+            .iter()
+            .map(|(p, r)| (p - mean_p) * (r - mean_r))
+            .sum();
+        let var: f32 = points.iter().map(|(p, _)| (p - mean_p).powi(2)).sum();
+
+        match var > 0.0 {
+            true => Some(cov / var),
+            false => None,
+        }
+    }
+}
+
+impl core::fmt::Display for PowerSweepInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>6} {:>8} {:>12} {:>12}",
+            "power", "sent", "received", "local rssi", "remote rssi"
+        )?;
+        for l in &self.levels {
+            let rssi = |s: &Samples| match s.mean() {
+                Some(m) => format!("{:.1}", m),
+                None => "-".to_string(),
+            };
+            let received = match l.received {
+                Some(r) => r.to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:>6} {:>6} {:>8} {:>12} {:>12}",
+                l.power,
+                l.sent,
+                received,
+                rssi(&l.local_rssi),
+                rssi(&l.remote_rssi)
+            )?;
+        }
+        match self.slope() {
+            Some(s) => write!(f, "remote rssi slope: {:.2} dB/dB", s),
+            None => write!(f, "remote rssi slope: -"),
+        }
+    }
+}
+
+/// Step transmit power across the configured range, transmitting a test pattern at
+/// each level and (with `echo`) recording RSSI reported by an echo peer
+pub fn do_power_sweep<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: PowerSweepOptions,
+) -> Result<PowerSweepInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    let levels = options.levels();
+    let mut progress = options.progress_options.reporter();
+    let mut info = PowerSweepInfo::default();
+    let mut data: Vec<u8> = (0..options.size.max(HEADER_LEN)).map(|i| i as u8).collect();
+    let mut seq = 0u32;
+
+    for (i, power) in levels.iter().enumerate() {
+        radio.set_power(*power)?;
+        data[4] = *power as u8;
+
+        let mut level = PowerLevel {
+            power: *power,
+            received: options.echo.then_some(0),
+            ..Default::default()
+        };
+
+        // Transmit at least one packet per level
+        let start = Instant::now();
+        loop {
+            data[..4].copy_from_slice(&seq.to_be_bytes());
+            seq = seq.wrapping_add(1);
+
+            radio.do_transmit(&data, options.blocking_options.clone())?;
+            level.sent += 1;
+
+            if options.echo {
+                match radio.do_receive(buff, options.blocking_options.clone()) {
+                    // Responses echo the packet, followed by the peer RSSI
+                    Ok((n, i))
+                        if n >= data.len() + 2 && buff[..HEADER_LEN] == data[..HEADER_LEN] =>
+                    {
+                        let remote = i16::from_be_bytes([buff[n - 2], buff[n - 1]]);
+                        level.received = level.received.map(|r| r + 1);
+                        level.local_rssi.update(i.rssi() as f32);
+                        level.remote_rssi.update(remote as f32);
+                    }
+                    Ok((n, _)) => debug!("Unexpected {} byte response", n),
+                    Err(BlockingError::Timeout) => debug!("Timeout awaiting response"),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if start.elapsed() >= *options.dwell {
+                break;
+            }
+            radio.delay_us(options.period.as_micros() as u32);
+        }
+
+        debug!(
+            "Power {} dBm: sent {} received {:?}",
+            power, level.sent, level.received
+        );
+        info.levels.push(level);
+
+        progress.update(&Progress::new(
+            "power-sweep",
+            i as u64 + 1,
+            Some(levels.len() as u64),
+        ));
+    }
+    progress.finish(&Progress::new(
+        "power-sweep",
+        levels.len() as u64,
+        Some(levels.len() as u64),
+    ));
+
+    info!("Power sweep:\n{}", info);
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio echoing each packet with a peer RSSI tracking the transmit power,
+    /// compressing above 10 dBm
+    #[derive(Default)]
+    struct EchoRadio {
+        power: i8,
+        last: Option<Vec<u8>>,
+    }
+
+    impl Transmit for EchoRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let rssi = self.power.min(10) as i16 - 60;
+            let mut d = data.to_vec();
+            d.extend_from_slice(&rssi.to_be_bytes());
+            self.last = Some(d);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for EchoRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(-50, 0)))
+        }
+    }
+
+    impl Power for EchoRadio {
+        type Error = ();
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            self.power = power;
+            Ok(())
+        }
+    }
+
+    impl DelayNs for EchoRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn power_sweep_echo() {
+        let options = PowerSweepOptions {
+            start: 0,
+            stop: 14,
+            step: 2,
+            dwell: std::time::Duration::ZERO.into(),
+            echo: true,
+            ..Default::default()
+        };
+        assert_eq!(options.levels(), vec![0, 2, 4, 6, 8, 10, 12, 14]);
+        assert_eq!(
+            PowerSweepOptions {
+                start: 5,
+                stop: 0,
+                step: 2,
+                ..Default::default()
+            }
+            .levels(),
+            vec![5, 3, 1]
+        );
+
+        let mut buff = [0u8; 64];
+        let info = do_power_sweep(&mut EchoRadio::default(), &mut buff, options).unwrap();
+
+        assert_eq!(info.levels.len(), 8);
+        assert!(info.levels.iter().all(|l| l.received == Some(l.sent)));
+        assert_eq!(info.levels[2].remote_rssi.mean(), Some(-56.0));
+
+        // Compression above 10 dBm reduces the slope below 1 dB/dB
+        let slope = info.slope().unwrap();
+        assert!(slope > 0.5 && slope < 1.0, "slope: {}", slope);
+    }
+}
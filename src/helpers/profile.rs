@@ -0,0 +1,164 @@
+//! Radio configuration profiles loaded from TOML or JSON files
+//!
+//! Binaries flatten [`ConfigFileOptions`] into their arguments to accept `--config <file>`,
+//! loading a declarative [`RadioConfig`] (frequency, data rate, sync word, preamble length
+//! and so on) that is applied to radios implementing [`Configure`] with
+//! [`apply_radio_config`] (or [`do_operation_config`](super::do_operation_config)) before
+//! running an operation. Files with a `.toml` extension are parsed as TOML, others as JSON:
+//!
+//! ```toml
+//! frequency = 868100000
+//! bitrate = 50000
+//! sync_word = 0x2dd4
+//! preamble_length = 8
+//! ```
+
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::config::{ConfigError, Configure, RadioConfig};
+
+use super::json;
+
+/// Options for loading a radio configuration profile
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ConfigFileOptions {
+    /// Radio configuration profile (TOML or JSON) applied before running operations
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub config: Option<PathBuf>,
+}
+
+impl ConfigFileOptions {
+    /// Load the configured profile, `None` where no profile is configured
+    pub fn load(&self) -> Result<Option<RadioConfig>, Error> {
+        self.config.as_deref().map(load_radio_config).transpose()
+    }
+}
+
+/// Load a radio configuration profile, parsing files with a `.toml` extension as TOML
+/// and others as JSON
+pub fn load_radio_config(path: &Path) -> Result<RadioConfig, Error> {
+    let d = std::fs::read_to_string(path)?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "toml")]
+        Some("toml") => toml::from_str(&d).map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        #[cfg(not(feature = "toml"))]
+        Some("toml") => Err(Error::new(
+            ErrorKind::Unsupported,
+            "TOML profiles require the `toml` feature",
+        )),
+        _ => json::from_str(&d),
+    }
+}
+
+/// Apply a radio configuration profile, skipping (and warning on) options not supported
+/// by the radio and returning the number of options applied
+pub fn apply_radio_config<T, E>(radio: &mut T, config: &RadioConfig) -> Result<usize, E>
+where
+    T: Configure<Error = E>,
+    E: core::fmt::Debug,
+{
+    let mut applied = 0;
+
+    for o in config.options() {
+        match radio.set_option(&o) {
+            Ok(()) => {
+                debug!("Applied config option: {:?}", o);
+                applied += 1;
+            }
+            Err(ConfigError::NotSupported) => {
+                warn!("Config option not supported by radio: {:?}", o)
+            }
+            Err(ConfigError::Other(e)) => return Err(e),
+        }
+    }
+
+    Ok(applied)
+}
+
+#[cfg(all(test, feature = "json", feature = "toml"))]
+mod tests {
+    use super::*;
+    use crate::config::ConfigOption;
+
+    /// Radio supporting frequency and bitrate options
+    #[derive(Default)]
+    struct Settings {
+        options: Vec<ConfigOption>,
+    }
+
+    impl Configure for Settings {
+        type Error = ();
+
+        fn set_option(&mut self, o: &ConfigOption) -> Result<(), ConfigError<Self::Error>> {
+            match o {
+                ConfigOption::Frequency(_) | ConfigOption::Bitrate(_) => {
+                    self.options.push(o.clone());
+                    Ok(())
+                }
+                _ => Err(ConfigError::NotSupported),
+            }
+        }
+
+        fn get_option(&mut self, _o: &mut ConfigOption) -> Result<(), ConfigError<Self::Error>> {
+            Err(ConfigError::NotSupported)
+        }
+    }
+
+    #[test]
+    fn load_profiles() {
+        let dir = std::env::temp_dir().join(format!("radio-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_path = dir.join("radio.toml");
+        std::fs::write(
+            &toml_path,
+            "frequency = 868100000\nbitrate = 50000\nsync_word = 0x2dd4\npreamble_length = 8\n",
+        )
+        .unwrap();
+        let json_path = dir.join("radio.json");
+        std::fs::write(
+            &json_path,
+            r#"{"frequency": 868100000, "bitrate": 50000, "sync_word": 11732, "preamble_length": 8}"#,
+        )
+        .unwrap();
+
+        let config = load_radio_config(&toml_path).unwrap();
+        assert_eq!(config, load_radio_config(&json_path).unwrap());
+        assert_eq!(config.frequency, Some(868_100_000));
+        assert_eq!(config.sync_word, Some(0x2dd4));
+
+        // Unknown settings are rejected
+        std::fs::write(&toml_path, "frequncy = 868100000\n").unwrap();
+        assert!(load_radio_config(&toml_path).is_err());
+        assert_eq!(ConfigFileOptions::default().load().unwrap(), None);
+
+        // Unsupported options are skipped, and strict application fails
+        let mut radio = Settings::default();
+        assert_eq!(apply_radio_config(&mut radio, &config), Ok(2));
+        assert_eq!(
+            radio.options,
+            vec![
+                ConfigOption::Frequency(868_100_000),
+                ConfigOption::Bitrate(50_000)
+            ]
+        );
+        assert_eq!(
+            Settings::default().apply_config(&config),
+            Err(ConfigError::NotSupported)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+//! Progress reporting for long-running operations
+//!
+//! Operations such as link tests, transmission from packet sources and channel scans
+//! report structured [`Progress`] updates to a [`ProgressReporter`], allowing GUI embedders
+//! to provide their own reporters (including closures). By default progress is logged
+//! at `--progress-interval`, with `--progress` showing progress bars where the `progress`
+//! feature is enabled.
+
+use std::time::Instant;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use humantime::Duration as HumanDuration;
+
+/// Progress reporting options
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ProgressOptions {
+    /// Show progress bars (requires the `progress` feature, progress is logged otherwise)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub progress: bool,
+
+    /// Interval between logged progress updates
+    #[cfg_attr(feature = "clap", clap(long, default_value = "5s"))]
+    pub progress_interval: HumanDuration,
+}
+
+impl Default for ProgressOptions {
+    fn default() -> Self {
+        Self {
+            progress: false,
+            progress_interval: std::time::Duration::from_secs(5).into(),
+        }
+    }
+}
+
+impl ProgressOptions {
+    /// Create the configured progress reporter
+    pub fn reporter(&self) -> Box<dyn ProgressReporter + Send> {
+        #[cfg(feature = "progress")]
+        if self.progress {
+            return Box::new(BarProgress::new());
+        }
+
+        Box::new(LogProgress::new(*self.progress_interval))
+    }
+}
+
+/// Progress of an operation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress<'a> {
+    /// Operation name
+    pub operation: &'a str,
+    /// Completed steps (rounds, payloads or samples)
+    pub done: u64,
+    /// Total steps, where known
+    pub total: Option<u64>,
+}
+
+impl<'a> Progress<'a> {
+    /// Create a progress update
+    pub fn new(operation: &'a str, done: u64, total: Option<u64>) -> Self {
+        Self {
+            operation,
+            done,
+            total,
+        }
+    }
+
+    /// Completed fraction (0.0 to 1.0), where the total is known
+    pub fn fraction(&self) -> Option<f32> {
+        self.total
+            .filter(|t| *t > 0)
+            .map(|t| (self.done as f32 / t as f32).min(1.0))
+    }
+}
+
+impl core::fmt::Display for Progress<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match (self.total, self.fraction()) {
+            (Some(t), Some(p)) => write!(
+                f,
+                "{} {}/{} ({:.0}%)",
+                self.operation,
+                self.done,
+                t,
+                p * 100.0
+            ),
+            _ => write!(f, "{} {}", self.operation, self.done),
+        }
+    }
+}
+
+/// Receiver for progress updates from long-running operations
+pub trait ProgressReporter {
+    /// Report progress, called as each step completes
+    fn update(&mut self, progress: &Progress);
+
+    /// Report completion, called once when the operation finishes
+    fn finish(&mut self, progress: &Progress) {
+        self.update(progress)
+    }
+}
+
+impl<F: FnMut(&Progress)> ProgressReporter for F {
+    fn update(&mut self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// Progress reporter discarding updates
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn update(&mut self, _progress: &Progress) {}
+}
+
+/// Progress reporter logging updates at a fixed interval
+///
+/// Completion is not logged, as operations log their own results.
+#[derive(Clone, Debug)]
+pub struct LogProgress {
+    interval: std::time::Duration,
+    last: Option<Instant>,
+}
+
+impl LogProgress {
+    /// Create a reporter logging at most once per `interval`
+    pub fn new(interval: std::time::Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+}
+
+impl ProgressReporter for LogProgress {
+    fn update(&mut self, progress: &Progress) {
+        let now = Instant::now();
+        let last = *self.last.get_or_insert(now);
+
+        if now.duration_since(last) >= self.interval {
+            self.last = Some(now);
+            info!("Progress: {}", progress);
+        }
+    }
+
+    fn finish(&mut self, _progress: &Progress) {
+        self.last = None;
+    }
+}
+
+/// Progress reporter drawing a terminal progress bar (or spinner where the total is
+/// unknown)
+#[cfg(feature = "progress")]
+#[derive(Debug, Default)]
+pub struct BarProgress {
+    bar: Option<indicatif::ProgressBar>,
+}
+
+#[cfg(feature = "progress")]
+impl BarProgress {
+    /// Create a reporter, with the bar shown on the first update
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "progress")]
+impl ProgressReporter for BarProgress {
+    fn update(&mut self, progress: &Progress) {
+        let bar = self.bar.get_or_insert_with(|| {
+            let (bar, template) = match progress.total {
+                Some(t) => (
+                    indicatif::ProgressBar::new(t),
+                    "{msg} [{elapsed_precise}] {wide_bar} {pos}/{len} (eta {eta})",
+                ),
+                None => (
+                    indicatif::ProgressBar::new_spinner(),
+                    "{msg} [{elapsed_precise}] {spinner} {pos}",
+                ),
+            };
+            if let Ok(s) = indicatif::ProgressStyle::with_template(template) {
+                bar.set_style(s);
+            }
+            bar.set_message(progress.operation.to_string());
+            bar
+        });
+
+        bar.set_position(progress.done);
+    }
+
+    fn finish(&mut self, progress: &Progress) {
+        self.update(progress);
+        if let Some(b) = self.bar.take() {
+            b.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_updates() {
+        let p = Progress::new("ping-pong", 25, Some(100));
+        assert_eq!(p.fraction(), Some(0.25));
+        assert_eq!(p.to_string(), "ping-pong 25/100 (25%)");
+        assert_eq!(Progress::new("tx", 3, None).to_string(), "tx 3");
+        assert_eq!(Progress::new("scan", 0, Some(0)).fraction(), None);
+
+        // Closures may be used as reporters
+        let mut seen = vec![];
+        let mut r = |p: &Progress| seen.push((p.done, p.total));
+        for i in 1..=3 {
+            r.update(&Progress::new("test", i, Some(3)));
+        }
+        r.finish(&Progress::new("test", 3, Some(3)));
+        assert_eq!(
+            seen,
+            vec![(1, Some(3)), (2, Some(3)), (3, Some(3)), (3, Some(3))]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Receive quality metrics beyond RSSI
+//!
+//! Drivers may report LQI, SNR, frequency error and capture timestamps via the optional
+//! [`ReceiveInfo`] accessors. [`ReceiveQuality`] collects these for capture output and
+//! link statistics, and encodes them after the RSSI appended to echo responses
+//! (`echo --append-info`) so `ping-pong --parse-info` can report remote link quality.
+//!
+//! Appended info is the 2-byte RSSI (as previously), followed where any metrics are
+//! available by a flags byte indicating which of LQI (2 bytes), SNR (2 bytes, in 0.25 dB
+//! steps) and frequency error (4 bytes, in Hz) follow, so peers parsing only the RSSI
+//! remain compatible.
+
+use byteorder::{ByteOrder, NetworkEndian};
+use serde::{Deserialize, Serialize};
+
+use crate::ReceiveInfo;
+
+const FLAG_LQI: u8 = 1 << 0;
+const FLAG_SNR: u8 = 1 << 1;
+const FLAG_FREQUENCY_ERROR: u8 = 1 << 2;
+
+/// Receive quality metrics, where reported by the radio
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReceiveQuality {
+    /// Link Quality Indicator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lqi: Option<u16>,
+    /// Signal to noise ratio in dB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snr_db: Option<f32>,
+    /// Frequency error in Hz (received - tuned)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_error_hz: Option<i32>,
+    /// Capture timestamp in microseconds on the driver clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_us: Option<u64>,
+}
+
+impl ReceiveQuality {
+    /// Collect quality metrics from driver receive info
+    pub fn from_info<I: ReceiveInfo>(info: &I) -> Self {
+        Self {
+            lqi: info.lqi(),
+            snr_db: info.snr_db(),
+            frequency_error_hz: info.frequency_error_hz(),
+            timestamp_us: info.timestamp_us(),
+        }
+    }
+
+    /// Check whether no metrics were reported
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Append the RSSI and any available metrics (excluding the driver timestamp) to a
+    /// response, returning the number of bytes written or `None` where space is insufficient
+    pub fn encode(&self, rssi: i16, buff: &mut [u8]) -> Option<usize> {
+        let mut flags = 0;
+        let mut len = 2;
+        for (present, flag, n) in [
+            (self.lqi.is_some(), FLAG_LQI, 2),
+            (self.snr_db.is_some(), FLAG_SNR, 2),
+            (self.frequency_error_hz.is_some(), FLAG_FREQUENCY_ERROR, 4),
+        ] {
+            if present {
+                flags |= flag;
+                len += n;
+            }
+        }
+        if flags != 0 {
+            len += 1;
+        }
+        if buff.len() < len {
+            return None;
+        }
+
+        NetworkEndian::write_i16(buff, rssi);
+        if flags == 0 {
+            return Some(2);
+        }
+        buff[2] = flags;
+
+        let mut n = 3;
+        if let Some(lqi) = self.lqi {
+            NetworkEndian::write_u16(&mut buff[n..], lqi);
+            n += 2;
+        }
+        if let Some(snr) = self.snr_db {
+            NetworkEndian::write_i16(&mut buff[n..], (snr * 4.0).round() as i16);
+            n += 2;
+        }
+        if let Some(e) = self.frequency_error_hz {
+            NetworkEndian::write_i32(&mut buff[n..], e);
+            n += 4;
+        }
+
+        Some(n)
+    }
+
+    /// Parse appended info, returning the RSSI and any metrics provided
+    pub fn decode(buff: &[u8]) -> Option<(i16, Self)> {
+        if buff.len() < 2 {
+            return None;
+        }
+        let rssi = NetworkEndian::read_i16(buff);
+
+        let mut q = Self::default();
+        let flags = match buff.get(2) {
+            Some(f) => *f,
+            None => return Some((rssi, q)),
+        };
+
+        let mut d = &buff[3..];
+        let mut take = |n: usize| match d.len() >= n {
+            true => {
+                let (v, rest) = d.split_at(n);
+                d = rest;
+                Some(v)
+            }
+            false => None,
+        };
+        if flags & FLAG_LQI != 0 {
+            q.lqi = Some(NetworkEndian::read_u16(take(2)?));
+        }
+        if flags & FLAG_SNR != 0 {
+            q.snr_db = Some(NetworkEndian::read_i16(take(2)?) as f32 / 4.0);
+        }
+        if flags & FLAG_FREQUENCY_ERROR != 0 {
+            q.frequency_error_hz = Some(NetworkEndian::read_i32(take(4)?));
+        }
+
+        Some((rssi, q))
+    }
+
+    /// Parse metrics from a PCAP-NG packet comment (see [`packet_comment`](super::packet_comment))
+    pub fn from_comment(comment: &str) -> Self {
+        // Driver info follows the last field, and may contain spaces
+        let fields = comment.split_once(" info=").map_or(comment, |(f, _)| f);
+        let field = |name: &str| {
+            fields
+                .split(' ')
+                .find_map(|f| f.strip_prefix(name)?.strip_prefix('='))
+        };
+
+        Self {
+            lqi: field("lqi").and_then(|v| v.parse().ok()),
+            snr_db: field("snr").and_then(|v| v.parse().ok()),
+            frequency_error_hz: field("cfo").and_then(|v| v.parse().ok()),
+            timestamp_us: field("ts").and_then(|v| v.parse().ok()),
+        }
+    }
+}
+
+/// Space separated `name=value` fields for available metrics, as used in capture comments
+impl core::fmt::Display for ReceiveQuality {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut sep = "";
+        let mut field =
+            |f: &mut core::fmt::Formatter<'_>, name: &str, v: &dyn core::fmt::Display| {
+                let r = write!(f, "{}{}={}", sep, name, v);
+                sep = " ";
+                r
+            };
+
+        if let Some(v) = self.lqi {
+            field(f, "lqi", &v)?;
+        }
+        if let Some(v) = self.snr_db {
+            field(f, "snr", &v)?;
+        }
+        if let Some(v) = self.frequency_error_hz {
+            field(f, "cfo", &v)?;
+        }
+        if let Some(v) = self.timestamp_us {
+            field(f, "ts", &v)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_quality() {
+        let q = ReceiveQuality {
+            lqi: Some(200),
+            snr_db: Some(-7.25),
+            frequency_error_hz: Some(-1_200),
+            timestamp_us: Some(123_456),
+        };
+
+        let mut buff = [0u8; 16];
+        assert_eq!(q.encode(-80, &mut buff[..10]), None);
+        let n = q.encode(-80, &mut buff).unwrap();
+        assert_eq!(n, 11);
+
+        // Driver timestamps are not sent over the air
+        let (rssi, d) = ReceiveQuality::decode(&buff[..n]).unwrap();
+        assert_eq!(rssi, -80);
+        assert_eq!(
+            d,
+            ReceiveQuality {
+                timestamp_us: None,
+                ..q.clone()
+            }
+        );
+
+        // RSSI-only responses remain compatible
+        assert_eq!(ReceiveQuality::default().encode(-80, &mut buff), Some(2));
+        assert_eq!(
+            ReceiveQuality::decode(&buff[..2]),
+            Some((-80, ReceiveQuality::default()))
+        );
+
+        let comment = format!("rssi=-80 {} info=Info {{ snr: 1 }}", q);
+        assert_eq!(
+            comment,
+            "rssi=-80 lqi=200 snr=-7.25 cfo=-1200 ts=123456 info=Info { snr: 1 }"
+        );
+        assert_eq!(ReceiveQuality::from_comment(&comment), q);
+    }
+}
@@ -0,0 +1,127 @@
+//! Rolling receive rate tracking for continuous captures
+
+use std::time::Duration;
+
+use crate::clock::{Clock, StdClock};
+
+/// Receive rates over a reporting interval
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateSample {
+    /// Packets received per second
+    pub packets_per_sec: f32,
+    /// Bytes received per second
+    pub bytes_per_sec: f32,
+    /// Fraction of receive attempts resulting in errors (`0.0..=1.0`)
+    pub error_rate: f32,
+}
+
+impl core::fmt::Display for RateSample {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:.1} pkt/s, {:.1} B/s, {:.1}% errors",
+            self.packets_per_sec,
+            self.bytes_per_sec,
+            self.error_rate * 100.0
+        )
+    }
+}
+
+/// Rate tracker, accumulating packets, bytes and errors over a fixed reporting interval
+#[derive(Clone, Debug)]
+pub struct RateTracker {
+    interval: Duration,
+    start_us: u64,
+    packets: u32,
+    bytes: usize,
+    errors: u32,
+}
+
+impl RateTracker {
+    /// Create a new rate tracker with the provided reporting interval
+    pub fn new(interval: Duration) -> Self {
+        Self::new_at(interval, StdClock.now_us())
+    }
+
+    /// Create a new rate tracker with the interval starting at the provided [`Clock`] time
+    pub fn new_at(interval: Duration, start_us: u64) -> Self {
+        Self {
+            interval,
+            start_us,
+            packets: 0,
+            bytes: 0,
+            errors: 0,
+        }
+    }
+
+    /// Record a received packet
+    pub fn packet(&mut self, len: usize) {
+        self.packets += 1;
+        self.bytes += len;
+    }
+
+    /// Record a receive error
+    pub fn error(&mut self) {
+        self.errors += 1;
+    }
+
+    /// Fetch rates if the reporting interval has elapsed, starting a new interval
+    pub fn poll(&mut self) -> Option<RateSample> {
+        self.poll_at(StdClock.now_us())
+    }
+
+    /// Fetch rates if the reporting interval has elapsed at the provided [`Clock`] time
+    pub fn poll_at(&mut self, now_us: u64) -> Option<RateSample> {
+        let elapsed = Duration::from_micros(now_us.saturating_sub(self.start_us));
+        if elapsed < self.interval {
+            return None;
+        }
+
+        let secs = elapsed.as_secs_f32();
+        let attempts = self.packets + self.errors;
+        let sample = RateSample {
+            packets_per_sec: self.packets as f32 / secs,
+            bytes_per_sec: self.bytes as f32 / secs,
+            error_rate: match attempts {
+                0 => 0.0,
+                n => self.errors as f32 / n as f32,
+            },
+        };
+
+        *self = Self::new_at(self.interval, now_us);
+
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    #[test]
+    fn rate_tracking() {
+        let clock = VirtualClock::new();
+        let mut r = RateTracker::new_at(Duration::from_secs(2), clock.now_us());
+
+        for _ in 0..9 {
+            r.packet(10);
+        }
+        r.error();
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(r.poll_at(clock.now_us()), None);
+
+        clock.advance(Duration::from_secs(1));
+        let s = r.poll_at(clock.now_us()).unwrap();
+        assert_eq!(s.packets_per_sec, 4.5);
+        assert_eq!(s.bytes_per_sec, 45.0);
+        assert_eq!(s.error_rate, 0.1);
+
+        // Counters reset for the next interval
+        clock.advance(Duration::from_secs(2));
+        let s = r.poll_at(clock.now_us()).unwrap();
+        assert_eq!(s.packets_per_sec, 0.0);
+        assert_eq!(s.error_rate, 0.0);
+    }
+}
@@ -0,0 +1,265 @@
+//! Raw sample capture operation, for offline demodulation and diagnostics
+//!
+//! Samples captured by [`RawSamples`] radios around receive events are written either as
+//! CSV (`capture,index,time_us,rssi,i,q`) or as interleaved little-endian 16-bit IQ pairs
+//! (`cs16`, as used by most SDR tooling, with RSSI samples written as `rssi,0`).
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use embedded_hal::delay::DelayNs;
+
+use crate::{
+    RawSamples, Receive, ReceiveInfo, Sample,
+    blocking::{BlockingError, BlockingOptions},
+};
+
+/// Raw sample output format
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum RawFormat {
+    /// CSV with one sample per line
+    Csv,
+    /// Interleaved little-endian 16-bit IQ pairs
+    Cs16,
+}
+
+/// Configuration for raw sample capture operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct CaptureRawOptions {
+    /// File to write captured samples to
+    pub file: String,
+
+    /// Number of receive events to capture
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1"))]
+    pub count: u32,
+
+    /// Maximum number of samples per capture
+    #[cfg_attr(feature = "clap", clap(long, default_value = "4096"))]
+    pub samples: usize,
+
+    /// Output format
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "csv"))]
+    pub format: RawFormat,
+
+    /// Also capture samples on receive errors (such as CRC failures)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub include_errors: bool,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Raw sample writer
+pub struct RawSampleWriter<W: Write> {
+    w: W,
+    format: RawFormat,
+}
+
+impl RawSampleWriter<BufWriter<File>> {
+    /// Create a new raw sample file
+    pub fn create(path: &str, format: RawFormat) -> Result<Self, std::io::Error> {
+        Self::new(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+impl<W: Write> RawSampleWriter<W> {
+    /// Create a new raw sample writer, writing the CSV header where required
+    pub fn new(mut w: W, format: RawFormat) -> Result<Self, std::io::Error> {
+        if format == RawFormat::Csv {
+            writeln!(w, "capture,index,time_us,rssi,i,q")?;
+        }
+        Ok(Self { w, format })
+    }
+
+    /// Write the samples from a capture
+    pub fn write(
+        &mut self,
+        capture: u32,
+        sample_rate_hz: u32,
+        samples: &[Sample],
+    ) -> Result<(), std::io::Error> {
+        for (n, s) in samples.iter().enumerate() {
+            match self.format {
+                RawFormat::Csv => {
+                    let t = match sample_rate_hz {
+                        0 => 0,
+                        r => n as u64 * 1_000_000 / r as u64,
+                    };
+                    match s {
+                        Sample::Rssi(r) => writeln!(self.w, "{},{},{},{},,", capture, n, t, r)?,
+                        Sample::Iq(i, q) => {
+                            writeln!(self.w, "{},{},{},,{},{}", capture, n, t, i, q)?
+                        }
+                    }
+                }
+                RawFormat::Cs16 => {
+                    let (i, q) = match s {
+                        Sample::Rssi(r) => (*r, 0),
+                        Sample::Iq(i, q) => (*i, *q),
+                    };
+                    self.w.write_all(&i.to_le_bytes())?;
+                    self.w.write_all(&q.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush buffered output
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.w.flush()
+    }
+}
+
+/// Capture raw samples around receive events, returning the number of captures written
+pub fn do_capture_raw<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: CaptureRawOptions,
+) -> Result<u32, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + RawSamples<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut w = RawSampleWriter::create(&options.file, options.format)
+        .expect("Error creating raw sample file");
+    let mut samples = vec![Sample::Rssi(0); options.samples];
+    let mut captures = 0;
+
+    radio.start_receive()?;
+
+    while captures < options.count {
+        let event = match radio.check_receive(false) {
+            Ok(true) => {
+                let (n, i) = radio.get_received(buff)?;
+                info!("Received {} bytes info: {:?}", n, i);
+                true
+            }
+            Ok(false) => false,
+            Err(e) if options.include_errors => {
+                info!("Receive error: {:?}", e);
+                true
+            }
+            Err(e) => {
+                debug!("Receive error: {:?}", e);
+                radio.start_receive()?;
+                false
+            }
+        };
+
+        if event {
+            let n = radio.get_samples(&mut samples)?;
+            info!("Captured {} samples", n);
+
+            w.write(captures, radio.sample_rate_hz(), &samples[..n])
+                .expect("Error writing raw samples");
+            captures += 1;
+
+            radio.start_receive()?;
+        }
+
+        radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+
+    w.flush().expect("Error writing raw samples");
+
+    Ok(captures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    struct SampleRadio {
+        events: Vec<Result<bool, ()>>,
+    }
+
+    impl Receive for SampleRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            self.events.pop().unwrap_or(Ok(false))
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Ok((0, BasicInfo::default()))
+        }
+    }
+
+    impl RawSamples for SampleRadio {
+        type Error = ();
+
+        fn sample_rate_hz(&self) -> u32 {
+            1_000
+        }
+
+        fn get_samples(&mut self, buff: &mut [Sample]) -> Result<usize, Self::Error> {
+            buff[0] = Sample::Iq(1, -1);
+            buff[1] = Sample::Rssi(-90);
+            Ok(2)
+        }
+    }
+
+    impl DelayNs for SampleRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn raw_sample_formats() {
+        let samples = [Sample::Iq(1, -2), Sample::Rssi(-80)];
+
+        let mut w = RawSampleWriter::new(vec![], RawFormat::Csv).unwrap();
+        w.write(0, 1_000, &samples).unwrap();
+        assert_eq!(
+            String::from_utf8(w.w).unwrap(),
+            "capture,index,time_us,rssi,i,q\n0,0,0,,1,-2\n0,1,1000,-80,,\n"
+        );
+
+        let mut w = RawSampleWriter::new(vec![], RawFormat::Cs16).unwrap();
+        w.write(0, 1_000, &samples).unwrap();
+        assert_eq!(w.w, vec![1, 0, 0xfe, 0xff, 0xb0, 0xff, 0, 0]);
+    }
+
+    #[test]
+    fn capture_raw_events() {
+        let path = std::env::temp_dir().join(format!("radio-raw-{}.csv", std::process::id()));
+        let options = CaptureRawOptions {
+            file: path.to_string_lossy().to_string(),
+            count: 2,
+            samples: 16,
+            format: RawFormat::Csv,
+            include_errors: true,
+            blocking_options: BlockingOptions::default(),
+        };
+
+        // Events are popped from the end
+        let mut radio = SampleRadio {
+            events: vec![Ok(true), Err(()), Ok(false)],
+        };
+        let mut buff = [0u8; 16];
+        assert_eq!(do_capture_raw(&mut radio, &mut buff, options), Ok(2));
+
+        let s = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(s.lines().count(), 5);
+        assert!(s.lines().nth(3).unwrap().starts_with("1,0,0,,1,-1"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+//! Dual-radio relay (cross-band repeater) operation
+//!
+//! [`do_relay`] receives frames on one radio and retransmits them on a second, typically
+//! on a different band or modulation, optionally in both directions. Each direction has
+//! its own RSSI and prefix filters and statistics, allowing 2.4 GHz and sub-GHz segments
+//! of a test network to be bridged.
+
+use std::time::Instant;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+
+#[cfg(feature = "clap")]
+use super::parse_hex;
+use super::squelched;
+use crate::{
+    Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Configuration for Relay operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct RelayOptions {
+    /// Label for the first radio
+    #[cfg_attr(feature = "clap", clap(long, default_value = "a"))]
+    pub name_a: String,
+
+    /// Label for the second radio
+    #[cfg_attr(feature = "clap", clap(long, default_value = "b"))]
+    pub name_b: String,
+
+    /// Also relay frames received on the second radio to the first
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub bidirectional: bool,
+
+    /// Only relay frames received on the first radio at or above this RSSI (dBm)
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub filter_rssi_a: Option<i16>,
+
+    /// Only relay frames received on the first radio starting with this hex prefix
+    #[cfg_attr(feature = "clap", clap(long, value_parser = parse_hex))]
+    pub filter_prefix_a: Option<Vec<u8>>,
+
+    /// Only relay frames received on the second radio at or above this RSSI (dBm)
+    #[cfg_attr(feature = "clap", clap(long, allow_hyphen_values = true))]
+    pub filter_rssi_b: Option<i16>,
+
+    /// Only relay frames received on the second radio starting with this hex prefix
+    #[cfg_attr(feature = "clap", clap(long, value_parser = parse_hex))]
+    pub filter_prefix_b: Option<Vec<u8>>,
+
+    /// Run for this duration (runs until interrupted if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub duration: Option<HumanDuration>,
+
+    /// Print relay statistics at this interval
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub stats_interval: Option<HumanDuration>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for RelayOptions {
+    fn default() -> Self {
+        Self {
+            name_a: "a".to_string(),
+            name_b: "b".to_string(),
+            bidirectional: false,
+            filter_rssi_a: None,
+            filter_prefix_a: None,
+            filter_rssi_b: None,
+            filter_prefix_b: None,
+            duration: None,
+            stats_interval: None,
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+/// Relay statistics for a single direction
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RelayDirectionStats {
+    /// Frames received
+    pub received: u64,
+    /// Frames dropped by filters
+    pub filtered: u64,
+    /// Frames retransmitted
+    pub relayed: u64,
+    /// Retransmissions timing out
+    pub timeouts: u64,
+}
+
+impl core::fmt::Display for RelayDirectionStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "relayed {}/{} ({} filtered, {} timeouts)",
+            self.relayed, self.received, self.filtered, self.timeouts
+        )
+    }
+}
+
+/// Relay statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RelayStats {
+    /// Frames received on the first radio, relayed to the second
+    pub a_to_b: RelayDirectionStats,
+    /// Frames received on the second radio, relayed to the first
+    pub b_to_a: RelayDirectionStats,
+}
+
+/// Error running a relay, identifying the radio that failed
+#[derive(Clone, Debug, PartialEq)]
+pub enum RelayError<A, B> {
+    /// Error from the first radio
+    A(BlockingError<A>),
+    /// Error from the second radio
+    B(BlockingError<B>),
+}
+
+/// Check whether a received frame passes a relay direction's filters
+fn relay_filter(rssi: Option<i16>, prefix: &Option<Vec<u8>>, frame_rssi: i16, data: &[u8]) -> bool {
+    if squelched(rssi, frame_rssi, data.len()) {
+        return false;
+    }
+
+    match prefix {
+        Some(p) => data.starts_with(p),
+        None => true,
+    }
+}
+
+/// Relay a frame received on one radio (if any) by retransmitting it on the other,
+/// recording statistics and returning whether a frame was received
+fn relay_once<R, IR, ER, T, ET, X>(
+    rx: &mut R,
+    tx: &mut T,
+    filter: (Option<i16>, &Option<Vec<u8>>),
+    blocking_options: &BlockingOptions,
+    stats: &mut RelayDirectionStats,
+    rx_err: impl Fn(BlockingError<ER>) -> X,
+    tx_err: impl Fn(BlockingError<ET>) -> X,
+) -> Result<bool, X>
+where
+    R: Receive<Info = IR, Error = ER>,
+    IR: ReceiveInfo,
+    ER: std::fmt::Debug,
+    T: Transmit<Error = ET> + DelayNs,
+    ET: std::fmt::Debug,
+{
+    if !rx.check_receive(true).map_err(|e| rx_err(e.into()))? {
+        return Ok(false);
+    }
+    let mut buff = [0u8; 1024];
+    let (n, i) = rx.get_received(&mut buff).map_err(|e| rx_err(e.into()))?;
+    stats.received += 1;
+
+    if !relay_filter(filter.0, filter.1, i.rssi(), &buff[..n]) {
+        debug!("Filtered {} byte frame with rssi: {}", n, i.rssi());
+        stats.filtered += 1;
+        return Ok(true);
+    }
+
+    match tx.do_transmit(&buff[..n], blocking_options.clone()) {
+        Ok(_) => stats.relayed += 1,
+        Err(BlockingError::Timeout) => {
+            warn!("Relay transmit timeout ({} bytes)", n);
+            stats.timeouts += 1;
+        }
+        Err(e) => return Err(tx_err(e)),
+    }
+
+    Ok(true)
+}
+
+/// Relay frames between two radios, returning statistics for each direction
+///
+/// Frames received on the first radio are retransmitted on the second, and where
+/// `--bidirectional` is set, frames received on the second are retransmitted on the first.
+pub fn do_relay<A, IA, EA, B, IB, EB>(
+    radio_a: &mut A,
+    radio_b: &mut B,
+    options: RelayOptions,
+) -> Result<RelayStats, RelayError<EA, EB>>
+where
+    A: Receive<Info = IA, Error = EA> + Transmit<Error = EA> + DelayNs,
+    IA: ReceiveInfo,
+    EA: std::fmt::Debug,
+    B: Receive<Info = IB, Error = EB> + Transmit<Error = EB> + DelayNs,
+    IB: ReceiveInfo,
+    EB: std::fmt::Debug,
+{
+    let (a, b) = (&options.name_a, &options.name_b);
+    let ea = |e: EA| RelayError::A(BlockingError::Inner(e));
+    let eb = |e: EB| RelayError::B(BlockingError::Inner(e));
+
+    let mut stats = RelayStats::default();
+    let start = Instant::now();
+    let mut last_stats = start;
+
+    radio_a.start_receive().map_err(ea)?;
+    if options.bidirectional {
+        radio_b.start_receive().map_err(eb)?;
+    }
+
+    info!(
+        "Relaying {} -> {}{}",
+        a,
+        b,
+        if options.bidirectional {
+            " (bidirectional)"
+        } else {
+            ""
+        }
+    );
+
+    loop {
+        if let Some(d) = options.duration
+            && start.elapsed() >= *d
+        {
+            break;
+        }
+
+        // Relay from the first radio to the second
+        let filter = (options.filter_rssi_a, &options.filter_prefix_a);
+        let relayed = relay_once(
+            radio_a,
+            radio_b,
+            filter,
+            &options.blocking_options,
+            &mut stats.a_to_b,
+            RelayError::A,
+            RelayError::B,
+        )?;
+        if relayed {
+            radio_a.start_receive().map_err(ea)?;
+            if options.bidirectional {
+                radio_b.start_receive().map_err(eb)?;
+            }
+        }
+
+        // And from the second to the first where enabled
+        if options.bidirectional {
+            let filter = (options.filter_rssi_b, &options.filter_prefix_b);
+            let relayed = relay_once(
+                radio_b,
+                radio_a,
+                filter,
+                &options.blocking_options,
+                &mut stats.b_to_a,
+                RelayError::B,
+                RelayError::A,
+            )?;
+            if relayed {
+                radio_b.start_receive().map_err(eb)?;
+                radio_a.start_receive().map_err(ea)?;
+            }
+        }
+
+        if let Some(i) = options.stats_interval
+            && last_stats.elapsed() >= *i
+        {
+            info!("Relay {} -> {}: {}", a, b, stats.a_to_b);
+            if options.bidirectional {
+                info!("Relay {} -> {}: {}", b, a, stats.b_to_a);
+            }
+            last_stats = Instant::now();
+        }
+
+        radio_a.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+
+    info!("Relay complete, {} -> {}: {}", a, b, stats.a_to_b);
+    if options.bidirectional {
+        info!("Relay complete, {} -> {}: {}", b, a, stats.b_to_a);
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio receiving queued frames and recording transmissions
+    #[derive(Default)]
+    struct RelayRadio {
+        rx: VecDeque<(Vec<u8>, i16)>,
+        tx: Vec<Vec<u8>>,
+    }
+
+    impl Transmit for RelayRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.tx.push(data.to_vec());
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for RelayRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(!self.rx.is_empty())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (d, rssi) = self.rx.pop_front().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(rssi, 0)))
+        }
+    }
+
+    impl DelayNs for RelayRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn cross_band_relay() {
+        let mut a = RelayRadio::default();
+        a.rx.extend([
+            (vec![0xaa, 0x01], -60),
+            (vec![0xbb, 0x02], -60),
+            (vec![0xaa, 0x03], -100),
+        ]);
+        let mut b = RelayRadio::default();
+        b.rx.extend([(vec![0x01], -70), (vec![0x02], -70)]);
+
+        let options = RelayOptions {
+            bidirectional: true,
+            filter_rssi_a: Some(-90),
+            filter_prefix_a: Some(vec![0xaa]),
+            duration: Some(std::time::Duration::from_millis(10).into()),
+            ..Default::default()
+        };
+        let stats = do_relay(&mut a, &mut b, options).unwrap();
+
+        assert_eq!(b.tx, vec![vec![0xaa, 0x01]]);
+        assert_eq!(a.tx, vec![vec![0x01], vec![0x02]]);
+        assert_eq!(
+            stats.a_to_b,
+            RelayDirectionStats {
+                received: 3,
+                filtered: 2,
+                relayed: 1,
+                timeouts: 0,
+            }
+        );
+        assert_eq!(stats.b_to_a.relayed, 2);
+    }
+}
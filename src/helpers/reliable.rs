@@ -0,0 +1,205 @@
+//! Reliable transmit and echo operations
+//!
+//! With `--reliable`, `tx` sends each payload through a [`ReliableLink`], retransmitting
+//! until acknowledged, while `echo` acts as the acknowledging peer. Link statistics
+//! (including retransmission counts) are reported on completion.
+//!
+//! With `--queue-store`, frames are persisted until acknowledged, with frames left
+//! unacknowledged by a previous run resent before the configured payloads.
+//!
+//! With `--keepalive`, idle links are probed by the transmitter between payloads, with
+//! lost peers reported and the session re-established once they return. Echoing peers
+//! answer probes but do not probe themselves, as transmitters only listen for responses
+//! to their own frames.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+use embedded_hal::delay::DelayNs;
+
+use super::{EchoOptions, OperationError, TransmitOptions};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    arq::{ArqStats, LinkState, ReliableLink},
+    blocking::BlockingError,
+    clock::{Clock, StdClock},
+};
+
+/// Transmit using the provided configuration, retransmitting each payload until
+/// acknowledged or the configured retries are exhausted
+pub fn do_transmit_reliable<T, I, E>(
+    radio: &mut T,
+    options: TransmitOptions,
+) -> Result<ArqStats, OperationError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    do_transmit_reliable_clocked(radio, options, &StdClock)
+}
+
+/// Transmit reliably, timing keepalive probes with the provided clock
+pub fn do_transmit_reliable_clocked<T, I, E, C>(
+    radio: &mut T,
+    options: TransmitOptions,
+    clock: &C,
+) -> Result<ArqStats, OperationError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+    C: Clock,
+{
+    let mut source = options.open_source().expect("Error opening packet source");
+
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut store = options.persist_options.open().map_err(OperationError::Io)?;
+
+    let mut link: ReliableLink<_> = ReliableLink::new(
+        &mut *radio,
+        options.arq_options.clone(),
+        options.blocking_options.clone(),
+    );
+
+    if let Some(s) = &mut store {
+        s.replay_arq(&mut link)?;
+    }
+
+    while let Some(data) = source.next_payload().expect("Error reading packet source") {
+        // Delay between transmissions
+        if link.stats().sent > 0
+            && let Some(p) = &options.period
+        {
+            link.inner().delay_us(p.as_micros() as u32);
+        }
+
+        if link.keepalive(clock)? == LinkState::Down {
+            debug!("Peer down, attempting to send frame {}", link.stats().sent);
+        }
+
+        let acked = match &mut store {
+            Some(s) => s.send(&mut link, &data)?,
+            None => link.send(&data)?,
+        };
+        if !acked {
+            debug!("Frame {} unacknowledged", link.stats().sent);
+        }
+    }
+
+    let stats = link.stats().clone();
+    info!("Reliable transmit: {}", stats);
+
+    Ok(stats)
+}
+
+/// Receive and acknowledge frames from a reliable transmitter, once or continuously
+pub fn do_echo_reliable<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: EchoOptions,
+) -> Result<ArqStats, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: core::fmt::Debug,
+{
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut link: ReliableLink<_> = ReliableLink::new(
+        &mut *radio,
+        options.arq_options.clone(),
+        options.blocking_options.clone(),
+    );
+
+    loop {
+        // Exit cleanly on shutdown, returning the link statistics
+        if options.shutdown.is_triggered() {
+            break;
+        }
+
+        match link.receive(buff) {
+            Ok((n, i)) => {
+                debug!("Received: {:02x?} info: {:?}", &buff[..n], i);
+
+                if !options.continuous {
+                    break;
+                }
+            }
+            Err(BlockingError::Timeout) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let stats = link.stats().clone();
+    info!("Reliable echo: {}", stats);
+
+    Ok(stats)
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        arq::ArqOptions,
+        blocking::BlockingOptions,
+        mock::{MediumOptions, SharedMedium},
+    };
+
+    #[test]
+    fn reliable_keepalive_link() {
+        let (mut tx, mut rx) = SharedMedium::pair(MediumOptions::default());
+        let arq_options = ArqOptions {
+            reliable: true,
+            keepalive: Some(Duration::from_millis(5)),
+            ..Default::default()
+        };
+        let blocking_options = BlockingOptions {
+            timeout: Duration::from_millis(20),
+            ..Default::default()
+        };
+
+        let echo = EchoOptions {
+            continuous: true,
+            arq_options: arq_options.clone(),
+            blocking_options: blocking_options.clone(),
+            ..Default::default()
+        };
+        let shutdown = echo.shutdown.clone();
+        let peer = std::thread::spawn(move || do_echo_reliable(&mut rx, &mut [0u8; 64], echo));
+
+        // Gaps between payloads exceed the keepalive interval and the echoing peer's
+        // receive timeout
+        let options = TransmitOptions {
+            source: Some("gen:8:5".parse().unwrap()),
+            period: Some(Duration::from_millis(30).into()),
+            arq_options,
+            blocking_options,
+            ..Default::default()
+        };
+        let stats = do_transmit_reliable(&mut tx, options).unwrap();
+
+        // The echoing peer stays up while the transmitter is idle, until shut down
+        std::thread::sleep(Duration::from_millis(100));
+        shutdown.trigger();
+        let echoed = peer.join().unwrap().unwrap();
+
+        assert_eq!((stats.sent, stats.acked, stats.disconnects), (5, 5, 0));
+        assert_eq!(
+            (echoed.received, echoed.keepalives, echoed.disconnects),
+            (5, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,642 @@
+//! Remote radio server and client over TCP or Unix sockets
+//!
+//! The `serve-socket` operation wraps a local radio (eg. one attached to a Raspberry Pi)
+//! and serves it to a single client at a time over a TCP or Unix stream socket, with
+//! [`SocketRadio`] implementing [`Transmit`], [`Receive`], [`Power`] and [`Rssi`] over the
+//! connection so test code can drive the radio from another host. Unlike the gRPC
+//! `RemoteRadio` (with the `grpc` feature) this requires no async runtime or code
+//! generation.
+//!
+//! Messages are length-prefixed, encoded as `[len (BE u32), kind, body..]` (see
+//! [`SocketMessage`]). Clients send transmit, power and RSSI requests, each answered with
+//! a response, while the server continuously receives, forwarding each received frame to
+//! the client as an unsolicited receive event.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(target_family = "unix")]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+
+use super::Shutdown;
+use crate::{
+    BasicInfo, Power, Receive, ReceiveInfo, Rssi, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Maximum encoded message length, bounding allocations for corrupt length prefixes
+const MAX_MESSAGE: usize = 4096;
+
+/// Configuration for socket server operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct SocketServeOptions {
+    /// Address to listen for TCP connections
+    #[cfg_attr(feature = "clap", clap(long, required_unless_present = "unix"))]
+    pub listen: Option<SocketAddr>,
+
+    /// Unix socket path to listen for connections
+    #[cfg_attr(feature = "clap", clap(long, conflicts_with = "listen"))]
+    pub unix: Option<PathBuf>,
+
+    /// Shutdown signal, stopping the server cleanly once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Socket protocol message
+#[derive(Clone, Debug, PartialEq)]
+pub enum SocketMessage {
+    /// Transmit request (kind `0x01`), with the frame to transmit
+    Transmit(Vec<u8>),
+    /// RSSI query (kind `0x02`)
+    Rssi,
+    /// Transmit power request (kind `0x03`), in dBm
+    SetPower(i8),
+    /// Request completed (kind `0x81`)
+    Ok,
+    /// RSSI response (kind `0x82`), in dBm
+    RssiValue(i16),
+    /// Request failed (kind `0xff`), with the radio error
+    Error(String),
+    /// Received frame event (kind `0x90`), encoded as `[rssi (BE i16), data..]`
+    Received { rssi: i16, data: Vec<u8> },
+}
+
+impl SocketMessage {
+    /// Encode the message, including the length prefix
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, body) = match self {
+            SocketMessage::Transmit(d) => (0x01, d.clone()),
+            SocketMessage::Rssi => (0x02, vec![]),
+            SocketMessage::SetPower(p) => (0x03, p.to_be_bytes().to_vec()),
+            SocketMessage::Ok => (0x81, vec![]),
+            SocketMessage::RssiValue(r) => (0x82, r.to_be_bytes().to_vec()),
+            SocketMessage::Error(e) => (0xff, e.as_bytes().to_vec()),
+            SocketMessage::Received { rssi, data } => {
+                (0x90, [&rssi.to_be_bytes()[..], data].concat())
+            }
+        };
+
+        let mut buff = ((body.len() + 1) as u32).to_be_bytes().to_vec();
+        buff.push(kind);
+        buff.extend_from_slice(&body);
+        buff
+    }
+
+    /// Decode a message from the start of the provided data, returning the message and
+    /// the number of bytes consumed, or `None` where the message is incomplete
+    pub fn decode(data: &[u8]) -> Result<Option<(Self, usize)>, std::io::Error> {
+        let invalid = |m| std::io::Error::new(ErrorKind::InvalidData, m);
+
+        let len = match data.get(..4) {
+            Some(l) => u32::from_be_bytes([l[0], l[1], l[2], l[3]]) as usize,
+            None => return Ok(None),
+        };
+        if len == 0 || len > MAX_MESSAGE {
+            return Err(invalid("invalid message length"));
+        }
+        let (kind, body) = match data.get(4..4 + len) {
+            Some(m) => (m[0], &m[1..]),
+            None => return Ok(None),
+        };
+
+        let m = match (kind, body) {
+            (0x01, d) => SocketMessage::Transmit(d.to_vec()),
+            (0x02, []) => SocketMessage::Rssi,
+            (0x03, [p]) => SocketMessage::SetPower(*p as i8),
+            (0x81, []) => SocketMessage::Ok,
+            (0x82, [a, b]) => SocketMessage::RssiValue(i16::from_be_bytes([*a, *b])),
+            (0xff, e) => SocketMessage::Error(String::from_utf8_lossy(e).to_string()),
+            (0x90, [a, b, d @ ..]) => SocketMessage::Received {
+                rssi: i16::from_be_bytes([*a, *b]),
+                data: d.to_vec(),
+            },
+            _ => return Err(invalid("invalid message")),
+        };
+
+        Ok(Some((m, 4 + len)))
+    }
+}
+
+/// TCP or Unix stream connection
+#[derive(Debug)]
+pub enum SocketStream {
+    Tcp(TcpStream),
+    #[cfg(target_family = "unix")]
+    Unix(UnixStream),
+}
+
+impl SocketStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), std::io::Error> {
+        match self {
+            SocketStream::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(target_family = "unix")]
+            SocketStream::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for SocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            SocketStream::Tcp(s) => s.read(buf),
+            #[cfg(target_family = "unix")]
+            SocketStream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for SocketStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            SocketStream::Tcp(s) => s.write(buf),
+            #[cfg(target_family = "unix")]
+            SocketStream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            SocketStream::Tcp(s) => s.flush(),
+            #[cfg(target_family = "unix")]
+            SocketStream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Message connection over a non-blocking stream, buffering partial messages
+#[derive(Debug)]
+struct Connection {
+    stream: SocketStream,
+    buff: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: SocketStream) -> Result<Self, std::io::Error> {
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            buff: vec![],
+        })
+    }
+
+    /// Write a message, blocking until sent
+    fn send(&mut self, m: &SocketMessage) -> Result<(), std::io::Error> {
+        self.stream.set_nonblocking(false)?;
+        let r = self.stream.write_all(&m.encode());
+        self.stream.set_nonblocking(true)?;
+        r
+    }
+
+    /// Read the next available message, `None` where no complete message is available
+    fn poll(&mut self) -> Result<Option<SocketMessage>, std::io::Error> {
+        let mut chunk = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(n) => self.buff.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        match SocketMessage::decode(&self.buff)? {
+            Some((m, n)) => {
+                self.buff.drain(..n);
+                Ok(Some(m))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Listening TCP or Unix socket
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(target_family = "unix")]
+    Unix(UnixListener),
+}
+
+/// Remote radio server, serving a single client connection at a time
+pub struct SocketServer {
+    listener: Listener,
+    client: Option<Connection>,
+    blocking_options: BlockingOptions,
+}
+
+impl SocketServer {
+    /// Bind the configured TCP address or Unix socket
+    pub fn bind(options: &SocketServeOptions) -> Result<Self, std::io::Error> {
+        let listener = match (&options.listen, &options.unix) {
+            (Some(addr), _) => {
+                let l = TcpListener::bind(addr)?;
+                l.set_nonblocking(true)?;
+                Listener::Tcp(l)
+            }
+            #[cfg(target_family = "unix")]
+            (None, Some(path)) => {
+                // Remove any stale socket from a previous server
+                let _ = std::fs::remove_file(path);
+                let l = UnixListener::bind(path)?;
+                l.set_nonblocking(true)?;
+                Listener::Unix(l)
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "no listen address or unix socket",
+                ));
+            }
+        };
+
+        Ok(Self {
+            listener,
+            client: None,
+            blocking_options: options.blocking_options.clone(),
+        })
+    }
+
+    /// Local TCP address, `None` for Unix sockets
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.listener {
+            Listener::Tcp(l) => l.local_addr().ok(),
+            #[cfg(target_family = "unix")]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    /// Accept a pending connection where no client is connected
+    fn accept(&mut self) -> Result<(), std::io::Error> {
+        let stream = match &self.listener {
+            Listener::Tcp(l) => l.accept().map(|(s, a)| {
+                info!("Socket client connected from {}", a);
+                SocketStream::Tcp(s)
+            }),
+            #[cfg(target_family = "unix")]
+            Listener::Unix(l) => l.accept().map(|(s, _)| {
+                info!("Socket client connected");
+                SocketStream::Unix(s)
+            }),
+        };
+
+        match stream {
+            Ok(s) => self.client = Some(Connection::new(s)?),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+
+    /// Handle a client request
+    fn handle<T, E>(&self, radio: &mut T, m: SocketMessage) -> Result<SocketMessage, E>
+    where
+        T: Transmit<Error = E> + Receive<Error = E> + Power<Error = E> + Rssi<Error = E> + DelayNs,
+        E: core::fmt::Debug,
+    {
+        let r = match m {
+            SocketMessage::Transmit(d) => {
+                match radio.do_transmit(&d, self.blocking_options.clone()) {
+                    Ok(_) => SocketMessage::Ok,
+                    Err(BlockingError::Timeout) => SocketMessage::Error("Timeout".to_string()),
+                    Err(BlockingError::Inner(e)) => SocketMessage::Error(format!("{:?}", e)),
+                    Err(e) => SocketMessage::Error(format!("{:?}", e)),
+                }
+            }
+            SocketMessage::Rssi => match radio.poll_rssi() {
+                Ok(r) => SocketMessage::RssiValue(r),
+                Err(e) => SocketMessage::Error(format!("{:?}", e)),
+            },
+            SocketMessage::SetPower(p) => match radio.set_power(p) {
+                Ok(_) => SocketMessage::Ok,
+                Err(e) => SocketMessage::Error(format!("{:?}", e)),
+            },
+            m => SocketMessage::Error(format!("unexpected request: {:?}", m)),
+        };
+
+        // Resume receiving following transmission
+        radio.start_receive()?;
+
+        Ok(r)
+    }
+
+    /// Service the server, accepting connections, handling requests and forwarding
+    /// received frames to the connected client
+    pub fn poll<T, I, E>(&mut self, radio: &mut T, buff: &mut [u8]) -> Result<(), E>
+    where
+        T: Transmit<Error = E>
+            + Receive<Info = I, Error = E>
+            + Power<Error = E>
+            + Rssi<Error = E>
+            + DelayNs,
+        I: ReceiveInfo + std::fmt::Debug,
+        E: core::fmt::Debug,
+    {
+        if self.client.is_none()
+            && let Err(e) = self.accept()
+        {
+            debug!("Socket accept error: {:?}", e);
+        }
+
+        let mut outgoing = vec![];
+
+        if let Some(c) = &mut self.client {
+            match c.poll() {
+                Ok(Some(m)) => {
+                    debug!("Socket request: {:?}", m);
+                    outgoing.push(m);
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    info!("Socket client disconnected: {:?}", e);
+                    self.client = None;
+                }
+            }
+        }
+
+        let mut responses: Vec<_> = outgoing
+            .into_iter()
+            .map(|m| self.handle(radio, m))
+            .collect::<Result<_, _>>()?;
+
+        // Forward received frames, dropped where no client is connected
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(buff)?;
+            responses.push(SocketMessage::Received {
+                rssi: i.rssi(),
+                data: buff[..n].to_vec(),
+            });
+            radio.start_receive()?;
+        }
+
+        if let Some(c) = &mut self.client {
+            for r in &responses {
+                if let Err(e) = c.send(r) {
+                    info!("Socket client disconnected: {:?}", e);
+                    self.client = None;
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Serve a local radio over a TCP or Unix socket until shutdown
+pub fn do_socket_serve<T, I, E>(
+    radio: &mut T,
+    options: SocketServeOptions,
+) -> Result<(), BlockingError<E>>
+where
+    T: Transmit<Error = E>
+        + Receive<Info = I, Error = E>
+        + Power<Error = E>
+        + Rssi<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut server = SocketServer::bind(&options).expect("Error binding socket listener");
+
+    match (&options.listen, &options.unix) {
+        (Some(a), _) => info!("Serving radio on tcp://{}", a),
+        (_, Some(p)) => info!("Serving radio on unix://{}", p.display()),
+        _ => (),
+    }
+
+    let mut buff = [0u8; 1024];
+    radio.start_receive()?;
+
+    while !options.shutdown.is_triggered() {
+        server.poll(radio, &mut buff)?;
+        radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+
+    Ok(())
+}
+
+/// Radio implemented over a connection to a `serve-socket` server
+#[derive(Debug)]
+pub struct SocketRadio {
+    conn: Connection,
+    received: VecDeque<(i16, Vec<u8>)>,
+    timeout: Duration,
+}
+
+impl SocketRadio {
+    /// Connect to a server over TCP
+    pub fn connect_tcp(addr: SocketAddr) -> Result<Self, std::io::Error> {
+        let s = TcpStream::connect(addr)?;
+        s.set_nodelay(true)?;
+        Self::new(SocketStream::Tcp(s))
+    }
+
+    /// Connect to a server over a Unix socket
+    #[cfg(target_family = "unix")]
+    pub fn connect_unix(path: &std::path::Path) -> Result<Self, std::io::Error> {
+        Self::new(SocketStream::Unix(UnixStream::connect(path)?))
+    }
+
+    fn new(stream: SocketStream) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            conn: Connection::new(stream)?,
+            received: VecDeque::new(),
+            timeout: Duration::from_secs(5),
+        })
+    }
+
+    /// Set the time to wait for responses to requests
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Read available messages, buffering received frames and returning any response
+    fn poll(&mut self) -> Result<Option<SocketMessage>, std::io::Error> {
+        while let Some(m) = self.conn.poll()? {
+            match m {
+                SocketMessage::Received { rssi, data } => self.received.push_back((rssi, data)),
+                m => return Ok(Some(m)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Send a request and wait for the response
+    fn request(&mut self, m: &SocketMessage) -> Result<SocketMessage, std::io::Error> {
+        self.conn.send(m)?;
+
+        let start = Instant::now();
+        loop {
+            match self.poll()? {
+                Some(SocketMessage::Error(e)) => return Err(std::io::Error::other(e)),
+                Some(r) => return Ok(r),
+                None if start.elapsed() >= self.timeout => return Err(ErrorKind::TimedOut.into()),
+                None => std::thread::sleep(Duration::from_micros(100)),
+            }
+        }
+    }
+}
+
+fn unexpected(m: SocketMessage) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("unexpected response: {:?}", m),
+    )
+}
+
+impl Transmit for SocketRadio {
+    type Error = std::io::Error;
+
+    /// Transmit via the server, which completes the transmission before responding
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        match self.request(&SocketMessage::Transmit(data.to_vec()))? {
+            SocketMessage::Ok => Ok(()),
+            m => Err(unexpected(m)),
+        }
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+impl Receive for SocketRadio {
+    type Error = std::io::Error;
+    type Info = BasicInfo;
+
+    /// The server receives continuously, so this is a no-op
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+        if let Some(m) = self.poll()? {
+            return Err(unexpected(m));
+        }
+        Ok(!self.received.is_empty())
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let (rssi, data) = self
+            .received
+            .pop_front()
+            .ok_or_else(|| std::io::Error::from(ErrorKind::WouldBlock))?;
+
+        let n = data.len().min(buff.len());
+        buff[..n].copy_from_slice(&data[..n]);
+
+        Ok((n, BasicInfo::new(rssi, 0)))
+    }
+}
+
+impl Power for SocketRadio {
+    type Error = std::io::Error;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        match self.request(&SocketMessage::SetPower(power))? {
+            SocketMessage::Ok => Ok(()),
+            m => Err(unexpected(m)),
+        }
+    }
+}
+
+impl Rssi for SocketRadio {
+    type Error = std::io::Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        match self.request(&SocketMessage::Rssi)? {
+            SocketMessage::RssiValue(r) => Ok(r),
+            m => Err(unexpected(m)),
+        }
+    }
+}
+
+impl DelayNs for SocketRadio {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(ns as u64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::BlockingReceive;
+    use crate::helpers::UdpRadio;
+
+    #[test]
+    fn socket_radio() {
+        let m = SocketMessage::Received {
+            rssi: -60,
+            data: vec![1, 2, 3],
+        };
+        let e = m.encode();
+        assert_eq!(SocketMessage::decode(&e[..5]).unwrap(), None);
+        assert_eq!(SocketMessage::decode(&e).unwrap(), Some((m, e.len())));
+
+        let any = "127.0.0.1:0".parse().unwrap();
+        let mut peer = UdpRadio::new(any, any).unwrap();
+        let mut local = UdpRadio::new(any, peer.local_addr().unwrap()).unwrap();
+        local.set_rssi(-55);
+        peer.set_peer(local.local_addr().unwrap());
+
+        let options = SocketServeOptions {
+            listen: Some(any),
+            unix: None,
+            shutdown: Shutdown::default(),
+            blocking_options: BlockingOptions::default(),
+        };
+        let mut server = SocketServer::bind(&options).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let shutdown = options.shutdown.clone();
+        let t = std::thread::spawn(move || {
+            let mut buff = [0u8; 256];
+            local.start_receive().unwrap();
+            while !shutdown.is_triggered() {
+                server.poll(&mut local, &mut buff).unwrap();
+                std::thread::sleep(Duration::from_micros(100));
+            }
+            local
+        });
+
+        let mut remote = SocketRadio::connect_tcp(addr).unwrap();
+        let opts = BlockingOptions {
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        // Transmit via the remote radio
+        remote.do_transmit(&[0xaa, 0xbb], opts.clone()).unwrap();
+        let mut buff = [0u8; 16];
+        let (n, _) = peer.do_receive(&mut buff, opts.clone()).unwrap();
+        assert_eq!(&buff[..n], &[0xaa, 0xbb]);
+
+        // Receive frames forwarded by the server
+        peer.do_transmit(&[0x01, 0x02, 0x03], opts.clone()).unwrap();
+        let (n, i) = remote.do_receive(&mut buff, opts).unwrap();
+        assert_eq!((&buff[..n], i.rssi()), (&[0x01, 0x02, 0x03][..], -55));
+
+        assert_eq!(remote.poll_rssi().unwrap(), -55);
+        remote.set_power(12).unwrap();
+
+        options.shutdown.trigger();
+        assert_eq!(t.join().unwrap().power(), 12);
+    }
+}
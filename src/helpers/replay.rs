@@ -0,0 +1,372 @@
+//! Replay of captured traffic from PCAP and PCAP-NG files
+//!
+//! Captures (as written with `--pcap-file`, or by other tools) are read with a
+//! [`CaptureReader`], detecting the format from the file magic, and retransmitted by the
+//! `replay` operation either back-to-back, with the original inter-packet timing
+//! (`--original-timing`) or with a fixed `--period`, allowing regression testing against
+//! real-world traffic without the original devices.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use pcap_file::{
+    pcap::PcapReader,
+    pcapng::{
+        Block, PcapNgReader,
+        blocks::{
+            enhanced_packet::EnhancedPacketOption,
+            interface_description::InterfaceDescriptionOption,
+        },
+    },
+};
+
+use super::{PacketSource, Progress, ProgressOptions};
+use crate::{
+    Power, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// PCAP-NG section header block type, as found at the start of PCAP-NG files
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+/// Packet read from a capture
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedPacket {
+    /// Capture timestamp, `None` for PCAP-NG simple packets
+    pub timestamp: Option<Duration>,
+    /// Packet data
+    pub data: Vec<u8>,
+    /// Packet comment (PCAP-NG only, holding receive metadata for captures written by
+    /// [`PcapNgSink`](super::PcapNgSink))
+    pub comment: Option<String>,
+}
+
+/// Reader for PCAP and PCAP-NG captures
+pub enum CaptureReader<R: BufRead> {
+    Pcap(PcapReader<R>),
+    PcapNg(PcapNgReader<R>),
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Open a capture file
+    pub fn open(path: &str) -> Result<Self, std::io::Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    /// Create a capture reader, detecting the capture format and reading headers
+    pub fn new(mut r: R) -> Result<Self, std::io::Error> {
+        let pcapng = r.fill_buf()?.starts_with(&PCAPNG_MAGIC);
+
+        match pcapng {
+            true => PcapNgReader::new(r).map(CaptureReader::PcapNg),
+            false => PcapReader::new(r).map(CaptureReader::Pcap),
+        }
+        .map_err(std::io::Error::other)
+    }
+
+    /// Read the next packet, returning `None` at the end of the capture
+    pub fn next_packet(&mut self) -> Result<Option<CapturedPacket>, std::io::Error> {
+        let r = match self {
+            CaptureReader::Pcap(r) => {
+                return r
+                    .next_packet()
+                    .transpose()
+                    .map(|p| {
+                        p.map(|p| CapturedPacket {
+                            timestamp: Some(p.timestamp),
+                            data: p.data.into_owned(),
+                            comment: None,
+                        })
+                    })
+                    .map_err(std::io::Error::other);
+            }
+            CaptureReader::PcapNg(r) => r,
+        };
+
+        // Skip non-packet blocks (interface descriptions, statistics etc.)
+        loop {
+            let (interface, raw, data, comment) = match r.next_block().transpose() {
+                Ok(Some(Block::EnhancedPacket(p))) => {
+                    let comment = p.options.iter().find_map(|o| match o {
+                        EnhancedPacketOption::Comment(c) => Some(c.to_string()),
+                        _ => None,
+                    });
+                    (p.interface_id, p.timestamp, p.data.into_owned(), comment)
+                }
+                Ok(Some(Block::SimplePacket(p))) => {
+                    return Ok(Some(CapturedPacket {
+                        timestamp: None,
+                        data: p.data.into_owned(),
+                        comment: None,
+                    }));
+                }
+                Ok(Some(_)) => continue,
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(std::io::Error::other(e)),
+            };
+
+            // Timestamps are read as nanoseconds, scale per the interface resolution
+            let tsresol = r
+                .interfaces()
+                .get(interface as usize)
+                .and_then(|i| {
+                    i.options.iter().find_map(|o| match o {
+                        InterfaceDescriptionOption::IfTsResol(r) => Some(*r),
+                        _ => None,
+                    })
+                })
+                .unwrap_or(6);
+
+            return Ok(Some(CapturedPacket {
+                timestamp: Some(scale_timestamp(raw, tsresol)),
+                data,
+                comment,
+            }));
+        }
+    }
+}
+
+impl<R: BufRead> PacketSource for CaptureReader<R> {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        self.next_packet().map(|p| p.map(|p| p.data))
+    }
+}
+
+/// Convert a raw PCAP-NG timestamp (read in nanoseconds) to the interface resolution
+/// (`if_tsresol`, a negative power of 10, or of 2 where the high bit is set)
+fn scale_timestamp(raw: Duration, tsresol: u8) -> Duration {
+    let ticks = raw.as_nanos();
+
+    let nanos = match tsresol {
+        r if r & 0x80 != 0 => (ticks * 1_000_000_000) >> (r & 0x7f),
+        r if r <= 9 => ticks * 10u128.pow(9 - r as u32),
+        r => ticks / 10u128.pow((r.min(38) - 9) as u32),
+    };
+
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+/// Configuration for Replay operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ReplayOptions {
+    /// PCAP or PCAP-NG capture file to transmit packets from
+    pub file: String,
+
+    /// Preserve the original inter-packet timing from the capture
+    #[cfg_attr(feature = "clap", clap(long, conflicts_with = "period"))]
+    pub original_timing: bool,
+
+    /// Fixed period between packets (packets are sent back-to-back otherwise)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub period: Option<HumanDuration>,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Retransmit packets from a capture, returning the number of packets sent
+pub fn do_replay<T, E>(radio: &mut T, options: ReplayOptions) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    let mut capture = CaptureReader::open(&options.file).expect("Error opening capture");
+
+    do_replay_from(radio, &mut capture, options)
+}
+
+/// Retransmit packets from the provided capture reader, returning the number of packets sent
+pub fn do_replay_from<T, E, R>(
+    radio: &mut T,
+    capture: &mut CaptureReader<R>,
+    options: ReplayOptions,
+) -> Result<usize, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    R: BufRead,
+{
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut progress = options.progress_options.reporter();
+    let mut last = None;
+    let mut sent = 0;
+
+    while let Some(p) = capture.next_packet().expect("Error reading capture") {
+        // Wait between packets
+        let delay_us = match (&options.period, last.zip(p.timestamp)) {
+            (Some(period), _) if sent > 0 => period.as_micros(),
+            (None, Some((l, t))) if options.original_timing => t.saturating_sub(l).as_micros(),
+            _ => 0,
+        };
+        if delay_us > 0 {
+            radio.delay_us(delay_us.min(u32::MAX as u128) as u32);
+        }
+        last = p.timestamp.or(last);
+
+        debug!("Replaying packet {} ({} bytes)", sent, p.data.len());
+
+        radio.do_transmit(&p.data, options.blocking_options.clone())?;
+        sent += 1;
+
+        progress.update(&Progress::new("replay", sent as u64, None));
+    }
+    progress.finish(&Progress::new("replay", sent as u64, None));
+
+    info!("Replay complete, sent {} packets", sent);
+
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use pcap_file::{
+        DataLink,
+        pcap::{PcapHeader, PcapPacket, PcapWriter},
+    };
+
+    use super::*;
+    use crate::helpers::{DecodedFrame, PacketSink, PcapNgSink};
+
+    /// Radio recording transmitted packets and delays
+    #[derive(Default)]
+    struct ReplayRadio {
+        sent: Vec<Vec<u8>>,
+        delays_us: Vec<u32>,
+    }
+
+    impl Transmit for ReplayRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.sent.push(data.to_vec());
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Power for ReplayRadio {
+        type Error = ();
+
+        fn set_power(&mut self, _power: i8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl DelayNs for ReplayRadio {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delays_us.push(ns / 1000);
+        }
+    }
+
+    #[test]
+    fn replay_captures() {
+        let packets = [(1_000u64, vec![0x01, 0x02]), (4_000, vec![0x03])];
+
+        // Legacy PCAP
+        let mut w = PcapWriter::with_header(
+            vec![],
+            PcapHeader {
+                datalink: DataLink::USER0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for (t, d) in &packets {
+            let ts = Duration::from_micros(*t);
+            w.write_packet(&PcapPacket::new(ts, d.len() as u32, d))
+                .unwrap();
+        }
+        let pcap = w.into_writer();
+
+        // PCAP-NG, as written by capture output
+        let mut w = PcapNgSink::new(vec![], DataLink::USER0, None).unwrap();
+        for (t, d) in &packets {
+            let frame = DecodedFrame {
+                timestamp_us: *t,
+                rssi: -70,
+                data: d.clone(),
+                text: None,
+                protocol: None,
+                summary: None,
+                device: None,
+                quality: Default::default(),
+                info: String::new(),
+            };
+            w.write(&frame).unwrap();
+        }
+        let pcapng = w.into_inner();
+
+        let options = ReplayOptions {
+            file: String::new(),
+            original_timing: true,
+            period: None,
+            power: None,
+            progress_options: Default::default(),
+            blocking_options: Default::default(),
+        };
+
+        for capture in [pcap, pcapng] {
+            let mut r = CaptureReader::new(&capture[..]).unwrap();
+            let p = r.next_packet().unwrap().unwrap();
+            assert_eq!(p.timestamp, Some(Duration::from_micros(1_000)));
+            assert_eq!(p.data, vec![0x01, 0x02]);
+
+            // Replay with the original timing
+            let mut radio = ReplayRadio::default();
+            let mut r = CaptureReader::new(&capture[..]).unwrap();
+            let n = do_replay_from(&mut radio, &mut r, options.clone()).unwrap();
+            assert_eq!(n, 2);
+            assert_eq!(radio.sent, vec![vec![0x01, 0x02], vec![0x03]]);
+            assert_eq!(radio.delays_us, vec![3_000]);
+
+            // Or a fixed period
+            let mut radio = ReplayRadio::default();
+            let mut r = CaptureReader::new(&capture[..]).unwrap();
+            let fixed = ReplayOptions {
+                original_timing: false,
+                period: Some(Duration::from_millis(10).into()),
+                ..options.clone()
+            };
+            do_replay_from(&mut radio, &mut r, fixed).unwrap();
+            assert_eq!(radio.delays_us, vec![10_000]);
+        }
+
+        // Microsecond and binary resolution PCAP-NG timestamps are scaled
+        assert_eq!(
+            scale_timestamp(Duration::from_nanos(1_500), 6),
+            Duration::from_micros(1_500)
+        );
+        assert_eq!(
+            scale_timestamp(Duration::from_nanos(3), 0x81),
+            Duration::from_millis(1_500)
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Human readable (Markdown / HTML) report generation for link tests
+
+use std::fmt::Write as _;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use super::{LinkTestInfo, LinkTestReport};
+
+/// Options for writing link test reports
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ReportOptions {
+    /// Write link test results as a JSON report for later comparison
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub report: Option<String>,
+
+    /// Write a Markdown report of link test results
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub report_md: Option<String>,
+
+    /// Write a standalone HTML report of link test results
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub report_html: Option<String>,
+}
+
+impl ReportOptions {
+    /// Write all configured reports
+    pub fn write(&self, report: &LinkTestReport) -> Result<(), std::io::Error> {
+        if let Some(path) = &self.report {
+            report.save(path)?;
+        }
+        if let Some(path) = &self.report_md {
+            std::fs::write(path, render_markdown(report))?;
+        }
+        if let Some(path) = &self.report_html {
+            std::fs::write(path, render_html(report))?;
+        }
+        Ok(())
+    }
+}
+
+const HEADINGS: [&str; 9] = [
+    "size",
+    "sent",
+    "received",
+    "loss (%)",
+    "longest outage",
+    "local rssi (dBm)",
+    "remote rssi (dBm)",
+    "rtt mean (ms)",
+    "rtt p95 (ms)",
+];
+
+fn loss(r: &LinkTestInfo) -> f32 {
+    100.0 - r.received as f32 * 100.0 / r.sent.max(1) as f32
+}
+
+fn opt(v: Option<f32>) -> String {
+    v.map(|v| format!("{:.1}", v)).unwrap_or_else(|| "-".into())
+}
+
+fn row(r: &LinkTestInfo) -> [String; 9] {
+    [
+        r.payload_len.to_string(),
+        r.sent.to_string(),
+        r.received.to_string(),
+        format!("{:.1}", loss(r)),
+        r.loss_bursts.longest.to_string(),
+        opt(r.local_rssi.mean()),
+        opt(r.remote_rssi.mean()),
+        opt(r.rtt.mean()),
+        opt(r.rtt.percentile(95.0)),
+    ]
+}
+
+/// Render a link test report as Markdown, with a text bar chart of loss by payload size
+pub fn render_markdown(report: &LinkTestReport) -> String {
+    let mut s = String::new();
+
+    let _ = writeln!(s, "# Link test report\n");
+    let _ = writeln!(s, "| {} |", HEADINGS.join(" | "));
+    let _ = writeln!(s, "|{}", "---|".repeat(HEADINGS.len()));
+    for r in &report.results {
+        let _ = writeln!(s, "| {} |", row(r).join(" | "));
+    }
+
+    let _ = writeln!(s, "\n## Loss by payload size\n\n```");
+    for r in &report.results {
+        let l = loss(r);
+        let filled = (l / 5.0).round() as usize;
+        let _ = writeln!(
+            s,
+            "{:>6} | {}{} {:.1}%",
+            r.payload_len,
+            "#".repeat(filled),
+            ".".repeat(20 - filled.min(20)),
+            l
+        );
+    }
+    let _ = writeln!(s, "```");
+
+    s
+}
+
+/// Render a link test report as a standalone HTML document with inline SVG charts
+pub fn render_html(report: &LinkTestReport) -> String {
+    let mut s = String::new();
+
+    let _ = writeln!(
+        s,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Link test report</title>"
+    );
+    let _ = writeln!(
+        s,
+        "<style>body{{font-family:sans-serif}}table{{border-collapse:collapse}}\
+         td,th{{border:1px solid #ccc;padding:4px 8px;text-align:right}}</style></head><body>"
+    );
+    let _ = writeln!(s, "<h1>Link test report</h1>\n<table>\n<tr>");
+    for h in HEADINGS {
+        let _ = write!(s, "<th>{}</th>", h);
+    }
+    let _ = writeln!(s, "</tr>");
+    for r in &report.results {
+        let _ = write!(s, "<tr>");
+        for c in row(r) {
+            let _ = write!(s, "<td>{}</td>", c);
+        }
+        let _ = writeln!(s, "</tr>");
+    }
+    let _ = writeln!(s, "</table>");
+
+    let loss: Vec<_> = report
+        .results
+        .iter()
+        .map(|r| (r.payload_len, loss(r)))
+        .collect();
+    let rtt: Vec<_> = report
+        .results
+        .iter()
+        .map(|r| (r.payload_len, r.rtt.mean().unwrap_or_default()))
+        .collect();
+
+    let _ = writeln!(s, "<h2>Loss (%) by payload size</h2>\n{}", svg_bars(&loss));
+    let _ = writeln!(
+        s,
+        "<h2>Mean RTT (ms) by payload size</h2>\n{}",
+        svg_bars(&rtt)
+    );
+    let _ = writeln!(s, "</body></html>");
+
+    s
+}
+
+/// Render a simple SVG bar chart of labelled values
+fn svg_bars(values: &[(usize, f32)]) -> String {
+    const W: usize = 40;
+    const H: f32 = 200.0;
+
+    let max = values.iter().map(|v| v.1).fold(f32::EPSILON, f32::max);
+    let mut s = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+        values.len() * W + W,
+        H as usize + 40
+    );
+
+    for (i, (label, v)) in values.iter().enumerate() {
+        let h = v / max * H;
+        let x = i * W + W / 2;
+        let _ = write!(
+            s,
+            "<rect x=\"{}\" y=\"{:.1}\" width=\"{}\" height=\"{:.1}\" fill=\"#4a7fb5\"><title>{:.2}</title></rect>\
+             <text x=\"{}\" y=\"{}\" font-size=\"10\" text-anchor=\"middle\">{}</text>",
+            x,
+            H + 10.0 - h,
+            W - 8,
+            h,
+            v,
+            x + (W - 8) / 2,
+            H as usize + 25,
+            label
+        );
+    }
+
+    s.push_str("</svg>");
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{BurstLoss, Samples};
+
+    #[test]
+    fn render_reports() {
+        let report = LinkTestReport {
+            results: vec![LinkTestInfo {
+                sent: 10,
+                received: 9,
+                payload_len: 16,
+                local_rssi: Samples::new(),
+                remote_rssi: Samples::new(),
+                rtt: Samples::new(),
+                loss_bursts: BurstLoss::default(),
+                ..LinkTestInfo::new(16)
+            }],
+        };
+
+        let md = render_markdown(&report);
+        assert!(md.contains("| 16 | 10 | 9 | 10.0 | 0 | - | - | - | - |"));
+        assert!(md.contains("    16 | ##.................. 10.0%"));
+
+        let html = render_html(&report);
+        assert!(html.contains("<td>16</td><td>10</td><td>9</td>"));
+        assert!(html.contains("<svg"));
+    }
+}
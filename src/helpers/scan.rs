@@ -0,0 +1,373 @@
+//! Spectrum scan / channel survey operation
+//!
+//! Samples RSSI on each channel in a list, reporting minimum, mean and maximum RSSI along
+//! with occupancy (the fraction of samples above a threshold) per channel, for picking a
+//! clear channel. Channels are specified as driver-specific numbers (or frequencies) and
+//! mapped to the radio [`Channel`] type by the caller.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{Progress, ProgressOptions, ProgressReporter, Samples};
+use crate::{Channel, Receive, Rssi};
+
+/// Configuration for spectrum scan operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ScanOptions {
+    /// Channels to scan, comma separated (driver-specific channel numbers or frequencies)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ',', required = true))]
+    pub channels: Vec<u32>,
+
+    /// RSSI samples per channel on each sweep
+    #[cfg_attr(feature = "clap", clap(long, default_value = "16"))]
+    pub samples: usize,
+
+    /// Interval between RSSI samples
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1ms"))]
+    pub sample_interval: HumanDuration,
+
+    /// Number of sweeps across the channel list
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1"))]
+    pub sweeps: u32,
+
+    /// RSSI threshold (dBm) above which a channel is considered occupied
+    #[cfg_attr(
+        feature = "clap",
+        clap(long, default_value = "-90", allow_hyphen_values = true)
+    )]
+    pub occupancy_threshold: i16,
+
+    /// Write per-channel results to a CSV file
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub csv: Option<String>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            channels: vec![],
+            samples: 16,
+            sample_interval: std::time::Duration::from_millis(1).into(),
+            sweeps: 1,
+            occupancy_threshold: -90,
+            csv: None,
+            progress_options: Default::default(),
+        }
+    }
+}
+
+/// Automatic clear-channel selection options
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct AutoChannelOptions {
+    /// Candidate channels, comma separated, switching to the quietest before the operation
+    /// (driver-specific channel numbers or frequencies)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub auto_channel: Vec<u32>,
+
+    /// RSSI samples per candidate channel
+    #[cfg_attr(feature = "clap", clap(long, default_value = "16"))]
+    pub auto_channel_samples: usize,
+}
+
+impl Default for AutoChannelOptions {
+    fn default() -> Self {
+        Self {
+            auto_channel: vec![],
+            auto_channel_samples: 16,
+        }
+    }
+}
+
+impl AutoChannelOptions {
+    /// Check whether automatic channel selection is enabled
+    pub fn enabled(&self) -> bool {
+        !self.auto_channel.is_empty()
+    }
+}
+
+/// Scan results for a single channel
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelScan {
+    pub channel: u32,
+    pub rssi: Samples,
+    /// Samples above the occupancy threshold
+    pub occupied: u32,
+}
+
+impl ChannelScan {
+    /// Fraction of samples above the occupancy threshold
+    pub fn occupancy(&self) -> f32 {
+        match self.rssi.count() {
+            0 => 0.0,
+            n => self.occupied as f32 / n as f32,
+        }
+    }
+}
+
+/// Format a per-channel RSSI and occupancy table
+pub struct ScanTable<'a>(pub &'a [ChannelScan]);
+
+impl core::fmt::Display for ScanTable<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>8} {:>8} {:>8} {:>13}",
+            "channel", "min", "mean", "max", "occupancy (%)"
+        )?;
+        for c in self.0 {
+            let v = |m: Option<f32>| m.map(|v| format!("{:.1}", v)).unwrap_or("-".into());
+            writeln!(
+                f,
+                "{:>10} {:>8} {:>8} {:>8} {:>13.1}",
+                c.channel,
+                v(c.rssi.min()),
+                v(c.rssi.mean()),
+                v(c.rssi.max()),
+                c.occupancy() * 100.0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write scan results as CSV (`channel,samples,min,mean,max,occupancy`)
+pub fn write_scan_csv<W: Write>(mut w: W, results: &[ChannelScan]) -> Result<(), std::io::Error> {
+    writeln!(w, "channel,samples,min,mean,max,occupancy")?;
+    for c in results {
+        let v = |m: Option<f32>| m.map(|v| format!("{:.1}", v)).unwrap_or_default();
+        writeln!(
+            w,
+            "{},{},{},{},{},{:.3}",
+            c.channel,
+            c.rssi.count(),
+            v(c.rssi.min()),
+            v(c.rssi.mean()),
+            v(c.rssi.max()),
+            c.occupancy()
+        )?;
+    }
+    w.flush()
+}
+
+/// Scan the configured channels, returning RSSI and occupancy per channel
+///
+/// `to_channel` maps configured channel numbers to the radio channel type.
+pub fn do_scan<T, I, E, F>(
+    radio: &mut T,
+    options: ScanOptions,
+    to_channel: F,
+) -> Result<Vec<ChannelScan>, E>
+where
+    T: Receive<Info = I, Error = E> + Rssi<Error = E> + Channel<Error = E> + DelayNs,
+    E: std::fmt::Debug,
+    F: FnMut(u32) -> T::Channel,
+{
+    let mut progress = options.progress_options.reporter();
+    do_scan_with(radio, options, to_channel, &mut *progress)
+}
+
+/// Scan the configured channels, reporting progress per channel to the provided reporter
+pub fn do_scan_with<T, I, E, F, P>(
+    radio: &mut T,
+    options: ScanOptions,
+    mut to_channel: F,
+    progress: &mut P,
+) -> Result<Vec<ChannelScan>, E>
+where
+    T: Receive<Info = I, Error = E> + Rssi<Error = E> + Channel<Error = E> + DelayNs,
+    E: std::fmt::Debug,
+    F: FnMut(u32) -> T::Channel,
+    P: ProgressReporter + ?Sized,
+{
+    let total = options.sweeps as u64 * options.channels.len() as u64;
+    let mut done = 0;
+
+    let mut results: Vec<_> = options
+        .channels
+        .iter()
+        .map(|c| ChannelScan {
+            channel: *c,
+            rssi: Samples::new(),
+            occupied: 0,
+        })
+        .collect();
+
+    for sweep in 0..options.sweeps {
+        debug!("Scan sweep {}", sweep);
+
+        for r in results.iter_mut() {
+            radio.set_channel(&to_channel(r.channel))?;
+            radio.start_receive()?;
+
+            for _ in 0..options.samples {
+                let rssi = radio.poll_rssi()?;
+                r.rssi.update(rssi as f32);
+                if rssi > options.occupancy_threshold {
+                    r.occupied += 1;
+                }
+
+                radio.delay_us(options.sample_interval.as_micros() as u32);
+            }
+
+            done += 1;
+            progress.update(&Progress::new("scan", done, Some(total)));
+        }
+    }
+    progress.finish(&Progress::new("scan", done, Some(total)));
+
+    info!("Scan complete:\n{}", ScanTable(&results));
+
+    if let Some(p) = &options.csv {
+        let f = BufWriter::new(File::create(p).expect("Error creating scan CSV"));
+        write_scan_csv(f, &results).expect("Error writing scan CSV");
+    }
+
+    Ok(results)
+}
+
+/// Select the quietest channel from scan results, by mean RSSI then occupancy
+pub fn quietest_channel(results: &[ChannelScan]) -> Option<u32> {
+    let mean = |r: &ChannelScan| r.rssi.mean().unwrap_or(f32::MAX);
+
+    results
+        .iter()
+        .min_by(|a, b| {
+            mean(a)
+                .total_cmp(&mean(b))
+                .then(a.occupancy().total_cmp(&b.occupancy()))
+        })
+        .map(|r| r.channel)
+}
+
+/// Scan the candidate channels and configure the radio to the quietest, returning the
+/// selected channel (or `None` where no candidates are configured)
+///
+/// `to_channel` maps configured channel numbers to the radio channel type.
+pub fn select_channel<T, I, E, F>(
+    radio: &mut T,
+    options: &AutoChannelOptions,
+    mut to_channel: F,
+) -> Result<Option<u32>, E>
+where
+    T: Receive<Info = I, Error = E> + Rssi<Error = E> + Channel<Error = E> + DelayNs,
+    E: std::fmt::Debug,
+    F: FnMut(u32) -> T::Channel,
+{
+    if !options.enabled() {
+        return Ok(None);
+    }
+
+    let scan = ScanOptions {
+        channels: options.auto_channel.clone(),
+        samples: options.auto_channel_samples,
+        ..Default::default()
+    };
+    let results = do_scan(radio, scan, &mut to_channel)?;
+
+    let selected = quietest_channel(&results);
+    if let Some(c) = selected {
+        info!("Selected channel {}", c);
+        radio.set_channel(&to_channel(c))?;
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio reporting RSSI by channel, with channel 2 occupied
+    struct ScanRadio {
+        channel: u32,
+        n: i16,
+    }
+
+    impl Receive for ScanRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Ok((0, BasicInfo::default()))
+        }
+    }
+
+    impl Rssi for ScanRadio {
+        type Error = ();
+
+        fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+            self.n += 1;
+            match self.channel {
+                2 => Ok(-60 - self.n % 2 * 40),
+                _ => Ok(-110),
+            }
+        }
+    }
+
+    impl Channel for ScanRadio {
+        type Channel = u32;
+        type Error = ();
+
+        fn set_channel(&mut self, channel: &u32) -> Result<(), Self::Error> {
+            self.channel = *channel;
+            Ok(())
+        }
+    }
+
+    impl DelayNs for ScanRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn channel_scan() {
+        let options = ScanOptions {
+            channels: vec![1, 2],
+            samples: 4,
+            sweeps: 2,
+            ..Default::default()
+        };
+        let mut radio = ScanRadio { channel: 0, n: 0 };
+        let r = do_scan(&mut radio, options, |c| c).unwrap();
+
+        assert_eq!(r[0].rssi.count(), 8);
+        assert_eq!(r[0].occupancy(), 0.0);
+        assert_eq!(
+            (r[1].rssi.min(), r[1].rssi.max()),
+            (Some(-100.0), Some(-60.0))
+        );
+        assert_eq!(r[1].occupancy(), 0.5);
+
+        assert_eq!(quietest_channel(&r), Some(1));
+
+        let mut csv = vec![];
+        write_scan_csv(&mut csv, &r).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(2), Some("2,8,-100.0,-80.0,-60.0,0.500"));
+    }
+}
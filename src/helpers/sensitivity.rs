@@ -0,0 +1,515 @@
+//! Receiver sensitivity threshold search
+//!
+//! The `sensitivity` operation steps the transmit power downward from `--start` in `--step`
+//! increments, sending `--packets` framed test packets at each level to a peer running
+//! `echo` and measuring the packet error rate (PER) from missing responses. The search stops
+//! once the PER exceeds `--per-threshold`, interpolating the power at which the threshold is
+//! crossed. Where the link is padded or attenuated, `--path-loss` converts this to the
+//! effective sensitivity of the receiving radio and driver.
+//!
+//! Where the transmit power range is insufficient, an external step attenuator may be driven
+//! instead, with `--attenuator-exec` running a command (with `RADIO_ATTENUATION` set in dB)
+//! at each level, or with [`do_sensitivity_with`] calling a provided hook. The radio then
+//! transmits at `--start` throughout, with each level reported as the effective power.
+//!
+//! Responses are assumed to return over a link with margin (for example from a peer at full
+//! power), so errors are attributed to the attenuated forward link.
+
+use std::process::Command;
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{Progress, ProgressOptions, ReceiveQuality, Samples};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingReceive, BlockingTransmit},
+};
+
+/// Length of the sequence number prefixing each test packet
+const HEADER_LEN: usize = 4;
+
+/// Configuration for Sensitivity operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct SensitivityOptions {
+    /// Initial power level in dBm
+    #[cfg_attr(
+        feature = "clap",
+        clap(long, default_value = "13", allow_hyphen_values = true)
+    )]
+    pub start: i8,
+
+    /// Lowest power level in dBm
+    #[cfg_attr(
+        feature = "clap",
+        clap(long, default_value = "-18", allow_hyphen_values = true)
+    )]
+    pub stop: i8,
+
+    /// Power (or attenuation) step in dB
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1", value_parser = clap::value_parser!(u8).range(1..)))]
+    pub step: u8,
+
+    /// Packets sent at each level
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100"))]
+    pub packets: u32,
+
+    /// Packet error rate (%) defining the sensitivity threshold
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10"))]
+    pub per_threshold: f32,
+
+    /// Fixed loss in dB between radios (cables, pads and fixed attenuators)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub path_loss: f32,
+
+    /// Packet size in bytes (including a 4 byte sequence number)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "16"))]
+    pub size: usize,
+
+    /// Period between packets
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10ms"))]
+    pub period: HumanDuration,
+
+    /// Command setting an external attenuator at each level (with `RADIO_ATTENUATION` set
+    /// in dB), transmitting at `--start` power throughout
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub attenuator_exec: Option<String>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for SensitivityOptions {
+    fn default() -> Self {
+        Self {
+            start: 13,
+            stop: -18,
+            step: 1,
+            packets: 100,
+            per_threshold: 10.0,
+            path_loss: 0.0,
+            size: 16,
+            period: Duration::from_millis(10).into(),
+            attenuator_exec: None,
+            progress_options: ProgressOptions::default(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+impl SensitivityOptions {
+    /// Effective power levels in the search, stepping down from `start`
+    pub fn levels(&self) -> Vec<i8> {
+        (self.stop.min(self.start)..=self.start)
+            .rev()
+            .step_by(self.step.max(1) as usize)
+            .collect()
+    }
+}
+
+/// Results at a single level
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SensitivityLevel {
+    /// Effective transmit power in dBm (configured power less any attenuation)
+    pub power: i8,
+    /// External attenuation in dB, where driving an attenuator
+    pub attenuation: Option<f32>,
+    pub sent: u32,
+    pub received: u32,
+    /// RSSI reported by the peer (with `echo --append-info`)
+    pub remote_rssi: Samples,
+}
+
+impl SensitivityLevel {
+    /// Packet error rate (%)
+    pub fn per(&self) -> f32 {
+        100.0 - self.received as f32 * 100.0 / self.sent.max(1) as f32
+    }
+}
+
+/// Sensitivity search results
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SensitivityInfo {
+    pub levels: Vec<SensitivityLevel>,
+    /// PER threshold (%)
+    pub per_threshold: f32,
+    /// Effective power at which the PER threshold is crossed (interpolated between
+    /// levels), where crossed
+    pub threshold_power: Option<f32>,
+    /// Effective sensitivity in dBm (threshold power less path loss)
+    pub sensitivity: Option<f32>,
+}
+
+impl SensitivityInfo {
+    /// Interpolate the threshold crossing between the last two levels
+    fn interpolate(&mut self, path_loss: f32) {
+        let (pass, fail) = match self.levels.as_slice() {
+            [.., p, f] if p.per() <= self.per_threshold && f.per() > self.per_threshold => (p, f),
+            [f] if f.per() > self.per_threshold => (f, f),
+            _ => return,
+        };
+
+        let power = match fail.per() - pass.per() {
+            d if d > 0.0 => {
+                let t = (self.per_threshold - pass.per()) / d;
+                pass.power as f32 + t * (fail.power as f32 - pass.power as f32)
+            }
+            _ => fail.power as f32,
+        };
+        self.threshold_power = Some(power);
+        self.sensitivity = Some(power - path_loss);
+    }
+}
+
+impl core::fmt::Display for SensitivityInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>6} {:>6} {:>8} {:>8} {:>12}",
+            "power", "atten", "sent", "received", "per (%)", "remote rssi"
+        )?;
+        for l in &self.levels {
+            let opt = |v: Option<f32>| match v {
+                Some(v) => format!("{:.1}", v),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:>6} {:>6} {:>6} {:>8} {:>8.1} {:>12}",
+                l.power,
+                opt(l.attenuation),
+                l.sent,
+                l.received,
+                l.per(),
+                opt(l.remote_rssi.mean())
+            )?;
+        }
+        match (self.threshold_power, self.sensitivity) {
+            (Some(p), Some(s)) => write!(
+                f,
+                "{:.1}% PER at {:.1} dBm, sensitivity {:.1} dBm",
+                self.per_threshold, p, s
+            ),
+            _ => write!(f, "{:.1}% PER threshold not reached", self.per_threshold),
+        }
+    }
+}
+
+/// Search for the receiver sensitivity threshold, stepping transmit power downward
+/// (or driving `--attenuator-exec` where set) until the PER threshold is crossed
+pub fn do_sensitivity<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: SensitivityOptions,
+) -> Result<SensitivityInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    match options.attenuator_exec.clone() {
+        Some(cmd) => do_sensitivity_with(radio, buff, options, |a| run_attenuator(&cmd, a)),
+        None => sensitivity_search(radio, buff, options, None::<fn(f32) -> std::io::Result<()>>),
+    }
+}
+
+/// Search for the receiver sensitivity threshold, transmitting at `--start` power and
+/// calling `attenuate` with the attenuation (dB) for each level
+pub fn do_sensitivity_with<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: SensitivityOptions,
+    attenuate: F,
+) -> Result<SensitivityInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+    F: FnMut(f32) -> Result<(), std::io::Error>,
+{
+    sensitivity_search(radio, buff, options, Some(attenuate))
+}
+
+fn sensitivity_search<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: SensitivityOptions,
+    mut attenuate: Option<F>,
+) -> Result<SensitivityInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+    F: FnMut(f32) -> Result<(), std::io::Error>,
+{
+    let levels = options.levels();
+    let mut progress = options.progress_options.reporter();
+    let mut info = SensitivityInfo {
+        per_threshold: options.per_threshold,
+        ..Default::default()
+    };
+    let mut data: Vec<u8> = (0..options.size.max(HEADER_LEN)).map(|i| i as u8).collect();
+    let mut seq = 0u32;
+
+    if attenuate.is_some() {
+        radio.set_power(options.start)?;
+    }
+
+    for (i, power) in levels.iter().enumerate() {
+        // Set the power or attenuation for this level
+        let attenuation = match attenuate.as_mut() {
+            Some(f) => {
+                let a = options.start as f32 - *power as f32;
+                if let Err(e) = f(a) {
+                    warn!("Error setting attenuation to {} dB: {:?}", a, e);
+                    break;
+                }
+                Some(a)
+            }
+            None => {
+                radio.set_power(*power)?;
+                None
+            }
+        };
+
+        let mut level = SensitivityLevel {
+            power: *power,
+            attenuation,
+            ..Default::default()
+        };
+
+        for _ in 0..options.packets.max(1) {
+            data[..HEADER_LEN].copy_from_slice(&seq.to_be_bytes());
+            seq = seq.wrapping_add(1);
+
+            radio.do_transmit(&data, options.blocking_options.clone())?;
+            level.sent += 1;
+
+            match radio.do_receive(buff, options.blocking_options.clone()) {
+                // Responses echo the packet, followed by any appended info
+                Ok((n, _)) if n >= data.len() && buff[..HEADER_LEN] == data[..HEADER_LEN] => {
+                    level.received += 1;
+                    if let Some((rssi, _)) = ReceiveQuality::decode(&buff[data.len()..n]) {
+                        level.remote_rssi.update(rssi as f32);
+                    }
+                }
+                Ok((n, _)) => debug!("Unexpected {} byte response", n),
+                Err(BlockingError::Timeout) => debug!("Timeout awaiting response"),
+                Err(e) => return Err(e),
+            }
+
+            radio.delay_us(options.period.as_micros() as u32);
+        }
+
+        let per = level.per();
+        debug!("Power {} dBm: PER {:.1}%", power, per);
+        info.levels.push(level);
+
+        progress.update(&Progress::new(
+            "sensitivity",
+            i as u64 + 1,
+            Some(levels.len() as u64),
+        ));
+
+        if per > options.per_threshold {
+            break;
+        }
+    }
+    progress.finish(&Progress::new(
+        "sensitivity",
+        info.levels.len() as u64,
+        Some(levels.len() as u64),
+    ));
+
+    info.interpolate(options.path_loss);
+
+    info!("Sensitivity search:\n{}", info);
+
+    Ok(info)
+}
+
+/// Run an attenuator command, waiting for it to complete
+fn run_attenuator(cmd: &str, attenuation: f32) -> Result<(), std::io::Error> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("RADIO_ATTENUATION", attenuation.to_string())
+        .status()?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(std::io::Error::other(format!(
+            "attenuator command exited with {}",
+            status
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio echoing packets with 10% additional loss per dB below 0 dBm (of effective
+    /// power), appending the peer RSSI
+    #[derive(Default)]
+    struct LossyEcho {
+        power: i8,
+        attenuation: f32,
+        last: Option<Vec<u8>>,
+    }
+
+    impl Transmit for LossyEcho {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let effective = self.power as f32 - self.attenuation;
+            let seq = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            if ((seq % 10) as f32) < -effective {
+                return Ok(());
+            }
+
+            let mut d = data.to_vec();
+            d.extend_from_slice(&(effective as i16 - 100).to_be_bytes());
+            self.last = Some(d);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for LossyEcho {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.last.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let d = self.last.take().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(-50, 0)))
+        }
+    }
+
+    impl Power for LossyEcho {
+        type Error = ();
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            self.power = power;
+            Ok(())
+        }
+    }
+
+    impl DelayNs for LossyEcho {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn sensitivity_search() {
+        let options = SensitivityOptions {
+            start: 2,
+            stop: -10,
+            packets: 10,
+            per_threshold: 15.0,
+            path_loss: 100.0,
+            period: Duration::ZERO.into(),
+            blocking_options: BlockingOptions {
+                timeout: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(options.levels()[..3], [2, 1, 0]);
+
+        // Stepping power stops once PER exceeds the threshold, at -2 dBm (20%)
+        let mut buff = [0u8; 64];
+        let info = do_sensitivity(&mut LossyEcho::default(), &mut buff, options.clone()).unwrap();
+        let per: Vec<_> = info.levels.iter().map(|l| (l.power, l.per())).collect();
+        assert_eq!(
+            per,
+            vec![(2, 0.0), (1, 0.0), (0, 0.0), (-1, 10.0), (-2, 20.0)]
+        );
+        assert_eq!(info.levels[0].remote_rssi.mean(), Some(-98.0));
+        assert_eq!(info.threshold_power, Some(-1.5));
+        assert_eq!(info.sensitivity, Some(-101.5));
+
+        // Driving an attenuator gives the same effective levels
+        let mut radio = LossyEcho::default();
+        let attenuation = std::cell::Cell::new(0.0);
+        let hook = |a| {
+            attenuation.set(a);
+            Ok(())
+        };
+        let mut r = AttenuatedRadio(&mut radio, &attenuation);
+        let info = do_sensitivity_with(&mut r, &mut buff, options, hook).unwrap();
+        assert_eq!(info.levels[4].attenuation, Some(4.0));
+        assert_eq!(info.threshold_power, Some(-1.5));
+    }
+
+    /// Radio applying attenuation set by the hook
+    struct AttenuatedRadio<'a>(&'a mut LossyEcho, &'a std::cell::Cell<f32>);
+
+    impl Transmit for AttenuatedRadio<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.0.attenuation = self.1.get();
+            self.0.start_transmit(data)
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            self.0.check_transmit()
+        }
+    }
+
+    impl Receive for AttenuatedRadio<'_> {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            self.0.start_receive()
+        }
+
+        fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+            self.0.check_receive(restart)
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            self.0.get_received(buff)
+        }
+    }
+
+    impl Power for AttenuatedRadio<'_> {
+        type Error = ();
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            self.0.set_power(power)
+        }
+    }
+
+    impl DelayNs for AttenuatedRadio<'_> {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+}
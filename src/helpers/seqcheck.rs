@@ -0,0 +1,212 @@
+//! Receive-side sequence analysis
+//!
+//! With `--seq-check N`, receive operations interpret the first N bytes of each frame as a
+//! big-endian sequence number (matching `gen:` sources and `{seq:N}` templates), logging
+//! gaps, reordering and duplicates as they occur.
+
+/// Sequence history depth for reorder and duplicate detection
+const SEQ_HISTORY: u32 = 64;
+
+/// Sequence anomaly detected in a received frame
+#[derive(Clone, Debug, PartialEq)]
+pub enum SeqEvent {
+    /// Frames missing between the expected and received sequence numbers
+    Gap { expected: u32, received: u32 },
+    /// Frame received after a later sequence number
+    Reordered(u32),
+    /// Frame with a previously received sequence number
+    Duplicate(u32),
+    /// Frame too short to contain a sequence number
+    Short(usize),
+}
+
+impl core::fmt::Display for SeqEvent {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SeqEvent::Gap { expected, received } => write!(
+                f,
+                "gap, expected {} received {} ({} missing)",
+                expected,
+                received,
+                received.wrapping_sub(*expected)
+            ),
+            SeqEvent::Reordered(s) => write!(f, "reordered {}", s),
+            SeqEvent::Duplicate(s) => write!(f, "duplicate {}", s),
+            SeqEvent::Short(n) => write!(f, "short frame ({} bytes)", n),
+        }
+    }
+}
+
+/// Sequence analysis statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SeqStats {
+    /// Frames received with a sequence number
+    pub received: u32,
+    /// Frames missing (excluding those later received out of order)
+    pub missing: u32,
+    /// Frames received out of order
+    pub reordered: u32,
+    /// Duplicate frames
+    pub duplicates: u32,
+    /// Frames too short to contain a sequence number
+    pub short: u32,
+}
+
+impl SeqStats {
+    /// Fraction of frames missing
+    pub fn loss(&self) -> f32 {
+        let expected = self.received - self.duplicates + self.missing;
+        match expected {
+            0 => 0.0,
+            n => self.missing as f32 / n as f32,
+        }
+    }
+}
+
+impl core::fmt::Display for SeqStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "received {} missing {} ({:.1}%) reordered {} duplicates {} short {}",
+            self.received,
+            self.missing,
+            self.loss() * 100.0,
+            self.reordered,
+            self.duplicates,
+            self.short
+        )
+    }
+}
+
+/// Sequence checker, tracking the highest sequence number received and a history of
+/// recent sequence numbers for reorder and duplicate detection
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeqChecker {
+    width: usize,
+    highest: Option<u32>,
+    history: u64,
+    stats: SeqStats,
+}
+
+impl SeqChecker {
+    /// Create a checker for sequence numbers of `width` bytes (clamped to 1..=4)
+    pub fn new(width: usize) -> Self {
+        Self {
+            width: width.clamp(1, 4),
+            highest: None,
+            history: 0,
+            stats: SeqStats::default(),
+        }
+    }
+
+    /// Sequence analysis statistics
+    pub fn stats(&self) -> &SeqStats {
+        &self.stats
+    }
+
+    /// Check a received frame, returning any sequence anomaly
+    pub fn update(&mut self, data: &[u8]) -> Option<SeqEvent> {
+        if data.len() < self.width {
+            self.stats.short += 1;
+            return Some(SeqEvent::Short(data.len()));
+        }
+
+        let seq = data[..self.width]
+            .iter()
+            .fold(0u32, |a, b| (a << 8) | *b as u32);
+        let mask = u32::MAX >> (32 - 8 * self.width);
+        self.stats.received += 1;
+
+        let highest = match self.highest {
+            Some(h) => h,
+            None => {
+                self.highest = Some(seq);
+                self.history = 1;
+                return None;
+            }
+        };
+
+        // Sequence numbers within half the range ahead are newer, otherwise older
+        let ahead = seq.wrapping_sub(highest) & mask;
+        let behind = highest.wrapping_sub(seq) & mask;
+
+        if ahead == 0 {
+            self.stats.duplicates += 1;
+            return Some(SeqEvent::Duplicate(seq));
+        }
+
+        if ahead <= mask / 2 {
+            self.history = match ahead < SEQ_HISTORY {
+                true => (self.history << ahead) | 1,
+                false => 1,
+            };
+            self.highest = Some(seq);
+
+            return match ahead - 1 {
+                0 => None,
+                gap => {
+                    self.stats.missing += gap;
+                    Some(SeqEvent::Gap {
+                        expected: highest.wrapping_add(1) & mask,
+                        received: seq,
+                    })
+                }
+            };
+        }
+
+        // Late frames within the history fill earlier gaps
+        if behind < SEQ_HISTORY {
+            let bit = 1u64 << behind;
+            if self.history & bit != 0 {
+                self.stats.duplicates += 1;
+                return Some(SeqEvent::Duplicate(seq));
+            }
+            self.history |= bit;
+            self.stats.missing = self.stats.missing.saturating_sub(1);
+        }
+
+        self.stats.reordered += 1;
+        Some(SeqEvent::Reordered(seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_analysis() {
+        let mut c = SeqChecker::new(2);
+
+        assert_eq!(c.update(&[0, 1, 0xaa]), None);
+        assert_eq!(c.update(&[0, 2]), None);
+        assert_eq!(
+            c.update(&[0, 5]),
+            Some(SeqEvent::Gap {
+                expected: 3,
+                received: 5
+            })
+        );
+        assert_eq!(c.update(&[0, 3]), Some(SeqEvent::Reordered(3)));
+        assert_eq!(c.update(&[0, 3]), Some(SeqEvent::Duplicate(3)));
+        assert_eq!(c.update(&[0, 5]), Some(SeqEvent::Duplicate(5)));
+        assert_eq!(c.update(&[0]), Some(SeqEvent::Short(1)));
+
+        assert_eq!(
+            c.stats(),
+            &SeqStats {
+                received: 6,
+                missing: 1,
+                reordered: 1,
+                duplicates: 2,
+                short: 1,
+            }
+        );
+
+        // Sequence numbers wrap at the configured width
+        let mut c = SeqChecker::new(1);
+        assert_eq!(c.update(&[0xff]), None);
+        assert_eq!(c.update(&[0x00]), None);
+        assert_eq!(c.stats().missing, 0);
+    }
+}
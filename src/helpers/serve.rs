@@ -0,0 +1,478 @@
+//! HTTP REST control server, exposing a radio node for remote control
+//!
+//! Endpoints (JSON request and response bodies):
+//!
+//! - `POST /send` with `{"data": "<hex>"}` transmits a frame
+//! - `GET /frames` returns (and clears) frames received since the last fetch
+//! - `GET /rssi` returns the current channel RSSI
+//! - `PUT /config` with `{"power": <dBm>}` updates radio configuration
+//!
+//! Requests are bounded to [`MAX_HEADERS`] header lines of up to [`MAX_LINE`] bytes
+//! (`431` otherwise) and bodies of up to [`MAX_BODY`] bytes (`413` otherwise).
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, SystemTime};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{DecodedFrame, DecoderRegistry, ReceiveQuality, ReceivedFrame, parse_hex};
+use crate::{
+    Power, Receive, ReceiveInfo, Rssi, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Maximum request line or header line length
+pub const MAX_LINE: usize = 1024;
+
+/// Maximum number of request headers
+pub const MAX_HEADERS: usize = 32;
+
+/// Maximum request body length, bounding allocations for untrusted content lengths
+pub const MAX_BODY: usize = 4096;
+
+/// Configuration for Serve operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ServeOptions {
+    /// Address to listen for HTTP requests
+    #[cfg_attr(feature = "clap", clap(long, default_value = "127.0.0.1:8080"))]
+    pub listen: SocketAddr,
+
+    /// Maximum number of received frames buffered between fetches (oldest are dropped)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "256"))]
+    pub buffer: usize,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Frame transmit request
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SendRequest {
+    /// Hex encoded frame payload
+    pub data: String,
+}
+
+/// Configuration update request
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfigRequest {
+    /// Transmit power in dBm
+    pub power: Option<i8>,
+}
+
+/// HTTP request, as parsed by the control server
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// Errors reading an HTTP request
+#[derive(Debug)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum HttpError {
+    /// Malformed request
+    #[cfg_attr(feature = "thiserror", error("Invalid request: {0}"))]
+    Invalid(&'static str),
+    /// Request line, header line or header count exceeds the configured limits
+    #[cfg_attr(feature = "thiserror", error("Request headers too large"))]
+    HeadersTooLarge,
+    /// Content length exceeds [`MAX_BODY`]
+    #[cfg_attr(feature = "thiserror", error("Payload of {0} bytes too large"))]
+    PayloadTooLarge(usize),
+    /// Connection error
+    #[cfg_attr(feature = "thiserror", error("IO: {0}"))]
+    Io(std::io::Error),
+}
+
+impl HttpError {
+    /// HTTP status for responding to the failed request
+    pub fn status(&self) -> u16 {
+        match self {
+            HttpError::HeadersTooLarge => 431,
+            HttpError::PayloadTooLarge(_) => 413,
+            _ => 400,
+        }
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+/// Read a line of at most [`MAX_LINE`] bytes
+fn read_line<R: BufRead>(r: &mut R, line: &mut String) -> Result<usize, HttpError> {
+    line.clear();
+    let n = r.by_ref().take(MAX_LINE as u64 + 1).read_line(line)?;
+    match n > MAX_LINE {
+        true => Err(HttpError::HeadersTooLarge),
+        false => Ok(n),
+    }
+}
+
+impl HttpRequest {
+    /// Read an HTTP/1.1 request from the provided reader
+    pub fn read<R: Read>(r: R) -> Result<Self, HttpError> {
+        let mut r = BufReader::new(r);
+
+        let mut line = String::new();
+        read_line(&mut r, &mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next()) {
+            (Some(m), Some(p)) => (m.to_string(), p.to_string()),
+            _ => return Err(HttpError::Invalid("invalid request line")),
+        };
+
+        // Read headers, keeping only the content length
+        let mut len = 0;
+        for i in 0.. {
+            if read_line(&mut r, &mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if i >= MAX_HEADERS {
+                return Err(HttpError::HeadersTooLarge);
+            }
+            if let Some((k, v)) = line.split_once(':')
+                && k.trim().eq_ignore_ascii_case("content-length")
+            {
+                len = v
+                    .trim()
+                    .parse()
+                    .map_err(|_| HttpError::Invalid("invalid content length"))?;
+            }
+        }
+
+        if len > MAX_BODY {
+            return Err(HttpError::PayloadTooLarge(len));
+        }
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body)?;
+
+        Ok(Self { method, path, body })
+    }
+}
+
+/// HTTP response, as returned by the control server
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+impl HttpResponse {
+    fn ok(body: serde_json::Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+
+    /// Write the response to the provided writer
+    pub fn write<W: Write>(&self, mut w: W) -> Result<(), std::io::Error> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            413 => "Payload Too Large",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        };
+        let body = self.body.to_string();
+
+        write!(
+            w,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason,
+            body.len(),
+            body
+        )?;
+        w.flush()
+    }
+}
+
+/// Control server state, handling requests against a radio
+pub struct Server {
+    options: ServeOptions,
+    frames: VecDeque<ReceivedFrame>,
+    registry: DecoderRegistry,
+}
+
+impl Server {
+    /// Create a new server
+    pub fn new(options: ServeOptions) -> Self {
+        Self {
+            options,
+            frames: VecDeque::new(),
+            registry: DecoderRegistry::default(),
+        }
+    }
+
+    /// Buffer a received frame, dropping the oldest frame when full
+    pub fn push(&mut self, frame: ReceivedFrame) {
+        if self.frames.len() >= self.options.buffer {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Handle a request
+    pub fn handle<T, I, E>(&mut self, radio: &mut T, req: &HttpRequest) -> HttpResponse
+    where
+        T: Transmit<Error = E>
+            + Receive<Info = I, Error = E>
+            + Rssi<Error = E>
+            + Power<Error = E>
+            + DelayNs,
+        I: ReceiveInfo,
+        E: core::fmt::Debug,
+    {
+        match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/send") => {
+                let data = match serde_json::from_slice::<SendRequest>(&req.body)
+                    .map_err(|e| e.to_string())
+                    .and_then(|r| parse_hex(&r.data))
+                {
+                    Ok(d) => d,
+                    Err(e) => return HttpResponse::error(400, e),
+                };
+
+                let r = radio
+                    .do_transmit(&data, self.options.blocking_options.clone())
+                    .and_then(|_| radio.start_receive().map_err(BlockingError::from));
+
+                match r {
+                    Ok(_) => HttpResponse::ok(json!({ "sent": data.len() })),
+                    Err(e) => HttpResponse::error(500, format!("{:?}", e)),
+                }
+            }
+            ("GET", "/frames") => {
+                let frames: Vec<_> = self
+                    .frames
+                    .drain(..)
+                    .map(|f| DecodedFrame::decode(&f, &self.registry, &["auto".to_string()]))
+                    .collect();
+                HttpResponse::ok(json!(frames))
+            }
+            ("GET", "/rssi") => match radio.poll_rssi() {
+                Ok(rssi) => HttpResponse::ok(json!({ "rssi": rssi })),
+                Err(e) => HttpResponse::error(500, format!("{:?}", e)),
+            },
+            ("PUT", "/config") => {
+                let c = match serde_json::from_slice::<ConfigRequest>(&req.body) {
+                    Ok(c) => c,
+                    Err(e) => return HttpResponse::error(400, e),
+                };
+
+                if let Some(p) = c.power
+                    && let Err(e) = radio.set_power(p)
+                {
+                    return HttpResponse::error(500, format!("{:?}", e));
+                }
+
+                HttpResponse::ok(json!({ "power": c.power }))
+            }
+            _ => HttpResponse::error(404, "not found"),
+        }
+    }
+}
+
+fn handle_connection<T, I, E>(
+    server: &mut Server,
+    radio: &mut T,
+    stream: TcpStream,
+) -> Result<(), std::io::Error>
+where
+    T: Transmit<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + Power<Error = E>
+        + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    let resp = match HttpRequest::read(&stream) {
+        Ok(req) => {
+            debug!("{} {}", req.method, req.path);
+            server.handle(radio, &req)
+        }
+        Err(e) => HttpResponse::error(e.status(), format!("{:?}", e)),
+    };
+
+    resp.write(&stream)
+}
+
+/// Run the HTTP control server, buffering received frames between requests
+pub fn do_serve<T, I, E>(radio: &mut T, options: ServeOptions) -> Result<(), BlockingError<E>>
+where
+    T: Transmit<Error = E>
+        + Receive<Info = I, Error = E>
+        + Rssi<Error = E>
+        + Power<Error = E>
+        + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let listener = TcpListener::bind(options.listen).expect("Error binding HTTP listener");
+    listener
+        .set_nonblocking(true)
+        .expect("Error configuring HTTP listener");
+
+    info!("Serving radio control API on http://{}", options.listen);
+
+    let poll_interval = options.blocking_options.poll_interval;
+    let mut server = Server::new(options);
+    let mut buff = [0u8; 1024];
+
+    radio.start_receive()?;
+
+    loop {
+        // Handle pending requests
+        match listener.accept() {
+            Ok((stream, _addr)) => {
+                if let Err(e) = handle_connection(&mut server, radio, stream) {
+                    debug!("HTTP connection error: {:?}", e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+            Err(e) => warn!("HTTP listener error: {:?}", e),
+        }
+
+        // Buffer received frames
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(&mut buff)?;
+            server.push(ReceivedFrame {
+                timestamp: SystemTime::now(),
+                rssi: i.rssi(),
+                data: buff[..n].to_vec(),
+                quality: ReceiveQuality::from_info(&i),
+                info: format!("{:?}", i),
+            });
+            radio.start_receive()?;
+        }
+
+        radio.delay_us(poll_interval.as_micros() as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::BlockingReceive;
+    use crate::helpers::UdpRadio;
+
+    fn request(method: &str, path: &str, body: &str) -> HttpRequest {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        HttpRequest::read(raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn serve_requests() {
+        let any = "127.0.0.1:0".parse().unwrap();
+        let mut peer = UdpRadio::new(any, any).unwrap();
+        let mut radio = UdpRadio::new(any, peer.local_addr().unwrap()).unwrap();
+        radio.set_rssi(-70);
+
+        let options = ServeOptions {
+            listen: any,
+            buffer: 1,
+            blocking_options: BlockingOptions::default(),
+        };
+        let mut server = Server::new(options);
+
+        let r = server.handle(&mut radio, &request("POST", "/send", r#"{"data":"0a0b"}"#));
+        assert_eq!(r, HttpResponse::ok(json!({ "sent": 2 })));
+
+        let mut buff = [0u8; 16];
+        let (n, _) = peer
+            .do_receive(&mut buff, BlockingOptions::default())
+            .unwrap();
+        assert_eq!(&buff[..n], &[0x0a, 0x0b]);
+
+        // Only the latest frame is kept with a buffer of one
+        for d in [[1u8], [2u8]] {
+            server.push(ReceivedFrame {
+                timestamp: SystemTime::UNIX_EPOCH,
+                rssi: -80,
+                data: d.to_vec(),
+                quality: Default::default(),
+                info: String::new(),
+            });
+        }
+        let r = server.handle(&mut radio, &request("GET", "/frames", ""));
+        assert_eq!(r.body[0]["data"], json!([2]));
+        assert_eq!(r.body.as_array().unwrap().len(), 1);
+
+        let r = server.handle(&mut radio, &request("GET", "/rssi", ""));
+        assert_eq!(r.body, json!({ "rssi": -70 }));
+
+        let r = server.handle(&mut radio, &request("PUT", "/config", r#"{"power":10}"#));
+        assert_eq!(r.status, 200);
+        assert_eq!(radio.power(), 10);
+
+        assert_eq!(
+            server
+                .handle(&mut radio, &request("GET", "/nope", ""))
+                .status,
+            404
+        );
+        assert_eq!(
+            server
+                .handle(&mut radio, &request("POST", "/send", "{}"))
+                .status,
+            400
+        );
+    }
+
+    #[test]
+    fn request_limits() {
+        let read = |raw: String| HttpRequest::read(raw.as_bytes()).map_err(|e| e.status());
+
+        // Content lengths are bounded before allocating
+        let raw = format!(
+            "POST /send HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            1u64 << 40
+        );
+        assert_eq!(read(raw), Err(413));
+
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(read(raw), Err(431));
+
+        let raw = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-A: b\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(read(raw), Err(431));
+
+        let raw = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADERS));
+        assert!(read(raw).is_ok());
+    }
+}
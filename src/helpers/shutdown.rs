@@ -0,0 +1,193 @@
+//! Graceful shutdown of continuous operations
+//!
+//! Continuous `rx`, `echo` and `rssi` loops poll a [`Shutdown`] signal (by default the
+//! process-wide [`Shutdown::global`]) and exit cleanly once it is triggered, flushing
+//! capture outputs and logging a [`RunSummary`] of packet counts, RSSI and run duration.
+//! With the `ctrlc` feature, [`install_shutdown_handler`] triggers the global signal on
+//! ctrl-c. The handler never exits the process, a second ctrl-c marks the signal as forced
+//! (see [`Shutdown::is_forced`]) so applications can choose to exit without waiting for the
+//! operation to finish.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log", feature = "ctrlc"))]
+use log::info;
+
+#[cfg(all(feature = "defmt", feature = "ctrlc"))]
+use defmt::info;
+
+use serde::{Deserialize, Serialize};
+
+use super::Samples;
+
+/// Process-wide shutdown signal
+static GLOBAL: OnceLock<Shutdown> = OnceLock::new();
+
+/// Shared shutdown signal, counting shutdown requests
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(Arc<AtomicU32>);
+
+impl Shutdown {
+    /// Process-wide shutdown signal, polled by continuous operations unless otherwise
+    /// configured (and triggered by [`install_shutdown_handler`])
+    pub fn global() -> Self {
+        GLOBAL.get_or_init(Shutdown::default).clone()
+    }
+
+    /// Request shutdown
+    pub fn trigger(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Check whether shutdown has been requested
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst) > 0
+    }
+
+    /// Check whether shutdown has been requested again since first triggered, for
+    /// applications exiting immediately rather than awaiting a graceful shutdown
+    pub fn is_forced(&self) -> bool {
+        self.0.load(Ordering::SeqCst) > 1
+    }
+}
+
+/// Signals are equal where they share the same underlying flag
+impl PartialEq for Shutdown {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Trigger the global shutdown signal on ctrl-c
+///
+/// Repeated ctrl-c marks the signal as forced rather than exiting the process, poll
+/// [`Shutdown::is_forced`] to exit without waiting for the operation to finish.
+#[cfg(feature = "ctrlc")]
+pub fn install_shutdown_handler() -> Result<Shutdown, ctrlc::Error> {
+    let shutdown = Shutdown::global();
+    let s = shutdown.clone();
+
+    ctrlc::set_handler(move || {
+        match s.is_triggered() {
+            true => info!("Shutdown forced"),
+            false => info!("Shutdown requested, ctrl-c again to force"),
+        }
+        s.trigger();
+    })?;
+
+    Ok(shutdown)
+}
+
+/// End-of-run statistics for continuous operations
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// Packets handled
+    pub packets: u64,
+    /// Bytes handled
+    pub bytes: u64,
+    /// RSSI of received packets (or polled samples)
+    pub rssi: Samples,
+    /// Run duration
+    pub elapsed: Duration,
+}
+
+impl RunSummary {
+    /// Record a packet of `len` bytes received at the provided RSSI
+    pub fn packet(&mut self, len: usize, rssi: i16) {
+        self.packets += 1;
+        self.bytes += len as u64;
+        self.rssi.update(rssi as f32);
+    }
+}
+
+impl core::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} packets ({} bytes) in {:.1} s, rssi (dBm): {}",
+            self.packets,
+            self.bytes,
+            self.elapsed.as_secs_f32(),
+            self.rssi
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::delay::DelayNs;
+
+    use super::*;
+    use crate::helpers::{RssiOptions, do_rssi};
+    use crate::{BasicInfo, Receive, Rssi};
+
+    /// Radio triggering shutdown after a number of RSSI polls
+    struct PollRadio {
+        polls: u32,
+        shutdown: Shutdown,
+    }
+
+    impl Receive for PollRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Err(())
+        }
+    }
+
+    impl Rssi for PollRadio {
+        type Error = ();
+
+        fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+            self.polls += 1;
+            if self.polls == 3 {
+                self.shutdown.trigger();
+            }
+            Ok(-90 + self.polls as i16)
+        }
+    }
+
+    impl DelayNs for PollRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn continuous_shutdown() {
+        let shutdown = Shutdown::default();
+        assert_ne!(shutdown, Shutdown::global());
+        assert_eq!(Shutdown::global(), Shutdown::global());
+
+        let mut radio = PollRadio {
+            polls: 0,
+            shutdown: shutdown.clone(),
+        };
+        let options = RssiOptions {
+            period: Duration::ZERO.into(),
+            continuous: true,
+            samples: 1,
+            shutdown,
+            channel_options: Default::default(),
+            output_options: Default::default(),
+        };
+
+        // Continuous polling exits once shutdown is triggered, returning the last batch
+        assert_eq!(do_rssi(&mut radio, options), Ok(vec![-87]));
+        assert_eq!(radio.polls, 3);
+
+        // Repeated requests force shutdown
+        assert!(!radio.shutdown.is_forced());
+        radio.shutdown.trigger();
+        assert!(radio.shutdown.is_forced());
+    }
+}
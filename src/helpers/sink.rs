@@ -0,0 +1,227 @@
+//! Pluggable packet sinks for receive output
+//!
+//! Decoded frames are written to a stack of [`PacketSink`]s by the decode pipeline, with
+//! console (plain or annotated), JSON, frame log, pcap and WebSocket sinks built from
+//! [`WorkerOptions`] and user-provided sinks (for example MQTT or database writers) added
+//! alongside them.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "helpers-pcap")]
+use pcap_file::pcap::{PcapPacket, PcapWriter};
+
+#[cfg(feature = "websocket")]
+use super::WsBroadcaster;
+use super::json;
+use super::{AnnotatedConsoleSink, DecodedFrame, FrameLogWriter, FrameRecord, WorkerOptions};
+
+/// Output for decoded frames
+pub trait PacketSink: Send {
+    /// Write a decoded frame
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error>;
+
+    /// Flush buffered output
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+/// Console sink, logging each frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsoleSink;
+
+impl PacketSink for ConsoleSink {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        info!("Received: {}", frame);
+        Ok(())
+    }
+}
+
+/// JSON lines sink
+pub struct JsonSink<W: Write> {
+    w: W,
+}
+
+impl JsonSink<BufWriter<File>> {
+    /// Create a new JSON lines file
+    pub fn create(path: &str) -> Result<Self, std::io::Error> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> JsonSink<W> {
+    /// Create a JSON lines sink writing to the provided writer
+    pub fn new(w: W) -> Self {
+        Self { w }
+    }
+}
+
+impl<W: Write + Send> PacketSink for JsonSink<W> {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        json::to_writer(&mut self.w, frame)?;
+        writeln!(self.w)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.w.flush()
+    }
+}
+
+impl<W: Write + Send> PacketSink for FrameLogWriter<W> {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        FrameLogWriter::write(
+            self,
+            &FrameRecord {
+                timestamp_us: frame.timestamp_us,
+                rssi: frame.rssi,
+                data: frame.data.clone(),
+            },
+        )
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        FrameLogWriter::flush(self)
+    }
+}
+
+#[cfg(feature = "helpers-pcap")]
+impl<W: Write + Send> PacketSink for PcapWriter<W> {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        let t = std::time::Duration::from_micros(frame.timestamp_us);
+        self.write_packet(&PcapPacket::new(t, frame.data.len() as u32, &frame.data))
+            .map(|_| ())
+            .map_err(std::io::Error::other)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl PacketSink for WsBroadcaster {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        self.broadcast(frame);
+        Ok(())
+    }
+}
+
+/// Stack of sinks, each receiving every frame in order
+#[derive(Default)]
+pub struct SinkStack {
+    sinks: Vec<Box<dyn PacketSink>>,
+}
+
+impl SinkStack {
+    /// Create an empty sink stack
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sink to the stack
+    pub fn push(&mut self, sink: impl PacketSink + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    /// Add a boxed sink to the stack
+    pub fn push_boxed(&mut self, sink: Box<dyn PacketSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Number of sinks in the stack
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Check whether the stack is empty
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+}
+
+impl PacketSink for SinkStack {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        self.sinks.iter_mut().try_for_each(|s| s.write(frame))
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.sinks.iter_mut().try_for_each(|s| s.flush())
+    }
+}
+
+impl WorkerOptions {
+    /// Build the sink stack for the configured outputs
+    pub fn sinks(&self) -> Result<SinkStack, std::io::Error> {
+        let mut s = SinkStack::new();
+        match self.annotate {
+            true => s.push(AnnotatedConsoleSink::new(&self.decode, !self.no_color)),
+            false => s.push(ConsoleSink),
+        }
+
+        #[cfg(feature = "helpers-pcap")]
+        if let Some(p) = self.pcap_options.open()? {
+            s.push_boxed(p);
+        }
+        if let Some(f) = &self.json_file {
+            s.push(JsonSink::create(f)?);
+        }
+        if let Some(f) = &self.frame_log {
+            s.push(FrameLogWriter::create(f)?);
+        }
+        #[cfg(feature = "websocket")]
+        if let Some(a) = self.ws_listen {
+            s.push(WsBroadcaster::listen(a)?);
+        }
+
+        Ok(s)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// User-provided sink collecting frames
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<DecodedFrame>>>);
+
+    impl PacketSink for Collect {
+        fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+            self.0.lock().unwrap().push(frame.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_stack() {
+        let frame = DecodedFrame {
+            timestamp_us: 1,
+            rssi: -70,
+            data: vec![0x61],
+            text: Some("a".into()),
+            protocol: None,
+            summary: None,
+            device: None,
+            quality: Default::default(),
+            info: String::new(),
+        };
+
+        let (a, b) = (Collect::default(), Collect::default());
+        let mut s = SinkStack::new();
+        s.push(a.clone());
+        s.push(JsonSink::new(vec![]));
+        s.push(b.clone());
+        assert_eq!(s.len(), 3);
+
+        s.write(&frame).unwrap();
+        s.write(&frame).unwrap();
+        s.flush().unwrap();
+
+        assert_eq!(a.0.lock().unwrap().len(), 2);
+        assert_eq!(b.0.lock().unwrap()[1], frame);
+    }
+}
@@ -0,0 +1,180 @@
+//! Radio sleep between periodic activity
+//!
+//! With `--sleep-between`, periodic transmit and echo operations on radios implementing
+//! [`State`] put the radio to sleep for the delay between activity (the transmit period,
+//! or the echo response delay), rather than leaving it idle. Wake-up time is measured on
+//! each wake and the following sleep shortened accordingly, so transmissions keep to the
+//! configured period, with sleep and wake-up timing logged. See
+//! [`do_transmit_sleep`](super::do_transmit_sleep) and
+//! [`do_echo_sleep`](super::do_echo_sleep).
+
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::debug;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+
+use super::Samples;
+use crate::{RadioState, State};
+
+/// Options for sleeping between activity
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct SleepOptions {
+    /// Put the radio to sleep between periodic activity (requires a radio implementing
+    /// State, see `do_operation_state`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub sleep_between: bool,
+}
+
+/// Sleep and wake-up statistics
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SleepStats {
+    /// Number of times the radio was put to sleep
+    pub sleeps: u64,
+    /// Total time asleep
+    pub asleep: Duration,
+    /// Wake-up times in microseconds
+    pub wake_us: Samples,
+}
+
+impl core::fmt::Display for SleepStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} sleeps, {:.1} s asleep, wake-up (us): {}",
+            self.sleeps,
+            self.asleep.as_secs_f32(),
+            self.wake_us
+        )
+    }
+}
+
+/// Radio sleep handling, tracking wake-up time to shorten following sleeps
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SleepTracker {
+    stats: SleepStats,
+    wake: Duration,
+}
+
+impl SleepTracker {
+    /// Create a new tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sleep the radio for `duration` (less the last measured wake-up time), returning the
+    /// radio to idle, or delay without sleeping where the duration is shorter than wake-up
+    pub fn sleep<T, E>(&mut self, radio: &mut T, duration: Duration) -> Result<(), E>
+    where
+        T: State<Error = E> + DelayNs,
+        E: core::fmt::Debug,
+    {
+        let asleep = match duration.checked_sub(self.wake) {
+            Some(d) if !d.is_zero() => d,
+            _ => {
+                radio.delay_us(duration.as_micros() as u32);
+                return Ok(());
+            }
+        };
+
+        radio.set_state(<T::State as RadioState>::sleep())?;
+        radio.delay_us(asleep.as_micros() as u32);
+
+        let t = Instant::now();
+        radio.set_state(<T::State as RadioState>::idle())?;
+        self.wake = t.elapsed();
+        debug!("Slept for {:?}, wake-up took {:?}", asleep, self.wake);
+
+        self.stats.sleeps += 1;
+        self.stats.asleep += asleep;
+        self.stats.wake_us.update(self.wake.as_micros() as f32);
+
+        Ok(())
+    }
+
+    /// Sleep and wake-up statistics
+    pub fn stats(&self) -> &SleepStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Mode {
+        Idle,
+        Sleep,
+    }
+
+    impl RadioState for Mode {
+        fn idle() -> Self {
+            Mode::Idle
+        }
+
+        fn sleep() -> Self {
+            Mode::Sleep
+        }
+    }
+
+    /// Radio taking 2 ms to wake, recording state changes and delays
+    #[derive(Default)]
+    struct SleepyRadio {
+        states: Vec<Mode>,
+        delays_us: Vec<u32>,
+    }
+
+    impl State for SleepyRadio {
+        type State = Mode;
+        type Error = ();
+
+        fn set_state(&mut self, state: Self::State) -> Result<(), Self::Error> {
+            if state == Mode::Idle {
+                std::thread::sleep(Duration::from_millis(2));
+            }
+            self.states.push(state);
+            Ok(())
+        }
+
+        fn get_state(&mut self) -> Result<Self::State, Self::Error> {
+            Err(())
+        }
+    }
+
+    impl DelayNs for SleepyRadio {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delays_us.push(ns / 1000);
+        }
+    }
+
+    #[test]
+    fn sleep_between() {
+        let mut radio = SleepyRadio::default();
+        let mut t = SleepTracker::new();
+
+        t.sleep(&mut radio, Duration::from_millis(10)).unwrap();
+        assert_eq!(radio.states, vec![Mode::Sleep, Mode::Idle]);
+        assert_eq!(radio.delays_us, vec![10_000]);
+
+        // Following sleeps are shortened by the measured wake-up time
+        t.sleep(&mut radio, Duration::from_millis(10)).unwrap();
+        assert!(radio.delays_us[1] <= 8_000);
+
+        // Delays shorter than wake-up don't sleep
+        t.sleep(&mut radio, Duration::from_micros(100)).unwrap();
+        assert_eq!(radio.states.len(), 4);
+        assert_eq!(radio.delays_us[2], 100);
+
+        assert_eq!(t.stats().sleeps, 2);
+        assert!(t.stats().wake_us.min().unwrap() >= 2_000.0);
+    }
+}
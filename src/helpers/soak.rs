@@ -0,0 +1,417 @@
+//! Long-duration soak testing with periodic summaries and rotating logs
+//!
+//! Soak tests repeatedly run echo or ping-pong operations for extended periods to
+//! qualify driver stability, recovering from (and recording) driver errors rather
+//! than exiting on the first failure.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::SystemTime;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::json;
+
+use super::{
+    ECHO_HEADROOM, EchoOptions, EchoTransform, JournalEvent, LinkTestInfo, PingPongOptions,
+    do_ping_pong_clocked, echo_response, journal,
+};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingReceive, BlockingTransmit},
+    clock::{Clock, StdClock},
+};
+
+/// Configuration for Soak operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct SoakOptions {
+    /// Total soak duration (runs until interrupted if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub duration: Option<HumanDuration>,
+
+    /// Interval between periodic summaries
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1h"))]
+    pub summary_interval: HumanDuration,
+
+    /// Directory for soak logs (summaries and recovery events as JSON lines)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub log_dir: Option<String>,
+
+    /// Interval at which a new log file is started
+    #[cfg_attr(feature = "clap", clap(long, default_value = "24h"))]
+    pub rotate_interval: HumanDuration,
+
+    /// Maximum consecutive driver errors before the soak test is aborted
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10"))]
+    pub max_errors: u32,
+
+    /// Delay before resuming after a driver error
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1s"))]
+    pub recovery_delay: HumanDuration,
+
+    #[cfg_attr(feature = "clap", clap(subcommand))]
+    pub mode: SoakMode,
+}
+
+/// Operation to run during a soak test
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub enum SoakMode {
+    #[cfg_attr(feature = "clap", clap(name = "echo"))]
+    /// Echo received messages (run against a ping-pong soak test)
+    Echo(EchoOptions),
+
+    #[cfg_attr(feature = "clap", clap(name = "ping-pong"))]
+    /// Repeat link tests of the configured number of rounds
+    PingPong(PingPongOptions),
+}
+
+/// Soak test summary, emitted periodically for the preceding interval and at completion
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SoakSummary {
+    /// Seconds since the start of the soak test
+    pub elapsed_secs: u64,
+    /// Link statistics, in echo mode `sent` counts received frames and `received` counts responses
+    pub link: LinkTestInfo,
+    /// Number of driver errors recovered from
+    pub recoveries: u32,
+}
+
+impl SoakSummary {
+    fn new(payload_len: usize) -> Self {
+        Self {
+            elapsed_secs: 0,
+            link: LinkTestInfo::new(payload_len),
+            recoveries: 0,
+        }
+    }
+
+    fn merge(&mut self, other: &SoakSummary) {
+        self.link.merge(&other.link);
+        self.recoveries += other.recoveries;
+    }
+}
+
+impl core::fmt::Display for SoakSummary {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{}s elapsed, {} recovery events",
+            self.elapsed_secs, self.recoveries
+        )?;
+        write!(f, "{}", self.link)
+    }
+}
+
+/// Soak log entries, written as one JSON object per line
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SoakEvent {
+    /// Periodic summary
+    Summary(SoakSummary),
+    /// Driver error, followed by recovery
+    Recovery { elapsed_secs: u64, error: String },
+    /// Soak test complete
+    Complete(SoakSummary),
+}
+
+/// Rotating soak log writer
+struct SoakLog {
+    dir: Option<String>,
+    rotate_interval: std::time::Duration,
+    /// Open log file and the (clock) time it was opened
+    file: Option<(File, u64)>,
+}
+
+impl SoakLog {
+    fn write(&mut self, event: &SoakEvent, now_us: u64) -> Result<(), std::io::Error> {
+        let dir = match &self.dir {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        // Start a new file if none is open or the rotation interval has elapsed
+        let rotate = match &self.file {
+            Some((_, opened)) => {
+                now_us.saturating_sub(*opened) >= self.rotate_interval.as_micros() as u64
+            }
+            None => true,
+        };
+        if rotate {
+            let t = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let path = std::path::Path::new(dir).join(format!("soak-{}.log", t));
+
+            std::fs::create_dir_all(dir)?;
+            let f = OpenOptions::new().create(true).append(true).open(path)?;
+            self.file = Some((f, now_us));
+        }
+
+        let (f, _) = self.file.as_mut().unwrap();
+        json::to_writer(&mut *f, event)?;
+        writeln!(f)?;
+        f.flush()
+    }
+}
+
+/// Run a soak test, returning the summary for the full run
+pub fn do_soak<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: SoakOptions,
+) -> Result<SoakSummary, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    do_soak_clocked(radio, buff, options, &StdClock)
+}
+
+/// Run a soak test, timing the run, summaries and log rotation with the provided clock
+pub fn do_soak_clocked<T, I, E, C>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: SoakOptions,
+    clock: &C,
+) -> Result<SoakSummary, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+    C: Clock,
+{
+    let mut log = SoakLog {
+        dir: options.log_dir.clone(),
+        rotate_interval: *options.rotate_interval,
+        file: None,
+    };
+
+    let payload_len = match &options.mode {
+        SoakMode::Echo(_) => 0,
+        SoakMode::PingPong(o) => o.payload_len(),
+    };
+
+    let start = clock.now_us();
+    let elapsed_secs = || clock.elapsed_us(start) / 1_000_000;
+    let mut period_start = start;
+    let mut total = SoakSummary::new(payload_len);
+    let mut period = SoakSummary::new(payload_len);
+    let mut errors = 0;
+
+    // Echo transforms are fixed for the duration of the test
+    let transform = match &options.mode {
+        SoakMode::Echo(o) => o.transform,
+        _ => EchoTransform::None,
+    };
+    let mut apply = |b: &mut [u8], n: usize, _i: &I| transform.apply(b, n);
+
+    // Set output power if specified
+    let power = match &options.mode {
+        SoakMode::Echo(o) => o.power,
+        SoakMode::PingPong(o) => o.power,
+    };
+    if let Some(p) = power {
+        radio.set_power(p)?;
+    }
+
+    loop {
+        let res = match &options.mode {
+            SoakMode::Echo(o) => soak_echo(radio, buff, o, &mut apply, &mut period),
+            SoakMode::PingPong(o) => {
+                let mut progress = o.progress_options.reporter();
+                do_ping_pong_clocked(radio, o.clone(), clock, &mut *progress)
+                    .map(|l| period.link.merge(&l))
+            }
+        };
+
+        // Record driver errors and back off before resuming
+        match res {
+            Ok(_) => errors = 0,
+            Err(e) => {
+                errors += 1;
+                period.recoveries += 1;
+
+                warn!(
+                    "Soak recovery event {}/{}: {:?}",
+                    errors, options.max_errors, e
+                );
+
+                let event = SoakEvent::Recovery {
+                    elapsed_secs: elapsed_secs(),
+                    error: format!("{:?}", e),
+                };
+                log.write(&event, clock.now_us())
+                    .expect("Error writing soak log");
+                journal(JournalEvent::Recovery {
+                    operation: "soak".to_string(),
+                    error: format!("{:?}", e),
+                });
+
+                if errors >= options.max_errors {
+                    return Err(e);
+                }
+
+                radio.delay_us(options.recovery_delay.as_micros() as u32);
+            }
+        }
+
+        let done = match options.duration {
+            Some(d) => clock.elapsed_us(start) >= d.as_micros() as u64,
+            None => false,
+        };
+
+        // Emit periodic summary
+        if done || clock.elapsed_us(period_start) >= options.summary_interval.as_micros() as u64 {
+            period.link.loss_bursts.finish();
+            period.elapsed_secs = elapsed_secs();
+
+            info!("Soak summary: {}", period);
+            log.write(&SoakEvent::Summary(period.clone()), clock.now_us())
+                .expect("Error writing soak log");
+
+            total.merge(&period);
+            period = SoakSummary::new(payload_len);
+            period_start = clock.now_us();
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    total.elapsed_secs = elapsed_secs();
+
+    info!("Soak test complete: {}", total);
+    log.write(&SoakEvent::Complete(total.clone()), clock.now_us())
+        .expect("Error writing soak log");
+
+    Ok(total)
+}
+
+/// Receive and respond to a single echo request, timeouts are not considered errors
+fn soak_echo<T, I, E, F>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: &EchoOptions,
+    transform: &mut F,
+    summary: &mut SoakSummary,
+) -> Result<(), BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+    F: FnMut(&mut [u8], usize, &I) -> usize,
+{
+    let mut work = vec![0u8; buff.len() + ECHO_HEADROOM];
+    let (n, i) = match radio.do_receive(&mut work[..buff.len()], options.blocking_options.clone()) {
+        Ok(r) => r,
+        Err(BlockingError::Timeout) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    summary.link.sent += 1;
+    summary.link.local_rssi.update(i.rssi() as f32);
+
+    let n = match echo_response(&mut work, n, &i, options, transform) {
+        Some(n) => n,
+        None => return Ok(()),
+    };
+
+    let frames = options.response_frames(&work[..n], buff.len());
+    if frames.is_empty() {
+        return Ok(());
+    }
+
+    radio.delay_us(options.delay.as_micros() as u32);
+    for f in &frames {
+        radio.do_transmit(f, options.blocking_options.clone())?;
+    }
+
+    summary.link.received += 1;
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "mock", feature = "json"))]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::clock::VirtualClock;
+    use crate::mock::{ImpairedRadio, Impairments};
+
+    #[test]
+    fn soak_ping_pong_summaries() {
+        let clock = Rc::new(VirtualClock::new());
+        let impairments = Impairments {
+            loss: 0.1,
+            latency: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let mut radio = ImpairedRadio::echo(impairments, 11).with_clock(clock.clone());
+
+        let dir = std::env::temp_dir().join(format!("radio-soak-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let options = SoakOptions {
+            duration: Some(Duration::from_secs(60).into()),
+            summary_interval: Duration::from_secs(20).into(),
+            log_dir: Some(dir.to_string_lossy().to_string()),
+            rotate_interval: Duration::from_secs(3600).into(),
+            max_errors: 3,
+            recovery_delay: Duration::from_secs(1).into(),
+            mode: SoakMode::PingPong(PingPongOptions {
+                rounds: 10,
+                ..Default::default()
+            }),
+        };
+        let total = do_soak_clocked(&mut radio, &mut [0u8; 255], options, &*clock).unwrap();
+
+        // The soak runs for the configured (virtual) duration, with rounds lost to the
+        // channel counted rather than aborting the test
+        assert!(total.elapsed_secs >= 60, "{}", total);
+        assert_eq!(total.link.sent % 10, 0);
+        assert!(total.link.received > 0 && total.link.received < total.link.sent);
+        assert_eq!(total.recoveries, 0);
+
+        // Periodic summaries cover the full run, followed by the completion summary
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let log = std::fs::read_to_string(path).unwrap();
+        let events: Vec<SoakEvent> = log.lines().map(|l| json::from_str(l).unwrap()).collect();
+
+        let (summaries, complete) = events.split_at(events.len() - 1);
+        assert_eq!(complete, &[SoakEvent::Complete(total.clone())]);
+        assert!(summaries.len() >= 3, "{:?}", summaries);
+
+        let sent: u32 = summaries
+            .iter()
+            .map(|e| match e {
+                SoakEvent::Summary(s) => s.link.sent,
+                e => panic!("unexpected event {:?}", e),
+            })
+            .sum();
+        assert_eq!(sent, total.link.sent);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,325 @@
+//! Pluggable packet sources for transmit input
+//!
+//! Payloads for [`do_transmit`](super::do_transmit) are read from a [`PacketSource`], with
+//! fixed data, stdin (one payload per line), frame log, generator and pcap sources
+//! selectable with `--source`, and user-provided sources passed to
+//! [`do_transmit_from`](super::do_transmit_from).
+
+use std::io::{BufRead, BufReader, Read, Stdin};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::warn;
+
+#[cfg(feature = "defmt")]
+use defmt::warn;
+
+#[cfg(feature = "helpers-pcap")]
+use pcap_file::pcap::PcapReader;
+
+#[cfg(feature = "helpers-pcap")]
+use super::CaptureReader;
+use super::{FrameLogReader, TemplateSource, TransmitOptions};
+use crate::aggregate::{AggregateError, Aggregator};
+
+/// Source of payloads to be transmitted
+pub trait PacketSource {
+    /// Fetch the next payload, returning `None` once the source is exhausted
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error>;
+}
+
+/// Fixed payload, returned once or repeated indefinitely
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixedSource {
+    data: Vec<u8>,
+    repeat: bool,
+    done: bool,
+}
+
+impl FixedSource {
+    /// Create a fixed source, repeating the payload indefinitely where `repeat` is set
+    pub fn new(data: &[u8], repeat: bool) -> Self {
+        Self {
+            data: data.to_vec(),
+            repeat,
+            done: false,
+        }
+    }
+}
+
+impl PacketSource for FixedSource {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = !self.repeat;
+        Ok(Some(self.data.clone()))
+    }
+}
+
+/// Line-delimited source, returning each line (without line endings) as a payload
+pub struct LineSource<R: BufRead> {
+    r: R,
+}
+
+impl LineSource<BufReader<Stdin>> {
+    /// Read payloads from stdin
+    pub fn stdin() -> Self {
+        Self::new(BufReader::new(std::io::stdin()))
+    }
+}
+
+impl<R: BufRead> LineSource<R> {
+    /// Read payloads from the provided reader
+    pub fn new(r: R) -> Self {
+        Self { r }
+    }
+}
+
+impl<R: BufRead> PacketSource for LineSource<R> {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let mut line = vec![];
+        if self.r.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+
+        while matches!(line.last(), Some(b'\n' | b'\r')) {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+}
+
+impl<R: Read> PacketSource for FrameLogReader<R> {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        self.next().transpose().map(|r| r.map(|r| r.data))
+    }
+}
+
+#[cfg(feature = "helpers-pcap")]
+impl<R: Read> PacketSource for PcapReader<R> {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        self.next_packet()
+            .transpose()
+            .map(|p| p.map(|p| p.data.into_owned()))
+            .map_err(std::io::Error::other)
+    }
+}
+
+/// Generated payloads of a fixed size, each starting with a big-endian sequence number
+/// followed by an incrementing byte pattern
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorSource {
+    size: usize,
+    count: Option<u32>,
+    index: u32,
+}
+
+impl GeneratorSource {
+    /// Create a generator for `count` payloads (or indefinitely if unset) of `size` bytes
+    pub fn new(size: usize, count: Option<u32>) -> Self {
+        Self {
+            size: size.max(4),
+            count,
+            index: 0,
+        }
+    }
+}
+
+impl PacketSource for GeneratorSource {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        if let Some(c) = self.count
+            && self.index >= c
+        {
+            return Ok(None);
+        }
+
+        let mut p = self.index.to_be_bytes().to_vec();
+        p.extend((0..self.size - 4).map(|j| j as u8));
+        self.index = self.index.wrapping_add(1);
+
+        Ok(Some(p))
+    }
+}
+
+/// Aggregating source, packing payloads from an inner source into aggregated frames of
+/// up to `size` bytes (see [`aggregate`](crate::aggregate))
+///
+/// Frames are returned once full or the inner source is exhausted, payloads too large to
+/// aggregate are skipped.
+pub struct AggregatingSource<S> {
+    inner: S,
+    size: usize,
+    pending: Option<Vec<u8>>,
+}
+
+impl<S: PacketSource> AggregatingSource<S> {
+    /// Aggregate payloads from the provided source into frames of up to `size` bytes
+    pub fn new(inner: S, size: usize) -> Self {
+        Self {
+            inner,
+            size,
+            pending: None,
+        }
+    }
+}
+
+impl<S: PacketSource> PacketSource for AggregatingSource<S> {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let mut a = Aggregator::new(vec![0u8; self.size]);
+
+        loop {
+            let p = match self.pending.take() {
+                Some(p) => p,
+                None => match self.inner.next_payload()? {
+                    Some(p) => p,
+                    None => break,
+                },
+            };
+
+            match a.push(&p) {
+                Ok(()) => (),
+                Err(AggregateError::Full) => {
+                    self.pending = Some(p);
+                    break;
+                }
+                Err(e) => warn!("Skipping {} byte payload: {:?}", p.len(), e),
+            }
+        }
+
+        match a.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(a.frame().to_vec())),
+        }
+    }
+}
+
+impl PacketSource for Box<dyn PacketSource> {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        (**self).next_payload()
+    }
+}
+
+/// Packet source selection, parsed from `stdin`, `file:PATH` (frame log),
+/// `gen:SIZE[:COUNT]` or `pcap:PATH`
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceSpec {
+    /// Lines from stdin
+    Stdin,
+    /// Frames from a frame log
+    File(String),
+    /// Generated payloads
+    Generator { size: usize, count: Option<u32> },
+    /// Packets from a PCAP or PCAP-NG capture
+    #[cfg(feature = "helpers-pcap")]
+    Pcap(String),
+}
+
+impl std::str::FromStr for SourceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+
+        match (kind, arg) {
+            ("stdin", "") => Ok(SourceSpec::Stdin),
+            ("file", p) if !p.is_empty() => Ok(SourceSpec::File(p.to_string())),
+            #[cfg(feature = "helpers-pcap")]
+            ("pcap", p) if !p.is_empty() => Ok(SourceSpec::Pcap(p.to_string())),
+            ("gen", a) => {
+                let (size, count) = match a.split_once(':') {
+                    Some((s, c)) => (s, Some(c)),
+                    None => (a, None),
+                };
+                Ok(SourceSpec::Generator {
+                    size: size.parse().map_err(|e| format!("{}: {}", size, e))?,
+                    count: count
+                        .map(|c| c.parse().map_err(|e| format!("{}: {}", c, e)))
+                        .transpose()?,
+                })
+            }
+            _ => Err(format!(
+                "unrecognised source '{}', expected stdin, file:PATH, gen:SIZE[:COUNT] or pcap:PATH",
+                s
+            )),
+        }
+    }
+}
+
+impl SourceSpec {
+    /// Open the selected source
+    pub fn open(&self) -> Result<Box<dyn PacketSource>, std::io::Error> {
+        let s: Box<dyn PacketSource> = match self {
+            SourceSpec::Stdin => Box::new(LineSource::stdin()),
+            SourceSpec::File(p) => Box::new(FrameLogReader::open(p)?),
+            SourceSpec::Generator { size, count } => Box::new(GeneratorSource::new(*size, *count)),
+            #[cfg(feature = "helpers-pcap")]
+            SourceSpec::Pcap(p) => Box::new(CaptureReader::open(p)?),
+        };
+        Ok(s)
+    }
+}
+
+impl TransmitOptions {
+    /// Open the configured payload source
+    ///
+    /// Fixed data and templates are sent once, or repeated with the configured period.
+    /// Payloads are packed into aggregated frames where `--aggregate` is set.
+    pub fn open_source(&self) -> Result<Box<dyn PacketSource>, std::io::Error> {
+        let repeat = self.period.is_some();
+
+        let source = match (&self.source, &self.template) {
+            (Some(s), _) => s.open()?,
+            (None, Some(t)) => Box::new(TemplateSource::new(t.clone(), self.node_id, repeat)),
+            (None, None) => Box::new(FixedSource::new(&self.data, repeat)),
+        };
+
+        match self.aggregate {
+            Some(size) => Ok(Box::new(AggregatingSource::new(source, size))),
+            None => Ok(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(s: &mut dyn PacketSource) -> Vec<Vec<u8>> {
+        std::iter::from_fn(|| s.next_payload().unwrap()).collect()
+    }
+
+    #[test]
+    fn packet_sources() {
+        assert_eq!(
+            drain(&mut FixedSource::new(&[1, 2], false)),
+            vec![vec![1, 2]]
+        );
+
+        let mut s = LineSource::new(&b"abc\r\n\nd"[..]);
+        assert_eq!(drain(&mut s), vec![b"abc".to_vec(), vec![], b"d".to_vec()]);
+
+        let mut s = GeneratorSource::new(6, Some(2));
+        assert_eq!(
+            drain(&mut s),
+            vec![vec![0, 0, 0, 0, 0, 1], vec![0, 0, 0, 1, 0, 1]]
+        );
+
+        // Small payloads are packed into aggregated frames
+        let mut s = AggregatingSource::new(GeneratorSource::new(4, Some(5)), 12);
+        let frames = drain(&mut s);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0], vec![4, 0, 0, 0, 0, 4, 0, 0, 0, 1]);
+
+        assert_eq!("stdin".parse(), Ok(SourceSpec::Stdin));
+        assert_eq!(
+            "gen:16:10".parse(),
+            Ok(SourceSpec::Generator {
+                size: 16,
+                count: Some(10)
+            })
+        );
+        assert_eq!("file:a.rflg".parse(), Ok(SourceSpec::File("a.rflg".into())));
+        assert!("gen:x".parse::<SourceSpec>().is_err());
+        assert!("file:".parse::<SourceSpec>().is_err());
+    }
+}
@@ -0,0 +1,311 @@
+//! Coordinated start of operations across multiple nodes
+//!
+//! Captures and link tests run on several nodes begin within a bounded skew, so logs
+//! from each node can be aligned directly. With `--start-at`, nodes with synchronised
+//! clocks (eg. via NTP or GPS) wait for a wall-clock trigger time. Otherwise one node
+//! runs with `--start-countdown`, broadcasting countdown frames carrying the time
+//! remaining until the start, with other nodes running with `--start-follow` starting
+//! once the received countdown elapses. Countdown skew is bounded by the countdown frame
+//! airtime plus the receive poll interval.
+//!
+//! The wall-clock start time is logged on each node for aligning logs.
+//!
+//! Frame format: `magic "RSTA" | remaining us (u32)`, big-endian.
+
+use std::time::{Duration, SystemTime};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::{Duration as HumanDuration, Timestamp};
+
+use crate::{
+    Receive, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+    clock::{Clock, StdClock},
+};
+
+/// Magic identifying countdown frames
+pub const START_MAGIC: [u8; 4] = *b"RSTA";
+
+/// Length of countdown frames
+pub const START_FRAME_LEN: usize = 8;
+
+/// Configuration for coordinated start
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct StartOptions {
+    /// Start at this wall-clock time (RFC3339), for nodes with synchronised clocks
+    #[cfg_attr(
+        feature = "clap",
+        clap(long, conflicts_with_all = ["start_countdown", "start_follow"])
+    )]
+    pub start_at: Option<Timestamp>,
+
+    /// Broadcast a countdown for this duration before starting, for nodes run with
+    /// `--start-follow`
+    #[cfg_attr(feature = "clap", clap(long, conflicts_with = "start_follow"))]
+    pub start_countdown: Option<HumanDuration>,
+
+    /// Await a countdown broadcast by a node run with `--start-countdown` before starting
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub start_follow: bool,
+
+    /// Interval between countdown frames
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100ms"))]
+    pub start_interval: HumanDuration,
+
+    /// Timeout awaiting a countdown (waits indefinitely if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub start_timeout: Option<HumanDuration>,
+}
+
+impl Default for StartOptions {
+    fn default() -> Self {
+        Self {
+            start_at: None,
+            start_countdown: None,
+            start_follow: false,
+            start_interval: Duration::from_millis(100).into(),
+            start_timeout: None,
+        }
+    }
+}
+
+impl StartOptions {
+    /// Check whether a coordinated start is configured
+    pub fn enabled(&self) -> bool {
+        self.start_at.is_some() || self.start_countdown.is_some() || self.start_follow
+    }
+}
+
+/// Encode a countdown frame
+pub fn start_frame(remaining: Duration) -> [u8; START_FRAME_LEN] {
+    let mut b = [0u8; START_FRAME_LEN];
+    b[..4].copy_from_slice(&START_MAGIC);
+    b[4..].copy_from_slice(&(remaining.as_micros().min(u32::MAX as u128) as u32).to_be_bytes());
+    b
+}
+
+/// Decode a countdown frame, returning the time remaining until the start
+pub fn parse_start_frame(data: &[u8]) -> Option<Duration> {
+    if data.len() != START_FRAME_LEN || data[..4] != START_MAGIC {
+        return None;
+    }
+    let us = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    Some(Duration::from_micros(us as u64))
+}
+
+/// Wait for the configured coordinated start, returning the wall-clock start time
+/// (`None` where no coordinated start is configured)
+pub fn wait_start<T, E>(
+    radio: &mut T,
+    options: &StartOptions,
+    blocking: &BlockingOptions,
+) -> Result<Option<SystemTime>, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    if wait_start_clocked(radio, options, blocking, &StdClock)?.is_none() {
+        return Ok(None);
+    }
+
+    let t = SystemTime::now();
+    info!(
+        "Coordinated start at {}",
+        humantime::format_rfc3339_micros(t)
+    );
+
+    Ok(Some(t))
+}
+
+/// Wait for the configured coordinated start, timing countdowns with the provided clock
+/// and returning the start time in clock microseconds (`None` where no coordinated start
+/// is configured)
+///
+/// Starting at a wall-clock time (`--start-at`) reads the system clock, so targets
+/// without one must use countdowns.
+pub fn wait_start_clocked<T, E, C>(
+    radio: &mut T,
+    options: &StartOptions,
+    blocking: &BlockingOptions,
+    clock: &C,
+) -> Result<Option<u64>, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    C: Clock,
+{
+    let start = if let Some(t) = options.start_at {
+        let now = SystemTime::now();
+        match SystemTime::from(t).duration_since(now) {
+            Ok(d) => clock.now_us() + d.as_micros() as u64,
+            Err(_) => {
+                warn!("Start time {} has passed, starting immediately", t);
+                clock.now_us()
+            }
+        }
+    } else if let Some(d) = options.start_countdown {
+        countdown(radio, *d, *options.start_interval, blocking, clock)?
+    } else if options.start_follow {
+        follow(radio, options.start_timeout.map(|t| *t), blocking, clock)?
+    } else {
+        return Ok(None);
+    };
+
+    delay_until(radio, clock, start);
+
+    Ok(Some(start))
+}
+
+/// Broadcast countdown frames until the countdown elapses, returning the start time
+fn countdown<T, E, C>(
+    radio: &mut T,
+    duration: Duration,
+    interval: Duration,
+    blocking: &BlockingOptions,
+    clock: &C,
+) -> Result<u64, BlockingError<E>>
+where
+    T: Transmit<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    C: Clock,
+{
+    let start = clock.now_us() + duration.as_micros() as u64;
+    let interval_us = interval.as_micros() as u64;
+
+    info!("Broadcasting start countdown ({:?})", duration);
+
+    let mut sent = 0;
+    loop {
+        let now = clock.now_us();
+        if now >= start {
+            break;
+        }
+
+        let remaining = Duration::from_micros(start - now);
+        radio.do_transmit(&start_frame(remaining), blocking.clone())?;
+        sent += 1;
+
+        delay_until(radio, clock, (clock.now_us() + interval_us).min(start));
+    }
+
+    debug!("Sent {} countdown frames", sent);
+
+    Ok(start)
+}
+
+/// Await a countdown frame, returning the start time
+fn follow<T, E, C>(
+    radio: &mut T,
+    timeout: Option<Duration>,
+    blocking: &BlockingOptions,
+    clock: &C,
+) -> Result<u64, BlockingError<E>>
+where
+    T: Receive<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+    C: Clock,
+{
+    let mut buff = [0u8; 255];
+    let t = clock.now_us();
+
+    info!("Awaiting start countdown");
+
+    radio.start_receive()?;
+
+    loop {
+        if timeout.is_some_and(|d| clock.elapsed_us(t) > d.as_micros() as u64) {
+            return Err(BlockingError::Timeout);
+        }
+
+        if radio.check_receive(true)? {
+            let (n, _) = radio.get_received(&mut buff)?;
+            let received = clock.now_us();
+
+            match parse_start_frame(&buff[..n]) {
+                Some(remaining) => {
+                    debug!("Received countdown, {:?} remaining", remaining);
+                    return Ok(received + remaining.as_micros() as u64);
+                }
+                None => radio.start_receive()?,
+            }
+        }
+
+        radio.delay_us(blocking.poll_interval.as_micros() as u32);
+    }
+}
+
+/// Delay until the provided clock time
+fn delay_until<T: DelayNs, C: Clock>(radio: &mut T, clock: &C, t: u64) {
+    loop {
+        let now = clock.now_us();
+        if now >= t {
+            break;
+        }
+        radio.delay_us((t - now).min(1_000_000) as u32);
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::test_support::impaired;
+
+    #[test]
+    fn coordinated_start() {
+        assert_eq!(
+            parse_start_frame(&start_frame(Duration::from_millis(250))),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_start_frame(b"RSTB\0\0\0\0"), None);
+
+        // Countdown broadcasts frames until the start
+        let (mut radio, clock) = impaired(|_| None);
+        let blocking = BlockingOptions::default();
+        let options = StartOptions {
+            start_countdown: Some(Duration::from_millis(50).into()),
+            start_interval: Duration::from_millis(10).into(),
+            ..Default::default()
+        };
+        let started = wait_start_clocked(&mut radio, &options, &blocking, &*clock).unwrap();
+        assert_eq!(started, Some(50_000));
+        assert!(clock.now_us() >= 50_000);
+        let sent = radio.stats().frames;
+        assert!(sent >= 3, "sent {}", sent);
+
+        // Followers start once a received countdown elapses
+        radio.inject(&start_frame(Duration::from_millis(30)));
+        let options = StartOptions {
+            start_follow: true,
+            ..Default::default()
+        };
+        let t = clock.now_us();
+        wait_start_clocked(&mut radio, &options, &blocking, &*clock).unwrap();
+        assert!(clock.now_us() - t >= 30_000);
+
+        // Or time out awaiting a countdown
+        let options = StartOptions {
+            start_follow: true,
+            start_timeout: Some(Duration::from_millis(5).into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            wait_start_clocked(&mut radio, &options, &blocking, &*clock),
+            Err(BlockingError::Timeout)
+        );
+
+        // Unconfigured starts return immediately
+        assert_eq!(
+            wait_start(&mut radio, &StartOptions::default(), &blocking),
+            Ok(None)
+        );
+    }
+}
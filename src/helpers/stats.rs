@@ -0,0 +1,569 @@
+//! Statistics helpers for link test reporting
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::frame::{self, Address};
+
+/// Burst loss tracking for link tests
+///
+/// Average loss obscures bursty failures, so this records the length of each run of
+/// consecutive lost packets and the longest outage observed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BurstLoss {
+    /// Histogram of loss burst lengths (burst length -> count)
+    pub histogram: BTreeMap<u32, u32>,
+    /// Longest run of consecutive lost packets
+    pub longest: u32,
+    /// Length of the current (unfinished) burst
+    #[serde(skip)]
+    current: u32,
+}
+
+impl BurstLoss {
+    /// Record the outcome of a round
+    pub fn update(&mut self, received: bool) {
+        match received {
+            true => self.finish(),
+            false => {
+                self.current += 1;
+                self.longest = self.longest.max(self.current);
+            }
+        }
+    }
+
+    /// Close any in-progress burst, called at the end of a test
+    pub fn finish(&mut self) {
+        if self.current > 0 {
+            *self.histogram.entry(self.current).or_default() += 1;
+            self.current = 0;
+        }
+    }
+
+    /// Number of completed loss bursts
+    pub fn bursts(&self) -> u32 {
+        self.histogram.values().sum()
+    }
+
+    /// Merge completed bursts from another tracker, used to aggregate repeated tests
+    pub fn merge(&mut self, other: &BurstLoss) {
+        for (l, c) in &other.histogram {
+            *self.histogram.entry(*l).or_default() += c;
+        }
+        self.longest = self.longest.max(other.longest);
+    }
+
+    /// Mean burst length, or zero if no losses occurred
+    pub fn mean_length(&self) -> f32 {
+        let lost: u32 = self.histogram.iter().map(|(l, c)| l * c).sum();
+        match self.bursts() {
+            0 => 0.0,
+            n => lost as f32 / n as f32,
+        }
+    }
+}
+
+/// Samples retained exactly, beyond which percentiles are estimated from a histogram
+const EXACT_SAMPLES: usize = 1024;
+
+/// Ratio between histogram bucket bounds, limiting the relative error of estimated
+/// percentiles to about 1%
+const BUCKET_GAMMA: f64 = 1.02;
+
+/// Offset keeping bucket indices for magnitudes above [`BUCKET_MIN`] positive
+const BUCKET_BIAS: i32 = 1_000;
+
+/// Magnitudes below this are counted in the zero bucket
+const BUCKET_MIN: f64 = 1e-6;
+
+/// Bounded sample set supporting percentiles and confidence intervals
+///
+/// The count, mean and variance are tracked incrementally (using Welford's method),
+/// with the first [`EXACT_SAMPLES`] values retained for exact percentiles. Larger sets
+/// are folded into a histogram of logarithmically sized buckets, bounding memory use for
+/// long running tests while estimating percentiles to within about 1%.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Samples {
+    count: u64,
+    mean: f64,
+    m2: f64,
+    min: f32,
+    max: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    values: Vec<f32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    buckets: BTreeMap<i32, u64>,
+}
+
+impl Samples {
+    /// Create a new empty sample set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sample
+    pub fn update(&mut self, value: f32) {
+        self.merge_moments(1, value as f64, 0.0, value, value);
+
+        match self.buckets.is_empty() && self.values.len() < EXACT_SAMPLES {
+            true => self.values.push(value),
+            false => {
+                self.fold();
+                *self.buckets.entry(bucket(value)).or_default() += 1;
+            }
+        }
+    }
+
+    /// Append samples from another sample set
+    pub fn merge(&mut self, other: &Samples) {
+        if other.count == 0 {
+            return;
+        }
+        self.merge_moments(other.count, other.mean, other.m2, other.min, other.max);
+
+        match self.buckets.is_empty()
+            && other.buckets.is_empty()
+            && self.values.len() + other.values.len() <= EXACT_SAMPLES
+        {
+            true => self.values.extend_from_slice(&other.values),
+            false => {
+                self.fold();
+                for v in &other.values {
+                    *self.buckets.entry(bucket(*v)).or_default() += 1;
+                }
+                for (b, c) in &other.buckets {
+                    *self.buckets.entry(*b).or_default() += c;
+                }
+            }
+        }
+    }
+
+    /// Number of samples
+    pub fn count(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Raw sample values in the order they were recorded, empty once the sample set
+    /// exceeds the exactly retained samples
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    /// Minimum sample value
+    pub fn min(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// Maximum sample value
+    pub fn max(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Sample mean
+    pub fn mean(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.mean as f32)
+    }
+
+    /// Sample standard deviation
+    pub fn std_dev(&self) -> Option<f32> {
+        match self.count {
+            0 => None,
+            1 => Some(0.0),
+            n => Some((self.m2 / (n - 1) as f64).sqrt() as f32),
+        }
+    }
+
+    /// Percentile (`0.0..=100.0`) using linear interpolation between closest ranks,
+    /// estimated from the histogram bucket containing the rank for large sample sets
+    pub fn percentile(&self, p: f32) -> Option<f32> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = (p.clamp(0.0, 100.0) as f64 / 100.0) * (self.count - 1) as f64;
+
+        if self.buckets.is_empty() {
+            let mut sorted = self.values.clone();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            let f = (rank - lo as f64) as f32;
+            return Some(sorted[lo] + (sorted[hi] - sorted[lo]) * f);
+        }
+
+        // Extremes are tracked exactly
+        if rank == 0.0 {
+            return Some(self.min);
+        } else if rank >= (self.count - 1) as f64 {
+            return Some(self.max);
+        }
+
+        let mut seen = 0;
+        for (b, c) in &self.buckets {
+            seen += c;
+            if seen as f64 > rank.floor() {
+                return Some(bucket_value(*b).clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// 95% confidence interval for the mean (normal approximation)
+    pub fn ci95(&self) -> Option<(f32, f32)> {
+        let mean = self.mean()?;
+        let e = 1.96 * self.std_dev()? / (self.count as f32).sqrt();
+        Some((mean - e, mean + e))
+    }
+
+    /// Combine running moments with those of another set (Chan et al.)
+    fn merge_moments(&mut self, count: u64, mean: f64, m2: f64, min: f32, max: f32) {
+        let n = self.count + count;
+        let delta = mean - self.mean;
+
+        self.m2 += m2 + delta * delta * (self.count * count) as f64 / n as f64;
+        self.mean += delta * count as f64 / n as f64;
+        (self.min, self.max) = match self.count {
+            0 => (min, max),
+            _ => (self.min.min(min), self.max.max(max)),
+        };
+        self.count = n;
+    }
+
+    /// Move retained values into the histogram
+    fn fold(&mut self) {
+        for v in self.values.drain(..) {
+            *self.buckets.entry(bucket(v)).or_default() += 1;
+        }
+    }
+}
+
+/// Histogram bucket for a value, ordered by value with bucket zero holding values of
+/// negligible magnitude
+fn bucket(v: f32) -> i32 {
+    let m = (v as f64).abs();
+    if m < BUCKET_MIN || m.is_nan() {
+        return 0;
+    }
+
+    let b = (m.ln() / BUCKET_GAMMA.ln()).ceil() as i32 + BUCKET_BIAS;
+    match v < 0.0 {
+        true => -b,
+        false => b,
+    }
+}
+
+/// Representative value for a histogram bucket, between the bucket bounds
+fn bucket_value(b: i32) -> f32 {
+    if b == 0 {
+        return 0.0;
+    }
+
+    let upper = BUCKET_GAMMA.powi(b.abs() - BUCKET_BIAS);
+    let m = 2.0 * upper / (1.0 + BUCKET_GAMMA);
+    match b < 0 {
+        true => -m as f32,
+        false => m as f32,
+    }
+}
+
+impl core::fmt::Display for Samples {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (mean, sd, (lo, hi)) = match (self.mean(), self.std_dev(), self.ci95()) {
+            (Some(m), Some(s), Some(c)) => (m, s, c),
+            _ => return write!(f, "n=0"),
+        };
+
+        write!(
+            f,
+            "n={} mean={:.2} (95% CI {:.2}..{:.2}) sd={:.2} min={:.2} p50={:.2} p95={:.2} p99={:.2} max={:.2}",
+            self.count(),
+            mean,
+            lo,
+            hi,
+            sd,
+            self.min().unwrap_or_default(),
+            self.percentile(50.0).unwrap_or_default(),
+            self.percentile(95.0).unwrap_or_default(),
+            self.percentile(99.0).unwrap_or_default(),
+            self.max().unwrap_or_default(),
+        )
+    }
+}
+
+/// Per-peer receive statistics
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// Frames received from the peer
+    pub packets: u64,
+    /// Bytes received from the peer (including frame headers)
+    pub bytes: u64,
+    /// Frames lost, inferred from gaps in frame sequence numbers
+    pub lost: u64,
+    /// Minimum received RSSI
+    pub rssi_min: Option<i16>,
+    /// Maximum received RSSI
+    pub rssi_max: Option<i16>,
+    rssi_sum: i64,
+    #[serde(skip)]
+    last_seq: Option<u8>,
+}
+
+impl PeerStats {
+    /// Record a frame received from the peer
+    pub fn update(&mut self, seq: u8, len: usize, rssi: i16) {
+        // Sequence gaps indicate loss, duplicates and reordering are ignored
+        if let Some(last) = self.last_seq {
+            let gap = seq.wrapping_sub(last).wrapping_sub(1);
+            if gap < 128 {
+                self.lost += gap as u64;
+            }
+        }
+        self.last_seq = Some(seq);
+
+        self.packets += 1;
+        self.bytes += len as u64;
+        self.rssi_sum += rssi as i64;
+        self.rssi_min = Some(self.rssi_min.map_or(rssi, |r| r.min(rssi)));
+        self.rssi_max = Some(self.rssi_max.map_or(rssi, |r| r.max(rssi)));
+    }
+
+    /// Mean received RSSI
+    pub fn rssi_mean(&self) -> Option<f32> {
+        match self.packets {
+            0 => None,
+            n => Some(self.rssi_sum as f32 / n as f32),
+        }
+    }
+
+    /// Fraction of frames lost (`0.0..=1.0`)
+    pub fn loss(&self) -> f32 {
+        match self.packets + self.lost {
+            0 => 0.0,
+            n => self.lost as f32 / n as f32,
+        }
+    }
+}
+
+/// Per-peer statistics table, keyed by [`frame`](crate::frame) source address
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PeerTable {
+    peers: BTreeMap<Address, PeerStats>,
+}
+
+impl PeerTable {
+    /// Create a new empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a received frame, returning the source address where the frame has a valid header
+    pub fn update(&mut self, data: &[u8], rssi: i16) -> Option<Address> {
+        let (h, _) = frame::decode(data).ok()?;
+        self.peers
+            .entry(h.src)
+            .or_default()
+            .update(h.seq, data.len(), rssi);
+        Some(h.src)
+    }
+
+    /// Fetch statistics for a peer
+    pub fn get(&self, address: Address) -> Option<&PeerStats> {
+        self.peers.get(&address)
+    }
+
+    /// Iterate over peers in address order
+    pub fn iter(&self) -> impl Iterator<Item = (&Address, &PeerStats)> {
+        self.peers.iter()
+    }
+
+    /// Check whether any peers have been seen
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+impl core::fmt::Display for PeerTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:>6} {:>8} {:>10} {:>8} {:>7} {:>8} {:>8} {:>8}",
+            "peer", "packets", "bytes", "lost", "loss%", "rssi", "min", "max"
+        )?;
+
+        for (a, p) in &self.peers {
+            write!(
+                f,
+                "\n{:>6} {:>8} {:>10} {:>8} {:>7.2} {:>8.1} {:>8} {:>8}",
+                format!("{:04x}", a),
+                p.packets,
+                p.bytes,
+                p.lost,
+                p.loss() * 100.0,
+                p.rssi_mean().unwrap_or_default(),
+                p.rssi_min.unwrap_or_default(),
+                p.rssi_max.unwrap_or_default(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compression statistics
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompressionStats {
+    /// Payloads processed
+    pub payloads: u32,
+    /// Uncompressed payload bytes
+    pub raw_bytes: u64,
+    /// Compressed (encoded) payload bytes
+    pub compressed_bytes: u64,
+    /// Received payloads failing decompression
+    pub failures: u32,
+}
+
+impl CompressionStats {
+    /// Achieved compression ratio (uncompressed / compressed bytes)
+    pub fn ratio(&self) -> f32 {
+        match self.compressed_bytes {
+            0 => 1.0,
+            n => self.raw_bytes as f32 / n as f32,
+        }
+    }
+}
+
+impl core::fmt::Display for CompressionStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "payloads {} raw {} B compressed {} B (ratio {:.2}) failures {}",
+            self.payloads,
+            self.raw_bytes,
+            self.compressed_bytes,
+            self.ratio(),
+            self.failures
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_statistics() {
+        let mut s = Samples::new();
+        assert_eq!(s.mean(), None);
+        assert_eq!(s.percentile(50.0), None);
+
+        for v in 1..=100 {
+            s.update(v as f32);
+        }
+
+        assert_eq!(s.count(), 100);
+        assert_eq!(s.min(), Some(1.0));
+        assert_eq!(s.max(), Some(100.0));
+        assert_eq!(s.mean(), Some(50.5));
+        assert_eq!(s.percentile(0.0), Some(1.0));
+        assert_eq!(s.percentile(50.0), Some(50.5));
+        assert_eq!(s.percentile(100.0), Some(100.0));
+
+        let (lo, hi) = s.ci95().unwrap();
+        assert!(lo < 50.5 && hi > 50.5);
+        assert!((hi - lo - 2.0 * 1.96 * s.std_dev().unwrap() / 10.0).abs() < 1e-3);
+
+        // Merged sets match a set of the combined samples
+        let mut m = Samples::new();
+        for v in -50..=49 {
+            m.update(v as f32);
+        }
+        m.merge(&s);
+        assert_eq!(m.count(), 200);
+        assert_eq!((m.min(), m.max()), (Some(-50.0), Some(100.0)));
+        assert_eq!(m.mean(), Some(25.0));
+        assert_eq!(m.percentile(50.0), Some(25.0));
+    }
+
+    #[test]
+    fn bounded_sample_statistics() {
+        let mut s = Samples::new();
+        for i in 0..100_000 {
+            s.update((i % 1000) as f32 - 500.0);
+        }
+
+        // Samples are folded into the histogram, with statistics retained
+        assert!(s.values().is_empty());
+        assert!(s.buckets.len() < 1000);
+        assert_eq!(s.count(), 100_000);
+        assert_eq!((s.min(), s.max()), (Some(-500.0), Some(499.0)));
+        assert!((s.mean().unwrap() + 0.5).abs() < 1e-3);
+        assert!((s.std_dev().unwrap() - 288.68).abs() < 0.1);
+
+        // Percentiles estimated to within the bucket width
+        for (p, v) in [(5.0, -450.0), (25.0, -250.0), (75.0, 250.0), (99.0, 490.0)] {
+            let e = s.percentile(p).unwrap();
+            assert!((e - v).abs() <= v.abs() * 0.02 + 1.0, "p{}: {}", p, e);
+        }
+        assert_eq!(s.percentile(100.0), Some(499.0));
+    }
+
+    #[test]
+    fn burst_loss_tracking() {
+        let mut b = BurstLoss::default();
+
+        for r in [true, false, false, true, false, true, false, false, false] {
+            b.update(r);
+        }
+        b.finish();
+
+        assert_eq!(b.longest, 3);
+        assert_eq!(b.bursts(), 3);
+        assert_eq!(b.histogram.get(&1), Some(&1));
+        assert_eq!(b.histogram.get(&2), Some(&1));
+        assert_eq!(b.histogram.get(&3), Some(&1));
+        assert_eq!(b.mean_length(), 2.0);
+
+        let mut m = BurstLoss::default();
+        m.update(false);
+        m.finish();
+        m.merge(&b);
+        assert_eq!(m.longest, 3);
+        assert_eq!(m.histogram.get(&1), Some(&2));
+        assert_eq!(m.bursts(), 4);
+    }
+
+    #[test]
+    fn peer_statistics() {
+        use crate::frame::Header;
+
+        let mut t = PeerTable::new();
+        let mut buff = [0u8; 16];
+
+        for (src, seq, rssi) in [
+            (1, 0, -60),
+            (1, 1, -70),
+            (1, 4, -80),
+            (2, 255, -50),
+            (2, 0, -50),
+        ] {
+            let n = frame::encode(&Header::new(src, 0, seq), &[0xaa; 4], &mut buff).unwrap();
+            assert_eq!(t.update(&buff[..n], rssi), Some(src));
+        }
+        assert_eq!(t.update(&[0x00], -40), None);
+
+        let a = t.get(1).unwrap();
+        assert_eq!((a.packets, a.bytes, a.lost), (3, 30, 2));
+        assert_eq!(a.rssi_mean(), Some(-70.0));
+        assert_eq!((a.rssi_min, a.rssi_max), (Some(-80), Some(-60)));
+        assert_eq!(a.loss(), 0.4);
+
+        // Sequence numbers wrap without loss
+        assert_eq!(t.get(2).unwrap().lost, 0);
+
+        let s = t.to_string();
+        assert_eq!(s.lines().count(), 3);
+        assert!(s.lines().nth(1).unwrap().trim_start().starts_with("0001"));
+    }
+}
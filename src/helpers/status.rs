@@ -0,0 +1,281 @@
+//! Live radio state display for radios implementing [`StateQuery`]
+//!
+//! With `--show-state`, operations run with [`do_operation_status`](super::do_operation_status)
+//! query the radio state while polling, logging and journaling each state change. With
+//! `--stuck-after`, a warning is raised (and journaled) where the radio remains in the same
+//! transmit, receive or error state for longer than expected, showing at a glance when a
+//! driver is stuck in an unexpected state.
+
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+
+use super::{JournalEvent, journal};
+use crate::{Power, RadioStatus, Receive, Rssi, StateQuery, Transmit};
+
+/// Options for live state display
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct StatusOptions {
+    /// Display radio state changes while operations run (requires a radio implementing
+    /// StateQuery, see `do_operation_status`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub show_state: bool,
+
+    /// Warn where the radio remains in a transmit, receive or error state for this long
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub stuck_after: Option<HumanDuration>,
+
+    /// Minimum interval between radio state queries
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10ms"))]
+    pub state_interval: HumanDuration,
+}
+
+impl Default for StatusOptions {
+    fn default() -> Self {
+        Self {
+            show_state: false,
+            stuck_after: None,
+            state_interval: Duration::from_millis(10).into(),
+        }
+    }
+}
+
+impl StatusOptions {
+    /// Check whether state display is enabled
+    pub fn enabled(&self) -> bool {
+        self.show_state || self.stuck_after.is_some()
+    }
+}
+
+/// Radio wrapper tracking radio state, queried while the wrapped operation polls (delays)
+pub struct StateMonitor<T> {
+    radio: T,
+    options: StatusOptions,
+    operation: String,
+    state: Option<(RadioStatus, Instant)>,
+    last_query: Option<Instant>,
+    stuck: bool,
+}
+
+impl<T, E> StateMonitor<T>
+where
+    T: StateQuery<Error = E>,
+    E: core::fmt::Debug,
+{
+    /// Wrap a radio, recording state changes against the named operation
+    pub fn new(radio: T, options: StatusOptions, operation: &str) -> Self {
+        Self {
+            radio,
+            options,
+            operation: operation.to_string(),
+            state: None,
+            last_query: None,
+            stuck: false,
+        }
+    }
+
+    /// Current radio state, as last queried
+    pub fn state(&self) -> Option<RadioStatus> {
+        self.state.map(|(s, _)| s)
+    }
+
+    /// Query the radio state, logging changes and stuck states, returning the state
+    pub fn update(&mut self) -> Result<RadioStatus, E> {
+        let now = Instant::now();
+        let state = self.radio.query_state()?;
+        self.last_query = Some(now);
+
+        match self.state {
+            // Warn once where the radio remains in an active state beyond the limit
+            Some((s, since)) if s == state => {
+                let elapsed = now.duration_since(since);
+                let active = !matches!(s, RadioStatus::Idle | RadioStatus::Sleep);
+                if let Some(limit) = self.options.stuck_after
+                    && active
+                    && !self.stuck
+                    && elapsed >= *limit
+                {
+                    self.stuck = true;
+                    warn!("Radio stuck in {} for {:?}", s, elapsed);
+                    self.record(s, elapsed, true);
+                }
+            }
+            previous => {
+                let elapsed = previous.map(|(_, t)| now.duration_since(t));
+                if self.options.show_state {
+                    match previous {
+                        Some((p, _)) => info!("Radio state: {} -> {}", p, state),
+                        None => info!("Radio state: {}", state),
+                    }
+                }
+                self.record(state, elapsed.unwrap_or_default(), false);
+                self.state = Some((state, now));
+                self.stuck = false;
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Journal a state change (with the time spent in the previous state) or stuck state
+    fn record(&self, state: RadioStatus, elapsed: Duration, stuck: bool) {
+        journal(JournalEvent::State {
+            operation: self.operation.clone(),
+            state: state.to_string(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            stuck,
+        });
+    }
+
+    /// Query the radio state where the query interval has elapsed
+    fn poll(&mut self) {
+        if self
+            .last_query
+            .is_some_and(|t| t.elapsed() < *self.options.state_interval)
+        {
+            return;
+        }
+
+        if let Err(e) = self.update() {
+            debug!("Error querying radio state: {:?}", e);
+        }
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+}
+
+impl<T: Transmit> Transmit for StateMonitor<T> {
+    type Error = T::Error;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.radio.start_transmit(data)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit()
+    }
+}
+
+impl<T: Receive> Receive for StateMonitor<T> {
+    type Info = T::Info;
+    type Error = T::Error;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive()
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio.check_receive(restart)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff)
+    }
+}
+
+impl<T: Power> Power for StateMonitor<T> {
+    type Error = T::Error;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power)
+    }
+}
+
+impl<T: Rssi> Rssi for StateMonitor<T> {
+    type Error = T::Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi()
+    }
+}
+
+impl<T: StateQuery> StateQuery for StateMonitor<T> {
+    type Error = T::Error;
+
+    fn query_state(&mut self) -> Result<RadioStatus, Self::Error> {
+        self.radio.query_state()
+    }
+}
+
+/// Radio state is queried on each delay, as operations delay between polls
+impl<T, E> DelayNs for StateMonitor<T>
+where
+    T: StateQuery<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    fn delay_ns(&mut self, ns: u32) {
+        self.poll();
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Radio reporting a scripted sequence of states
+    struct Scripted {
+        states: Vec<RadioStatus>,
+    }
+
+    impl StateQuery for Scripted {
+        type Error = ();
+
+        fn query_state(&mut self) -> Result<RadioStatus, Self::Error> {
+            match self.states.len() {
+                0 => Err(()),
+                1 => Ok(self.states[0]),
+                _ => Ok(self.states.remove(0)),
+            }
+        }
+    }
+
+    #[test]
+    fn stuck_state() {
+        let radio = Scripted {
+            states: vec![
+                RadioStatus::Idle,
+                RadioStatus::Receive,
+                RadioStatus::Transmit,
+            ],
+        };
+        let options = StatusOptions {
+            show_state: true,
+            stuck_after: Some(Duration::from_millis(5).into()),
+            ..Default::default()
+        };
+        let mut m = StateMonitor::new(radio, options, "rx");
+
+        assert_eq!(m.update(), Ok(RadioStatus::Idle));
+        assert_eq!(m.update(), Ok(RadioStatus::Receive));
+        assert_eq!(m.update(), Ok(RadioStatus::Transmit));
+        assert!(!m.stuck);
+
+        // Remaining in the transmit state raises a single stuck warning
+        std::thread::sleep(Duration::from_millis(6));
+        assert_eq!(m.update(), Ok(RadioStatus::Transmit));
+        assert!(m.stuck);
+        assert_eq!(m.state(), Some(RadioStatus::Transmit));
+
+        m.inner().states.clear();
+        assert_eq!(m.update(), Err(()));
+    }
+}
@@ -0,0 +1,229 @@
+//! Transparent byte stream bridging between stdin/stdout and a radio link
+//!
+//! The `stream` operation reads stdin in chunks of up to `--mtu` bytes, transmitting each
+//! chunk as a frame, while received payloads are written to stdout, so data may be piped
+//! through the radio link in both directions (eg. `cat file | radio stream` on one node
+//! and `radio stream --keep-open > file` on the peer). With `--address`, chunks are framed
+//! and fragmented (see [`FramingOptions`]) and only frames addressed to this node are
+//! written out. Logging should be directed to stderr to keep stdout clean.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+
+use super::{
+    BridgeOptions, BridgeStats, DecodedFrame, Deframer, Framer, FramingOptions, PacketSink,
+    PacketSource, do_bridge,
+};
+use crate::{Receive, ReceiveInfo, Transmit, blocking::BlockingError};
+
+/// Configuration for Stream operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct StreamOptions {
+    /// Maximum number of stdin bytes sent per frame (before framing)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "64"))]
+    pub mtu: usize,
+
+    /// Continue receiving once stdin is closed, rather than exiting
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub keep_open: bool,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub framing_options: FramingOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub bridge_options: BridgeOptions,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            mtu: 64,
+            keep_open: false,
+            framing_options: FramingOptions::default(),
+            bridge_options: BridgeOptions::default(),
+        }
+    }
+}
+
+/// Packet source chunking a byte stream into payloads
+pub struct StreamSource<R> {
+    reader: R,
+    mtu: usize,
+    keep_open: bool,
+    framer: Option<Framer>,
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl<R: Read> StreamSource<R> {
+    /// Create a stream source reading up to `options.mtu` bytes per payload
+    pub fn new(reader: R, options: &StreamOptions) -> Self {
+        Self {
+            reader,
+            mtu: options.mtu.max(1),
+            keep_open: options.keep_open,
+            framer: options.framing_options.framer(),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: Read> PacketSource for StreamSource<R> {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let mut buff = vec![0u8; self.mtu];
+
+        while self.pending.is_empty() {
+            let n = match self.reader.read(&mut buff) {
+                Ok(0) if self.keep_open => loop {
+                    std::thread::park();
+                },
+                Ok(0) => return Ok(None),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            match &mut self.framer {
+                Some(f) => {
+                    let frames = f
+                        .frames(&buff[..n])
+                        .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+                    self.pending.extend(frames);
+                }
+                None => self.pending.push_back(buff[..n].to_vec()),
+            }
+        }
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Packet sink writing received payloads to a byte stream
+pub struct StreamSink<W> {
+    writer: W,
+    deframer: Option<Deframer>,
+}
+
+impl<W: Write> StreamSink<W> {
+    /// Create a stream sink, deframing received frames where framing is enabled
+    pub fn new(writer: W, options: &StreamOptions) -> Self {
+        Self {
+            writer,
+            deframer: options.framing_options.deframer(),
+        }
+    }
+
+    /// Consume the sink, returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> PacketSink for StreamSink<W> {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        let payload = match &mut self.deframer {
+            Some(d) => match d.accept(&frame.data) {
+                Some(p) => p,
+                None => return Ok(()),
+            },
+            None => &frame.data[..],
+        };
+
+        // Flush per payload so interactive streams are not held in buffers
+        self.writer.write_all(payload)?;
+        self.writer.flush()
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+}
+
+/// Stream stdin over the radio, writing received payloads to stdout, until stdin is closed
+/// (or indefinitely with `keep_open`)
+pub fn do_stream<T, I, E>(
+    radio: &mut T,
+    options: StreamOptions,
+) -> Result<BridgeStats, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    info!("Streaming stdin/stdout with {} byte chunks", options.mtu);
+
+    let source = StreamSource::new(std::io::stdin(), &options);
+    let sink = StreamSink::new(std::io::stdout(), &options);
+
+    do_bridge(radio, source, sink, options.bridge_options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(data: Vec<u8>) -> DecodedFrame {
+        DecodedFrame {
+            timestamp_us: 0,
+            rssi: 0,
+            data,
+            text: None,
+            protocol: None,
+            summary: None,
+            device: None,
+            quality: Default::default(),
+            info: String::new(),
+        }
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let input: Vec<u8> = (0..200).map(|i| i as u8).collect();
+
+        // Raw streams are chunked to the MTU
+        let options = StreamOptions {
+            mtu: 64,
+            ..Default::default()
+        };
+        let mut source = StreamSource::new(&input[..], &options);
+        let chunks: Vec<_> = std::iter::from_fn(|| source.next_payload().unwrap()).collect();
+        assert_eq!(
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+            vec![64, 64, 64, 8]
+        );
+
+        // Framed streams are fragmented and reassembled at the addressed peer
+        let tx = StreamOptions {
+            mtu: 100,
+            framing_options: FramingOptions {
+                address: Some(1),
+                dest: 2,
+                frame_mtu: 32,
+            },
+            ..Default::default()
+        };
+        let mut rx = tx.clone();
+        rx.framing_options.address = Some(2);
+
+        let mut source = StreamSource::new(&input[..], &tx);
+        let mut sink = StreamSink::new(Vec::new(), &rx);
+        let mut frames = 0;
+        while let Some(f) = source.next_payload().unwrap() {
+            assert!(f.len() <= 32);
+            sink.write(&decoded(f)).unwrap();
+            frames += 1;
+        }
+        assert!(frames > 7);
+        assert_eq!(sink.into_inner(), input);
+    }
+}
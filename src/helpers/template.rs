@@ -0,0 +1,264 @@
+//! Payload templates for structured test traffic
+//!
+//! Templates combine literal bytes with placeholders filled in for each transmission,
+//! allowing sequence numbered, timestamped and checksummed frames to be generated from
+//! the command line. Templates are written as hex bytes and quoted text, with
+//! placeholders in braces:
+//!
+//! - `{seq}` / `{seq:N}`: sequence number (N = 1, 2 or 4 bytes, default 4)
+//! - `{ts}`: transmit timestamp (milliseconds since the unix epoch, 8 bytes)
+//! - `{node}`: node ID (2 bytes)
+//! - `{rand:N}`: N random bytes
+//! - `{crc}`: CRC-16/CCITT-FALSE over the preceding bytes (2 bytes)
+//!
+//! Multi-byte fields are big-endian, for example `aa55{seq:2}{node}"hello"{crc}`.
+
+use std::time::SystemTime;
+
+use crate::{frame::Address, prng::XorShift32};
+
+use super::PacketSource;
+
+/// Template field
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplateField {
+    /// Literal bytes
+    Literal(Vec<u8>),
+    /// Sequence number of the provided width in bytes
+    Seq(usize),
+    /// Timestamp in milliseconds since the unix epoch
+    Timestamp,
+    /// Node ID
+    Node,
+    /// Random bytes
+    Random(usize),
+    /// CRC over the preceding bytes
+    Crc,
+}
+
+impl TemplateField {
+    /// Encoded length of the field
+    pub fn len(&self) -> usize {
+        match self {
+            TemplateField::Literal(d) => d.len(),
+            TemplateField::Seq(n) | TemplateField::Random(n) => *n,
+            TemplateField::Timestamp => 8,
+            TemplateField::Node | TemplateField::Crc => 2,
+        }
+    }
+
+    /// Check whether the field encodes to zero bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Payload template, parsed from a template string (see module documentation)
+#[derive(Clone, Debug, PartialEq)]
+pub struct PayloadTemplate {
+    fields: Vec<TemplateField>,
+}
+
+impl PayloadTemplate {
+    /// Template fields
+    pub fn fields(&self) -> &[TemplateField] {
+        &self.fields
+    }
+
+    /// Rendered payload length
+    pub fn len(&self) -> usize {
+        self.fields.iter().map(|f| f.len()).sum()
+    }
+
+    /// Check whether the template renders an empty payload
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Render a payload with the provided sequence number, node ID and timestamp
+    pub fn render(
+        &self,
+        seq: u32,
+        node: Address,
+        timestamp_ms: u64,
+        rng: &mut XorShift32,
+    ) -> Vec<u8> {
+        let mut p = Vec::with_capacity(self.len());
+
+        for f in &self.fields {
+            match f {
+                TemplateField::Literal(d) => p.extend_from_slice(d),
+                TemplateField::Seq(n) => p.extend_from_slice(&seq.to_be_bytes()[4 - n..]),
+                TemplateField::Timestamp => p.extend_from_slice(&timestamp_ms.to_be_bytes()),
+                TemplateField::Node => p.extend_from_slice(&node.to_be_bytes()),
+                TemplateField::Random(n) => {
+                    let start = p.len();
+                    p.resize(start + n, 0);
+                    rng.fill(&mut p[start..]);
+                }
+                TemplateField::Crc => {
+                    let crc = crc16_ccitt(&p);
+                    p.extend_from_slice(&crc.to_be_bytes());
+                }
+            }
+        }
+
+        p
+    }
+}
+
+impl std::str::FromStr for PayloadTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = vec![];
+        let mut literal = vec![];
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                c if c.is_whitespace() => (),
+                '"' => {
+                    let text: String = chars.by_ref().take_while(|c| *c != '"').collect();
+                    literal.extend_from_slice(text.as_bytes());
+                }
+                '{' => {
+                    let spec: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    if !literal.is_empty() {
+                        fields.push(TemplateField::Literal(std::mem::take(&mut literal)));
+                    }
+                    fields.push(parse_field(&spec)?);
+                }
+                c => {
+                    let l = chars
+                        .next()
+                        .ok_or_else(|| format!("incomplete hex byte '{}'", c))?;
+                    let b = u8::from_str_radix(&format!("{}{}", c, l), 16)
+                        .map_err(|_| format!("invalid hex byte '{}{}'", c, l))?;
+                    literal.push(b);
+                }
+            }
+        }
+
+        if !literal.is_empty() {
+            fields.push(TemplateField::Literal(literal));
+        }
+
+        Ok(Self { fields })
+    }
+}
+
+/// Parse a placeholder (without braces)
+fn parse_field(spec: &str) -> Result<TemplateField, String> {
+    let (name, arg) = match spec.split_once(':') {
+        Some((n, a)) => (n, Some(a)),
+        None => (spec, None),
+    };
+    let width = |a: &str| a.parse::<usize>().map_err(|e| format!("{}: {}", a, e));
+
+    match (name, arg) {
+        ("seq", None) => Ok(TemplateField::Seq(4)),
+        ("seq", Some(a)) => match width(a)? {
+            n @ (1 | 2 | 4) => Ok(TemplateField::Seq(n)),
+            n => Err(format!(
+                "unsupported sequence width {}, expected 1, 2 or 4",
+                n
+            )),
+        },
+        ("ts", None) => Ok(TemplateField::Timestamp),
+        ("node", None) => Ok(TemplateField::Node),
+        ("rand", Some(a)) => Ok(TemplateField::Random(width(a)?)),
+        ("crc", None) => Ok(TemplateField::Crc),
+        _ => Err(format!(
+            "unrecognised placeholder '{{{}}}', expected seq[:N], ts, node, rand:N or crc",
+            spec
+        )),
+    }
+}
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xffff)
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |mut crc, b| {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+        crc
+    })
+}
+
+/// Templated payloads, rendered once or repeated indefinitely with incrementing
+/// sequence numbers
+pub struct TemplateSource {
+    template: PayloadTemplate,
+    node: Address,
+    repeat: bool,
+    seq: u32,
+    rng: XorShift32,
+}
+
+impl TemplateSource {
+    /// Create a template source for the provided node, repeating indefinitely where `repeat` is set
+    pub fn new(template: PayloadTemplate, node: Address, repeat: bool) -> Self {
+        let seed = super::random_seed();
+
+        Self {
+            template,
+            node,
+            repeat,
+            seq: 0,
+            rng: XorShift32::new(seed),
+        }
+    }
+}
+
+impl PacketSource for TemplateSource {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        if self.seq > 0 && !self.repeat {
+            return Ok(None);
+        }
+
+        let ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let p = self.template.render(self.seq, self.node, ts, &mut self.rng);
+        self.seq = self.seq.wrapping_add(1);
+
+        Ok(Some(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_templates() {
+        let t: PayloadTemplate =
+            r#"aa 55 {seq:2} {node} "hi" {rand:3} {ts} {crc}"#.parse().unwrap();
+        assert_eq!(t.len(), 2 + 2 + 2 + 2 + 3 + 8 + 2);
+
+        let mut rng = XorShift32::new(1);
+        let p = t.render(0x0102, 0xbeef, 0x1122, &mut rng);
+        assert_eq!(&p[..8], &[0xaa, 0x55, 0x01, 0x02, 0xbe, 0xef, b'h', b'i']);
+        assert_eq!(&p[11..19], &0x1122u64.to_be_bytes());
+
+        // Trailing CRC covers the preceding bytes
+        let crc = crc16_ccitt(&p[..19]);
+        assert_eq!(&p[19..], &crc.to_be_bytes());
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29b1);
+
+        assert!("{seq:3}".parse::<PayloadTemplate>().is_err());
+        assert!("{bogus}".parse::<PayloadTemplate>().is_err());
+        assert!("a".parse::<PayloadTemplate>().is_err());
+
+        // Single-shot sources render once
+        let mut s = TemplateSource::new("{seq:1}".parse().unwrap(), 0, false);
+        assert_eq!(s.next_payload().unwrap(), Some(vec![0]));
+        assert_eq!(s.next_payload().unwrap(), None);
+    }
+}
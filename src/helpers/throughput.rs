@@ -0,0 +1,370 @@
+//! Throughput benchmark operation
+//!
+//! The `throughput` operation transmits a fixed number of sequence numbered packets
+//! as fast as the radio allows (or paced with `--traffic`), with a peer running
+//! `throughput --receive` counting packets on the other end. Both ends report packet
+//! and byte rates, airtime utilisation and (when receiving) loss in a
+//! [`ThroughputInfo`], allowing CI to assert on minimum throughput for a driver.
+
+use std::time::{Duration, Instant};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::{Progress, ProgressOptions, Samples, TrafficOptions, time_on_air};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Length of the sequence number prefixing each throughput packet
+const SEQ_LEN: usize = 4;
+
+/// Configuration for Throughput operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ThroughputOptions {
+    /// Receive and count packets from a transmitting peer
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub receive: bool,
+
+    /// Number of packets to send (or expect when receiving)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1000"))]
+    pub count: u32,
+
+    /// Packet size in bytes (including a 4 byte sequence number)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "32"))]
+    pub size: usize,
+
+    /// Bitrate (bits/s) for computing airtime utilisation, measured transmit time is
+    /// used otherwise (receivers report no utilisation without a bitrate)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub bitrate: Option<u32>,
+
+    /// Stop receiving after this period without packets
+    #[cfg_attr(feature = "clap", clap(long, default_value = "2s"))]
+    pub idle_timeout: HumanDuration,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub traffic_options: TrafficOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub progress_options: ProgressOptions,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for ThroughputOptions {
+    fn default() -> Self {
+        Self {
+            receive: false,
+            count: 1000,
+            size: 32,
+            bitrate: None,
+            idle_timeout: Duration::from_secs(2).into(),
+            power: None,
+            traffic_options: TrafficOptions::default(),
+            progress_options: ProgressOptions::default(),
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+/// Throughput benchmark results
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThroughputInfo {
+    /// Packets sent (or expected, when receiving)
+    pub sent: u32,
+    /// Packets received, `None` when transmitting
+    pub received: Option<u32>,
+    pub payload_len: usize,
+    /// Bytes sent or received
+    pub bytes: u64,
+    /// Elapsed time in seconds (from the first to the last packet when receiving)
+    pub elapsed: f32,
+    /// Time-on-air in seconds, where known
+    pub airtime: Option<f32>,
+    /// Received packet RSSI
+    pub rssi: Samples,
+}
+
+impl ThroughputInfo {
+    /// Packets sent or received per second
+    pub fn packets_per_sec(&self) -> f32 {
+        let n = self.received.unwrap_or(self.sent);
+        match self.elapsed > 0.0 {
+            true => n as f32 / self.elapsed,
+            false => 0.0,
+        }
+    }
+
+    /// Bytes sent or received per second
+    pub fn bytes_per_sec(&self) -> f32 {
+        match self.elapsed > 0.0 {
+            true => self.bytes as f32 / self.elapsed,
+            false => 0.0,
+        }
+    }
+
+    /// Fraction of the elapsed time spent on air, where known
+    pub fn utilisation(&self) -> Option<f32> {
+        self.airtime
+            .filter(|_| self.elapsed > 0.0)
+            .map(|a| (a / self.elapsed).min(1.0))
+    }
+
+    /// Fraction of expected packets lost, where receiving
+    pub fn loss(&self) -> Option<f32> {
+        self.received
+            .filter(|_| self.sent > 0)
+            .map(|r| 1.0 - (r.min(self.sent) as f32 / self.sent as f32))
+    }
+}
+
+impl core::fmt::Display for ThroughputInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.received {
+            Some(r) => write!(f, "received {}/{}", r, self.sent)?,
+            None => write!(f, "sent {}", self.sent)?,
+        }
+        write!(
+            f,
+            " ({} byte payload) in {:.3}s, {:.1} packets/s, {:.0} bytes/s",
+            self.payload_len,
+            self.elapsed,
+            self.packets_per_sec(),
+            self.bytes_per_sec()
+        )?;
+        if let Some(u) = self.utilisation() {
+            write!(f, ", {:.1}% airtime", u * 100.0)?;
+        }
+        if let Some(l) = self.loss() {
+            write!(f, ", {:.1}% loss", l * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a throughput benchmark, transmitting or (with `--receive`) counting packets
+pub fn do_throughput<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: ThroughputOptions,
+) -> Result<ThroughputInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + Power<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    let info = match options.receive {
+        true => throughput_receive(radio, buff, &options)?,
+        false => throughput_transmit(radio, &options)?,
+    };
+
+    info!("Throughput: {}", info);
+
+    Ok(info)
+}
+
+fn throughput_transmit<T, E>(
+    radio: &mut T,
+    options: &ThroughputOptions,
+) -> Result<ThroughputInfo, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: core::fmt::Debug,
+{
+    // Set output power if specified
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let mut progress = options.progress_options.reporter();
+    let mut traffic = options.traffic_options.generator();
+    let mut data = vec![0u8; options.size.max(SEQ_LEN)];
+    let mut airtime = Duration::ZERO;
+
+    let start = Instant::now();
+
+    for seq in 0..options.count {
+        if seq > 0
+            && let Some(t) = &mut traffic
+        {
+            radio.delay_us(t.next_delay(data.len()).as_micros() as u32);
+        }
+
+        data[..SEQ_LEN].copy_from_slice(&seq.to_be_bytes());
+
+        let t = Instant::now();
+        radio.do_transmit(&data, options.blocking_options.clone())?;
+        airtime += match options.bitrate {
+            Some(b) => time_on_air(data.len(), b),
+            None => t.elapsed(),
+        };
+
+        progress.update(&Progress::new(
+            "throughput",
+            seq as u64 + 1,
+            Some(options.count as u64),
+        ));
+    }
+
+    let elapsed = start.elapsed();
+    progress.finish(&Progress::new(
+        "throughput",
+        options.count as u64,
+        Some(options.count as u64),
+    ));
+
+    Ok(ThroughputInfo {
+        sent: options.count,
+        received: None,
+        payload_len: data.len(),
+        bytes: data.len() as u64 * options.count as u64,
+        elapsed: elapsed.as_secs_f32(),
+        airtime: Some(airtime.as_secs_f32()),
+        rssi: Samples::new(),
+    })
+}
+
+fn throughput_receive<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: &ThroughputOptions,
+) -> Result<ThroughputInfo, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: core::fmt::Debug,
+{
+    let mut progress = options.progress_options.reporter();
+    let mut info = ThroughputInfo {
+        sent: options.count,
+        received: Some(0),
+        payload_len: options.size.max(SEQ_LEN),
+        bytes: 0,
+        elapsed: 0.0,
+        airtime: None,
+        rssi: Samples::new(),
+    };
+    let mut airtime = Duration::ZERO;
+    let mut received = 0;
+    let mut seen = vec![false; options.count as usize];
+    let (mut first, mut last) = (None, Instant::now());
+
+    radio.start_receive()?;
+
+    while received < options.count && last.elapsed() < *options.idle_timeout {
+        if !radio.check_receive(true)? {
+            radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+            continue;
+        }
+
+        let (n, i) = radio.get_received(buff)?;
+        radio.start_receive()?;
+        last = Instant::now();
+
+        // Count each sequence number once, ignoring unrelated traffic
+        let seq = match buff.get(..SEQ_LEN) {
+            Some(s) if n >= SEQ_LEN => u32::from_be_bytes([s[0], s[1], s[2], s[3]]),
+            _ => continue,
+        };
+        match seen.get_mut(seq as usize) {
+            Some(s) if !*s => *s = true,
+            _ => {
+                debug!("Ignoring {} byte frame (seq: {})", n, seq);
+                continue;
+            }
+        }
+
+        first.get_or_insert(last);
+        received += 1;
+        info.bytes += n as u64;
+        info.rssi.update(i.rssi() as f32);
+        if let Some(b) = options.bitrate {
+            airtime += time_on_air(n, b);
+        }
+
+        progress.update(&Progress::new(
+            "throughput",
+            received as u64,
+            Some(options.count as u64),
+        ));
+    }
+    progress.finish(&Progress::new(
+        "throughput",
+        received as u64,
+        Some(options.count as u64),
+    ));
+
+    info.received = Some(received);
+    info.elapsed = first
+        .map(|f| last.duration_since(f).as_secs_f32())
+        .unwrap_or_default();
+    info.airtime = options.bitrate.map(|_| airtime.as_secs_f32());
+
+    Ok(info)
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::test_support::impaired;
+
+    #[test]
+    fn throughput_loopback() {
+        // Loop frames back, dropping every tenth and keeping the first for replay
+        let first = Rc::new(RefCell::new(None));
+        let f = first.clone();
+        let (mut radio, _clock) = impaired(move |d| {
+            f.borrow_mut().get_or_insert_with(|| d.to_vec());
+            (d[3] % 10 != 9).then(|| d.to_vec())
+        });
+
+        let mut buff = [0u8; 256];
+        let options = ThroughputOptions {
+            count: 100,
+            size: 16,
+            bitrate: Some(250_000),
+            idle_timeout: Duration::from_millis(10).into(),
+            ..Default::default()
+        };
+
+        let tx = do_throughput(&mut radio, &mut buff, options.clone()).unwrap();
+        assert_eq!(tx.sent, 100);
+        assert_eq!(tx.bytes, 1600);
+        assert_eq!(tx.loss(), None);
+        assert!((tx.airtime.unwrap() - 0.0512).abs() < 1e-6);
+
+        // Duplicates are ignored
+        let dup = first.borrow_mut().take().unwrap();
+        radio.inject(&dup);
+
+        let rx = ThroughputOptions {
+            receive: true,
+            ..options
+        };
+        let rx = do_throughput(&mut radio, &mut buff, rx).unwrap();
+        assert_eq!(rx.received, Some(90));
+        assert_eq!(rx.bytes, 1440);
+        assert_eq!(rx.rssi.count(), 90);
+        assert!((rx.loss().unwrap() - 0.1).abs() < 1e-6);
+    }
+}
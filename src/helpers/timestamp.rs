@@ -0,0 +1,396 @@
+//! Frame timestamping accuracy test
+//!
+//! Validates capture timestamps before relying on them for TDMA or localisation. The
+//! sending node transmits frames carrying a sequence number and the transmit completion
+//! time of the previous frame (as a follow-up, so the timestamp reflects the actual
+//! completion), and the receiving node pairs these with the capture timestamps of each
+//! frame, both from the driver ([`ReceiveInfo::timestamp_us`]) and from the helper (on
+//! detecting receive completion).
+//!
+//! Clock offset and drift between nodes are removed with a linear fit of capture against
+//! transmit times, with the residuals giving the timestamp error distribution.
+//!
+//! Frame format: `seq (u32) | previous tx completion us (u64)`, big-endian.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+use core::time::Duration;
+
+use byteorder::{ByteOrder, NetworkEndian};
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use embedded_hal::delay::DelayNs;
+use serde::{Deserialize, Serialize};
+
+use super::Samples;
+use crate::{
+    Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingOptions},
+    clock::Clock,
+};
+
+/// Length of timestamp test frames
+pub const TIMESTAMP_FRAME_LEN: usize = 12;
+
+/// Role of a node in the timestamp test
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum TimestampRole {
+    /// Transmit timestamped frames
+    Send,
+    /// Receive frames and compute capture timestamp errors
+    Receive,
+}
+
+/// Configuration for timestamp accuracy test operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct TimestampOptions {
+    /// Node role
+    #[cfg_attr(feature = "clap", clap(value_enum))]
+    pub role: TimestampRole,
+
+    /// Number of timestamped frames
+    #[cfg_attr(feature = "clap", clap(long, default_value = "100"))]
+    pub count: u32,
+
+    /// Interval between transmitted frames
+    #[cfg_attr(feature="clap", clap(long, default_value="100ms", value_parser=crate::duration_from_str))]
+    pub period: Duration,
+
+    /// Timeout awaiting each frame, ending the test when exceeded
+    #[cfg_attr(feature="clap", clap(long, default_value="1s", value_parser=crate::duration_from_str))]
+    pub frame_timeout: Duration,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Encode a timestamp test frame
+pub fn timestamp_frame(seq: u32, prev_tx_us: Option<u64>) -> [u8; TIMESTAMP_FRAME_LEN] {
+    let mut b = [0u8; TIMESTAMP_FRAME_LEN];
+    NetworkEndian::write_u32(&mut b[0..4], seq);
+    NetworkEndian::write_u64(&mut b[4..12], prev_tx_us.unwrap_or(u64::MAX));
+    b
+}
+
+/// Decode a timestamp test frame, returning the sequence number and previous transmit time
+pub fn parse_timestamp_frame(data: &[u8]) -> Option<(u32, Option<u64>)> {
+    if data.len() < TIMESTAMP_FRAME_LEN {
+        return None;
+    }
+
+    let seq = NetworkEndian::read_u32(&data[0..4]);
+    let prev = match NetworkEndian::read_u64(&data[4..12]) {
+        u64::MAX => None,
+        t => Some(t),
+    };
+    Some((seq, prev))
+}
+
+/// Timestamp error distribution for one timestamp source
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimestampErrors {
+    /// Clock drift of the receiver relative to the sender, in parts per million
+    pub drift_ppm: f32,
+    /// Residual capture timestamp errors in microseconds
+    pub errors: Samples,
+}
+
+impl TimestampErrors {
+    /// Fit capture against transmit times (in microseconds), returning `None` with fewer
+    /// than two pairs
+    pub fn fit(pairs: &[(u64, u64)]) -> Option<Self> {
+        let (tx0, rx0) = *pairs.first()?;
+        if pairs.len() < 2 {
+            return None;
+        }
+
+        // Least squares fit relative to the first pair for precision
+        let xy: Vec<_> = pairs
+            .iter()
+            .map(|(tx, rx)| {
+                (
+                    (*tx as i64 - tx0 as i64) as f64,
+                    (*rx as i64 - rx0 as i64) as f64,
+                )
+            })
+            .collect();
+        let n = xy.len() as f64;
+        let (mx, my) = xy
+            .iter()
+            .fold((0.0, 0.0), |(a, b), (x, y)| (a + x / n, b + y / n));
+        let sxx: f64 = xy.iter().map(|(x, _)| (x - mx).powi(2)).sum();
+        let sxy: f64 = xy.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+
+        let slope = match sxx {
+            0.0 => 1.0,
+            _ => sxy / sxx,
+        };
+        let intercept = my - slope * mx;
+
+        let mut errors = Samples::new();
+        for (x, y) in &xy {
+            errors.update((y - (intercept + slope * x)) as f32);
+        }
+
+        Some(Self {
+            drift_ppm: ((slope - 1.0) * 1e6) as f32,
+            errors,
+        })
+    }
+
+    /// Maximum absolute timestamp error
+    pub fn max_abs(&self) -> Option<f32> {
+        Some(self.errors.min()?.abs().max(self.errors.max()?.abs()))
+    }
+}
+
+impl core::fmt::Display for TimestampErrors {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let v = |m: Option<f32>| m.map(|v| format!("{:.1}", v)).unwrap_or("-".into());
+        write!(
+            f,
+            "{} pairs, drift {:.1} ppm, error (us) std dev {} p1 {} p99 {} max {}",
+            self.errors.count(),
+            self.drift_ppm,
+            v(self.errors.std_dev()),
+            v(self.errors.percentile(1.0)),
+            v(self.errors.percentile(99.0)),
+            v(self.max_abs()),
+        )
+    }
+}
+
+/// Timestamp accuracy test results
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimestampStats {
+    /// Frames received
+    pub frames: u32,
+    /// Driver capture timestamp errors, `None` where unsupported
+    pub driver: Option<TimestampErrors>,
+    /// Helper capture timestamp errors
+    pub helper: Option<TimestampErrors>,
+}
+
+impl core::fmt::Display for TimestampStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "frames: {}", self.frames)?;
+        for (name, e) in [("driver", &self.driver), ("helper", &self.helper)] {
+            match e {
+                Some(e) => writeln!(f, "{:>8}: {}", name, e)?,
+                None => writeln!(f, "{:>8}: unavailable", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Transmit timestamped frames for a peer running the receive role, returning the number
+/// of frames sent
+///
+/// One frame more than the configured count is sent, carrying the final follow-up.
+pub fn do_timestamp_send<T, C, E>(
+    radio: &mut T,
+    clock: C,
+    options: TimestampOptions,
+) -> Result<u32, BlockingError<E>>
+where
+    T: Transmit<Error = E> + DelayNs,
+    C: Clock,
+    E: core::fmt::Debug,
+{
+    let timeout_us = options.blocking_options.timeout.as_micros() as u64;
+    let poll_us = options.blocking_options.poll_interval.as_micros() as u32;
+    let mut prev = None;
+
+    for seq in 0..=options.count {
+        let t0 = clock.now_us();
+        radio.start_transmit(&timestamp_frame(seq, prev))?;
+
+        while !radio.check_transmit()? {
+            if clock.elapsed_us(t0) > timeout_us {
+                return Err(BlockingError::Timeout);
+            }
+            radio.delay_us(poll_us);
+        }
+        prev = Some(clock.now_us());
+
+        debug!("Sent timestamp frame {}", seq);
+
+        radio.delay_us(options.period.as_micros() as u32);
+    }
+
+    Ok(options.count + 1)
+}
+
+/// Receive timestamped frames from a peer running the send role, returning the capture
+/// timestamp error distributions
+pub fn do_timestamp_receive<T, I, C, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    clock: C,
+    options: TimestampOptions,
+) -> Result<TimestampStats, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo,
+    C: Clock,
+    E: core::fmt::Debug,
+{
+    let timeout_us = options.frame_timeout.as_micros() as u64;
+    let poll_us = options.blocking_options.poll_interval.as_micros() as u32;
+
+    let mut frames = 0;
+    let (mut driver, mut helper) = (vec![], vec![]);
+    // Sequence number and capture timestamps (driver, helper) of the previous frame
+    let mut last: Option<(u32, Option<u64>, u64)> = None;
+
+    radio.start_receive()?;
+
+    'frames: while frames <= options.count {
+        let t0 = clock.now_us();
+        while !radio.check_receive(true)? {
+            if clock.elapsed_us(t0) > timeout_us {
+                debug!("Timeout awaiting timestamp frame");
+                break 'frames;
+            }
+            radio.delay_us(poll_us);
+        }
+        let rx_us = clock.now_us();
+
+        let (n, info) = radio.get_received(buff)?;
+        radio.start_receive()?;
+
+        let (seq, prev) = match parse_timestamp_frame(&buff[..n]) {
+            Some(f) => f,
+            None => {
+                debug!("Invalid timestamp frame ({} bytes)", n);
+                continue;
+            }
+        };
+        frames += 1;
+
+        // Pair the follow-up transmit time with the previous frame
+        if let (Some((s, d, h)), Some(tx)) = (last, prev)
+            && s.wrapping_add(1) == seq
+        {
+            helper.push((tx, h));
+            if let Some(d) = d {
+                driver.push((tx, d));
+            }
+        }
+        last = Some((seq, info.timestamp_us(), rx_us));
+    }
+
+    let stats = TimestampStats {
+        frames,
+        driver: TimestampErrors::fit(&driver),
+        helper: TimestampErrors::fit(&helper),
+    };
+
+    info!("Timestamp accuracy:\n{}", stats);
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    /// Receive info with a driver capture timestamp
+    #[derive(Debug, Default)]
+    struct StampInfo(u64);
+
+    impl ReceiveInfo for StampInfo {
+        fn rssi(&self) -> i16 {
+            0
+        }
+
+        fn timestamp_us(&self) -> Option<u64> {
+            Some(self.0)
+        }
+    }
+
+    /// Radio delivering frames at scheduled times, with a driver clock running fast
+    struct ScheduledRadio<'a> {
+        clock: &'a VirtualClock,
+        frames: VecDeque<(u64, Vec<u8>)>,
+    }
+
+    impl Receive for ScheduledRadio<'_> {
+        type Error = ();
+        type Info = StampInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(matches!(self.frames.front(), Some((t, _)) if self.clock.now_us() >= *t))
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (t, d) = self.frames.pop_front().unwrap();
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), StampInfo(5_000 + t + t / 10_000)))
+        }
+    }
+
+    impl DelayNs for ScheduledRadio<'_> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.clock.delay_ns(ns)
+        }
+    }
+
+    #[test]
+    fn timestamp_accuracy() {
+        let clock = VirtualClock::new();
+
+        // Frames captured 300 us after transmit completion on the sender clock
+        let tx = |seq: u64| 1_000 + seq * 100_000;
+        let frames = (0..=5u64)
+            .map(|s| {
+                let prev = s.checked_sub(1).map(tx);
+                (tx(s) + 300, timestamp_frame(s as u32, prev).to_vec())
+            })
+            .collect();
+        let mut radio = ScheduledRadio {
+            clock: &clock,
+            frames,
+        };
+
+        let options = TimestampOptions {
+            role: TimestampRole::Receive,
+            count: 5,
+            period: Duration::from_millis(100),
+            frame_timeout: Duration::from_secs(1),
+            blocking_options: BlockingOptions::default(),
+        };
+        let mut buff = [0u8; 32];
+        let stats = do_timestamp_receive(&mut radio, &mut buff, &clock, options).unwrap();
+
+        assert_eq!(stats.frames, 6);
+
+        // Driver clock runs 100 ppm fast with exact capture
+        let driver = stats.driver.unwrap();
+        assert_eq!(driver.errors.count(), 5);
+        assert!((driver.drift_ppm - 100.0).abs() < 0.1);
+        assert!(driver.max_abs().unwrap() < 0.1);
+
+        // Helper capture is quantised to the poll interval
+        let helper = stats.helper.unwrap();
+        assert_eq!(helper.errors.count(), 5);
+        assert!(helper.max_abs().unwrap() <= 100.0);
+
+        let f = timestamp_frame(7, None);
+        assert_eq!(parse_timestamp_frame(&f), Some((7, None)));
+    }
+}
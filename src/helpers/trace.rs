@@ -0,0 +1,104 @@
+//! Transmitted frame tracing
+//!
+//! With `--trace-tx` each transmitted frame is logged with a hexdump, time-on-air and
+//! output power, symmetric with received frame logging, so both directions of an exchange
+//! can be inspected from a single node.
+
+use core::time::Duration;
+
+/// Hexdump formatter, 16 bytes per line with offsets and printable characters
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl core::fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, line) in self.0.chunks(16).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:04x}  ", i * 16)?;
+            for j in 0..16 {
+                match line.get(j) {
+                    Some(b) => write!(f, "{:02x} ", b)?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            write!(f, " |")?;
+            for b in line {
+                let c = match b.is_ascii_graphic() || *b == b' ' {
+                    true => *b as char,
+                    false => '.',
+                };
+                write!(f, "{}", c)?;
+            }
+            write!(f, "|")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the time-on-air for a payload of `len` bytes at the provided bitrate (bits/s)
+pub fn time_on_air(len: usize, bitrate: u32) -> Duration {
+    Duration::from_micros(len as u64 * 8 * 1_000_000 / bitrate.max(1) as u64)
+}
+
+/// Trace of a transmitted frame
+pub struct TxTrace<'a> {
+    /// Frame data
+    pub data: &'a [u8],
+    /// Time-on-air, computed from the bitrate where configured or measured otherwise
+    pub time_on_air: Duration,
+    /// Whether the time-on-air was computed (rather than measured)
+    pub computed: bool,
+    /// Configured output power in dBm, `None` for the driver default
+    pub power: Option<i8>,
+}
+
+impl core::fmt::Display for TxTrace<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} bytes, time-on-air {:.3} ms ({}), power ",
+            self.data.len(),
+            self.time_on_air.as_secs_f32() * 1000.0,
+            if self.computed {
+                "computed"
+            } else {
+                "measured"
+            },
+        )?;
+        match self.power {
+            Some(p) => writeln!(f, "{} dBm", p)?,
+            None => writeln!(f, "default")?,
+        }
+        write!(f, "{}", HexDump(self.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tx_trace() {
+        let data: Vec<u8> = (0x30..0x42).collect();
+        assert_eq!(
+            HexDump(&data).to_string(),
+            "0000  30 31 32 33 34 35 36 37 38 39 3a 3b 3c 3d 3e 3f  |0123456789:;<=>?|\n\
+             0010  40 41                                            |@A|"
+        );
+
+        assert_eq!(time_on_air(25, 50_000), Duration::from_millis(4));
+
+        let t = TxTrace {
+            data: &[0x00, 0x61],
+            time_on_air: Duration::from_micros(320),
+            computed: true,
+            power: Some(10),
+        };
+        assert_eq!(
+            t.to_string(),
+            "2 bytes, time-on-air 0.320 ms (computed), power 10 dBm\n\
+             0000  00 61                                            |.a|"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Deterministic pseudo-random traffic generator profiles
+//!
+//! Traffic profiles control the spacing of generated packets, allowing channel access
+//! algorithms to be evaluated under realistic and reproducible load. Profiles are
+//! selected with `--traffic`:
+//!
+//! - `cbr:BITRATE`: constant bitrate (bits/s), spacing packets by their length
+//! - `poisson:RATE`: Poisson arrivals with a mean RATE (packets/s)
+//! - `bursty:RATE:ON:OFF`: on/off bursts at RATE (packets/s), with exponentially
+//!   distributed burst and idle durations of mean ON and OFF (for example `100ms`)
+//!
+//! Random profiles are driven by an [`XorShift32`] seeded with `--traffic-seed`, so
+//! runs with the same seed produce the same arrival times.
+
+use std::time::Duration;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use humantime::Duration as HumanDuration;
+
+use super::time_on_air;
+use crate::prng::XorShift32;
+
+/// Traffic profile
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrafficProfile {
+    /// Constant bitrate (bits/s)
+    Constant { bitrate: u32 },
+    /// Poisson arrivals with mean rate (packets/s)
+    Poisson { rate: f32 },
+    /// On/off bursts at a rate (packets/s) with mean burst and idle durations
+    Bursty {
+        rate: f32,
+        on: Duration,
+        off: Duration,
+    },
+}
+
+impl std::str::FromStr for TrafficProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split(':').collect();
+
+        let rate = |r: &str| match r.parse::<f32>() {
+            Ok(v) if v > 0.0 => Ok(v),
+            _ => Err(format!("{}: expected a positive rate", r)),
+        };
+        let duration = |d: &str| {
+            d.parse::<HumanDuration>()
+                .map(|d| *d)
+                .map_err(|e| format!("{}: {}", d, e))
+        };
+
+        match parts[..] {
+            ["cbr", b] => Ok(TrafficProfile::Constant {
+                bitrate: b.parse().map_err(|e| format!("{}: {}", b, e))?,
+            }),
+            ["poisson", r] => Ok(TrafficProfile::Poisson { rate: rate(r)? }),
+            ["bursty", r, on, off] => Ok(TrafficProfile::Bursty {
+                rate: rate(r)?,
+                on: duration(on)?,
+                off: duration(off)?,
+            }),
+            _ => Err(format!(
+                "unrecognised traffic profile '{}', expected cbr:BITRATE, poisson:RATE or bursty:RATE:ON:OFF",
+                s
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for TrafficProfile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TrafficProfile::Constant { bitrate } => write!(f, "cbr:{}", bitrate),
+            TrafficProfile::Poisson { rate } => write!(f, "poisson:{}", rate),
+            TrafficProfile::Bursty { rate, on, off } => write!(
+                f,
+                "bursty:{}:{}:{}",
+                rate,
+                HumanDuration::from(*on),
+                HumanDuration::from(*off)
+            ),
+        }
+    }
+}
+
+/// Traffic generation options
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct TrafficOptions {
+    /// Space packets using a traffic profile
+    /// (`cbr:BITRATE`, `poisson:RATE` or `bursty:RATE:ON:OFF`)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub traffic: Option<TrafficProfile>,
+
+    /// Seed for random traffic profiles (defaults to a random seed)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub traffic_seed: Option<u32>,
+}
+
+impl TrafficOptions {
+    /// Create a generator for the configured profile, if any
+    pub fn generator(&self) -> Option<TrafficGenerator> {
+        let seed = self.traffic_seed.unwrap_or_else(super::random_seed);
+
+        self.traffic.clone().map(|p| TrafficGenerator::new(p, seed))
+    }
+}
+
+/// Generator for inter-packet delays following a [`TrafficProfile`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrafficGenerator {
+    profile: TrafficProfile,
+    rng: XorShift32,
+    burst_remaining: Duration,
+}
+
+impl TrafficGenerator {
+    /// Create a generator with the provided seed
+    pub fn new(profile: TrafficProfile, seed: u32) -> Self {
+        let mut g = Self {
+            profile,
+            rng: XorShift32::new(seed),
+            burst_remaining: Duration::ZERO,
+        };
+        if let TrafficProfile::Bursty { on, .. } = g.profile {
+            g.burst_remaining = g.exponential(on);
+        }
+        g
+    }
+
+    /// Traffic profile
+    pub fn profile(&self) -> &TrafficProfile {
+        &self.profile
+    }
+
+    /// Draw an exponentially distributed duration with the provided mean
+    fn exponential(&mut self, mean: Duration) -> Duration {
+        let u = self.rng.next_f32();
+        mean.mul_f32(-(1.0 - u).ln())
+    }
+
+    /// Delay before sending the next packet, following a packet of `len` bytes
+    pub fn next_delay(&mut self, len: usize) -> Duration {
+        match self.profile.clone() {
+            TrafficProfile::Constant { bitrate } => time_on_air(len, bitrate),
+            TrafficProfile::Poisson { rate } => {
+                self.exponential(Duration::from_secs_f32(1.0 / rate))
+            }
+            TrafficProfile::Bursty { rate, on, off } => {
+                let interval = Duration::from_secs_f32(1.0 / rate);
+
+                // Continue the current burst
+                if let Some(r) = self.burst_remaining.checked_sub(interval) {
+                    self.burst_remaining = r;
+                    return interval;
+                }
+
+                // Or idle until the next burst
+                let idle = self.burst_remaining + self.exponential(off);
+                self.burst_remaining = self.exponential(on);
+                idle
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traffic_profiles() {
+        let p: TrafficProfile = "bursty:100:50ms:1s".parse().unwrap();
+        assert_eq!(
+            p,
+            TrafficProfile::Bursty {
+                rate: 100.0,
+                on: Duration::from_millis(50),
+                off: Duration::from_secs(1),
+            }
+        );
+        assert_eq!(p.to_string().parse(), Ok(p));
+        assert!("poisson:0".parse::<TrafficProfile>().is_err());
+        assert!("uniform:10".parse::<TrafficProfile>().is_err());
+
+        // Constant bitrate spaces packets by their length
+        let mut g = TrafficGenerator::new("cbr:8000".parse().unwrap(), 1);
+        assert_eq!(g.next_delay(100), Duration::from_millis(100));
+
+        // Random profiles are reproducible for a given seed
+        let poisson: TrafficProfile = "poisson:100".parse().unwrap();
+        let mut a = TrafficGenerator::new(poisson.clone(), 42);
+        let mut b = TrafficGenerator::new(poisson, 42);
+        let delays: Vec<_> = (0..10_000).map(|_| a.next_delay(10)).collect();
+        assert!(delays.iter().all(|d| *d == b.next_delay(10)));
+
+        // With the expected mean interval
+        let mean = delays.iter().sum::<Duration>() / delays.len() as u32;
+        assert!(
+            (mean.as_secs_f32() - 0.01).abs() < 0.001,
+            "mean: {:?}",
+            mean
+        );
+
+        // Bursts are separated by idle periods
+        let mut g = TrafficGenerator::new("bursty:1000:10ms:100ms".parse().unwrap(), 7);
+        let delays: Vec<_> = (0..1_000).map(|_| g.next_delay(10)).collect();
+        assert!(delays.contains(&Duration::from_millis(1)));
+        assert!(delays.iter().any(|d| *d > Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,381 @@
+//! GPIO-triggered transmit operation
+//!
+//! Waits for an edge on a trigger input and transmits a configured frame with minimal
+//! latency, for sensor-event emulation and latency budgeting. Latency is measured from
+//! edge detection to `start_transmit` returning and to transmit completion, with detection
+//! itself bounded by the poll interval for [`InputPin`] triggers. With the `gpiochip`
+//! feature, Linux GPIO character devices may be used via [`GpiochipInput`].
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+#[cfg(feature = "clap")]
+use clap::{Parser, ValueEnum};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::InputPin;
+use serde::{Deserialize, Serialize};
+
+use super::Samples;
+use crate::{
+    Power, Transmit,
+    blocking::{BlockingError, BlockingOptions},
+    clock::Clock,
+};
+
+/// Trigger edge
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum Edge {
+    /// Low to high transition
+    Rising,
+    /// High to low transition
+    Falling,
+    /// Any transition
+    Both,
+}
+
+/// Configuration for GPIO-triggered transmit operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct TriggerOptions {
+    /// Data to be transmitted on each trigger
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub data: Vec<u8>,
+
+    /// Power in dBm (range -18dBm to 13dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub power: Option<i8>,
+
+    /// Trigger edge
+    #[cfg_attr(feature = "clap", clap(long, value_enum, default_value = "rising"))]
+    pub edge: Edge,
+
+    /// Number of triggers before exiting (unlimited if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub count: Option<u32>,
+
+    /// Interval for polling the trigger input (`InputPin` triggers)
+    #[cfg_attr(feature="clap", clap(long, default_value="10us", value_parser=crate::duration_from_str))]
+    pub trigger_poll: Duration,
+
+    /// GPIO character device for the trigger input
+    #[cfg(feature = "gpiochip")]
+    #[cfg_attr(feature = "clap", clap(long, default_value = "/dev/gpiochip0"))]
+    pub gpiochip: String,
+
+    /// GPIO line offset for the trigger input
+    #[cfg(feature = "gpiochip")]
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub gpio_line: Option<u32>,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for TriggerOptions {
+    fn default() -> Self {
+        Self {
+            data: vec![],
+            power: None,
+            edge: Edge::Rising,
+            count: None,
+            trigger_poll: Duration::from_micros(10),
+            #[cfg(feature = "gpiochip")]
+            gpiochip: "/dev/gpiochip0".to_string(),
+            #[cfg(feature = "gpiochip")]
+            gpio_line: None,
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+/// Trigger input, blocking until the configured edge occurs
+pub trait TriggerInput {
+    /// Input error type
+    type Error: Debug;
+
+    /// Wait for an edge, using the provided delay between polls where required
+    fn wait_edge<D: DelayNs>(&mut self, edge: Edge, delay: &mut D) -> Result<(), Self::Error>;
+}
+
+/// Trigger input polling an [`InputPin`] at a fixed interval
+pub struct PolledInput<P> {
+    pin: P,
+    poll_us: u32,
+}
+
+impl<P: InputPin> PolledInput<P> {
+    /// Create a polled trigger input
+    pub fn new(pin: P, poll: Duration) -> Self {
+        Self {
+            pin,
+            poll_us: poll.as_micros() as u32,
+        }
+    }
+
+    /// Release the input pin
+    pub fn free(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: InputPin> TriggerInput for PolledInput<P> {
+    type Error = P::Error;
+
+    fn wait_edge<D: DelayNs>(&mut self, edge: Edge, delay: &mut D) -> Result<(), Self::Error> {
+        let mut last = self.pin.is_high()?;
+
+        loop {
+            delay.delay_us(self.poll_us);
+
+            let level = self.pin.is_high()?;
+            let fired = match edge {
+                Edge::Rising => !last && level,
+                Edge::Falling => last && !level,
+                Edge::Both => last != level,
+            };
+            if fired {
+                return Ok(());
+            }
+            last = level;
+        }
+    }
+}
+
+/// Trigger input using Linux GPIO character device edge events
+#[cfg(feature = "gpiochip")]
+pub struct GpiochipInput {
+    events: gpio_cdev::LineEventHandle,
+}
+
+#[cfg(feature = "gpiochip")]
+impl GpiochipInput {
+    /// Request edge events for a line on the provided GPIO chip
+    pub fn open(path: &str, line: u32, edge: Edge) -> Result<Self, gpio_cdev::Error> {
+        use gpio_cdev::{Chip, EventRequestFlags, LineRequestFlags};
+
+        let flags = match edge {
+            Edge::Rising => EventRequestFlags::RISING_EDGE,
+            Edge::Falling => EventRequestFlags::FALLING_EDGE,
+            Edge::Both => EventRequestFlags::BOTH_EDGES,
+        };
+        let events = Chip::new(path)?.get_line(line)?.events(
+            LineRequestFlags::INPUT,
+            flags,
+            "radio-trigger",
+        )?;
+
+        Ok(Self { events })
+    }
+}
+
+#[cfg(feature = "gpiochip")]
+impl TriggerInput for GpiochipInput {
+    type Error = gpio_cdev::Error;
+
+    fn wait_edge<D: DelayNs>(&mut self, _edge: Edge, _delay: &mut D) -> Result<(), Self::Error> {
+        // Edges are filtered by the kernel on request
+        self.events.get_event().map(|_| ())
+    }
+}
+
+/// Trigger-to-air latency statistics, in microseconds from edge detection
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TriggerStats {
+    /// Triggers received
+    pub triggers: u32,
+    /// Latency to `start_transmit` returning
+    pub start: Samples,
+    /// Latency to transmit completion
+    pub complete: Samples,
+}
+
+impl core::fmt::Display for TriggerStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let v = |m: Option<f32>| m.map(|v| format!("{:.0}", v)).unwrap_or("-".into());
+        writeln!(f, "triggers: {}", self.triggers)?;
+        for (name, s) in [("start", &self.start), ("complete", &self.complete)] {
+            writeln!(
+                f,
+                "{:>8} (us): min {} mean {} p99 {} max {}",
+                name,
+                v(s.min()),
+                v(s.mean()),
+                v(s.percentile(99.0)),
+                v(s.max())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Transmit the configured frame on each trigger edge, returning latency statistics
+///
+/// Time is read from the provided clock, with transmit completion polled at the blocking
+/// poll interval.
+pub fn do_trigger_tx<T, P, C, E>(
+    radio: &mut T,
+    input: &mut P,
+    clock: C,
+    options: TriggerOptions,
+) -> Result<TriggerStats, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    P: TriggerInput,
+    C: Clock,
+    E: Debug,
+{
+    let mut stats = TriggerStats::default();
+
+    // Configure power ahead of the first trigger
+    if let Some(p) = options.power {
+        radio.set_power(p)?;
+    }
+
+    let timeout_us = options.blocking_options.timeout.as_micros() as u64;
+    let poll_us = options.blocking_options.poll_interval.as_micros() as u32;
+
+    while options.count.map(|c| stats.triggers < c).unwrap_or(true) {
+        input
+            .wait_edge(options.edge, radio)
+            .expect("Error waiting for trigger");
+
+        let t0 = clock.now_us();
+        radio.start_transmit(&options.data)?;
+        let start = clock.elapsed_us(t0);
+
+        while !radio.check_transmit()? {
+            if clock.elapsed_us(t0) > timeout_us {
+                return Err(BlockingError::Timeout);
+            }
+            radio.delay_us(poll_us);
+        }
+        let complete = clock.elapsed_us(t0);
+
+        debug!(
+            "Trigger {}: start {} us, complete {} us",
+            stats.triggers, start, complete
+        );
+
+        stats.triggers += 1;
+        stats.start.update(start as f32);
+        stats.complete.update(complete as f32);
+    }
+
+    info!("Trigger latency:\n{}", stats);
+
+    Ok(stats)
+}
+
+/// Transmit on edges from the configured GPIO character device line
+#[cfg(feature = "gpiochip")]
+pub fn do_trigger_gpiochip<T, E>(
+    radio: &mut T,
+    options: TriggerOptions,
+) -> Result<TriggerStats, BlockingError<E>>
+where
+    T: Transmit<Error = E> + Power<Error = E> + DelayNs,
+    E: Debug,
+{
+    let line = options.gpio_line.expect("trigger requires --gpio-line");
+    let mut input =
+        GpiochipInput::open(&options.gpiochip, line, options.edge).expect("Error opening GPIO");
+
+    do_trigger_tx(radio, &mut input, crate::clock::StdClock, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::ErrorType;
+
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    /// Input toggling on every read after the first
+    struct TogglePin(u32);
+
+    impl ErrorType for TogglePin {
+        type Error = Infallible;
+    }
+
+    impl InputPin for TogglePin {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            self.0 += 1;
+            Ok(self.0.is_multiple_of(2))
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            self.is_high().map(|v| !v)
+        }
+    }
+
+    /// Radio completing transmissions on the third check, with delays advancing a clock
+    struct ClockedRadio<'a> {
+        clock: &'a VirtualClock,
+        checks: u32,
+        power: Option<i8>,
+    }
+
+    impl Transmit for ClockedRadio<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            self.checks = 0;
+            self.clock.advance_us(50);
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            self.checks += 1;
+            Ok(self.checks == 3)
+        }
+    }
+
+    impl Power for ClockedRadio<'_> {
+        type Error = ();
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            self.power = Some(power);
+            Ok(())
+        }
+    }
+
+    impl DelayNs for ClockedRadio<'_> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.clock.delay_ns(ns)
+        }
+    }
+
+    #[test]
+    fn trigger_latency() {
+        let clock = VirtualClock::new();
+        let mut radio = ClockedRadio {
+            clock: &clock,
+            checks: 0,
+            power: None,
+        };
+        let mut input = PolledInput::new(TogglePin(0), Duration::from_micros(10));
+        let options = TriggerOptions {
+            data: vec![0xaa, 0xbb],
+            power: Some(10),
+            count: Some(3),
+            ..Default::default()
+        };
+
+        let stats = do_trigger_tx(&mut radio, &mut input, &clock, options).unwrap();
+
+        assert_eq!(radio.power, Some(10));
+        assert_eq!(stats.triggers, 3);
+        assert_eq!(stats.start.values(), &[50.0; 3]);
+        // Two polls at the default 100 us interval before completion
+        assert_eq!(stats.complete.values(), &[250.0; 3]);
+    }
+}
@@ -0,0 +1,505 @@
+//! Modulation parameter tuning operation
+//!
+//! Sweeps FSK bitrate / deviation or LoRa spreading factor / bandwidth combinations
+//! against a peer running `tune --respond`, running a link test at each setting and
+//! reporting which settings achieve the target packet error rate (PER).
+//!
+//! Settings are agreed over the base (starting) configuration: the initiator sends a
+//! tune request, the responder acknowledges and both apply the setting for the trial.
+//! Both nodes revert to the base configuration after each trial (the responder once no
+//! frames have been received for the revert timeout), so unusable settings cannot
+//! strand the peer.
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+
+use super::{LinkTestInfo, PingPongOptions, do_ping_pong};
+use crate::{
+    Power, Receive, ReceiveInfo, Transmit,
+    blocking::{BlockingError, BlockingReceive, BlockingTransmit, Phase, record_wait},
+    config::{ConfigError, ConfigOption, Configure},
+};
+
+/// Tune request frame magic
+pub const TUNE_REQUEST: [u8; 4] = *b"TUNE";
+
+/// Tune acknowledgement frame magic
+pub const TUNE_ACK: [u8; 4] = *b"TACK";
+
+/// Attempts to agree each setting with the responder
+const REQUEST_ATTEMPTS: u32 = 3;
+
+/// Configuration for Tune operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct TuneOptions {
+    /// FSK bitrates to sweep in bps (comma separated)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub bitrate: Vec<u32>,
+
+    /// FSK deviations to sweep in Hz (comma separated)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub deviation: Vec<u32>,
+
+    /// LoRa spreading factors to sweep (comma separated)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub sf: Vec<u8>,
+
+    /// LoRa bandwidths to sweep in Hz (comma separated)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ','))]
+    pub bw: Vec<u32>,
+
+    /// Target packet error rate (`0.0..=1.0`)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0.01"))]
+    pub target_per: f32,
+
+    /// Respond to tune requests and echo link test frames, rather than initiating
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub respond: bool,
+
+    /// Inactivity after which the responder reverts to the base configuration
+    #[cfg_attr(feature = "clap", clap(long, default_value = "2s"))]
+    pub revert_timeout: HumanDuration,
+
+    /// Delay before responding to frames, and before starting each trial
+    #[cfg_attr(feature = "clap", clap(long, default_value = "10ms"))]
+    pub turnaround: HumanDuration,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub ping_pong_options: PingPongOptions,
+}
+
+impl TuneOptions {
+    /// Combinations of settings to be swept
+    ///
+    /// FSK and LoRa parameters are each swept as the product of the provided values,
+    /// where only one of a pair is provided that value alone is swept.
+    pub fn settings(&self) -> Vec<Vec<ConfigOption>> {
+        fn product(a: Vec<ConfigOption>, b: Vec<ConfigOption>) -> Vec<Vec<ConfigOption>> {
+            match (a.is_empty(), b.is_empty()) {
+                (true, _) => b.into_iter().map(|o| vec![o]).collect(),
+                (_, true) => a.into_iter().map(|o| vec![o]).collect(),
+                _ => a
+                    .iter()
+                    .flat_map(|x| b.iter().map(move |y| vec![x.clone(), y.clone()]))
+                    .collect(),
+            }
+        }
+
+        let mut s = product(
+            self.bitrate
+                .iter()
+                .map(|v| ConfigOption::Bitrate(*v))
+                .collect(),
+            self.deviation
+                .iter()
+                .map(|v| ConfigOption::Deviation(*v))
+                .collect(),
+        );
+        s.extend(product(
+            self.sf
+                .iter()
+                .map(|v| ConfigOption::SpreadingFactor(*v))
+                .collect(),
+            self.bw
+                .iter()
+                .map(|v| ConfigOption::Bandwidth(*v))
+                .collect(),
+        ));
+        s
+    }
+}
+
+/// Result of a single tuning trial
+#[derive(Clone, Debug, PartialEq)]
+pub struct TuneResult {
+    /// Settings applied for the trial
+    pub settings: Vec<ConfigOption>,
+    /// Measured packet error rate (1.0 where the setting could not be agreed)
+    pub per: f32,
+    /// Link test results, where the trial was run
+    pub link: Option<LinkTestInfo>,
+}
+
+impl TuneResult {
+    /// Check whether the trial achieved the provided target PER
+    pub fn passed(&self, target_per: f32) -> bool {
+        self.link.is_some() && self.per <= target_per
+    }
+}
+
+impl core::fmt::Display for TuneResult {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, o) in self.settings.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            match o {
+                ConfigOption::Bitrate(v) => write!(f, "bitrate={}", v)?,
+                ConfigOption::Deviation(v) => write!(f, "deviation={}", v)?,
+                ConfigOption::SpreadingFactor(v) => write!(f, "sf={}", v)?,
+                ConfigOption::Bandwidth(v) => write!(f, "bw={}", v)?,
+                o => write!(f, "{:?}", o)?,
+            }
+        }
+        match &self.link {
+            Some(_) => write!(f, " per={:.4}", self.per),
+            None => write!(f, " not agreed"),
+        }
+    }
+}
+
+/// Encode a tune request for the provided settings
+pub fn encode_tune_request(settings: &[ConfigOption]) -> Vec<u8> {
+    let mut b = TUNE_REQUEST.to_vec();
+    for o in settings {
+        let (tag, v) = match o {
+            ConfigOption::Bitrate(v) => (1, *v),
+            ConfigOption::Deviation(v) => (2, *v),
+            ConfigOption::SpreadingFactor(v) => (3, *v as u32),
+            ConfigOption::Bandwidth(v) => (4, *v),
+            _ => continue,
+        };
+        b.push(tag);
+        b.extend_from_slice(&v.to_be_bytes());
+    }
+    b
+}
+
+/// Decode a tune request, returning `None` if the frame is not a valid request
+pub fn decode_tune_request(data: &[u8]) -> Option<Vec<ConfigOption>> {
+    let body = data.strip_prefix(&TUNE_REQUEST)?;
+    if !body.len().is_multiple_of(5) {
+        return None;
+    }
+
+    body.chunks(5)
+        .map(|c| {
+            let v = u32::from_be_bytes([c[1], c[2], c[3], c[4]]);
+            match c[0] {
+                1 => Some(ConfigOption::Bitrate(v)),
+                2 => Some(ConfigOption::Deviation(v)),
+                3 => u8::try_from(v).ok().map(ConfigOption::SpreadingFactor),
+                4 => Some(ConfigOption::Bandwidth(v)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Fetch the current values of the provided options, used to restore the base configuration
+fn base_settings<T, E>(
+    radio: &mut T,
+    settings: &[Vec<ConfigOption>],
+) -> Result<Vec<ConfigOption>, ConfigError<E>>
+where
+    T: Configure<Error = E>,
+{
+    let mut base: Vec<ConfigOption> = vec![];
+    for o in settings.iter().flatten() {
+        if base
+            .iter()
+            .any(|b| core::mem::discriminant(b) == core::mem::discriminant(o))
+        {
+            continue;
+        }
+        let mut v = o.clone();
+        radio.get_option(&mut v)?;
+        base.push(v);
+    }
+    Ok(base)
+}
+
+fn apply<T, E>(radio: &mut T, settings: &[ConfigOption]) -> Result<(), ConfigError<E>>
+where
+    T: Configure<Error = E>,
+{
+    settings.iter().try_for_each(|o| radio.set_option(o))
+}
+
+fn config_err<E>(e: ConfigError<E>) -> BlockingError<E> {
+    match e {
+        ConfigError::Other(e) => BlockingError::Inner(e),
+        ConfigError::NotSupported => {
+            BlockingError::Unsupported("tuning option not supported by radio")
+        }
+    }
+}
+
+/// Run the tune operation, returning the result for each swept setting
+/// (or no results in responder mode, which runs until an error occurs)
+pub fn do_tune<T, I, E>(
+    radio: &mut T,
+    options: TuneOptions,
+) -> Result<Vec<TuneResult>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + Configure<Error = E>
+        + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+{
+    match options.respond {
+        true => do_tune_respond(radio, options).map(|_| vec![]),
+        false => do_tune_initiate(radio, options),
+    }
+}
+
+fn do_tune_initiate<T, I, E>(
+    radio: &mut T,
+    options: TuneOptions,
+) -> Result<Vec<TuneResult>, BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E>
+        + Transmit<Error = E>
+        + Power<Error = E>
+        + Configure<Error = E>
+        + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+{
+    let settings = options.settings();
+    let base = base_settings(radio, &settings).map_err(config_err)?;
+    let blocking = options.ping_pong_options.blocking_options.clone();
+    let mut buff = [0u8; 64];
+    let mut results = vec![];
+
+    for s in settings {
+        let mut result = TuneResult {
+            settings: s.clone(),
+            per: 1.0,
+            link: None,
+        };
+
+        // Agree the setting with the responder using the base configuration
+        let req = encode_tune_request(&s);
+        let mut agreed = false;
+        for _ in 0..REQUEST_ATTEMPTS {
+            radio.do_transmit(&req, blocking.clone())?;
+            let sent_at = std::time::Instant::now();
+            let timeout_us = blocking.timeout.as_micros();
+            match radio.do_receive(&mut buff, blocking.clone()) {
+                Ok((n, _)) if buff[..n] == TUNE_ACK => {
+                    record_wait(Phase::Ack, sent_at.elapsed().as_micros(), timeout_us, false);
+                    agreed = true;
+                    break;
+                }
+                Ok(_) => (),
+                Err(BlockingError::Timeout) => record_wait(Phase::Ack, 0, timeout_us, true),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !agreed {
+            warn!("Tune request not acknowledged: {}", result);
+            results.push(result);
+            continue;
+        }
+
+        // Run the trial
+        apply(radio, &s).map_err(config_err)?;
+        radio.delay_us(options.turnaround.as_micros() as u32);
+
+        let link = do_ping_pong(radio, options.ping_pong_options.clone())?;
+        result.per = match link.sent {
+            0 => 1.0,
+            n => 1.0 - link.received as f32 / n as f32,
+        };
+        result.link = Some(link);
+
+        info!("Tune trial: {}", result);
+        results.push(result);
+
+        // Revert, waiting for the responder to do the same
+        apply(radio, &base).map_err(config_err)?;
+        radio.delay_us(options.revert_timeout.as_micros() as u32);
+    }
+
+    info!("Tuning results (target PER {}):", options.target_per);
+    for r in &results {
+        let status = match r.passed(options.target_per) {
+            true => "PASS",
+            false => "FAIL",
+        };
+        info!("  {} {}", status, r);
+    }
+
+    Ok(results)
+}
+
+fn do_tune_respond<T, I, E>(radio: &mut T, options: TuneOptions) -> Result<(), BlockingError<E>>
+where
+    T: Receive<Info = I, Error = E> + Transmit<Error = E> + Configure<Error = E> + DelayNs,
+    I: ReceiveInfo,
+    E: std::fmt::Debug,
+{
+    let settings = options.settings();
+    let base = base_settings(radio, &settings).map_err(config_err)?;
+    let blocking = options.ping_pong_options.blocking_options.clone();
+    let mut buff = [0u8; 1024];
+
+    let mut tuned = false;
+    let mut last_rx = std::time::Instant::now();
+
+    loop {
+        let (n, _) = match radio.do_receive(&mut buff, blocking.clone()) {
+            Ok(r) => r,
+            Err(BlockingError::Timeout) => {
+                // Revert to base configuration when the trial goes quiet
+                if tuned && last_rx.elapsed() >= *options.revert_timeout {
+                    debug!("Reverting to base configuration");
+                    apply(radio, &base).map_err(config_err)?;
+                    tuned = false;
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        last_rx = std::time::Instant::now();
+
+        radio.delay_us(options.turnaround.as_micros() as u32);
+
+        match (tuned, decode_tune_request(&buff[..n])) {
+            (false, Some(s)) => {
+                radio.do_transmit(&TUNE_ACK, blocking.clone())?;
+                apply(radio, &s).map_err(config_err)?;
+                tuned = true;
+
+                info!(
+                    "Tuned: {}",
+                    TuneResult {
+                        settings: s,
+                        per: 0.0,
+                        link: None
+                    }
+                );
+            }
+            _ => radio.do_transmit(&buff[..n], blocking.clone())?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tune_settings() {
+        let mut o = TuneOptions {
+            bitrate: vec![4_800, 9_600],
+            deviation: vec![5_000],
+            sf: vec![7, 12],
+            bw: vec![],
+            target_per: 0.01,
+            respond: false,
+            revert_timeout: std::time::Duration::from_secs(1).into(),
+            turnaround: std::time::Duration::from_millis(1).into(),
+            ping_pong_options: PingPongOptions {
+                rounds: 10,
+                delay: std::time::Duration::from_millis(1).into(),
+                backoff: std::time::Duration::from_millis(1).into(),
+                ..Default::default()
+            },
+        };
+
+        let s = o.settings();
+        assert_eq!(
+            s,
+            vec![
+                vec![ConfigOption::Bitrate(4_800), ConfigOption::Deviation(5_000)],
+                vec![ConfigOption::Bitrate(9_600), ConfigOption::Deviation(5_000)],
+                vec![ConfigOption::SpreadingFactor(7)],
+                vec![ConfigOption::SpreadingFactor(12)],
+            ]
+        );
+
+        o.bitrate.clear();
+        assert_eq!(o.settings()[0], vec![ConfigOption::Deviation(5_000)]);
+    }
+
+    /// Radio supporting only LoRa configuration options
+    struct LoRaRadio;
+
+    impl Configure for LoRaRadio {
+        type Error = ();
+
+        fn set_option(&mut self, o: &ConfigOption) -> Result<(), ConfigError<Self::Error>> {
+            match o {
+                ConfigOption::SpreadingFactor(_) => Ok(()),
+                _ => Err(ConfigError::NotSupported),
+            }
+        }
+
+        fn get_option(&mut self, o: &mut ConfigOption) -> Result<(), ConfigError<Self::Error>> {
+            match o {
+                ConfigOption::SpreadingFactor(v) => {
+                    *v = 7;
+                    Ok(())
+                }
+                _ => Err(ConfigError::NotSupported),
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_settings() {
+        let mut radio = LoRaRadio;
+
+        let s = vec![vec![ConfigOption::SpreadingFactor(9)]];
+        assert_eq!(
+            base_settings(&mut radio, &s).map_err(config_err),
+            Ok(vec![ConfigOption::SpreadingFactor(7)])
+        );
+
+        // Sweeping FSK settings on a LoRa radio fails rather than aborting
+        let s = vec![vec![ConfigOption::Bitrate(9_600)]];
+        assert_eq!(
+            base_settings(&mut radio, &s).map_err(config_err),
+            Err(BlockingError::Unsupported(
+                "tuning option not supported by radio"
+            ))
+        );
+        assert_eq!(
+            apply(&mut radio, &s[0]).map_err(config_err),
+            Err(BlockingError::Unsupported(
+                "tuning option not supported by radio"
+            ))
+        );
+    }
+
+    #[test]
+    fn tune_requests() {
+        let s = vec![
+            ConfigOption::SpreadingFactor(9),
+            ConfigOption::Bandwidth(125_000),
+        ];
+        let b = encode_tune_request(&s);
+        assert_eq!(decode_tune_request(&b), Some(s.clone()));
+
+        assert_eq!(decode_tune_request(b"TUNE"), Some(vec![]));
+        assert_eq!(decode_tune_request(b"TACK"), None);
+        assert_eq!(decode_tune_request(&[b'T', b'U', b'N', b'E', 9, 0]), None);
+        assert_eq!(
+            decode_tune_request(&[b'T', b'U', b'N', b'E', 9, 0, 0, 0, 0]),
+            None
+        );
+
+        let r = TuneResult {
+            settings: s,
+            per: 0.5,
+            link: Some(LinkTestInfo::new(4)),
+        };
+        assert_eq!(r.to_string(), "sf=9 bw=125000 per=0.5000");
+        assert!(!r.passed(0.1));
+    }
+}
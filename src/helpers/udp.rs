@@ -0,0 +1,168 @@
+//! UDP-backed radio, carrying packets as datagrams between peers
+//!
+//! Useful for simulating links between nodes on a host or network (or in scripted tests)
+//! without radio hardware. Received packets report the configured RSSI, and delays run
+//! on a (shareable) [`ScaledClock`] so simulations may run faster than real time.
+
+use std::net::{SocketAddr, UdpSocket};
+
+use embedded_hal::delay::DelayNs;
+
+use crate::{BasicInfo, Power, Receive, Rssi, Transmit, clock::ScaledClock};
+
+/// Maximum datagram size
+const MAX_DATAGRAM: usize = 2048;
+
+/// Radio implemented over a UDP socket
+#[derive(Debug)]
+pub struct UdpRadio {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    power: i8,
+    rssi: i16,
+    rx: Option<Vec<u8>>,
+    clock: ScaledClock,
+}
+
+impl UdpRadio {
+    /// Create a UDP radio bound to `bind`, transmitting packets to `peer`
+    pub fn new(bind: SocketAddr, peer: SocketAddr) -> Result<Self, std::io::Error> {
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_nonblocking(true)?;
+        socket.set_broadcast(true)?;
+
+        Ok(Self {
+            socket,
+            peer,
+            power: 0,
+            rssi: 0,
+            rx: None,
+            clock: ScaledClock::default(),
+        })
+    }
+
+    /// Local socket address
+    pub fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        self.socket.local_addr()
+    }
+
+    /// Set the peer address packets are transmitted to
+    pub fn set_peer(&mut self, peer: SocketAddr) {
+        self.peer = peer;
+    }
+
+    /// Set the RSSI reported for received packets
+    pub fn set_rssi(&mut self, rssi: i16) {
+        self.rssi = rssi;
+    }
+
+    /// Set the clock used for delays, sharing a [`ScaledClock`] between nodes to accelerate
+    /// simulations
+    pub fn set_clock(&mut self, clock: ScaledClock) {
+        self.clock = clock;
+    }
+
+    /// Clock used for delays
+    pub fn clock(&self) -> ScaledClock {
+        self.clock
+    }
+
+    /// Configured transmit power in dBm
+    pub fn power(&self) -> i8 {
+        self.power
+    }
+}
+
+impl Transmit for UdpRadio {
+    type Error = std::io::Error;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.socket.send_to(data, self.peer)?;
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+impl Receive for UdpRadio {
+    type Error = std::io::Error;
+    type Info = BasicInfo;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.rx = None;
+        Ok(())
+    }
+
+    fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+        if self.rx.is_some() {
+            return Ok(true);
+        }
+
+        let mut b = vec![0u8; MAX_DATAGRAM];
+        match self.socket.recv_from(&mut b) {
+            Ok((n, _from)) => {
+                b.truncate(n);
+                self.rx = Some(b);
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let data = self.rx.take().unwrap_or_default();
+        let n = data.len().min(buff.len());
+        buff[..n].copy_from_slice(&data[..n]);
+
+        Ok((n, BasicInfo::new(self.rssi, 0)))
+    }
+}
+
+impl Power for UdpRadio {
+    type Error = std::io::Error;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.power = power;
+        Ok(())
+    }
+}
+
+impl Rssi for UdpRadio {
+    type Error = std::io::Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        Ok(self.rssi)
+    }
+}
+
+impl DelayNs for UdpRadio {
+    fn delay_ns(&mut self, ns: u32) {
+        self.clock.delay_ns(ns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::{BlockingOptions, BlockingReceive, BlockingTransmit};
+
+    #[test]
+    fn udp_radio_link() {
+        let any = "127.0.0.1:0".parse().unwrap();
+        let mut a = UdpRadio::new(any, any).unwrap();
+        let mut b = UdpRadio::new(any, a.local_addr().unwrap()).unwrap();
+        a.set_peer(b.local_addr().unwrap());
+        b.set_rssi(-60);
+
+        let opts = BlockingOptions::default();
+        a.do_transmit(&[1, 2, 3], opts.clone()).unwrap();
+
+        let mut buff = [0u8; 16];
+        let (n, i) = b.do_receive(&mut buff, opts).unwrap();
+        assert_eq!(&buff[..n], &[1, 2, 3]);
+        assert_eq!(i, BasicInfo::new(-60, 0));
+    }
+}
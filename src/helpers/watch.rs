@@ -0,0 +1,370 @@
+//! Peer liveness monitoring
+//!
+//! The `watch` operation listens for periodic beacons (any valid [`frame`](crate::frame))
+//! from a configured set of peers, reporting when a peer has not been heard for
+//! `--silent-after` and again when it recovers, turning a spare radio into a simple
+//! network health monitor. Peer state changes are logged, passed to any registered
+//! callbacks and optionally run a `--watch-exec` command.
+
+use std::process::Command;
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info, warn};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info, warn};
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+use humantime::Duration as HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use super::Shutdown;
+use crate::{
+    Receive, ReceiveInfo,
+    blocking::BlockingOptions,
+    clock::{Clock, StdClock},
+    frame::{self, Address},
+};
+
+/// Configuration for Watch operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct WatchOptions {
+    /// Peer addresses expected to beacon (comma separated)
+    #[cfg_attr(feature = "clap", clap(long, value_delimiter = ',', required = true))]
+    pub peers: Vec<Address>,
+
+    /// Report a peer as silent where no frame has been heard for this duration
+    #[cfg_attr(feature = "clap", clap(long, default_value = "30s"))]
+    pub silent_after: HumanDuration,
+
+    /// Interval at which peer status is printed
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub status_interval: Option<HumanDuration>,
+
+    /// Watch duration (runs until interrupted if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub duration: Option<HumanDuration>,
+
+    /// Command run (with `sh -c`) on peer state changes, with the peer described by the
+    /// `RADIO_PEER` (address in hex) and `RADIO_PEER_STATE` (silent or alive) environment
+    /// variables
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub watch_exec: Option<String>,
+
+    /// Shutdown signal, stopping the watch cleanly once triggered
+    #[cfg_attr(feature = "clap", clap(skip = Shutdown::global()))]
+    pub shutdown: Shutdown,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub blocking_options: BlockingOptions,
+}
+
+/// Liveness of a watched peer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub address: Address,
+    /// Whether the peer has been heard within the silence timeout
+    pub alive: bool,
+    /// Time the peer was last heard, in microseconds since the unix epoch
+    pub last_seen_us: Option<u64>,
+    /// RSSI of the most recently received frame
+    pub last_rssi: Option<i16>,
+    /// Frames received from the peer
+    pub frames: u64,
+    /// Number of times the peer has gone silent
+    pub outages: u32,
+}
+
+impl core::fmt::Display for PeerStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = match self.alive {
+            true => "alive",
+            false => "silent",
+        };
+        write!(
+            f,
+            "{:04x} {} ({} frames, {} outages",
+            self.address, state, self.frames, self.outages
+        )?;
+        if let Some(r) = self.last_rssi {
+            write!(f, ", last rssi {} dBm", r)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Peer state change
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerEvent {
+    pub address: Address,
+    /// Whether the peer recovered (or went silent)
+    pub alive: bool,
+    /// Time since the peer was last heard (or since the watch started) in microseconds
+    pub silent_us: u64,
+}
+
+impl core::fmt::Display for PeerEvent {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = match self.alive {
+            true => "recovered",
+            false => "silent",
+        };
+        write!(
+            f,
+            "peer {:04x} {} after {:.1} s",
+            self.address,
+            state,
+            self.silent_us as f32 / 1e6
+        )
+    }
+}
+
+type PeerHandler = Box<dyn FnMut(&PeerEvent) + Send>;
+
+/// Peer liveness tracker, raising events as peers go silent and recover
+pub struct PeerWatch {
+    peers: Vec<PeerStatus>,
+    silent_after_us: u64,
+    start_us: u64,
+    handlers: Vec<PeerHandler>,
+    exec: Option<String>,
+}
+
+impl PeerWatch {
+    /// Create a tracker for the provided peers, starting at `now_us`
+    ///
+    /// Peers are considered alive until they have not been heard for `silent_after`
+    /// from the start of the watch.
+    pub fn new(peers: &[Address], silent_after: std::time::Duration, now_us: u64) -> Self {
+        let peers = peers
+            .iter()
+            .map(|a| PeerStatus {
+                address: *a,
+                alive: true,
+                last_seen_us: None,
+                last_rssi: None,
+                frames: 0,
+                outages: 0,
+            })
+            .collect();
+
+        Self {
+            peers,
+            silent_after_us: silent_after.as_micros() as u64,
+            start_us: now_us,
+            handlers: vec![],
+            exec: None,
+        }
+    }
+
+    /// Register a callback, called on each peer state change
+    pub fn on_event<F: FnMut(&PeerEvent) + Send + 'static>(&mut self, f: F) {
+        self.handlers.push(Box::new(f));
+    }
+
+    /// Record a frame heard from `address` at `now_us`, returning an event where the peer
+    /// has recovered, or `None` for unwatched addresses
+    pub fn heard(&mut self, address: Address, rssi: i16, now_us: u64) -> Option<PeerEvent> {
+        let start_us = self.start_us;
+        let p = self.peers.iter_mut().find(|p| p.address == address)?;
+
+        let since = p.last_seen_us.unwrap_or(start_us);
+        p.last_seen_us = Some(now_us);
+        p.last_rssi = Some(rssi);
+        p.frames += 1;
+
+        match p.alive {
+            true => None,
+            false => {
+                p.alive = true;
+                let event = PeerEvent {
+                    address,
+                    alive: true,
+                    silent_us: now_us.saturating_sub(since),
+                };
+                Some(self.dispatch(event))
+            }
+        }
+    }
+
+    /// Check for peers silent at `now_us`, returning an event for each newly silent peer
+    pub fn poll(&mut self, now_us: u64) -> Vec<PeerEvent> {
+        let mut events = vec![];
+        for p in self.peers.iter_mut().filter(|p| p.alive) {
+            let silent_us = now_us.saturating_sub(p.last_seen_us.unwrap_or(self.start_us));
+            if silent_us >= self.silent_after_us {
+                p.alive = false;
+                p.outages += 1;
+                events.push(PeerEvent {
+                    address: p.address,
+                    alive: false,
+                    silent_us,
+                });
+            }
+        }
+
+        events.into_iter().map(|e| self.dispatch(e)).collect()
+    }
+
+    /// Current status of each watched peer
+    pub fn peers(&self) -> &[PeerStatus] {
+        &self.peers
+    }
+
+    /// Log and dispatch a peer event
+    fn dispatch(&mut self, event: PeerEvent) -> PeerEvent {
+        match event.alive {
+            true => info!("Watch: {}", event),
+            false => warn!("Watch: {}", event),
+        }
+
+        for h in self.handlers.iter_mut() {
+            h(&event);
+        }
+
+        if let Some(cmd) = &self.exec {
+            run_watch_command(cmd, &event);
+        }
+
+        event
+    }
+}
+
+impl core::fmt::Debug for PeerWatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PeerWatch")
+            .field("peers", &self.peers)
+            .field("silent_after_us", &self.silent_after_us)
+            .finish()
+    }
+}
+
+/// Run a watch command without blocking the receive loop, reaping it on a background thread
+fn run_watch_command(cmd: &str, event: &PeerEvent) {
+    let state = match event.alive {
+        true => "alive",
+        false => "silent",
+    };
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("RADIO_PEER", format!("{:04x}", event.address))
+        .env("RADIO_PEER_STATE", state)
+        .spawn();
+
+    match child {
+        Ok(mut c) => {
+            std::thread::spawn(move || c.wait());
+        }
+        Err(e) => warn!("Error running watch command '{}': {:?}", cmd, e),
+    }
+}
+
+/// Watch for beacons from the configured peers, returning the final status of each peer
+pub fn do_watch<T, I, E>(
+    radio: &mut T,
+    buff: &mut [u8],
+    options: WatchOptions,
+) -> Result<Vec<PeerStatus>, E>
+where
+    T: Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let mut watch = PeerWatch::new(&options.peers, *options.silent_after, StdClock.now_us());
+    watch.exec = options.watch_exec.clone();
+
+    let start = std::time::Instant::now();
+    let mut last_status = start;
+
+    radio.start_receive()?;
+
+    loop {
+        if options.shutdown.is_triggered()
+            || options.duration.is_some_and(|d| start.elapsed() >= *d)
+        {
+            break;
+        }
+
+        let now_us = StdClock.now_us();
+        watch.poll(now_us);
+
+        if let Some(i) = options.status_interval
+            && last_status.elapsed() >= *i
+        {
+            last_status = std::time::Instant::now();
+            for p in watch.peers() {
+                info!("Watch: {}", p);
+            }
+        }
+
+        if radio.check_receive(true)? {
+            let (n, i) = radio.get_received(buff)?;
+            match frame::decode(&buff[..n]) {
+                Ok((h, _)) if options.peers.contains(&h.src) => {
+                    watch.heard(h.src, i.rssi(), now_us);
+                }
+                Ok((h, _)) => debug!("Watch ignoring frame from unwatched peer {:04x}", h.src),
+                Err(e) => debug!("Watch ignoring invalid frame: {:?}", e),
+            }
+            radio.start_receive()?;
+        }
+
+        radio.delay_us(options.blocking_options.poll_interval.as_micros() as u32);
+    }
+
+    for p in watch.peers() {
+        info!("Watch: {}", p);
+    }
+
+    Ok(watch.peers().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn peer_liveness() {
+        let mut w = PeerWatch::new(&[0x0001, 0x0002], Duration::from_secs(10), 0);
+        let seen = Arc::new(Mutex::new(vec![]));
+        let s = seen.clone();
+        w.on_event(move |e| s.lock().unwrap().push(e.clone()));
+
+        // Peers are given the silence timeout from the start of the watch
+        assert_eq!(w.heard(0x0001, -70, 5_000_000), None);
+        assert_eq!(w.heard(0x0003, -70, 5_000_000), None);
+        assert!(w.poll(9_000_000).is_empty());
+
+        let events = w.poll(10_000_000);
+        assert_eq!(
+            events,
+            vec![PeerEvent {
+                address: 0x0002,
+                alive: false,
+                silent_us: 10_000_000,
+            }]
+        );
+        assert!(w.poll(12_000_000).is_empty());
+
+        // Silent peers recover when heard, and others go silent from their last frame
+        let e = w.heard(0x0002, -80, 13_000_000).unwrap();
+        assert_eq!((e.alive, e.silent_us), (true, 13_000_000));
+        assert_eq!(w.poll(15_000_000)[0].address, 0x0001);
+
+        let p = &w.peers()[1];
+        assert_eq!(
+            (p.alive, p.frames, p.outages, p.last_rssi),
+            (true, 1, 1, Some(-80))
+        );
+        assert!(!w.peers()[0].alive);
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+}
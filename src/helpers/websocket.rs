@@ -0,0 +1,108 @@
+//! WebSocket live packet streaming
+//!
+//! Clients connecting to the WebSocket listener receive each decoded frame (including
+//! RSSI) as a JSON text message, allowing browser dashboards to visualise live traffic.
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::{debug, info};
+
+#[cfg(feature = "defmt")]
+use defmt::{debug, info};
+
+use tungstenite::{Message, WebSocket};
+
+use super::DecodedFrame;
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+/// WebSocket broadcaster, accepting clients in the background
+pub struct WsBroadcaster {
+    addr: SocketAddr,
+    clients: Clients,
+}
+
+impl WsBroadcaster {
+    /// Listen for WebSocket clients on the provided address
+    pub fn listen(addr: SocketAddr) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let clients = Clients::default();
+
+        info!("Streaming received frames on ws://{}", addr);
+
+        let c = clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                match tungstenite::accept(stream) {
+                    Ok(ws) => c.lock().unwrap().push(ws),
+                    Err(e) => debug!("WebSocket handshake error: {:?}", e),
+                }
+            }
+        });
+
+        Ok(Self { addr, clients })
+    }
+
+    /// Bound listener address
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of connected clients
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Send a decoded frame to all clients, dropping disconnected clients
+    pub fn broadcast(&self, frame: &DecodedFrame) {
+        let msg = serde_json::to_string(frame).expect("Error encoding frame");
+
+        self.clients
+            .lock()
+            .unwrap()
+            .retain_mut(|ws| ws.send(Message::text(msg.clone())).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ws_broadcast() {
+        let ws = WsBroadcaster::listen("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let (mut client, _) = tungstenite::connect(format!("ws://{}", ws.local_addr())).unwrap();
+        while ws.clients() == 0 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let frame = DecodedFrame {
+            timestamp_us: 1,
+            rssi: -65,
+            data: vec![0xaa],
+            text: None,
+            protocol: None,
+            summary: None,
+            device: None,
+            quality: Default::default(),
+            info: String::new(),
+        };
+        ws.broadcast(&frame);
+
+        let msg = client.read().unwrap();
+        let decoded: DecodedFrame = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+        assert_eq!(decoded, frame);
+
+        // Disconnected clients are dropped on the next broadcast
+        client.close(None).unwrap();
+        drop(client);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        ws.broadcast(&frame);
+        ws.broadcast(&frame);
+        assert_eq!(ws.clients(), 0);
+    }
+}
@@ -0,0 +1,500 @@
+//! Decode worker pipeline for high-rate captures
+//!
+//! Received frames are passed through a bounded queue to worker threads for decoding
+//! and filtering, with a single writer thread writing to the configured [`PacketSink`]s,
+//! so the radio polling loop never blocks on output. Frames are dropped (and counted)
+//! rather than stalling the polling loop where the queue is full or the receive buffer
+//! pool is exhausted, bounding memory use.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "helpers-pcap")]
+use super::PcapOptions;
+use super::{DecoderRegistry, DeviceRegistry, PacketSink, ReceiveQuality, SinkStack};
+use crate::pool::{PoolStats, VecPool};
+
+/// Options for the receive decode pipeline
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct WorkerOptions {
+    /// Number of decode worker threads (decoding is performed inline if zero)
+    #[cfg_attr(feature = "clap", clap(long, default_value = "0"))]
+    pub workers: usize,
+
+    /// Maximum number of frames queued for decoding before frames are dropped
+    #[cfg_attr(feature = "clap", clap(long, default_value = "1024"))]
+    pub queue_depth: usize,
+
+    /// Maximum number of receive buffers in flight (defaults to the queue depth plus one
+    /// per worker thread)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub pool_buffers: Option<usize>,
+
+    /// Only output frames with an RSSI at or above this value (dBm)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub filter_rssi: Option<i16>,
+
+    /// Only output frames starting with this hex prefix
+    #[cfg_attr(feature = "clap", clap(long, value_parser = parse_hex))]
+    pub filter_prefix: Option<Vec<u8>>,
+
+    /// Write decoded frames to a file as JSON lines
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub json_file: Option<String>,
+
+    /// Write received frames to a raw frame log for replay with `import`
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub frame_log: Option<String>,
+
+    /// Device table (JSON) used to label frames by source device, updated with last-seen statistics
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub devices: Option<String>,
+
+    /// Stream decoded frames as JSON to WebSocket clients on this address
+    #[cfg(feature = "websocket")]
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub ws_listen: Option<std::net::SocketAddr>,
+
+    #[cfg(feature = "helpers-pcap")]
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub pcap_options: PcapOptions,
+
+    /// Protocol decoders to apply to received frames, in order (`auto` to try all registered decoders)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub decode: Vec<String>,
+
+    /// Print received frames with labelled, colour-coded fields (using the `--decode`
+    /// decoders, or all decoders where none are selected)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub annotate: bool,
+
+    /// Disable colours in annotated output
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub no_color: bool,
+}
+
+/// Parse a hex string (optionally `0x` prefixed) to bytes
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.trim_start_matches("0x");
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd length hex string '{}'", s));
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| format!("{}: {}", s, e)))
+        .collect()
+}
+
+/// Frame received by the radio polling loop
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceivedFrame {
+    pub timestamp: SystemTime,
+    pub rssi: i16,
+    pub data: Vec<u8>,
+    /// Receive quality metrics, where reported by the radio
+    pub quality: ReceiveQuality,
+    /// Formatted receive info
+    pub info: String,
+}
+
+/// Decoded frame, as written to JSON output
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DecodedFrame {
+    /// Receive time in microseconds since the unix epoch
+    pub timestamp_us: u64,
+    pub rssi: i16,
+    pub data: Vec<u8>,
+    /// Payload as text, where valid UTF-8
+    pub text: Option<String>,
+    /// Name of the matching protocol decoder
+    pub protocol: Option<String>,
+    /// Protocol decoder summary
+    pub summary: Option<String>,
+    /// Source device label, where a device registry is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Receive quality metrics, where reported by the radio
+    #[serde(default, skip_serializing_if = "ReceiveQuality::is_empty")]
+    pub quality: ReceiveQuality,
+    pub info: String,
+}
+
+impl DecodedFrame {
+    /// Decode a received frame, applying the selected protocol decoders
+    pub fn decode(frame: &ReceivedFrame, registry: &DecoderRegistry, selected: &[String]) -> Self {
+        let timestamp_us = frame
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let (protocol, summary) = registry.decode(selected, &frame.data).unzip();
+
+        Self {
+            timestamp_us,
+            rssi: frame.rssi,
+            data: frame.data.clone(),
+            text: std::str::from_utf8(&frame.data).ok().map(String::from),
+            protocol,
+            summary,
+            device: None,
+            quality: frame.quality.clone(),
+            info: frame.info.clone(),
+        }
+    }
+}
+
+impl core::fmt::Display for DecodedFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(d) = &self.device {
+            write!(f, "<{}> ", d)?;
+        }
+
+        match (&self.protocol, &self.summary, &self.text) {
+            (Some(p), Some(s), _) => write!(f, "[{}] {} info: {}", p, s, self.info),
+            (_, _, Some(s)) => write!(f, "'{}' info: {}", s, self.info),
+            _ => write!(f, "'{:02x?}' info: {}", self.data, self.info),
+        }
+    }
+}
+
+impl WorkerOptions {
+    /// Check whether a frame passes the configured filters
+    pub fn matches(&self, frame: &ReceivedFrame) -> bool {
+        if let Some(rssi) = self.filter_rssi
+            && frame.rssi < rssi
+        {
+            return false;
+        }
+        if let Some(prefix) = &self.filter_prefix
+            && !frame.data.starts_with(prefix)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+/// Pipeline statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorkerStats {
+    /// Frames submitted by the polling loop
+    pub received: u64,
+    /// Frames dropped due to a full queue
+    pub dropped: u64,
+    /// Frames removed by filters
+    pub filtered: u64,
+    /// Frames written to output
+    pub written: u64,
+    /// Receive buffer pool statistics
+    pub pool: PoolStats,
+}
+
+#[derive(Default)]
+struct Counters {
+    filtered: AtomicU64,
+    written: AtomicU64,
+}
+
+/// Output sinks, owned by the writer thread
+struct Output {
+    sinks: SinkStack,
+    devices: Option<DeviceRegistry>,
+}
+
+impl Output {
+    fn write(&mut self, mut d: DecodedFrame) -> Result<(), std::io::Error> {
+        if let Some(r) = &mut self.devices {
+            d.device = r.seen_frame(&d.data, d.rssi, d.timestamp_us);
+        }
+
+        self.sinks.write(&d)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.sinks.flush()?;
+        if let Some(r) = &mut self.devices {
+            r.save()?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode worker pipeline
+pub struct DecodeWorker {
+    options: WorkerOptions,
+    tx: Option<SyncSender<ReceivedFrame>>,
+    workers: Vec<JoinHandle<()>>,
+    writer: Option<JoinHandle<()>>,
+    counters: Arc<Counters>,
+    stats: WorkerStats,
+    output: Option<Output>,
+    registry: Arc<DecoderRegistry>,
+    pool: VecPool,
+}
+
+impl DecodeWorker {
+    /// Create a decode pipeline with the built-in protocol decoders
+    pub fn new(options: WorkerOptions) -> Result<Self, std::io::Error> {
+        Self::with_registry(options, Arc::new(DecoderRegistry::default()))
+    }
+
+    /// Create a decode pipeline using the provided decoder registry, writing to the configured sinks
+    pub fn with_registry(
+        options: WorkerOptions,
+        registry: Arc<DecoderRegistry>,
+    ) -> Result<Self, std::io::Error> {
+        let sinks = options.sinks()?;
+        Self::with_sinks(options, registry, sinks)
+    }
+
+    /// Create a decode pipeline writing to the provided sinks, spawning worker threads where configured
+    pub fn with_sinks(
+        options: WorkerOptions,
+        registry: Arc<DecoderRegistry>,
+        sinks: SinkStack,
+    ) -> Result<Self, std::io::Error> {
+        let devices = match &options.devices {
+            Some(f) => Some(DeviceRegistry::open(f)?),
+            None => None,
+        };
+        let output = Output { sinks, devices };
+        let counters = Arc::new(Counters::default());
+        let pool = VecPool::new(
+            options
+                .pool_buffers
+                .unwrap_or(options.queue_depth + options.workers + 1),
+        );
+
+        let mut w = Self {
+            options,
+            tx: None,
+            workers: vec![],
+            writer: None,
+            counters,
+            stats: WorkerStats::default(),
+            output: None,
+            registry,
+            pool,
+        };
+
+        // Decode inline where no workers are configured
+        if w.options.workers == 0 {
+            w.output = Some(output);
+            return Ok(w);
+        }
+
+        let (tx, rx) = sync_channel::<ReceivedFrame>(w.options.queue_depth);
+        let (out_tx, out_rx) = sync_channel::<DecodedFrame>(w.options.queue_depth);
+        let rx = Arc::new(Mutex::new(rx));
+
+        for _ in 0..w.options.workers {
+            let (rx, out_tx) = (rx.clone(), out_tx.clone());
+            let (opts, counters) = (w.options.clone(), w.counters.clone());
+            let (registry, pool) = (w.registry.clone(), w.pool.clone());
+
+            w.workers.push(std::thread::spawn(move || {
+                decode_worker(rx, out_tx, opts, registry, counters, pool)
+            }));
+        }
+
+        let counters = w.counters.clone();
+        w.writer = Some(std::thread::spawn(move || {
+            write_worker(out_rx, output, counters)
+        }));
+        w.tx = Some(tx);
+
+        Ok(w)
+    }
+
+    /// Copy received data into a buffer from the receive buffer pool, returning `None`
+    /// (and counting the frame as dropped) where the pool is exhausted
+    pub fn alloc(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let b = self.pool.alloc_from(data);
+        if b.is_none() {
+            self.stats.dropped += 1;
+        }
+        b
+    }
+
+    /// Submit a received frame, returning false if the frame was dropped
+    ///
+    /// Frame data is returned to the receive buffer pool once decoded.
+    pub fn submit(&mut self, frame: ReceivedFrame) -> bool {
+        self.stats.received += 1;
+
+        // Inline decoding
+        if let Some(o) = &mut self.output {
+            match self.options.matches(&frame) {
+                true => {
+                    let d = DecodedFrame::decode(&frame, &self.registry, &self.options.decode);
+                    o.write(d).expect("Error writing received frame");
+                    self.counters.written.fetch_add(1, Ordering::Relaxed);
+                }
+                false => {
+                    self.counters.filtered.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            self.pool.release(frame.data);
+            return true;
+        }
+
+        match self.tx.as_ref().map(|tx| tx.try_send(frame)) {
+            Some(Ok(_)) => true,
+            Some(Err(TrySendError::Full(f))) | Some(Err(TrySendError::Disconnected(f))) => {
+                self.pool.release(f.data);
+                self.stats.dropped += 1;
+                false
+            }
+            None => {
+                self.stats.dropped += 1;
+                false
+            }
+        }
+    }
+
+    /// Current pipeline statistics
+    pub fn stats(&self) -> WorkerStats {
+        WorkerStats {
+            filtered: self.counters.filtered.load(Ordering::Relaxed),
+            written: self.counters.written.load(Ordering::Relaxed),
+            pool: self.pool.stats(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Flush queued frames and stop worker threads
+    pub fn finish(mut self) -> WorkerStats {
+        // Closing the queue terminates workers, followed by the writer
+        drop(self.tx.take());
+        for w in self.workers.drain(..) {
+            let _ = w.join();
+        }
+        if let Some(w) = self.writer.take() {
+            let _ = w.join();
+        }
+        if let Some(o) = self.output.as_mut() {
+            let _ = o.flush();
+        }
+
+        self.stats()
+    }
+}
+
+fn decode_worker(
+    rx: Arc<Mutex<Receiver<ReceivedFrame>>>,
+    out: SyncSender<DecodedFrame>,
+    options: WorkerOptions,
+    registry: Arc<DecoderRegistry>,
+    counters: Arc<Counters>,
+    pool: VecPool,
+) {
+    loop {
+        // Release the lock prior to processing so other workers can proceed
+        let frame = match rx.lock().unwrap().recv() {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+
+        let matches = options.matches(&frame);
+        let d = matches.then(|| DecodedFrame::decode(&frame, &registry, &options.decode));
+        pool.release(frame.data);
+
+        let d = match d {
+            Some(d) => d,
+            None => {
+                counters.filtered.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        if out.send(d).is_err() {
+            return;
+        }
+    }
+}
+
+fn write_worker(rx: Receiver<DecodedFrame>, mut output: Output, counters: Arc<Counters>) {
+    for frame in rx.iter() {
+        output.write(frame).expect("Error writing received frame");
+        counters.written.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let _ = output.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(rssi: i16, data: &[u8]) -> ReceivedFrame {
+        ReceivedFrame {
+            timestamp: SystemTime::now(),
+            rssi,
+            data: data.to_vec(),
+            quality: Default::default(),
+            info: String::new(),
+        }
+    }
+
+    #[test]
+    fn worker_pipeline_filters() {
+        let opts = WorkerOptions {
+            workers: 2,
+            queue_depth: 64,
+            filter_rssi: Some(-80),
+            filter_prefix: Some(parse_hex("0xab").unwrap()),
+            ..Default::default()
+        };
+        let mut w = DecodeWorker::new(opts).unwrap();
+
+        for i in 0..32 {
+            let rssi = if i % 2 == 0 { -60 } else { -90 };
+            assert!(w.submit(frame(rssi, &[0xab, i])));
+        }
+        w.submit(frame(-50, &[0x01]));
+
+        let s = w.finish();
+        assert_eq!(s.received, 33);
+        assert_eq!(s.dropped, 0);
+        assert_eq!(s.written, 16);
+        assert_eq!(s.filtered, 17);
+    }
+
+    #[test]
+    fn worker_buffer_pool() {
+        let opts = WorkerOptions {
+            pool_buffers: Some(1),
+            ..Default::default()
+        };
+        let mut w = DecodeWorker::new(opts).unwrap();
+
+        // Buffers are returned to the pool once decoded
+        let a = w.alloc(&[1, 2]).unwrap();
+        assert_eq!(w.alloc(&[3]), None);
+        assert!(w.submit(ReceivedFrame {
+            data: a,
+            ..frame(-50, &[])
+        }));
+        assert_eq!(w.alloc(&[3]), Some(vec![3]));
+
+        let s = w.finish();
+        assert_eq!(s.dropped, 1);
+        assert_eq!((s.pool.in_use, s.pool.high_watermark), (1, 1));
+        assert_eq!(s.pool.exhausted, 1);
+    }
+
+    #[test]
+    fn hex_parsing() {
+        assert_eq!(parse_hex("0x01ff"), Ok(vec![0x01, 0xff]));
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+}
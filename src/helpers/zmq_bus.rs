@@ -0,0 +1,217 @@
+//! ZeroMQ packet bus, for integration with SDR and analysis toolchains
+//!
+//! Received frames are published on a PUB socket (as JSON [`DecodedFrame`]s, or raw
+//! payloads), and messages arriving on a PULL socket are transmitted as frames.
+//! The bus is bridged to the radio using [`do_bridge`].
+
+#[cfg(all(not(feature = "defmt"), feature = "log"))]
+use log::info;
+
+#[cfg(feature = "defmt")]
+use defmt::info;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+use embedded_hal::delay::DelayNs;
+
+use super::{
+    BridgeOptions, DecodedFrame, DecoderRegistry, PacketSink, PacketSource, ReceivedFrame,
+    do_bridge,
+};
+use crate::{Receive, ReceiveInfo, Transmit, blocking::BlockingError};
+
+/// Configuration for ZeroMQ bus operation
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ZmqOptions {
+    /// Endpoint to bind the PUB socket for received frames
+    #[cfg_attr(feature = "clap", clap(long, default_value = "tcp://*:5556"))]
+    pub zmq_pub: String,
+
+    /// Endpoint to bind the PULL socket for frames to transmit
+    #[cfg_attr(feature = "clap", clap(long, default_value = "tcp://*:5557"))]
+    pub zmq_pull: String,
+
+    /// Publish raw frame payloads rather than JSON
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub raw: bool,
+
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub bridge_options: BridgeOptions,
+}
+
+/// ZeroMQ PUB/PULL packet bus
+pub struct ZmqBus {
+    publisher: zmq::Socket,
+    puller: zmq::Socket,
+    raw: bool,
+    registry: DecoderRegistry,
+}
+
+impl ZmqBus {
+    /// Bind bus sockets to the configured endpoints
+    pub fn bind(options: &ZmqOptions) -> Result<Self, zmq::Error> {
+        Self::bind_with_context(&zmq::Context::new(), options)
+    }
+
+    /// Bind bus sockets using the provided ZeroMQ context
+    pub fn bind_with_context(ctx: &zmq::Context, options: &ZmqOptions) -> Result<Self, zmq::Error> {
+        let publisher = ctx.socket(zmq::PUB)?;
+        publisher.bind(&options.zmq_pub)?;
+
+        let puller = ctx.socket(zmq::PULL)?;
+        puller.bind(&options.zmq_pull)?;
+
+        Ok(Self {
+            publisher,
+            puller,
+            raw: options.raw,
+            registry: DecoderRegistry::default(),
+        })
+    }
+
+    /// Bound PUB and PULL endpoints (resolving wildcard ports)
+    pub fn endpoints(&self) -> Result<(String, String), zmq::Error> {
+        let ep = |s: &zmq::Socket| s.get_last_endpoint().map(|e| e.unwrap_or_default());
+        Ok((ep(&self.publisher)?, ep(&self.puller)?))
+    }
+
+    /// Publish a received frame
+    pub fn publish(&self, frame: &ReceivedFrame) -> Result<(), zmq::Error> {
+        match self.raw {
+            true => self.publisher.send(&frame.data, 0),
+            false => {
+                let d = DecodedFrame::decode(frame, &self.registry, &["auto".to_string()]);
+                let s = serde_json::to_string(&d).expect("Error encoding frame");
+                self.publisher.send(s.as_bytes(), 0)
+            }
+        }
+    }
+
+    /// Fetch the next frame to transmit without blocking
+    pub fn try_recv(&self) -> Result<Option<Vec<u8>>, zmq::Error> {
+        match self.puller.recv_bytes(zmq::DONTWAIT) {
+            Ok(b) => Ok(Some(b)),
+            Err(zmq::Error::EAGAIN) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Split the bus into a source (PULL) and sink (PUB) for bridging
+    pub fn split(self) -> (ZmqSource, ZmqSink) {
+        (
+            ZmqSource {
+                puller: self.puller,
+            },
+            ZmqSink {
+                publisher: self.publisher,
+                raw: self.raw,
+            },
+        )
+    }
+}
+
+/// ZeroMQ PULL socket source, blocking until frames are available
+pub struct ZmqSource {
+    puller: zmq::Socket,
+}
+
+impl PacketSource for ZmqSource {
+    fn next_payload(&mut self) -> Result<Option<Vec<u8>>, std::io::Error> {
+        self.puller
+            .recv_bytes(0)
+            .map(Some)
+            .map_err(std::io::Error::other)
+    }
+}
+
+/// ZeroMQ PUB socket sink
+pub struct ZmqSink {
+    publisher: zmq::Socket,
+    raw: bool,
+}
+
+impl PacketSink for ZmqSink {
+    fn write(&mut self, frame: &DecodedFrame) -> Result<(), std::io::Error> {
+        let r = match self.raw {
+            true => self.publisher.send(&frame.data, 0),
+            false => {
+                let s = serde_json::to_string(frame).expect("Error encoding frame");
+                self.publisher.send(s.as_bytes(), 0)
+            }
+        };
+        r.map_err(std::io::Error::other)
+    }
+}
+
+/// Bridge a radio to a ZeroMQ bus, publishing received frames and transmitting pulled frames
+pub fn do_zmq<T, I, E>(radio: &mut T, options: ZmqOptions) -> Result<(), BlockingError<E>>
+where
+    T: Transmit<Error = E> + Receive<Info = I, Error = E> + DelayNs,
+    I: ReceiveInfo + std::fmt::Debug,
+    E: std::fmt::Debug,
+{
+    let bus = ZmqBus::bind(&options).expect("Error binding ZeroMQ sockets");
+
+    info!(
+        "ZeroMQ bus publishing on {}, pulling from {}",
+        options.zmq_pub, options.zmq_pull
+    );
+
+    let (source, sink) = bus.split();
+    do_bridge(radio, source, sink, options.bridge_options).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn zmq_bus() {
+        let ctx = zmq::Context::new();
+        let options = ZmqOptions {
+            zmq_pub: "tcp://127.0.0.1:*".to_string(),
+            zmq_pull: "tcp://127.0.0.1:*".to_string(),
+            raw: true,
+            bridge_options: BridgeOptions::default(),
+        };
+        let bus = ZmqBus::bind_with_context(&ctx, &options).unwrap();
+        let (pub_ep, pull_ep) = bus.endpoints().unwrap();
+
+        let sub = ctx.socket(zmq::SUB).unwrap();
+        sub.connect(&pub_ep).unwrap();
+        sub.set_subscribe(b"").unwrap();
+
+        let push = ctx.socket(zmq::PUSH).unwrap();
+        push.connect(&pull_ep).unwrap();
+
+        // Frames pushed to the bus are available for transmission
+        push.send(&[1u8, 2, 3][..], 0).unwrap();
+        let data = (0..100)
+            .find_map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                bus.try_recv().unwrap()
+            })
+            .unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert_eq!(bus.try_recv().unwrap(), None);
+
+        // Received frames are published to subscribers (once connected)
+        sub.set_rcvtimeo(10).unwrap();
+        let frame = ReceivedFrame {
+            timestamp: SystemTime::now(),
+            rssi: -60,
+            data: vec![4, 5],
+            quality: Default::default(),
+            info: String::new(),
+        };
+        let msg = (0..100)
+            .find_map(|_| {
+                bus.publish(&frame).unwrap();
+                sub.recv_bytes(0).ok()
+            })
+            .unwrap();
+        assert_eq!(msg, vec![4, 5]);
+    }
+}
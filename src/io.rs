@@ -0,0 +1,481 @@
+//! Byte-stream I/O adapters over a radio link
+//!
+//! [`RadioIo`] provides `embedded_io::{Read, Write}` (with the `embedded-io` feature) and
+//! `std::io::{Read, Write}` (with the `std` feature) over a packet radio, so code written
+//! against serial-port style I/O (XMODEM, command shells) can run transparently over RF.
+//! Writes are split into frames of up to `M` bytes and received frames are buffered for
+//! reading. The adapter does not itself provide ordering or retransmission, and should
+//! be layered over a reliable link where lossless delivery is required.
+//!
+//! [`RadioPort`] (with the `std` feature) provides `std::io::{Read, Write}` with heap
+//! buffers and a runtime MTU, coalescing small writes into frames until the MTU is
+//! reached or the port is flushed, for serial tools and file transfer over radio-hal drivers.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::{
+    Receive, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// Byte-stream adapter over a radio, with frames of up to `M` bytes
+pub struct RadioIo<T, const M: usize> {
+    radio: T,
+    options: BlockingOptions,
+    rx_buff: [u8; M],
+    rx_start: usize,
+    rx_end: usize,
+    receiving: bool,
+}
+
+impl<T, E, const M: usize> RadioIo<T, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    /// Create a new I/O adapter, using the provided options for transmit completion
+    /// and read timeouts
+    pub fn new(radio: T, options: BlockingOptions) -> Self {
+        Self {
+            radio,
+            options,
+            rx_buff: [0u8; M],
+            rx_start: 0,
+            rx_end: 0,
+            receiving: false,
+        }
+    }
+
+    /// Release the inner radio, discarding any buffered data
+    pub fn free(self) -> T {
+        self.radio
+    }
+
+    /// Number of bytes buffered for reading
+    pub fn available(&self) -> usize {
+        self.rx_end - self.rx_start
+    }
+
+    /// Poll for a received frame without blocking, buffering it for reading
+    fn poll(&mut self) -> Result<bool, BlockingError<E>> {
+        if self.available() > 0 {
+            return Ok(true);
+        }
+
+        if !self.receiving {
+            self.radio.start_receive()?;
+            self.receiving = true;
+        }
+
+        if !self.radio.check_receive(true)? {
+            return Ok(false);
+        }
+
+        let (n, _i) = self.radio.get_received(&mut self.rx_buff)?;
+        self.radio.start_receive()?;
+
+        self.rx_start = 0;
+        self.rx_end = n;
+
+        Ok(n > 0)
+    }
+
+    /// Read buffered or received bytes, blocking until data is available or the
+    /// read timeout elapses
+    fn read_bytes(&mut self, buff: &mut [u8]) -> Result<usize, BlockingError<E>> {
+        if buff.is_empty() {
+            return Ok(0);
+        }
+
+        let t = self.options.timeout.as_micros();
+        let mut c = 0;
+        while !self.poll()? {
+            c += self.options.poll_interval.as_micros();
+            if c > t {
+                return Err(BlockingError::Timeout);
+            }
+
+            self.radio
+                .delay_us(self.options.poll_interval.as_micros() as u32);
+        }
+
+        let n = self.available().min(buff.len());
+        buff[..n].copy_from_slice(&self.rx_buff[self.rx_start..][..n]);
+        self.rx_start += n;
+
+        Ok(n)
+    }
+
+    /// Write up to one frame of bytes, returning the number of bytes sent
+    fn write_bytes(&mut self, buff: &[u8]) -> Result<usize, BlockingError<E>> {
+        if buff.is_empty() {
+            return Ok(0);
+        }
+
+        let n = buff.len().min(M);
+        self.radio.do_transmit(&buff[..n], self.options.clone())?;
+
+        // Transmitting leaves receive mode, restart on next read
+        self.receiving = false;
+
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<E: Debug> embedded_io::Error for BlockingError<E> {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            e if e.is_timeout() => embedded_io::ErrorKind::TimedOut,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T, E, const M: usize> embedded_io::ErrorType for RadioIo<T, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    type Error = BlockingError<E>;
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T, E, const M: usize> embedded_io::Read for RadioIo<T, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_bytes(buf)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T, E, const M: usize> embedded_io::ReadReady for RadioIo<T, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        self.poll()
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T, E, const M: usize> embedded_io::Write for RadioIo<T, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_bytes(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        // Writes block until transmission is complete
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: Debug> From<BlockingError<E>> for std::io::Error {
+    fn from(e: BlockingError<E>) -> Self {
+        match e {
+            BlockingError::Timeout => std::io::Error::from(std::io::ErrorKind::TimedOut),
+            BlockingError::Inner(e) => std::io::Error::other(format!("{e:?}")),
+            e @ BlockingError::Unsupported(_) => {
+                std::io::Error::new(std::io::ErrorKind::Unsupported, format!("{e:?}"))
+            }
+            e @ BlockingError::Oversize { .. } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{e:?}"))
+            }
+            e @ BlockingError::Exhausted { .. } => std::io::Error::new(
+                match e.is_timeout() {
+                    true => std::io::ErrorKind::TimedOut,
+                    false => std::io::ErrorKind::Other,
+                },
+                format!("{e:?}"),
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, E, const M: usize> std::io::Read for RadioIo<T, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(self.read_bytes(buf)?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, E, const M: usize> std::io::Write for RadioIo<T, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(self.write_bytes(buf)?)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Buffered `std::io` port over a radio, with frames of up to `mtu` bytes
+///
+/// Writes are buffered until a full frame is available or [`std::io::Write::flush`] is
+/// called, buffered data is discarded (not transmitted) when the port is dropped.
+#[cfg(feature = "std")]
+pub struct RadioPort<T> {
+    radio: T,
+    options: BlockingOptions,
+    mtu: usize,
+    rx: std::collections::VecDeque<u8>,
+    rx_buff: std::vec::Vec<u8>,
+    tx: std::vec::Vec<u8>,
+    receiving: bool,
+}
+
+#[cfg(feature = "std")]
+impl<T, E> RadioPort<T>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    /// Create a new port with the provided frame MTU, using the provided options for
+    /// transmit completion and read timeouts
+    pub fn new(radio: T, mtu: usize, options: BlockingOptions) -> Self {
+        let mtu = mtu.max(1);
+        Self {
+            radio,
+            options,
+            mtu,
+            rx: std::collections::VecDeque::new(),
+            rx_buff: std::vec![0u8; mtu],
+            tx: std::vec::Vec::with_capacity(mtu),
+            receiving: false,
+        }
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio, discarding any buffered data
+    pub fn free(self) -> T {
+        self.radio
+    }
+
+    /// Update the transmit completion and read timeout options
+    pub fn set_options(&mut self, options: BlockingOptions) {
+        self.options = options;
+    }
+
+    /// Number of bytes buffered for reading
+    pub fn available(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Number of bytes buffered awaiting transmission
+    pub fn pending(&self) -> usize {
+        self.tx.len()
+    }
+
+    /// Poll for a received frame without blocking, buffering it for reading
+    fn poll(&mut self) -> Result<bool, BlockingError<E>> {
+        if !self.receiving {
+            self.radio.start_receive()?;
+            self.receiving = true;
+        }
+
+        if !self.radio.check_receive(true)? {
+            return Ok(false);
+        }
+
+        let (n, _i) = self.radio.get_received(&mut self.rx_buff)?;
+        self.radio.start_receive()?;
+
+        self.rx.extend(&self.rx_buff[..n]);
+
+        Ok(n > 0)
+    }
+
+    /// Transmit a frame from the write buffer
+    fn send_frame(&mut self) -> Result<(), BlockingError<E>> {
+        let n = self.tx.len().min(self.mtu);
+        self.radio
+            .do_transmit(&self.tx[..n], self.options.clone())?;
+        self.tx.drain(..n);
+
+        // Transmitting leaves receive mode, restart on next read
+        self.receiving = false;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, E> std::io::Read for RadioPort<T>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let t = self.options.timeout.as_micros();
+        let mut c = 0;
+        while self.rx.is_empty() && !self.poll()? {
+            c += self.options.poll_interval.as_micros();
+            if c > t {
+                return Err(BlockingError::<E>::Timeout.into());
+            }
+
+            self.radio
+                .delay_us(self.options.poll_interval.as_micros() as u32);
+        }
+
+        let n = self.rx.len().min(buf.len());
+        for (b, d) in buf.iter_mut().zip(self.rx.drain(..n)) {
+            *b = d;
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, E> std::io::Write for RadioPort<T>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tx.extend_from_slice(buf);
+        while self.tx.len() >= self.mtu {
+            self.send_frame()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        while !self.tx.is_empty() {
+            self.send_frame()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+    use core::cell::RefCell;
+
+    type Air = RefCell<Option<([u8; 16], usize)>>;
+
+    struct Loopback<'a> {
+        tx: &'a Air,
+        rx: &'a Air,
+    }
+
+    impl Transmit for Loopback<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let mut b = [0u8; 16];
+            b[..data.len()].copy_from_slice(data);
+            *self.tx.borrow_mut() = Some((b, data.len()));
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for Loopback<'_> {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.rx.borrow().is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (b, n) = self.rx.borrow_mut().take().unwrap();
+            buff[..n].copy_from_slice(&b[..n]);
+            Ok((n, BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for Loopback<'_> {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn stream_chunking() {
+        let (a, b) = (Air::default(), Air::default());
+        let mut tx = RadioIo::<_, 4>::new(Loopback { tx: &a, rx: &b }, BlockingOptions::default());
+        let mut rx = RadioIo::<_, 4>::new(Loopback { tx: &b, rx: &a }, BlockingOptions::default());
+
+        // Writes are limited to a single frame
+        assert_eq!(tx.write_bytes(b"hello"), Ok(4));
+
+        // Reads drain buffered frames across calls
+        let mut buff = [0u8; 3];
+        assert_eq!(rx.read_bytes(&mut buff), Ok(3));
+        assert_eq!(&buff, b"hel");
+        assert_eq!(rx.available(), 1);
+        assert_eq!(rx.read_bytes(&mut buff), Ok(1));
+        assert_eq!(buff[0], b'l');
+
+        assert_eq!(rx.read_bytes(&mut buff), Err(BlockingError::Timeout));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn port_buffering() {
+        use std::io::{Read, Write};
+
+        let (a, b) = (Air::default(), Air::default());
+        let mut tx = RadioPort::new(Loopback { tx: &a, rx: &b }, 8, BlockingOptions::default());
+        let mut rx = RadioPort::new(Loopback { tx: &b, rx: &a }, 8, BlockingOptions::default());
+
+        // Small writes are coalesced until flushed
+        tx.write_all(b"hi").unwrap();
+        assert_eq!((tx.pending(), a.borrow().is_some()), (2, false));
+        tx.flush().unwrap();
+
+        let mut buff = [0u8; 16];
+        assert_eq!(rx.read(&mut buff).unwrap(), 2);
+        assert_eq!(&buff[..2], b"hi");
+
+        // Full frames are sent immediately, with the remainder buffered
+        tx.write_all(b"0123456789").unwrap();
+        assert_eq!(tx.pending(), 2);
+        assert_eq!(rx.read(&mut buff[..3]).unwrap(), 3);
+        assert_eq!(rx.available(), 5);
+
+        let e = rx.read(&mut buff[..8]).and_then(|_| rx.read(&mut buff));
+        assert_eq!(e.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,236 @@
+//! Interrupt-driven receive
+//!
+//! Helpers poll `check_receive` every `poll_interval`, which for SPI radios means a bus
+//! transaction per poll. [`IrqReceive`] wraps a radio with its receive interrupt (DIO / IRQ)
+//! line, only checking the radio once the line is asserted, so `do_receive`, `do_echo` and
+//! `do_ping_pong` wait on the interrupt line rather than the radio status registers. For
+//! async executors see `asynch::AsyncReceive::do_receive_irq`.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::InputPin;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// IrqOptions select interrupt or polled receive mode
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IrqOptions {
+    /// Wait on the receive interrupt line rather than polling radio status
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub irq: bool,
+
+    /// Receive interrupt line is active low
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub irq_active_low: bool,
+}
+
+/// IrqReceive wraps a radio with its receive interrupt line
+///
+/// In IRQ mode `check_receive` returns `false` without accessing the radio until the
+/// interrupt line is asserted. Errors reading the line fall back to checking the radio.
+pub struct IrqReceive<T, P> {
+    radio: T,
+    pin: P,
+    options: IrqOptions,
+}
+
+impl<T, P: InputPin> IrqReceive<T, P> {
+    /// Wrap a radio with the provided interrupt line
+    pub fn new(radio: T, pin: P, options: IrqOptions) -> Self {
+        Self {
+            radio,
+            pin,
+            options,
+        }
+    }
+
+    /// Check whether the interrupt line is asserted (always `true` in polled mode)
+    pub fn irq_asserted(&mut self) -> bool {
+        if !self.options.irq {
+            return true;
+        }
+
+        let level = match self.options.irq_active_low {
+            true => self.pin.is_low(),
+            false => self.pin.is_high(),
+        };
+
+        match level {
+            Ok(v) => v,
+            Err(_e) => {
+                #[cfg(any(feature = "log", feature = "defmt"))]
+                debug!("Error reading receive interrupt line, polling radio");
+                true
+            }
+        }
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio and interrupt line
+    pub fn free(self) -> (T, P) {
+        (self.radio, self.pin)
+    }
+}
+
+impl<T: Transmit, P> Transmit for IrqReceive<T, P> {
+    type Error = T::Error;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.radio.start_transmit(data)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit()
+    }
+}
+
+impl<T: Receive, P: InputPin> Receive for IrqReceive<T, P> {
+    type Info = T::Info;
+    type Error = T::Error;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive()
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        if !self.irq_asserted() {
+            return Ok(false);
+        }
+        self.radio.check_receive(restart)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff)
+    }
+}
+
+impl<T: Power, P> Power for IrqReceive<T, P> {
+    type Error = T::Error;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power)
+    }
+}
+
+impl<T: Rssi, P> Rssi for IrqReceive<T, P> {
+    type Error = T::Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.radio.poll_rssi()
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.radio.poll_rssi_n(out)
+    }
+}
+
+impl<T: Channel, P> Channel for IrqReceive<T, P> {
+    type Channel = T::Channel;
+    type Error = T::Error;
+
+    fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+        self.radio.set_channel(channel)
+    }
+}
+
+impl<T: DelayNs, P> DelayNs for IrqReceive<T, P> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::ErrorType;
+
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Interrupt line with a shared level
+    struct Line<'a>(&'a Cell<bool>);
+
+    impl ErrorType for Line<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for Line<'_> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.0.get())
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.0.get())
+        }
+    }
+
+    /// Radio counting status checks
+    #[derive(Default)]
+    struct CountingRadio {
+        checks: u32,
+    }
+
+    impl Receive for CountingRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            self.checks += 1;
+            Ok(true)
+        }
+
+        fn get_received(&mut self, _buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            Ok((0, BasicInfo::default()))
+        }
+    }
+
+    #[test]
+    fn irq_gated_receive() {
+        let level = Cell::new(false);
+        let options = IrqOptions {
+            irq: true,
+            irq_active_low: false,
+        };
+        let mut r = IrqReceive::new(CountingRadio::default(), Line(&level), options);
+
+        // Radio is not accessed until the line is asserted
+        assert_eq!(r.check_receive(true), Ok(false));
+        assert_eq!(r.check_receive(true), Ok(false));
+        assert_eq!(r.inner().checks, 0);
+
+        level.set(true);
+        assert_eq!(r.check_receive(true), Ok(true));
+        assert_eq!(r.inner().checks, 1);
+
+        // Polled mode always checks the radio
+        let mut r = IrqReceive::new(
+            CountingRadio::default(),
+            Line(&level),
+            IrqOptions::default(),
+        );
+        level.set(false);
+        assert_eq!(r.check_receive(true), Ok(true));
+    }
+}
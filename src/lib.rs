@@ -12,16 +12,57 @@
 use core::convert::TryFrom;
 use core::fmt::Debug;
 
+pub mod afa;
+pub mod afc;
+pub mod aggregate;
+pub mod arq;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod blacklist;
 pub mod blocking;
+pub mod cca;
+pub mod clock;
+#[cfg(feature = "compression")]
+pub mod compress;
 pub mod config;
-
-#[cfg(feature = "helpers")]
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod doppler;
+pub mod downlink;
+pub mod duty;
+pub mod fair;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fhss;
+pub mod fixed;
+pub mod frame;
+pub mod frontend;
+#[cfg(any(feature = "embedded-io", feature = "std"))]
+pub mod io;
+pub mod irq;
+pub mod negotiate;
+pub mod pool;
+pub mod prng;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod reattach;
+pub mod reflector;
+pub mod rpc;
+pub mod tpc;
+pub mod txqueue;
+
+#[cfg(feature = "helpers-core")]
 pub mod helpers;
 #[cfg(feature = "mock")]
 pub mod mock;
+#[cfg(feature = "embedded-nal")]
+pub mod nal;
 #[cfg(feature = "nonblocking")]
 pub mod nonblocking;
 
+#[cfg(test)]
+mod test_support;
+
 /// Radio trait combines Base, Configure, Send and Receive for a generic radio object
 pub trait Radio: Transmit + Receive + State {}
 
@@ -75,12 +116,64 @@ pub trait Receive {
     fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error>;
 }
 
+/// Transmit for mutable references, allowing wrappers to borrow a radio
+impl<T: Transmit + ?Sized> Transmit for &mut T {
+    type Error = T::Error;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        T::start_transmit(self, data)
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        T::check_transmit(self)
+    }
+}
+
+/// Receive for mutable references, allowing wrappers to borrow a radio
+impl<T: Receive + ?Sized> Receive for &mut T {
+    type Error = T::Error;
+    type Info = T::Info;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        T::start_receive(self)
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        T::check_receive(self, restart)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        T::get_received(self, buff)
+    }
+}
+
 /// ReceiveInfo trait for receive information objects
 ///
 /// This sup[ports the constraint of generic `Receive::Info`, allowing generic middleware
 /// to access the rssi of received packets
 pub trait ReceiveInfo: Debug + Default {
     fn rssi(&self) -> i16;
+
+    /// Frequency error of the received packet in Hz (received - tuned), where supported
+    fn frequency_error_hz(&self) -> Option<i32> {
+        None
+    }
+
+    /// Capture timestamp of the received packet in microseconds on the driver clock,
+    /// where supported
+    fn timestamp_us(&self) -> Option<u64> {
+        None
+    }
+
+    /// Link Quality Indicator (LQI) of the received packet, where supported
+    fn lqi(&self) -> Option<u16> {
+        None
+    }
+
+    /// Signal to noise ratio (SNR) of the received packet in dB, where supported
+    fn snr_db(&self) -> Option<f32> {
+        None
+    }
 }
 
 /// Default / Standard packet information structure for radio devices that provide only rssi
@@ -96,8 +189,8 @@ pub struct BasicInfo {
 impl Default for BasicInfo {
     fn default() -> Self {
         Self {
-            rssi: core::i16::MIN,
-            lqi: core::u16::MIN,
+            rssi: i16::MIN,
+            lqi: u16::MIN,
         }
     }
 }
@@ -113,6 +206,10 @@ impl ReceiveInfo for BasicInfo {
     fn rssi(&self) -> i16 {
         self.rssi
     }
+
+    fn lqi(&self) -> Option<u16> {
+        Some(self.lqi)
+    }
 }
 
 /// Default / Standard radio channel object for radio devices with integer channels
@@ -142,6 +239,44 @@ pub trait Channel {
     fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error>;
 }
 
+/// Channel for mutable references, allowing wrappers to borrow a radio
+impl<T: Channel + ?Sized> Channel for &mut T {
+    type Channel = T::Channel;
+    type Error = T::Error;
+
+    fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+        T::set_channel(self, channel)
+    }
+}
+
+/// AntennaSelect trait for radios with switched antennas
+///
+/// This allows boards with RF switches (for antenna diversity or multiple ports) to be
+/// controlled generically. Antennas are indexed from zero.
+pub trait AntennaSelect {
+    /// Radio error type
+    type Error: Debug;
+
+    /// Number of selectable antennas
+    fn antenna_count(&self) -> u8;
+
+    /// Select the antenna for future transmit and receive operations
+    fn set_antenna(&mut self, index: u8) -> Result<(), Self::Error>;
+}
+
+/// AntennaSelect for mutable references, allowing wrappers to borrow a radio
+impl<T: AntennaSelect + ?Sized> AntennaSelect for &mut T {
+    type Error = T::Error;
+
+    fn antenna_count(&self) -> u8 {
+        T::antenna_count(self)
+    }
+
+    fn set_antenna(&mut self, index: u8) -> Result<(), Self::Error> {
+        T::set_antenna(self, index)
+    }
+}
+
 /// Power trait for configuring radio power
 pub trait Power {
     /// Radio error type
@@ -151,6 +286,43 @@ pub trait Power {
     fn set_power(&mut self, power: i8) -> Result<(), Self::Error>;
 }
 
+/// Power for mutable references, allowing wrappers to borrow a radio
+impl<T: Power + ?Sized> Power for &mut T {
+    type Error = T::Error;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        T::set_power(self, power)
+    }
+}
+
+/// TransmitPower trait for transmitting frames at a per-frame power
+///
+/// The default implementation sets the radio power prior to starting each transmission,
+/// radios able to load the power with the frame may override this to avoid the extra
+/// configuration. See [`tpc`] for per-destination power control.
+pub trait TransmitPower: Transmit + Power<Error = <Self as Transmit>::Error> {
+    /// Start sending a packet at the provided power in dBm
+    fn start_transmit_with_power(
+        &mut self,
+        data: &[u8],
+        power: i8,
+    ) -> Result<(), <Self as Transmit>::Error> {
+        self.set_power(power)?;
+        self.start_transmit(data)
+    }
+}
+
+/// TransmitPower for mutable references, allowing wrappers to borrow a radio
+impl<T: TransmitPower + ?Sized> TransmitPower for &mut T {
+    fn start_transmit_with_power(
+        &mut self,
+        data: &[u8],
+        power: i8,
+    ) -> Result<(), <Self as Transmit>::Error> {
+        T::start_transmit_with_power(self, data, power)
+    }
+}
+
 /// Rssi trait allows polling for RSSI on the current channel
 ///
 /// Note that the radio should be in receive mode prior to polling for this.
@@ -162,6 +334,103 @@ pub trait Rssi {
     /// Note that the radio MUST be in RX mode (or capable of measuring RSSI) when this is called
     /// or an error should be returned
     fn poll_rssi(&mut self) -> Result<i16, Self::Error>;
+
+    /// Fetch a batch of RSSI samples, returning the number of samples written to `out`
+    ///
+    /// The default implementation calls [`Rssi::poll_rssi`] for each sample, drivers able
+    /// to capture multiple samples (eg. via DMA) should override this.
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        for o in out.iter_mut() {
+            *o = self.poll_rssi()?;
+        }
+        Ok(out.len())
+    }
+}
+
+/// Rssi for mutable references, allowing wrappers to borrow a radio
+impl<T: Rssi + ?Sized> Rssi for &mut T {
+    type Error = T::Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        T::poll_rssi(self)
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        T::poll_rssi_n(self, out)
+    }
+}
+
+/// Continuous transmission signals for regulatory testing, see [`TestMode`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestSignal {
+    /// Unmodulated continuous carrier (CW)
+    Carrier,
+    /// Continuous modulated transmission (eg. repeated preamble or pseudo-random data)
+    Modulated,
+}
+
+/// TestMode trait for radios supporting continuous transmission test modes
+///
+/// EMC and regulatory testing requires unmodulated carrier and continuous modulated
+/// transmission, which drivers may expose using device specific test registers.
+/// The radio should continue transmitting until [`TestMode::stop_test_mode`] is called.
+pub trait TestMode {
+    /// Radio error
+    type Error: Debug;
+
+    /// Start continuous transmission of the provided signal on the current channel
+    fn start_test_mode(&mut self, signal: TestSignal) -> Result<(), Self::Error>;
+
+    /// Stop continuous transmission, returning the radio to idle
+    fn stop_test_mode(&mut self) -> Result<(), Self::Error>;
+}
+
+/// TestMode for mutable references, allowing wrappers to borrow a radio
+impl<T: TestMode + ?Sized> TestMode for &mut T {
+    type Error = T::Error;
+
+    fn start_test_mode(&mut self, signal: TestSignal) -> Result<(), Self::Error> {
+        T::start_test_mode(self, signal)
+    }
+
+    fn stop_test_mode(&mut self) -> Result<(), Self::Error> {
+        T::stop_test_mode(self)
+    }
+}
+
+/// Receive filtering modes, see [`ReceiveFilter`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReceiveMode {
+    /// Deliver all detected frames, including those failing CRC or address checks
+    Promiscuous,
+    /// Deliver frames passing CRC checks, regardless of address
+    CrcFiltered,
+    /// Deliver frames passing CRC checks and addressed to this node (or broadcast)
+    AddressFiltered,
+}
+
+/// ReceiveFilter trait for radios with configurable receive filtering
+///
+/// Drivers differ in whether frames failing hardware CRC or address matching are
+/// delivered, this allows applications (such as sniffers and BER tests) to select
+/// the behaviour explicitly. Modes unsupported by the radio should return an error.
+pub trait ReceiveFilter {
+    /// Radio error
+    type Error: Debug;
+
+    /// Set the receive filtering mode, applied to subsequent receptions
+    fn set_receive_mode(&mut self, mode: ReceiveMode) -> Result<(), Self::Error>;
+}
+
+/// ReceiveFilter for mutable references, allowing wrappers to borrow a radio
+impl<T: ReceiveFilter + ?Sized> ReceiveFilter for &mut T {
+    type Error = T::Error;
+
+    fn set_receive_mode(&mut self, mode: ReceiveMode) -> Result<(), Self::Error> {
+        T::set_receive_mode(self, mode)
+    }
 }
 
 /// State trait for configuring and reading radio states
@@ -198,6 +467,57 @@ pub trait Busy {
     fn is_busy(&mut self) -> Result<bool, Self::Error>;
 }
 
+/// Operating state reported by [`StateQuery`], common across drivers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RadioStatus {
+    /// Idle (standby), neither transmitting nor receiving
+    Idle,
+    /// Receiving or listening for packets
+    Receive,
+    /// Transmitting a packet
+    Transmit,
+    /// Sleeping (low power)
+    Sleep,
+    /// Error state (eg. a FIFO overflow or PLL unlock) requiring recovery
+    Error,
+}
+
+impl core::fmt::Display for RadioStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let s = match self {
+            RadioStatus::Idle => "idle",
+            RadioStatus::Receive => "rx",
+            RadioStatus::Transmit => "tx",
+            RadioStatus::Sleep => "sleep",
+            RadioStatus::Error => "error",
+        };
+        f.write_str(s)
+    }
+}
+
+/// StateQuery trait for introspecting the current radio state
+///
+/// Unlike [`State`], states are reported using the common [`RadioStatus`] so tooling
+/// can display driver state (and detect drivers stuck in an unexpected state) without
+/// knowledge of driver-specific state types.
+pub trait StateQuery {
+    /// Radio error type
+    type Error: Debug;
+
+    /// Fetch the current radio state
+    fn query_state(&mut self) -> Result<RadioStatus, Self::Error>;
+}
+
+/// StateQuery for mutable references, allowing wrappers to borrow a radio
+impl<T: StateQuery + ?Sized> StateQuery for &mut T {
+    type Error = T::Error;
+
+    fn query_state(&mut self) -> Result<RadioStatus, Self::Error> {
+        T::query_state(self)
+    }
+}
+
 /// Interrupts trait allows for reading interrupt state from the device,
 /// as well as configuring interrupt pins.
 ///
@@ -212,6 +532,49 @@ pub trait Interrupts {
     /// Fetch any pending interrupts from the device
     /// If the clear option is set, this will also clear any returned flags
     fn get_interrupts(&mut self, clear: bool) -> Result<Self::Irq, Self::Error>;
+
+    /// Decode receive progress from interrupt state, where supported by the device
+    ///
+    /// Returns the furthest receive stage reached, useful for debugging links where
+    /// preambles are detected but packets are never received.
+    fn rx_event(&self, _irq: &Self::Irq) -> Option<RxEvent> {
+        None
+    }
+}
+
+/// Receive progress events, ordered by receive stage
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RxEvent {
+    /// Preamble detected
+    PreambleDetected,
+    /// Sync word detected, payload reception in progress
+    SyncDetected,
+}
+
+/// Raw signal sample, as captured by [`RawSamples`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Sample {
+    /// RSSI measurement in dBm
+    Rssi(i16),
+    /// In-phase and quadrature sample
+    Iq(i16, i16),
+}
+
+/// RawSamples trait for radios that can expose raw signal samples (RSSI over time or IQ
+/// snapshots) around receive events, for offline demodulation and diagnostics
+pub trait RawSamples {
+    /// Radio error
+    type Error: Debug;
+
+    /// Sample rate in Hz
+    fn sample_rate_hz(&self) -> u32;
+
+    /// Fetch samples captured around the most recent receive event
+    ///
+    /// This copies samples into the provided buffer and returns the number of samples captured
+    fn get_samples(&mut self, buff: &mut [Sample]) -> Result<usize, Self::Error>;
 }
 
 /// Register contains the address and value of a register.
@@ -250,6 +613,42 @@ pub trait Registers<Word> {
     }
 }
 
+/// Raw register or driver state value, reported by [`RegisterDump`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DumpEntry {
+    /// Register address (or driver defined state index)
+    pub address: u16,
+    /// Register or field name, where known
+    pub name: Option<&'static str>,
+    /// Register or field value
+    pub value: u32,
+    /// Value width in bytes
+    pub width: u8,
+}
+
+/// RegisterDump trait for capturing raw register or driver state for diagnostics
+///
+/// Unlike [`Registers`] this provides untyped access to the full register map (or any
+/// driver state worth reporting) so driver state can be captured when a link misbehaves,
+/// without per-chip tooling.
+pub trait RegisterDump {
+    /// Radio error
+    type Error: Debug;
+
+    /// Report each register or state value, in address order
+    fn dump_registers(&mut self, f: &mut dyn FnMut(DumpEntry)) -> Result<(), Self::Error>;
+}
+
+/// RegisterDump for mutable references, allowing wrappers to borrow a radio
+impl<T: RegisterDump + ?Sized> RegisterDump for &mut T {
+    type Error = T::Error;
+
+    fn dump_registers(&mut self, f: &mut dyn FnMut(DumpEntry)) -> Result<(), Self::Error> {
+        T::dump_registers(self, f)
+    }
+}
+
 #[cfg(feature = "std")]
 use std::str::FromStr;
 
@@ -261,7 +660,7 @@ pub fn duration_from_str(s: &str) -> Result<core::time::Duration, humantime::Dur
 
 #[cfg(test)]
 mod tests {
-    use crate::{Register, Registers};
+    use crate::{Register, Registers, Rssi};
 
     use core::convert::{Infallible, TryInto};
 
@@ -381,4 +780,23 @@ mod tests {
             TestRegister2 { value: [2, 3] }
         );
     }
+
+    struct RampRssi(i16);
+
+    impl Rssi for RampRssi {
+        type Error = ();
+
+        fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+            self.0 += 1;
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn batch_rssi_default() {
+        let mut r = RampRssi(-100);
+        let mut out = [0i16; 3];
+        assert_eq!(r.poll_rssi_n(&mut out), Ok(3));
+        assert_eq!(out, [-99, -98, -97]);
+    }
 }
@@ -1,14 +1,23 @@
 //! Mock radio driver for application testing
 //!
 //! This provides a generic and specific mock implementation of the radio traits
-//! to support network and application level testing.
+//! to support network and application level testing, along with [`ImpairedRadio`],
+//! a simulation-oriented mock applying seeded loss, corruption, latency and RSSI
+//! models to frames exchanged with a scripted peer, and [`SharedMedium`], connecting
+//! virtual radio endpoints through an in-memory channel for end-to-end tests.
 //!
 //! ## <https://github.com/rust-iot/radio-hal>
 //! ## Copyright 2020-2022 Ryan Kurte
 
+use std::boxed::Box;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::Debug;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::vec::Vec;
 
+use core::time::Duration;
+
 use log::debug;
 
 use embedded_hal::delay::DelayNs;
@@ -16,8 +25,10 @@ use embedded_hal::delay::DelayNs;
 use embedded_hal_mock::common::Generic;
 
 use crate::{
-    BasicInfo, Busy, Channel, Interrupts, Power, RadioState, Receive, ReceiveInfo, Rssi, State,
-    Transmit,
+    BasicInfo, Busy, Channel, Interrupts, Power, RadioState, Receive, ReceiveFilter, ReceiveInfo,
+    ReceiveMode, Rssi, State, TestMode, TestSignal, Transmit,
+    clock::{Clock, ScaledClock, VirtualClock},
+    prng::XorShift32,
 };
 
 /// Generic mock radio
@@ -33,6 +44,7 @@ pub struct Radio<
     E: Debug + Clone + PartialEq,
 > {
     inner: Generic<Transaction<St, Reg, Ch, Inf, Irq, E>>,
+    clock: Option<Rc<VirtualClock>>,
 }
 
 impl<St, Reg, Ch, Inf, Irq, E> Radio<St, Reg, Ch, Inf, Irq, E>
@@ -47,7 +59,13 @@ where
     pub fn new(expectations: &[Transaction<St, Reg, Ch, Inf, Irq, E>]) -> Self {
         let inner = Generic::new(expectations);
 
-        Self { inner }
+        Self { inner, clock: None }
+    }
+
+    /// Attach a virtual clock, advanced by expected delays
+    pub fn with_clock(mut self, clock: Rc<VirtualClock>) -> Self {
+        self.clock = Some(clock);
+        self
     }
 
     pub fn update_expectations(&mut self, expectations: &[Transaction<St, Reg, Ch, Inf, Irq, E>]) {
@@ -158,6 +176,30 @@ impl<St, Reg, Ch, Inf, Irq, E> Transaction<St, Reg, Ch, Inf, Irq, E> {
         }
     }
 
+    /// Start a continuous transmission test mode
+    pub fn start_test_mode(signal: TestSignal, err: Option<E>) -> Self {
+        Self {
+            request: Request::StartTestMode(signal),
+            response: err.into(),
+        }
+    }
+
+    /// Stop a continuous transmission test mode
+    pub fn stop_test_mode(err: Option<E>) -> Self {
+        Self {
+            request: Request::StopTestMode,
+            response: err.into(),
+        }
+    }
+
+    /// Set the receive filtering mode
+    pub fn set_receive_mode(mode: ReceiveMode, err: Option<E>) -> Self {
+        Self {
+            request: Request::SetReceiveMode(mode),
+            response: err.into(),
+        }
+    }
+
     /// Start radio transmission
     pub fn start_transmit(data: Vec<u8>, err: Option<E>) -> Self {
         Self {
@@ -250,6 +292,11 @@ enum Request<St, Reg, Ch> {
     StartTransmit(Vec<u8>),
     CheckTransmit,
 
+    StartTestMode(TestSignal),
+    StopTestMode,
+
+    SetReceiveMode(ReceiveMode),
+
     StartReceive,
     CheckReceive(bool),
     GetReceived,
@@ -291,6 +338,10 @@ where
         let n = self.next().expect("no expectation for delay_ns call");
 
         assert_eq!(&n.request, &Request::DelayNs(ns));
+
+        if let Some(c) = &self.clock {
+            (&**c).delay_ns(ns);
+        }
     }
 }
 
@@ -537,6 +588,78 @@ where
     }
 }
 
+impl<St, Reg, Ch, Inf, Irq, E> TestMode for Radio<St, Reg, Ch, Inf, Irq, E>
+where
+    St: PartialEq + Debug + Clone,
+    Reg: PartialEq + Debug + Clone,
+    Ch: PartialEq + Debug + Clone,
+    Inf: PartialEq + Debug + Clone,
+    Irq: PartialEq + Debug + Clone,
+    E: PartialEq + Debug + Clone,
+{
+    type Error = E;
+
+    fn start_test_mode(&mut self, signal: TestSignal) -> Result<(), Self::Error> {
+        debug!("Start test mode {:?}", signal);
+
+        let n = self
+            .next()
+            .expect("no expectation for TestMode::start_test_mode call");
+
+        assert_eq!(&n.request, &Request::StartTestMode(signal));
+
+        match &n.response {
+            Response::Ok => Ok(()),
+            Response::Err(e) => Err(e.clone()),
+            _ => unreachable!(),
+        }
+    }
+
+    fn stop_test_mode(&mut self) -> Result<(), Self::Error> {
+        debug!("Stop test mode");
+
+        let n = self
+            .next()
+            .expect("no expectation for TestMode::stop_test_mode call");
+
+        assert_eq!(&n.request, &Request::StopTestMode);
+
+        match &n.response {
+            Response::Ok => Ok(()),
+            Response::Err(e) => Err(e.clone()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<St, Reg, Ch, Inf, Irq, E> ReceiveFilter for Radio<St, Reg, Ch, Inf, Irq, E>
+where
+    St: PartialEq + Debug + Clone,
+    Reg: PartialEq + Debug + Clone,
+    Ch: PartialEq + Debug + Clone,
+    Inf: PartialEq + Debug + Clone,
+    Irq: PartialEq + Debug + Clone,
+    E: PartialEq + Debug + Clone,
+{
+    type Error = E;
+
+    fn set_receive_mode(&mut self, mode: ReceiveMode) -> Result<(), Self::Error> {
+        debug!("Set receive mode {:?}", mode);
+
+        let n = self
+            .next()
+            .expect("no expectation for ReceiveFilter::set_receive_mode call");
+
+        assert_eq!(&n.request, &Request::SetReceiveMode(mode));
+
+        match &n.response {
+            Response::Ok => Ok(()),
+            Response::Err(e) => Err(e.clone()),
+            _ => unreachable!(),
+        }
+    }
+}
+
 impl<St, Reg, Ch, Inf, Irq, E> Receive for Radio<St, Reg, Ch, Inf, Irq, E>
 where
     St: PartialEq + Debug + Clone,
@@ -608,6 +731,532 @@ where
     }
 }
 
+/// Channel impairments applied by [`ImpairedRadio`] to each frame, in each direction
+#[derive(Clone, Debug, PartialEq)]
+pub struct Impairments {
+    /// Probability of a frame being lost
+    pub loss: f32,
+    /// Probability of a delivered frame having a (single) bit flipped
+    pub corruption: f32,
+    /// Mean RSSI of delivered frames, in dBm
+    pub rssi: i16,
+    /// Maximum deviation of the RSSI from the mean, uniformly distributed
+    pub rssi_jitter: i16,
+    /// Propagation and processing latency before a frame is delivered
+    pub latency: Duration,
+}
+
+impl Default for Impairments {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            corruption: 0.0,
+            rssi: -60,
+            rssi_jitter: 0,
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+/// Impairment statistics, counting frames in both directions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImpairmentStats {
+    /// Frames offered to the channel
+    pub frames: u32,
+    /// Frames lost
+    pub lost: u32,
+    /// Frames delivered with a corrupted bit
+    pub corrupted: u32,
+}
+
+/// Peer response to a transmitted frame
+pub type Responder = Box<dyn FnMut(&[u8]) -> Option<Vec<u8>>>;
+
+/// Simulation mock radio, exchanging frames with a scripted peer over an impaired channel
+///
+/// Transmitted frames are passed (subject to impairments) to the responder, with responses
+/// (again subject to impairments) queued for reception once the channel latency has
+/// elapsed. Time advances on a [`VirtualClock`] through delays, and impairments are drawn
+/// from a seeded generator, so runs are reproducible. Impairments may also be configured
+/// per channel (see [`ImpairedRadio::with_channel`]) for testing channel selection.
+pub struct ImpairedRadio {
+    impairments: Impairments,
+    rng: XorShift32,
+    clock: Rc<VirtualClock>,
+    responder: Responder,
+    rx: VecDeque<(u64, Vec<u8>, i16)>,
+    stats: ImpairmentStats,
+    power: i8,
+    mode: ReceiveMode,
+    channel: u16,
+    channels: BTreeMap<u16, Impairments>,
+}
+
+impl ImpairedRadio {
+    /// Create a radio with the provided peer responder, impairments and RNG seed
+    pub fn new<F>(responder: F, impairments: Impairments, seed: u32) -> Self
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>> + 'static,
+    {
+        Self {
+            impairments,
+            rng: XorShift32::new(seed),
+            clock: Rc::new(VirtualClock::new()),
+            responder: Box::new(responder),
+            rx: VecDeque::new(),
+            stats: ImpairmentStats::default(),
+            power: 0,
+            mode: ReceiveMode::Promiscuous,
+            channel: 0,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    /// Create a radio with a peer echoing each received frame
+    pub fn echo(impairments: Impairments, seed: u32) -> Self {
+        Self::new(|d| Some(d.to_vec()), impairments, seed)
+    }
+
+    /// Attach a virtual clock, advanced by delays
+    pub fn with_clock(mut self, clock: Rc<VirtualClock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Update channel impairments
+    pub fn set_impairments(&mut self, impairments: Impairments) {
+        self.impairments = impairments;
+    }
+
+    /// Configure impairments for a channel, applied while the radio is set to the channel
+    pub fn with_channel(mut self, channel: u16, impairments: Impairments) -> Self {
+        self.channels.insert(channel, impairments);
+        self
+    }
+
+    /// Currently selected channel
+    pub fn channel(&self) -> u16 {
+        self.channel
+    }
+
+    /// Impairment statistics
+    pub fn stats(&self) -> &ImpairmentStats {
+        &self.stats
+    }
+
+    /// Configured transmit power in dBm
+    pub fn power(&self) -> i8 {
+        self.power
+    }
+
+    /// Deliver a frame from the peer without a preceding transmission
+    pub fn inject(&mut self, data: &[u8]) {
+        let mut d = data.to_vec();
+        if self.impair(&mut d) {
+            let at = self.clock.now_us() + self.impairments.latency.as_micros() as u64;
+            let rssi = self.sample_rssi();
+            self.rx.push_back((at, d, rssi));
+        }
+    }
+
+    /// Apply loss and corruption to a frame, returning whether it is delivered
+    fn impair(&mut self, data: &mut [u8]) -> bool {
+        self.stats.frames += 1;
+
+        if self.rng.next_f32() < self.impairments.loss {
+            self.stats.lost += 1;
+            return false;
+        }
+
+        if !data.is_empty() && self.rng.next_f32() < self.impairments.corruption {
+            let bit = self.rng.below(data.len() as u32 * 8);
+            data[bit as usize / 8] ^= 1 << (bit % 8);
+            self.stats.corrupted += 1;
+
+            // Corrupted frames fail CRC checks where filtering is enabled
+            return self.mode == ReceiveMode::Promiscuous;
+        }
+
+        true
+    }
+
+    fn sample_rssi(&mut self) -> i16 {
+        let j = self.impairments.rssi_jitter.unsigned_abs() as u32;
+        self.impairments.rssi + (self.rng.below(2 * j + 1) as i32 - j as i32) as i16
+    }
+}
+
+impl Transmit for ImpairedRadio {
+    type Error = MockError;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let mut d = data.to_vec();
+        if !self.impair(&mut d) {
+            return Ok(());
+        }
+
+        let response = match (self.responder)(&d) {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+
+        // Responses incur latency in each direction
+        let mut r = response;
+        if self.impair(&mut r) {
+            let at = self.clock.now_us() + 2 * self.impairments.latency.as_micros() as u64;
+            let rssi = self.sample_rssi();
+            self.rx.push_back((at, r, rssi));
+        }
+
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+impl Receive for ImpairedRadio {
+    type Error = MockError;
+    type Info = BasicInfo;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+        let now = self.clock.now_us();
+        Ok(self.rx.front().is_some_and(|(at, _, _)| *at <= now))
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let (_, d, rssi) = self.rx.pop_front().ok_or(MockError::Timeout)?;
+        let n = d.len().min(buff.len());
+        buff[..n].copy_from_slice(&d[..n]);
+
+        Ok((n, BasicInfo::new(rssi, 0)))
+    }
+}
+
+impl Power for ImpairedRadio {
+    type Error = MockError;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.power = power;
+        Ok(())
+    }
+}
+
+impl Rssi for ImpairedRadio {
+    type Error = MockError;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        Ok(self.sample_rssi())
+    }
+}
+
+impl Channel for ImpairedRadio {
+    type Channel = u16;
+    type Error = MockError;
+
+    fn set_channel(&mut self, channel: &u16) -> Result<(), Self::Error> {
+        self.channel = *channel;
+        if let Some(i) = self.channels.get(channel) {
+            self.impairments = i.clone();
+        }
+        Ok(())
+    }
+}
+
+impl ReceiveFilter for ImpairedRadio {
+    type Error = MockError;
+
+    fn set_receive_mode(&mut self, mode: ReceiveMode) -> Result<(), Self::Error> {
+        self.mode = mode;
+        Ok(())
+    }
+}
+
+impl DelayNs for ImpairedRadio {
+    fn delay_ns(&mut self, ns: u32) {
+        (&*self.clock).delay_ns(ns);
+    }
+}
+
+/// Shared medium configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediumOptions {
+    /// Propagation delay between endpoints
+    pub delay: Duration,
+    /// Bitrate used to compute frame airtime, zero for instantaneous transmission
+    pub bitrate: u32,
+    /// RSSI of received frames (and of the channel while occupied), in dBm
+    pub rssi: i16,
+    /// RSSI of the idle channel, in dBm
+    pub noise_floor: i16,
+}
+
+impl Default for MediumOptions {
+    fn default() -> Self {
+        Self {
+            delay: Duration::ZERO,
+            bitrate: 0,
+            rssi: -60,
+            noise_floor: -120,
+        }
+    }
+}
+
+/// Shared medium statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediumStats {
+    /// Frames transmitted by all endpoints
+    pub transmitted: u32,
+    /// Frames delivered to receiving endpoints
+    pub delivered: u32,
+    /// Frames lost at receiving endpoints due to overlapping transmissions
+    pub collisions: u32,
+}
+
+#[derive(Debug)]
+struct Transmission {
+    id: u64,
+    from: usize,
+    start_us: u64,
+    end_us: u64,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct MediumState {
+    air: VecDeque<Transmission>,
+    next_id: u64,
+    cursors: Vec<u64>,
+    stats: MediumStats,
+}
+
+/// In-memory radio channel connecting any number of [`MediumRadio`] endpoints
+///
+/// Each transmission reaches every other endpoint after the propagation delay, with
+/// frames overlapping another transmission (including one from the receiving endpoint)
+/// lost to collision. Endpoints are `Send`, so peers may run on separate threads, and
+/// time is read from a [`ScaledClock`] so simulations may be accelerated.
+#[derive(Clone, Debug)]
+pub struct SharedMedium {
+    state: Arc<Mutex<MediumState>>,
+    options: MediumOptions,
+    clock: ScaledClock,
+}
+
+impl SharedMedium {
+    /// Create a new medium
+    pub fn new(options: MediumOptions) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MediumState::default())),
+            options,
+            clock: ScaledClock::default(),
+        }
+    }
+
+    /// Use the provided clock for airtime, propagation and endpoint delays
+    pub fn with_clock(mut self, clock: ScaledClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Create a pair of connected endpoints
+    pub fn pair(options: MediumOptions) -> (MediumRadio, MediumRadio) {
+        let m = Self::new(options);
+        (m.endpoint(), m.endpoint())
+    }
+
+    /// Attach a new endpoint, receiving transmissions started after attachment
+    pub fn endpoint(&self) -> MediumRadio {
+        let mut s = self.state.lock().unwrap();
+        let id = s.cursors.len();
+        let next = s.next_id;
+        s.cursors.push(next);
+
+        MediumRadio {
+            medium: self.clone(),
+            id,
+            rx: VecDeque::new(),
+            tx_end_us: 0,
+            power: 0,
+        }
+    }
+
+    /// Medium statistics
+    pub fn stats(&self) -> MediumStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    fn airtime_us(&self, len: usize) -> u64 {
+        match self.options.bitrate {
+            0 => 0,
+            b => (len as u64 * 8 * 1_000_000).div_ceil(b as u64),
+        }
+    }
+}
+
+/// Virtual radio endpoint on a [`SharedMedium`]
+#[derive(Debug)]
+pub struct MediumRadio {
+    medium: SharedMedium,
+    id: usize,
+    rx: VecDeque<Vec<u8>>,
+    tx_end_us: u64,
+    power: i8,
+}
+
+impl MediumRadio {
+    /// Endpoint index on the medium
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Medium this endpoint is attached to
+    pub fn medium(&self) -> &SharedMedium {
+        &self.medium
+    }
+
+    /// Configured transmit power in dBm
+    pub fn power(&self) -> i8 {
+        self.power
+    }
+
+    /// Move completed transmissions from other endpoints into the receive queue
+    fn poll(&mut self) {
+        let now = self.medium.clock.now_us();
+        let delay = self.medium.options.delay.as_micros() as u64;
+        let mut s = self.medium.state.lock().unwrap();
+        let s = &mut *s;
+
+        let cursor = s.cursors[self.id];
+        for t in s.air.iter().filter(|t| t.id >= cursor) {
+            if t.end_us + delay > now {
+                break;
+            }
+            s.cursors[self.id] = t.id + 1;
+            if t.from == self.id {
+                continue;
+            }
+
+            // Zero airtime frames occupy the channel for a microsecond
+            let overlaps = |o: &Transmission| {
+                o.id != t.id
+                    && o.start_us < t.end_us.max(t.start_us + 1)
+                    && t.start_us < o.end_us.max(o.start_us + 1)
+            };
+            match s.air.iter().any(overlaps) {
+                true => {
+                    debug!("Endpoint {} lost frame {} to collision", self.id, t.id);
+                    s.stats.collisions += 1;
+                }
+                false => {
+                    self.rx.push_back(t.data.clone());
+                    s.stats.delivered += 1;
+                }
+            }
+        }
+
+        // Drop transmissions processed by all endpoints, once they can no longer overlap
+        // later transmissions
+        let min = s.cursors.iter().copied().min().unwrap_or(0);
+        while let Some(t) = s.air.front() {
+            if t.id >= min || t.end_us + delay + 1_000_000 > now {
+                break;
+            }
+            s.air.pop_front();
+        }
+    }
+}
+
+impl Transmit for MediumRadio {
+    type Error = MockError;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let start_us = self.medium.clock.now_us();
+        let end_us = start_us + self.medium.airtime_us(data.len());
+        self.tx_end_us = end_us;
+
+        let mut s = self.medium.state.lock().unwrap();
+        let id = s.next_id;
+        s.next_id += 1;
+        s.stats.transmitted += 1;
+        s.air.push_back(Transmission {
+            id,
+            from: self.id,
+            start_us,
+            end_us,
+            data: data.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.medium.clock.now_us() >= self.tx_end_us)
+    }
+}
+
+impl Receive for MediumRadio {
+    type Error = MockError;
+    type Info = BasicInfo;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+        self.poll();
+        Ok(!self.rx.is_empty())
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let d = self.rx.pop_front().ok_or(MockError::Timeout)?;
+        let n = d.len().min(buff.len());
+        buff[..n].copy_from_slice(&d[..n]);
+
+        Ok((n, BasicInfo::new(self.medium.options.rssi, 0)))
+    }
+}
+
+impl Rssi for MediumRadio {
+    type Error = MockError;
+
+    /// Channel RSSI, raised while another endpoint's transmission is arriving
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        let now = self.medium.clock.now_us();
+        let delay = self.medium.options.delay.as_micros() as u64;
+        let s = self.medium.state.lock().unwrap();
+
+        let busy = s
+            .air
+            .iter()
+            .any(|t| t.from != self.id && t.start_us + delay <= now && now < t.end_us + delay);
+        Ok(match busy {
+            true => self.medium.options.rssi,
+            false => self.medium.options.noise_floor,
+        })
+    }
+}
+
+impl Power for MediumRadio {
+    type Error = MockError;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.power = power;
+        Ok(())
+    }
+}
+
+impl DelayNs for MediumRadio {
+    fn delay_ns(&mut self, ns: u32) {
+        self.medium.clock.delay_ns(ns);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::vec;
@@ -661,6 +1310,18 @@ mod test {
         radio.done();
     }
 
+    #[test]
+    fn test_radio_mock_set_receive_mode() {
+        let mut radio = MockRadio::new(&[Transaction::set_receive_mode(
+            ReceiveMode::Promiscuous,
+            None,
+        )]);
+
+        radio.set_receive_mode(ReceiveMode::Promiscuous).unwrap();
+
+        radio.done();
+    }
+
     #[test]
     fn test_radio_mock_start_transmit() {
         let mut radio =
@@ -728,4 +1389,142 @@ mod test {
 
         radio.done();
     }
+
+    #[test]
+    fn test_impaired_radio_arq() {
+        use crate::arq::{ARQ_ACK, ARQ_DATA, ARQ_SYNC, ArqOptions, ReliableLink};
+        use crate::blocking::BlockingOptions;
+
+        // Peer acknowledging ARQ sync and data frames over a lossy channel
+        let impairments = Impairments {
+            loss: 0.3,
+            latency: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let radio = ImpairedRadio::new(
+            |d| match d {
+                [ARQ_SYNC | ARQ_DATA, seq, ..] => Some(vec![ARQ_ACK, *seq]),
+                _ => None,
+            },
+            impairments,
+            1234,
+        );
+        let options = ArqOptions {
+            reliable: true,
+            arq_retries: 8,
+            ..Default::default()
+        };
+        let mut link = ReliableLink::<_, 64>::new(radio, options, BlockingOptions::default());
+
+        for i in 0..20u8 {
+            assert!(link.send(&[i; 8]).unwrap());
+        }
+        assert_eq!(link.stats().acked, 20);
+        assert!(link.stats().retransmissions > 0);
+
+        let stats = link.inner().stats().clone();
+        assert!(stats.lost > 0 && stats.lost < stats.frames);
+    }
+
+    #[test]
+    fn test_impaired_radio_delivery() {
+        let impairments = Impairments {
+            corruption: 1.0,
+            rssi: -80,
+            rssi_jitter: 3,
+            latency: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut radio = ImpairedRadio::echo(impairments, 7);
+        let mut buff = [0u8; 16];
+
+        // Frames are delivered after the round trip latency, with both directions corrupted
+        radio.start_transmit(&[0u8; 4]).unwrap();
+        assert!(!radio.check_receive(false).unwrap());
+        radio.delay_ms(2);
+        assert!(radio.check_receive(false).unwrap());
+
+        let (n, i) = radio.get_received(&mut buff).unwrap();
+        assert_eq!(n, 4);
+        assert!((-83..=-77).contains(&i.rssi()));
+        assert_eq!(radio.stats().corrupted, 2);
+        assert!(buff[..4].iter().map(|b| b.count_ones()).sum::<u32>() <= 2);
+
+        // Corrupted frames are dropped where CRC filtering is enabled
+        radio.set_receive_mode(ReceiveMode::CrcFiltered).unwrap();
+        radio.start_transmit(&[0u8; 4]).unwrap();
+        radio.delay_ms(2);
+        assert!(!radio.check_receive(false).unwrap());
+
+        // Runs are reproducible for a given seed
+        let mut a = ImpairedRadio::echo(Impairments::default(), 99);
+        let mut b = ImpairedRadio::echo(Impairments::default(), 99);
+        assert_eq!(a.poll_rssi(), b.poll_rssi());
+    }
+
+    #[test]
+    fn test_shared_medium_echo() {
+        use crate::blocking::{BlockingOptions, BlockingReceive, BlockingTransmit};
+
+        let (mut a, mut b) = SharedMedium::pair(MediumOptions {
+            delay: Duration::from_millis(1),
+            ..Default::default()
+        });
+        let opts = BlockingOptions {
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        // Peer echoing frames from a separate thread
+        let o = opts.clone();
+        let peer = std::thread::spawn(move || {
+            let mut buff = [0u8; 16];
+            for _ in 0..5 {
+                let (n, _) = b.do_receive(&mut buff, o.clone()).unwrap();
+                b.do_transmit(&buff[..n], o.clone()).unwrap();
+            }
+        });
+
+        let mut buff = [0u8; 16];
+        for i in 0..5u8 {
+            a.do_transmit(&[i, i + 1], opts.clone()).unwrap();
+            let (n, info) = a.do_receive(&mut buff, opts.clone()).unwrap();
+            assert_eq!(&buff[..n], &[i, i + 1]);
+            assert_eq!(info.rssi(), -60);
+        }
+        peer.join().unwrap();
+
+        let stats = a.medium().stats();
+        assert_eq!((stats.transmitted, stats.delivered), (10, 10));
+    }
+
+    #[test]
+    fn test_shared_medium_collisions() {
+        let medium = SharedMedium::new(MediumOptions {
+            bitrate: 1_000,
+            ..Default::default()
+        });
+        let (mut a, mut b, mut c) = (medium.endpoint(), medium.endpoint(), medium.endpoint());
+        let mut buff = [0u8; 16];
+
+        // Overlapping transmissions are lost, while occupying the channel
+        a.start_transmit(&[1; 4]).unwrap();
+        b.start_transmit(&[2; 4]).unwrap();
+        assert!(!a.check_transmit().unwrap());
+        assert_eq!(c.poll_rssi(), Ok(-60));
+
+        c.delay_ms(40);
+        assert!(a.check_transmit().unwrap());
+        assert!(!c.check_receive(false).unwrap());
+        assert_eq!(c.poll_rssi(), Ok(-120));
+        assert_eq!(medium.stats().collisions, 2);
+
+        // Frames on a clear channel are delivered to every other endpoint
+        a.start_transmit(&[3; 4]).unwrap();
+        c.delay_ms(40);
+        assert!(b.check_receive(false).unwrap());
+        assert!(c.check_receive(false).unwrap());
+        assert_eq!(c.get_received(&mut buff).unwrap().0, 4);
+        assert_eq!(&buff[..4], &[3; 4]);
+    }
 }
@@ -0,0 +1,464 @@
+//! `embedded-nal` UDP stack over the addressed radio MAC
+//!
+//! Implements [`UdpClientStack`] and [`UdpFullStack`] over any [`Transmit`] + [`Receive`]
+//! radio using [`frame`](crate::frame) addressing, so existing embedded networking code
+//! (CoAP, MQTT-SN clients etc.) can run over a radio link unchanged.
+//!
+//! IP addresses are mapped to node addresses using the low 16 bits of the address, such
+//! that `10.0.1.2` (or any IPv6 address ending in `::102`) maps to node `0x0102`, and the
+//! configured prefix is used when reporting remote IPv4 addresses.
+//! Datagrams are limited to a single frame.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+use core::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use embedded_hal::delay::DelayNs;
+use embedded_nal::{UdpClientStack, UdpFullStack};
+
+use crate::{
+    Receive, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+    frame::{Address, BROADCAST, Header},
+};
+
+/// Frame flag identifying UDP datagrams
+pub const UDP_FLAG: u8 = 0x80;
+
+/// UDP header length (source and destination ports)
+const UDP_HEADER_LEN: usize = 4;
+
+/// First ephemeral port assigned to unbound sockets
+const EPHEMERAL_PORT: u16 = 49152;
+
+/// Map an IP address to a node address
+pub fn ip_to_address(ip: IpAddr) -> Address {
+    let o = match ip {
+        IpAddr::V4(v4) => v4.octets(),
+        IpAddr::V6(v6) => {
+            let o = v6.octets();
+            [0, 0, o[14], o[15]]
+        }
+    };
+    u16::from_be_bytes([o[2], o[3]])
+}
+
+/// Map a node address to an IPv4 address with the provided prefix
+pub fn address_to_ip(prefix: [u8; 2], address: Address) -> IpAddr {
+    let a = address.to_be_bytes();
+    IpAddr::V4(Ipv4Addr::new(prefix[0], prefix[1], a[0], a[1]))
+}
+
+/// NalError describes failures in the UDP stack
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NalError<E> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(BlockingError<E>),
+    /// No sockets available
+    #[cfg_attr(feature = "thiserror", error("No sockets available"))]
+    NoSockets,
+    /// Socket is not connected
+    #[cfg_attr(feature = "thiserror", error("Socket not connected"))]
+    NotConnected,
+    /// Local port already in use
+    #[cfg_attr(feature = "thiserror", error("Port in use"))]
+    PortInUse,
+    /// Datagram too large for a single frame
+    #[cfg_attr(feature = "thiserror", error("Datagram too large"))]
+    TooLarge,
+}
+
+impl<E> From<BlockingError<E>> for NalError<E> {
+    fn from(e: BlockingError<E>) -> Self {
+        NalError::Radio(e)
+    }
+}
+
+impl<E> From<E> for NalError<E> {
+    fn from(e: E) -> Self {
+        NalError::Radio(BlockingError::Inner(e))
+    }
+}
+
+/// UDP socket handle
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RadioUdpSocket(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SocketState {
+    local_port: Option<u16>,
+    remote: Option<SocketAddr>,
+}
+
+/// Datagram received for a socket other than the one being polled
+#[derive(Clone, Debug)]
+struct Queued<const M: usize> {
+    src: Address,
+    src_port: u16,
+    dst_port: u16,
+    len: usize,
+    data: [u8; M],
+}
+
+/// UDP stack over an addressed radio, supporting `S` sockets and frames of up to `M` bytes
+pub struct RadioUdpStack<T, const S: usize, const M: usize> {
+    radio: T,
+    address: Address,
+    prefix: [u8; 2],
+    options: BlockingOptions,
+    sockets: [Option<SocketState>; S],
+    queued: Option<Queued<M>>,
+    seq: u8,
+    next_port: u16,
+    receiving: bool,
+}
+
+impl<T, E, const S: usize, const M: usize> RadioUdpStack<T, S, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    /// Create a new UDP stack for the node address, using the provided IPv4 prefix for remote addresses
+    pub fn new(radio: T, address: Address, prefix: [u8; 2], options: BlockingOptions) -> Self {
+        Self {
+            radio,
+            address,
+            prefix,
+            options,
+            sockets: [None; S],
+            queued: None,
+            seq: 0,
+            next_port: EPHEMERAL_PORT,
+            receiving: false,
+        }
+    }
+
+    /// Local IP address of this node
+    pub fn local_ip(&self) -> IpAddr {
+        address_to_ip(self.prefix, self.address)
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+
+    fn state(&mut self, socket: &RadioUdpSocket) -> &mut SocketState {
+        self.sockets[socket.0].as_mut().unwrap()
+    }
+
+    fn local_port(&mut self, socket: &RadioUdpSocket) -> u16 {
+        if let Some(p) = self.state(socket).local_port {
+            return p;
+        }
+
+        // Assign the next free ephemeral port
+        let mut p = self.next_port;
+        while self
+            .sockets
+            .iter()
+            .flatten()
+            .any(|s| s.local_port == Some(p))
+        {
+            p = p.checked_add(1).unwrap_or(EPHEMERAL_PORT);
+        }
+        self.next_port = p.checked_add(1).unwrap_or(EPHEMERAL_PORT);
+        self.state(socket).local_port = Some(p);
+        p
+    }
+
+    fn transmit(
+        &mut self,
+        socket: &RadioUdpSocket,
+        remote: SocketAddr,
+        data: &[u8],
+    ) -> Result<(), NalError<E>> {
+        let n = Header::LEN + UDP_HEADER_LEN + data.len();
+        if n > M {
+            return Err(NalError::TooLarge);
+        }
+
+        let src_port = self.local_port(socket);
+        let mut h = Header::new(self.address, ip_to_address(remote.ip()), self.seq);
+        h.flags = UDP_FLAG;
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut buff = [0u8; M];
+        buff[..Header::LEN].copy_from_slice(&h.to_bytes());
+        buff[Header::LEN..][..2].copy_from_slice(&src_port.to_be_bytes());
+        buff[Header::LEN + 2..][..2].copy_from_slice(&remote.port().to_be_bytes());
+        buff[Header::LEN + UDP_HEADER_LEN..n].copy_from_slice(data);
+
+        self.radio.do_transmit(&buff[..n], self.options.clone())?;
+        self.radio.start_receive()?;
+        self.receiving = true;
+
+        Ok(())
+    }
+
+    /// Check whether a datagram is accepted by the provided socket
+    fn accepts(&self, socket: &RadioUdpSocket, q: &Queued<M>) -> bool {
+        let s = match &self.sockets[socket.0] {
+            Some(s) => s,
+            None => return false,
+        };
+        let remote_ok = match s.remote {
+            Some(r) => ip_to_address(r.ip()) == q.src && r.port() == q.src_port,
+            None => true,
+        };
+        s.local_port == Some(q.dst_port) && remote_ok
+    }
+
+    fn deliver(&mut self, q: Queued<M>, buff: &mut [u8]) -> (usize, SocketAddr) {
+        let n = q.len.min(buff.len());
+        buff[..n].copy_from_slice(&q.data[..n]);
+        let remote = SocketAddr::new(address_to_ip(self.prefix, q.src), q.src_port);
+        (n, remote)
+    }
+}
+
+impl<T, E, const S: usize, const M: usize> UdpClientStack for RadioUdpStack<T, S, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    type UdpSocket = RadioUdpSocket;
+    type Error = NalError<E>;
+
+    fn socket(&mut self) -> Result<Self::UdpSocket, Self::Error> {
+        let i = self
+            .sockets
+            .iter()
+            .position(|s| s.is_none())
+            .ok_or(NalError::NoSockets)?;
+
+        self.sockets[i] = Some(SocketState {
+            local_port: None,
+            remote: None,
+        });
+
+        Ok(RadioUdpSocket(i))
+    }
+
+    fn connect(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        remote: SocketAddr,
+    ) -> Result<(), Self::Error> {
+        self.local_port(socket);
+        self.state(socket).remote = Some(remote);
+        Ok(())
+    }
+
+    fn send(&mut self, socket: &mut Self::UdpSocket, buffer: &[u8]) -> nb::Result<(), Self::Error> {
+        let remote = self.state(socket).remote.ok_or(NalError::NotConnected)?;
+        self.transmit(socket, remote, buffer)?;
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<(usize, SocketAddr), Self::Error> {
+        // Deliver queued datagrams for this socket
+        if let Some(q) = self.queued.take() {
+            if self.accepts(socket, &q) {
+                return Ok(self.deliver(q, buffer));
+            }
+            self.queued = Some(q);
+        }
+
+        if !self.receiving {
+            self.radio.start_receive().map_err(NalError::from)?;
+            self.receiving = true;
+        }
+
+        if !self.radio.check_receive(true).map_err(NalError::from)? {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let mut b = [0u8; M];
+        let (n, _i) = self.radio.get_received(&mut b).map_err(NalError::from)?;
+        self.radio.start_receive().map_err(NalError::from)?;
+
+        // Filter for UDP frames addressed to this node
+        let h = match Header::from_bytes(&b[..n]) {
+            Ok(h) if h.flags & UDP_FLAG != 0 && h.is_for(self.address) => h,
+            _ => return Err(nb::Error::WouldBlock),
+        };
+        if n < Header::LEN + UDP_HEADER_LEN {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let p = &b[Header::LEN..];
+        let mut q = Queued {
+            src: h.src,
+            src_port: u16::from_be_bytes([p[0], p[1]]),
+            dst_port: u16::from_be_bytes([p[2], p[3]]),
+            len: n - Header::LEN - UDP_HEADER_LEN,
+            data: [0u8; M],
+        };
+        q.data[..q.len].copy_from_slice(&p[UDP_HEADER_LEN..][..q.len]);
+
+        if self.accepts(socket, &q) {
+            return Ok(self.deliver(q, buffer));
+        }
+
+        // Hold datagrams for other sockets (dropping any previously queued)
+        let other = (0..S).any(|i| self.accepts(&RadioUdpSocket(i), &q));
+        if other {
+            self.queued = Some(q);
+        }
+
+        Err(nb::Error::WouldBlock)
+    }
+
+    fn close(&mut self, socket: Self::UdpSocket) -> Result<(), Self::Error> {
+        self.sockets[socket.0] = None;
+        Ok(())
+    }
+}
+
+impl<T, E, const S: usize, const M: usize> UdpFullStack for RadioUdpStack<T, S, M>
+where
+    T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+    E: Debug,
+{
+    fn bind(&mut self, socket: &mut Self::UdpSocket, local_port: u16) -> Result<(), Self::Error> {
+        let in_use = self.sockets.iter().enumerate().any(|(i, s)| {
+            i != socket.0 && matches!(s, Some(s) if s.local_port == Some(local_port))
+        });
+        if in_use {
+            return Err(NalError::PortInUse);
+        }
+
+        self.state(socket).local_port = Some(local_port);
+        Ok(())
+    }
+
+    fn send_to(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        remote: SocketAddr,
+        buffer: &[u8],
+    ) -> nb::Result<(), Self::Error> {
+        self.transmit(socket, remote, buffer)?;
+        Ok(())
+    }
+}
+
+/// Broadcast socket address for the provided port
+pub fn broadcast(prefix: [u8; 2], port: u16) -> SocketAddr {
+    SocketAddr::new(address_to_ip(prefix, BROADCAST), port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+    use core::cell::RefCell;
+
+    type Air = RefCell<Option<([u8; 64], usize)>>;
+
+    struct Loopback<'a> {
+        tx: &'a Air,
+        rx: &'a Air,
+    }
+
+    impl Transmit for Loopback<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let mut b = [0u8; 64];
+            b[..data.len()].copy_from_slice(data);
+            *self.tx.borrow_mut() = Some((b, data.len()));
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for Loopback<'_> {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.rx.borrow().is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (b, n) = self.rx.borrow_mut().take().unwrap();
+            buff[..n].copy_from_slice(&b[..n]);
+            Ok((n, BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for Loopback<'_> {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn address_mapping() {
+        let ip = address_to_ip([10, 0], 0x0102);
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(10, 0, 1, 2)));
+        assert_eq!(ip_to_address(ip), 0x0102);
+        assert_eq!(ip_to_address("fe80::102".parse().unwrap()), 0x0102);
+    }
+
+    #[test]
+    fn udp_exchange() {
+        let (a, b) = (Air::default(), Air::default());
+        let opts = BlockingOptions::default();
+        let mut client = RadioUdpStack::<_, 2, 64>::new(
+            Loopback { tx: &a, rx: &b },
+            0x0001,
+            [10, 0],
+            opts.clone(),
+        );
+        let mut server =
+            RadioUdpStack::<_, 2, 64>::new(Loopback { tx: &b, rx: &a }, 0x0002, [10, 0], opts);
+
+        let mut ss = server.socket().unwrap();
+        server.bind(&mut ss, 5683).unwrap();
+
+        let mut cs = client.socket().unwrap();
+        client
+            .connect(&mut cs, SocketAddr::new(server.local_ip(), 5683))
+            .unwrap();
+        client.send(&mut cs, b"ping").unwrap();
+
+        let mut buff = [0u8; 16];
+        let (n, from) = server.receive(&mut ss, &mut buff).unwrap();
+        assert_eq!(&buff[..n], b"ping");
+        assert_eq!(from.ip(), client.local_ip());
+        assert_eq!(from.port(), EPHEMERAL_PORT);
+
+        server.send_to(&mut ss, from, b"pong").unwrap();
+        let (n, from) = client.receive(&mut cs, &mut buff).unwrap();
+        assert_eq!(&buff[..n], b"pong");
+        assert_eq!(from.port(), 5683);
+
+        assert!(matches!(
+            client.receive(&mut cs, &mut buff),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        let mut other = client.socket().unwrap();
+        assert_eq!(
+            client.bind(&mut other, EPHEMERAL_PORT),
+            Err(NalError::PortInUse)
+        );
+    }
+}
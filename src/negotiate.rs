@@ -0,0 +1,239 @@
+//! Capability descriptors for negotiating link features between peers
+//!
+//! Nodes exchange [`CapabilityDescriptor`]s advertising their maximum payload length, the
+//! [`Features`] they support and the features they have been asked to use. Each side
+//! then independently selects the same common configuration (see
+//! [`CapabilityDescriptor::select`]): features requested by either node and supported by
+//! both, with the smaller of the two maximum payloads. One node can therefore be run
+//! with the desired features while its peer only enables negotiation.
+//!
+//! Descriptors are encoded as `[magic "RCAP" (4), kind, version, max payload (BE u16),
+//! supported (BE u16), requested (BE u16)]`. Later versions only extend the descriptor,
+//! so decoders accept descriptors of any version and unknown feature bits are never
+//! selected as they are unsupported locally.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::ops::{BitAnd, BitOr, Not};
+
+/// Magic identifying capability frames
+pub const CAPS_MAGIC: [u8; 4] = *b"RCAP";
+
+/// Current capability descriptor version
+pub const CAPS_VERSION: u8 = 1;
+
+/// Set of link features
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Features(pub u16);
+
+impl Features {
+    /// No features
+    pub const NONE: Self = Self(0);
+    /// LZ4 payload compression
+    pub const COMPRESS: Self = Self(1 << 0);
+    /// AES-128-CCM payload encryption (with a shared key)
+    pub const CRYPTO: Self = Self(1 << 1);
+    /// Aggregation of payloads into frames
+    pub const AGGREGATE: Self = Self(1 << 2);
+    /// Reliable transmission with acknowledgements (ARQ)
+    pub const ARQ: Self = Self(1 << 3);
+
+    /// Check whether all the provided features are set
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Set or clear the provided features
+    pub fn set(&mut self, other: Self, enabled: bool) {
+        *self = match enabled {
+            true => *self | other,
+            false => *self & !other,
+        };
+    }
+
+    /// Check whether no features are set
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Features {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for Features {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+impl core::fmt::Display for Features {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let names = [
+            (Self::COMPRESS, "compress"),
+            (Self::CRYPTO, "crypto"),
+            (Self::AGGREGATE, "aggregate"),
+            (Self::ARQ, "arq"),
+        ];
+
+        let mut first = true;
+        for (_, name) in names.iter().filter(|(v, _)| self.contains(*v)) {
+            if !first {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", name)?;
+            first = false;
+        }
+        if first {
+            write!(f, "none")?;
+        }
+        Ok(())
+    }
+}
+
+/// Capability frame kind
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CapsKind {
+    /// Descriptor offered to a peer, which responds with an [`CapsKind::Answer`]
+    Offer = 1,
+    /// Descriptor sent in response to an offer
+    Answer = 2,
+}
+
+/// Capabilities advertised by a node
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CapabilityDescriptor {
+    /// Descriptor version
+    pub version: u8,
+    /// Maximum payload length in bytes
+    pub max_payload: u16,
+    /// Features supported by the node
+    pub supported: Features,
+    /// Features the node has been configured to use
+    pub requested: Features,
+}
+
+/// Configuration selected from a pair of descriptors
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Negotiated {
+    /// Maximum payload length supported by both nodes
+    pub max_payload: u16,
+    /// Features to be used
+    pub features: Features,
+    /// Features requested by either node but unsupported by one of them
+    pub dropped: Features,
+}
+
+impl CapabilityDescriptor {
+    /// Encoded descriptor length in bytes
+    pub const LEN: usize = 12;
+
+    /// Create a descriptor with the current version
+    pub fn new(max_payload: u16, supported: Features, requested: Features) -> Self {
+        Self {
+            version: CAPS_VERSION,
+            max_payload,
+            supported,
+            requested,
+        }
+    }
+
+    /// Encode the descriptor as a frame of the provided kind
+    pub fn encode(&self, kind: CapsKind) -> [u8; Self::LEN] {
+        let mut b = [0u8; Self::LEN];
+        b[..4].copy_from_slice(&CAPS_MAGIC);
+        b[4] = kind as u8;
+        b[5] = self.version;
+        b[6..8].copy_from_slice(&self.max_payload.to_be_bytes());
+        b[8..10].copy_from_slice(&self.supported.0.to_be_bytes());
+        b[10..12].copy_from_slice(&self.requested.0.to_be_bytes());
+        b
+    }
+
+    /// Decode a capability frame, `None` where the data is not a capability frame
+    pub fn decode(data: &[u8]) -> Option<(CapsKind, Self)> {
+        if data.len() < Self::LEN || data[..4] != CAPS_MAGIC || data[5] == 0 {
+            return None;
+        }
+
+        let kind = match data[4] {
+            1 => CapsKind::Offer,
+            2 => CapsKind::Answer,
+            _ => return None,
+        };
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+
+        Some((
+            kind,
+            Self {
+                version: data[5],
+                max_payload: u16_at(6),
+                supported: Features(u16_at(8)),
+                requested: Features(u16_at(10)),
+            },
+        ))
+    }
+
+    /// Select the common configuration with a peer, identical on both nodes
+    pub fn select(&self, peer: &Self) -> Negotiated {
+        let requested = self.requested | peer.requested;
+        let features = requested & self.supported & peer.supported;
+
+        Negotiated {
+            max_payload: self.max_payload.min(peer.max_payload),
+            features,
+            dropped: requested & !features,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capability_selection() {
+        let a = CapabilityDescriptor::new(
+            255,
+            Features::COMPRESS | Features::AGGREGATE | Features::ARQ,
+            Features::COMPRESS | Features::ARQ,
+        );
+        let b = CapabilityDescriptor::new(
+            64,
+            Features::COMPRESS | Features::CRYPTO | Features::AGGREGATE,
+            Features::CRYPTO,
+        );
+
+        let b_frame = b.encode(CapsKind::Answer);
+        assert_eq!(
+            CapabilityDescriptor::decode(&b_frame),
+            Some((CapsKind::Answer, b))
+        );
+        assert_eq!(CapabilityDescriptor::decode(&b_frame[..8]), None);
+
+        // Both nodes select the same configuration
+        let n = a.select(&b);
+        assert_eq!(n, b.select(&a));
+        assert_eq!(n.max_payload, 64);
+        assert_eq!(n.features, Features::COMPRESS);
+        assert_eq!(n.dropped, Features::CRYPTO | Features::ARQ);
+    }
+}
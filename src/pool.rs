@@ -0,0 +1,237 @@
+//! Receive buffer pools
+//!
+//! Bounds (and reports) memory use of high-rate captures by drawing receive buffers from a
+//! fixed-size pool, with frames dropped rather than allocating once the pool is exhausted.
+//! [`StaticPool`] provides `N` fixed `M` byte buffers for `no_std` environments, while
+//! [`VecPool`] (with `std`) provides a shareable pool of reusable `Vec` buffers for use
+//! across threads, as used by the receive decode pipeline.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+/// Buffer pool statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PoolStats {
+    /// Maximum number of buffers
+    pub capacity: usize,
+    /// Buffers currently allocated
+    pub in_use: usize,
+    /// Maximum buffers allocated at any one time
+    pub high_watermark: usize,
+    /// Total allocations
+    pub allocations: u64,
+    /// Allocations failed due to an exhausted pool
+    pub exhausted: u64,
+}
+
+impl PoolStats {
+    /// Create statistics for a pool of `capacity` buffers
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    /// Record an allocation attempt, returning whether a buffer is available
+    fn alloc(&mut self) -> bool {
+        if self.in_use >= self.capacity {
+            self.exhausted += 1;
+            return false;
+        }
+
+        self.in_use += 1;
+        self.allocations += 1;
+        self.high_watermark = self.high_watermark.max(self.in_use);
+        true
+    }
+
+    /// Record a buffer release
+    fn release(&mut self) {
+        self.in_use = self.in_use.saturating_sub(1);
+    }
+}
+
+impl core::fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} / {} buffers in use (high watermark {}), {} allocations, {} exhausted",
+            self.in_use, self.capacity, self.high_watermark, self.allocations, self.exhausted
+        )
+    }
+}
+
+/// Handle to a buffer allocated from a [`StaticPool`]
+///
+/// Handles are not `Clone`, ensuring each allocation is released once.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PoolBuffer(usize);
+
+/// Fixed buffer pool of `N` buffers of `M` bytes, without heap allocation
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticPool<const N: usize, const M: usize> {
+    buffers: [[u8; M]; N],
+    used: [bool; N],
+    stats: PoolStats,
+}
+
+impl<const N: usize, const M: usize> Default for StaticPool<N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const M: usize> StaticPool<N, M> {
+    /// Create a new pool with all buffers free
+    pub fn new() -> Self {
+        Self {
+            buffers: [[0u8; M]; N],
+            used: [false; N],
+            stats: PoolStats::new(N),
+        }
+    }
+
+    /// Allocate a buffer, returning `None` where the pool is exhausted
+    pub fn alloc(&mut self) -> Option<PoolBuffer> {
+        if !self.stats.alloc() {
+            return None;
+        }
+
+        let i = self.used.iter().position(|u| !u)?;
+        self.used[i] = true;
+        Some(PoolBuffer(i))
+    }
+
+    /// Access an allocated buffer
+    pub fn get_mut(&mut self, b: &PoolBuffer) -> &mut [u8; M] {
+        &mut self.buffers[b.0]
+    }
+
+    /// Access an allocated buffer
+    pub fn get(&self, b: &PoolBuffer) -> &[u8; M] {
+        &self.buffers[b.0]
+    }
+
+    /// Return a buffer to the pool
+    pub fn release(&mut self, b: PoolBuffer) {
+        self.used[b.0] = false;
+        self.stats.release();
+    }
+
+    /// Pool statistics
+    pub fn stats(&self) -> &PoolStats {
+        &self.stats
+    }
+}
+
+#[cfg(feature = "std")]
+mod vec_pool {
+    use std::sync::{Arc, Mutex};
+
+    use super::PoolStats;
+
+    struct Inner {
+        free: Vec<Vec<u8>>,
+        stats: PoolStats,
+    }
+
+    /// Shared pool of reusable `Vec` buffers, bounding the number of buffers in flight
+    ///
+    /// Clones share the same pool, allowing buffers to be released from other threads.
+    /// Released buffers retain their allocation for reuse.
+    #[derive(Clone)]
+    pub struct VecPool {
+        inner: Arc<Mutex<Inner>>,
+    }
+
+    impl VecPool {
+        /// Create a pool of up to `capacity` buffers
+        pub fn new(capacity: usize) -> Self {
+            Self {
+                inner: Arc::new(Mutex::new(Inner {
+                    free: Vec::new(),
+                    stats: PoolStats::new(capacity),
+                })),
+            }
+        }
+
+        /// Allocate a buffer containing a copy of `data`, returning `None` where the pool is
+        /// exhausted
+        pub fn alloc_from(&self, data: &[u8]) -> Option<Vec<u8>> {
+            let mut b = self.alloc()?;
+            b.extend_from_slice(data);
+            Some(b)
+        }
+
+        /// Allocate an empty buffer, returning `None` where the pool is exhausted
+        pub fn alloc(&self) -> Option<Vec<u8>> {
+            let mut inner = self.inner.lock().unwrap();
+            if !inner.stats.alloc() {
+                return None;
+            }
+
+            let mut b = inner.free.pop().unwrap_or_default();
+            b.clear();
+            Some(b)
+        }
+
+        /// Return a buffer to the pool
+        pub fn release(&self, b: Vec<u8>) {
+            let mut inner = self.inner.lock().unwrap();
+            inner.stats.release();
+
+            // Buffers not allocated from the pool are discarded once the free list is full
+            if inner.free.len() < inner.stats.capacity {
+                inner.free.push(b);
+            }
+        }
+
+        /// Pool statistics
+        pub fn stats(&self) -> PoolStats {
+            self.inner.lock().unwrap().stats.clone()
+        }
+    }
+
+    impl core::fmt::Debug for VecPool {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_tuple("VecPool").field(&self.stats()).finish()
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub use vec_pool::VecPool;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_pool() {
+        let mut p: StaticPool<2, 16> = StaticPool::new();
+
+        let a = p.alloc().unwrap();
+        let b = p.alloc().unwrap();
+        assert_eq!(p.alloc(), None);
+
+        p.get_mut(&a)[..2].copy_from_slice(&[1, 2]);
+        assert_eq!(p.get(&a)[..2], [1, 2]);
+
+        p.release(b);
+        let c = p.alloc().unwrap();
+        assert_eq!(c, PoolBuffer(1));
+
+        assert_eq!(
+            p.stats(),
+            &PoolStats {
+                capacity: 2,
+                in_use: 2,
+                high_watermark: 2,
+                allocations: 3,
+                exhausted: 1,
+            }
+        );
+    }
+}
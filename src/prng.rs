@@ -0,0 +1,82 @@
+//! Small seedable pseudo-random number generator
+//!
+//! This provides a `no_std` compatible xorshift generator for jitter, backoff and
+//! test traffic generation, where reproducibility matters more than quality.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+/// Xorshift32 pseudo-random number generator
+#[derive(Clone, Debug, PartialEq)]
+pub struct XorShift32 {
+    state: u32,
+}
+
+impl XorShift32 {
+    /// Create a new generator with the provided seed (a zero seed is replaced with a fixed value)
+    pub fn new(seed: u32) -> Self {
+        let state = if seed == 0 { 0x9e37_79b9 } else { seed };
+        Self { state }
+    }
+
+    /// Fetch the next random u32
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Fetch a random value in the range `[0, max)`, returning 0 where `max` is 0
+    pub fn below(&mut self, max: u32) -> u32 {
+        match max {
+            0 => 0,
+            _ => self.next_u32() % max,
+        }
+    }
+
+    /// Fetch a random value in the range `[0.0, 1.0)`
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Fill the provided buffer with random bytes
+    pub fn fill(&mut self, buff: &mut [u8]) {
+        for c in buff.chunks_mut(4) {
+            let v = self.next_u32().to_le_bytes();
+            c.copy_from_slice(&v[..c.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prng_is_deterministic() {
+        let mut a = XorShift32::new(1234);
+        let mut b = XorShift32::new(1234);
+
+        for _ in 0..16 {
+            assert_eq!(a.next_u32(), b.next_u32());
+        }
+    }
+
+    #[test]
+    fn prng_ranges() {
+        let mut r = XorShift32::new(0);
+
+        for _ in 0..256 {
+            assert!(r.below(10) < 10);
+            let f = r.next_f32();
+            assert!((0.0..1.0).contains(&f));
+        }
+        assert_eq!(r.below(0), 0);
+
+        let mut buff = [0u8; 7];
+        r.fill(&mut buff);
+        assert!(buff.iter().any(|b| *b != 0));
+    }
+}
@@ -0,0 +1,315 @@
+//! Python bindings for lab automation
+//!
+//! Exposes the transmit, receive, echo and ping-pong operations, their options, and the
+//! [`UdpRadio`] to Python, so link tests can be scripted (and results parsed) from pytest
+//! without writing Rust. UDP radios bound to the loopback interface provide a simulated
+//! link for testing scripts without hardware.
+//!
+//! The extension module is built with:
+//!
+//! ```text
+//! cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib
+//! ```
+//!
+//! ```python
+//! import radio
+//! r = radio.UdpRadio("127.0.0.1:9000", "127.0.0.1:9001")
+//! info = radio.ping_pong(r, radio.PingPongOptions(rounds=10))
+//! assert info["received"] == 10
+//! ```
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use pyo3::exceptions::{PyNotImplementedError, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::{
+    blocking::{self, BlockingError, BlockingReceive},
+    helpers::{self, EchoTransform, UdpRadio},
+};
+
+fn to_py_err(e: BlockingError<std::io::Error>) -> PyErr {
+    match e {
+        BlockingError::Timeout | BlockingError::Exhausted { error: None, .. } => {
+            PyTimeoutError::new_err("radio operation timed out")
+        }
+        BlockingError::Inner(e) | BlockingError::Exhausted { error: Some(e), .. } => e.into(),
+        e @ BlockingError::Oversize { .. } => PyValueError::new_err(format!("{e:?}")),
+        e @ BlockingError::Unsupported(_) => PyNotImplementedError::new_err(format!("{e:?}")),
+    }
+}
+
+fn parse_addr(addr: &str) -> PyResult<SocketAddr> {
+    addr.parse()
+        .map_err(|e| PyValueError::new_err(format!("invalid address '{addr}': {e}")))
+}
+
+/// UDP-backed radio, transmitting packets to a peer address
+#[pyclass(name = "UdpRadio")]
+pub struct PyUdpRadio(UdpRadio);
+
+#[pymethods]
+impl PyUdpRadio {
+    #[new]
+    fn new(bind: &str, peer: &str) -> PyResult<Self> {
+        Ok(Self(UdpRadio::new(parse_addr(bind)?, parse_addr(peer)?)?))
+    }
+
+    /// Local socket address
+    fn local_addr(&self) -> PyResult<String> {
+        Ok(self.0.local_addr()?.to_string())
+    }
+
+    /// Set the RSSI reported for received packets
+    fn set_rssi(&mut self, rssi: i16) {
+        self.0.set_rssi(rssi);
+    }
+}
+
+/// Blocking operation options
+#[pyclass(name = "BlockingOptions", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct PyBlockingOptions {
+    /// Interval for polling for device state in microseconds
+    pub poll_interval_us: u64,
+    /// Timeout for blocking operations in milliseconds
+    pub timeout_ms: u64,
+}
+
+#[pymethods]
+impl PyBlockingOptions {
+    #[new]
+    #[pyo3(signature = (poll_interval_us = 100, timeout_ms = 100))]
+    fn new(poll_interval_us: u64, timeout_ms: u64) -> Self {
+        Self {
+            poll_interval_us,
+            timeout_ms,
+        }
+    }
+}
+
+impl From<Option<PyBlockingOptions>> for blocking::BlockingOptions {
+    fn from(o: Option<PyBlockingOptions>) -> Self {
+        match o {
+            Some(o) => Self {
+                poll_interval: Duration::from_micros(o.poll_interval_us),
+                timeout: Duration::from_millis(o.timeout_ms),
+                ..Default::default()
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+/// Ping-pong operation options
+#[pyclass(name = "PingPongOptions", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct PyPingPongOptions {
+    /// Number of rounds to run
+    pub rounds: u32,
+    /// Transmit power in dBm
+    pub power: Option<i8>,
+    /// Delay between rounds in milliseconds
+    pub delay_ms: u64,
+    /// Parse RSSI from responses (echo server must append info)
+    pub parse_info: bool,
+    /// Payload size in bytes (minimum 4)
+    pub size: usize,
+    /// Blocking options for each transmit and receive
+    pub blocking: Option<PyBlockingOptions>,
+}
+
+#[pymethods]
+impl PyPingPongOptions {
+    #[new]
+    #[pyo3(signature = (rounds = 100, power = None, delay_ms = 100, parse_info = false, size = 4, blocking = None))]
+    fn new(
+        rounds: u32,
+        power: Option<i8>,
+        delay_ms: u64,
+        parse_info: bool,
+        size: usize,
+        blocking: Option<PyBlockingOptions>,
+    ) -> Self {
+        Self {
+            rounds,
+            power,
+            delay_ms,
+            parse_info,
+            size,
+            blocking,
+        }
+    }
+}
+
+impl From<PyPingPongOptions> for helpers::PingPongOptions {
+    fn from(o: PyPingPongOptions) -> Self {
+        Self {
+            rounds: o.rounds,
+            power: o.power,
+            delay: Duration::from_millis(o.delay_ms).into(),
+            parse_info: o.parse_info,
+            size: o.size,
+            blocking_options: o.blocking.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Echo operation options
+#[pyclass(name = "EchoOptions", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct PyEchoOptions {
+    /// Run continuously
+    pub continuous: bool,
+    /// Transmit power in dBm
+    pub power: Option<i8>,
+    /// Delay before responding in milliseconds
+    pub delay_ms: u64,
+    /// Append RSSI and LQI to responses
+    pub append_info: bool,
+    /// Payload transform (`none`, `reverse`, `invert` or `checksum`)
+    pub transform: String,
+    /// Node address for frame filtering
+    pub address: Option<u16>,
+    /// Blocking options for each transmit and receive
+    pub blocking: Option<PyBlockingOptions>,
+}
+
+#[pymethods]
+impl PyEchoOptions {
+    #[new]
+    #[pyo3(signature = (continuous = false, power = None, delay_ms = 100, append_info = false, transform = "none".to_string(), address = None, blocking = None))]
+    fn new(
+        continuous: bool,
+        power: Option<i8>,
+        delay_ms: u64,
+        append_info: bool,
+        transform: String,
+        address: Option<u16>,
+        blocking: Option<PyBlockingOptions>,
+    ) -> Self {
+        Self {
+            continuous,
+            power,
+            delay_ms,
+            append_info,
+            transform,
+            address,
+            blocking,
+        }
+    }
+}
+
+impl TryFrom<PyEchoOptions> for helpers::EchoOptions {
+    type Error = PyErr;
+
+    fn try_from(o: PyEchoOptions) -> PyResult<Self> {
+        let transform = match o.transform.as_str() {
+            "none" => EchoTransform::None,
+            "reverse" => EchoTransform::Reverse,
+            "invert" => EchoTransform::Invert,
+            "checksum" => EchoTransform::Checksum,
+            t => return Err(PyValueError::new_err(format!("unknown transform '{t}'"))),
+        };
+
+        Ok(Self {
+            continuous: o.continuous,
+            power: o.power,
+            delay: Duration::from_millis(o.delay_ms).into(),
+            append_info: o.append_info,
+            transform,
+            address: o.address,
+            shutdown: helpers::Shutdown::global(),
+            blocking_options: o.blocking.into(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Transmit a packet
+#[pyfunction]
+#[pyo3(signature = (radio, data, power = None, blocking = None))]
+fn transmit(
+    py: Python<'_>,
+    radio: &mut PyUdpRadio,
+    data: Vec<u8>,
+    power: Option<i8>,
+    blocking: Option<PyBlockingOptions>,
+) -> PyResult<()> {
+    let options = helpers::TransmitOptions {
+        data,
+        power,
+        blocking_options: blocking.into(),
+        ..Default::default()
+    };
+
+    py.allow_threads(|| helpers::do_transmit(&mut radio.0, options))
+        .map(|_| ())
+        .map_err(to_py_err)
+}
+
+/// Receive a single packet, returning the payload and RSSI
+#[pyfunction]
+#[pyo3(signature = (radio, blocking = None))]
+fn receive<'py>(
+    py: Python<'py>,
+    radio: &mut PyUdpRadio,
+    blocking: Option<PyBlockingOptions>,
+) -> PyResult<(Bound<'py, PyBytes>, i16)> {
+    let mut buff = [0u8; 2048];
+    let (n, info) = py
+        .allow_threads(|| radio.0.do_receive(&mut buff, blocking.into()))
+        .map_err(to_py_err)?;
+
+    Ok((
+        PyBytes::new(py, &buff[..n]),
+        crate::ReceiveInfo::rssi(&info),
+    ))
+}
+
+/// Respond to received packets, returning the length of the last response
+#[pyfunction]
+fn echo(py: Python<'_>, radio: &mut PyUdpRadio, options: PyEchoOptions) -> PyResult<usize> {
+    let options = options.try_into()?;
+    let mut buff = [0u8; 2048];
+
+    py.allow_threads(|| helpers::do_echo(&mut radio.0, &mut buff, options))
+        .map_err(|e| match e {
+            helpers::OperationError::Radio(e) => to_py_err(e),
+            helpers::OperationError::Io(e) => e.into(),
+        })
+}
+
+/// Run a ping-pong link test, returning results as a dictionary
+#[pyfunction]
+fn ping_pong<'py>(
+    py: Python<'py>,
+    radio: &mut PyUdpRadio,
+    options: PyPingPongOptions,
+) -> PyResult<Bound<'py, PyAny>> {
+    let info = py
+        .allow_threads(|| helpers::do_ping_pong(&mut radio.0, options.into()))
+        .map_err(to_py_err)?;
+
+    let s = serde_json::to_string(&info).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    py.import("json")?.call_method1("loads", (s,))
+}
+
+/// Python module definition
+#[pymodule]
+fn radio(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyUdpRadio>()?;
+    m.add_class::<PyBlockingOptions>()?;
+    m.add_class::<PyPingPongOptions>()?;
+    m.add_class::<PyEchoOptions>()?;
+    m.add_function(wrap_pyfunction!(transmit, m)?)?;
+    m.add_function(wrap_pyfunction!(receive, m)?)?;
+    m.add_function(wrap_pyfunction!(echo, m)?)?;
+    m.add_function(wrap_pyfunction!(ping_pong, m)?)?;
+    Ok(())
+}
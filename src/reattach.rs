@@ -0,0 +1,326 @@
+//! Hot-unplug detection and reconnection for detachable radios
+//!
+//! Radios connected via USB adapters or SPI bridges may disappear mid-operation.
+//! [`Reattaching`] wraps a [`Detachable`] radio, detecting bus-gone errors, waiting for
+//! the device to reappear and re-initialising it before resuming the interrupted operation,
+//! so helpers and long-running captures continue across unplug events.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+
+#[cfg(all(feature = "log", not(feature = "defmt")))]
+use log::debug;
+
+#[cfg(feature = "clap")]
+use clap::Parser;
+
+use crate::{Channel, Power, Receive, Rssi, Transmit};
+
+/// Detachable trait for radios that may be disconnected at runtime
+pub trait Detachable {
+    /// Radio error type
+    type Error: Debug;
+
+    /// Check whether an error indicates the device has been disconnected
+    fn is_detached(&self, err: &Self::Error) -> bool;
+
+    /// Attempt to reopen the device and re-initialise it from stored configuration
+    fn reattach(&mut self) -> Result<(), Self::Error>;
+}
+
+/// ReattachOptions configure reconnection behaviour
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReattachOptions {
+    /// Interval between reconnection attempts
+    #[cfg_attr(feature="clap", clap(long, default_value="1s", value_parser=crate::duration_from_str))]
+    pub reattach_interval: Duration,
+
+    /// Maximum reconnection attempts before failing (unlimited if unset)
+    #[cfg_attr(feature = "clap", clap(long))]
+    pub reattach_attempts: Option<u32>,
+}
+
+impl Default for ReattachOptions {
+    fn default() -> Self {
+        Self {
+            reattach_interval: Duration::from_secs(1),
+            reattach_attempts: None,
+        }
+    }
+}
+
+/// Reattaching wraps a [`Detachable`] radio, transparently reconnecting on disconnection
+///
+/// Interrupted operations are retried once following reconnection, and receive mode is
+/// restarted where the device was disconnected while receiving.
+pub struct Reattaching<T> {
+    radio: T,
+    options: ReattachOptions,
+    reattached: u32,
+}
+
+impl<T, E> Reattaching<T>
+where
+    T: Detachable<Error = E> + DelayNs,
+    E: Debug,
+{
+    /// Wrap a detachable radio
+    pub fn new(radio: T, options: ReattachOptions) -> Self {
+        Self {
+            radio,
+            options,
+            reattached: 0,
+        }
+    }
+
+    /// Number of times the radio has been reattached
+    pub fn reattached(&self) -> u32 {
+        self.reattached
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+
+    /// Wait for the device to reappear and reinitialise it, returning the original error on failure
+    fn reattach(&mut self, err: E) -> Result<(), E> {
+        #[cfg(any(feature = "log", feature = "defmt"))]
+        debug!("Radio detached, awaiting reconnection");
+
+        let mut attempts = 0;
+        loop {
+            if let Some(max) = self.options.reattach_attempts
+                && attempts >= max
+            {
+                return Err(err);
+            }
+            attempts += 1;
+
+            self.radio
+                .delay_us(self.options.reattach_interval.as_micros() as u32);
+
+            match self.radio.reattach() {
+                Ok(_) => break,
+                Err(e) if self.radio.is_detached(&e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        #[cfg(any(feature = "log", feature = "defmt"))]
+        debug!("Radio reattached after {} attempts", attempts);
+
+        self.reattached += 1;
+
+        Ok(())
+    }
+
+    /// Run an operation, reattaching and retrying once on disconnection
+    fn with_reattach<R>(&mut self, mut f: impl FnMut(&mut T) -> Result<R, E>) -> Result<R, E> {
+        match f(&mut self.radio) {
+            Err(e) if self.radio.is_detached(&e) => {
+                self.reattach(e)?;
+                f(&mut self.radio)
+            }
+            r => r,
+        }
+    }
+}
+
+impl<T, E> Transmit for Reattaching<T>
+where
+    T: Transmit<Error = E> + Detachable<Error = E> + DelayNs,
+    E: Debug,
+{
+    type Error = E;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.with_reattach(|r| r.start_transmit(data))
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        match self.radio.check_transmit() {
+            // Transmission was lost with the device, report completion to avoid blocking
+            Err(e) if self.radio.is_detached(&e) => self.reattach(e).map(|_| true),
+            r => r,
+        }
+    }
+}
+
+impl<T, E> Receive for Reattaching<T>
+where
+    T: Receive<Error = E> + Detachable<Error = E> + DelayNs,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = E;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.with_reattach(|r| r.start_receive())
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        match self.radio.check_receive(restart) {
+            // Restart receive mode on the reattached device
+            Err(e) if self.radio.is_detached(&e) => {
+                self.reattach(e)?;
+                self.radio.start_receive()?;
+                Ok(false)
+            }
+            r => r,
+        }
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        self.radio.get_received(buff)
+    }
+}
+
+impl<T, E> Power for Reattaching<T>
+where
+    T: Power<Error = E> + Detachable<Error = E> + DelayNs,
+    E: Debug,
+{
+    type Error = E;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.with_reattach(|r| r.set_power(power))
+    }
+}
+
+impl<T, E> Rssi for Reattaching<T>
+where
+    T: Rssi<Error = E> + Detachable<Error = E> + DelayNs,
+    E: Debug,
+{
+    type Error = E;
+
+    fn poll_rssi(&mut self) -> Result<i16, Self::Error> {
+        self.with_reattach(|r| r.poll_rssi())
+    }
+
+    fn poll_rssi_n(&mut self, out: &mut [i16]) -> Result<usize, Self::Error> {
+        self.with_reattach(|r| r.poll_rssi_n(out))
+    }
+}
+
+impl<T, E> Channel for Reattaching<T>
+where
+    T: Channel<Error = E> + Detachable<Error = E> + DelayNs,
+    E: Debug,
+{
+    type Channel = T::Channel;
+    type Error = E;
+
+    fn set_channel(&mut self, channel: &Self::Channel) -> Result<(), Self::Error> {
+        self.with_reattach(|r| r.set_channel(channel))
+    }
+}
+
+impl<T: DelayNs> DelayNs for Reattaching<T> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum UsbError {
+        Gone,
+    }
+
+    /// Fake USB radio, unplugged for a number of reattach attempts
+    struct UsbRadio {
+        unplugged: u32,
+        power: Option<i8>,
+        configured_power: i8,
+    }
+
+    impl Detachable for UsbRadio {
+        type Error = UsbError;
+
+        fn is_detached(&self, err: &Self::Error) -> bool {
+            *err == UsbError::Gone
+        }
+
+        fn reattach(&mut self) -> Result<(), Self::Error> {
+            match self.unplugged {
+                0 => {
+                    self.power = Some(self.configured_power);
+                    Ok(())
+                }
+                _ => {
+                    self.unplugged -= 1;
+                    Err(UsbError::Gone)
+                }
+            }
+        }
+    }
+
+    impl Power for UsbRadio {
+        type Error = UsbError;
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            match self.power {
+                Some(_) => {
+                    self.power = Some(power);
+                    Ok(())
+                }
+                None => Err(UsbError::Gone),
+            }
+        }
+    }
+
+    impl DelayNs for UsbRadio {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn reattach_and_retry() {
+        let radio = UsbRadio {
+            unplugged: 3,
+            power: None,
+            configured_power: 10,
+        };
+        let mut r = Reattaching::new(radio, ReattachOptions::default());
+
+        assert_eq!(r.set_power(5), Ok(()));
+        assert_eq!(r.reattached(), 1);
+        assert_eq!(r.inner().power, Some(5));
+    }
+
+    #[test]
+    fn reattach_attempt_limit() {
+        let radio = UsbRadio {
+            unplugged: 10,
+            power: None,
+            configured_power: 10,
+        };
+        let opts = ReattachOptions {
+            reattach_attempts: Some(2),
+            ..Default::default()
+        };
+        let mut r = Reattaching::new(radio, opts);
+
+        assert_eq!(r.set_power(5), Err(UsbError::Gone));
+        assert_eq!(r.reattached(), 0);
+        assert_eq!(r.free().unplugged, 8);
+    }
+}
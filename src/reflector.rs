@@ -0,0 +1,166 @@
+//! Reflector frame format for over-the-air loopback tests
+//!
+//! Link tests send reflector frames to a peer (the reflector), which returns each frame
+//! with [`REFLECTED_FLAG`] set, optionally appending receive quality information (RSSI
+//! and LQI etc.) after the frame and setting [`INFO_FLAG`]. Frames carry a sequence
+//! number and the initiator's transmit timestamp, which reflectors return unchanged,
+//! so round-trip times can be computed from responses alone.
+//!
+//! Frames are encoded as `[magic (2), version, flags, seq (BE u32), timestamp us (BE
+//! u64)]`, followed by any padding. Later versions only extend the header, so decoders
+//! accept frames of any version (reading the fields defined here), and reflectors
+//! return frames of any version unchanged other than the flags and appended info,
+//! allowing nodes running mixed versions (or third-party reflector firmware) to
+//! interoperate. Reflectors ignore frames with [`REFLECTED_FLAG`] already set, avoiding
+//! loops between reflectors sharing a channel.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+/// Magic identifying reflector frames
+pub const REFLECTOR_MAGIC: [u8; 2] = [0x52, 0xef];
+
+/// Current reflector frame version
+pub const REFLECTOR_VERSION: u8 = 1;
+
+/// Flag set by reflectors on returned frames
+pub const REFLECTED_FLAG: u8 = 0x01;
+
+/// Flag set by reflectors where receive quality information follows the frame
+pub const INFO_FLAG: u8 = 0x02;
+
+/// Reflector frame header
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReflectorFrame {
+    /// Frame version
+    pub version: u8,
+    /// Frame flags
+    pub flags: u8,
+    /// Sequence number
+    pub seq: u32,
+    /// Initiator transmit timestamp in microseconds
+    pub timestamp_us: u64,
+}
+
+impl ReflectorFrame {
+    /// Encoded header length in bytes
+    pub const LEN: usize = 16;
+
+    /// Create a new (unreflected) frame with the current version
+    pub fn new(seq: u32, timestamp_us: u64) -> Self {
+        Self {
+            version: REFLECTOR_VERSION,
+            flags: 0,
+            seq,
+            timestamp_us,
+        }
+    }
+
+    /// Check whether the frame has been returned by a reflector
+    pub fn is_reflected(&self) -> bool {
+        self.flags & REFLECTED_FLAG != 0
+    }
+
+    /// Check whether receive quality information follows the frame
+    pub fn has_info(&self) -> bool {
+        self.flags & INFO_FLAG != 0
+    }
+
+    /// Encode the header to bytes
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut b = [0u8; Self::LEN];
+        b[..2].copy_from_slice(&REFLECTOR_MAGIC);
+        b[2] = self.version;
+        b[3] = self.flags;
+        b[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        b[8..16].copy_from_slice(&self.timestamp_us.to_be_bytes());
+        b
+    }
+
+    /// Decode a header from the start of the provided data, `None` where the data is not a
+    /// reflector frame
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < Self::LEN || data[..2] != REFLECTOR_MAGIC || data[2] == 0 {
+            return None;
+        }
+
+        let mut seq = [0u8; 4];
+        seq.copy_from_slice(&data[4..8]);
+        let mut ts = [0u8; 8];
+        ts.copy_from_slice(&data[8..16]);
+
+        Some(Self {
+            version: data[2],
+            flags: data[3],
+            seq: u32::from_be_bytes(seq),
+            timestamp_us: u64::from_be_bytes(ts),
+        })
+    }
+
+    /// Encode a frame padded to `len` bytes (at least [`ReflectorFrame::LEN`]) into
+    /// `buff`, returning the frame length
+    pub fn encode(&self, len: usize, buff: &mut [u8]) -> Option<usize> {
+        let n = len.max(Self::LEN);
+        if buff.len() < n {
+            return None;
+        }
+
+        buff[..Self::LEN].copy_from_slice(&self.to_bytes());
+        for (i, b) in buff[Self::LEN..n].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        Some(n)
+    }
+}
+
+/// Mark a received reflector frame as reflected in place, returning the frame header, or
+/// `None` where the data is not an unreflected reflector frame
+///
+/// Where `info` is set the caller appends receive quality information to the frame.
+pub fn reflect(data: &mut [u8], info: bool) -> Option<ReflectorFrame> {
+    let mut frame = ReflectorFrame::from_bytes(data)?;
+    if frame.is_reflected() {
+        return None;
+    }
+
+    frame.flags |= REFLECTED_FLAG;
+    if info {
+        frame.flags |= INFO_FLAG;
+    }
+    data[3] = frame.flags;
+
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflector_frames() {
+        let mut buff = [0u8; 32];
+        let f = ReflectorFrame::new(7, 1_234_567);
+        assert_eq!(f.encode(20, &mut buff), Some(20));
+        assert_eq!(f.encode(4, &mut buff), Some(ReflectorFrame::LEN));
+        assert_eq!(f.encode(40, &mut buff), None);
+        assert_eq!(ReflectorFrame::from_bytes(&buff), Some(f));
+
+        // Reflection sets flags, leaving other fields unchanged
+        let r = reflect(&mut buff, true).unwrap();
+        assert!(r.is_reflected() && r.has_info());
+        assert_eq!((r.seq, r.timestamp_us), (7, 1_234_567));
+        assert_eq!(ReflectorFrame::from_bytes(&buff), Some(r));
+
+        // Reflected frames are not reflected again
+        assert_eq!(reflect(&mut buff, false), None);
+
+        // Later versions are reflected
+        buff[2] = 9;
+        buff[3] = 0;
+        assert_eq!(reflect(&mut buff, false).map(|f| f.version), Some(9));
+
+        assert_eq!(ReflectorFrame::from_bytes(&[0u8; 16]), None);
+        assert_eq!(ReflectorFrame::from_bytes(&buff[..8]), None);
+    }
+}
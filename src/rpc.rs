@@ -0,0 +1,502 @@
+//! Request/response RPC over a radio link
+//!
+//! Provides correlated request / response exchanges with request IDs, timeouts, retries
+//! and multiple concurrent outstanding requests, so command-and-control applications
+//! need not hand-roll matching logic over raw frames. RPC messages may be carried over
+//! any [`Transmit`] + [`Receive`] implementation, including reliable or addressed links
+//! layered over a radio.
+//!
+//! Messages are encoded as `[kind, id (BE u16), method, payload..]`. Timestamps are
+//! provided by the caller in milliseconds, allowing use in `no_std` environments.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::{
+    Receive, Transmit,
+    blocking::{BlockingError, BlockingOptions, BlockingTransmit},
+};
+
+/// RPC message kinds
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RpcKind {
+    Request = 1,
+    Response = 2,
+}
+
+/// RPC message header
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RpcHeader {
+    pub kind: RpcKind,
+    pub id: u16,
+    pub method: u8,
+}
+
+impl RpcHeader {
+    /// Encoded header length in bytes
+    pub const LEN: usize = 4;
+
+    /// Encode the header to bytes
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let id = self.id.to_be_bytes();
+        [self.kind as u8, id[0], id[1], self.method]
+    }
+
+    /// Decode a header from the start of the provided data
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < Self::LEN {
+            return None;
+        }
+        let kind = match data[0] {
+            1 => RpcKind::Request,
+            2 => RpcKind::Response,
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            id: u16::from_be_bytes([data[1], data[2]]),
+            method: data[3],
+        })
+    }
+}
+
+/// RPC configuration
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RpcOptions {
+    /// Time to wait for a response before retrying
+    pub timeout_ms: u32,
+    /// Number of retries before a request times out
+    pub retries: u8,
+    /// Options for blocking transmission of messages
+    pub blocking_options: BlockingOptions,
+}
+
+impl Default for RpcOptions {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 500,
+            retries: 3,
+            blocking_options: BlockingOptions::default(),
+        }
+    }
+}
+
+/// RpcError describes failures issuing or processing RPC messages
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RpcError<E> {
+    /// Underlying radio error
+    #[cfg_attr(feature = "thiserror", error("Radio: {0:?}"))]
+    Radio(BlockingError<E>),
+    /// No slots available for another outstanding request
+    #[cfg_attr(feature = "thiserror", error("Too many outstanding requests"))]
+    Full,
+    /// Payload too large for the request or response buffer
+    #[cfg_attr(feature = "thiserror", error("Payload too large"))]
+    TooLarge,
+}
+
+impl<E> From<BlockingError<E>> for RpcError<E> {
+    fn from(e: BlockingError<E>) -> Self {
+        RpcError::Radio(e)
+    }
+}
+
+impl<E> From<E> for RpcError<E> {
+    fn from(e: E) -> Self {
+        RpcError::Radio(BlockingError::Inner(e))
+    }
+}
+
+/// Client events returned by [`RpcClient::poll`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RpcEvent {
+    /// Response received, with payload of the provided length at the start of the poll buffer
+    Response { id: u16, method: u8, len: usize },
+    /// Request timed out after all retries
+    Timeout { id: u16, method: u8 },
+}
+
+/// Outstanding request, retaining the encoded message for retries
+#[derive(Clone, Debug)]
+struct Pending<const M: usize> {
+    id: u16,
+    method: u8,
+    sent_at: u32,
+    retries: u8,
+    len: usize,
+    data: [u8; M],
+}
+
+/// RPC client supporting up to `N` outstanding requests of up to `M` encoded bytes
+pub struct RpcClient<const N: usize, const M: usize> {
+    options: RpcOptions,
+    next_id: u16,
+    pending: [Option<Pending<M>>; N],
+}
+
+impl<const N: usize, const M: usize> RpcClient<N, M> {
+    /// Create a new RPC client
+    pub fn new(options: RpcOptions) -> Self {
+        Self {
+            options,
+            next_id: 1,
+            pending: [const { None }; N],
+        }
+    }
+
+    /// Number of outstanding requests
+    pub fn outstanding(&self) -> usize {
+        self.pending.iter().filter(|p| p.is_some()).count()
+    }
+
+    /// Issue a request, returning the request ID used to correlate the response
+    ///
+    /// The radio is returned to receive mode following transmission.
+    pub fn request<T, E>(
+        &mut self,
+        radio: &mut T,
+        method: u8,
+        payload: &[u8],
+        now_ms: u32,
+    ) -> Result<u16, RpcError<E>>
+    where
+        T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+        E: Debug,
+    {
+        let n = RpcHeader::LEN + payload.len();
+        if n > M {
+            return Err(RpcError::TooLarge);
+        }
+        let slot = self
+            .pending
+            .iter()
+            .position(|p| p.is_none())
+            .ok_or(RpcError::Full)?;
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        let mut p = Pending {
+            id,
+            method,
+            sent_at: now_ms,
+            retries: 0,
+            len: n,
+            data: [0u8; M],
+        };
+        let h = RpcHeader {
+            kind: RpcKind::Request,
+            id,
+            method,
+        };
+        p.data[..RpcHeader::LEN].copy_from_slice(&h.to_bytes());
+        p.data[RpcHeader::LEN..n].copy_from_slice(payload);
+
+        radio.do_transmit(&p.data[..n], self.options.blocking_options.clone())?;
+        radio.start_receive()?;
+
+        self.pending[slot] = Some(p);
+
+        Ok(id)
+    }
+
+    /// Cancel an outstanding request
+    pub fn cancel(&mut self, id: u16) -> bool {
+        match self
+            .pending
+            .iter_mut()
+            .find(|p| matches!(p, Some(p) if p.id == id))
+        {
+            Some(p) => {
+                *p = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Poll for responses and timeouts, retrying requests where required
+    ///
+    /// Response payloads are copied to the start of `buff`.
+    pub fn poll<T, E>(
+        &mut self,
+        radio: &mut T,
+        buff: &mut [u8],
+        now_ms: u32,
+    ) -> Result<Option<RpcEvent>, RpcError<E>>
+    where
+        T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+        E: Debug,
+    {
+        // Check for responses
+        if radio.check_receive(true)? {
+            let (n, _i) = radio.get_received(buff)?;
+            radio.start_receive()?;
+
+            let h = match RpcHeader::from_bytes(&buff[..n]) {
+                Some(h) if h.kind == RpcKind::Response => h,
+                _ => return Ok(None),
+            };
+
+            // Match response to outstanding request, ignoring stale or duplicate responses
+            let slot = self
+                .pending
+                .iter_mut()
+                .find(|p| matches!(p, Some(p) if p.id == h.id && p.method == h.method));
+            if let Some(slot) = slot {
+                *slot = None;
+                buff.copy_within(RpcHeader::LEN..n, 0);
+                return Ok(Some(RpcEvent::Response {
+                    id: h.id,
+                    method: h.method,
+                    len: n - RpcHeader::LEN,
+                }));
+            }
+
+            return Ok(None);
+        }
+
+        // Check for timeouts
+        let timeout = self.options.timeout_ms;
+        let slot = self
+            .pending
+            .iter_mut()
+            .find(|p| matches!(p, Some(p) if now_ms.wrapping_sub(p.sent_at) >= timeout));
+        let slot = match slot {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let p = slot.as_mut().unwrap();
+        if p.retries >= self.options.retries {
+            let e = RpcEvent::Timeout {
+                id: p.id,
+                method: p.method,
+            };
+            *slot = None;
+            return Ok(Some(e));
+        }
+
+        p.retries += 1;
+        p.sent_at = now_ms;
+        radio.do_transmit(&p.data[..p.len], self.options.blocking_options.clone())?;
+        radio.start_receive()?;
+
+        Ok(None)
+    }
+}
+
+/// RPC server, responding to requests using a handler
+///
+/// The most recent response is cached so retried requests are answered without
+/// re-running the handler.
+pub struct RpcServer<const M: usize> {
+    options: RpcOptions,
+    last: Option<(u16, usize)>,
+    response: [u8; M],
+}
+
+impl<const M: usize> RpcServer<M> {
+    /// Create a new RPC server
+    pub fn new(options: RpcOptions) -> Self {
+        Self {
+            options,
+            last: None,
+            response: [0u8; M],
+        }
+    }
+
+    /// Poll for requests, calling the handler with the method, request payload and a response
+    /// buffer and returning the response length (or `None` to not respond)
+    ///
+    /// Returns the ID of any request handled. The radio should be in receive mode.
+    pub fn poll<T, E, F>(
+        &mut self,
+        radio: &mut T,
+        buff: &mut [u8],
+        mut handler: F,
+    ) -> Result<Option<u16>, RpcError<E>>
+    where
+        T: Transmit<Error = E> + Receive<Error = E> + DelayNs,
+        E: Debug,
+        F: FnMut(u8, &[u8], &mut [u8]) -> Option<usize>,
+    {
+        if !radio.check_receive(true)? {
+            return Ok(None);
+        }
+
+        let (n, _i) = radio.get_received(buff)?;
+        let h = match RpcHeader::from_bytes(&buff[..n]) {
+            Some(h) if h.kind == RpcKind::Request => h,
+            _ => {
+                radio.start_receive()?;
+                return Ok(None);
+            }
+        };
+
+        // Retried request, resend cached response
+        if let Some((id, len)) = self.last
+            && id == h.id
+        {
+            radio.do_transmit(&self.response[..len], self.options.blocking_options.clone())?;
+            radio.start_receive()?;
+            return Ok(None);
+        }
+
+        let len = match handler(
+            h.method,
+            &buff[RpcHeader::LEN..n],
+            &mut self.response[RpcHeader::LEN..],
+        ) {
+            Some(len) => len,
+            None => {
+                radio.start_receive()?;
+                return Ok(Some(h.id));
+            }
+        };
+        if RpcHeader::LEN + len > M {
+            radio.start_receive()?;
+            return Err(RpcError::TooLarge);
+        }
+
+        let r = RpcHeader {
+            kind: RpcKind::Response,
+            ..h
+        };
+        self.response[..RpcHeader::LEN].copy_from_slice(&r.to_bytes());
+        let len = RpcHeader::LEN + len;
+        self.last = Some((h.id, len));
+
+        radio.do_transmit(&self.response[..len], self.options.blocking_options.clone())?;
+        radio.start_receive()?;
+
+        Ok(Some(h.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+    use core::cell::RefCell;
+
+    type Air = RefCell<Option<([u8; 32], usize)>>;
+
+    /// Loopback radio sending to one channel and receiving from another
+    struct Loopback<'a> {
+        tx: &'a Air,
+        rx: &'a Air,
+    }
+
+    impl Transmit for Loopback<'_> {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let mut b = [0u8; 32];
+            b[..data.len()].copy_from_slice(data);
+            *self.tx.borrow_mut() = Some((b, data.len()));
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Receive for Loopback<'_> {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.rx.borrow().is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (b, n) = self.rx.borrow_mut().take().unwrap();
+            buff[..n].copy_from_slice(&b[..n]);
+            Ok((n, BasicInfo::default()))
+        }
+    }
+
+    impl DelayNs for Loopback<'_> {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn rpc_request_response() {
+        let (a, b) = (Air::default(), Air::default());
+        let mut client_radio = Loopback { tx: &a, rx: &b };
+        let mut server_radio = Loopback { tx: &b, rx: &a };
+
+        let mut client = RpcClient::<2, 16>::new(RpcOptions::default());
+        let mut server = RpcServer::<16>::new(RpcOptions::default());
+        let mut buff = [0u8; 32];
+
+        let id = client.request(&mut client_radio, 7, &[1, 2], 0).unwrap();
+        assert_eq!(client.outstanding(), 1);
+
+        // Server sums the request payload
+        let handled = server
+            .poll(&mut server_radio, &mut buff, |m, req, resp| {
+                assert_eq!(m, 7);
+                resp[0] = req.iter().sum();
+                Some(1)
+            })
+            .unwrap();
+        assert_eq!(handled, Some(id));
+
+        let e = client.poll(&mut client_radio, &mut buff, 10).unwrap();
+        assert_eq!(
+            e,
+            Some(RpcEvent::Response {
+                id,
+                method: 7,
+                len: 1
+            })
+        );
+        assert_eq!(buff[0], 3);
+        assert_eq!(client.outstanding(), 0);
+    }
+
+    #[test]
+    fn rpc_retry_and_timeout() {
+        let (a, b) = (Air::default(), Air::default());
+        let mut radio = Loopback { tx: &a, rx: &b };
+        let opts = RpcOptions {
+            timeout_ms: 100,
+            retries: 1,
+            ..Default::default()
+        };
+        let mut client = RpcClient::<1, 16>::new(opts);
+        let mut buff = [0u8; 32];
+
+        let id = client.request(&mut radio, 1, &[], 0).unwrap();
+        assert_eq!(client.request(&mut radio, 1, &[], 0), Err(RpcError::Full));
+        a.borrow_mut().take();
+
+        // Retry after the timeout
+        assert_eq!(client.poll(&mut radio, &mut buff, 50), Ok(None));
+        assert_eq!(client.poll(&mut radio, &mut buff, 100), Ok(None));
+        assert!(a.borrow().is_some());
+
+        // Timeout after retries are exhausted
+        assert_eq!(
+            client.poll(&mut radio, &mut buff, 200),
+            Ok(Some(RpcEvent::Timeout { id, method: 1 }))
+        );
+        assert_eq!(client.outstanding(), 0);
+    }
+}
@@ -0,0 +1,73 @@
+//! Shared test fixtures
+//!
+//! [`TxRadio`] stands in for a transmit-only radio in `no_std` unit tests, while tests
+//! exchanging frames use the [`ImpairedRadio`](crate::mock::ImpairedRadio) mock on a
+//! shared [`VirtualClock`](crate::clock::VirtualClock) (see [`impaired`]).
+
+use crate::Transmit;
+
+/// Radio recording transmitted frames, completing transmissions immediately or (where
+/// created with [`TxRadio::held`]) once `done` is set
+#[derive(Default)]
+pub(crate) struct TxRadio {
+    /// First byte of each started frame
+    pub started: [u8; 16],
+    /// Number of started frames
+    pub n: usize,
+    /// Whether the in-flight transmission has completed, where held
+    pub done: bool,
+    hold: bool,
+    last: [u8; 32],
+    len: usize,
+}
+
+impl TxRadio {
+    /// Create a radio holding transmissions in flight until `done` is set
+    pub fn held() -> Self {
+        Self {
+            hold: true,
+            ..Default::default()
+        }
+    }
+
+    /// The most recently started frame
+    pub fn last(&self) -> &[u8] {
+        &self.last[..self.len]
+    }
+}
+
+impl Transmit for TxRadio {
+    type Error = ();
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.started[self.n] = data[0];
+        self.n += 1;
+        self.done = false;
+
+        self.last[..data.len()].copy_from_slice(data);
+        self.len = data.len();
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.hold || self.done)
+    }
+}
+
+/// Create an unimpaired mock radio answering transmissions with the provided responder,
+/// with delays advancing the returned virtual clock
+#[cfg(feature = "mock")]
+pub(crate) fn impaired<F>(
+    responder: F,
+) -> (
+    crate::mock::ImpairedRadio,
+    std::rc::Rc<crate::clock::VirtualClock>,
+)
+where
+    F: FnMut(&[u8]) -> Option<std::vec::Vec<u8>> + 'static,
+{
+    let clock = std::rc::Rc::new(crate::clock::VirtualClock::new());
+    let radio =
+        crate::mock::ImpairedRadio::new(responder, Default::default(), 1).with_clock(clock.clone());
+    (radio, clock)
+}
@@ -0,0 +1,346 @@
+//! Per-destination transmit power control
+//!
+//! Rather than transmitting every frame at a single global power, [`PowerControl`] wraps
+//! a radio using [`frame`](crate::frame) addressing, estimating the path loss to each peer
+//! from the RSSI of frames received from it and transmitting frames to that peer at the
+//! minimum power expected to arrive at the target RSSI. Broadcast frames and frames for
+//! unknown peers are sent at the maximum power.
+//!
+//! Path loss estimates assume reciprocal links with peers transmitting at
+//! [`TpcOptions::peer_power`], estimates may also be fed directly (for example from RSSI
+//! reported by peers) with [`PowerTable::update`].
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use core::fmt::Debug;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::frame::{Address, BROADCAST, Header};
+use crate::{Power, Receive, ReceiveInfo, Transmit, TransmitPower};
+
+/// Transmit power control configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TpcOptions {
+    /// RSSI (dBm) targeted at peers
+    pub target_rssi: i16,
+    /// Margin (dB) added to the power required to reach the target
+    pub margin: i8,
+    /// Minimum transmit power (dBm)
+    pub min_power: i8,
+    /// Maximum transmit power (dBm), used for broadcasts and unknown peers
+    pub max_power: i8,
+    /// Transmit power (dBm) assumed for peers when estimating path loss
+    pub peer_power: i8,
+    /// Weight (`0.0..=1.0`) of each new RSSI sample in the moving average
+    pub alpha: f32,
+}
+
+impl Default for TpcOptions {
+    fn default() -> Self {
+        Self {
+            target_rssi: -85,
+            margin: 3,
+            min_power: -18,
+            max_power: 13,
+            peer_power: 13,
+            alpha: 0.25,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PeerLink {
+    address: Address,
+    rssi: f32,
+    samples: u32,
+}
+
+/// Link quality estimates and transmit powers for up to `N` peers
+///
+/// Peers beyond the table capacity replace the peer with the fewest samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerTable<const N: usize> {
+    options: TpcOptions,
+    peers: [Option<PeerLink>; N],
+}
+
+impl<const N: usize> PowerTable<N> {
+    /// Create an empty power table
+    pub fn new(options: TpcOptions) -> Self {
+        Self {
+            options,
+            peers: [None; N],
+        }
+    }
+
+    /// Record the RSSI of a frame received from the provided peer
+    pub fn update(&mut self, address: Address, rssi: i16) {
+        let alpha = self.options.alpha.clamp(0.0, 1.0);
+
+        if let Some(p) = self
+            .peers
+            .iter_mut()
+            .flatten()
+            .find(|p| p.address == address)
+        {
+            p.rssi += alpha * (rssi as f32 - p.rssi);
+            p.samples = p.samples.saturating_add(1);
+            return;
+        }
+
+        let new = PeerLink {
+            address,
+            rssi: rssi as f32,
+            samples: 1,
+        };
+        let slot = match self.peers.iter().position(|p| p.is_none()) {
+            Some(i) => self.peers.get_mut(i),
+            None => self
+                .peers
+                .iter_mut()
+                .min_by_key(|p| p.as_ref().map(|p| p.samples)),
+        };
+        if let Some(s) = slot {
+            *s = Some(new);
+        }
+    }
+
+    /// Fetch the smoothed RSSI of frames received from the provided peer
+    pub fn rssi(&self, address: Address) -> Option<f32> {
+        self.peer(address).map(|p| p.rssi)
+    }
+
+    /// Fetch the estimated path loss (dB) to the provided peer
+    pub fn path_loss(&self, address: Address) -> Option<f32> {
+        self.rssi(address)
+            .map(|r| self.options.peer_power as f32 - r)
+    }
+
+    /// Fetch the transmit power (dBm) for frames to the provided address
+    pub fn power(&self, address: Address) -> i8 {
+        let o = &self.options;
+
+        match self.path_loss(address).filter(|_| address != BROADCAST) {
+            Some(l) => {
+                let p = o.target_rssi as f32 + l + o.margin as f32;
+                // Round up (without `f32::ceil` for `no_std`)
+                let p = match p as i32 {
+                    c if (c as f32) < p => c + 1,
+                    c => c,
+                };
+                p.clamp(o.min_power as i32, o.max_power as i32) as i8
+            }
+            None => o.max_power,
+        }
+    }
+
+    fn peer(&self, address: Address) -> Option<&PeerLink> {
+        self.peers.iter().flatten().find(|p| p.address == address)
+    }
+}
+
+/// Radio wrapper transmitting each frame at the power for its destination
+pub struct PowerControl<T, const N: usize> {
+    radio: T,
+    table: PowerTable<N>,
+    current: Option<i8>,
+}
+
+impl<T, const N: usize> PowerControl<T, N> {
+    /// Wrap a radio, tracking up to `N` peers
+    pub fn new(radio: T, options: TpcOptions) -> Self {
+        Self {
+            radio,
+            table: PowerTable::new(options),
+            current: None,
+        }
+    }
+
+    /// Fetch the peer power table
+    pub fn table(&mut self) -> &mut PowerTable<N> {
+        &mut self.table
+    }
+
+    /// Fetch the power used for the last transmission
+    pub fn current_power(&self) -> Option<i8> {
+        self.current
+    }
+
+    /// Fetch the inner radio
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.radio
+    }
+
+    /// Release the inner radio
+    pub fn free(self) -> T {
+        self.radio
+    }
+}
+
+impl<T, E, const N: usize> Transmit for PowerControl<T, N>
+where
+    T: TransmitPower + Transmit<Error = E>,
+    E: Debug,
+{
+    type Error = E;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let dst = Header::from_bytes(data).map(|h| h.dst).unwrap_or(BROADCAST);
+        let power = self.table.power(dst);
+
+        self.radio.start_transmit_with_power(data, power)?;
+        self.current = Some(power);
+
+        Ok(())
+    }
+
+    fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+        self.radio.check_transmit()
+    }
+}
+
+impl<T, E, const N: usize> Receive for PowerControl<T, N>
+where
+    T: Receive<Error = E>,
+    E: Debug,
+{
+    type Info = T::Info;
+    type Error = E;
+
+    fn start_receive(&mut self) -> Result<(), Self::Error> {
+        self.radio.start_receive()
+    }
+
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Self::Error> {
+        self.radio.check_receive(restart)
+    }
+
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+        let (n, i) = self.radio.get_received(buff)?;
+
+        if let Ok(h) = Header::from_bytes(&buff[..n]) {
+            self.table.update(h.src, i.rssi());
+        }
+
+        Ok((n, i))
+    }
+}
+
+impl<T, E, const N: usize> Power for PowerControl<T, N>
+where
+    T: Power<Error = E>,
+    E: Debug,
+{
+    type Error = E;
+
+    fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+        self.radio.set_power(power)?;
+        self.current = Some(power);
+        Ok(())
+    }
+}
+
+impl<T: DelayNs, const N: usize> DelayNs for PowerControl<T, N> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.radio.delay_ns(ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicInfo;
+
+    /// Radio recording the power of each transmission
+    #[derive(Default)]
+    struct PowerRadio {
+        power: i8,
+        sent: Option<(i8, usize)>,
+        rx: Option<([u8; Header::LEN], i16)>,
+    }
+
+    impl Transmit for PowerRadio {
+        type Error = ();
+
+        fn start_transmit(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.sent = Some((self.power, data.len()));
+            Ok(())
+        }
+
+        fn check_transmit(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Power for PowerRadio {
+        type Error = ();
+
+        fn set_power(&mut self, power: i8) -> Result<(), Self::Error> {
+            self.power = power;
+            Ok(())
+        }
+    }
+
+    impl TransmitPower for PowerRadio {}
+
+    impl Receive for PowerRadio {
+        type Error = ();
+        type Info = BasicInfo;
+
+        fn start_receive(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn check_receive(&mut self, _restart: bool) -> Result<bool, Self::Error> {
+            Ok(self.rx.is_some())
+        }
+
+        fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, Self::Info), Self::Error> {
+            let (d, rssi) = self.rx.take().ok_or(())?;
+            buff[..d.len()].copy_from_slice(&d);
+            Ok((d.len(), BasicInfo::new(rssi, 0)))
+        }
+    }
+
+    #[test]
+    fn per_destination_power() {
+        let mut r = PowerControl::<_, 2>::new(PowerRadio::default(), TpcOptions::default());
+        let mut buff = [0u8; 32];
+
+        // Near and far peers, estimated from received frames
+        for (src, rssi) in [(0x0001, -40), (0x0002, -100)] {
+            r.inner().rx = Some((Header::new(src, 0x0010, 0).to_bytes(), rssi));
+            r.get_received(&mut buff).unwrap();
+        }
+
+        // Near peer at 13 - (-40) = 53 dB path loss, requiring -85 + 53 + 3 = -29 dBm
+        r.start_transmit(&Header::new(0x0010, 0x0001, 0).to_bytes())
+            .unwrap();
+        assert_eq!(r.current_power(), Some(-18));
+
+        // Far peer at 113 dB path loss, limited to the maximum power
+        r.start_transmit(&Header::new(0x0010, 0x0002, 0).to_bytes())
+            .unwrap();
+        assert_eq!(r.inner().sent, Some((13, Header::LEN)));
+
+        // Moderate links are tuned, with estimates smoothed over frames
+        let mut t = PowerTable::<2>::new(TpcOptions::default());
+        t.update(0x0003, -70);
+        assert_eq!(t.power(0x0003), 1);
+        t.update(0x0003, -90);
+        assert_eq!(t.rssi(0x0003), Some(-75.0));
+        assert_eq!(t.power(0x0003), 6);
+
+        // Broadcasts and unknown peers use the maximum power
+        assert_eq!(t.power(BROADCAST), 13);
+        assert_eq!(t.power(0x0004), 13);
+
+        // Full tables replace the least observed peer
+        t.update(0x0004, -60);
+        t.update(0x0005, -60);
+        assert_eq!(t.rssi(0x0003), Some(-75.0));
+        assert_eq!(t.rssi(0x0004), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! Prioritised transmit queue with preemption and per-class airtime accounting
+//!
+//! Where several subsystems (eg. ARQ acknowledgements, coordinator beacons and bulk data
+//! transfers) share one radio, [`TxQueue`] orders frames by [`TxPriority`] class so
+//! control frames are sent ahead of queued bulk data, with frames in each class sent in
+//! the order queued. With [`TxQueueOptions::preempt`] set, an in-flight transmission of
+//! a lower priority class is interrupted (by restarting the transmission) and requeued
+//! ahead of other frames in its class once a higher priority frame is queued.
+//!
+//! Where the queue is full, a frame of a higher priority class displaces the most
+//! recently queued frame of the lowest queued class.
+//!
+//! Airtime is accounted per class from transmit start to completion (including time
+//! spent on preempted transmissions), with timestamps provided by the caller in
+//! milliseconds, allowing use in `no_std` environments.
+//!
+//! ## <https://github.com/rust-iot/radio-hal>
+
+use crate::Transmit;
+
+/// Number of priority classes
+pub const TX_CLASSES: usize = 4;
+
+/// Transmit priority class, in order of decreasing priority
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TxPriority {
+    /// Acknowledgements and other time-critical control frames
+    Ack = 0,
+    /// Beacons and synchronisation frames
+    Beacon = 1,
+    /// Join (association) requests and responses
+    Join = 2,
+    /// Bulk data
+    Bulk = 3,
+}
+
+impl TxPriority {
+    /// Priority classes, in order of decreasing priority
+    pub const ALL: [TxPriority; TX_CLASSES] = [
+        TxPriority::Ack,
+        TxPriority::Beacon,
+        TxPriority::Join,
+        TxPriority::Bulk,
+    ];
+
+    /// Class index, used for per-class statistics
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Transmit queue configuration
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TxQueueOptions {
+    /// Interrupt in-flight transmissions of a lower priority class when a higher priority
+    /// frame is queued
+    pub preempt: bool,
+}
+
+/// TxQueueError describes failures queueing frames for transmission
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TxQueueError {
+    /// No free slots, or lower priority frames to displace
+    #[cfg_attr(feature = "thiserror", error("Transmit queue full"))]
+    Full,
+    /// Frame exceeds the queue slot size
+    #[cfg_attr(feature = "thiserror", error("Frame too large"))]
+    TooLarge,
+}
+
+/// Per-class transmit statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TxClassStats {
+    /// Frames queued
+    pub queued: u32,
+    /// Frames transmitted to completion
+    pub sent: u32,
+    /// Bytes transmitted to completion
+    pub bytes: u64,
+    /// Airtime used, including preempted transmissions, in milliseconds
+    pub airtime_ms: u64,
+    /// Transmissions interrupted by higher priority frames
+    pub preempted: u32,
+    /// Frames displaced from a full queue by higher priority frames
+    pub dropped: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Queued<const M: usize> {
+    priority: TxPriority,
+    order: u32,
+    len: usize,
+    data: [u8; M],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct InFlight<const M: usize> {
+    frame: Queued<M>,
+    started_at: u32,
+}
+
+/// Prioritised transmit queue for up to `N` frames of up to `M` bytes
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxQueue<const N: usize, const M: usize> {
+    options: TxQueueOptions,
+    frames: [Option<Queued<M>>; N],
+    in_flight: Option<InFlight<M>>,
+    order: u32,
+    stats: [TxClassStats; TX_CLASSES],
+}
+
+impl<const N: usize, const M: usize> TxQueue<N, M> {
+    /// Create a new empty transmit queue
+    pub fn new(options: TxQueueOptions) -> Self {
+        Self {
+            options,
+            frames: [None; N],
+            in_flight: None,
+            order: 0,
+            stats: [TxClassStats::default(); TX_CLASSES],
+        }
+    }
+
+    /// Queue a frame for transmission in the provided priority class
+    pub fn enqueue(&mut self, priority: TxPriority, data: &[u8]) -> Result<(), TxQueueError> {
+        if data.len() > M {
+            return Err(TxQueueError::TooLarge);
+        }
+
+        let slot = match self.frames.iter().position(|f| f.is_none()) {
+            Some(i) => i,
+            None => self.displace(priority).ok_or(TxQueueError::Full)?,
+        };
+
+        let mut frame = Queued {
+            priority,
+            order: self.order,
+            len: data.len(),
+            data: [0u8; M],
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+
+        self.frames[slot] = Some(frame);
+        self.order = self.order.wrapping_add(1);
+        self.stats[priority.index()].queued += 1;
+
+        Ok(())
+    }
+
+    /// Drop the most recently queued frame of the lowest class below `priority`,
+    /// returning the freed slot
+    fn displace(&mut self, priority: TxPriority) -> Option<usize> {
+        let order = self.order;
+        let (i, p) = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| f.map(|f| (i, f)))
+            .filter(|(_, f)| f.priority > priority)
+            .max_by_key(|(_, f)| (f.priority, f.order.wrapping_sub(order)))
+            .map(|(i, f)| (i, f.priority))?;
+
+        self.frames[i] = None;
+        self.stats[p.index()].dropped += 1;
+        Some(i)
+    }
+
+    /// Index of the next frame to transmit, by class then queue order
+    fn next(&self) -> Option<usize> {
+        let order = self.order;
+        self.frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| f.map(|f| (i, f)))
+            .min_by_key(|(_, f)| (f.priority, f.order.wrapping_sub(order)))
+            .map(|(i, _)| i)
+    }
+
+    /// Service the queue at `now_ms`, completing in-flight transmissions, preempting
+    /// lower priority transmissions where enabled, and starting the next queued frame,
+    /// returning the class of any transmission completed
+    pub fn poll<T: Transmit>(
+        &mut self,
+        radio: &mut T,
+        now_ms: u32,
+    ) -> Result<Option<TxPriority>, T::Error> {
+        let mut completed = None;
+
+        if let Some(f) = self.in_flight {
+            if radio.check_transmit()? {
+                let s = &mut self.stats[f.frame.priority.index()];
+                s.sent += 1;
+                s.bytes += f.frame.len as u64;
+                s.airtime_ms += now_ms.wrapping_sub(f.started_at) as u64;
+
+                self.in_flight = None;
+                completed = Some(f.frame.priority);
+            } else {
+                let urgent = self
+                    .next()
+                    .and_then(|i| self.frames[i])
+                    .is_some_and(|n| n.priority < f.frame.priority);
+                if !(self.options.preempt && urgent) {
+                    return Ok(None);
+                }
+
+                // Requeue the interrupted frame, retaining its place in its class
+                let s = &mut self.stats[f.frame.priority.index()];
+                s.preempted += 1;
+                s.airtime_ms += now_ms.wrapping_sub(f.started_at) as u64;
+                if let Some(slot) = self.frames.iter_mut().find(|s| s.is_none()) {
+                    *slot = Some(f.frame);
+                } else {
+                    s.dropped += 1;
+                }
+                self.in_flight = None;
+            }
+        }
+
+        let i = match self.next() {
+            Some(i) => i,
+            None => return Ok(completed),
+        };
+        let frame = match self.frames[i].take() {
+            Some(f) => f,
+            None => return Ok(completed),
+        };
+
+        if let Err(e) = radio.start_transmit(&frame.data[..frame.len]) {
+            self.frames[i] = Some(frame);
+            return Err(e);
+        }
+        self.in_flight = Some(InFlight {
+            frame,
+            started_at: now_ms,
+        });
+
+        Ok(completed)
+    }
+
+    /// Queued frames in transmit order, starting with any in-flight transmission
+    /// (which is requeued at the head of its class where preempted)
+    pub fn frames(&self) -> impl Iterator<Item = (TxPriority, &[u8])> + '_ {
+        let order = self.order;
+        let key = move |f: &Queued<M>| (f.priority, f.order.wrapping_sub(order));
+        let first = self.frames.iter().flatten().min_by_key(|f| key(f));
+        let queued = core::iter::successors(first, move |p| {
+            self.frames
+                .iter()
+                .flatten()
+                .filter(|f| key(f) > key(p))
+                .min_by_key(|f| key(f))
+        });
+
+        self.in_flight
+            .iter()
+            .map(|f| &f.frame)
+            .chain(queued)
+            .map(|f| (f.priority, &f.data[..f.len]))
+    }
+
+    /// Class of the in-flight transmission, if any
+    pub fn in_flight(&self) -> Option<TxPriority> {
+        self.in_flight.map(|f| f.frame.priority)
+    }
+
+    /// Number of frames queued in a class (excluding any in-flight transmission)
+    pub fn pending(&self, priority: TxPriority) -> usize {
+        self.frames
+            .iter()
+            .flatten()
+            .filter(|f| f.priority == priority)
+            .count()
+    }
+
+    /// Total number of queued frames (excluding any in-flight transmission)
+    pub fn len(&self) -> usize {
+        self.frames.iter().flatten().count()
+    }
+
+    /// Check whether the queue is empty (a transmission may still be in flight)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check whether the queue is empty with no transmission in flight
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.is_none() && self.is_empty()
+    }
+
+    /// Transmit statistics for a class
+    pub fn stats(&self, priority: TxPriority) -> &TxClassStats {
+        &self.stats[priority.index()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TxRadio;
+
+    #[test]
+    fn priority_classes() {
+        let mut radio = TxRadio::held();
+        let mut q = TxQueue::<3, 4>::new(TxQueueOptions::default());
+
+        q.enqueue(TxPriority::Bulk, &[1]).unwrap();
+        q.enqueue(TxPriority::Bulk, &[2]).unwrap();
+        q.enqueue(TxPriority::Beacon, &[3]).unwrap();
+        assert_eq!(
+            q.enqueue(TxPriority::Ack, &[0; 5]),
+            Err(TxQueueError::TooLarge)
+        );
+
+        // Full queues displace the newest lower priority frame
+        q.enqueue(TxPriority::Ack, &[4]).unwrap();
+        assert_eq!(q.enqueue(TxPriority::Bulk, &[5]), Err(TxQueueError::Full));
+        assert_eq!(q.stats(TxPriority::Bulk).dropped, 1);
+
+        // Frames are sent by class, then in queue order
+        assert_eq!(q.poll(&mut radio, 0), Ok(None));
+        assert_eq!(q.poll(&mut radio, 5), Ok(None));
+        radio.done = true;
+        assert_eq!(q.poll(&mut radio, 10), Ok(Some(TxPriority::Ack)));
+        radio.done = true;
+        assert_eq!(q.poll(&mut radio, 30), Ok(Some(TxPriority::Beacon)));
+        radio.done = true;
+        assert_eq!(q.poll(&mut radio, 130), Ok(Some(TxPriority::Bulk)));
+        assert!(q.is_idle());
+        assert_eq!(&radio.started[..3], &[4, 3, 1]);
+
+        let s = q.stats(TxPriority::Beacon);
+        assert_eq!((s.queued, s.sent, s.bytes, s.airtime_ms), (1, 1, 1, 20));
+        assert_eq!(q.stats(TxPriority::Bulk).airtime_ms, 100);
+    }
+
+    #[test]
+    fn preempt_bulk() {
+        let mut radio = TxRadio::held();
+        let mut q = TxQueue::<4, 4>::new(TxQueueOptions { preempt: true });
+
+        q.enqueue(TxPriority::Bulk, &[1]).unwrap();
+        q.enqueue(TxPriority::Bulk, &[2]).unwrap();
+        q.poll(&mut radio, 0).unwrap();
+        assert_eq!(q.in_flight(), Some(TxPriority::Bulk));
+
+        // Queued control frames interrupt the in-flight bulk transmission
+        q.enqueue(TxPriority::Join, &[3]).unwrap();
+        assert_eq!(q.poll(&mut radio, 40), Ok(None));
+        assert_eq!(q.in_flight(), Some(TxPriority::Join));
+        assert_eq!(q.pending(TxPriority::Bulk), 2);
+
+        // The interrupted frame is resent ahead of later bulk frames
+        radio.done = true;
+        assert_eq!(q.poll(&mut radio, 50), Ok(Some(TxPriority::Join)));
+        radio.done = true;
+        assert_eq!(q.poll(&mut radio, 150), Ok(Some(TxPriority::Bulk)));
+        assert_eq!(&radio.started[..4], &[1, 3, 1, 2]);
+
+        let s = q.stats(TxPriority::Bulk);
+        assert_eq!((s.preempted, s.sent, s.airtime_ms), (1, 1, 140));
+    }
+}